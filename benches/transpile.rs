@@ -0,0 +1,101 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Measures transpilation (pattern matching) and patching (program emission) of fixtures and of
+//! synthetic modules of growing size. Run with `cargo bench --features bench`.
+use std::fs::read;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use inkwell::context::Context;
+use qcs_sdk_qir::{
+    bench::{generate_synthetic_qir, SyntheticOptions},
+    patch_qir_with_qcs, transpile_qir_to_quil, PatchOptions,
+};
+
+const FIXTURES: &[&str] = &["bell_state", "record_output", "parametric"];
+
+fn fixtures(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fixture");
+    for name in FIXTURES {
+        let data = read(format!("tests/fixtures/programs/{}.bc", name)).unwrap();
+        group.bench_with_input(BenchmarkId::new("transpile", name), &data, |b, data| {
+            b.iter(|| transpile_qir_to_quil(data).unwrap());
+        });
+        group.bench_with_input(BenchmarkId::new("patch", name), &data, |b, data| {
+            b.iter(|| {
+                let context = Context::create();
+                patch_qir_with_qcs(PatchOptions::default(), data, &context).unwrap();
+            });
+        });
+    }
+    group.finish();
+}
+
+fn synthetic(
+    c: &mut Criterion,
+    group_name: &str,
+    sizes: &[u64],
+    options: fn(u64) -> SyntheticOptions,
+) {
+    let mut group = c.benchmark_group(group_name);
+    for &size in sizes {
+        let data = generate_synthetic_qir(&options(size)).unwrap();
+        group.bench_with_input(BenchmarkId::new("transpile", size), &data, |b, data| {
+            b.iter(|| transpile_qir_to_quil(data).unwrap());
+        });
+        group.bench_with_input(BenchmarkId::new("patch", size), &data, |b, data| {
+            b.iter(|| {
+                let context = Context::create();
+                patch_qir_with_qcs(PatchOptions::default(), data, &context).unwrap();
+            });
+        });
+    }
+    group.finish();
+}
+
+fn large_shot_count(c: &mut Criterion) {
+    synthetic(
+        c,
+        "large_shot_count",
+        &[16, 64, 256],
+        SyntheticOptions::large_shot_count,
+    );
+}
+
+fn many_parameter_vqe(c: &mut Criterion) {
+    synthetic(
+        c,
+        "many_parameter_vqe",
+        &[16, 64, 256],
+        SyntheticOptions::many_parameter_vqe,
+    );
+}
+
+fn wide_measurement(c: &mut Criterion) {
+    synthetic(
+        c,
+        "wide_measurement",
+        &[64, 256, 1024],
+        SyntheticOptions::wide_measurement,
+    );
+}
+
+criterion_group!(
+    benches,
+    fixtures,
+    large_shot_count,
+    many_parameter_vqe,
+    wide_measurement
+);
+criterion_main!(benches);
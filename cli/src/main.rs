@@ -0,0 +1,938 @@
+#![deny(clippy::pedantic)]
+
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use clap::{CommandFactory, Parser};
+use clap_complete::Shell;
+use eyre::{Report, Result, WrapErr};
+
+use qcs_sdk_qir::{
+    diff::DiffScope,
+    example::{ExampleCircuit, ExampleOptions, ExampleProfile},
+    quilc::{QuilCompiler, Quilc},
+    validation::ConformanceLevel,
+    CallingConvention, CustomInstructionHandler, ExecutionTarget, MaxShotCount, MeasurementOrder,
+    MessageHandling, PatchFormat, PatchOptions, QubitReuse, RawQuilHandler, RuntimeFlavor,
+    ShotChunking, ShotCountPolicy, TranspileOptions,
+};
+
+/// The name of this tool's executable, as invoked from a shell.
+const BIN_NAME: &str = "qcs-sdk-qir";
+
+#[derive(Parser, Debug)]
+#[clap(
+    name = "QCS SDK QIR Command Line Tool",
+    about = "Transform & translate QIR programs to target Rigetti systems."
+)]
+enum QcsQirCli {
+    #[clap(
+        name = "transform",
+        about = "Given an LLVM bitcode file, replace quantum intrinsics with calls to execute equivalent Quil on Rigetti QCS"
+    )]
+    Transform {
+        #[clap(long, default_value = "shot-count")]
+        format: QirFormat,
+
+        llvm_bitcode_path: PathBuf,
+
+        #[clap(parse(from_os_str))]
+        bitcode_out: Option<PathBuf>,
+
+        #[clap(
+            long = "link",
+            parse(from_os_str),
+            help = "Additional LLVM bitcode file (such as a QIR library) to link into the input before transformation; may be repeated"
+        )]
+        link_bitcode_paths: Vec<PathBuf>,
+
+        #[clap(
+            long,
+            default_value = "bitcode",
+            help = "Kind of output to write: \"bitcode\", \"llvm-ir\", or \"obj\" for a relocatable object file"
+        )]
+        emit: EmitKind,
+
+        #[clap(long)]
+        add_main_entrypoint: bool,
+
+        #[clap(
+            name = "target",
+            long,
+            env = "QCS_QIR_TARGET",
+            default_value = "qvm",
+            help = "QPU ID to target for execution, or \"qvm\" to target a generic device on the Quil QVM"
+        )]
+        execution_target: ExecutionTarget,
+
+        #[clap(long, env = "QCS_QIR_CACHE_EXECUTABLES")]
+        cache_executables: bool,
+
+        #[clap(long, env = "QCS_QIR_REWIRING_PRAGMA")]
+        quil_rewiring_pragma: Option<String>,
+
+        #[clap(
+            long,
+            help = "Use each Result index as its readout offset instead of assigning offsets densely"
+        )]
+        sparse_readout: bool,
+
+        #[clap(
+            long,
+            help = "Flip each qubit before it is measured and record its readout as flipped, to symmetrize readout error"
+        )]
+        flip_readout: bool,
+
+        #[clap(
+            long,
+            help = "Target triple to set on the output module, or \"host\" for the triple of this machine"
+        )]
+        target_triple: Option<String>,
+
+        #[clap(long, help = "Data layout to set on the output module")]
+        data_layout: Option<String>,
+
+        #[clap(
+            long,
+            help = "Round constant parameters to this many decimal places when writing them to Quil"
+        )]
+        float_precision: Option<usize>,
+
+        #[clap(
+            long,
+            help = "Write constant parameters near multiples of pi symbolically, such as pi/2"
+        )]
+        symbolic_pi: bool,
+
+        #[clap(
+            long,
+            help = "Cancel adjacent inverse gates and merge consecutive rotations in the Quil programs"
+        )]
+        optimize_quil: bool,
+
+        #[clap(
+            long,
+            default_value = "ignore",
+            help = "How to handle a program in which a gate follows a measurement: \"ignore\", \"warn\", \"deny\", or \"reorder\" the measurements after the gates"
+        )]
+        measurement_order: MeasurementOrder,
+
+        #[clap(
+            long,
+            default_value = "c",
+            help = "Calling convention of the helper functions added to the module: \"c\" or \"fast\""
+        )]
+        helper_calling_convention: CallingConvention,
+
+        #[clap(
+            long,
+            default_value = "qcs-c",
+            help = "Runtime the patched module calls into, which names its functions: \"qcs-c\", \"qcs-rust\", or \"custom:<prefix>\" for the names of helper.h with a prefix"
+        )]
+        runtime_flavor: RuntimeFlavor,
+
+        #[clap(
+            long,
+            help = "Pass every runtime function an extra final argument read from the global qcs_sdk_qir_user_context, which the host sets before calling the entrypoint"
+        )]
+        user_context: bool,
+
+        #[clap(
+            long,
+            help = "Give the symbols added to the module hidden visibility, for linking into a shared library along with the runtime"
+        )]
+        hidden_visibility: bool,
+
+        #[clap(
+            long,
+            help = "Record in the module the crate version, options, input digest, and time of patching, readable with the inspect command"
+        )]
+        stamp_provenance: bool,
+
+        #[clap(
+            long = "only-function",
+            help = "Name of a function to transform, leaving all others untouched; may be repeated"
+        )]
+        only_functions: Vec<String>,
+
+        #[clap(
+            long = "raw-quil-function",
+            help = "Name of a function taking a constant string of Quil, to be merged verbatim into the program at each call; may be repeated"
+        )]
+        raw_quil_functions: Vec<String>,
+
+        #[clap(
+            long,
+            help = "Keep the original code alongside the transformation, running it in place of the transformed code when the runtime's use_qcs() returns false"
+        )]
+        keep_original_code: bool,
+
+        #[clap(
+            long,
+            parse(from_os_str),
+            help = "Also write a module implementing the QIR runtime functions the output still calls, as no-ops or minimal implementations, in the same kind of output as --emit"
+        )]
+        runtime_shim: Option<PathBuf>,
+
+        #[clap(
+            long,
+            parse(from_os_str),
+            value_name = "DIR",
+            help = "Write the pattern context, Quil program, and IR before and after transformation of each matched basic block into this directory"
+        )]
+        dump_intermediate: Option<PathBuf>,
+
+        #[clap(
+            long,
+            parse(from_os_str),
+            value_name = "PATH",
+            help = "Write a manifest of every Quil program inserted into the module, with its shot count, recorded output, and cache index, to this path"
+        )]
+        emit_manifest: Option<PathBuf>,
+
+        #[clap(long, help = "Clamp the shot count of every program to this maximum")]
+        max_shots: Option<u64>,
+
+        #[clap(
+            long,
+            default_value = "whole",
+            help = "Split the shots of every program among several executions: \"whole\", \"count:<executions>\", or \"max-shots:<shots>\" per execution"
+        )]
+        shot_chunking: ShotChunking,
+
+        #[clap(
+            long,
+            help = "Allocate each execution result once per call to the function executing it, and refill it on every execution"
+        )]
+        reuse_execution_results: bool,
+
+        #[clap(
+            long,
+            help = "Order the blocks of each patched function by control flow, and name injected blocks predictably"
+        )]
+        stable_block_layout: bool,
+
+        #[clap(
+            long,
+            default_value = "ignore",
+            help = "How to handle a qubit which a program shares with a program extracted before it: \"ignore\", \"warn\", or \"reset\" it at the start of the later program"
+        )]
+        qubit_reuse: QubitReuse,
+
+        #[clap(
+            long,
+            default_value = "retain",
+            help = "What to do with each call to __quantum__rt__message: \"retain\" it in the module, write it into the Quil program as a \"pragma\", or \"remove\" it"
+        )]
+        messages: MessageHandling,
+
+        #[clap(
+            long,
+            env = "QCS_QIR_TARGET_QUBITS",
+            help = "Number of qubit indices addressable on the target, overriding the size known for a named QPU; programs using a greater index are reported"
+        )]
+        target_qubits: Option<u64>,
+
+        #[clap(
+            long,
+            env = "QCS_QIR_DENY_EXCESS_QUBITS",
+            help = "Fail, rather than warn, when a program uses a qubit index beyond the size of the target"
+        )]
+        deny_excess_qubits: bool,
+
+        #[clap(
+            long,
+            help = "Fail, rather than warn, when a program holds a likely mistake, such as recording a result it never measures"
+        )]
+        deny_warnings: bool,
+
+        #[cfg(feature = "translation")]
+        #[clap(
+            long,
+            help = "Translate each program with the QCS translation service when targeting a QPU, failing with its diagnostics if a program does not translate or its readout does not survive translation"
+        )]
+        verify_translation: bool,
+
+        #[clap(
+            long,
+            help = "Report the programs which would be extracted and the symbols which would be added, without writing any output"
+        )]
+        dry_run: bool,
+
+        #[clap(
+            long,
+            help = "Print a unified diff between the textual IR of the module before and after patching"
+        )]
+        diff: bool,
+
+        #[clap(
+            long,
+            requires = "diff",
+            help = "Limit the diff to the functions patching changed or added"
+        )]
+        diff_affected_only: bool,
+    },
+    #[clap(
+        name = "transpile-to-quil",
+        about = "Given an LLVM bitcode file, output the equivalent Quil program"
+    )]
+    TranspileToQuil {
+        #[clap(long, default_value = "shot-count")]
+        format: QirFormat,
+
+        llvm_bitcode_path: PathBuf,
+
+        #[clap(
+            long,
+            help = "Precede each Quil instruction with a comment naming the QIR instruction it was translated from"
+        )]
+        trace: bool,
+
+        #[clap(
+            long,
+            help = "Round constant parameters to this many decimal places when writing them to Quil"
+        )]
+        float_precision: Option<usize>,
+
+        #[clap(
+            long,
+            help = "Write constant parameters near multiples of pi symbolically, such as pi/2"
+        )]
+        symbolic_pi: bool,
+
+        #[clap(
+            long,
+            help = "Cancel adjacent inverse gates and merge consecutive rotations in the Quil programs"
+        )]
+        optimize_quil: bool,
+
+        #[clap(
+            long,
+            default_value = "ignore",
+            help = "How to handle a program in which a gate follows a measurement: \"ignore\", \"warn\", \"deny\", or \"reorder\" the measurements after the gates"
+        )]
+        measurement_order: MeasurementOrder,
+
+        #[clap(
+            long,
+            help = "Renumber the program's qubits as 0, 1, 2, ..., reporting the index given to each"
+        )]
+        compact_qubits: bool,
+
+        #[clap(
+            long,
+            help = "Name of the basic block holding the shot count loop, if it cannot be found automatically"
+        )]
+        body_block: Option<String>,
+
+        #[clap(
+            long,
+            value_name = "ISA",
+            help = "Also compile the program to native Quil with quilc for this ISA, either one built into quilc such as \"8Q\" or the path of a QPU's ISA file"
+        )]
+        quilc_preprocess: Option<String>,
+
+        #[clap(
+            long,
+            default_value = "quilc",
+            requires = "quilc-preprocess",
+            help = "The quilc executable run by --quilc-preprocess"
+        )]
+        quilc_path: PathBuf,
+
+        #[clap(
+            long,
+            default_value = "retain",
+            help = "What to do with each call to __quantum__rt__message: \"retain\" it in the module, write it into the Quil program as a \"pragma\", or \"remove\" it"
+        )]
+        messages: MessageHandling,
+
+        #[clap(
+            long,
+            help = "Fail, rather than report a warning, when the program holds a likely mistake, such as recording a result it never measures"
+        )]
+        deny_warnings: bool,
+    },
+    #[clap(
+        name = "explain",
+        about = "Given an LLVM bitcode file, describe how each instruction was matched when transpiling it to Quil, or why matching stopped"
+    )]
+    Explain { llvm_bitcode_path: PathBuf },
+    #[clap(
+        name = "inspect",
+        about = "Given a patched LLVM bitcode file, print the provenance recorded in it when it was patched"
+    )]
+    Inspect { llvm_bitcode_path: PathBuf },
+    #[clap(
+        name = "check-conformance",
+        about = "Given an LLVM bitcode file, check its conformance to the QIR specification or one of its profiles, listing every violation"
+    )]
+    CheckConformance {
+        #[clap(
+            long,
+            default_value = "full",
+            help = "Level to check against: \"base\", \"adaptive\", or \"full\""
+        )]
+        level: ConformanceLevel,
+
+        llvm_bitcode_path: PathBuf,
+    },
+    #[clap(
+        name = "gen-fixture",
+        about = "Given a textual LLVM IR file, compile it to bitcode with the version of LLVM linked into this tool"
+    )]
+    GenFixture {
+        #[clap(parse(from_os_str))]
+        llvm_ir_path: PathBuf,
+
+        #[clap(
+            long,
+            parse(from_os_str),
+            help = "Path to write the bitcode to; defaults to the input path with a .bc extension"
+        )]
+        out: Option<PathBuf>,
+    },
+    #[clap(
+        name = "example",
+        about = "Generate an LLVM bitcode file holding a QIR program for a well-known circuit"
+    )]
+    Example {
+        #[clap(long, default_value = "shot-count")]
+        profile: ExampleProfile,
+
+        #[clap(
+            long,
+            default_value = "bell",
+            help = "Circuit to generate: \"bell\", \"ghz\", or \"qft\""
+        )]
+        gates: ExampleCircuit,
+
+        #[clap(
+            long,
+            help = "Number of qubits on which to apply the circuit; defaults to 2 for bell and 3 otherwise"
+        )]
+        qubits: Option<u64>,
+
+        #[clap(
+            long,
+            env = "QCS_QIR_SHOTS",
+            default_value = "1000",
+            help = "Number of shots for which the shot-count profile runs"
+        )]
+        shots: u64,
+
+        #[clap(short, long, parse(from_os_str), help = "Path to write the bitcode to")]
+        out: PathBuf,
+    },
+    #[clap(
+        name = "completions",
+        about = "Write a shell completion script for this tool, or with --man its manual page, to standard output"
+    )]
+    Completions {
+        #[clap(
+            arg_enum,
+            required_unless_present = "man",
+            help = "Shell to generate completions for"
+        )]
+        shell: Option<Shell>,
+
+        #[clap(
+            long,
+            conflicts_with = "shell",
+            help = "Write a manual page in roff format instead of completions"
+        )]
+        man: bool,
+    },
+    #[cfg(feature = "execution-jit")]
+    #[clap(
+        name = "run",
+        about = "Given an LLVM bitcode file, transform it and run it in-process, executing its Quil on Rigetti QCS"
+    )]
+    Run {
+        #[clap(long, default_value = "shot-count")]
+        format: QirFormat,
+
+        llvm_bitcode_path: PathBuf,
+
+        #[clap(
+            long = "link",
+            parse(from_os_str),
+            help = "Additional LLVM bitcode file (such as a QIR library) to link into the input before transformation; may be repeated"
+        )]
+        link_bitcode_paths: Vec<PathBuf>,
+
+        #[clap(
+            name = "target",
+            long,
+            env = "QCS_QIR_TARGET",
+            default_value = "qvm",
+            help = "QPU ID to target for execution, or \"qvm\" to target a generic device on the Quil QVM"
+        )]
+        execution_target: ExecutionTarget,
+
+        #[clap(long, env = "QCS_QIR_CACHE_EXECUTABLES")]
+        cache_executables: bool,
+
+        #[clap(long, env = "QCS_QIR_REWIRING_PRAGMA")]
+        quil_rewiring_pragma: Option<String>,
+
+        #[clap(
+            long,
+            parse(from_os_str),
+            help = "Return readout from this table instead of executing on QVM or QCS; requires the mock-runtime feature"
+        )]
+        mock_readout: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug)]
+enum QirFormat {
+    ShotCount,
+    Hybrid,
+    Unitary,
+}
+
+impl FromStr for QirFormat {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "shot-count" => Ok(QirFormat::ShotCount),
+            "hybrid" => Ok(QirFormat::Hybrid),
+            "unitary" => Ok(QirFormat::Unitary),
+            _ => Err(eyre::eyre!("unrecognized QIR format")),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum EmitKind {
+    Bitcode,
+    LlvmIr,
+    Object,
+}
+
+impl FromStr for EmitKind {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bitcode" => Ok(EmitKind::Bitcode),
+            "llvm-ir" => Ok(EmitKind::LlvmIr),
+            "obj" => Ok(EmitKind::Object),
+            _ => Err(eyre::eyre!("unrecognized output kind")),
+        }
+    }
+}
+
+/// Write `module` to `path` as the given kind of output.
+fn write_module(module: &inkwell::module::Module, path: &Path, emit: &EmitKind) -> Result<()> {
+    match emit {
+        EmitKind::Bitcode => {
+            module.write_bitcode_to_path(path);
+        }
+        EmitKind::LlvmIr => {
+            module
+                .print_to_file(path)
+                .map_err(|e| eyre::eyre!(e.to_string()))?;
+        }
+        EmitKind::Object => {
+            qcs_sdk_qir::emit::write_object_file(module, path)?;
+        }
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+
+    let opt = QcsQirCli::parse();
+    match opt {
+        QcsQirCli::Transform {
+            format,
+            emit,
+            add_main_entrypoint,
+            llvm_bitcode_path,
+            bitcode_out,
+            link_bitcode_paths,
+            execution_target,
+            cache_executables,
+            quil_rewiring_pragma,
+            sparse_readout,
+            flip_readout,
+            target_triple,
+            data_layout,
+            float_precision,
+            symbolic_pi,
+            optimize_quil,
+            measurement_order,
+            helper_calling_convention,
+            runtime_flavor,
+            user_context,
+            hidden_visibility,
+            stamp_provenance,
+            only_functions,
+            raw_quil_functions,
+            keep_original_code,
+            runtime_shim,
+            dump_intermediate,
+            emit_manifest,
+            max_shots,
+            shot_chunking,
+            reuse_execution_results,
+            stable_block_layout,
+            qubit_reuse,
+            messages,
+            target_qubits,
+            deny_excess_qubits,
+            deny_warnings,
+            #[cfg(feature = "translation")]
+            verify_translation,
+            dry_run,
+            diff,
+            diff_affected_only,
+        } => {
+            let bitcodes = std::iter::once(llvm_bitcode_path)
+                .chain(link_bitcode_paths)
+                .map(std::fs::read)
+                .collect::<std::io::Result<Vec<_>>>()?;
+            let bitcodes = bitcodes.iter().map(Vec::as_slice).collect::<Vec<_>>();
+            let options = PatchOptions {
+                add_main_entrypoint,
+                execution_target,
+                cache_executables,
+                quil_rewiring_pragma,
+                sparse_readout,
+                flip_readout,
+                target_triple,
+                data_layout,
+                float_precision,
+                symbolic_pi,
+                optimize_quil,
+                measurement_order,
+                helper_calling_convention,
+                runtime_flavor,
+                user_context,
+                hidden_visibility,
+                stamp_provenance,
+                function_allowlist: (!only_functions.is_empty()).then(|| only_functions),
+                custom_instruction_handlers: raw_quil_functions
+                    .into_iter()
+                    .map(|function_name| {
+                        Arc::new(RawQuilHandler::new(function_name))
+                            as Arc<dyn CustomInstructionHandler>
+                    })
+                    .collect(),
+                keep_original_code,
+                dump_intermediate,
+                shot_count_policy: max_shots
+                    .map(|max_shots| Arc::new(MaxShotCount(max_shots)) as Arc<dyn ShotCountPolicy>),
+                shot_chunking,
+                reuse_execution_results,
+                stable_block_layout,
+                qubit_reuse,
+                message_handling: messages,
+                target_qubit_count: target_qubits,
+                deny_excess_qubits,
+                deny_warnings,
+                #[cfg(feature = "translation")]
+                translation_service: if verify_translation {
+                    Some(
+                        Arc::new(qcs_sdk_qir::translation::QcsTranslationService::new()?)
+                            as Arc<dyn qcs_sdk_qir::translation::TranslationService>,
+                    )
+                } else {
+                    None
+                },
+                ..PatchOptions::default()
+            };
+            let format = match format {
+                QirFormat::ShotCount => PatchFormat::ShotCount,
+                QirFormat::Hybrid => PatchFormat::Hybrid,
+                QirFormat::Unitary => PatchFormat::Unitary,
+            };
+            if dry_run {
+                let summary = qcs_sdk_qir::dry_run_patch(format, options, &bitcodes)?;
+
+                #[cfg(feature = "serde_support")]
+                println!("{}", serde_json::to_string_pretty(&summary)?);
+
+                #[cfg(not(feature = "serde_support"))]
+                print!("{}", summary);
+
+                return Ok(());
+            }
+
+            let context = inkwell::context::Context::create();
+            let (module, manifest) =
+                qcs_sdk_qir::patch_with_manifest(format, options, &bitcodes, &context)?;
+            if let Some(path) = emit_manifest {
+                #[cfg(feature = "serde_support")]
+                let contents = serde_json::to_string_pretty(&manifest)?;
+
+                #[cfg(not(feature = "serde_support"))]
+                let contents = manifest.to_string();
+
+                std::fs::write(&path, contents)
+                    .wrap_err_with(|| format!("failed to write manifest to {:?}", path))?;
+            }
+            if diff {
+                let scope = if diff_affected_only {
+                    DiffScope::AffectedFunctions
+                } else {
+                    DiffScope::Module
+                };
+                print!(
+                    "{}",
+                    qcs_sdk_qir::diff::diff_patched_ir(&bitcodes, &module, scope)?
+                );
+            }
+            if let Some(path) = runtime_shim {
+                let shim = qcs_sdk_qir::shim::generate_runtime_shim(&context, &module)?;
+                write_module(&shim, &path, &emit)?;
+            }
+            match (bitcode_out, emit) {
+                (Some(path), emit) => write_module(&module, &path, &emit)?,
+                (None, EmitKind::Object) => {
+                    return Err(eyre::eyre!(
+                        "an output path is required to emit an object file"
+                    ));
+                }
+                (None, _) => {
+                    module.print_to_stderr();
+                }
+            }
+            Ok(())
+        }
+        QcsQirCli::TranspileToQuil {
+            format,
+            llvm_bitcode_path,
+            trace,
+            float_precision,
+            symbolic_pi,
+            optimize_quil,
+            measurement_order,
+            compact_qubits,
+            body_block,
+            quilc_preprocess,
+            quilc_path,
+            deny_warnings,
+            messages,
+        } => {
+            let data = std::fs::read(llvm_bitcode_path)?;
+            let options = TranspileOptions {
+                trace,
+                float_precision,
+                symbolic_pi,
+                optimize_quil,
+                measurement_order,
+                compact_qubits,
+                body_block,
+                quil_compiler: quilc_preprocess.map(|isa| {
+                    Arc::new(Quilc {
+                        command: quilc_path,
+                        isa,
+                    }) as Arc<dyn QuilCompiler>
+                }),
+                deny_warnings,
+                message_handling: messages,
+                ..TranspileOptions::default()
+            };
+
+            match format {
+                // A hybrid program's quantum kernel is a shot count loop; only its executable's
+                // lifetime differs once transformed.
+                QirFormat::ShotCount | QirFormat::Hybrid => {
+                    let output = qcs_sdk_qir::transpile_qir_to_quil_with_options(&data, &options)?;
+
+                    #[cfg(feature = "serde_support")]
+                    println!("{}", serde_json::to_string_pretty(&output)?);
+
+                    #[cfg(not(feature = "serde_support"))]
+                    {
+                        println!("shot count: {}\n", output.shot_count);
+                        println!("quil:\n{}", output.to_traced_string());
+                        println!("recorded output:\n{:#?}", output.recorded_output);
+                        println!("parameters:\n{:#?}", output.parameters);
+                        if !output.qubit_mapping.is_empty() {
+                            println!("qubit mapping:\n{:#?}", output.qubit_mapping);
+                        }
+                        if let Some(native_program) = &output.native_program {
+                            println!("native quil:\n{}", native_program.to_string(true));
+                        }
+                        for warning in &output.warnings {
+                            println!("warning: {}", warning);
+                        }
+                    }
+                }
+                QirFormat::Unitary => {
+                    let output =
+                        qcs_sdk_qir::transpile_unitary_qir_to_quil_with_options(&data, &options)?;
+
+                    #[cfg(feature = "serde_support")]
+                    println!("{}", serde_json::to_string_pretty(&output)?);
+
+                    #[cfg(not(feature = "serde_support"))]
+                    {
+                        if let Some(shot_count) = output.shot_count {
+                            println!("shot count: {}\n", shot_count);
+                        }
+                        println!("quil:\n{}", output.to_traced_string());
+                        println!("recorded output:\n{:#?}", output.recorded_output);
+                        println!("parameters:\n{:#?}", output.parameters);
+                        if !output.arguments.is_empty() {
+                            println!("arguments:\n{:#?}", output.arguments);
+                        }
+                        if !output.qubit_mapping.is_empty() {
+                            println!("qubit mapping:\n{:#?}", output.qubit_mapping);
+                        }
+                        if let Some(native_program) = &output.native_program {
+                            println!("native quil:\n{}", native_program.to_string(true));
+                        }
+                        for warning in &output.warnings {
+                            println!("warning: {}", warning);
+                        }
+                    }
+                }
+            }
+
+            Ok(())
+        }
+        QcsQirCli::Explain { llvm_bitcode_path } => {
+            let data = std::fs::read(llvm_bitcode_path)?;
+            print!("{}", qcs_sdk_qir::explain_qir_to_quil(&data)?);
+            Ok(())
+        }
+        QcsQirCli::Inspect { llvm_bitcode_path } => {
+            let data = std::fs::read(&llvm_bitcode_path)?;
+            let provenance = qcs_sdk_qir::read_provenance(&data)?.ok_or_else(|| {
+                eyre::eyre!(
+                    "{} carries no provenance; patch it with --stamp-provenance to record one",
+                    llvm_bitcode_path.display()
+                )
+            })?;
+
+            #[cfg(feature = "serde_support")]
+            println!("{}", serde_json::to_string_pretty(&provenance)?);
+
+            #[cfg(not(feature = "serde_support"))]
+            print!("{}", provenance);
+
+            Ok(())
+        }
+        QcsQirCli::CheckConformance {
+            level,
+            llvm_bitcode_path,
+        } => {
+            let data = std::fs::read(llvm_bitcode_path)?;
+            let report = qcs_sdk_qir::validation::check_conformance(&data, level)?;
+            if report.is_conformant() {
+                println!("{}", report);
+                Ok(())
+            } else {
+                Err(eyre::eyre!("{}", report))
+            }
+        }
+        QcsQirCli::Completions { shell, man } => {
+            let mut command = QcsQirCli::command().name(BIN_NAME);
+            if man {
+                clap_mangen::Man::new(command).render(&mut std::io::stdout())?;
+            } else if let Some(shell) = shell {
+                clap_complete::generate(shell, &mut command, BIN_NAME, &mut std::io::stdout());
+            }
+            Ok(())
+        }
+        QcsQirCli::GenFixture { llvm_ir_path, out } => {
+            let ir = std::fs::read_to_string(&llvm_ir_path)?;
+            let bitcode = qcs_sdk_qir::compile_ir_to_bitcode(&ir)?;
+            let out = out.unwrap_or_else(|| llvm_ir_path.with_extension("bc"));
+            std::fs::write(out, bitcode)?;
+            Ok(())
+        }
+        QcsQirCli::Example {
+            profile,
+            gates,
+            qubits,
+            shots,
+            out,
+        } => {
+            let bitcode = qcs_sdk_qir::example::generate_example_qir(&ExampleOptions {
+                profile,
+                circuit: gates,
+                qubits,
+                shot_count: shots,
+            })?;
+            std::fs::write(out, bitcode)?;
+            Ok(())
+        }
+        #[cfg(feature = "execution-jit")]
+        QcsQirCli::Run {
+            format,
+            llvm_bitcode_path,
+            link_bitcode_paths,
+            execution_target,
+            cache_executables,
+            quil_rewiring_pragma,
+            mock_readout,
+        } => {
+            let bitcodes = std::iter::once(llvm_bitcode_path)
+                .chain(link_bitcode_paths)
+                .map(std::fs::read)
+                .collect::<std::io::Result<Vec<_>>>()?;
+            let bitcodes = bitcodes.iter().map(Vec::as_slice).collect::<Vec<_>>();
+            let options = PatchOptions {
+                add_main_entrypoint: true,
+                execution_target,
+                cache_executables,
+                quil_rewiring_pragma,
+                ..PatchOptions::default()
+            };
+            let context = inkwell::context::Context::create();
+            let module = match format {
+                QirFormat::ShotCount => {
+                    qcs_sdk_qir::patch_qir_with_qcs_multi(options, &bitcodes, &context)?
+                }
+                QirFormat::Hybrid => {
+                    qcs_sdk_qir::patch_hybrid_qir_with_qcs_multi(options, &bitcodes, &context)?
+                }
+                QirFormat::Unitary => {
+                    qcs_sdk_qir::patch_unitary_qir_with_qcs_multi(options, &bitcodes, &context)?
+                }
+            };
+            let status = match mock_readout {
+                #[cfg(feature = "mock-runtime")]
+                Some(path) => {
+                    let backend = qcs_sdk_qir::runtime::MockBackend::from_table(
+                        &std::fs::read_to_string(path)?,
+                    )?;
+                    qcs_sdk_qir::runtime::run_patched_module_with_backend(&module, backend)?
+                }
+                #[cfg(not(feature = "mock-runtime"))]
+                Some(_) => {
+                    return Err(eyre::eyre!(
+                        "--mock-readout requires the mock-runtime feature"
+                    ))
+                }
+                None => qcs_sdk_qir::runtime::run_patched_module(&module)?,
+            };
+            std::process::exit(status)
+        }
+    }
+}
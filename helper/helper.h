@@ -14,6 +14,7 @@
  * limitations under the License.
  **/
 #include <stdio.h>
+#include <string.h>
 #include "libqcs.h"
 
 typedef struct ExecutableCache
@@ -79,6 +80,39 @@ void panic_on_failure(ExecutionResult *result)
    }
 }
 
+// Empty `result` so that it may be filled again by `execute_on_qvm_into` or `execute_on_qpu_into`,
+// or allocate an empty result if `result` is null. An empty result holds a null handle.
+ExecutionResult *reset_execution_result(ExecutionResult *result)
+{
+   if (result == NULL)
+   {
+      result = (ExecutionResult *)malloc(sizeof(ExecutionResult));
+   }
+   else if (result->tag == ExecutionResult_Error || result->handle != NULL)
+   {
+      free_execution_result(*result);
+   }
+
+   result->tag = ExecutionResult_Handle;
+   result->handle = NULL;
+   return result;
+}
+
+// Execute on the QVM, writing the result into `result` rather than returning a new one.
+void execute_on_qvm_into(Executable *executable, ExecutionResult *result)
+{
+   *result = execute_on_qvm(executable);
+}
+
+// Execute on the QPU `qpu_id`, writing the result into `result` rather than returning a new one.
+void execute_on_qpu_into(Executable *executable, char *qpu_id, ExecutionResult *result)
+{
+   const ExecutionResult *returned = execute_on_qpu(executable, qpu_id);
+   *result = *returned;
+   // `result` now owns the data of the returned result, so only the allocation holding it is freed
+   free((void *)returned);
+}
+
 // Get the bit present at `ro[readout_index]` recorded in shot index `shot_index - 1`.
 // NOTE: shot_index is 1-indexed while the data is stored in a 0-indexed array. Thus, to read out from the first shot, pass `shot_index=1`.
 bool get_readout_bit(ExecutionResult *result, int64_t shot_index, int64_t readout_index)
@@ -123,4 +157,13 @@ bool get_readout_bit(ExecutionResult *result, int64_t shot_index, int64_t readou
 
    bool bit = (ro->data.byte[shot_index - 1][readout_index]);
    return bit;
-}
\ No newline at end of file
+}
+
+// Whether a module patched to keep its original code should execute its programs on QCS, rather than
+// run its original code against another QIR runtime. This is so unless the environment variable
+// `QCS_QIR_USE_QCS` is set to "0" or "false".
+bool use_qcs()
+{
+   const char *value = getenv("QCS_QIR_USE_QCS");
+   return value == NULL || (strcmp(value, "0") != 0 && strcmp(value, "false") != 0);
+}
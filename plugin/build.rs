@@ -0,0 +1,42 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::process::Command;
+
+/// Compile the C++ shim registering the passes against the headers of the LLVM given by
+/// `LLVM_CONFIG`, or the `llvm-config` on the path, which must be the LLVM of the `opt` loading the
+/// plugin.
+fn main() {
+    println!("cargo:rerun-if-changed=shim/QcsPassPlugin.cpp");
+    println!("cargo:rerun-if-env-changed=LLVM_CONFIG");
+
+    let llvm_config = std::env::var("LLVM_CONFIG").unwrap_or_else(|_| String::from("llvm-config"));
+    let output = Command::new(&llvm_config)
+        .arg("--cxxflags")
+        .output()
+        .unwrap_or_else(|error| panic!("failed to run {}: {}", llvm_config, error));
+    assert!(
+        output.status.success(),
+        "{} --cxxflags failed: {}",
+        llvm_config,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let mut build = cc::Build::new();
+    build.cpp(true).file("shim/QcsPassPlugin.cpp");
+    for flag in String::from_utf8_lossy(&output.stdout).split_whitespace() {
+        build.flag(flag);
+    }
+    build.compile("qcs_pass_plugin");
+}
@@ -0,0 +1,148 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Registers the transformations of qcs-sdk-qir as module passes of LLVM's new pass manager:
+//
+//   opt -load-pass-plugin=libqcs_sdk_qir_plugin.so -passes=qcs-patch input.bc -o output.bc
+//
+// Each pass writes the module to bitcode, patches it with qcs_sdk_qir_patch_bitcode, and replaces
+// the contents of the module with the patched bitcode.
+
+#include <cstddef>
+#include <cstdint>
+#include <memory>
+#include <string>
+
+#include "llvm/Bitcode/BitcodeReader.h"
+#include "llvm/Bitcode/BitcodeWriter.h"
+#include "llvm/Config/llvm-config.h"
+#include "llvm/IR/Module.h"
+#include "llvm/IR/PassManager.h"
+#include "llvm/Linker/Linker.h"
+#include "llvm/Passes/PassBuilder.h"
+#include "llvm/Passes/PassPlugin.h"
+#include "llvm/Support/Error.h"
+#include "llvm/Support/ErrorHandling.h"
+#include "llvm/Support/MemoryBuffer.h"
+#include "llvm/Support/raw_ostream.h"
+
+extern "C" {
+int qcs_sdk_qir_patch_bitcode(int format, const uint8_t *bitcode, size_t length,
+                              uint8_t **output, size_t *output_length, char **error);
+void qcs_sdk_qir_free_bitcode(uint8_t *bitcode, size_t length);
+void qcs_sdk_qir_free_error(char *error);
+}
+
+namespace {
+
+// The formats of qcs_sdk_qir::PatchFormat, as numbered by qcs_sdk_qir::plugin.
+enum PatchFormat { ShotCount = 0, Hybrid = 1, Unitary = 2 };
+
+// Empty `module` of its definitions and metadata, then link `patched` into it.
+void replaceModuleContents(llvm::Module &module, std::unique_ptr<llvm::Module> patched) {
+  // Drop every reference between definitions first, so that they may be erased in any order
+  for (auto &function : module) {
+    function.dropAllReferences();
+  }
+  for (auto &global : module.globals()) {
+    global.dropAllReferences();
+  }
+  for (auto &alias : module.aliases()) {
+    alias.dropAllReferences();
+  }
+  while (!module.alias_empty()) {
+    module.alias_begin()->eraseFromParent();
+  }
+  while (!module.empty()) {
+    module.begin()->removeDeadConstantUsers();
+    module.begin()->eraseFromParent();
+  }
+  while (!module.global_empty()) {
+    module.global_begin()->removeDeadConstantUsers();
+    module.global_begin()->eraseFromParent();
+  }
+  while (!module.named_metadata_empty()) {
+    module.eraseNamedMetadata(&*module.named_metadata_begin());
+  }
+
+  module.setTargetTriple(patched->getTargetTriple());
+  module.setDataLayout(patched->getDataLayout());
+  if (llvm::Linker::linkModules(module, std::move(patched))) {
+    llvm::report_fatal_error("qcs-sdk-qir: failed to link the patched module");
+  }
+}
+
+struct QcsPatchPass : llvm::PassInfoMixin<QcsPatchPass> {
+  explicit QcsPatchPass(PatchFormat format) : format(format) {}
+
+  llvm::PreservedAnalyses run(llvm::Module &module, llvm::ModuleAnalysisManager &) {
+    llvm::SmallVector<char, 0> bitcode;
+    llvm::raw_svector_ostream stream(bitcode);
+    llvm::WriteBitcodeToFile(module, stream);
+
+    uint8_t *output = nullptr;
+    size_t output_length = 0;
+    char *error = nullptr;
+    if (qcs_sdk_qir_patch_bitcode(format, reinterpret_cast<const uint8_t *>(bitcode.data()),
+                                  bitcode.size(), &output, &output_length, &error) != 0) {
+      std::string message(error);
+      qcs_sdk_qir_free_error(error);
+      llvm::report_fatal_error("qcs-sdk-qir: " + message);
+    }
+
+    auto buffer = llvm::MemoryBuffer::getMemBufferCopy(
+        llvm::StringRef(reinterpret_cast<const char *>(output), output_length),
+        module.getModuleIdentifier());
+    qcs_sdk_qir_free_bitcode(output, output_length);
+
+    auto patched = llvm::parseBitcodeFile(buffer->getMemBufferRef(), module.getContext());
+    if (!patched) {
+      llvm::report_fatal_error("qcs-sdk-qir: failed to read the patched module: " +
+                               llvm::toString(patched.takeError()));
+    }
+    replaceModuleContents(module, std::move(*patched));
+
+    return llvm::PreservedAnalyses::none();
+  }
+
+  // Run even on modules of functions marked `optnone`
+  static bool isRequired() { return true; }
+
+  PatchFormat format;
+};
+
+} // namespace
+
+extern "C" LLVM_ATTRIBUTE_WEAK ::llvm::PassPluginLibraryInfo llvmGetPassPluginInfo() {
+  return {LLVM_PLUGIN_API_VERSION, "qcs-sdk-qir", LLVM_VERSION_STRING,
+          [](llvm::PassBuilder &builder) {
+            builder.registerPipelineParsingCallback(
+                [](llvm::StringRef name, llvm::ModulePassManager &passes,
+                   llvm::ArrayRef<llvm::PassBuilder::PipelineElement>) {
+                  if (name == "qcs-patch") {
+                    passes.addPass(QcsPatchPass(ShotCount));
+                    return true;
+                  }
+                  if (name == "qcs-patch-hybrid") {
+                    passes.addPass(QcsPatchPass(Hybrid));
+                    return true;
+                  }
+                  if (name == "qcs-patch-unitary") {
+                    passes.addPass(QcsPatchPass(Unitary));
+                    return true;
+                  }
+                  return false;
+                });
+          }};
+}
@@ -0,0 +1,21 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An LLVM pass plugin, loaded with `opt -load-pass-plugin`, which registers the transformations of
+//! `qcs-sdk-qir` as module passes. The pass registration lives in the C++ shim under `shim/`, which
+//! calls back into the C entry points re-exported here.
+
+pub use qcs_sdk_qir::plugin::{
+    qcs_sdk_qir_free_bitcode, qcs_sdk_qir_free_error, qcs_sdk_qir_patch_bitcode,
+};
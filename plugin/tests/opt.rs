@@ -0,0 +1,70 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::PathBuf;
+use std::process::Command;
+
+/// The `opt` of the LLVM given by `LLVM_CONFIG`, or the `llvm-config` on the path, against which the
+/// plugin was built.
+fn opt() -> PathBuf {
+    let llvm_config = std::env::var("LLVM_CONFIG").unwrap_or_else(|_| String::from("llvm-config"));
+    let output = Command::new(&llvm_config)
+        .arg("--bindir")
+        .output()
+        .unwrap_or_else(|error| panic!("failed to run {}: {}", llvm_config, error));
+    assert!(output.status.success(), "{} --bindir failed", llvm_config);
+    PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()).join("opt")
+}
+
+/// The plugin library, which Cargo builds next to the directory of this test's executable.
+fn plugin() -> PathBuf {
+    let mut directory = std::env::current_exe().unwrap();
+    directory.pop();
+    if directory.ends_with("deps") {
+        directory.pop();
+    }
+    directory.join(format!(
+        "{}qcs_sdk_qir_plugin{}",
+        std::env::consts::DLL_PREFIX,
+        std::env::consts::DLL_SUFFIX
+    ))
+}
+
+#[test]
+fn opt_patches_module_with_plugin() {
+    let output = Command::new(opt())
+        .arg(format!("-load-pass-plugin={}", plugin().display()))
+        .arg("-passes=qcs-patch")
+        .args(["-S", "-o", "-"])
+        .arg("../tests/fixtures/programs/bell_state.bc")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "opt failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let ir = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        ir.contains("@executable_from_quil("),
+        "expected the module to be patched:\n{}",
+        ir
+    );
+    assert!(
+        !ir.contains("call void @__quantum__qis__h__body("),
+        "expected the quantum intrinsics to be replaced:\n{}",
+        ir
+    );
+}
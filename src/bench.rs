@@ -0,0 +1,167 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Synthesis of QIR modules of configurable size, for measuring how the cost of transpilation and
+//! patching grows with the module, as by the benchmarks in `benches/`.
+use std::f64::consts::PI;
+
+use eyre::{eyre, Result};
+use inkwell::context::Context;
+
+use crate::example::{build_circuit_module, ExampleProfile, Gate};
+
+/// Options for [`generate_synthetic_qir`]. The module applies `layers` layers to `qubits` qubits,
+/// each of an `H` on every qubit, a chain of `CNOT`s, and an `RZ` on every qubit, then measures
+/// and records every qubit.
+#[derive(Clone, Debug)]
+pub struct SyntheticOptions {
+    /// The shape of the module to generate.
+    pub profile: ExampleProfile,
+    /// The number of qubits on which to apply each layer.
+    pub qubits: u64,
+    /// The number of layers to apply.
+    pub layers: u64,
+    /// The number of distinct angles taken by the `RZ`s, in turn. With none, no `RZ` is applied.
+    pub parameters: u64,
+    /// The number of shots for which the shot count loop runs. Unused by the unitary profile.
+    pub shot_count: u64,
+}
+
+impl Default for SyntheticOptions {
+    fn default() -> Self {
+        Self {
+            profile: ExampleProfile::ShotCount,
+            qubits: 8,
+            layers: 8,
+            parameters: 0,
+            shot_count: 1000,
+        }
+    }
+}
+
+impl SyntheticOptions {
+    /// A shot count module of eight qubits with `layers` layers, run for a million shots.
+    #[must_use]
+    pub fn large_shot_count(layers: u64) -> Self {
+        Self {
+            layers,
+            shot_count: 1_000_000,
+            ..Self::default()
+        }
+    }
+
+    /// A variational ansatz of eight qubits in which each `RZ` takes one of `parameters` angles.
+    #[must_use]
+    pub fn many_parameter_vqe(parameters: u64) -> Self {
+        Self {
+            layers: parameters.max(1),
+            parameters,
+            ..Self::default()
+        }
+    }
+
+    /// A single layer on `qubits` qubits, every one of which is measured and recorded.
+    #[must_use]
+    pub fn wide_measurement(qubits: u64) -> Self {
+        Self {
+            qubits,
+            layers: 1,
+            ..Self::default()
+        }
+    }
+}
+
+/// The gates applied by a synthetic module, in order.
+fn get_synthetic_gates(options: &SyntheticOptions) -> Result<Vec<Gate>> {
+    if options.qubits == 0 {
+        return Err(eyre!("a synthetic module requires a qubit"));
+    }
+
+    let mut gates = vec![];
+    let mut parameter = 0;
+    for _ in 0..options.layers {
+        gates.extend((0..options.qubits).map(Gate::H));
+        gates.extend((1..options.qubits).map(|target| Gate::Cnot(target - 1, target)));
+        if options.parameters > 0 {
+            for qubit in 0..options.qubits {
+                // Spread the angles over (0, pi), so that none is written symbolically.
+                #[allow(clippy::cast_precision_loss)]
+                let angle = PI * (parameter + 1) as f64 / (options.parameters + 1) as f64;
+                gates.push(Gate::Rz(angle, qubit));
+                parameter = (parameter + 1) % options.parameters;
+            }
+        }
+    }
+    Ok(gates)
+}
+
+/// Generate the bitcode of a synthetic QIR module of the size given by `options`.
+///
+/// # Errors
+/// 1. Returns a [`eyre::Report`] if `options` has no qubits.
+pub fn generate_synthetic_qir(options: &SyntheticOptions) -> Result<Vec<u8>> {
+    let gates = get_synthetic_gates(options)?;
+    let context = Context::create();
+    let module = build_circuit_module(
+        &context,
+        options.profile,
+        options.qubits,
+        &gates,
+        options.shot_count,
+    );
+    module
+        .verify()
+        .map_err(|error| eyre!("generated an invalid module: {}", error.to_string()))?;
+    Ok(module.write_bitcode_to_memory().as_slice().to_vec())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn synthetic_modules_grow_with_their_options() {
+        let options = SyntheticOptions {
+            qubits: 3,
+            layers: 2,
+            parameters: 4,
+            ..SyntheticOptions::default()
+        };
+        let gates = get_synthetic_gates(&options).unwrap();
+        assert_eq!(gates.len(), 2 * (3 + 2 + 3));
+        let angles = gates
+            .iter()
+            .filter_map(|gate| match gate {
+                Gate::Rz(angle, _) => Some(angle.to_bits()),
+                _ => None,
+            })
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(angles.len(), 4);
+        assert!(get_synthetic_gates(&SyntheticOptions {
+            qubits: 0,
+            ..SyntheticOptions::default()
+        })
+        .is_err());
+
+        let output = crate::transpile_qir_to_quil(
+            &generate_synthetic_qir(&SyntheticOptions::wide_measurement(64)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(output.shot_count, 1000);
+        assert_eq!(
+            output.program.to_string(true).matches("MEASURE").count(),
+            64
+        );
+    }
+}
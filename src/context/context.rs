@@ -12,21 +12,63 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::Arc;
+
 use eyre::Result;
 
-use crate::interop::load::load_module_from_bitcode;
+use crate::interop::{
+    load::load_and_link_modules_from_bitcode, metadata::get_calibrations, validate::validate_limits,
+};
+
+use crate::transform::custom::CustomInstructionHandler;
 
-use super::{target::ExecutionTarget, types::Types, values::Values};
+use super::target::ExecutionTarget;
+#[cfg(feature = "transform")]
+use super::{types::Types, values::Values};
 
 pub(crate) struct QCSCompilerContext<'ctx> {
     pub(crate) base_context: &'ctx inkwell::context::Context,
     pub(crate) module: inkwell::module::Module<'ctx>,
+    #[cfg(feature = "transform")]
     pub(crate) builder: inkwell::builder::Builder<'ctx>,
+    #[cfg(feature = "transform")]
     pub(crate) types: Types<'ctx>,
+    #[cfg(feature = "transform")]
     pub(crate) values: Values<'ctx>,
+    #[cfg_attr(not(feature = "transform"), allow(dead_code))]
     pub(crate) target: ExecutionTarget,
-    pub(crate) quil_programs: Vec<quil_rs::program::Program>,
+    /// Every Quil program the transformation has inserted into the module, whether or not it is
+    /// cached, in the order inserted. Cached programs are numbered in the same order.
+    #[cfg(feature = "transform")]
+    pub(crate) emitted_programs: Vec<EmittedProgram>,
+    /// Quil-T calibrations carried by the input module, to be included in every generated program
+    pub(crate) calibrations: Option<quil_rs::program::Program>,
     pub(crate) options: ContextOptions,
+    /// When [`ContextOptions::explain`] is set, a description of how pattern matching treated
+    /// each instruction, in the order they were visited
+    pub(crate) explanation: Vec<String>,
+    /// Blocks forming the body of a loop which was unrolled into an enclosing shot count loop, and
+    /// so are not transpiled on their own
+    pub(crate) unrolled_blocks: Vec<inkwell::basic_block::BasicBlock<'ctx>>,
+    /// Blocks injected by the transformation, in the order injected
+    #[cfg(feature = "transform")]
+    pub(crate) injected_blocks: Vec<crate::transform::layout::InjectedBlock<'ctx>>,
+}
+
+/// A Quil program inserted into the module, along with where it was inserted
+#[cfg(feature = "transform")]
+pub(crate) struct EmittedProgram {
+    pub(crate) program: quil_rs::program::Program,
+    /// The name of the function holding the block replaced by the program
+    pub(crate) function: String,
+    /// The name of the block replaced by the program
+    pub(crate) block: String,
+    /// The number of shots for which the program is run, or `None` for a unitary program
+    pub(crate) shot_count: Option<u64>,
+    /// The output recorded from the program's readout
+    pub(crate) recorded_output: Vec<crate::RecordedOutput>,
+    /// The index of the program in the executable cache, if executables are cached
+    pub(crate) cache_index: Option<usize>,
 }
 
 impl<'ctx> QCSCompilerContext<'ctx> {
@@ -36,26 +78,128 @@ impl<'ctx> QCSCompilerContext<'ctx> {
         target: ExecutionTarget,
         options: ContextOptions,
     ) -> Result<Self> {
+        Self::new_from_linked_data(context, &[data], target, options)
+    }
+
+    /// Build a context from several bitcodes, which are linked into a single module in the order given.
+    pub(crate) fn new_from_linked_data(
+        context: &'ctx inkwell::context::Context,
+        data: &[&[u8]],
+        target: ExecutionTarget,
+        options: ContextOptions,
+    ) -> Result<Self> {
+        let module = load_and_link_modules_from_bitcode(context, data)?;
+        validate_limits(&module, &options.limits)?;
+        let calibrations = get_calibrations(&module)?;
+
+        // The runtime ABI is only declared in modules which may be patched to call into it.
+        #[cfg(feature = "transform")]
         let builder = context.create_builder();
-        let module = load_module_from_bitcode(context, data)?;
+        #[cfg(feature = "transform")]
         let types = Types::new(context);
-        let values = Values::new(context, &builder, &module, &types, &target)?;
+        #[cfg(feature = "transform")]
+        let values = Values::new(
+            context,
+            &builder,
+            &module,
+            &types,
+            &target,
+            &options.runtime_flavor,
+            options.user_context,
+        )?;
 
         Ok(Self {
             base_context: context,
+            #[cfg(feature = "transform")]
             builder,
             module,
+            #[cfg(feature = "transform")]
             types,
+            #[cfg(feature = "transform")]
             values,
             target,
-            quil_programs: vec![],
+            #[cfg(feature = "transform")]
+            emitted_programs: vec![],
+            calibrations,
             options,
+            explanation: vec![],
+            unrolled_blocks: vec![],
+            #[cfg(feature = "transform")]
+            injected_blocks: vec![],
         })
     }
+
+    /// Record a line of the pattern-matching explanation, if one was requested. The line is only
+    /// built when explaining, so callers may format instructions freely.
+    pub(crate) fn explain(&mut self, line: impl FnOnce() -> String) {
+        if self.options.explain {
+            self.explanation.push(line());
+        }
+    }
+
+    /// The programs stored in the executable cache, in the order of their index within it.
+    #[cfg(feature = "transform")]
+    pub(crate) fn cached_programs(&self) -> impl Iterator<Item = &quil_rs::program::Program> {
+        self.emitted_programs
+            .iter()
+            .filter(|emitted| emitted.cache_index.is_some())
+            .map(|emitted| &emitted.program)
+    }
 }
 
 #[derive(Default)]
 pub(crate) struct ContextOptions {
+    #[cfg_attr(not(feature = "transform"), allow(dead_code))]
     pub(crate) cache_executables: bool,
     pub(crate) rewiring_pragma: Option<String>,
+    pub(crate) sparse_readout: bool,
+    pub(crate) flip_readout: bool,
+    pub(crate) custom_instruction_handlers: Vec<Arc<dyn CustomInstructionHandler>>,
+    /// Rewrite each Quil program before it is embedded in the module
+    #[cfg(feature = "transform")]
+    pub(crate) quil_post_processors: Vec<Arc<dyn crate::QuilPostProcessor>>,
+    pub(crate) trace: bool,
+    pub(crate) float_precision: Option<usize>,
+    pub(crate) symbolic_pi: bool,
+    pub(crate) optimize_quil: bool,
+    pub(crate) compact_qubits: bool,
+    pub(crate) measurement_order: crate::MeasurementOrder,
+    #[cfg(feature = "transform")]
+    pub(crate) runtime_flavor: crate::RuntimeFlavor,
+    /// Whether every runtime call passes the user context read from its global
+    #[cfg(feature = "transform")]
+    pub(crate) user_context: bool,
+    pub(crate) explain: bool,
+    /// The name of the block holding the shot count loop, when it cannot be found structurally
+    pub(crate) body_block: Option<String>,
+    pub(crate) limits: crate::Limits,
+    #[cfg_attr(not(feature = "transform"), allow(dead_code))]
+    pub(crate) helper_calling_convention: crate::CallingConvention,
+    /// The names of the only functions to transform, if not every function reached from the
+    /// entrypoint
+    #[cfg_attr(not(feature = "transform"), allow(dead_code))]
+    pub(crate) function_allowlist: Option<Vec<String>>,
+    /// The directory into which to write the intermediate state of each basic block transformed
+    #[cfg_attr(not(feature = "transform"), allow(dead_code))]
+    pub(crate) dump_intermediate: Option<std::path::PathBuf>,
+    /// Reviews the shot count of each program before it is compiled in
+    #[cfg(feature = "transform")]
+    pub(crate) shot_count_policy: Option<Arc<dyn crate::ShotCountPolicy>>,
+    /// How to split the shots of each program among executions
+    #[cfg(feature = "transform")]
+    pub(crate) shot_chunking: crate::ShotChunking,
+    /// Whether each execution result is allocated once per call and refilled by each execution
+    #[cfg(feature = "transform")]
+    pub(crate) reuse_execution_results: bool,
+    /// How to handle a qubit which a program shares with a program emitted before it
+    #[cfg(feature = "transform")]
+    pub(crate) qubit_reuse: crate::QubitReuse,
+    /// Whether the blocks of each function changed are reordered and the injected blocks renamed
+    /// once the module is patched
+    #[cfg(feature = "transform")]
+    pub(crate) stable_block_layout: bool,
+    /// Whether a [`crate::TranspileWarning`] fails transpilation rather than being reported
+    pub(crate) deny_warnings: bool,
+    /// What to do with each call to `__quantum__rt__message` among the quantum instructions
+    pub(crate) message_handling: crate::MessageHandling,
 }
@@ -15,7 +15,9 @@
 #[allow(clippy::module_inception)]
 pub(crate) mod context;
 pub(crate) mod target;
+#[cfg(feature = "transform")]
 pub(crate) mod types;
+#[cfg(feature = "transform")]
 pub(crate) mod values;
 
 pub(crate) use context::QCSCompilerContext;
@@ -12,15 +12,85 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::str::FromStr;
+use std::{fmt, str::FromStr};
 
-#[derive(Debug)]
+use eyre::{eyre, Result};
+use lazy_static::lazy_static;
+use log::warn;
+use regex::Regex;
+
+lazy_static! {
+    /// The form of QCS quantum processor IDs: a family name followed by one or more
+    /// hyphen-separated designations, such as `Aspen-11`, `Aspen-M-3`, or `Ankaa-9Q-1`.
+    static ref QPU_ID_REGEX: Regex = Regex::new(r"^[A-Za-z]+(-[A-Za-z0-9]+)+$").unwrap();
+}
+
+/// The target on which Quil is executed. This is written as `qvm` or as the ID of a QPU, both when
+/// parsed and when serialized, so that it may be given in a configuration file.
+#[derive(Clone, Debug, PartialEq, Eq)]
 #[allow(clippy::module_name_repetitions)]
 pub enum ExecutionTarget {
     Qpu(String),
     Qvm,
 }
 
+/// The number of qubit indices addressable on each QPU whose size is known, one more than its
+/// greatest qubit index. Qubits on Aspen processors are numbered by octagon, so on those this
+/// exceeds the number of qubits present.
+const KNOWN_QPU_QUBIT_COUNTS: &[(&str, u64)] = &[
+    ("Aspen-9", 38),
+    ("Aspen-10", 48),
+    ("Aspen-11", 48),
+    ("Aspen-M-1", 148),
+    ("Aspen-M-2", 148),
+    ("Aspen-M-3", 148),
+    ("Ankaa-2", 84),
+    ("Ankaa-3", 84),
+    ("Ankaa-9Q-1", 9),
+    ("Ankaa-9Q-3", 9),
+];
+
+impl ExecutionTarget {
+    /// Parse `s` as `qvm`, in any case, or as a QPU ID, failing if it is not of the form of QCS
+    /// quantum processor IDs, such as `Aspen-M-3`. Unlike [`ExecutionTarget::from_str`], which
+    /// accepts any QPU ID so that new naming schemes need not wait on this crate, this catches
+    /// mistyped targets before a program is patched to run on them.
+    ///
+    /// # Errors
+    /// 1. Returns a [`eyre::Report`] if `s` is neither `qvm` nor of the form of a QPU ID.
+    pub fn parse_strict(s: &str) -> Result<Self> {
+        match Self::parse(s) {
+            Self::Qpu(id) if !QPU_ID_REGEX.is_match(&id) => Err(eyre!(
+                "{:?} is not a valid execution target; expected \"qvm\" or a QPU ID such as \"Aspen-M-3\"",
+                id
+            )),
+            target => Ok(target),
+        }
+    }
+
+    fn parse(s: &str) -> Self {
+        if s.eq_ignore_ascii_case("qvm") {
+            Self::Qvm
+        } else {
+            Self::Qpu(String::from(s))
+        }
+    }
+
+    /// The number of qubit indices addressable on this target, where known: that is, one more than
+    /// its greatest qubit index. The QVM simulates as many qubits as a program uses, so its size is
+    /// never known.
+    #[must_use]
+    pub fn qubit_count(&self) -> Option<u64> {
+        match self {
+            Self::Qpu(id) => KNOWN_QPU_QUBIT_COUNTS
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(id))
+                .map(|(_, count)| *count),
+            Self::Qvm => None,
+        }
+    }
+}
+
 impl Default for ExecutionTarget {
     fn default() -> Self {
         Self::Qvm
@@ -30,10 +100,116 @@ impl Default for ExecutionTarget {
 impl FromStr for ExecutionTarget {
     type Err = String;
 
+    /// Parse `s` as `qvm`, in any case, or otherwise as a QPU ID. A QPU ID which is not of the usual
+    /// form is accepted with a warning; see [`ExecutionTarget::parse_strict`] to reject it instead.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(match s.to_lowercase().as_str() {
-            "qvm" => Self::Qvm,
-            _ => Self::Qpu(String::from(s)),
-        })
+        let target = Self::parse(s);
+        if let Self::Qpu(id) = &target {
+            if !QPU_ID_REGEX.is_match(id) {
+                warn!(
+                    "{:?} is not of the form of a QPU ID, such as \"Aspen-M-3\"; using it as one",
+                    id
+                );
+            }
+        }
+        Ok(target)
+    }
+}
+
+impl fmt::Display for ExecutionTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Qpu(id) => write!(f, "{}", id),
+            Self::Qvm => write!(f, "qvm"),
+        }
+    }
+}
+
+#[cfg(feature = "serde_support")]
+impl serde::Serialize for ExecutionTarget {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde_support")]
+impl<'de> serde::Deserialize<'de> for ExecutionTarget {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let target = String::deserialize(deserializer)?;
+        Self::parse_strict(&target).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn knows_the_size_of_named_qpus() {
+        assert_eq!(
+            ExecutionTarget::from_str("aspen-11").unwrap().qubit_count(),
+            Some(48)
+        );
+        assert_eq!(
+            ExecutionTarget::from_str("Ankaa-9Q-1")
+                .unwrap()
+                .qubit_count(),
+            Some(9)
+        );
+        assert_eq!(
+            ExecutionTarget::from_str("Unknown-1")
+                .unwrap()
+                .qubit_count(),
+            None
+        );
+        assert_eq!(ExecutionTarget::Qvm.qubit_count(), None);
+    }
+
+    #[test]
+    fn strict_parsing_rejects_malformed_qpu_ids() {
+        assert_eq!(
+            ExecutionTarget::parse_strict("QVM").unwrap(),
+            ExecutionTarget::Qvm
+        );
+        for id in ["Aspen-11", "Aspen-M-3", "Ankaa-9Q-1"] {
+            assert_eq!(
+                ExecutionTarget::parse_strict(id).unwrap(),
+                ExecutionTarget::Qpu(String::from(id))
+            );
+        }
+        for id in ["", "Aspen", "Aspen 11", "Aspen-11-", "-11"] {
+            assert!(ExecutionTarget::parse_strict(id).is_err(), "{:?}", id);
+            // Lenient parsing still accepts it.
+            assert_eq!(
+                ExecutionTarget::from_str(id).unwrap(),
+                ExecutionTarget::Qpu(String::from(id))
+            );
+        }
+    }
+
+    #[cfg(feature = "serde_support")]
+    #[test]
+    fn round_trips_through_serde() {
+        for target in [
+            ExecutionTarget::Qvm,
+            ExecutionTarget::Qpu(String::from("Aspen-M-3")),
+        ] {
+            let json = serde_json::to_string(&target).unwrap();
+            assert_eq!(
+                serde_json::from_str::<ExecutionTarget>(&json).unwrap(),
+                target
+            );
+        }
+        assert_eq!(
+            serde_json::to_string(&ExecutionTarget::Qvm).unwrap(),
+            "\"qvm\""
+        );
+        assert!(serde_json::from_str::<ExecutionTarget>("\"Aspen 11\"").is_err());
     }
 }
@@ -16,11 +16,12 @@ use inkwell::{
     context::Context,
     module::Module,
     types::{PointerType, StructType},
-    AddressSpace,
 };
 
+use crate::interop::compat::default_address_space;
+
 fn build_string_type(context: &Context) -> PointerType {
-    context.i8_type().ptr_type(AddressSpace::Generic)
+    context.i8_type().ptr_type(default_address_space())
 }
 
 const TYPE_NAME_EXECUTION_RESULT: &str = "ExecutionResult";
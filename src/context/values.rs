@@ -14,101 +14,183 @@
 
 use eyre::{eyre, Result};
 use inkwell::{
+    attributes::{Attribute, AttributeLoc},
     builder::Builder,
     context::Context,
     module::{Linkage, Module},
     types::BasicMetadataTypeEnum,
     values::{FunctionValue, GlobalValue, PointerValue},
-    AddressSpace,
 };
 
+use crate::error::KindError;
+use crate::interop::abi::RuntimeFlavor;
+use crate::interop::compat::default_address_space;
 use crate::interop::entrypoint::get_entry_function;
 use crate::transform::PARAMETER_MEMORY_REGION_NAME;
 
 use super::{target::ExecutionTarget, types::Types};
 
+/// Name of the global from which every runtime call reads the user context, which the host sets
+/// before calling the entrypoint.
+pub(crate) const USER_CONTEXT_GLOBAL_NAME: &str = "qcs_sdk_qir_user_context";
+
+/// The parameters of a runtime function taking `parameters`, followed by the `i8*` user context
+/// when patching with [`crate::PatchOptions::user_context`].
+fn runtime_parameters<'ctx>(
+    types: &Types<'ctx>,
+    user_context: bool,
+    parameters: &[BasicMetadataTypeEnum<'ctx>],
+) -> Vec<BasicMetadataTypeEnum<'ctx>> {
+    let mut parameters = parameters.to_vec();
+    if user_context {
+        parameters.push(types.string().into());
+    }
+    parameters
+}
+
+/// Declare the global through which the host passes the user context to every runtime call,
+/// reusing it if the module already declares one.
+fn build_user_context<'ctx>(module: &Module<'ctx>, types: &Types<'ctx>) -> GlobalValue<'ctx> {
+    module
+        .get_global(USER_CONTEXT_GLOBAL_NAME)
+        .unwrap_or_else(|| {
+            let user_context = module.add_global(types.string(), None, USER_CONTEXT_GLOBAL_NAME);
+            user_context.set_linkage(Linkage::WeakAny);
+            user_context.set_initializer(&types.string().const_null());
+            user_context
+        })
+}
+
+/// Add the named enum attributes to a runtime function at `location`. These describe how the runtime
+/// treats its arguments, so that LLVM may optimize the code surrounding calls into it: for example,
+/// no runtime function retains a pointer it is passed, and Quil programs and names are only read.
+/// Executables are not marked `readonly` where they are executed, since the runtime may update them.
+fn add_attributes(
+    context: &Context,
+    function: FunctionValue,
+    location: AttributeLoc,
+    attribute_names: &[&str],
+) {
+    for name in attribute_names {
+        let kind_id = Attribute::get_named_enum_kind_id(name);
+        function.add_attribute(location, context.create_enum_attribute(kind_id, 0));
+    }
+}
+
 fn build_executable_from_quil_function<'ctx>(
-    _context: &'ctx Context,
+    context: &'ctx Context,
     _builder: &Builder<'ctx>,
     module: &Module<'ctx>,
     types: &Types<'ctx>,
+    flavor: &RuntimeFlavor,
+    user_context: bool,
 ) -> FunctionValue<'ctx> {
     const FN_NAME_EXECUTABLE_FROM_QUIL: &str = "executable_from_quil";
+    let name = flavor.function_name(FN_NAME_EXECUTABLE_FROM_QUIL);
 
-    if let Some(existing_function) = module.get_function(FN_NAME_EXECUTABLE_FROM_QUIL) {
+    if let Some(existing_function) = module.get_function(&name) {
         existing_function
     } else {
         let string_type = types.string();
         let executable_type = types.executable(module);
-        let executable_pointer_type = executable_type.ptr_type(AddressSpace::Generic);
+        let executable_pointer_type = executable_type.ptr_type(default_address_space());
 
-        let executable_from_quil_type = executable_pointer_type
-            .fn_type(&[BasicMetadataTypeEnum::PointerType(string_type)], false);
-        module.add_function(
-            FN_NAME_EXECUTABLE_FROM_QUIL,
-            executable_from_quil_type,
-            Some(Linkage::External),
-        )
+        let executable_from_quil_type = executable_pointer_type.fn_type(
+            &runtime_parameters(
+                types,
+                user_context,
+                &[BasicMetadataTypeEnum::PointerType(string_type)],
+            ),
+            false,
+        );
+        let function =
+            module.add_function(&name, executable_from_quil_type, Some(Linkage::External));
+        add_attributes(context, function, AttributeLoc::Return, &["noalias"]);
+        add_attributes(
+            context,
+            function,
+            AttributeLoc::Param(0),
+            &["nocapture", "readonly"],
+        );
+        function
     }
 }
 
 fn build_execute_on_qpu_function<'ctx>(
-    _context: &'ctx Context,
+    context: &'ctx Context,
     _builder: &Builder<'ctx>,
     module: &Module<'ctx>,
     types: &Types<'ctx>,
+    flavor: &RuntimeFlavor,
+    user_context: bool,
 ) -> FunctionValue<'ctx> {
     const FN_NAME_EXECUTE_ON_QPU: &str = "execute_on_qpu";
+    let name = flavor.function_name(FN_NAME_EXECUTE_ON_QPU);
 
-    if let Some(existing_function) = module.get_function(FN_NAME_EXECUTE_ON_QPU) {
+    if let Some(existing_function) = module.get_function(&name) {
         existing_function
     } else {
         let string_type = types.string();
         let executable_type = types.executable(module);
-        let executable_pointer_type = executable_type.ptr_type(AddressSpace::Generic);
+        let executable_pointer_type = executable_type.ptr_type(default_address_space());
 
         let execution_result_type = types.execution_result(module);
-        let execution_result_pointer_type = execution_result_type.ptr_type(AddressSpace::Generic);
+        let execution_result_pointer_type = execution_result_type.ptr_type(default_address_space());
         let execute_on_qpu_type = execution_result_pointer_type.fn_type(
-            &[
-                BasicMetadataTypeEnum::PointerType(executable_pointer_type),
-                BasicMetadataTypeEnum::PointerType(string_type),
-            ],
+            &runtime_parameters(
+                types,
+                user_context,
+                &[
+                    BasicMetadataTypeEnum::PointerType(executable_pointer_type),
+                    BasicMetadataTypeEnum::PointerType(string_type),
+                ],
+            ),
             false,
         );
-        module.add_function(
-            FN_NAME_EXECUTE_ON_QPU,
-            execute_on_qpu_type,
-            Some(Linkage::External),
-        )
+        let function = module.add_function(&name, execute_on_qpu_type, Some(Linkage::External));
+        add_attributes(context, function, AttributeLoc::Return, &["noalias"]);
+        add_attributes(context, function, AttributeLoc::Param(0), &["nocapture"]);
+        add_attributes(
+            context,
+            function,
+            AttributeLoc::Param(1),
+            &["nocapture", "readonly"],
+        );
+        function
     }
 }
 
 fn build_execute_on_qvm_function<'ctx>(
-    _context: &'ctx Context,
+    context: &'ctx Context,
     _builder: &Builder<'ctx>,
     module: &Module<'ctx>,
     types: &Types<'ctx>,
+    flavor: &RuntimeFlavor,
+    user_context: bool,
 ) -> FunctionValue<'ctx> {
     const FN_NAME_EXECUTE_ON_QVM: &str = "execute_on_qvm";
+    let name = flavor.function_name(FN_NAME_EXECUTE_ON_QVM);
 
-    if let Some(existing_function) = module.get_function(FN_NAME_EXECUTE_ON_QVM) {
+    if let Some(existing_function) = module.get_function(&name) {
         existing_function
     } else {
         let executable_type = types.executable(module);
-        let executable_pointer_type = executable_type.ptr_type(AddressSpace::Generic);
+        let executable_pointer_type = executable_type.ptr_type(default_address_space());
 
         let execution_result_type = types.execution_result(module);
-        let execution_result_pointer_type = execution_result_type.ptr_type(AddressSpace::Generic);
+        let execution_result_pointer_type = execution_result_type.ptr_type(default_address_space());
         let execute_on_qvm_type = execution_result_pointer_type.fn_type(
-            &[BasicMetadataTypeEnum::PointerType(executable_pointer_type)],
+            &runtime_parameters(
+                types,
+                user_context,
+                &[BasicMetadataTypeEnum::PointerType(executable_pointer_type)],
+            ),
             false,
         );
-        module.add_function(
-            FN_NAME_EXECUTE_ON_QVM,
-            execute_on_qvm_type,
-            Some(Linkage::External),
-        )
+        let function = module.add_function(&name, execute_on_qvm_type, Some(Linkage::External));
+        add_attributes(context, function, AttributeLoc::Return, &["noalias"]);
+        add_attributes(context, function, AttributeLoc::Param(0), &["nocapture"]);
+        function
     }
 }
 
@@ -117,24 +199,29 @@ fn build_free_executable_function<'ctx>(
     _builder: &Builder<'ctx>,
     module: &Module<'ctx>,
     types: &Types<'ctx>,
+    flavor: &RuntimeFlavor,
+    user_context: bool,
 ) -> FunctionValue<'ctx> {
     const FN_NAME_FREE_EXECUTABLE: &str = "free_executable";
+    let name = flavor.function_name(FN_NAME_FREE_EXECUTABLE);
 
-    if let Some(existing_function) = module.get_function(FN_NAME_FREE_EXECUTABLE) {
+    if let Some(existing_function) = module.get_function(&name) {
         existing_function
     } else {
         let executable_type = types.executable(module);
-        let executable_pointer_type = executable_type.ptr_type(AddressSpace::Generic);
+        let executable_pointer_type = executable_type.ptr_type(default_address_space());
 
         let free_executable_type = context.void_type().fn_type(
-            &[BasicMetadataTypeEnum::PointerType(executable_pointer_type)],
+            &runtime_parameters(
+                types,
+                user_context,
+                &[BasicMetadataTypeEnum::PointerType(executable_pointer_type)],
+            ),
             false,
         );
-        module.add_function(
-            FN_NAME_FREE_EXECUTABLE,
-            free_executable_type,
-            Some(Linkage::External),
-        )
+        let function = module.add_function(&name, free_executable_type, Some(Linkage::External));
+        add_attributes(context, function, AttributeLoc::Param(0), &["nocapture"]);
+        function
     }
 }
 
@@ -143,132 +230,193 @@ fn build_free_execution_result_function<'ctx>(
     _builder: &Builder<'ctx>,
     module: &Module<'ctx>,
     types: &Types<'ctx>,
+    flavor: &RuntimeFlavor,
+    user_context: bool,
 ) -> FunctionValue<'ctx> {
     const FN_NAME_FREE_EXECUTION_RESULT: &str = "free_execution_result";
+    let name = flavor.function_name(FN_NAME_FREE_EXECUTION_RESULT);
 
-    if let Some(existing_function) = module.get_function(FN_NAME_FREE_EXECUTION_RESULT) {
+    if let Some(existing_function) = module.get_function(&name) {
         existing_function
     } else {
         let execution_result_type = types.execution_result(module);
-        let execution_result_pointer_type = execution_result_type.ptr_type(AddressSpace::Generic);
+        let execution_result_pointer_type = execution_result_type.ptr_type(default_address_space());
         let free_execution_result_type = context.void_type().fn_type(
-            &[BasicMetadataTypeEnum::PointerType(
-                execution_result_pointer_type,
-            )],
+            &runtime_parameters(
+                types,
+                user_context,
+                &[BasicMetadataTypeEnum::PointerType(
+                    execution_result_pointer_type,
+                )],
+            ),
             false,
         );
-        module.add_function(
-            FN_NAME_FREE_EXECUTION_RESULT,
-            free_execution_result_type,
-            Some(Linkage::External),
-        )
+        let function =
+            module.add_function(&name, free_execution_result_type, Some(Linkage::External));
+        add_attributes(context, function, AttributeLoc::Param(0), &["nocapture"]);
+        function
     }
 }
 
-fn build_create_executable_cache_function<'ctx>(
+/// Name of the runtime function which allocates an empty executable cache. Like the other executable
+/// cache functions, it is only declared when executables are cached.
+pub(crate) const FN_NAME_CREATE_EXECUTABLE_CACHE: &str = "create_executable_cache";
+
+pub(crate) fn build_create_executable_cache_function<'ctx>(
     context: &'ctx Context,
     _builder: &Builder<'ctx>,
     module: &Module<'ctx>,
     types: &Types<'ctx>,
+    flavor: &RuntimeFlavor,
+    user_context: bool,
 ) -> FunctionValue<'ctx> {
-    const FN_NAME_CREATE_EXECUTABLE_CACHE: &str = "create_executable_cache";
+    let name = flavor.function_name(FN_NAME_CREATE_EXECUTABLE_CACHE);
 
-    if let Some(existing_function) = module.get_function(FN_NAME_CREATE_EXECUTABLE_CACHE) {
+    if let Some(existing_function) = module.get_function(&name) {
         existing_function
     } else {
-        module.add_function(
-            FN_NAME_CREATE_EXECUTABLE_CACHE,
+        let function = module.add_function(
+            &name,
             types
                 .executable_cache(module)
-                .ptr_type(AddressSpace::Generic)
-                .fn_type(&[context.i32_type().into()], false),
+                .ptr_type(default_address_space())
+                .fn_type(
+                    &runtime_parameters(types, user_context, &[context.i32_type().into()]),
+                    false,
+                ),
             None,
-        )
+        );
+        add_attributes(context, function, AttributeLoc::Return, &["noalias"]);
+        function
     }
 }
 
-fn build_add_executable_cache_item_function<'ctx>(
+/// Name of the runtime function which adds the executable of a program to the executable cache.
+pub(crate) const FN_NAME_ADD_EXECUTABLE_CACHE_ITEM: &str = "add_executable_cache_item";
+
+pub(crate) fn build_add_executable_cache_item_function<'ctx>(
     context: &'ctx Context,
     _builder: &Builder<'ctx>,
     module: &Module<'ctx>,
     types: &Types<'ctx>,
+    flavor: &RuntimeFlavor,
+    user_context: bool,
 ) -> FunctionValue<'ctx> {
-    const FN_NAME_ADD_EXECUTABLE_CACHE_ITEM: &str = "add_executable_cache_item";
+    let name = flavor.function_name(FN_NAME_ADD_EXECUTABLE_CACHE_ITEM);
 
-    if let Some(existing_function) = module.get_function(FN_NAME_ADD_EXECUTABLE_CACHE_ITEM) {
+    if let Some(existing_function) = module.get_function(&name) {
         existing_function
     } else {
-        module.add_function(
-            FN_NAME_ADD_EXECUTABLE_CACHE_ITEM,
+        let function = module.add_function(
+            &name,
             context.void_type().fn_type(
-                &[
-                    types
-                        .executable_cache(module)
-                        .ptr_type(AddressSpace::Generic)
-                        .into(),
-                    context.i32_type().into(),
-                    types.string().into(),
-                ],
+                &runtime_parameters(
+                    types,
+                    user_context,
+                    &[
+                        types
+                            .executable_cache(module)
+                            .ptr_type(default_address_space())
+                            .into(),
+                        context.i32_type().into(),
+                        types.string().into(),
+                    ],
+                ),
                 false,
             ),
             None,
-        )
+        );
+        add_attributes(context, function, AttributeLoc::Param(0), &["nocapture"]);
+        add_attributes(
+            context,
+            function,
+            AttributeLoc::Param(2),
+            &["nocapture", "readonly"],
+        );
+        function
     }
 }
 
-fn build_read_from_executable_cache_function<'ctx>(
+/// Name of the runtime function which reads an executable from the executable cache.
+pub(crate) const FN_NAME_READ_FROM_EXECUTABLE_CACHE: &str = "read_from_executable_cache";
+
+pub(crate) fn build_read_from_executable_cache_function<'ctx>(
     context: &'ctx Context,
     _builder: &Builder<'ctx>,
     module: &Module<'ctx>,
     types: &Types<'ctx>,
+    flavor: &RuntimeFlavor,
+    user_context: bool,
 ) -> FunctionValue<'ctx> {
-    const FN_NAME_READ_FROM_EXECTUABLE_CACHE: &str = "read_from_executable_cache";
+    let name = flavor.function_name(FN_NAME_READ_FROM_EXECUTABLE_CACHE);
 
-    if let Some(existing_function) = module.get_function(FN_NAME_READ_FROM_EXECTUABLE_CACHE) {
+    if let Some(existing_function) = module.get_function(&name) {
         existing_function
     } else {
-        module.add_function(
-            FN_NAME_READ_FROM_EXECTUABLE_CACHE,
+        let function = module.add_function(
+            &name,
             types
                 .executable(module)
-                .ptr_type(AddressSpace::Generic)
+                .ptr_type(default_address_space())
                 .fn_type(
-                    &[
-                        types
-                            .executable_cache(module)
-                            .ptr_type(AddressSpace::Generic)
-                            .into(),
-                        context.i32_type().into(),
-                    ],
+                    &runtime_parameters(
+                        types,
+                        user_context,
+                        &[
+                            types
+                                .executable_cache(module)
+                                .ptr_type(default_address_space())
+                                .into(),
+                            context.i32_type().into(),
+                        ],
+                    ),
                     false,
                 ),
             None,
-        )
+        );
+        add_attributes(
+            context,
+            function,
+            AttributeLoc::Param(0),
+            &["nocapture", "readonly"],
+        );
+        function
     }
 }
 
-fn build_free_executable_cache_function<'ctx>(
+/// Name of the runtime function which frees the executable cache.
+pub(crate) const FN_NAME_FREE_EXECUTABLE_CACHE: &str = "free_executable_cache";
+
+pub(crate) fn build_free_executable_cache_function<'ctx>(
     context: &'ctx Context,
     _builder: &Builder<'ctx>,
     module: &Module<'ctx>,
     types: &Types<'ctx>,
+    flavor: &RuntimeFlavor,
+    user_context: bool,
 ) -> FunctionValue<'ctx> {
-    const FN_NAME_FREE_EXECUTABLE_CACHE: &str = "free_executable_cache";
+    let name = flavor.function_name(FN_NAME_FREE_EXECUTABLE_CACHE);
 
-    if let Some(existing_function) = module.get_function(FN_NAME_FREE_EXECUTABLE_CACHE) {
+    if let Some(existing_function) = module.get_function(&name) {
         existing_function
     } else {
-        module.add_function(
-            FN_NAME_FREE_EXECUTABLE_CACHE,
+        let function = module.add_function(
+            &name,
             context.void_type().fn_type(
-                &[types
-                    .executable_cache(module)
-                    .ptr_type(AddressSpace::Generic)
-                    .into()],
+                &runtime_parameters(
+                    types,
+                    user_context,
+                    &[types
+                        .executable_cache(module)
+                        .ptr_type(default_address_space())
+                        .into()],
+                ),
                 false,
             ),
             None,
-        )
+        );
+        add_attributes(context, function, AttributeLoc::Param(0), &["nocapture"]);
+        function
     }
 }
 
@@ -277,31 +425,41 @@ fn build_get_readout_bit_function<'ctx>(
     _builder: &Builder<'ctx>,
     module: &Module<'ctx>,
     types: &Types<'ctx>,
+    flavor: &RuntimeFlavor,
+    user_context: bool,
 ) -> FunctionValue<'ctx> {
     const FN_NAME_GET_READOUT_BIT: &str = "get_readout_bit";
+    let name = flavor.function_name(FN_NAME_GET_READOUT_BIT);
 
-    if let Some(existing_function) = module.get_function(FN_NAME_GET_READOUT_BIT) {
+    if let Some(existing_function) = module.get_function(&name) {
         existing_function
     } else {
         let execution_result_type = types.execution_result(module);
-        let execution_result_pointer_type = execution_result_type.ptr_type(AddressSpace::Generic);
+        let execution_result_pointer_type = execution_result_type.ptr_type(default_address_space());
 
         let i64_type = context.i64_type();
 
         let get_readout_bit_type = context.bool_type().fn_type(
-            &[
-                BasicMetadataTypeEnum::PointerType(execution_result_pointer_type),
-                BasicMetadataTypeEnum::IntType(i64_type),
-                BasicMetadataTypeEnum::IntType(i64_type),
-            ],
+            &runtime_parameters(
+                types,
+                user_context,
+                &[
+                    BasicMetadataTypeEnum::PointerType(execution_result_pointer_type),
+                    BasicMetadataTypeEnum::IntType(i64_type),
+                    BasicMetadataTypeEnum::IntType(i64_type),
+                ],
+            ),
             false,
         );
 
-        module.add_function(
-            FN_NAME_GET_READOUT_BIT,
-            get_readout_bit_type,
-            Some(Linkage::External),
-        )
+        let function = module.add_function(&name, get_readout_bit_type, Some(Linkage::External));
+        add_attributes(
+            context,
+            function,
+            AttributeLoc::Param(0),
+            &["nocapture", "readonly"],
+        );
+        function
     }
 }
 
@@ -310,14 +468,17 @@ fn build_set_param_function<'ctx>(
     _builder: &Builder<'ctx>,
     module: &Module<'ctx>,
     types: &Types<'ctx>,
+    flavor: &RuntimeFlavor,
+    user_context: bool,
 ) -> FunctionValue<'ctx> {
     const FN_NAME_SET_PARAM: &str = "set_param";
+    let name = flavor.function_name(FN_NAME_SET_PARAM);
 
-    if let Some(existing_function) = module.get_function(FN_NAME_SET_PARAM) {
+    if let Some(existing_function) = module.get_function(&name) {
         existing_function
     } else {
         let executable_type = types.executable(module);
-        let executable_pointer_type = executable_type.ptr_type(AddressSpace::Generic);
+        let executable_pointer_type = executable_type.ptr_type(default_address_space());
 
         let string_type = types.string();
         let name_type = string_type;
@@ -325,16 +486,28 @@ fn build_set_param_function<'ctx>(
         let value_type = context.f64_type();
 
         let set_param_type = context.void_type().fn_type(
-            &[
-                executable_pointer_type.into(),
-                name_type.into(),
-                index_type.into(),
-                value_type.into(),
-            ],
+            &runtime_parameters(
+                types,
+                user_context,
+                &[
+                    executable_pointer_type.into(),
+                    name_type.into(),
+                    index_type.into(),
+                    value_type.into(),
+                ],
+            ),
             false,
         );
 
-        module.add_function(FN_NAME_SET_PARAM, set_param_type, Some(Linkage::External))
+        let function = module.add_function(&name, set_param_type, Some(Linkage::External));
+        add_attributes(context, function, AttributeLoc::Param(0), &["nocapture"]);
+        add_attributes(
+            context,
+            function,
+            AttributeLoc::Param(1),
+            &["nocapture", "readonly"],
+        );
+        function
     }
 }
 
@@ -343,27 +516,37 @@ fn build_panic_on_failure_function<'ctx>(
     _builder: &Builder<'ctx>,
     module: &Module<'ctx>,
     types: &Types<'ctx>,
+    flavor: &RuntimeFlavor,
+    user_context: bool,
 ) -> FunctionValue<'ctx> {
     const FN_NAME_PANIC_ON_FAILURE: &str = "panic_on_failure";
+    let name = flavor.function_name(FN_NAME_PANIC_ON_FAILURE);
 
-    if let Some(existing_function) = module.get_function(FN_NAME_PANIC_ON_FAILURE) {
+    if let Some(existing_function) = module.get_function(&name) {
         existing_function
     } else {
         let execution_result_type = types.execution_result(module);
-        let execution_result_pointer_type = execution_result_type.ptr_type(AddressSpace::Generic);
+        let execution_result_pointer_type = execution_result_type.ptr_type(default_address_space());
 
         let panic_type = context.void_type().fn_type(
-            &[BasicMetadataTypeEnum::PointerType(
-                execution_result_pointer_type,
-            )],
+            &runtime_parameters(
+                types,
+                user_context,
+                &[BasicMetadataTypeEnum::PointerType(
+                    execution_result_pointer_type,
+                )],
+            ),
             false,
         );
 
-        module.add_function(
-            FN_NAME_PANIC_ON_FAILURE,
-            panic_type,
-            Some(Linkage::External),
-        )
+        let function = module.add_function(&name, panic_type, Some(Linkage::External));
+        add_attributes(
+            context,
+            function,
+            AttributeLoc::Param(0),
+            &["nocapture", "readonly"],
+        );
+        function
     }
 }
 
@@ -416,29 +599,155 @@ fn build_wrap_in_shots_function<'ctx>(
     _builder: &Builder<'ctx>,
     module: &Module<'ctx>,
     types: &Types<'ctx>,
+    flavor: &RuntimeFlavor,
+    user_context: bool,
 ) -> FunctionValue<'ctx> {
     const FN_NAME_WRAP_IN_SHOTS: &str = "wrap_in_shots";
+    let name = flavor.function_name(FN_NAME_WRAP_IN_SHOTS);
 
-    if let Some(existing_function) = module.get_function(FN_NAME_WRAP_IN_SHOTS) {
+    if let Some(existing_function) = module.get_function(&name) {
         existing_function
     } else {
         let executable_type = types.executable(module);
-        let executable_pointer_type = executable_type.ptr_type(AddressSpace::Generic);
+        let executable_pointer_type = executable_type.ptr_type(default_address_space());
         let i32_type = context.i32_type();
 
         let wrap_in_shots_type = context.void_type().fn_type(
-            &[
-                BasicMetadataTypeEnum::PointerType(executable_pointer_type),
-                BasicMetadataTypeEnum::IntType(i32_type),
-            ],
+            &runtime_parameters(
+                types,
+                user_context,
+                &[
+                    BasicMetadataTypeEnum::PointerType(executable_pointer_type),
+                    BasicMetadataTypeEnum::IntType(i32_type),
+                ],
+            ),
+            false,
+        );
+
+        let function = module.add_function(&name, wrap_in_shots_type, Some(Linkage::External));
+        add_attributes(context, function, AttributeLoc::Param(0), &["nocapture"]);
+        function
+    }
+}
+
+/// Name of the runtime function which empties an execution result for reuse, or allocates an empty
+/// one. Like the other functions reusing execution results, it is only declared when used.
+pub(crate) const FN_NAME_RESET_EXECUTION_RESULT: &str = "reset_execution_result";
+
+/// Name of the runtime function which executes on the QVM into an existing execution result.
+pub(crate) const FN_NAME_EXECUTE_ON_QVM_INTO: &str = "execute_on_qvm_into";
+
+/// Name of the runtime function which executes on a QPU into an existing execution result.
+pub(crate) const FN_NAME_EXECUTE_ON_QPU_INTO: &str = "execute_on_qpu_into";
+
+pub(crate) fn build_reset_execution_result_function<'ctx>(
+    context: &'ctx Context,
+    _builder: &Builder<'ctx>,
+    module: &Module<'ctx>,
+    types: &Types<'ctx>,
+    flavor: &RuntimeFlavor,
+    user_context: bool,
+) -> FunctionValue<'ctx> {
+    let name = flavor.function_name(FN_NAME_RESET_EXECUTION_RESULT);
+
+    if let Some(existing_function) = module.get_function(&name) {
+        existing_function
+    } else {
+        let execution_result_type = types.execution_result(module);
+        let execution_result_pointer_type = execution_result_type.ptr_type(default_address_space());
+        let reset_execution_result_type = execution_result_pointer_type.fn_type(
+            &runtime_parameters(
+                types,
+                user_context,
+                &[BasicMetadataTypeEnum::PointerType(
+                    execution_result_pointer_type,
+                )],
+            ),
+            false,
+        );
+        let function =
+            module.add_function(&name, reset_execution_result_type, Some(Linkage::External));
+        add_attributes(context, function, AttributeLoc::Param(0), &["nocapture"]);
+        function
+    }
+}
+
+pub(crate) fn build_execute_on_qvm_into_function<'ctx>(
+    context: &'ctx Context,
+    _builder: &Builder<'ctx>,
+    module: &Module<'ctx>,
+    types: &Types<'ctx>,
+    flavor: &RuntimeFlavor,
+    user_context: bool,
+) -> FunctionValue<'ctx> {
+    let name = flavor.function_name(FN_NAME_EXECUTE_ON_QVM_INTO);
+
+    if let Some(existing_function) = module.get_function(&name) {
+        existing_function
+    } else {
+        let executable_pointer_type = types.executable(module).ptr_type(default_address_space());
+        let execution_result_pointer_type = types
+            .execution_result(module)
+            .ptr_type(default_address_space());
+        let execute_on_qvm_into_type = context.void_type().fn_type(
+            &runtime_parameters(
+                types,
+                user_context,
+                &[
+                    BasicMetadataTypeEnum::PointerType(executable_pointer_type),
+                    BasicMetadataTypeEnum::PointerType(execution_result_pointer_type),
+                ],
+            ),
             false,
         );
+        let function =
+            module.add_function(&name, execute_on_qvm_into_type, Some(Linkage::External));
+        add_attributes(context, function, AttributeLoc::Param(0), &["nocapture"]);
+        add_attributes(context, function, AttributeLoc::Param(1), &["nocapture"]);
+        function
+    }
+}
+
+pub(crate) fn build_execute_on_qpu_into_function<'ctx>(
+    context: &'ctx Context,
+    _builder: &Builder<'ctx>,
+    module: &Module<'ctx>,
+    types: &Types<'ctx>,
+    flavor: &RuntimeFlavor,
+    user_context: bool,
+) -> FunctionValue<'ctx> {
+    let name = flavor.function_name(FN_NAME_EXECUTE_ON_QPU_INTO);
 
-        module.add_function(
-            FN_NAME_WRAP_IN_SHOTS,
-            wrap_in_shots_type,
-            Some(Linkage::External),
-        )
+    if let Some(existing_function) = module.get_function(&name) {
+        existing_function
+    } else {
+        let executable_pointer_type = types.executable(module).ptr_type(default_address_space());
+        let execution_result_pointer_type = types
+            .execution_result(module)
+            .ptr_type(default_address_space());
+        let execute_on_qpu_into_type = context.void_type().fn_type(
+            &runtime_parameters(
+                types,
+                user_context,
+                &[
+                    BasicMetadataTypeEnum::PointerType(executable_pointer_type),
+                    BasicMetadataTypeEnum::PointerType(types.string()),
+                    BasicMetadataTypeEnum::PointerType(execution_result_pointer_type),
+                ],
+            ),
+            false,
+        );
+        let function =
+            module.add_function(&name, execute_on_qpu_into_type, Some(Linkage::External));
+        add_attributes(context, function, AttributeLoc::Param(0), &["nocapture"]);
+        add_attributes(
+            context,
+            function,
+            AttributeLoc::Param(1),
+            &["nocapture", "readonly"],
+        );
+        add_attributes(context, function, AttributeLoc::Param(2), &["nocapture"]);
+        function
     }
 }
 
@@ -455,11 +764,10 @@ pub(crate) struct Values<'ctx> {
     set_param_function: FunctionValue<'ctx>,
     wrap_in_shots_function: FunctionValue<'ctx>,
 
-    executable_cache: GlobalValue<'ctx>,
-    create_executable_cache: FunctionValue<'ctx>,
-    add_executable_cache_item: FunctionValue<'ctx>,
-    read_from_executable_cache: FunctionValue<'ctx>,
-    free_executable_cache: FunctionValue<'ctx>,
+    /// The global holding the executable cache, or `None` once removed from the module.
+    executable_cache: Option<GlobalValue<'ctx>>,
+
+    user_context: Option<GlobalValue<'ctx>>,
 }
 
 impl<'ctx> Values<'ctx> {
@@ -490,12 +798,16 @@ impl<'ctx> Values<'ctx> {
         module: &Module<'ctx>,
         types: &Types<'ctx>,
         target: &ExecutionTarget,
+        flavor: &RuntimeFlavor,
+        user_context: bool,
     ) -> Result<Self> {
         // To create global values, the builder must be positioned inside a basic block even if it never writes within that basic block.
         // see https://github.com/TheDan64/inkwell/issues/32
         let basic_block = get_entry_function(module)
             .and_then(FunctionValue::get_first_basic_block)
-            .ok_or_else(|| eyre!("QIR expected entrypoint not found"))?;
+            .ok_or_else(|| {
+                KindError::MissingEntrypoint(String::from("QIR expected entrypoint not found"))
+            })?;
         builder.position_at_end(basic_block);
 
         let executable_cache = module
@@ -505,7 +817,7 @@ impl<'ctx> Values<'ctx> {
                     module.add_global(
                         types
                             .executable_cache(module)
-                            .ptr_type(AddressSpace::Generic),
+                            .ptr_type(default_address_space()),
                         None,
                         "executable_cache",
                     ),
@@ -517,27 +829,68 @@ impl<'ctx> Values<'ctx> {
         executable_cache.set_externally_initialized(false);
         let initializer = types
             .executable_cache(module)
-            .ptr_type(AddressSpace::Generic)
+            .ptr_type(default_address_space())
             .const_zero();
         executable_cache.set_initializer(&initializer);
 
+        let user_context = user_context.then(|| build_user_context(module, types));
+
         Ok(Self {
             executable_from_quil_function: build_executable_from_quil_function(
-                context, builder, module, types,
+                context,
+                builder,
+                module,
+                types,
+                flavor,
+                user_context.is_some(),
+            ),
+            execute_on_qpu_function: build_execute_on_qpu_function(
+                context,
+                builder,
+                module,
+                types,
+                flavor,
+                user_context.is_some(),
+            ),
+            execute_on_qvm_function: build_execute_on_qvm_function(
+                context,
+                builder,
+                module,
+                types,
+                flavor,
+                user_context.is_some(),
             ),
-            execute_on_qpu_function: build_execute_on_qpu_function(context, builder, module, types),
-            execute_on_qvm_function: build_execute_on_qvm_function(context, builder, module, types),
             free_executable_function: build_free_executable_function(
-                context, builder, module, types,
+                context,
+                builder,
+                module,
+                types,
+                flavor,
+                user_context.is_some(),
             ),
             free_execution_result_function: build_free_execution_result_function(
-                context, builder, module, types,
+                context,
+                builder,
+                module,
+                types,
+                flavor,
+                user_context.is_some(),
             ),
             get_readout_bit_function: build_get_readout_bit_function(
-                context, builder, module, types,
+                context,
+                builder,
+                module,
+                types,
+                flavor,
+                user_context.is_some(),
             ),
             panic_on_failure_function: build_panic_on_failure_function(
-                context, builder, module, types,
+                context,
+                builder,
+                module,
+                types,
+                flavor,
+                user_context.is_some(),
             ),
             parameter_memory_region_name: build_parameter_memory_region_name(
                 context, builder, module, types,
@@ -545,25 +898,33 @@ impl<'ctx> Values<'ctx> {
             quantum_processor_id: build_quantum_processor_id(
                 context, builder, module, types, target,
             ),
-            set_param_function: build_set_param_function(context, builder, module, types),
-            wrap_in_shots_function: build_wrap_in_shots_function(context, builder, module, types),
-
-            executable_cache,
-            create_executable_cache: build_create_executable_cache_function(
-                context, builder, module, types,
-            ),
-            add_executable_cache_item: build_add_executable_cache_item_function(
-                context, builder, module, types,
-            ),
-            read_from_executable_cache: build_read_from_executable_cache_function(
-                context, builder, module, types,
+            set_param_function: build_set_param_function(
+                context,
+                builder,
+                module,
+                types,
+                flavor,
+                user_context.is_some(),
             ),
-            free_executable_cache: build_free_executable_cache_function(
-                context, builder, module, types,
+            wrap_in_shots_function: build_wrap_in_shots_function(
+                context,
+                builder,
+                module,
+                types,
+                flavor,
+                user_context.is_some(),
             ),
+
+            executable_cache: Some(executable_cache),
+            user_context,
         })
     }
 
+    /// Get a reference to the values's user context, if runtime calls pass one.
+    pub(crate) fn user_context(&self) -> Option<GlobalValue<'ctx>> {
+        self.user_context
+    }
+
     /// Get a reference to the values's panic on failure function.
     pub(crate) fn panic_on_failure_function(&self) -> FunctionValue<'ctx> {
         self.panic_on_failure_function
@@ -600,29 +961,42 @@ impl<'ctx> Values<'ctx> {
         self.free_execution_result_function
     }
 
-    /// Get a reference to the values's create executable cache.
-    pub(crate) fn create_executable_cache(&self) -> FunctionValue<'ctx> {
-        self.create_executable_cache
-    }
-
-    /// Get a reference to the values's add executable cache item.
-    pub(crate) fn add_executable_cache_item(&self) -> FunctionValue<'ctx> {
-        self.add_executable_cache_item
-    }
-
-    /// Get a reference to the values's read from executable cache.
-    pub(crate) fn read_from_executable_cache(&self) -> FunctionValue<'ctx> {
-        self.read_from_executable_cache
-    }
-
-    /// Get a reference to the values's free executable cache.
-    #[allow(dead_code)]
-    pub(crate) fn free_executable_cache(&self) -> FunctionValue<'ctx> {
-        self.free_executable_cache
+    /// Every runtime function declared in the module.
+    pub(crate) fn runtime_functions(&self) -> [FunctionValue<'ctx>; 9] {
+        [
+            self.free_executable_function,
+            self.free_execution_result_function,
+            self.executable_from_quil_function,
+            self.execute_on_qpu_function,
+            self.execute_on_qvm_function,
+            self.get_readout_bit_function,
+            self.panic_on_failure_function,
+            self.set_param_function,
+            self.wrap_in_shots_function,
+        ]
     }
 
     /// Get a reference to the values's executable cache.
-    pub(crate) fn executable_cache(&self) -> GlobalValue<'ctx> {
+    ///
+    /// # Errors
+    /// 1. Returns a [`eyre::Report`] if the executable cache was removed from the module.
+    pub(crate) fn executable_cache(&self) -> Result<GlobalValue<'ctx>> {
         self.executable_cache
+            .ok_or_else(|| eyre!("the executable cache was removed from the module"))
+    }
+
+    /// Delete the executable cache from the module if nothing uses it.
+    pub(crate) fn remove_unused_executable_cache(&mut self) {
+        if let Some(executable_cache) = self.executable_cache {
+            if executable_cache
+                .as_pointer_value()
+                .get_first_use()
+                .is_none()
+            {
+                self.executable_cache = None;
+                // SAFETY: the global has no uses, and its only handle was just cleared.
+                unsafe { executable_cache.delete() };
+            }
+        }
     }
 }
@@ -0,0 +1,173 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Unified diffs between the textual IR of a module before and after it was patched, so that the
+//! changes made by the transformation may be reviewed.
+
+use eyre::Result;
+use inkwell::{context::Context, module::Module};
+use similar::TextDiff;
+
+use crate::interop::load::load_and_link_modules_from_bitcode;
+
+/// The number of unchanged lines shown around each change.
+const CONTEXT_LINES: usize = 3;
+
+/// How much of the module to diff.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiffScope {
+    /// The whole module, including declarations, globals, and attributes.
+    Module,
+    /// Only the definitions of functions which were changed or added, each diffed separately. In a
+    /// large module, this omits the functions which patching left alone.
+    AffectedFunctions,
+}
+
+/// Diff the textual IR of the module linked from `bitcodes`, as it was before patching, against
+/// that of `patched`.
+///
+/// # Errors
+/// 1. Returns a [`eyre::Report`] if the bitcodes cannot be loaded and linked.
+pub fn diff_patched_ir(bitcodes: &[&[u8]], patched: &Module, scope: DiffScope) -> Result<String> {
+    let context = Context::create();
+    let original = load_and_link_modules_from_bitcode(&context, bitcodes)?;
+    Ok(diff_ir(
+        original.print_to_string().to_str()?,
+        patched.print_to_string().to_str()?,
+        scope,
+    ))
+}
+
+/// Diff two modules' textual IR, returning an empty string if they do not differ within `scope`.
+#[must_use]
+pub fn diff_ir(original: &str, patched: &str, scope: DiffScope) -> String {
+    match scope {
+        DiffScope::Module => unified_diff(original, patched, "original", "patched"),
+        DiffScope::AffectedFunctions => {
+            let original_functions = get_function_definitions(original);
+            get_function_definitions(patched)
+                .into_iter()
+                .map(|(name, definition)| {
+                    let before = original_functions
+                        .iter()
+                        .find(|(original_name, _)| *original_name == name)
+                        .map_or("", |(_, definition)| definition);
+                    unified_diff(
+                        before,
+                        definition,
+                        &format!("original @{}", name),
+                        &format!("patched @{}", name),
+                    )
+                })
+                .collect()
+        }
+    }
+}
+
+fn unified_diff(original: &str, patched: &str, original_name: &str, patched_name: &str) -> String {
+    if original == patched {
+        return String::new();
+    }
+    TextDiff::from_lines(original, patched)
+        .unified_diff()
+        .context_radius(CONTEXT_LINES)
+        .header(original_name, patched_name)
+        .to_string()
+}
+
+/// Split textual IR into the definition of each function, keyed by name, in the order they appear.
+/// A definition runs from its `define` line to the closing brace at the start of a line.
+fn get_function_definitions(ir: &str) -> Vec<(&str, &str)> {
+    let mut definitions = vec![];
+    let mut offset = 0;
+    let mut start = None;
+    for line in ir.split_inclusive('\n') {
+        if line.starts_with("define ") {
+            start = Some(offset);
+        } else if line.starts_with('}') {
+            if let Some(start) = start.take() {
+                let definition = &ir[start..offset + line.len()];
+                if let Some(name) = get_function_name(definition) {
+                    definitions.push((name, definition));
+                }
+            }
+        }
+        offset += line.len();
+    }
+    definitions
+}
+
+fn get_function_name(definition: &str) -> Option<&str> {
+    let name = &definition[definition.find('@')? + 1..];
+    Some(&name[..name.find('(')?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL: &str = "declare void @g()
+
+define void @f() {
+entry:
+  call void @g()
+  ret void
+}
+
+define void @unchanged() {
+entry:
+  ret void
+}
+";
+
+    const PATCHED: &str = "declare void @g()
+
+declare void @h()
+
+define void @f() {
+entry:
+  call void @h()
+  ret void
+}
+
+define void @unchanged() {
+entry:
+  ret void
+}
+
+define void @added() {
+entry:
+  ret void
+}
+";
+
+    #[test]
+    fn diffs_the_whole_module() {
+        let diff = diff_ir(ORIGINAL, PATCHED, DiffScope::Module);
+        assert!(diff.starts_with("--- original\n+++ patched\n"));
+        assert!(diff.contains("+declare void @h()\n"));
+        assert!(diff.contains("-  call void @g()\n+  call void @h()\n"));
+        assert!(diff_ir(ORIGINAL, ORIGINAL, DiffScope::Module).is_empty());
+    }
+
+    #[test]
+    fn diffs_only_affected_functions() {
+        let diff = diff_ir(ORIGINAL, PATCHED, DiffScope::AffectedFunctions);
+        assert!(diff.contains("--- original @f\n+++ patched @f\n"));
+        assert!(diff.contains("-  call void @g()\n+  call void @h()\n"));
+        assert!(diff.contains("--- original @added\n+++ patched @added\n"));
+        assert!(!diff.contains("@unchanged"));
+        assert!(!diff.contains("declare void @h()"));
+    }
+}
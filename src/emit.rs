@@ -0,0 +1,74 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Emission of patched modules as native code, so that a QIR program may be taken to a linkable
+//! artifact without a separate invocation of `llc`.
+
+use std::path::Path;
+
+use eyre::{eyre, Result};
+use inkwell::{
+    module::Module,
+    targets::{CodeModel, FileType, InitializationConfig, RelocMode, Target, TargetMachine},
+    OptimizationLevel,
+};
+
+/// Compile the given module to a relocatable object file at `path`, for the target triple set on the
+/// module. If the module has no target triple, the host triple is used.
+///
+/// # Errors
+/// 1. Returns a [`eyre::Report`] if the target is unavailable in this build of LLVM or if code
+///    generation fails.
+pub fn write_object_file(module: &Module, path: &Path) -> Result<()> {
+    Target::initialize_native(&InitializationConfig::default()).map_err(|e| eyre!(e))?;
+
+    let host_triple = TargetMachine::get_default_triple();
+    if module.get_triple().as_str().to_bytes().is_empty() {
+        module.set_triple(&host_triple);
+    }
+    let triple = module.get_triple();
+    let triple_name = triple.as_str().to_string_lossy();
+
+    // CPU name and features are only meaningful when compiling for the machine we're running on.
+    let (cpu, features) = if triple.as_str() == host_triple.as_str() {
+        (
+            TargetMachine::get_host_cpu_name().to_string(),
+            TargetMachine::get_host_cpu_features().to_string(),
+        )
+    } else {
+        (String::from("generic"), String::new())
+    };
+
+    let target = Target::from_triple(&triple).map_err(|e| {
+        eyre!(e.to_string()).wrap_err(format!("no target available for triple {}", triple_name))
+    })?;
+    let target_machine = target
+        .create_target_machine(
+            &triple,
+            &cpu,
+            &features,
+            OptimizationLevel::Default,
+            RelocMode::PIC,
+            CodeModel::Default,
+        )
+        .ok_or_else(|| eyre!("unable to create target machine for triple {}", triple_name))?;
+
+    if module.get_data_layout().as_str().to_bytes().is_empty() {
+        module.set_data_layout(&target_machine.get_target_data().get_data_layout());
+    }
+
+    target_machine
+        .write_to_file(module, FileType::Object, path)
+        .map_err(|e| eyre!(e.to_string()).wrap_err("failed to write object file"))
+}
@@ -0,0 +1,147 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Classification of the errors returned when transpilation fails. Errors are reported as
+//! [`eyre::Report`]s with human readable messages; a [`TranspileError`] pairs such a message with
+//! the [`TranspileErrorKind`] of failure, so that callers and tests may depend on why transpilation
+//! failed without depending on the wording of the message. Each kind is exercised by a program in
+//! `tests/fixtures/invalid`.
+
+use std::fmt;
+
+use eyre::Report;
+use thiserror::Error;
+
+use crate::{transpile_qir_to_quil, ProgramOutput};
+
+/// Why transpilation failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TranspileErrorKind {
+    /// The input is not valid LLVM bitcode.
+    InvalidBitcode,
+    /// The module has no entrypoint: neither a function with the `EntryPoint` attribute nor
+    /// `QuantumApplication__Run__body`.
+    MissingEntrypoint,
+    /// The module uses an instruction which cannot be transpiled, such as `switch` or `atomicrmw`.
+    UnsupportedInstruction,
+    /// A quantum intrinsic or output recording function was called with an argument of the wrong
+    /// type, or one whose value could not be determined.
+    InvalidArgument,
+    /// The tuples and arrays of recorded output are unbalanced or improperly nested.
+    UnbalancedRecordedOutput,
+    /// The module or the Quil program exceeds a configured [`crate::Limits`].
+    LimitExceeded,
+    /// Any other failure.
+    Other,
+}
+
+/// An error of a known [`TranspileErrorKind`], raised where the failure is detected so that
+/// [`TranspileError::from_report`] may find it among the causes of a report. Each displays only its
+/// message.
+#[derive(Debug, Error)]
+pub(crate) enum KindError {
+    #[error("{0}")]
+    InvalidBitcode(String),
+    #[error("{0}")]
+    MissingEntrypoint(String),
+    #[error("{0}")]
+    UnsupportedInstruction(String),
+    #[error("{0}")]
+    InvalidArgument(String),
+    #[error("{0}")]
+    UnbalancedRecordedOutput(String),
+    #[error("{0}")]
+    LimitExceeded(String),
+}
+
+impl KindError {
+    fn kind(&self) -> TranspileErrorKind {
+        match self {
+            Self::InvalidBitcode(_) => TranspileErrorKind::InvalidBitcode,
+            Self::MissingEntrypoint(_) => TranspileErrorKind::MissingEntrypoint,
+            Self::UnsupportedInstruction(_) => TranspileErrorKind::UnsupportedInstruction,
+            Self::InvalidArgument(_) => TranspileErrorKind::InvalidArgument,
+            Self::UnbalancedRecordedOutput(_) => TranspileErrorKind::UnbalancedRecordedOutput,
+            Self::LimitExceeded(_) => TranspileErrorKind::LimitExceeded,
+        }
+    }
+}
+
+/// A transpilation failure, classified by its kind.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TranspileError {
+    /// Why transpilation failed.
+    pub kind: TranspileErrorKind,
+    /// The full message of the error, including each of its causes.
+    pub message: String,
+}
+
+impl TranspileError {
+    /// Classify a report returned by one of the `transpile_*` functions by the first of its causes
+    /// raised with a known kind.
+    #[must_use]
+    pub fn from_report(report: &Report) -> Self {
+        let kind = report
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<KindError>())
+            .map_or(TranspileErrorKind::Other, KindError::kind);
+
+        Self {
+            kind,
+            message: format!("{:#}", report),
+        }
+    }
+}
+
+impl fmt::Display for TranspileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.kind, self.message)
+    }
+}
+
+impl std::error::Error for TranspileError {}
+
+/// Transpile `bitcode` as [`transpile_qir_to_quil`] does, classifying the error if it fails.
+///
+/// # Errors
+/// 1. Returns a [`TranspileError`] if the transpilation fails.
+pub fn transpile_with_error_kind(bitcode: &[u8]) -> Result<ProgramOutput, TranspileError> {
+    transpile_qir_to_quil(bitcode).map_err(|report| TranspileError::from_report(&report))
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::{eyre, WrapErr};
+
+    use super::*;
+
+    #[test]
+    fn a_cause_of_known_kind_decides_the_kind() {
+        let report = Err::<(), _>(KindError::LimitExceeded(String::from(
+            "module contains more than the limit of 2 basic blocks",
+        )))
+        .wrap_err("transpilation failed")
+        .unwrap_err();
+        let error = TranspileError::from_report(&report);
+        assert_eq!(error.kind, TranspileErrorKind::LimitExceeded);
+        assert_eq!(
+            error.message,
+            "transpilation failed: module contains more than the limit of 2 basic blocks"
+        );
+
+        // The kind is decided by how an error was raised, not by what its message says
+        let error = TranspileError::from_report(&eyre!("entrypoint not found in module"));
+        assert_eq!(error.kind, TranspileErrorKind::Other);
+    }
+}
@@ -0,0 +1,394 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Synthesis of QIR modules for well-known example circuits, which may be used to test a
+//! toolchain's integration with this crate or as test fixtures.
+use std::{f64::consts::PI, str::FromStr};
+
+use eyre::{eyre, Report, Result};
+use inkwell::{
+    attributes::AttributeLoc,
+    context::Context,
+    module::Module,
+    types::{BasicMetadataTypeEnum, PointerType},
+    values::{BasicMetadataValueEnum, BasicValue, FunctionValue},
+};
+
+use crate::interop::compat::default_address_space;
+
+/// The shape of the module to generate, matching the formats accepted by the `transform` and
+/// `transpile-to-quil` commands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExampleProfile {
+    /// The circuit is run within a shot count loop.
+    ShotCount,
+    /// The circuit is run once, within a single block.
+    Unitary,
+}
+
+impl FromStr for ExampleProfile {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "shot-count" => Ok(Self::ShotCount),
+            "unitary" => Ok(Self::Unitary),
+            other => Err(eyre!(
+                "unrecognized example profile {}; expected \"shot-count\" or \"unitary\"",
+                other
+            )),
+        }
+    }
+}
+
+/// The circuit applied by a generated module, which measures every qubit once it is applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExampleCircuit {
+    /// A Bell state on two qubits.
+    Bell,
+    /// A GHZ state, entangling every qubit with the first.
+    Ghz,
+    /// The quantum Fourier transform, decomposed into `H`, `CNOT`, `RZ`, and `SWAP`.
+    Qft,
+}
+
+impl FromStr for ExampleCircuit {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bell" => Ok(Self::Bell),
+            "ghz" => Ok(Self::Ghz),
+            "qft" => Ok(Self::Qft),
+            other => Err(eyre!(
+                "unrecognized example circuit {}; expected \"bell\", \"ghz\", or \"qft\"",
+                other
+            )),
+        }
+    }
+}
+
+/// Options for [`generate_example_qir`].
+#[derive(Clone, Debug)]
+pub struct ExampleOptions {
+    /// The shape of the module to generate.
+    pub profile: ExampleProfile,
+    /// The circuit to apply.
+    pub circuit: ExampleCircuit,
+    /// The number of qubits on which to apply the circuit. Defaults to two for a Bell state, which
+    /// supports no other size, and three otherwise.
+    pub qubits: Option<u64>,
+    /// The number of shots for which the shot count loop runs. Unused by the unitary profile.
+    pub shot_count: u64,
+}
+
+impl Default for ExampleOptions {
+    fn default() -> Self {
+        Self {
+            profile: ExampleProfile::ShotCount,
+            circuit: ExampleCircuit::Bell,
+            qubits: None,
+            shot_count: 1000,
+        }
+    }
+}
+
+/// A gate applied by an example circuit, each of which corresponds to a QIR intrinsic.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Gate {
+    H(u64),
+    Cnot(u64, u64),
+    Rz(f64, u64),
+    Swap(u64, u64),
+}
+
+/// The gates applied by `circuit` on `qubits` qubits, in order.
+fn get_circuit_gates(circuit: ExampleCircuit, qubits: u64) -> Result<Vec<Gate>> {
+    match circuit {
+        ExampleCircuit::Bell if qubits != 2 => Err(eyre!(
+            "a Bell state is prepared on two qubits; got {}",
+            qubits
+        )),
+        ExampleCircuit::Ghz if qubits < 2 => Err(eyre!(
+            "a GHZ state requires at least two qubits; got {}",
+            qubits
+        )),
+        ExampleCircuit::Qft if qubits < 1 => {
+            Err(eyre!("the quantum Fourier transform requires a qubit"))
+        }
+        ExampleCircuit::Bell | ExampleCircuit::Ghz => Ok(std::iter::once(Gate::H(0))
+            .chain((1..qubits).map(|target| Gate::Cnot(target - 1, target)))
+            .collect()),
+        ExampleCircuit::Qft => {
+            let mut gates = vec![];
+            for target in 0..qubits {
+                gates.push(Gate::H(target));
+                for control in target + 1..qubits {
+                    // A controlled phase of pi/2^(control - target), up to a global phase.
+                    #[allow(clippy::cast_precision_loss)]
+                    let angle = PI / (1_u64 << (control - target).min(63)) as f64;
+                    gates.extend([
+                        Gate::Rz(angle / 2.0, control),
+                        Gate::Cnot(control, target),
+                        Gate::Rz(-angle / 2.0, target),
+                        Gate::Cnot(control, target),
+                        Gate::Rz(angle / 2.0, target),
+                    ]);
+                }
+            }
+            gates.extend((0..qubits / 2).map(|qubit| Gate::Swap(qubit, qubits - 1 - qubit)));
+            Ok(gates)
+        }
+    }
+}
+
+/// Generate the bitcode of a QIR module which applies an example circuit and measures every qubit
+/// into the result of the same index, recording each result as an element of an array.
+///
+/// # Errors
+/// 1. Returns a [`eyre::Report`] if the circuit does not support the number of qubits requested.
+pub fn generate_example_qir(options: &ExampleOptions) -> Result<Vec<u8>> {
+    let context = Context::create();
+    let module = build_example_module(&context, options)?;
+    module
+        .verify()
+        .map_err(|error| eyre!("generated an invalid module: {}", error.to_string()))?;
+    Ok(module.write_bitcode_to_memory().as_slice().to_vec())
+}
+
+fn build_example_module<'ctx>(
+    context: &'ctx Context,
+    options: &ExampleOptions,
+) -> Result<Module<'ctx>> {
+    let qubits = options.qubits.unwrap_or(match options.circuit {
+        ExampleCircuit::Bell => 2,
+        ExampleCircuit::Ghz | ExampleCircuit::Qft => 3,
+    });
+    let gates = get_circuit_gates(options.circuit, qubits)?;
+    Ok(build_circuit_module(
+        context,
+        options.profile,
+        qubits,
+        &gates,
+        options.shot_count,
+    ))
+}
+
+/// Build a module in the shape of `profile` which applies `gates`, then measures each of `qubits`
+/// into the result of the same index and records every result as an element of an array.
+pub(crate) fn build_circuit_module<'ctx>(
+    context: &'ctx Context,
+    profile: ExampleProfile,
+    qubits: u64,
+    gates: &[Gate],
+    shot_count: u64,
+) -> Module<'ctx> {
+    let module = context.create_module("example");
+    let builder = context.create_builder();
+    let qubit_type = context
+        .opaque_struct_type("Qubit")
+        .ptr_type(default_address_space());
+    let result_type = context
+        .opaque_struct_type("Result")
+        .ptr_type(default_address_space());
+
+    let declare = |name: &str, parameters: &[BasicMetadataTypeEnum<'ctx>]| -> FunctionValue<'ctx> {
+        module.get_function(name).unwrap_or_else(|| {
+            module.add_function(name, context.void_type().fn_type(parameters, false), None)
+        })
+    };
+    let pointer = |pointer_type: PointerType<'ctx>, index: u64| -> BasicMetadataValueEnum<'ctx> {
+        context
+            .i64_type()
+            .const_int(index, false)
+            .const_to_pointer(pointer_type)
+            .into()
+    };
+
+    let entry_function = module.add_function(
+        "QuantumApplication__Run__body",
+        context.void_type().fn_type(&[], false),
+        None,
+    );
+    entry_function.add_attribute(
+        AttributeLoc::Function,
+        context.create_string_attribute("EntryPoint", ""),
+    );
+    let entry_block = context.append_basic_block(entry_function, "entry");
+    builder.position_at_end(entry_block);
+
+    let shot_count_loop = match profile {
+        ExampleProfile::ShotCount => {
+            let body_block = context.append_basic_block(entry_function, "body");
+            builder.build_unconditional_branch(body_block);
+            builder.position_at_end(body_block);
+            let shot = builder.build_phi(context.i64_type(), "shot");
+            Some((body_block, shot))
+        }
+        ExampleProfile::Unitary => None,
+    };
+
+    for gate in gates {
+        let (name, arguments) = match *gate {
+            Gate::H(qubit) => ("__quantum__qis__h__body", vec![pointer(qubit_type, qubit)]),
+            Gate::Cnot(control, target) => (
+                "__quantum__qis__cnot__body",
+                vec![pointer(qubit_type, control), pointer(qubit_type, target)],
+            ),
+            Gate::Rz(angle, qubit) => (
+                "__quantum__qis__rz__body",
+                vec![
+                    context.f64_type().const_float(angle).into(),
+                    pointer(qubit_type, qubit),
+                ],
+            ),
+            Gate::Swap(first, second) => (
+                "__quantum__qis__swap__body",
+                vec![pointer(qubit_type, first), pointer(qubit_type, second)],
+            ),
+        };
+        let parameters = arguments
+            .iter()
+            .map(|argument| match argument {
+                BasicMetadataValueEnum::FloatValue(_) => context.f64_type().into(),
+                _ => qubit_type.into(),
+            })
+            .collect::<Vec<BasicMetadataTypeEnum>>();
+        builder.build_call(declare(name, &parameters), &arguments, "");
+    }
+
+    let measure = declare(
+        "__quantum__qis__mz__body",
+        &[qubit_type.into(), result_type.into()],
+    );
+    for qubit in 0..qubits {
+        builder.build_call(
+            measure,
+            &[pointer(qubit_type, qubit), pointer(result_type, qubit)],
+            "",
+        );
+    }
+
+    let record_result = declare("__quantum__rt__result_record_output", &[result_type.into()]);
+    builder.build_call(
+        declare("__quantum__rt__array_start_record_output", &[]),
+        &[],
+        "",
+    );
+    for result in 0..qubits {
+        builder.build_call(record_result, &[pointer(result_type, result)], "");
+    }
+    builder.build_call(
+        declare("__quantum__rt__array_end_record_output", &[]),
+        &[],
+        "",
+    );
+
+    if let Some((body_block, shot)) = shot_count_loop {
+        let exit_block = context.append_basic_block(entry_function, "exit");
+        let shot_value = shot.as_basic_value().into_int_value();
+        let next_shot = builder.build_int_nuw_add(
+            shot_value,
+            context.i64_type().const_int(1, false),
+            "next_shot",
+        );
+        let more_shots = builder.build_int_compare(
+            inkwell::IntPredicate::ULT,
+            shot_value,
+            context.i64_type().const_int(shot_count, false),
+            "more_shots",
+        );
+        builder.build_conditional_branch(more_shots, body_block, exit_block);
+        let first_shot = context.i64_type().const_int(1, false);
+        shot.add_incoming(&[
+            (&first_shot as &dyn BasicValue, entry_block),
+            (&next_shot, body_block),
+        ]);
+        builder.position_at_end(exit_block);
+    }
+    builder.build_return(None);
+
+    module
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn generate(profile: ExampleProfile, circuit: ExampleCircuit, qubits: Option<u64>) -> Vec<u8> {
+        generate_example_qir(&ExampleOptions {
+            profile,
+            circuit,
+            qubits,
+            shot_count: 42,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn bell_state_transpiles_to_quil() {
+        let output = crate::transpile_qir_to_quil(&generate(
+            ExampleProfile::ShotCount,
+            ExampleCircuit::Bell,
+            None,
+        ))
+        .unwrap();
+        assert_eq!(output.shot_count, 42);
+        assert_eq!(
+            output.program.to_string(true),
+            "DECLARE ro BIT[2]\nH 0\nCNOT 0 1\nMEASURE 0 ro[0]\nMEASURE 1 ro[1]\n"
+        );
+
+        let output = crate::transpile_unitary_qir_to_quil(&generate(
+            ExampleProfile::Unitary,
+            ExampleCircuit::Bell,
+            None,
+        ))
+        .unwrap();
+        assert_eq!(
+            output.program.to_string(true),
+            "DECLARE ro BIT[2]\nH 0\nCNOT 0 1\nMEASURE 0 ro[0]\nMEASURE 1 ro[1]\n"
+        );
+    }
+
+    #[test]
+    fn every_circuit_transpiles_in_every_profile() {
+        for circuit in [
+            ExampleCircuit::Bell,
+            ExampleCircuit::Ghz,
+            ExampleCircuit::Qft,
+        ] {
+            let data = generate(ExampleProfile::ShotCount, circuit, None);
+            crate::transpile_qir_to_quil(&data).unwrap();
+            let data = generate(ExampleProfile::Unitary, circuit, None);
+            crate::transpile_unitary_qir_to_quil(&data).unwrap();
+        }
+    }
+
+    #[test]
+    fn circuit_sizes_are_checked() {
+        assert_eq!(
+            get_circuit_gates(ExampleCircuit::Ghz, 4).unwrap(),
+            vec![
+                Gate::H(0),
+                Gate::Cnot(0, 1),
+                Gate::Cnot(1, 2),
+                Gate::Cnot(2, 3)
+            ]
+        );
+        assert!(get_circuit_gates(ExampleCircuit::Bell, 3).is_err());
+        assert!(get_circuit_gates(ExampleCircuit::Ghz, 1).is_err());
+        assert!(get_circuit_gates(ExampleCircuit::Qft, 0).is_err());
+    }
+}
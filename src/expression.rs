@@ -0,0 +1,225 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Classical functions of readout, such as the parity or majority vote of several measurements,
+//! which a program computes on the host before recording them. Quil cannot compute these values,
+//! so an executor running the Quil program alone evaluates them for each shot from the readout.
+
+use std::fmt;
+
+#[cfg(feature = "serde_support")]
+use serde::{Deserialize, Serialize};
+
+/// An integer computed from readout, as reconstructed from the classical instructions of a program.
+/// Booleans are computed as `0` or `1`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde_support",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum ClassicalExpression {
+    /// The value read from `offset` within the memory `region`, as `0` or `1` for a measured result
+    Readout { region: String, offset: u64 },
+    /// A constant
+    Constant(i64),
+    /// An operator applied to two expressions
+    Binary {
+        operator: BinaryOperator,
+        left: Box<ClassicalExpression>,
+        right: Box<ClassicalExpression>,
+    },
+    /// `if_true` if `condition` is non-zero, otherwise `if_false`
+    Select {
+        condition: Box<ClassicalExpression>,
+        if_true: Box<ClassicalExpression>,
+        if_false: Box<ClassicalExpression>,
+    },
+}
+
+/// An operator of a [`ClassicalExpression::Binary`]. Arithmetic wraps on overflow, and comparisons
+/// evaluate to `0` or `1`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde_support",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum BinaryOperator {
+    And,
+    Or,
+    Xor,
+    Add,
+    Sub,
+    Mul,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl BinaryOperator {
+    fn apply(self, left: i64, right: i64) -> i64 {
+        match self {
+            Self::And => left & right,
+            Self::Or => left | right,
+            Self::Xor => left ^ right,
+            Self::Add => left.wrapping_add(right),
+            Self::Sub => left.wrapping_sub(right),
+            Self::Mul => left.wrapping_mul(right),
+            Self::Eq => i64::from(left == right),
+            Self::Ne => i64::from(left != right),
+            Self::Lt => i64::from(left < right),
+            Self::Le => i64::from(left <= right),
+            Self::Gt => i64::from(left > right),
+            Self::Ge => i64::from(left >= right),
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Self::And => "&",
+            Self::Or => "|",
+            Self::Xor => "^",
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+        }
+    }
+}
+
+impl ClassicalExpression {
+    /// The region and offset of each value of readout on which the expression depends, in the order
+    /// they appear.
+    #[must_use]
+    pub fn readouts(&self) -> Vec<(&str, u64)> {
+        match self {
+            Self::Readout { region, offset } => vec![(region.as_str(), *offset)],
+            Self::Constant(_) => vec![],
+            Self::Binary { left, right, .. } => {
+                let mut readouts = left.readouts();
+                readouts.extend(right.readouts());
+                readouts
+            }
+            Self::Select {
+                condition,
+                if_true,
+                if_false,
+            } => {
+                let mut readouts = condition.readouts();
+                readouts.extend(if_true.readouts());
+                readouts.extend(if_false.readouts());
+                readouts
+            }
+        }
+    }
+
+    /// Evaluate the expression for a single shot, reading each value of readout with `read`, which
+    /// is given its region and offset.
+    ///
+    /// # Errors
+    /// Returns the first error returned by `read`.
+    pub fn evaluate<E>(
+        &self,
+        read: &mut impl FnMut(&str, u64) -> Result<i64, E>,
+    ) -> Result<i64, E> {
+        match self {
+            Self::Readout { region, offset } => read(region, *offset),
+            Self::Constant(value) => Ok(*value),
+            Self::Binary {
+                operator,
+                left,
+                right,
+            } => Ok(operator.apply(left.evaluate(read)?, right.evaluate(read)?)),
+            Self::Select {
+                condition,
+                if_true,
+                if_false,
+            } => {
+                if condition.evaluate(read)? == 0 {
+                    if_false.evaluate(read)
+                } else {
+                    if_true.evaluate(read)
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for ClassicalExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Readout { region, offset } => write!(f, "{}[{}]", region, offset),
+            Self::Constant(value) => write!(f, "{}", value),
+            Self::Binary {
+                operator,
+                left,
+                right,
+            } => write!(f, "({} {} {})", left, operator.symbol(), right),
+            Self::Select {
+                condition,
+                if_true,
+                if_false,
+            } => write!(f, "({} ? {} : {})", condition, if_true, if_false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn readout(offset: u64) -> Box<ClassicalExpression> {
+        Box::new(ClassicalExpression::Readout {
+            region: String::from("ro"),
+            offset,
+        })
+    }
+
+    #[test]
+    fn majority_vote_is_evaluated_from_readout() {
+        let sum = ClassicalExpression::Binary {
+            operator: BinaryOperator::Add,
+            left: Box::new(ClassicalExpression::Binary {
+                operator: BinaryOperator::Add,
+                left: readout(0),
+                right: readout(1),
+            }),
+            right: readout(2),
+        };
+        let majority = ClassicalExpression::Binary {
+            operator: BinaryOperator::Ge,
+            left: Box::new(sum),
+            right: Box::new(ClassicalExpression::Constant(2)),
+        };
+        assert_eq!(majority.to_string(), "(((ro[0] + ro[1]) + ro[2]) >= 2)");
+
+        for (bits, expected) in [([0, 1, 0], 0), ([1, 1, 0], 1), ([1, 1, 1], 1)] {
+            let value = majority
+                .evaluate(&mut |_region, offset| {
+                    Ok::<_, ()>(bits[usize::try_from(offset).unwrap()])
+                })
+                .unwrap();
+            assert_eq!(value, expected);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use inkwell::{
+    module::{Linkage, Module},
+    values::BasicValueEnum,
+    GlobalVisibility,
+};
+
+use crate::context::values::{
+    FN_NAME_ADD_EXECUTABLE_CACHE_ITEM, FN_NAME_CREATE_EXECUTABLE_CACHE,
+    FN_NAME_EXECUTE_ON_QPU_INTO, FN_NAME_EXECUTE_ON_QVM_INTO, FN_NAME_FREE_EXECUTABLE_CACHE,
+    FN_NAME_READ_FROM_EXECUTABLE_CACHE, FN_NAME_RESET_EXECUTION_RESULT,
+};
+use crate::context::QCSCompilerContext;
+use crate::transform::fallback::FN_NAME_USE_QCS;
+use crate::transform::shot_count_block::qir::{
+    FN_NAME_FREE_EXECUTABLE_ARRAY, FN_NAME_POPULATE_EXECUTABLE_ARRAY,
+};
+
+/// Version of the runtime ABI (see `helper/helper.h`) which patched modules call into. Increment this
+/// whenever the signature or semantics of a runtime function changes.
+pub(crate) const RUNTIME_ABI_VERSION: u64 = 1;
+
+/// Name of the global recording the runtime ABI version a module was patched against.
+const ABI_VERSION_GLOBAL_NAME: &str = "qcs_sdk_qir_abi_version";
+
+/// Name of the function inserted into every module patched before the ABI version global existed.
+const LEGACY_PATCHED_FUNCTION_NAME: &str = FN_NAME_POPULATE_EXECUTABLE_ARRAY;
+
+/// Prefix of the runtime functions exported by a runtime built on the QCS Rust SDK.
+const QCS_RUST_FUNCTION_PREFIX: &str = "qcs_sdk_qir_";
+
+/// The runtime a patched module calls into, which determines the names under which the runtime
+/// functions of `helper/helper.h`, such as `executable_from_quil`, are declared. Their signatures
+/// are the same for every flavor.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RuntimeFlavor {
+    /// The helper library built on the QCS C SDK, whose functions have the names of `helper.h`.
+    QcsC,
+    /// A runtime built on the QCS Rust SDK, whose functions are prefixed `qcs_sdk_qir_` so that it
+    /// may be linked alongside the helper library, as in `qcs_sdk_qir_executable_from_quil`. The
+    /// JIT of the `run` command provides either flavor.
+    QcsRust,
+    /// A runtime of the user's own, whose functions have the names of `helper.h` with `prefix`
+    /// prepended.
+    Custom {
+        /// The prefix of each runtime function's name.
+        prefix: String,
+    },
+}
+
+impl RuntimeFlavor {
+    /// The name under which this flavor of runtime exports the function named `name` in `helper.h`.
+    #[must_use]
+    pub fn function_name(&self, name: &str) -> String {
+        match self {
+            Self::QcsC => name.to_string(),
+            Self::QcsRust => format!("{}{}", QCS_RUST_FUNCTION_PREFIX, name),
+            Self::Custom { prefix } => format!("{}{}", prefix, name),
+        }
+    }
+}
+
+impl Default for RuntimeFlavor {
+    fn default() -> Self {
+        Self::QcsC
+    }
+}
+
+impl std::str::FromStr for RuntimeFlavor {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "qcs-c" => Ok(Self::QcsC),
+            "qcs-rust" => Ok(Self::QcsRust),
+            _ => match s.strip_prefix("custom:") {
+                Some(prefix) if !prefix.is_empty() => Ok(Self::Custom {
+                    prefix: prefix.to_string(),
+                }),
+                _ => Err(format!(
+                    "unknown runtime flavor \"{}\"; expected \"qcs-c\", \"qcs-rust\", or \"custom:<prefix>\"",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for RuntimeFlavor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::QcsC => write!(f, "qcs-c"),
+            Self::QcsRust => write!(f, "qcs-rust"),
+            Self::Custom { prefix } => write!(f, "custom:{}", prefix),
+        }
+    }
+}
+
+/// Record in the module that it has been patched against the current runtime ABI.
+///
+/// The global is `weak_odr` so that it survives optimization and that modules patched separately
+/// may still be linked together.
+pub(crate) fn mark_patched(context: &mut QCSCompilerContext) {
+    let version_type = context.base_context.i32_type();
+    let global = context
+        .module
+        .add_global(version_type, None, ABI_VERSION_GLOBAL_NAME);
+    global.set_linkage(Linkage::WeakODR);
+    global.set_constant(true);
+    global.set_initializer(&version_type.const_int(RUNTIME_ABI_VERSION, false));
+}
+
+/// Give hidden visibility to the symbols which patching added to the module: the runtime functions
+/// it declares, the helper functions it defines, and the runtime ABI version global.
+pub(crate) fn hide_injected_symbols(context: &QCSCompilerContext) {
+    // `use_qcs` is only declared when the original code is kept, and the functions reusing execution
+    // results only when they are reused, so these are not among the runtime functions.
+    let flavor = &context.options.runtime_flavor;
+    let helper_functions = [
+        String::from(FN_NAME_POPULATE_EXECUTABLE_ARRAY),
+        String::from(FN_NAME_FREE_EXECUTABLE_ARRAY),
+        flavor.function_name(FN_NAME_USE_QCS),
+        flavor.function_name(FN_NAME_RESET_EXECUTION_RESULT),
+        flavor.function_name(FN_NAME_EXECUTE_ON_QVM_INTO),
+        flavor.function_name(FN_NAME_EXECUTE_ON_QPU_INTO),
+        flavor.function_name(FN_NAME_CREATE_EXECUTABLE_CACHE),
+        flavor.function_name(FN_NAME_ADD_EXECUTABLE_CACHE_ITEM),
+        flavor.function_name(FN_NAME_READ_FROM_EXECUTABLE_CACHE),
+        flavor.function_name(FN_NAME_FREE_EXECUTABLE_CACHE),
+    ]
+    .into_iter()
+    .filter_map(|name| context.module.get_function(&name));
+
+    for function in context
+        .values
+        .runtime_functions()
+        .into_iter()
+        .chain(helper_functions)
+    {
+        function
+            .as_global_value()
+            .set_visibility(GlobalVisibility::Hidden);
+    }
+
+    if let Some(global) = context.module.get_global(ABI_VERSION_GLOBAL_NAME) {
+        global.set_visibility(GlobalVisibility::Hidden);
+    }
+}
+
+/// Return the runtime ABI version the module was previously patched against, if any. Modules patched
+/// before the version was recorded are reported as version 1.
+pub(crate) fn get_patched_abi_version(module: &Module) -> Option<u64> {
+    match module.get_global(ABI_VERSION_GLOBAL_NAME) {
+        Some(global) => global
+            .get_initializer()
+            .filter(BasicValueEnum::is_int_value)
+            .and_then(|initializer| initializer.into_int_value().get_zero_extended_constant()),
+        None => module
+            .get_function(LEGACY_PATCHED_FUNCTION_NAME)
+            .filter(|function| function.count_basic_blocks() > 0)
+            .map(|_| 1),
+    }
+}
@@ -12,14 +12,27 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::convert::TryInto;
+
 use eyre::{eyre, Result};
 use inkwell::{
     module::Linkage,
     types::BasicMetadataTypeEnum,
-    values::{BasicMetadataValueEnum, FloatValue, IntValue, PointerValue},
+    values::{
+        BasicMetadataValueEnum, CallSiteValue, FloatValue, FunctionValue, IntValue, PointerValue,
+    },
+};
+
+use crate::context::{
+    target::ExecutionTarget,
+    values::{
+        build_execute_on_qpu_into_function, build_execute_on_qvm_into_function,
+        build_read_from_executable_cache_function, build_reset_execution_result_function,
+    },
+    QCSCompilerContext,
 };
 
-use crate::context::QCSCompilerContext;
+use super::compat::{build_load, default_address_space};
 
 #[allow(dead_code)]
 pub(crate) fn printf<'ctx>(context: &mut QCSCompilerContext<'ctx>, string: PointerValue) {
@@ -40,6 +53,28 @@ pub(crate) fn printf<'ctx>(context: &mut QCSCompilerContext<'ctx>, string: Point
     );
 }
 
+/// Insert a call to the runtime `function` with `arguments`, followed by the user context read from
+/// its global when runtime calls pass one.
+pub(crate) fn build_runtime_call<'ctx>(
+    context: &QCSCompilerContext<'ctx>,
+    function: FunctionValue<'ctx>,
+    arguments: &[BasicMetadataValueEnum<'ctx>],
+) -> CallSiteValue<'ctx> {
+    let mut arguments = arguments.to_vec();
+    if let Some(user_context) = context.values.user_context() {
+        arguments.push(
+            build_load(
+                &context.builder,
+                context.types.string().into(),
+                user_context.as_pointer_value(),
+                "user_context",
+            )
+            .into(),
+        );
+    }
+    context.builder.build_call(function, &arguments, "")
+}
+
 pub(crate) struct Executable<'ctx>(pub(crate) PointerValue<'ctx>);
 
 #[allow(dead_code)]
@@ -48,29 +83,31 @@ pub(crate) fn executable_from_quil<'ctx>(
     quil: PointerValue<'ctx>,
 ) -> Result<Executable<'ctx>> {
     let string_type = context.types.string();
-    let executable_call_site_value = context.builder.build_call(
+    let executable_call_site_value = build_runtime_call(
+        context,
         context.values.executable_from_quil_function(),
         &[BasicMetadataValueEnum::PointerValue(
             quil.const_cast(string_type),
         )],
-        "",
     );
     Ok(Executable(
         executable_call_site_value
             .try_as_basic_value()
             .left()
             .ok_or_else(|| eyre!("expected basic value"))?
-            .into_pointer_value(),
+            .try_into()
+            .map_err(|_| eyre!("expected the runtime call to return a pointer"))?,
     ))
 }
 
-pub(crate) struct ExecutionResult<'ctx>(PointerValue<'ctx>);
+pub(crate) struct ExecutionResult<'ctx>(pub(crate) PointerValue<'ctx>);
 
 pub(crate) fn execute_on_qpu<'ctx>(
     context: &mut QCSCompilerContext<'ctx>,
     executable: &Executable<'ctx>,
 ) -> Result<ExecutionResult<'ctx>> {
-    let execution_result = context.builder.build_call(
+    let execution_result = build_runtime_call(
+        context,
         context.values.execute_on_qpu_function(),
         &[
             executable.0.into(),
@@ -80,7 +117,6 @@ pub(crate) fn execute_on_qpu<'ctx>(
                 .ok_or_else(|| eyre!("expected a quantum processor ID to be provided"))?
                 .into(),
         ],
-        "",
     );
 
     Ok(ExecutionResult(
@@ -88,7 +124,8 @@ pub(crate) fn execute_on_qpu<'ctx>(
             .try_as_basic_value()
             .left()
             .ok_or_else(|| eyre!("Expected a basic value"))?
-            .into_pointer_value(),
+            .try_into()
+            .map_err(|_| eyre!("expected the runtime call to return a pointer"))?,
     ))
 }
 
@@ -96,10 +133,10 @@ pub(crate) fn execute_on_qvm<'ctx>(
     context: &mut QCSCompilerContext<'ctx>,
     executable: &Executable<'ctx>,
 ) -> Result<ExecutionResult<'ctx>> {
-    let execution_result = context.builder.build_call(
+    let execution_result = build_runtime_call(
+        context,
         context.values.execute_on_qvm_function(),
         &[executable.0.into()],
-        "",
     );
 
     Ok(ExecutionResult(
@@ -107,19 +144,109 @@ pub(crate) fn execute_on_qvm<'ctx>(
             .try_as_basic_value()
             .left()
             .ok_or_else(|| eyre!("Expected a basic value"))?
-            .into_pointer_value(),
+            .try_into()
+            .map_err(|_| eyre!("expected the runtime call to return a pointer"))?,
     ))
 }
 
-#[allow(dead_code)]
+/// Insert a call which allocates an empty `ExecutionResult`, to be filled by [`execute_into`] each
+/// time the program executes and freed once the function returns.
+pub(crate) fn create_execution_result<'ctx>(
+    context: &mut QCSCompilerContext<'ctx>,
+) -> Result<ExecutionResult<'ctx>> {
+    let function = build_reset_execution_result_function(
+        context.base_context,
+        &context.builder,
+        &context.module,
+        &context.types,
+        &context.options.runtime_flavor,
+        context.options.user_context,
+    );
+    let null = context
+        .types
+        .execution_result(&context.module)
+        .ptr_type(default_address_space())
+        .const_null();
+    let execution_result = build_runtime_call(context, function, &[null.into()]);
+
+    Ok(ExecutionResult(
+        execution_result
+            .try_as_basic_value()
+            .left()
+            .ok_or_else(|| eyre!("Expected a basic value"))?
+            .try_into()
+            .map_err(|_| eyre!("expected the runtime call to return a pointer"))?,
+    ))
+}
+
+/// Insert a call which empties `execution_result` of the data of its last execution, so that it may
+/// be filled again by [`execute_into`].
+pub(crate) fn reset_execution_result<'ctx>(
+    context: &mut QCSCompilerContext<'ctx>,
+    execution_result: &ExecutionResult<'ctx>,
+) {
+    let function = build_reset_execution_result_function(
+        context.base_context,
+        &context.builder,
+        &context.module,
+        &context.types,
+        &context.options.runtime_flavor,
+        context.options.user_context,
+    );
+    build_runtime_call(context, function, &[execution_result.0.into()]);
+}
+
+/// Insert a call which executes `executable` on the target of the transformation, writing the
+/// outcome into `execution_result` rather than allocating a new one.
+pub(crate) fn execute_into<'ctx>(
+    context: &mut QCSCompilerContext<'ctx>,
+    executable: &Executable<'ctx>,
+    execution_result: &ExecutionResult<'ctx>,
+) -> Result<()> {
+    let (function, arguments) = match &context.target {
+        ExecutionTarget::Qpu(_) => (
+            build_execute_on_qpu_into_function(
+                context.base_context,
+                &context.builder,
+                &context.module,
+                &context.types,
+                &context.options.runtime_flavor,
+                context.options.user_context,
+            ),
+            vec![
+                executable.0.into(),
+                context
+                    .values
+                    .quantum_processor_id()
+                    .ok_or_else(|| eyre!("expected a quantum processor ID to be provided"))?
+                    .into(),
+                execution_result.0.into(),
+            ],
+        ),
+        ExecutionTarget::Qvm => (
+            build_execute_on_qvm_into_function(
+                context.base_context,
+                &context.builder,
+                &context.module,
+                &context.types,
+                &context.options.runtime_flavor,
+                context.options.user_context,
+            ),
+            vec![executable.0.into(), execution_result.0.into()],
+        ),
+    };
+    build_runtime_call(context, function, &arguments);
+    Ok(())
+}
+
 pub(crate) fn free_executable<'ctx>(
     context: &mut QCSCompilerContext<'ctx>,
     executable: &Executable<'ctx>,
 ) {
-    context.builder.build_call(
+    build_runtime_call(
+        context,
         context.values.free_executable_function(),
         &[executable.0.into()],
-        "",
     );
 }
 
@@ -127,10 +254,10 @@ pub(crate) fn free_execution_result<'ctx>(
     context: &mut QCSCompilerContext<'ctx>,
     execution_result: &ExecutionResult<'ctx>,
 ) {
-    context.builder.build_call(
+    build_runtime_call(
+        context,
         context.values.free_execution_result_function(),
         &[execution_result.0.into()],
-        "",
     );
 }
 
@@ -139,22 +266,35 @@ pub(crate) fn get_executable<'ctx>(
     context: &mut QCSCompilerContext<'ctx>,
     index: IntValue<'ctx>,
 ) -> Result<Executable<'ctx>> {
-    let cache_pointer = context
-        .builder
-        .build_load(context.values.executable_cache().as_pointer_value(), "");
-
-    let call_site_value = context.builder.build_call(
-        context.values.read_from_executable_cache(),
-        &[cache_pointer.into(), index.into()],
+    let cache_pointer = build_load(
+        &context.builder,
+        context
+            .types
+            .executable_cache(&context.module)
+            .ptr_type(default_address_space())
+            .into(),
+        context.values.executable_cache()?.as_pointer_value(),
         "",
     );
 
+    let function = build_read_from_executable_cache_function(
+        context.base_context,
+        &context.builder,
+        &context.module,
+        &context.types,
+        &context.options.runtime_flavor,
+        context.options.user_context,
+    );
+    let call_site_value =
+        build_runtime_call(context, function, &[cache_pointer.into(), index.into()]);
+
     Ok(Executable(
         call_site_value
             .try_as_basic_value()
             .left()
             .ok_or_else(|| eyre!("function does not return a value"))?
-            .into_pointer_value(),
+            .try_into()
+            .map_err(|_| eyre!("expected the runtime call to return a pointer"))?,
     ))
 }
 
@@ -164,10 +304,10 @@ pub(crate) fn panic_on_execution_result_failure<'ctx>(
     context: &mut QCSCompilerContext<'ctx>,
     execution_result: &ExecutionResult<'ctx>,
 ) {
-    context.builder.build_call(
+    build_runtime_call(
+        context,
         context.values.panic_on_failure_function(),
         &[execution_result.0.into()],
-        "",
     );
 }
 
@@ -177,7 +317,8 @@ pub(crate) fn get_readout_bit<'ctx>(
     shot_index: IntValue<'ctx>,
     readout_index: u64,
 ) -> Result<IntValue<'ctx>> {
-    let result = context.builder.build_call(
+    let result = build_runtime_call(
+        context,
         context.values.get_readout_bit_function(),
         &[
             BasicMetadataValueEnum::PointerValue(execution_result.0),
@@ -188,14 +329,14 @@ pub(crate) fn get_readout_bit<'ctx>(
                 .const_int(readout_index, false)
                 .into(),
         ],
-        "",
     );
 
     Ok(result
         .try_as_basic_value()
         .left()
         .ok_or_else(|| eyre!("Expected basic value"))?
-        .into_int_value())
+        .try_into()
+        .map_err(|_| eyre!("expected the runtime call to return an integer"))?)
 }
 
 pub(crate) fn set_param<'ctx>(
@@ -204,7 +345,8 @@ pub(crate) fn set_param<'ctx>(
     index: u64,
     value: FloatValue<'ctx>,
 ) {
-    context.builder.build_call(
+    build_runtime_call(
+        context,
         context.values.set_param_function(),
         &[
             BasicMetadataValueEnum::PointerValue(executable.0),
@@ -216,7 +358,6 @@ pub(crate) fn set_param<'ctx>(
                 .into(),
             value.into(),
         ],
-        "",
     );
 }
 
@@ -225,7 +366,8 @@ pub(crate) fn wrap_in_shots<'ctx>(
     executable: &Executable<'ctx>,
     shots: u64,
 ) {
-    context.builder.build_call(
+    build_runtime_call(
+        context,
         context.values.wrap_in_shots_function(),
         &[
             BasicMetadataValueEnum::PointerValue(executable.0),
@@ -235,6 +377,5 @@ pub(crate) fn wrap_in_shots<'ctx>(
                 .const_int(shots, false)
                 .into(),
         ],
-        "",
     );
 }
@@ -0,0 +1,108 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Queries over the control flow graph of a function, used to place instructions safely when patching a module
+use either::Either;
+use inkwell::{
+    basic_block::BasicBlock,
+    values::{BasicValueEnum, FunctionValue, InstructionOpcode},
+};
+
+/// The blocks to which control may pass at the end of `block`, in the order of its terminator's operands.
+pub(crate) fn successors(block: BasicBlock) -> Vec<BasicBlock> {
+    block
+        .get_terminator()
+        .map(|terminator| {
+            (0..terminator.get_num_operands())
+                .filter_map(|index| match terminator.get_operand(index) {
+                    Some(Either::Right(target)) => Some(target),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Given a conditional branch which closes a loop, return the target which leaves the loop, and
+/// whether it is the `then` target. A target leaves the loop if it is not one of `loop_blocks`.
+/// Returns `None` if the branch is not conditional, or if neither or both targets leave the loop.
+pub(crate) fn get_loop_exit_target<'ctx>(
+    block: BasicBlock<'ctx>,
+    loop_blocks: &[BasicBlock<'ctx>],
+) -> Option<(BasicBlock<'ctx>, bool)> {
+    let terminator = block.get_terminator()?;
+    if terminator.get_opcode() != InstructionOpcode::Br || terminator.get_num_operands() != 3 {
+        return None;
+    }
+
+    // A conditional branch's operands are ordered as its condition, then its `else` and `then` targets.
+    match (terminator.get_operand(2), terminator.get_operand(1)) {
+        (Some(Either::Right(then_block)), Some(Either::Right(else_block))) => match (
+            loop_blocks.contains(&then_block),
+            loop_blocks.contains(&else_block),
+        ) {
+            (true, false) => Some((else_block, false)),
+            (false, true) => Some((then_block, true)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Whether any of `targets` may be reached by following control flow onward from `from`, without
+/// passing through `barrier`. `from` itself is only counted if it may be reached again.
+pub(crate) fn reaches_any<'ctx>(
+    from: BasicBlock<'ctx>,
+    targets: &[BasicBlock<'ctx>],
+    barrier: BasicBlock<'ctx>,
+) -> bool {
+    let mut visited = vec![];
+    let mut pending = successors(from);
+
+    while let Some(block) = pending.pop() {
+        if block == barrier || visited.contains(&block) {
+            continue;
+        }
+        if targets.contains(&block) {
+            return true;
+        }
+        visited.push(block);
+        pending.extend(successors(block));
+    }
+
+    false
+}
+
+/// The blocks of `function` containing an instruction which uses `value` as an operand.
+pub(crate) fn blocks_using<'ctx>(
+    function: FunctionValue<'ctx>,
+    value: BasicValueEnum<'ctx>,
+) -> Vec<BasicBlock<'ctx>> {
+    function
+        .get_basic_blocks()
+        .into_iter()
+        .filter(|block| {
+            let mut next_instruction = block.get_first_instruction();
+            while let Some(instruction) = next_instruction {
+                if (0..instruction.get_num_operands())
+                    .any(|index| instruction.get_operand(index) == Some(Either::Left(value)))
+                {
+                    return true;
+                }
+                next_instruction = instruction.get_next_instruction();
+            }
+            false
+        })
+        .collect()
+}
@@ -0,0 +1,66 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Shims over the parts of the inkwell API which differ between the LLVM versions this crate
+//! supports. Code elsewhere in the crate should go through these rather than calling the
+//! version-specific APIs directly.
+use inkwell::{
+    builder::Builder,
+    types::{AnyTypeEnum, BasicTypeEnum, PointerType},
+    values::{BasicValueEnum, PointerValue},
+    AddressSpace,
+};
+
+/// The address space of every pointer created by this crate.
+pub(crate) fn default_address_space() -> AddressSpace {
+    AddressSpace::Generic
+}
+
+/// Return the type to which a pointer type points.
+///
+/// Pointers are opaque as of LLVM 15, in which case this returns `None` and the pointee type must
+/// be recovered some other way, such as from the declaration of the function the pointer is passed to.
+pub(crate) fn get_pointee_type(pointer_type: PointerType) -> Option<AnyTypeEnum> {
+    #[cfg(not(feature = "llvm15-0"))]
+    {
+        Some(pointer_type.get_element_type())
+    }
+
+    #[cfg(feature = "llvm15-0")]
+    {
+        let _ = pointer_type;
+        None
+    }
+}
+
+/// Build a load of `pointee_type` from `pointer`.
+///
+/// LLVM 15 requires the loaded type to be given explicitly; earlier versions take it from the pointer.
+pub(crate) fn build_load<'ctx>(
+    builder: &Builder<'ctx>,
+    pointee_type: BasicTypeEnum<'ctx>,
+    pointer: PointerValue<'ctx>,
+    name: &str,
+) -> BasicValueEnum<'ctx> {
+    #[cfg(not(feature = "llvm15-0"))]
+    {
+        let _ = pointee_type;
+        builder.build_load(pointer, name)
+    }
+
+    #[cfg(feature = "llvm15-0")]
+    {
+        builder.build_load(pointee_type, pointer, name)
+    }
+}
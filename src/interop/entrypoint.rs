@@ -12,12 +12,17 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use eyre::{eyre, Result};
+#[cfg(feature = "transform")]
+use eyre::Result;
 use inkwell::attributes::AttributeLoc;
 use inkwell::module::Module;
 use inkwell::values::FunctionValue;
 
+#[cfg(feature = "transform")]
 use crate::context::QCSCompilerContext;
+use crate::error::KindError;
+#[cfg(feature = "transform")]
+use crate::transform::shot_count_block::qir::FN_NAME_FREE_EXECUTABLE_ARRAY;
 
 /// First, check for a function with an attribute value of "`EntryPoint`". This indicates the starting
 /// point for the program. If no such function exists, look for one with the default name,
@@ -43,7 +48,9 @@ pub(crate) fn get_entrypoint_function<'ctx>(module: &Module<'ctx>) -> Option<Fun
 }
 
 /// Mutate the context to add a `main` function as an entrypoint for `x86_64`, which
-/// itself calls the QIR standard entrypoint.
+/// itself calls the QIR standard entrypoint. If the module caches executables, `main` frees
+/// the cache once the entrypoint returns.
+#[cfg(feature = "transform")]
 pub(crate) fn add_main_entrypoint(context: &mut QCSCompilerContext) -> Result<()> {
     let main_function = context.module.add_function(
         "main",
@@ -55,10 +62,18 @@ pub(crate) fn add_main_entrypoint(context: &mut QCSCompilerContext) -> Result<()
         .append_basic_block(main_function, "entry");
     context.builder.position_at_end(entry);
 
-    let qir_entrypoint = get_entry_function(&context.module)
-        .ok_or_else(|| eyre!("QIR expected entrypoint not found"))?;
+    let qir_entrypoint = get_entry_function(&context.module).ok_or_else(|| {
+        KindError::MissingEntrypoint(String::from("QIR expected entrypoint not found"))
+    })?;
 
     context.builder.build_call(qir_entrypoint, &[], "");
+    if let Some(free_executable_array) = context.module.get_function(FN_NAME_FREE_EXECUTABLE_ARRAY)
+    {
+        context
+            .builder
+            .build_call(free_executable_array, &[], "")
+            .set_call_convention(free_executable_array.get_call_conventions());
+    }
     context
         .builder
         .build_return(Some(&context.base_context.i32_type().const_int(0, false)));
@@ -12,25 +12,58 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::convert::{TryFrom, TryInto};
+use std::convert::TryFrom;
 
 // Functions which operate on and extract information from `inkwell` `InstructionValue`s
 use either::Either;
 use eyre::{eyre, Result, WrapErr};
 use inkwell::{
     basic_block::BasicBlock,
-    types::AnyTypeEnum,
+    module::Module,
+    types::{AnyTypeEnum, BasicTypeEnum},
     values::{
-        BasicValue, BasicValueEnum, FloatValue, InstructionOpcode, InstructionValue, IntValue,
-        PhiValue, PointerValue,
+        AnyValue, BasicValueEnum, FloatValue, FunctionValue, InstructionOpcode, InstructionValue,
+        IntValue, PointerValue,
     },
+    IntPredicate,
 };
 
+use lazy_static::lazy_static;
+use regex::Regex;
+
 use crate::context::QCSCompilerContext;
+use crate::error::KindError;
+
+use super::compat::get_pointee_type;
+
+lazy_static! {
+    static ref GLOBAL_REFERENCE_REGEX: Regex = Regex::new(r#"@(?:[-\w.$]+|"[^"]*")"#).unwrap();
+    /// Matches the constant address cast into another address space, which LLVM does not fold, as
+    /// in `addrspacecast (%Qubit addrspace(2)* inttoptr (i64 1 to %Qubit addrspace(2)*) to %Qubit*)`.
+    static ref ADDRESS_SPACE_CAST_REGEX: Regex = Regex::new(
+        r"addrspacecast \((?:.*? null|.*?inttoptr \(i64 (?P<address>\d+) to .*\)) to .*\)$"
+    )
+    .unwrap();
+    /// Matches a constant `getelementptr` into a global, as in
+    /// `getelementptr inbounds ([13 x i8], [13 x i8]* @0, i64 0, i64 7)`.
+    static ref CONSTANT_ELEMENT_POINTER_REGEX: Regex = Regex::new(
+        r#"getelementptr (?:inbounds )?\((?P<source>[^,]+), [^@]*?(?P<global>@(?:[-\w.$]+|"[^"]*"))(?P<indices>(?:, i\d+ -?\d+)*)\)"#
+    )
+    .unwrap();
+    static ref INDEX_REGEX: Regex = Regex::new(r"i\d+ (?P<index>-?\d+)").unwrap();
+    static ref BYTE_ARRAY_TYPE_REGEX: Regex = Regex::new(r"^\[(?P<length>\d+) x i8\]$").unwrap();
+    /// Matches the initializer of a global array of bytes, following the ` = ` of its definition.
+    static ref BYTE_ARRAY_INITIALIZER_REGEX: Regex = Regex::new(
+        r#"^[^\[]*\[(?P<length>\d+) x i8\] (?:c"(?P<string>[^"]*)"|zeroinitializer)"#
+    )
+    .unwrap();
+}
 
 pub(crate) fn get_called_function_name(instruction: InstructionValue) -> Result<Option<String>> {
     let intrinsic_function_target = instruction
-        .get_operand(instruction.get_num_operands() - 1)
+        .get_num_operands()
+        .checked_sub(1)
+        .and_then(|index| instruction.get_operand(index))
         .ok_or_else(|| eyre!("expected a final operand in Call instruction"))?;
 
     match intrinsic_function_target {
@@ -52,63 +85,174 @@ pub(crate) enum OperationArgument<'ctx> {
     Result(u64),
     Parameter(FloatValue<'ctx>),
     Instruction(InstructionValue<'ctx>),
+    /// The elements of a `%Array*` built with constant contents before the call, such as the Paulis
+    /// and qubits passed to `__quantum__qis__measure__body` and `__quantum__qis__exp__body`.
+    Array(Vec<ArrayElement>),
+}
+
+/// A constant element stored into a `%Array*`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ArrayElement {
+    /// An integer, such as a `%Pauli`.
+    Integer(u64),
+    /// The index of a `%Qubit*`.
+    Qubit(u64),
+}
+
+/// The QIR types which may be passed by pointer to a quantum intrinsic.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum QirPointerType {
+    Qubit,
+    Result,
+    Array,
+    String,
+}
+
+/// Determine which QIR type the parameter at `index` of the called function is declared as, if any.
+///
+/// This relies on the declaration of the called function rather than on the type of the argument
+/// itself, since an argument may be cast from another type, and since neither carries its pointee
+/// type once pointers are opaque (LLVM 15+). In the latter case the parameter is identified from
+/// the name of the intrinsic instead; see [`get_opaque_pointer_parameter_type`].
+/// The pointer may be in any address space.
+pub(crate) fn get_pointer_parameter_type(
+    function: FunctionValue,
+    index: usize,
+) -> Result<Option<QirPointerType>> {
+    let parameter_type = match function.get_type().get_param_types().get(index) {
+        Some(BasicTypeEnum::PointerType(pointer_type)) => *pointer_type,
+        _ => return Ok(None),
+    };
+
+    match get_pointee_type(parameter_type) {
+        Some(AnyTypeEnum::StructType(struct_type)) => {
+            let type_name = struct_type
+                .get_name()
+                .ok_or_else(|| eyre!("expected struct type to have name"))?;
+            match type_name
+                .to_str()
+                .wrap_err("unable to convert C String to string")?
+            {
+                "Qubit" => Ok(Some(QirPointerType::Qubit)),
+                "Result" => Ok(Some(QirPointerType::Result)),
+                "Array" => Ok(Some(QirPointerType::Array)),
+                "String" => Ok(Some(QirPointerType::String)),
+                // TODO: Ensure all required types are supported
+                other => Err(eyre!(
+                    "got unexpected type {} as argument to {}",
+                    other,
+                    function.get_name().to_string_lossy()
+                )),
+            }
+        }
+        Some(_) => Ok(None),
+        None => Ok(get_opaque_pointer_parameter_type(
+            &function.get_name().to_string_lossy(),
+            index,
+        )),
+    }
+}
+
+/// Identify the QIR type of a pointer parameter of an intrinsic by name, for modules whose
+/// pointers are opaque. Every pointer passed to a `__quantum__qis__` intrinsic is a `%Qubit*` except
+/// for the measurement targets of `mz`, `mresetz`, and `measure`, the argument to `read_result`,
+/// and the Pauli and qubit arrays passed to `measure` and `exp`.
+fn get_opaque_pointer_parameter_type(function_name: &str, index: usize) -> Option<QirPointerType> {
+    match (function_name, index) {
+        ("__quantum__qis__measure__body", 0 | 1)
+        | ("__quantum__qis__exp__body" | "__quantum__qis__exp__adj", 0 | 2) => {
+            Some(QirPointerType::Array)
+        }
+        ("__quantum__qis__mz__body" | "__quantum__qis__mresetz__body", 1)
+        | ("__quantum__qis__measure__body", 2)
+        | ("__quantum__qis__read_result__body" | "__quantum__rt__result_record_output", 0) => {
+            Some(QirPointerType::Result)
+        }
+        (name, _) if name.starts_with("__quantum__qis__") => Some(QirPointerType::Qubit),
+        _ => None,
+    }
 }
 
 /// Return the arguments used to invoke a quantum runtime intrinsic, `@__quantum__qis__*__body`, in order.
 pub(crate) fn get_qis_function_arguments<'ctx>(
-    context: &QCSCompilerContext,
+    context: &QCSCompilerContext<'ctx>,
     instruction: InstructionValue<'ctx>,
 ) -> Result<Vec<OperationArgument<'ctx>>> {
-    let operand_count = instruction.get_num_operands();
+    get_bound_qis_function_arguments(context, instruction, &[])
+}
 
+/// Return the arguments used to invoke a quantum runtime intrinsic, as [`get_qis_function_arguments`],
+/// where the qubit and result pointers may also be computed from the values given in `bindings`,
+/// such as the induction variables of a loop being unrolled.
+pub(crate) fn get_bound_qis_function_arguments<'ctx>(
+    context: &QCSCompilerContext<'ctx>,
+    instruction: InstructionValue<'ctx>,
+    bindings: &[(InstructionValue<'ctx>, i64)],
+) -> Result<Vec<OperationArgument<'ctx>>> {
     // The final operand of a call instruction is the function being called
-    (0..operand_count - 1)
+    let argument_count = instruction
+        .get_num_operands()
+        .checked_sub(1)
+        .ok_or_else(|| eyre!("expected a final operand in Call instruction"))?;
+
+    let function_name = get_called_function_name(instruction)?.ok_or_else(|| {
+        eyre!(
+            "expected a named function as the target of {:?}",
+            instruction
+        )
+    })?;
+    let function = context
+        .module
+        .get_function(&function_name)
+        .ok_or_else(|| eyre!("no declaration found for called function {}", function_name))?;
+
+    (0..argument_count)
         .map(|operand_index| {
             let target = instruction
                 .get_operand(operand_index)
                 .ok_or_else(|| eyre!("expected a first operand in Call instruction"))?;
             if let Either::Left(BasicValueEnum::PointerValue(ptr_value)) = target {
-                if let AnyTypeEnum::StructType(struct_type) =
-                    ptr_value.get_type().get_element_type()
-                {
-                    let type_name = struct_type
-                        .get_name()
-                        .ok_or_else(|| eyre!("expected struct type to have name"))?;
-                    match type_name
-                        .to_str()
-                        .wrap_err("unable to convert C String to string")?
-                    {
-                        "Qubit" => {
-                            let qubit_index =
-                                pointer_value_to_u64(context, ptr_value).ok_or_else(|| {
-                                    eyre!("qubit index must be a non-negative number")
-                                })?;
-                            Ok(OperationArgument::Qubit(qubit_index))
-                        }
-                        "Result" => {
-                            let result_index = pointer_value_to_u64(context, ptr_value)
-                                .ok_or_else(|| {
-                                    eyre!("unable to derive Result index from pointer")
-                                })?;
-                            Ok(OperationArgument::Result(result_index))
+                match get_pointer_parameter_type(function, operand_index as usize)? {
+                    Some(QirPointerType::Qubit) => {
+                        let qubit_index = pointer_value_to_u64(context, ptr_value)
+                            .or_else(|| evaluate_bound_pointer(ptr_value, bindings))
+                            .ok_or_else(|| {
+                                describe_unresolved_pointer(
+                                    QirPointerType::Qubit,
+                                    instruction,
+                                    ptr_value,
+                                )
+                            })?;
+                        Ok(OperationArgument::Qubit(qubit_index))
+                    }
+                    Some(QirPointerType::Result) => {
+                        let result_index = pointer_value_to_u64(context, ptr_value)
+                            .or_else(|| evaluate_bound_pointer(ptr_value, bindings))
+                            .ok_or_else(|| {
+                                describe_unresolved_pointer(
+                                    QirPointerType::Result,
+                                    instruction,
+                                    ptr_value,
+                                )
+                            })?;
+                        Ok(OperationArgument::Result(result_index))
+                    }
+                    Some(QirPointerType::Array) => Ok(OperationArgument::Array(
+                        get_constant_array_elements(context, instruction, ptr_value)?,
+                    )),
+                    Some(QirPointerType::String) | None => {
+                        if let Some(inst) = ptr_value.as_instruction() {
+                            Ok(OperationArgument::Instruction(inst))
+                        } else {
+                            // TODO: Support more pointer value types
+                            Err(eyre!(
+                                "unexpected pointer value {:?} as operand {} of instruction {:?}",
+                                ptr_value,
+                                operand_index,
+                                instruction
+                            ))
                         }
-                        // TODO: Ensure all required types are supported
-                        other => Err(eyre!(
-                            "got unexpected type {} as argument to {:?}",
-                            other,
-                            instruction
-                        )),
                     }
-                } else if let Some(inst) = ptr_value.as_instruction() {
-                    Ok(OperationArgument::Instruction(inst))
-                } else {
-                    // TODO: Support more pointer value types
-                    Err(eyre!(
-                        "unexpected pointer value {:?} as operand {} of instruction {:?}",
-                        ptr_value,
-                        operand_index,
-                        instruction
-                    ))
                 }
             } else if let Either::Left(BasicValueEnum::FloatValue(value)) = target {
                 Ok(OperationArgument::Parameter(value))
@@ -123,6 +267,217 @@ pub(crate) fn get_qis_function_arguments<'ctx>(
         .collect()
 }
 
+/// Return the elements of a `%Array*` passed to `instruction`, which must be created by
+/// `__quantum__rt__array_create_1d` with a constant length and have a constant stored into each
+/// element, through a pointer from `__quantum__rt__array_get_element_ptr_1d` at a constant index,
+/// within the same function. Q# lowers arrays of Paulis and qubits built from literals this way.
+fn get_constant_array_elements<'ctx>(
+    context: &QCSCompilerContext<'ctx>,
+    instruction: InstructionValue<'ctx>,
+    array: PointerValue<'ctx>,
+) -> Result<Vec<ArrayElement>> {
+    let describe = || instruction.print_to_string().to_string().trim().to_owned();
+
+    let length = array
+        .as_instruction()
+        .filter(|source| {
+            get_called_function_name(*source).ok().flatten().as_deref()
+                == Some("__quantum__rt__array_create_1d")
+        })
+        .and_then(|source| source.get_operand(1))
+        .and_then(operand_to_integer)
+        .and_then(|length| integer_value_to_u64(context, length))
+        .and_then(|length| usize::try_from(length).ok())
+        .ok_or_else(|| {
+            KindError::InvalidArgument(format!(
+                "unable to determine the contents of an array passed to `{}`: arrays must be created by `__quantum__rt__array_create_1d` with a constant length",
+                describe()
+            ))
+        })?;
+
+    let instructions = instruction
+        .get_parent()
+        .and_then(|block| block.get_parent())
+        .map(|function| {
+            function
+                .get_basic_blocks()
+                .into_iter()
+                .flat_map(|block| {
+                    std::iter::successors(block.get_first_instruction(), |instruction| {
+                        instruction.get_next_instruction()
+                    })
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    // The pointers through which each element is written, which may be cast to the element type.
+    let mut element_pointers: Vec<(PointerValue, usize)> = vec![];
+    for candidate in &instructions {
+        let operand = |index| match candidate.get_operand(index) {
+            Some(Either::Left(BasicValueEnum::PointerValue(pointer))) => Some(pointer),
+            _ => None,
+        };
+        let result = || {
+            candidate
+                .as_any_value_enum()
+                .is_pointer_value()
+                .then(|| candidate.as_any_value_enum().into_pointer_value())
+        };
+        match candidate.get_opcode() {
+            InstructionOpcode::Call
+                if get_called_function_name(*candidate)
+                    .ok()
+                    .flatten()
+                    .as_deref()
+                    == Some("__quantum__rt__array_get_element_ptr_1d")
+                    && operand(0) == Some(array) =>
+            {
+                let index = candidate
+                    .get_operand(1)
+                    .and_then(operand_to_integer)
+                    .and_then(|index| integer_value_to_u64(context, index))
+                    .and_then(|index| usize::try_from(index).ok())
+                    .ok_or_else(|| {
+                        KindError::InvalidArgument(format!(
+                            "unable to determine the contents of an array passed to `{}`: each element must be written at a constant index",
+                            describe()
+                        ))
+                    })?;
+                element_pointers.extend(result().map(|pointer| (pointer, index)));
+            }
+            InstructionOpcode::BitCast => {
+                if let Some(index) = operand(0).and_then(|source| {
+                    element_pointers
+                        .iter()
+                        .find(|(pointer, _)| *pointer == source)
+                        .map(|(_, index)| *index)
+                }) {
+                    element_pointers.extend(result().map(|pointer| (pointer, index)));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut elements = vec![None; length];
+    for candidate in &instructions {
+        if candidate.get_opcode() != InstructionOpcode::Store {
+            continue;
+        }
+        let index = match candidate.get_operand(1) {
+            Some(Either::Left(BasicValueEnum::PointerValue(target))) => element_pointers
+                .iter()
+                .find(|(pointer, _)| *pointer == target)
+                .map(|(_, index)| *index),
+            _ => None,
+        };
+        if let Some(index) = index {
+            let element = match candidate.get_operand(0) {
+                Some(Either::Left(BasicValueEnum::IntValue(value))) => value
+                    .get_zero_extended_constant()
+                    .map(ArrayElement::Integer),
+                Some(Either::Left(BasicValueEnum::PointerValue(value))) => {
+                    pointer_value_to_u64(context, value).map(ArrayElement::Qubit)
+                }
+                _ => None,
+            }
+            .ok_or_else(|| {
+                KindError::InvalidArgument(format!(
+                    "unable to determine element {} of an array passed to `{}`: it is set to a value which is not a constant",
+                    index,
+                    describe()
+                ))
+            })?;
+            let slot = elements.get_mut(index).ok_or_else(|| {
+                eyre!(
+                    "an array passed to `{}` holds {} elements but element {} is written",
+                    describe(),
+                    length,
+                    index
+                )
+            })?;
+            *slot = Some(element);
+        }
+    }
+
+    elements
+        .into_iter()
+        .enumerate()
+        .map(|(index, element)| {
+            element.ok_or_else(|| {
+                eyre::Report::new(KindError::InvalidArgument(format!(
+                    "unable to determine element {} of an array passed to `{}`: it is never written",
+                    index,
+                    describe()
+                )))
+            })
+        })
+        .collect()
+}
+
+/// Build a diagnostic for a `%Qubit*` or `%Result*` argument of `instruction` which does not fold to
+/// a constant, non-negative index, describing where the pointer came from and how to fix the input.
+fn describe_unresolved_pointer(
+    pointer_type: QirPointerType,
+    instruction: InstructionValue,
+    pointer: PointerValue,
+) -> eyre::Report {
+    let type_name = match pointer_type {
+        QirPointerType::Qubit => "Qubit",
+        QirPointerType::Result => "Result",
+        QirPointerType::Array => "Array",
+        QirPointerType::String => "String",
+    };
+
+    let (origin, suggestion) = if let Some(source) = pointer.as_instruction() {
+        let allocated = source.get_opcode() == InstructionOpcode::Call
+            && get_called_function_name(source)
+                .ok()
+                .flatten()
+                .map_or(false, |name| name.ends_with("_allocate"));
+        let origin = format!(
+            "it is produced by `{}`",
+            source.print_to_string().to_string().trim()
+        );
+        if allocated {
+            (
+                origin,
+                "dynamic qubit and result allocation is not supported; refer to each by a constant index, as in the QIR base profile",
+            )
+        } else {
+            (
+                origin,
+                "indices computed at runtime are not supported; refer to each by a constant index, as in the QIR base profile",
+            )
+        }
+    } else if pointer.is_const() {
+        (
+            format!(
+                "it is the constant `{}`",
+                pointer.print_to_string().to_string().trim()
+            ),
+            "indices must be non-negative integers, as in `inttoptr (i64 1 to %Qubit*)`",
+        )
+    } else {
+        (
+            format!(
+                "it is the value `{}`, which is not a constant",
+                pointer.print_to_string().to_string().trim()
+            ),
+            "pass each index as a constant rather than as a function parameter or global",
+        )
+    };
+
+    eyre::Report::new(KindError::InvalidArgument(format!(
+        "unable to determine the {} index of an argument to `{}`: {}\n  help: {}; see the QIR Preconditions in the README",
+        type_name,
+        instruction.print_to_string().to_string().trim(),
+        origin,
+        suggestion
+    )))
+}
+
 /// Attempt to extract an integer value from an operand, and return that integer value if successful
 pub(crate) fn operand_to_integer<'ctx>(
     operand: Either<BasicValueEnum<'ctx>, BasicBlock<'ctx>>,
@@ -134,7 +489,9 @@ pub(crate) fn operand_to_integer<'ctx>(
     }
 }
 
-/// Attempt to cast a pointer to an immediate int and return that value if successful
+/// Attempt to cast a pointer to an immediate int and return that value if successful. A pointer in
+/// any address space is accepted, including one cast from another address space, as some emitters
+/// place `%Qubit` and `%Result` in a non-default address space.
 pub(crate) fn pointer_value_to_u64(
     context: &QCSCompilerContext,
     value: PointerValue,
@@ -143,136 +500,280 @@ pub(crate) fn pointer_value_to_u64(
         .const_to_int(context.base_context.i64_type())
         .get_sign_extended_constant()
         .and_then(|value| u64::try_from(value).ok())
+        .or_else(|| {
+            if !value.is_const() {
+                return None;
+            }
+            let printed_value = value.print_to_string().to_string();
+            let captures = ADDRESS_SPACE_CAST_REGEX.captures(printed_value.trim())?;
+            match captures.name("address") {
+                Some(address) => address.as_str().parse().ok(),
+                None => Some(0),
+            }
+        })
 }
 
-/// Attempt to cast a pointer to an immediate int and return that value if successful
-pub(crate) fn integer_value_to_u64(_context: &QCSCompilerContext, value: IntValue) -> Option<u64> {
-    value
-        .get_sign_extended_constant()
-        .and_then(|value| u64::try_from(value).ok())
+/// Read the constant string to which a pointer refers, such as a `getelementptr` into a global
+/// holding a `c"..."` initializer, returning `None` if it refers to anything else. The string runs
+/// from the byte to which the pointer refers up to the first null byte, or else the end of the array.
+pub(crate) fn pointer_value_to_string(
+    context: &QCSCompilerContext,
+    value: PointerValue,
+) -> Option<String> {
+    let bytes = pointer_value_to_bytes(&context.module, value)?;
+    let end = bytes
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(bytes.len());
+    Some(String::from_utf8_lossy(&bytes[..end]).into_owned())
 }
 
-pub(crate) fn get_conditional_branch_else_target(
-    instruction: InstructionValue,
-) -> Option<BasicBlock> {
-    if let Some(Either::Right(target)) = instruction.get_operand(1) {
-        Some(target)
-    } else {
-        None
+/// Read the bytes of the constant global array to which a pointer refers, from the byte it points
+/// to through the end of the array. The pointer may be the global itself, a cast of it, or a
+/// constant `getelementptr` into it with any constant indices, as in
+/// `getelementptr inbounds ([13 x i8], [13 x i8]* @0, i64 0, i64 7)`, which refers to the last six
+/// bytes. Returns `None` if the pointer refers to anything else, or to a byte beyond the array.
+pub(crate) fn pointer_value_to_bytes(module: &Module, value: PointerValue) -> Option<Vec<u8>> {
+    // The global is found by the name it is printed with, since unnamed globals (`@0`) cannot be
+    // looked up by name.
+    let printed_value = value.print_to_string().to_string();
+    let (reference, offset) = match CONSTANT_ELEMENT_POINTER_REGEX.captures(&printed_value) {
+        Some(captures) => {
+            let indices = INDEX_REGEX
+                .captures_iter(&captures["indices"])
+                .map(|index| index["index"].parse().ok())
+                .collect::<Option<Vec<i64>>>()?;
+            let offset = get_byte_offset(captures["source"].trim(), &indices)?;
+            (captures["global"].to_owned(), offset)
+        }
+        None if !printed_value.contains("getelementptr") => (
+            GLOBAL_REFERENCE_REGEX
+                .find(&printed_value)?
+                .as_str()
+                .to_owned(),
+            0,
+        ),
+        None => return None,
+    };
+
+    let bytes = get_global_bytes(module, &reference)?;
+    let offset = usize::try_from(offset)
+        .ok()
+        .filter(|offset| *offset <= bytes.len())?;
+    Some(bytes[offset..].to_vec())
+}
+
+/// The offset in bytes of the element selected by the `indices` of a `getelementptr` from a pointer
+/// to `source`, either an array of bytes or a single byte.
+fn get_byte_offset(source: &str, indices: &[i64]) -> Option<i64> {
+    if source == "i8" {
+        return match indices {
+            [index] => Some(*index),
+            _ => None,
+        };
+    }
+    let length: i64 = BYTE_ARRAY_TYPE_REGEX.captures(source)?["length"]
+        .parse()
+        .ok()?;
+    match indices {
+        [array] => array.checked_mul(length),
+        [array, element] => array.checked_mul(length)?.checked_add(*element),
+        _ => None,
     }
 }
 
-/// Given a conditional branch (`br`) instruction, replace its then and/or else targets with the specified basic blocks.
-///
-/// Note: this function moves the builder's position and does not restore it.
-pub(crate) fn replace_conditional_branch_target(
-    context: &mut QCSCompilerContext,
-    instruction: InstructionValue,
-    replace_then: Option<&BasicBlock>,
-    replace_else: Option<&BasicBlock>,
-) -> Result<()> {
-    context.builder.position_at(
-        instruction
-            .get_parent()
-            .ok_or_else(|| eyre!("Expected instruction to have a parent"))?,
-        &instruction,
-    );
-
-    let original_then_block = if let Some(Either::Right(target)) = instruction.get_operand(2) {
-        target
-    } else {
-        return Err(eyre!("expected basic block target for branch"));
-    };
+/// The bytes with which the global array printed as `reference`, such as `@0`, is initialized.
+fn get_global_bytes(module: &Module, reference: &str) -> Option<Vec<u8>> {
+    let mut next_global = module.get_first_global();
+    while let Some(global) = next_global {
+        let printed_global = global.as_pointer_value().print_to_string().to_string();
+        if let Some((name, definition)) = printed_global.split_once(" = ") {
+            if name == reference {
+                let captures = BYTE_ARRAY_INITIALIZER_REGEX.captures(definition)?;
+                let length = captures["length"].parse().ok()?;
+                let bytes = match captures.name("string") {
+                    Some(string) => decode_string_constant(string.as_str())?,
+                    None => vec![0; length],
+                };
+                return (bytes.len() == length).then(|| bytes);
+            }
+        }
+        next_global = global.get_next_global();
+    }
+    None
+}
 
-    let original_else_block = if let Some(Either::Right(target)) = instruction.get_operand(1) {
-        target
-    } else {
-        return Err(eyre!("expected basic block target for branch"));
-    };
+/// Decode the text of a `c"..."` constant, in which LLVM writes a backslash as `\\` and each other
+/// byte which is not printable as `\` followed by its two hexadecimal digits.
+fn decode_string_constant(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = vec![];
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'\\' && tail.first() == Some(&b'\\') {
+            bytes.push(byte);
+            rest = &tail[1..];
+        } else if byte == b'\\' {
+            let digits = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(digits, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    Some(bytes)
+}
 
-    let (then_block, else_block) = (
-        replace_then.unwrap_or(&original_then_block),
-        replace_else.unwrap_or(&original_else_block),
-    );
+/// The runtime function which creates a `%String*` from a C string.
+pub(crate) const STRING_CREATE_FUNCTION_NAME: &str = "__quantum__rt__string_create";
 
-    let comparison = if let Some(Either::Left(BasicValueEnum::IntValue(comparison))) =
-        instruction.get_operand(0)
+/// Read the constant text of a `%String*` created by `__quantum__rt__string_create` from a pointer
+/// to a constant string, as read by [`pointer_value_to_string`], returning `None` if it is created
+/// in any other way.
+pub(crate) fn runtime_string_to_string(
+    context: &QCSCompilerContext,
+    value: PointerValue,
+) -> Option<String> {
+    let instruction = value.as_instruction()?;
+    if instruction.get_opcode() != InstructionOpcode::Call
+        || get_called_function_name(instruction).ok()?.as_deref()
+            != Some(STRING_CREATE_FUNCTION_NAME)
     {
-        comparison
-    } else {
-        return Err(eyre!("expected integer comparison for branch"));
-    };
+        return None;
+    }
+    match instruction.get_operand(0)? {
+        Either::Left(BasicValueEnum::PointerValue(pointer)) => {
+            pointer_value_to_string(context, pointer)
+        }
+        _ => None,
+    }
+}
 
-    let new_instruction =
-        context
-            .builder
-            .build_conditional_branch(comparison, *then_block, *else_block);
-    instruction.replace_all_uses_with(&new_instruction);
-    instruction.remove_from_basic_block();
-    Ok(())
+/// Evaluate a pointer computed by integer arithmetic on the values in `bindings`, such as
+/// `inttoptr i64 %index to %Qubit*` where `%index` is bound, and return its address.
+fn evaluate_bound_pointer<'ctx>(
+    value: PointerValue<'ctx>,
+    bindings: &[(InstructionValue<'ctx>, i64)],
+) -> Option<u64> {
+    if bindings.is_empty() {
+        return None;
+    }
+    evaluate_integer(value.into(), bindings).and_then(|value| u64::try_from(value).ok())
 }
 
-/// Given a `phi` instruction, replace the existing matching block with the new one specified.
-///
-/// Parameters:
-/// * `reverse_match`: whether to match all incoming clauses that _aren't_ from the specified original basic block instead
-///   of those that _are_.
-pub(crate) fn replace_phi_clause(
-    context: &mut QCSCompilerContext,
-    instruction: PhiValue,
-    old_basic_block: BasicBlock,
-    new_basic_block: BasicBlock,
-    reverse_match: bool,
-) -> Result<()> {
-    let basic_block_parent = instruction
-        .as_instruction()
-        .get_parent()
-        .ok_or_else(|| eyre!("Expected instruction to have a parent"))?;
-
-    // We have to ensure that we're writing all phi instructions at the start of the basic block;
-    // in LLVM IR no non-phi instructions may precede any phi instruction in the block.
-    context.builder.position_before(
-        &basic_block_parent
-            .get_first_instruction()
-            .ok_or_else(|| eyre!("Expected basic block to have at least one instruction"))?,
-    );
-
-    let mut new_incoming: Vec<(BasicValueEnum, BasicBlock)> = vec![];
-
-    // FromIterator not implemented
-    // let new_incoming = (0..instruction.count_incoming()).map(|index| {
-    // let value = instruction.get_incoming(index).unwrap();
-
-    // TODO: Rework this so it's less clumsy.
-    // The trick is that get_incoming gives you an owned value but add_incoming wants a &dyn,
-    // so you need to own the values somewhere long enough to be able to supply them to `add_incoming`.
-    for index in 0..instruction.count_incoming() {
-        let value = instruction
-            .get_incoming(index)
-            .ok_or_else(|| eyre!("Expected phi instruction to have incoming values"))?;
-
-        if reverse_match ^ (value.1 == old_basic_block) {
-            new_incoming.push((value.0, new_basic_block));
-        } else {
-            new_incoming.push((value.0, value.1));
-        }
+/// Evaluate an integer (or pointer) value built from constants and the values in `bindings` by
+/// `add`, `sub`, `mul`, `shl`, casts, and `icmp`, returning `None` if it depends on anything else.
+/// Each value is the unsigned integer of the bits of its type, held in an `i64`, so that arithmetic
+/// wraps at the width of its operands as in LLVM and a comparison evaluates to `0` or `1`. Pointers
+/// are taken to be 64 bits wide.
+pub(crate) fn evaluate_integer<'ctx>(
+    value: BasicValueEnum<'ctx>,
+    bindings: &[(InstructionValue<'ctx>, i64)],
+) -> Option<i64> {
+    let instruction = match value {
+        BasicValueEnum::IntValue(value) => match value.get_zero_extended_constant() {
+            Some(constant) => {
+                #[allow(clippy::cast_possible_wrap)]
+                let constant = constant as i64;
+                return Some(constant);
+            }
+            None => value.as_instruction()?,
+        },
+        BasicValueEnum::PointerValue(value) if value.is_null() => return Some(0),
+        BasicValueEnum::PointerValue(value) => value.as_instruction()?,
+        _ => return None,
+    };
+
+    if let Some((_, bound)) = bindings.iter().find(|(bound, _)| *bound == instruction) {
+        return Some(*bound);
     }
 
-    let mut new_incoming_ref: Vec<(&dyn BasicValue, BasicBlock)> = vec![];
+    let width = match instruction.get_type() {
+        AnyTypeEnum::IntType(int_type) => int_type.get_bit_width(),
+        AnyTypeEnum::PointerType(_) => 64,
+        _ => return None,
+    };
+    let operand = |index| instruction.get_operand(index).and_then(Either::left);
+    let evaluate = |index| operand(index).and_then(|operand| evaluate_integer(operand, bindings));
+
+    let result = match instruction.get_opcode() {
+        InstructionOpcode::Add => evaluate(0)?.wrapping_add(evaluate(1)?),
+        InstructionOpcode::Sub => evaluate(0)?.wrapping_sub(evaluate(1)?),
+        InstructionOpcode::Mul => evaluate(0)?.wrapping_mul(evaluate(1)?),
+        InstructionOpcode::Shl => {
+            // Shifting by the width of the operand or more is poison
+            let shift = u32::try_from(evaluate(1)?)
+                .ok()
+                .filter(|shift| *shift < width)?;
+            evaluate(0)? << shift
+        }
+        InstructionOpcode::SExt => sign_extend(evaluate(0)?, bit_width(operand(0)?)?),
+        // Values are already zero-extended, and truncated below
+        InstructionOpcode::ZExt
+        | InstructionOpcode::Trunc
+        | InstructionOpcode::IntToPtr
+        | InstructionOpcode::PtrToInt
+        | InstructionOpcode::AddrSpaceCast => evaluate(0)?,
+        InstructionOpcode::ICmp => {
+            let operand_width = bit_width(operand(0)?)?;
+            let (left, right) = (evaluate(0)?, evaluate(1)?);
+            let (signed_left, signed_right) = (
+                sign_extend(left, operand_width),
+                sign_extend(right, operand_width),
+            );
+            #[allow(clippy::cast_sign_loss)]
+            let (unsigned_left, unsigned_right) = (left as u64, right as u64);
+            let result = match instruction.get_icmp_predicate()? {
+                IntPredicate::EQ => left == right,
+                IntPredicate::NE => left != right,
+                IntPredicate::SLT => signed_left < signed_right,
+                IntPredicate::SLE => signed_left <= signed_right,
+                IntPredicate::SGT => signed_left > signed_right,
+                IntPredicate::SGE => signed_left >= signed_right,
+                IntPredicate::ULT => unsigned_left < unsigned_right,
+                IntPredicate::ULE => unsigned_left <= unsigned_right,
+                IntPredicate::UGT => unsigned_left > unsigned_right,
+                IntPredicate::UGE => unsigned_left >= unsigned_right,
+            };
+            i64::from(result)
+        }
+        _ => return None,
+    };
+    Some(truncate(result, width))
+}
 
-    for element in &new_incoming {
-        new_incoming_ref.push((&element.0, element.1));
+/// The width in bits of an integer or pointer value, taking pointers to be 64 bits wide.
+fn bit_width(value: BasicValueEnum) -> Option<u32> {
+    match value {
+        BasicValueEnum::IntValue(value) => Some(value.get_type().get_bit_width()),
+        BasicValueEnum::PointerValue(_) => Some(64),
+        _ => None,
     }
+}
 
-    // TODO: derive the type from the actual instruction instead of assuming i64
-    let new_instruction = context
-        .builder
-        .build_phi(context.base_context.i64_type(), "");
-    new_instruction.add_incoming(new_incoming_ref.as_slice());
+/// The low `width` bits of `value`, as an unsigned integer.
+fn truncate(value: i64, width: u32) -> i64 {
+    if width >= 64 {
+        value
+    } else {
+        value & !(-1 << width)
+    }
+}
 
-    instruction.replace_all_uses_with(&new_instruction);
-    instruction.as_instruction().remove_from_basic_block();
-    Ok(())
+/// The signed integer of the low `width` bits of `value`.
+fn sign_extend(value: i64, width: u32) -> i64 {
+    if width >= 64 {
+        value
+    } else {
+        (value << (64 - width)) >> (64 - width)
+    }
+}
+
+/// Attempt to cast a pointer to an immediate int and return that value if successful
+pub(crate) fn integer_value_to_u64(_context: &QCSCompilerContext, value: IntValue) -> Option<u64> {
+    value
+        .get_sign_extended_constant()
+        .and_then(|value| u64::try_from(value).ok())
 }
 
 /// Print each of the operands of an instruction in debug format to stdout on its own labeled line.
@@ -285,61 +786,131 @@ pub(crate) fn print_all_operands(instruction: InstructionValue) {
     }
 }
 
-pub(crate) fn replace_phi_clauses(
-    context: &mut QCSCompilerContext,
-    within_basic_block: BasicBlock,
-    old_basic_block: BasicBlock,
-    new_basic_block: BasicBlock,
-    reverse_match: bool,
-) -> Result<()> {
-    let mut instruction = within_basic_block.get_first_instruction();
-
-    while let Some(current_instruction) = instruction {
-        // We have to get a valid handle on the next instruction before replace_phi_clause deletes this one.
-        let next_instruction = current_instruction.get_next_instruction();
-        if current_instruction.get_opcode() == InstructionOpcode::Phi {
-            replace_phi_clause(
-                context,
-                current_instruction
-                    .try_into()
-                    .map_err(|_| eyre!("Expected phi instruction"))?,
-                old_basic_block,
-                new_basic_block,
-                reverse_match,
-            )?;
-        }
-        instruction = next_instruction;
-    }
-    Ok(())
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interop::load::load_module_from_ir;
+
+    const IR: &str = r#"
+@0 = internal constant [13 x i8] c"Hello, world\00"
+@1 = internal constant [4 x i8] c"a\00b\00"
+@2 = internal constant [4 x i8] zeroinitializer
+@3 = internal constant [3 x i8] c"\22\5C\0A"
+
+declare void @f(i8*)
+
+define void @main() {
+entry:
+  call void @f(i8* getelementptr inbounds ([13 x i8], [13 x i8]* @0, i64 0, i64 0))
+  call void @f(i8* getelementptr inbounds ([13 x i8], [13 x i8]* @0, i64 0, i64 7))
+  call void @f(i8* getelementptr ([13 x i8], [13 x i8]* @0, i32 0, i32 12))
+  call void @f(i8* getelementptr inbounds ([4 x i8], [4 x i8]* @1, i64 0, i64 2))
+  call void @f(i8* bitcast ([4 x i8]* @2 to i8*))
+  call void @f(i8* getelementptr inbounds ([3 x i8], [3 x i8]* @3, i64 0, i64 0))
+  call void @f(i8* getelementptr ([13 x i8], [13 x i8]* @0, i64 1, i64 0))
+  call void @f(i8* getelementptr ([13 x i8], [13 x i8]* @0, i64 0, i64 20))
+  call void @f(i8* getelementptr ([13 x i8], [13 x i8]* @0, i64 0, i64 -1))
+  ret void
 }
+"#;
+
+    #[test]
+    fn reads_constant_arrays_from_any_element() {
+        let context = inkwell::context::Context::create();
+        let module = load_module_from_ir(&context, IR).unwrap();
+        let entry = module
+            .get_function("main")
+            .and_then(|function| function.get_first_basic_block())
+            .unwrap();
+
+        let mut slices = vec![];
+        let mut next_instruction = entry.get_first_instruction();
+        while let Some(instruction) = next_instruction {
+            if let Some(Either::Left(BasicValueEnum::PointerValue(pointer))) =
+                instruction.get_operand(0)
+            {
+                slices.push(pointer_value_to_bytes(&module, pointer));
+            }
+            next_instruction = instruction.get_next_instruction();
+        }
 
-/// Remove instructions in topological order such that none is removed while any other instruction uses it.
-pub(crate) fn remove_instructions_in_safe_order(instructions: Vec<InstructionValue>) -> Result<()> {
-    let mut instructions = instructions;
-
-    loop {
-        let mut instructions_removed_in_round = false;
+        assert_eq!(
+            slices,
+            [
+                Some(b"Hello, world\0".to_vec()),
+                Some(b"world\0".to_vec()),
+                Some(b"\0".to_vec()),
+                Some(b"b\0".to_vec()),
+                Some(vec![0; 4]),
+                Some(b"\"\\\n".to_vec()),
+                Some(vec![]),
+                None,
+                None,
+            ]
+        );
+    }
 
-        instructions.retain(|instr| {
-            // if an instruction is still in use at current iteration, mark it to be kept until other
-            // instructions are removed (which may be dependent on this instruction)
-            if instr.get_first_use().is_some() {
-                true
-            } else {
-                instr.remove_from_basic_block();
-                instructions_removed_in_round = true;
-                false
+    #[test]
+    fn evaluates_integers_at_the_width_of_their_type() {
+        const IR: &str = r#"
+declare void @f1(i1)
+declare void @f8(i8)
+declare void @f64(i64)
+
+define void @main() {
+entry:
+  %zext_true = zext i1 true to i64
+  call void @f64(i64 %zext_true)
+  %zext_minus_one = zext i32 -1 to i64
+  call void @f64(i64 %zext_minus_one)
+  %sext_minus_one = sext i8 -1 to i64
+  call void @f64(i64 %sext_minus_one)
+  %trunc = trunc i64 256 to i8
+  call void @f8(i8 %trunc)
+  %add = add i8 -1, 1
+  call void @f8(i8 %add)
+  %mul = mul i32 65536, 65536
+  %mul_zext = zext i32 %mul to i64
+  call void @f64(i64 %mul_zext)
+  %slt = icmp slt i8 -1, 0
+  call void @f1(i1 %slt)
+  %ult = icmp ult i8 -1, 0
+  call void @f1(i1 %ult)
+  %shl = shl i8 1, 8
+  call void @f8(i8 %shl)
+  ret void
+}
+"#;
+        let context = inkwell::context::Context::create();
+        let module = load_module_from_ir(&context, IR).unwrap();
+        let entry = module
+            .get_function("main")
+            .and_then(|function| function.get_first_basic_block())
+            .unwrap();
+
+        let mut values = vec![];
+        let mut next_instruction = entry.get_first_instruction();
+        while let Some(instruction) = next_instruction {
+            if instruction.get_opcode() == InstructionOpcode::Call {
+                let argument = instruction.get_operand(0).and_then(Either::left).unwrap();
+                values.push(evaluate_integer(argument, &[]));
             }
-        });
-
-        // no more instructions to remove, bail
-        if instructions.is_empty() {
-            return Ok(());
+            next_instruction = instruction.get_next_instruction();
         }
 
-        // still have instructions to remove, but all were still in use and cannot be removed
-        if !instructions_removed_in_round {
-            return Err(eyre!("Unable to remove remaining instructions safely"));
-        }
+        assert_eq!(
+            values,
+            [
+                Some(1),
+                Some(0xffff_ffff),
+                Some(-1),
+                Some(0),
+                Some(0),
+                Some(0),
+                Some(1),
+                Some(0),
+                None,
+            ]
+        );
     }
 }
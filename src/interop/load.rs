@@ -12,18 +12,59 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use eyre::{eyre, Result};
+use eyre::{eyre, Report, Result};
 use inkwell::memory_buffer::MemoryBuffer;
 use inkwell::module::Module;
 
+use crate::error::KindError;
+
 // Given a file path to an LLVM bitcode file, load its contents into an `inkwell::Module`.
 pub(crate) fn load_module_from_bitcode<'ctx>(
     context: &'ctx inkwell::context::Context,
     data: &[u8],
 ) -> Result<Module<'ctx>> {
     let buffer = MemoryBuffer::create_from_memory_range_copy(data, "qcs");
-    Module::parse_bitcode_from_buffer(&buffer, context)
-        .map_err(|e| eyre!(e.to_string()).wrap_err("failed to parse bitcode"))
+    Module::parse_bitcode_from_buffer(&buffer, context).map_err(|e| {
+        Report::new(KindError::InvalidBitcode(e.to_string())).wrap_err("failed to parse bitcode")
+    })
+}
+
+/// Parse textual LLVM IR into a verified `inkwell::Module`.
+pub(crate) fn load_module_from_ir<'ctx>(
+    context: &'ctx inkwell::context::Context,
+    ir: &str,
+) -> Result<Module<'ctx>> {
+    let buffer = MemoryBuffer::create_from_memory_range_copy(ir.as_bytes(), "qcs");
+    let module = context.create_module_from_ir(buffer).map_err(|e| {
+        Report::new(KindError::InvalidBitcode(e.to_string())).wrap_err("failed to parse LLVM IR")
+    })?;
+    module.verify().map_err(|e| {
+        Report::new(KindError::InvalidBitcode(e.to_string()))
+            .wrap_err("LLVM IR is not a valid module")
+    })?;
+    Ok(module)
+}
+
+/// Given several LLVM bitcodes, load each and link them all into the first, so that a program may be
+/// factored into an entrypoint module and any number of library modules.
+pub(crate) fn load_and_link_modules_from_bitcode<'ctx>(
+    context: &'ctx inkwell::context::Context,
+    data: &[&[u8]],
+) -> Result<Module<'ctx>> {
+    let (first, rest) = data
+        .split_first()
+        .ok_or_else(|| eyre!("at least one bitcode must be provided"))?;
+    let module = load_module_from_bitcode(context, first)?;
+
+    for (index, bitcode) in rest.iter().enumerate() {
+        let other = load_module_from_bitcode(context, bitcode)?;
+        module.link_in_module(other).map_err(|e| {
+            eyre!(e.to_string())
+                .wrap_err(format!("failed to link bitcode at position {}", index + 1))
+        })?;
+    }
+
+    Ok(module)
 }
 
 #[cfg(test)]
@@ -37,4 +78,36 @@ mod tests {
         let context = inkwell::context::Context::create();
         load_module_from_bitcode(&context, &data).unwrap();
     }
+
+    #[test]
+    fn can_load_ir_file() {
+        let ir = std::fs::read_to_string("tests/fixtures/programs/bell_state.ll").unwrap();
+        let context = inkwell::context::Context::create();
+        let module = load_module_from_ir(&context, &ir).unwrap();
+
+        let bitcode = module.write_bitcode_to_memory();
+        let reloaded = load_module_from_bitcode(&context, bitcode.as_slice()).unwrap();
+        assert_eq!(
+            reloaded.print_to_string().to_string(),
+            module.print_to_string().to_string()
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_ir() {
+        let context = inkwell::context::Context::create();
+        assert!(load_module_from_ir(&context, "define void @f() {").is_err());
+    }
+
+    #[test]
+    fn can_link_bitcode_files() {
+        let entrypoint = std::fs::read("tests/fixtures/programs/linking/entrypoint.bc").unwrap();
+        let library = std::fs::read("tests/fixtures/programs/linking/library.bc").unwrap();
+        let context = inkwell::context::Context::create();
+        let module =
+            load_and_link_modules_from_bitcode(&context, &[&entrypoint, &library]).unwrap();
+
+        let library_function = module.get_function("prepare_bell_state").unwrap();
+        assert!(library_function.count_basic_blocks() > 0);
+    }
 }
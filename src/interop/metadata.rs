@@ -0,0 +1,217 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Functions which read program-level data attached to a QIR module as named metadata.
+use std::str::FromStr;
+
+use eyre::{eyre, Result};
+use inkwell::{
+    attributes::AttributeLoc,
+    module::Module,
+    values::{BasicMetadataValueEnum, FunctionValue},
+};
+
+/// Name of the metadata node holding Quil-T calibrations (`DEFCAL`, `DEFFRAME`, `DEFWAVEFORM`) to be
+/// included in every program generated from the module, for example:
+///
+/// ```llvm
+/// !qcs.defcal = !{!0}
+/// !0 = !{!"DEFCAL RX(pi/2) 0:\n    NONBLOCKING PULSE 0 \"rf\" flat(duration: 1e-6, iq: 0.5)"}
+/// ```
+pub(crate) const CALIBRATIONS_METADATA_NAME: &str = "qcs.defcal";
+
+/// Name of the metadata node holding the number of shots for which a unitary program runs, for
+/// example:
+///
+/// ```llvm
+/// !quantum.shots = !{!0}
+/// !0 = !{i64 1000}
+/// ```
+pub(crate) const SHOTS_METADATA_NAME: &str = "quantum.shots";
+
+/// Name of the entry point attribute and of the module flag which may also give the number of shots,
+/// as `"shots"="1000"` and `!{i32 1, !"shots", i64 1000}` respectively.
+pub(crate) const SHOTS_FLAG_NAME: &str = "shots";
+
+/// Name of the metadata node holding the module flags.
+pub(crate) const MODULE_FLAGS_METADATA_NAME: &str = "llvm.module.flags";
+
+/// Parse the Quil-T calibrations attached to a module, concatenating the strings of every
+/// `!qcs.defcal` operand in order. Returns `None` if the module carries no calibrations.
+pub(crate) fn get_calibrations(module: &Module) -> Result<Option<quil_rs::Program>> {
+    let calibrations = module
+        .get_global_metadata(CALIBRATIONS_METADATA_NAME)
+        .into_iter()
+        .flat_map(|node| node.get_node_values())
+        .filter_map(|value| match value {
+            BasicMetadataValueEnum::MetadataValue(value) => value
+                .get_string_value()
+                .map(|text| text.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    if calibrations.is_empty() {
+        return Ok(None);
+    }
+
+    quil_rs::Program::from_str(&calibrations.join("\n"))
+        .map(Some)
+        .map_err(|error| {
+            eyre!(
+                "invalid Quil in !{} metadata: {}",
+                CALIBRATIONS_METADATA_NAME,
+                error
+            )
+        })
+}
+
+/// Return the value of the module flag named `name`, if the module sets it.
+pub(crate) fn get_module_flag<'ctx>(
+    module: &Module<'ctx>,
+    name: &str,
+) -> Option<BasicMetadataValueEnum<'ctx>> {
+    module
+        .get_global_metadata(MODULE_FLAGS_METADATA_NAME)
+        .into_iter()
+        .find_map(|flag| match flag.get_node_values().as_slice() {
+            // Each flag is a triple of its merge behavior, its name, and its value
+            [_, BasicMetadataValueEnum::MetadataValue(key), value]
+                if key
+                    .get_string_value()
+                    .map_or(false, |key| key.to_string_lossy() == name) =>
+            {
+                Some(*value)
+            }
+            _ => None,
+        })
+}
+
+/// Read the number of shots for which the program in `function` runs, as given by the `shots`
+/// attribute of the function, the `shots` module flag, or the `!quantum.shots` metadata, in that
+/// order of precedence. Returns `None` if none of these is present.
+pub(crate) fn get_shot_count(module: &Module, function: FunctionValue) -> Result<Option<u64>> {
+    let (source, shot_count) = if let Some(attribute) =
+        function.get_string_attribute(AttributeLoc::Function, SHOTS_FLAG_NAME)
+    {
+        let value = attribute.get_string_value().to_string_lossy().into_owned();
+        (
+            format!(
+                "\"{}\" attribute of @{}",
+                SHOTS_FLAG_NAME,
+                function.get_name().to_string_lossy()
+            ),
+            value.parse::<u64>().ok(),
+        )
+    } else if let Some(value) = get_module_flag(module, SHOTS_FLAG_NAME) {
+        (
+            format!("\"{}\" module flag", SHOTS_FLAG_NAME),
+            parse_shot_count(value),
+        )
+    } else if let Some(value) = module
+        .get_global_metadata(SHOTS_METADATA_NAME)
+        .into_iter()
+        .find_map(|node| node.get_node_values().into_iter().next())
+    {
+        (
+            format!("!{} metadata", SHOTS_METADATA_NAME),
+            parse_shot_count(value),
+        )
+    } else {
+        return Ok(None);
+    };
+
+    match shot_count {
+        Some(shot_count) if shot_count > 0 => Ok(Some(shot_count)),
+        _ => Err(eyre!("expected a positive shot count in the {}", source)),
+    }
+}
+
+/// Read a shot count given as an integer constant or as a string holding one.
+fn parse_shot_count(value: BasicMetadataValueEnum) -> Option<u64> {
+    match value {
+        BasicMetadataValueEnum::IntValue(value) => value.get_zero_extended_constant(),
+        BasicMetadataValueEnum::MetadataValue(value) => value
+            .get_string_value()
+            .and_then(|text| text.to_string_lossy().parse().ok()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::interop::load::{load_module_from_bitcode, load_module_from_ir};
+
+    fn shot_count_of(ir: &str) -> Result<Option<u64>> {
+        let context = inkwell::context::Context::create();
+        let module = load_module_from_ir(&context, ir).unwrap();
+        get_shot_count(&module, module.get_function("f").unwrap())
+    }
+
+    #[test]
+    fn reads_calibrations_from_metadata() {
+        let context = inkwell::context::Context::create();
+        let data = std::fs::read("tests/fixtures/programs/defcal_metadata.bc").unwrap();
+        let module = load_module_from_bitcode(&context, &data).unwrap();
+
+        let calibrations = get_calibrations(&module).unwrap().unwrap();
+
+        assert!(calibrations.to_string(true).contains("DEFCAL H 0"));
+    }
+
+    #[test]
+    fn modules_without_calibrations_have_none() {
+        let context = inkwell::context::Context::create();
+        let data = std::fs::read("tests/fixtures/programs/measure.bc").unwrap();
+        let module = load_module_from_bitcode(&context, &data).unwrap();
+
+        assert!(get_calibrations(&module).unwrap().is_none());
+    }
+
+    #[test]
+    fn reads_shot_count_in_order_of_precedence() {
+        const METADATA: &str = r#"
+define void @f() #0 {
+  ret void
+}
+
+attributes #0 = { "shots"="20" }
+
+!llvm.module.flags = !{!0}
+!quantum.shots = !{!1}
+!0 = !{i32 1, !"shots", i64 500}
+!1 = !{i64 1000}
+"#;
+        assert_eq!(shot_count_of(METADATA).unwrap(), Some(20));
+
+        let without_attribute = METADATA
+            .replace(" #0 {", " {")
+            .replace("attributes #0", "; ");
+        assert_eq!(shot_count_of(&without_attribute).unwrap(), Some(500));
+
+        let without_flag = without_attribute.replace("!llvm.module.flags = !{!0}", "");
+        assert_eq!(shot_count_of(&without_flag).unwrap(), Some(1000));
+
+        let without_metadata = without_flag.replace("!quantum.shots = !{!1}", "");
+        assert_eq!(shot_count_of(&without_metadata).unwrap(), None);
+
+        let invalid = METADATA.replace(r#""shots"="20""#, r#""shots"="many""#);
+        assert_eq!(
+            shot_count_of(&invalid).unwrap_err().to_string(),
+            r#"expected a positive shot count in the "shots" attribute of @f"#
+        );
+    }
+}
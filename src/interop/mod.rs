@@ -12,7 +12,19 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[cfg(feature = "transform")]
+pub(crate) mod abi;
+#[cfg(feature = "transform")]
 pub(crate) mod call;
+#[cfg(feature = "transform")]
+pub(crate) mod cfg;
+pub(crate) mod compat;
 pub(crate) mod entrypoint;
 pub(crate) mod instruction;
 pub(crate) mod load;
+pub(crate) mod metadata;
+#[cfg(feature = "transform")]
+pub(crate) mod provenance;
+#[cfg(feature = "transform")]
+pub(crate) mod rewrite;
+pub(crate) mod validate;
@@ -0,0 +1,213 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Records in a patched module how it was produced, so that an artifact may be traced back to the
+// input and options it was patched from.
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use eyre::{eyre, Result};
+use inkwell::{
+    module::{Linkage, Module},
+    values::BasicMetadataValueEnum,
+    GlobalVisibility,
+};
+#[cfg(feature = "serde_support")]
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::context::QCSCompilerContext;
+use crate::interop::abi::RUNTIME_ABI_VERSION;
+use crate::PatchOptions;
+
+/// Name of the metadata node holding the provenance of a patched module, one key-value pair per
+/// operand, for example:
+///
+/// ```llvm
+/// !qcs_sdk_qir.provenance = !{!0, !1, !2, !3, !4}
+/// !0 = !{!"crate_version", !"0.1.0"}
+/// !1 = !{!"runtime_abi_version", !"1"}
+/// ```
+const PROVENANCE_METADATA_NAME: &str = "qcs_sdk_qir.provenance";
+
+/// Name of the global holding the same provenance as text, so that it may be found with `strings`
+/// in an object file or executable built from the module.
+const PROVENANCE_GLOBAL_NAME: &str = "qcs_sdk_qir_provenance";
+
+/// How a module was patched, as recorded in it when [`PatchOptions::stamp_provenance`] is set.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize))]
+pub struct Provenance {
+    /// The version of this crate which patched the module.
+    pub crate_version: String,
+    /// The version of the runtime ABI which the module was patched against.
+    pub runtime_abi_version: u64,
+    /// The options which affected the output, as space-separated `key=value` pairs.
+    pub options: String,
+    /// The hex-encoded SHA-256 digest of the input bitcodes, concatenated in the order they were
+    /// linked.
+    pub input_sha256: String,
+    /// When the module was patched, in seconds since the Unix epoch. `SOURCE_DATE_EPOCH` is used
+    /// in its place when set, so that builds may be reproduced exactly.
+    pub timestamp: u64,
+}
+
+impl Provenance {
+    /// Describe patching `bitcodes` with `options` now.
+    pub(crate) fn new(options: &PatchOptions, bitcodes: &[&[u8]]) -> Self {
+        let mut hasher = Sha256::new();
+        for bitcode in bitcodes {
+            hasher.update(bitcode);
+        }
+        let input_sha256 = hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        let timestamp = std::env::var("SOURCE_DATE_EPOCH")
+            .ok()
+            .and_then(|epoch| epoch.trim().parse().ok())
+            .unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |elapsed| elapsed.as_secs())
+            });
+
+        Self {
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            runtime_abi_version: RUNTIME_ABI_VERSION,
+            options: options.describe(),
+            input_sha256,
+            timestamp,
+        }
+    }
+
+    fn to_pairs(&self) -> [(&'static str, String); 5] {
+        [
+            ("crate_version", self.crate_version.clone()),
+            ("runtime_abi_version", self.runtime_abi_version.to_string()),
+            ("options", self.options.clone()),
+            ("input_sha256", self.input_sha256.clone()),
+            ("timestamp", self.timestamp.to_string()),
+        ]
+    }
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, value) in self.to_pairs() {
+            writeln!(f, "{}: {}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Record `provenance` in the module as named metadata and as a constant string global. The global
+/// is `weak` so that modules patched separately, each with their own provenance, may still be
+/// linked together.
+pub(crate) fn stamp_provenance(
+    context: &QCSCompilerContext,
+    provenance: &Provenance,
+) -> Result<()> {
+    let base_context = context.base_context;
+    let pairs = provenance.to_pairs();
+
+    for (key, value) in &pairs {
+        let node = base_context.metadata_node(&[
+            base_context.metadata_string(key).into(),
+            base_context.metadata_string(value).into(),
+        ]);
+        context
+            .module
+            .add_global_metadata(PROVENANCE_METADATA_NAME, &node)
+            .map_err(|error| eyre!("failed to add provenance metadata: {}", error))?;
+    }
+
+    let text = pairs
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let initializer = base_context.const_string(text.as_bytes(), true);
+    let global = context
+        .module
+        .add_global(initializer.get_type(), None, PROVENANCE_GLOBAL_NAME);
+    global.set_linkage(Linkage::Weak);
+    global.set_constant(true);
+    global.set_initializer(&initializer);
+
+    Ok(())
+}
+
+/// Give hidden visibility to the provenance global, if the module has one.
+pub(crate) fn hide_provenance(module: &Module) {
+    if let Some(global) = module.get_global(PROVENANCE_GLOBAL_NAME) {
+        global.set_visibility(GlobalVisibility::Hidden);
+    }
+}
+
+/// Read the provenance recorded in a module, returning `None` if it carries none.
+pub(crate) fn get_provenance(module: &Module) -> Result<Option<Provenance>> {
+    let pairs = module
+        .get_global_metadata(PROVENANCE_METADATA_NAME)
+        .into_iter()
+        .filter_map(|node| {
+            let strings = node
+                .get_node_values()
+                .into_iter()
+                .filter_map(|value| match value {
+                    BasicMetadataValueEnum::MetadataValue(value) => value
+                        .get_string_value()
+                        .map(|text| text.to_string_lossy().into_owned()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            match strings.as_slice() {
+                [key, value] => Some((key.clone(), value.clone())),
+                _ => None,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if pairs.is_empty() {
+        return Ok(None);
+    }
+
+    let get = |key: &str| {
+        pairs
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.clone())
+            .ok_or_else(|| eyre!("!{} metadata has no {}", PROVENANCE_METADATA_NAME, key))
+    };
+    let get_number = |key: &str| {
+        get(key)?.parse().map_err(|error| {
+            eyre!(
+                "invalid {} in !{} metadata: {}",
+                key,
+                PROVENANCE_METADATA_NAME,
+                error
+            )
+        })
+    };
+
+    Ok(Some(Provenance {
+        crate_version: get("crate_version")?,
+        runtime_abi_version: get_number("runtime_abi_version")?,
+        options: get("options")?,
+        input_sha256: get("input_sha256")?,
+        timestamp: get_number("timestamp")?,
+    }))
+}
@@ -0,0 +1,298 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Functions which rewrite `inkwell` `InstructionValue`s in place, used when patching a module
+use std::convert::TryInto;
+
+use either::Either;
+use eyre::{eyre, Result};
+use inkwell::{
+    basic_block::BasicBlock,
+    values::{AnyValue, BasicValue, BasicValueEnum, InstructionOpcode, InstructionValue, PhiValue},
+};
+
+use crate::context::QCSCompilerContext;
+
+/// Given a conditional branch (`br`) instruction, replace its then and/or else targets with the specified basic blocks.
+///
+/// Note: this function moves the builder's position and does not restore it.
+pub(crate) fn replace_conditional_branch_target(
+    context: &mut QCSCompilerContext,
+    instruction: InstructionValue,
+    replace_then: Option<&BasicBlock>,
+    replace_else: Option<&BasicBlock>,
+) -> Result<()> {
+    context.builder.position_at(
+        instruction
+            .get_parent()
+            .ok_or_else(|| eyre!("Expected instruction to have a parent"))?,
+        &instruction,
+    );
+
+    let original_then_block = if let Some(Either::Right(target)) = instruction.get_operand(2) {
+        target
+    } else {
+        return Err(eyre!("expected basic block target for branch"));
+    };
+
+    let original_else_block = if let Some(Either::Right(target)) = instruction.get_operand(1) {
+        target
+    } else {
+        return Err(eyre!("expected basic block target for branch"));
+    };
+
+    let (then_block, else_block) = (
+        replace_then.unwrap_or(&original_then_block),
+        replace_else.unwrap_or(&original_else_block),
+    );
+
+    let comparison = if let Some(Either::Left(BasicValueEnum::IntValue(comparison))) =
+        instruction.get_operand(0)
+    {
+        comparison
+    } else {
+        return Err(eyre!("expected integer comparison for branch"));
+    };
+
+    let new_instruction =
+        context
+            .builder
+            .build_conditional_branch(comparison, *then_block, *else_block);
+    copy_name_and_metadata(context, instruction, new_instruction)?;
+    instruction.replace_all_uses_with(&new_instruction);
+    instruction.remove_from_basic_block();
+    Ok(())
+}
+
+/// Kinds of metadata carried over from an instruction onto the one rebuilt to replace it.
+const PRESERVED_METADATA_KINDS: [&str; 4] = ["dbg", "prof", "llvm.loop", "annotation"];
+
+/// Carry the value name and metadata of an instruction over onto the instruction replacing it, so
+/// that the transformed module can be compared line by line against its input. The name is first
+/// cleared from the original so that LLVM does not uniquify the replacement's.
+pub(crate) fn copy_name_and_metadata(
+    context: &QCSCompilerContext,
+    original: InstructionValue,
+    replacement: InstructionValue,
+) -> Result<()> {
+    let name = original
+        .get_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    if !name.is_empty() {
+        original.set_name("").map_err(|e| eyre!(e))?;
+        replacement.set_name(&name).map_err(|e| eyre!(e))?;
+    }
+
+    for kind in PRESERVED_METADATA_KINDS {
+        let kind_id = context.base_context.get_kind_id(kind);
+        if let Some(metadata) = original.get_metadata(kind_id) {
+            replacement
+                .set_metadata(metadata, kind_id)
+                .map_err(|e| eyre!(e))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Given a `phi` instruction, replace the existing matching block with the new one specified.
+///
+/// Parameters:
+/// * `reverse_match`: whether to match all incoming clauses that _aren't_ from the specified original basic block instead
+///   of those that _are_.
+pub(crate) fn replace_phi_clause(
+    context: &mut QCSCompilerContext,
+    instruction: PhiValue,
+    old_basic_block: BasicBlock,
+    new_basic_block: BasicBlock,
+    reverse_match: bool,
+) -> Result<()> {
+    let basic_block_parent = instruction
+        .as_instruction()
+        .get_parent()
+        .ok_or_else(|| eyre!("Expected instruction to have a parent"))?;
+
+    // We have to ensure that we're writing all phi instructions at the start of the basic block;
+    // in LLVM IR no non-phi instructions may precede any phi instruction in the block.
+    context.builder.position_before(
+        &basic_block_parent
+            .get_first_instruction()
+            .ok_or_else(|| eyre!("Expected basic block to have at least one instruction"))?,
+    );
+
+    let mut new_incoming: Vec<(BasicValueEnum, BasicBlock)> = vec![];
+
+    // FromIterator not implemented
+    // let new_incoming = (0..instruction.count_incoming()).map(|index| {
+    // let value = instruction.get_incoming(index).unwrap();
+
+    // TODO: Rework this so it's less clumsy.
+    // The trick is that get_incoming gives you an owned value but add_incoming wants a &dyn,
+    // so you need to own the values somewhere long enough to be able to supply them to `add_incoming`.
+    for index in 0..instruction.count_incoming() {
+        let value = instruction
+            .get_incoming(index)
+            .ok_or_else(|| eyre!("Expected phi instruction to have incoming values"))?;
+
+        if reverse_match ^ (value.1 == old_basic_block) {
+            new_incoming.push((value.0, new_basic_block));
+        } else {
+            new_incoming.push((value.0, value.1));
+        }
+    }
+
+    let mut new_incoming_ref: Vec<(&dyn BasicValue, BasicBlock)> = vec![];
+
+    for element in &new_incoming {
+        new_incoming_ref.push((&element.0, element.1));
+    }
+
+    let new_instruction = context
+        .builder
+        .build_phi(instruction.as_basic_value().get_type(), "");
+    new_instruction.add_incoming(new_incoming_ref.as_slice());
+    copy_name_and_metadata(
+        context,
+        instruction.as_instruction(),
+        new_instruction.as_instruction(),
+    )?;
+
+    instruction.replace_all_uses_with(&new_instruction);
+    instruction.as_instruction().remove_from_basic_block();
+    Ok(())
+}
+
+pub(crate) fn replace_phi_clauses(
+    context: &mut QCSCompilerContext,
+    within_basic_block: BasicBlock,
+    old_basic_block: BasicBlock,
+    new_basic_block: BasicBlock,
+    reverse_match: bool,
+) -> Result<()> {
+    let mut instruction = within_basic_block.get_first_instruction();
+
+    while let Some(current_instruction) = instruction {
+        // We have to get a valid handle on the next instruction before replace_phi_clause deletes this one.
+        let next_instruction = current_instruction.get_next_instruction();
+        if current_instruction.get_opcode() == InstructionOpcode::Phi {
+            replace_phi_clause(
+                context,
+                current_instruction
+                    .try_into()
+                    .map_err(|_| eyre!("Expected phi instruction"))?,
+                old_basic_block,
+                new_basic_block,
+                reverse_match,
+            )?;
+        }
+        instruction = next_instruction;
+    }
+    Ok(())
+}
+
+/// Remove instructions in topological order such that none is removed while any other instruction uses it.
+pub(crate) fn remove_instructions_in_safe_order(instructions: Vec<InstructionValue>) -> Result<()> {
+    let mut instructions = instructions;
+
+    loop {
+        let mut instructions_removed_in_round = false;
+
+        instructions.retain(|instr| {
+            // if an instruction is still in use at current iteration, mark it to be kept until other
+            // instructions are removed (which may be dependent on this instruction)
+            if instr.get_first_use().is_some() {
+                true
+            } else {
+                instr.remove_from_basic_block();
+                instructions_removed_in_round = true;
+                false
+            }
+        });
+
+        // no more instructions to remove, bail
+        if instructions.is_empty() {
+            return Ok(());
+        }
+
+        // still have instructions to remove, but all were still in use and cannot be removed
+        if !instructions_removed_in_round {
+            let remaining = instructions
+                .iter()
+                .map(|instruction| describe_uses(*instruction))
+                .collect::<Vec<_>>()
+                .join("\n");
+            return Err(eyre!(
+                "unable to remove instructions which are still in use:\n{}",
+                remaining
+            ));
+        }
+    }
+}
+
+/// Describe an instruction and each of the values which use it, for diagnostics.
+fn describe_uses(instruction: InstructionValue) -> String {
+    let mut users = vec![];
+    let mut next_use = instruction.get_first_use();
+    while let Some(value_use) = next_use {
+        users.push(format!(
+            "    used by: {}",
+            value_use
+                .get_user()
+                .print_to_string()
+                .to_string_lossy()
+                .trim()
+        ));
+        next_use = value_use.get_next_use();
+    }
+
+    format!(
+        "  {}\n{}",
+        instruction.print_to_string().to_string_lossy().trim(),
+        users.join("\n")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::{context::ContextOptions, target::ExecutionTarget, QCSCompilerContext};
+
+    #[test]
+    fn reports_remaining_uses_of_instructions_which_cannot_be_removed() {
+        let data = std::fs::read("tests/fixtures/programs/measure.bc").unwrap();
+        let context = inkwell::context::Context::create();
+        let context = QCSCompilerContext::new_from_data(
+            &context,
+            &data,
+            ExecutionTarget::Qvm,
+            ContextOptions::default(),
+        )
+        .unwrap();
+        let body = context
+            .module
+            .get_function("QuantumApplication__Run__body")
+            .and_then(|function| function.get_basic_blocks().get(1).copied())
+            .unwrap();
+        let shot_count_phi = body.get_first_instruction().unwrap();
+
+        let error = remove_instructions_in_safe_order(vec![shot_count_phi])
+            .unwrap_err()
+            .to_string();
+
+        assert!(error.contains("phi i64"), "{}", error);
+        assert!(error.contains("used by: %2 = add"), "{}", error);
+        assert!(error.contains("used by: %3 = icmp"), "{}", error);
+    }
+}
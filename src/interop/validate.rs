@@ -0,0 +1,272 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Checks run over a module before transformation, so that unsupported input is reported up front
+// rather than part-way through pattern matching.
+use either::Either;
+use eyre::Result;
+use inkwell::{
+    basic_block::BasicBlock,
+    module::Module,
+    values::{AnyValue, BasicValueEnum, FunctionValue, InstructionOpcode, InstructionValue},
+};
+
+use super::entrypoint::get_entry_function;
+use crate::error::KindError;
+use crate::Limits;
+
+/// Opcodes which no QIR format supported by this crate may contain, anywhere in the program.
+const UNSUPPORTED_OPCODES: &[InstructionOpcode] = &[
+    InstructionOpcode::AtomicCmpXchg,
+    InstructionOpcode::AtomicRMW,
+    InstructionOpcode::CallBr,
+    InstructionOpcode::CatchPad,
+    InstructionOpcode::CatchRet,
+    InstructionOpcode::CatchSwitch,
+    InstructionOpcode::CleanupPad,
+    InstructionOpcode::CleanupRet,
+    InstructionOpcode::Fence,
+    InstructionOpcode::IndirectBr,
+    InstructionOpcode::Invoke,
+    InstructionOpcode::LandingPad,
+    InstructionOpcode::Resume,
+    InstructionOpcode::Switch,
+    InstructionOpcode::VAArg,
+];
+
+/// Scan every basic block reachable from the module's entrypoint, including the bodies of functions
+/// it calls, for instructions which cannot be transformed. If any are found, return a single error
+/// listing each of them along with the function and block in which it appears.
+///
+/// A module without an entrypoint passes validation; that is reported by the transformation itself.
+pub(crate) fn validate_supported_instructions(module: &Module) -> Result<()> {
+    let entrypoint = match get_entry_function(module) {
+        Some(function) => function,
+        None => return Ok(()),
+    };
+
+    let mut unsupported = vec![];
+    let mut visited_functions = vec![];
+    let mut pending_functions = vec![entrypoint];
+
+    while let Some(function) = pending_functions.pop() {
+        if visited_functions.contains(&function) {
+            continue;
+        }
+        visited_functions.push(function);
+
+        for block in get_reachable_blocks(function) {
+            let mut next_instruction = block.get_first_instruction();
+            while let Some(instruction) = next_instruction {
+                if UNSUPPORTED_OPCODES.contains(&instruction.get_opcode()) {
+                    unsupported.push(describe_location(function, block, instruction));
+                }
+                if let Some(called_function) = get_called_function(module, instruction) {
+                    if called_function.count_basic_blocks() > 0 {
+                        pending_functions.push(called_function);
+                    }
+                }
+                next_instruction = instruction.get_next_instruction();
+            }
+        }
+    }
+
+    if unsupported.is_empty() {
+        Ok(())
+    } else {
+        Err(KindError::UnsupportedInstruction(format!(
+            "module contains {} unsupported instruction(s):\n{}",
+            unsupported.len(),
+            unsupported.join("\n")
+        ))
+        .into())
+    }
+}
+
+/// Fail if the module holds more basic blocks, or any block more instructions, than `limits`
+/// allows. This walks the module without collecting it, stopping as soon as a limit is exceeded, so
+/// that it may guard the more costly checks and pattern matching which follow.
+pub(crate) fn validate_limits(module: &Module, limits: &Limits) -> Result<()> {
+    let mut block_count = 0;
+    let mut next_function = module.get_first_function();
+    while let Some(function) = next_function {
+        let mut next_block = function.get_first_basic_block();
+        while let Some(block) = next_block {
+            block_count += 1;
+            if let Some(max_blocks) = limits.max_blocks {
+                if block_count > max_blocks {
+                    return Err(KindError::LimitExceeded(format!(
+                        "module contains more than the limit of {} basic blocks",
+                        max_blocks
+                    ))
+                    .into());
+                }
+            }
+
+            if let Some(max_instructions) = limits.max_instructions_per_block {
+                let mut instruction_count = 0;
+                let mut next_instruction = block.get_first_instruction();
+                while let Some(instruction) = next_instruction {
+                    instruction_count += 1;
+                    if instruction_count > max_instructions {
+                        return Err(KindError::LimitExceeded(format!(
+                            "block %{} in @{} contains more than the limit of {} instructions",
+                            block.get_name().to_string_lossy(),
+                            function.get_name().to_string_lossy(),
+                            max_instructions
+                        ))
+                        .into());
+                    }
+                    next_instruction = instruction.get_next_instruction();
+                }
+            }
+
+            next_block = block.get_next_basic_block();
+        }
+        next_function = function.get_next_function();
+    }
+
+    Ok(())
+}
+
+/// Return the blocks of a function which are reachable from its entry block, in depth-first order.
+pub(crate) fn get_reachable_blocks(function: FunctionValue) -> Vec<BasicBlock> {
+    let mut reachable = vec![];
+    let mut pending = function
+        .get_first_basic_block()
+        .into_iter()
+        .collect::<Vec<_>>();
+
+    while let Some(block) = pending.pop() {
+        if reachable.contains(&block) {
+            continue;
+        }
+        reachable.push(block);
+
+        if let Some(terminator) = block.get_terminator() {
+            for index in (0..terminator.get_num_operands()).rev() {
+                if let Some(Either::Right(successor)) = terminator.get_operand(index) {
+                    pending.push(successor);
+                }
+            }
+        }
+    }
+
+    reachable
+}
+
+/// Return the function called by a `call` instruction, if it is a direct call to a function in this module.
+pub(crate) fn get_called_function<'ctx>(
+    module: &Module<'ctx>,
+    instruction: InstructionValue<'ctx>,
+) -> Option<FunctionValue<'ctx>> {
+    if instruction.get_opcode() != InstructionOpcode::Call {
+        return None;
+    }
+
+    match instruction.get_operand(instruction.get_num_operands().checked_sub(1)?)? {
+        Either::Left(BasicValueEnum::PointerValue(pointer)) => {
+            module.get_function(pointer.get_name().to_str().ok()?)
+        }
+        _ => None,
+    }
+}
+
+pub(crate) fn describe_location(
+    function: FunctionValue,
+    block: BasicBlock,
+    instruction: InstructionValue,
+) -> String {
+    let block_name = block.get_name().to_string_lossy();
+    format!(
+        "  {:?} in @{}, block %{}: {}",
+        instruction.get_opcode(),
+        function.get_name().to_string_lossy(),
+        if block_name.is_empty() {
+            "<unnamed>".into()
+        } else {
+            block_name
+        },
+        instruction
+            .print_to_string()
+            .to_string()
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::interop::load::load_module_from_bitcode;
+
+    #[test]
+    fn reports_every_unsupported_instruction() {
+        let context = inkwell::context::Context::create();
+        let data = std::fs::read("tests/fixtures/programs/unsupported_instructions.bc").unwrap();
+        let module = load_module_from_bitcode(&context, &data).unwrap();
+
+        let error = validate_supported_instructions(&module)
+            .unwrap_err()
+            .to_string();
+
+        assert!(error.starts_with("module contains 2 unsupported instruction(s):"));
+        assert!(error.contains(
+            "Switch in @QuantumApplication__Run__body, block %body: switch i64 %0, label %latch [ i64 7, label %skip ]"
+        ));
+        assert!(error.contains("AtomicRMW in @count_shot, block %entry: %previous = atomicrmw add"));
+        // Neither unreachable blocks nor uncalled functions are scanned.
+        assert!(!error.contains("Fence"));
+    }
+
+    #[test]
+    fn enforces_size_limits() {
+        let context = inkwell::context::Context::create();
+        let data = std::fs::read("tests/fixtures/programs/bell_state.bc").unwrap();
+        let module = load_module_from_bitcode(&context, &data).unwrap();
+
+        validate_limits(&module, &Limits::default()).unwrap();
+
+        let limits = Limits {
+            max_blocks: Some(2),
+            ..Limits::default()
+        };
+        let error = validate_limits(&module, &limits).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "module contains more than the limit of 2 basic blocks"
+        );
+
+        let limits = Limits {
+            max_instructions_per_block: Some(4),
+            ..Limits::default()
+        };
+        let error = validate_limits(&module, &limits).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "block %body in @QuantumApplication__Run__body contains more than the limit of 4 instructions"
+        );
+    }
+
+    #[test]
+    fn accepts_supported_programs() {
+        let context = inkwell::context::Context::create();
+        let data = std::fs::read("tests/fixtures/programs/reduction.bc").unwrap();
+        let module = load_module_from_bitcode(&context, &data).unwrap();
+
+        validate_supported_instructions(&module).unwrap();
+    }
+}
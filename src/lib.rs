@@ -14,109 +14,1118 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[cfg(feature = "transform")]
+use eyre::eyre;
+#[cfg(feature = "quil")]
 use eyre::{Result, WrapErr};
+#[cfg(feature = "transform")]
 use inkwell::context::Context;
+#[cfg(feature = "transform")]
+use inkwell::memory_buffer::MemoryBuffer;
+#[cfg(feature = "transform")]
 use inkwell::module::Module;
+#[cfg(feature = "transform")]
+use inkwell::targets::{TargetMachine, TargetTriple};
+#[cfg(feature = "transform")]
+use log::warn;
 
 #[cfg(feature = "serde_support")]
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "quil")]
 use crate::context::QCSCompilerContext;
+#[cfg(feature = "transform")]
+pub use crate::interop::abi::RuntimeFlavor;
+#[cfg(feature = "transform")]
+use crate::interop::abi::{
+    get_patched_abi_version, hide_injected_symbols, mark_patched, RUNTIME_ABI_VERSION,
+};
+#[cfg(feature = "transform")]
+pub use crate::interop::provenance::Provenance;
+#[cfg(feature = "transform")]
+use crate::interop::provenance::{hide_provenance, stamp_provenance};
+#[cfg(feature = "quil")]
+use crate::interop::validate::validate_supported_instructions;
+#[cfg(feature = "quil")]
 pub use crate::shot_count_block::quil::ProgramOutput;
+#[cfg(feature = "transform")]
+pub use crate::transform::budget::{MaxShotCount, ProgramMetrics, ShotChunking, ShotCountPolicy};
+#[cfg(feature = "quil")]
+pub use crate::transform::custom::{
+    CustomInstructionHandler, CustomInstructionOutput, IntrinsicArgument, IntrinsicCall,
+    RawQuilHandler, QUIL_INLINE_FUNCTION_NAME,
+};
+#[cfg(feature = "transform")]
+use crate::transform::hybrid;
+#[cfg(feature = "transform")]
+pub use crate::transform::liveness::QubitReuse;
+#[cfg(feature = "quil")]
+pub use crate::transform::message::MessageHandling;
+#[cfg(feature = "quil")]
+pub use crate::transform::ordering::MeasurementOrder;
+#[cfg(feature = "transform")]
+pub use crate::transform::postprocess::QuilPostProcessor;
+#[cfg(feature = "quil")]
 use crate::transform::{shot_count_block, unitary};
+#[cfg(feature = "quil")]
+use crate::validation::{enforce_conformance, ConformanceOptions};
+#[cfg(feature = "quil")]
 use context::context::ContextOptions;
+#[cfg(feature = "quil")]
 pub use context::target::ExecutionTarget;
+#[cfg(feature = "quil")]
+pub use transpiler::OwnedTranspiler;
 
+/// Synthetic QIR modules for benchmarking transpilation and patching.
+#[cfg(feature = "bench")]
+pub mod bench;
 /// This module contains different functions intended for use as LLVM passes.
+#[cfg(feature = "quil")]
 pub(crate) mod context;
+#[cfg(feature = "transform")]
+pub mod diff;
+#[cfg(feature = "transform")]
+pub mod emit;
+#[cfg(feature = "quil")]
+pub mod error;
+#[cfg(feature = "quil")]
+pub mod example;
+pub mod expression;
+#[cfg(feature = "quil")]
 pub(crate) mod interop;
+#[cfg(feature = "transform")]
+pub mod manifest;
 #[cfg(feature = "output")]
 pub mod output;
+#[cfg(feature = "pass-plugin")]
+pub mod plugin;
+#[cfg(feature = "quil")]
+pub mod quil_builder;
+#[cfg(feature = "quil")]
+pub mod quilc;
+#[cfg(any(feature = "execution-jit", feature = "mock-runtime"))]
+pub mod runtime;
+#[cfg(feature = "transform")]
+pub mod shim;
+#[cfg(feature = "quil")]
 pub(crate) mod transform;
+#[cfg(feature = "transform")]
+pub mod translation;
+#[cfg(feature = "quil")]
+pub mod transpiler;
+#[cfg(feature = "quil")]
+pub mod validation;
 
 /// Given an LLVM bitcode, replace quantum intrinsics with calls to execute equivalent Quil on Rigetti QCS
 ///
 /// # Errors
 /// 1. Returns a [`eyre::Report`] with human readable messages if the compilation fails.
+#[cfg(feature = "transform")]
 pub fn patch_qir_with_qcs<'ctx>(
     options: PatchOptions,
     bitcode: &[u8],
     context: &'ctx Context,
 ) -> Result<Module<'ctx>> {
-    let context_options = ContextOptions {
-        cache_executables: options.cache_executables,
-        rewiring_pragma: options.quil_rewiring_pragma,
-    };
+    patch_qir_with_qcs_multi(options, &[bitcode], context)
+}
 
-    let mut context = QCSCompilerContext::new_from_data(
-        context,
-        bitcode,
-        options.execution_target,
-        context_options,
-    )?;
+/// Given several LLVM bitcodes, link them into a single module and replace quantum intrinsics with
+/// calls to execute equivalent Quil on Rigetti QCS. The first bitcode must contain the entrypoint;
+/// the rest are linked into it in the order given.
+///
+/// # Errors
+/// 1. Returns a [`eyre::Report`] with human readable messages if linking or compilation fails.
+#[cfg(feature = "transform")]
+pub fn patch_qir_with_qcs_multi<'ctx>(
+    options: PatchOptions,
+    bitcodes: &[&[u8]],
+    context: &'ctx Context,
+) -> Result<Module<'ctx>> {
+    patch_module(options, bitcodes, context, PatchFormat::ShotCount)
+}
 
-    shot_count_block::qir::transpile_module(&mut context).wrap_err("transformation failed")?;
+/// Given an LLVM bitcode in which a classical loop updates the parameters of a shot count loop between
+/// executions, replace quantum intrinsics with calls to execute equivalent Quil on Rigetti QCS. The
+/// executable is created once, and its parameters are updated on each iteration of the classical loop.
+///
+/// # Errors
+/// 1. Returns a [`eyre::Report`] with human readable messages if the compilation fails.
+#[cfg(feature = "transform")]
+pub fn patch_hybrid_qir_with_qcs<'ctx>(
+    options: PatchOptions,
+    bitcode: &[u8],
+    context: &'ctx Context,
+) -> Result<Module<'ctx>> {
+    patch_hybrid_qir_with_qcs_multi(options, &[bitcode], context)
+}
 
-    if options.add_main_entrypoint {
-        crate::interop::entrypoint::add_main_entrypoint(&mut context)?;
-    }
-    Ok(context.module)
+/// As [`patch_hybrid_qir_with_qcs`], linking several LLVM bitcodes into the first before transformation.
+///
+/// # Errors
+/// 1. Returns a [`eyre::Report`] with human readable messages if linking or compilation fails.
+#[cfg(feature = "transform")]
+pub fn patch_hybrid_qir_with_qcs_multi<'ctx>(
+    options: PatchOptions,
+    bitcodes: &[&[u8]],
+    context: &'ctx Context,
+) -> Result<Module<'ctx>> {
+    patch_module(options, bitcodes, context, PatchFormat::Hybrid)
 }
 
 /// Given an LLVM bitcode, replace quantum intrinsics with calls to execute equivalent Quil on Rigetti QCS
 ///
 /// # Errors
 /// 1. Returns a [`eyre::Report`] with human readable messages if the compilation fails.
+#[cfg(feature = "transform")]
 pub fn patch_unitary_qir_with_qcs<'ctx>(
     options: PatchOptions,
     bitcode: &[u8],
     context: &'ctx Context,
 ) -> Result<Module<'ctx>> {
+    patch_unitary_qir_with_qcs_multi(options, &[bitcode], context)
+}
+
+/// As [`patch_unitary_qir_with_qcs`], linking several LLVM bitcodes into the first before transformation.
+///
+/// # Errors
+/// 1. Returns a [`eyre::Report`] with human readable messages if linking or compilation fails.
+#[cfg(feature = "transform")]
+pub fn patch_unitary_qir_with_qcs_multi<'ctx>(
+    options: PatchOptions,
+    bitcodes: &[&[u8]],
+    context: &'ctx Context,
+) -> Result<Module<'ctx>> {
+    patch_module(options, bitcodes, context, PatchFormat::Unitary)
+}
+
+/// Link the given bitcodes into a single module and apply the transformation of `format` to it. A
+/// module which has already been patched is left unchanged apart from its target triple and data
+/// layout.
+#[cfg(feature = "transform")]
+fn patch_module<'ctx>(
+    options: PatchOptions,
+    bitcodes: &[&[u8]],
+    context: &'ctx Context,
+    format: PatchFormat,
+) -> Result<Module<'ctx>> {
+    Ok(patch_context(options, bitcodes, context, format)?.module)
+}
+
+/// As [`patch_module`], returning the whole compiler context so that what the transformation did
+/// may be inspected.
+#[cfg(feature = "transform")]
+fn patch_context<'ctx>(
+    options: PatchOptions,
+    bitcodes: &[&[u8]],
+    context: &'ctx Context,
+    format: PatchFormat,
+) -> Result<QCSCompilerContext<'ctx>> {
+    let provenance = options
+        .stamp_provenance
+        .then(|| Provenance::new(&options, bitcodes));
+
     let context_options = ContextOptions {
         cache_executables: options.cache_executables,
         rewiring_pragma: options.quil_rewiring_pragma,
+        sparse_readout: options.sparse_readout,
+        flip_readout: options.flip_readout,
+        custom_instruction_handlers: options.custom_instruction_handlers,
+        quil_post_processors: options.quil_post_processors,
+        float_precision: options.float_precision,
+        symbolic_pi: options.symbolic_pi,
+        optimize_quil: options.optimize_quil,
+        measurement_order: options.measurement_order,
+        runtime_flavor: options.runtime_flavor,
+        user_context: options.user_context,
+        limits: options.limits,
+        helper_calling_convention: options.helper_calling_convention,
+        function_allowlist: options.function_allowlist,
+        dump_intermediate: options.dump_intermediate,
+        shot_count_policy: options.shot_count_policy,
+        shot_chunking: options.shot_chunking,
+        reuse_execution_results: options.reuse_execution_results,
+        qubit_reuse: options.qubit_reuse,
+        stable_block_layout: options.stable_block_layout,
+        deny_warnings: options.deny_warnings,
+        message_handling: options.message_handling,
+        ..ContextOptions::default()
     };
 
-    let mut context = QCSCompilerContext::new_from_data(
+    let mut context = QCSCompilerContext::new_from_linked_data(
         context,
-        bitcode,
+        bitcodes,
         options.execution_target,
         context_options,
     )?;
 
-    unitary::qir::transpile_module(&mut context).wrap_err("transformation failed")?;
+    match get_patched_abi_version(&context.module) {
+        Some(RUNTIME_ABI_VERSION) => {
+            warn!("module has already been patched; leaving its quantum instructions unchanged");
+        }
+        Some(version) => {
+            return Err(eyre!(
+                "module was already patched against runtime ABI version {}, but this is version {}",
+                version,
+                RUNTIME_ABI_VERSION
+            ));
+        }
+        None => {
+            validate_supported_instructions(&context.module)?;
+            enforce_conformance(&context.module, &options.conformance)?;
+            if let Some(profile) = crate::validation::get_qir_declarations(&context.module).profile
+            {
+                if !format.implemented_profiles().contains(&profile.as_str()) {
+                    warn!(
+                        "module claims the {} profile, which {:?} patching does not implement",
+                        profile, format
+                    );
+                }
+            }
+            let original = options
+                .keep_original_code
+                .then(|| crate::transform::fallback::copy_original_code(&context.module));
+            format.transpile_module()(&mut context).wrap_err("transformation failed")?;
+            if context.options.stable_block_layout {
+                crate::transform::layout::finalize_block_layout(&context)?;
+            }
+            crate::transform::check_target_qubit_count(
+                &context.emitted_programs,
+                &context.target,
+                options.target_qubit_count,
+                options.deny_excess_qubits,
+            )?;
+            if let (Some(service), ExecutionTarget::Qpu(quantum_processor_id)) =
+                (&options.translation_service, &context.target)
+            {
+                crate::translation::verify_translation(
+                    service.as_ref(),
+                    &context.emitted_programs,
+                    quantum_processor_id,
+                )?;
+            }
+
+            if let Some(original) = original {
+                crate::transform::fallback::add_fallback_to_original_code(&context, original)?;
+            }
+
+            if options.add_main_entrypoint {
+                crate::interop::entrypoint::add_main_entrypoint(&mut context)?;
+            }
+
+            mark_patched(&mut context);
 
-    if options.add_main_entrypoint {
-        crate::interop::entrypoint::add_main_entrypoint(&mut context)?;
+            if let Some(provenance) = &provenance {
+                stamp_provenance(&context, provenance)?;
+            }
+
+            if options.hidden_visibility {
+                hide_injected_symbols(&context);
+                hide_provenance(&context.module);
+            }
+        }
+    }
+
+    if let Some(target_triple) = &options.target_triple {
+        let target_triple = if target_triple == "host" {
+            TargetMachine::get_default_triple()
+        } else {
+            TargetTriple::create(target_triple)
+        };
+        context.module.set_triple(&target_triple);
+    }
+
+    if let Some(data_layout) = &options.data_layout {
+        let layout_module = parse_data_layout(context.base_context, data_layout)?;
+        context
+            .module
+            .set_data_layout(&layout_module.get_data_layout());
+    }
+
+    Ok(context)
+}
+
+/// Parse `data_layout` into an otherwise empty module. LLVM aborts the process when given a malformed
+/// layout directly, but reports one found in textual IR as an error.
+#[cfg(feature = "transform")]
+fn parse_data_layout<'ctx>(context: &'ctx Context, data_layout: &str) -> Result<Module<'ctx>> {
+    let error = || format!("invalid data layout {:?}", data_layout);
+    if data_layout.contains(|c| c == '"' || c == '\\') {
+        return Err(eyre::eyre!(error()));
     }
-    Ok(context.module)
+    let ir = format!("target datalayout = \"{}\"\n", data_layout);
+    let buffer = MemoryBuffer::create_from_memory_range_copy(ir.as_bytes(), "data_layout");
+    context
+        .create_module_from_ir(buffer)
+        .map_err(|e| eyre::eyre!(e.to_string()).wrap_err(error()))
+}
+
+/// The QIR formats which may be patched, each corresponding to one of the `patch_*` functions.
+#[cfg(feature = "transform")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PatchFormat {
+    /// As patched by [`patch_qir_with_qcs`].
+    ShotCount,
+    /// As patched by [`patch_hybrid_qir_with_qcs`].
+    Hybrid,
+    /// As patched by [`patch_unitary_qir_with_qcs`].
+    Unitary,
+}
+
+#[cfg(feature = "transform")]
+impl PatchFormat {
+    /// The transformation which patches a module in this format.
+    fn transpile_module<'ctx>(self) -> fn(&mut QCSCompilerContext<'ctx>) -> Result<()> {
+        match self {
+            Self::ShotCount => shot_count_block::qir::transpile_module,
+            Self::Hybrid => hybrid::qir::transpile_module,
+            Self::Unitary => unitary::qir::transpile_module,
+        }
+    }
+
+    /// The QIR profiles, as named by the `qir_profiles` attribute, of the programs which this
+    /// format transforms. The profiles forbid the loops on which the other formats rely.
+    fn implemented_profiles(self) -> &'static [&'static str] {
+        match self {
+            Self::Unitary => &["base_profile"],
+            Self::ShotCount | Self::Hybrid => &[],
+        }
+    }
+}
+
+/// What patching a module would change, as reported by [`dry_run_patch`].
+#[cfg(feature = "transform")]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde_support", derive(Serialize))]
+pub struct PatchSummary {
+    /// Each Quil program which would replace quantum instructions, in the order they were found.
+    pub programs: Vec<PatchedProgram>,
+    /// The names of the functions and globals which would be added to the module.
+    pub symbols_added: Vec<String>,
+}
+
+/// A Quil program which patching would insert in place of the quantum instructions of a block.
+#[cfg(feature = "transform")]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde_support", derive(Serialize))]
+pub struct PatchedProgram {
+    /// The function holding the block.
+    pub function: String,
+    /// The block whose quantum instructions would be replaced.
+    pub block: String,
+    /// The number of shots for which the program would run, or `None` for a unitary program, which
+    /// runs once each time its block is reached.
+    pub shot_count: Option<u64>,
+    /// The Quil program, as it would be sent for execution.
+    pub program: String,
+}
+
+#[cfg(feature = "transform")]
+impl std::fmt::Display for PatchSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} program(s) would be extracted:", self.programs.len())?;
+        for program in &self.programs {
+            match program.shot_count {
+                Some(shot_count) => writeln!(
+                    f,
+                    "\n@{}, block %{}, {} shot(s):",
+                    program.function, program.block, shot_count
+                )?,
+                None => writeln!(f, "\n@{}, block %{}:", program.function, program.block)?,
+            }
+            for line in program.program.lines() {
+                writeln!(f, "    {}", line)?;
+            }
+        }
+        writeln!(
+            f,
+            "\n{} symbol(s) would be added:",
+            self.symbols_added.len()
+        )?;
+        for symbol in &self.symbols_added {
+            writeln!(f, "    @{}", symbol)?;
+        }
+        Ok(())
+    }
+}
+
+/// Perform every step of patching the given bitcodes in `format`, including each check that
+/// patching makes, and report what would change. Patching is done on a copy of the module held
+/// only for the duration of this call, so nothing is written.
+///
+/// # Errors
+/// 1. Returns a [`eyre::Report`] with human readable messages if patching would fail.
+#[cfg(feature = "transform")]
+pub fn dry_run_patch(
+    format: PatchFormat,
+    options: PatchOptions,
+    bitcodes: &[&[u8]],
+) -> Result<PatchSummary> {
+    let original_symbols = {
+        let context = Context::create();
+        let module = crate::interop::load::load_and_link_modules_from_bitcode(&context, bitcodes)?;
+        get_symbol_names(&module)
+    };
+
+    let context = Context::create();
+    let context = patch_context(options, bitcodes, &context, format)?;
+
+    Ok(PatchSummary {
+        programs: context
+            .emitted_programs
+            .iter()
+            .map(|emitted| PatchedProgram {
+                function: emitted.function.clone(),
+                block: emitted.block.clone(),
+                shot_count: emitted.shot_count,
+                program: emitted.program.to_string(true),
+            })
+            .collect(),
+        symbols_added: get_symbol_names(&context.module)
+            .into_iter()
+            .filter(|symbol| !original_symbols.contains(symbol))
+            .collect(),
+    })
+}
+
+/// Patch the given bitcodes in `format` as the `patch_*` functions do, also returning a manifest
+/// of every Quil program inserted into the module, so that an external orchestrator may manage
+/// their execution and collect the output each of them records.
+///
+/// # Errors
+/// 1. Returns a [`eyre::Report`] with human readable messages if the compilation fails.
+#[cfg(feature = "transform")]
+pub fn patch_with_manifest<'ctx>(
+    format: PatchFormat,
+    options: PatchOptions,
+    bitcodes: &[&[u8]],
+    context: &'ctx Context,
+) -> Result<(Module<'ctx>, manifest::ExperimentManifest)> {
+    let context = patch_context(options, bitcodes, context, format)?;
+    let manifest = manifest::ExperimentManifest::from_context(&context);
+    Ok((context.module, manifest))
+}
+
+/// Read the provenance recorded in a patched bitcode, returning `None` if it was patched without
+/// [`PatchOptions::stamp_provenance`] or not patched at all.
+///
+/// # Errors
+/// 1. Returns a [`eyre::Report`] if the bitcode cannot be loaded or its provenance is malformed.
+#[cfg(feature = "transform")]
+pub fn read_provenance(bitcode: &[u8]) -> Result<Option<Provenance>> {
+    let context = Context::create();
+    let module = crate::interop::load::load_module_from_bitcode(&context, bitcode)?;
+    crate::interop::provenance::get_provenance(&module)
+}
+
+/// Return the names of the functions and globals of a module, in the order they appear.
+#[cfg(feature = "transform")]
+fn get_symbol_names(module: &Module) -> Vec<String> {
+    let mut names = module
+        .get_functions()
+        .map(|function| function.get_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    let mut next_global = module.get_first_global();
+    while let Some(global) = next_global {
+        names.push(global.get_name().to_string_lossy().into_owned());
+        next_global = global.get_next_global();
+    }
+    names
 }
 
 /// Signifies output to be recorded at the end of program execution
+///
+/// Each recorded value carries the `tag` given to the `__quantum__rt__*_record_output` call which
+/// recorded it, if that call was given a tag other than null.
 #[derive(Clone, Debug)]
 #[cfg_attr(
     feature = "serde_support",
-    derive(Serialize),
+    derive(Serialize, Deserialize),
     serde(rename_all = "snake_case")
 )]
 pub enum RecordedOutput {
     ShotStart,
     ShotEnd,
-    ResultReadoutOffset(u64),
-    BoolReadoutOffset(u64),
-    IntegerReadoutOffset(u64),
-    DoubleReadoutOffset(u64),
+    /// The value of a `%Result`, read from `offset` within the memory `region` it was measured into
+    ResultReadoutOffset {
+        region: String,
+        offset: u64,
+        #[cfg_attr(
+            feature = "serde_support",
+            serde(default, skip_serializing_if = "Option::is_none")
+        )]
+        tag: Option<String>,
+    },
+    /// As [`RecordedOutput::ResultReadoutOffset`], where the qubit was flipped before measurement
+    /// and the value at this offset must be inverted to recover the measured result.
+    FlippedResultReadoutOffset {
+        region: String,
+        offset: u64,
+        #[cfg_attr(
+            feature = "serde_support",
+            serde(default, skip_serializing_if = "Option::is_none")
+        )]
+        tag: Option<String>,
+    },
+    BoolReadoutOffset {
+        region: String,
+        offset: u64,
+        #[cfg_attr(
+            feature = "serde_support",
+            serde(default, skip_serializing_if = "Option::is_none")
+        )]
+        tag: Option<String>,
+    },
+    IntegerReadoutOffset {
+        region: String,
+        offset: u64,
+        #[cfg_attr(
+            feature = "serde_support",
+            serde(default, skip_serializing_if = "Option::is_none")
+        )]
+        tag: Option<String>,
+    },
+    DoubleReadoutOffset {
+        region: String,
+        offset: u64,
+        #[cfg_attr(
+            feature = "serde_support",
+            serde(default, skip_serializing_if = "Option::is_none")
+        )]
+        tag: Option<String>,
+    },
+    /// An integer computed by classical instructions in the program, such as a reduction over
+    /// measurement results, rather than read out from a memory region. Quil cannot compute this
+    /// value, so the executor must evaluate it for each shot; `value` is its LLVM IR, and
+    /// `expression` its computation from readout, if it could be reconstructed.
+    HostComputedInteger {
+        value: String,
+        #[cfg_attr(
+            feature = "serde_support",
+            serde(default, skip_serializing_if = "Option::is_none")
+        )]
+        expression: Option<expression::ClassicalExpression>,
+        #[cfg_attr(
+            feature = "serde_support",
+            serde(default, skip_serializing_if = "Option::is_none")
+        )]
+        tag: Option<String>,
+    },
+    /// As [`RecordedOutput::HostComputedInteger`], for a boolean such as the parity of several
+    /// measurement results.
+    HostComputedBool {
+        value: String,
+        #[cfg_attr(
+            feature = "serde_support",
+            serde(default, skip_serializing_if = "Option::is_none")
+        )]
+        expression: Option<expression::ClassicalExpression>,
+        #[cfg_attr(
+            feature = "serde_support",
+            serde(default, skip_serializing_if = "Option::is_none")
+        )]
+        tag: Option<String>,
+    },
     TupleStart,
     TupleEnd,
-    ArrayStart,
+    /// The start of an array. When recorded by `__quantum__rt__array_record_output`, `len` is the
+    /// number of elements it holds and `tag` is the label given to it, if not null; when recorded
+    /// by `__quantum__rt__array_start_record_output`, neither is known.
+    ArrayStart {
+        len: Option<u64>,
+        tag: Option<String>,
+    },
     ArrayEnd,
 }
 
+/// The output which a program records for each shot, in order, as planned when it is transpiled:
+/// the [`RecordedOutput`] between a [`RecordedOutput::ShotStart`] and [`RecordedOutput::ShotEnd`].
+/// It dereferences to the slice of [`RecordedOutput`] taken by the formatters of the `output` module.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(
+    feature = "serde_support",
+    derive(Serialize, Deserialize),
+    serde(transparent)
+)]
+pub struct RecordingPlan(Vec<RecordedOutput>);
+
+impl RecordingPlan {
+    /// Every entry of the plan, including shot and container markers, in order.
+    #[must_use]
+    pub fn entries(&self) -> &[RecordedOutput] {
+        &self.0
+    }
+
+    /// Take the entries of the plan, as held by the `recorded_output` of a transpiled program.
+    #[must_use]
+    pub fn into_entries(self) -> Vec<RecordedOutput> {
+        self.0
+    }
+
+    /// The values recorded by each shot, without the shot and container markers around them.
+    pub fn values(&self) -> impl Iterator<Item = &RecordedOutput> {
+        self.0.iter().filter(|entry| {
+            !matches!(
+                entry,
+                RecordedOutput::ShotStart
+                    | RecordedOutput::ShotEnd
+                    | RecordedOutput::TupleStart
+                    | RecordedOutput::TupleEnd
+                    | RecordedOutput::ArrayStart { .. }
+                    | RecordedOutput::ArrayEnd
+            )
+        })
+    }
+}
+
+impl From<Vec<RecordedOutput>> for RecordingPlan {
+    fn from(entries: Vec<RecordedOutput>) -> Self {
+        Self(entries)
+    }
+}
+
+impl std::ops::Deref for RecordingPlan {
+    type Target = [RecordedOutput];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// A condition found while transpiling which does not prevent a program from being produced, but
+/// which almost always indicates a mistake by the frontend which emitted the module. Warnings fail
+/// transpilation instead when [`TranspileOptions::deny_warnings`] or
+/// [`PatchOptions::deny_warnings`] is set.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde_support",
+    derive(Serialize, Deserialize),
+    serde(tag = "kind", rename_all = "snake_case")
+)]
+pub enum TranspileWarning {
+    /// A `%Result` was recorded as output but never measured, so the value recorded for it, read
+    /// from `offset` within the readout region, is always `0`.
+    UnmeasuredResultRecorded { result: u64, offset: u64 },
+}
+
+impl std::fmt::Display for TranspileWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnmeasuredResultRecorded { result, offset } => write!(
+                f,
+                "result {} is recorded but never measured, so ro[{}] always records 0",
+                result, offset
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "transform")]
+#[derive(Default)]
 pub struct PatchOptions {
     pub add_main_entrypoint: bool,
     pub execution_target: ExecutionTarget,
     pub cache_executables: bool,
     pub quil_rewiring_pragma: Option<String>,
+    /// Use each `%Result` index as its `ro` offset (`%Result 5` -> `ro[5]`) rather than assigning
+    /// offsets densely in order of first use.
+    pub sparse_readout: bool,
+    /// Flip each qubit with an `X` immediately before it is measured (and back again after), recording
+    /// each such readout as [`RecordedOutput::FlippedResultReadoutOffset`] so that output formatters
+    /// undo the flip. Readout used within the program itself is un-flipped before use. Running a
+    /// program both with and without this option symmetrizes readout error between `0` and `1`.
+    pub flip_readout: bool,
+    /// Target triple to set on the output module, overriding the one emitted by the frontend. The value
+    /// `host` selects the triple of the machine running the transformation.
+    pub target_triple: Option<String>,
+    /// Data layout to set on the output module, overriding the one emitted by the frontend.
+    pub data_layout: Option<String>,
+    /// Handlers offered each call which the built-in patterns do not recognize, in order.
+    pub custom_instruction_handlers: Vec<std::sync::Arc<dyn CustomInstructionHandler>>,
+    /// Post-processors run over each Quil program, in order, once it is assembled and before it is
+    /// embedded in the module.
+    pub quil_post_processors: Vec<std::sync::Arc<dyn QuilPostProcessor>>,
+    /// Round constant parameters to this many decimal places when writing them to Quil, so that,
+    /// for instance, `0.30000000000000004` is written as `0.3`. See [`TranspileOptions::float_precision`].
+    pub float_precision: Option<usize>,
+    /// Write constant parameters near multiples of pi symbolically. See [`TranspileOptions::symbolic_pi`].
+    pub symbolic_pi: bool,
+    /// Simplify each Quil program before it is emitted. See [`TranspileOptions::optimize_quil`].
+    pub optimize_quil: bool,
+    /// How to handle a program in which a gate follows a measurement or reset. See
+    /// [`TranspileOptions::measurement_order`].
+    pub measurement_order: MeasurementOrder,
+    /// Limits on the size of the module and of each Quil program translated from it.
+    pub limits: Limits,
+    /// The calling convention of the functions the transformation defines in the module, such as
+    /// the one populating the executable cache. The generated `main` always uses the C convention.
+    pub helper_calling_convention: CallingConvention,
+    /// The runtime the patched module calls into, which determines the names under which the
+    /// runtime functions are declared. By default, these are the names of the helper library.
+    pub runtime_flavor: RuntimeFlavor,
+    /// Pass every runtime function, and `use_qcs` when keeping the original code, an extra final
+    /// `i8*` argument read from the global `qcs_sdk_qir_user_context`, which the module defines
+    /// as null. The host sets it before calling the entrypoint, so that a runtime serving several
+    /// executions at once can tell them apart without thread-local state. The runtime must then
+    /// implement the extended signatures.
+    pub user_context: bool,
+    /// Give the symbols the transformation adds to the module hidden visibility, so that they are
+    /// neither exported from nor resolved across a shared library. This covers the helper functions
+    /// it defines, the runtime functions it declares, and the global recording the runtime ABI
+    /// version. The runtime must then be linked into the same shared library as the module.
+    pub hidden_visibility: bool,
+    /// Record in the module the version of this crate, the options below, a digest of the input,
+    /// and the time of patching, readable with [`read_provenance`]. This is off by default so that
+    /// patching the same input twice produces the same output.
+    pub stamp_provenance: bool,
+    /// The conformance check to run before transformation. See [`TranspileOptions::conformance`].
+    pub conformance: ConformanceOptions,
+    /// The number of qubit indices addressable on the execution target, overriding the size known
+    /// for a named QPU. Programs using a greater qubit index are reported once transformed. The QVM
+    /// has no fixed size, so programs targeting it are only checked if this is set.
+    pub target_qubit_count: Option<u64>,
+    /// Fail, rather than log a warning, when a program uses a qubit index beyond the size of the
+    /// execution target.
+    pub deny_excess_qubits: bool,
+    /// Fail, rather than log a warning, when a program is found to hold a likely mistake. See
+    /// [`TranspileOptions::deny_warnings`].
+    pub deny_warnings: bool,
+    /// What to do with each call to `__quantum__rt__message` among the quantum instructions of a
+    /// program. See [`TranspileOptions::message_handling`].
+    pub message_handling: MessageHandling,
+    /// Transform only the functions with these names, leaving the quantum intrinsics of every other
+    /// function untouched, such as when a module holds kernels intended for different backends.
+    /// Calls are not followed from these functions, so each function to transform must be listed.
+    /// By default, transformation starts from the entrypoint and follows the calls it matches.
+    pub function_allowlist: Option<Vec<String>>,
+    /// Keep the original code of the module alongside its transformation. Each function from which
+    /// transformation starts then calls the runtime function `use_qcs()` on entry and, if it returns
+    /// false, runs its original code in place of the transformed code. The patched module may then
+    /// be run either on QCS or against another QIR runtime which implements the quantum intrinsics.
+    pub keep_original_code: bool,
+    /// Write into this directory, for each basic block transformed, the pattern context which
+    /// matched it (as JSON, with the `serde_support` feature), the Quil program extracted from it,
+    /// and the IR of its function before and after transformation, so that a transformation may be
+    /// debugged offline. Blocks which no pattern matches are skipped.
+    pub dump_intermediate: Option<std::path::PathBuf>,
+    /// Review the shot count inferred for each program before it is compiled in, such as to clamp
+    /// it to a maximum with [`MaxShotCount`]. The policy may adjust the shot count or veto the
+    /// program. Programs transformed with [`PatchFormat::Unitary`] have no shot count to review.
+    pub shot_count_policy: Option<std::sync::Arc<dyn ShotCountPolicy>>,
+    /// Split the shots of each program among several executions, whose results are read back in
+    /// order as if from one, such as to fit each execution within the limits of a QPU reservation.
+    /// The split applies to the shot count left by the [`PatchOptions::shot_count_policy`]. By
+    /// default, every shot is executed at once.
+    pub shot_chunking: ShotChunking,
+    /// Allocate the result of each program's execution once, on entry to the function executing
+    /// it, and refill it on each execution with `reset_execution_result` and `execute_on_qvm_into`
+    /// or `execute_on_qpu_into`, freeing it as the function returns. This avoids allocating a
+    /// result on every iteration of a classical loop around the program, but requires a runtime
+    /// implementing these functions.
+    pub reuse_execution_results: bool,
+    /// Once the module is patched, order the blocks of each function holding a program by the
+    /// control flow between them, and name the blocks injected to execute and clean up after each
+    /// program after the block holding it, as `body_execution`, numbering any repeats in order as
+    /// `body_execution.1`. The patched IR then changes little as the module changes.
+    pub stable_block_layout: bool,
+    /// How to handle a qubit used by a program when a program extracted earlier from the module
+    /// also used it, as when several blocks execute one after another. By default, programs are
+    /// left unchanged.
+    pub qubit_reuse: QubitReuse,
+    /// Translate each program with this service once the module is patched for a QPU, failing if
+    /// a program does not translate or if its translation does not read out every memory reference
+    /// the program records, rather than failing at the first execution of the program. Nothing is
+    /// translated for the QVM.
+    pub translation_service: Option<std::sync::Arc<dyn translation::TranslationService>>,
+}
+
+#[cfg(feature = "transform")]
+impl PatchOptions {
+    /// Build options from environment variables, so that a containerized build may configure
+    /// patching without changing how it is invoked. These are the same variables read by the
+    /// `transform` command, any of which may be unset:
+    ///
+    /// - `QCS_QIR_TARGET`: [`PatchOptions::execution_target`], as `qvm` or a QPU ID
+    /// - `QCS_QIR_CACHE_EXECUTABLES`: [`PatchOptions::cache_executables`]
+    /// - `QCS_QIR_REWIRING_PRAGMA`: [`PatchOptions::quil_rewiring_pragma`]
+    /// - `QCS_QIR_TARGET_QUBITS`: [`PatchOptions::target_qubit_count`]
+    /// - `QCS_QIR_DENY_EXCESS_QUBITS`: [`PatchOptions::deny_excess_qubits`]
+    ///
+    /// A flag is unset by `0`, `false`, `no`, `off`, or an empty value, and set by any other.
+    ///
+    /// # Errors
+    /// 1. Returns a [`eyre::Report`] if a variable is set to a value which cannot be parsed.
+    pub fn from_env() -> Result<Self> {
+        let var = |name: &str| std::env::var(name).ok();
+        let flag = |name: &str| {
+            var(name).map_or(false, |value| {
+                !matches!(
+                    value.trim().to_lowercase().as_str(),
+                    "" | "0" | "false" | "no" | "off"
+                )
+            })
+        };
+
+        Ok(Self {
+            execution_target: match var("QCS_QIR_TARGET") {
+                Some(target) => target.parse().map_err(|error: String| eyre!(error))?,
+                None => ExecutionTarget::default(),
+            },
+            cache_executables: flag("QCS_QIR_CACHE_EXECUTABLES"),
+            quil_rewiring_pragma: var("QCS_QIR_REWIRING_PRAGMA"),
+            target_qubit_count: var("QCS_QIR_TARGET_QUBITS")
+                .map(|count| {
+                    count
+                        .parse()
+                        .wrap_err_with(|| format!("invalid QCS_QIR_TARGET_QUBITS {:?}", count))
+                })
+                .transpose()?,
+            deny_excess_qubits: flag("QCS_QIR_DENY_EXCESS_QUBITS"),
+            ..Self::default()
+        })
+    }
+
+    /// Describe the options which affect the patched module as space-separated `key=value` pairs,
+    /// omitting those which are unset. Custom instruction handlers are only counted.
+    pub(crate) fn describe(&self) -> String {
+        let mut pairs = vec![
+            format!("target={}", self.execution_target),
+            format!("add_main_entrypoint={}", self.add_main_entrypoint),
+            format!("cache_executables={}", self.cache_executables),
+            format!("sparse_readout={}", self.sparse_readout),
+            format!("flip_readout={}", self.flip_readout),
+            format!("symbolic_pi={}", self.symbolic_pi),
+            format!("optimize_quil={}", self.optimize_quil),
+            format!("hidden_visibility={}", self.hidden_visibility),
+            format!(
+                "helper_calling_convention={:?}",
+                self.helper_calling_convention
+            ),
+            format!("conformance_level={}", self.conformance.level),
+            format!("deny_excess_qubits={}", self.deny_excess_qubits),
+            format!("keep_original_code={}", self.keep_original_code),
+        ];
+        for (key, value) in [
+            ("quil_rewiring_pragma", self.quil_rewiring_pragma.clone()),
+            ("target_triple", self.target_triple.clone()),
+            ("data_layout", self.data_layout.clone()),
+            (
+                "float_precision",
+                self.float_precision.map(|precision| precision.to_string()),
+            ),
+            (
+                "target_qubit_count",
+                self.target_qubit_count.map(|count| count.to_string()),
+            ),
+        ] {
+            if let Some(value) = value {
+                pairs.push(format!("{}={}", key, value));
+            }
+        }
+        if let Some(allowlist) = &self.function_allowlist {
+            pairs.push(format!("function_allowlist={}", allowlist.join(",")));
+        }
+        if self.shot_count_policy.is_some() {
+            pairs.push(String::from("shot_count_policy=true"));
+        }
+        if self.reuse_execution_results {
+            pairs.push(String::from("reuse_execution_results=true"));
+        }
+        if self.stable_block_layout {
+            pairs.push(String::from("stable_block_layout=true"));
+        }
+        if self.deny_warnings {
+            pairs.push(String::from("deny_warnings=true"));
+        }
+        if self.shot_chunking != ShotChunking::Whole {
+            pairs.push(format!("shot_chunking={}", self.shot_chunking));
+        }
+        if self.translation_service.is_some() {
+            pairs.push(String::from("translation_service=true"));
+        }
+        if self.runtime_flavor != RuntimeFlavor::QcsC {
+            pairs.push(format!("runtime_flavor={}", self.runtime_flavor));
+        }
+        if self.user_context {
+            pairs.push(String::from("user_context=true"));
+        }
+        if self.measurement_order != MeasurementOrder::Ignore {
+            pairs.push(format!("measurement_order={:?}", self.measurement_order));
+        }
+        if self.message_handling != MessageHandling::Retain {
+            pairs.push(format!("message_handling={:?}", self.message_handling));
+        }
+        if self.qubit_reuse != QubitReuse::Ignore {
+            pairs.push(format!("qubit_reuse={:?}", self.qubit_reuse));
+        }
+        if !self.custom_instruction_handlers.is_empty() {
+            pairs.push(format!(
+                "custom_instruction_handlers={}",
+                self.custom_instruction_handlers.len()
+            ));
+        }
+        if !self.quil_post_processors.is_empty() {
+            pairs.push(format!(
+                "quil_post_processors={}",
+                self.quil_post_processors.len()
+            ));
+        }
+        pairs.join(" ")
+    }
+}
+
+/// The calling convention of functions defined by the transformation. These are only called from
+/// within the patched module, so need not follow the C convention.
+#[cfg(feature = "quil")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CallingConvention {
+    /// The C calling convention, `ccc`.
+    C,
+    /// The fast calling convention, `fastcc`.
+    Fast,
+}
+
+#[cfg(feature = "quil")]
+impl CallingConvention {
+    /// The LLVM identifier of this calling convention.
+    #[cfg_attr(not(feature = "transform"), allow(dead_code))]
+    pub(crate) fn llvm_id(self) -> u32 {
+        match self {
+            Self::C => 0,
+            Self::Fast => 8,
+        }
+    }
+}
+
+#[cfg(feature = "quil")]
+impl Default for CallingConvention {
+    fn default() -> Self {
+        Self::C
+    }
+}
+
+#[cfg(feature = "quil")]
+impl std::str::FromStr for CallingConvention {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "c" | "ccc" => Ok(Self::C),
+            "fast" | "fastcc" => Ok(Self::Fast),
+            _ => Err(format!(
+                "unknown calling convention \"{}\"; expected \"c\" or \"fast\"",
+                s
+            )),
+        }
+    }
+}
+
+/// Options for transpiling QIR to Quil with [`transpile_qir_to_quil_with_options`] and
+/// [`transpile_unitary_qir_to_quil_with_options`].
+#[cfg(feature = "quil")]
+#[derive(Clone, Debug, Default)]
+pub struct TranspileOptions {
+    /// Record the QIR instruction from which each Quil instruction was translated, available as the
+    /// `trace` of the output and rendered as comments by its `to_traced_string` method.
+    pub trace: bool,
+    /// Round constant parameters to this many decimal places when writing them to Quil, so that,
+    /// for instance, `0.30000000000000004` is written as `0.3`. Parameters are otherwise written
+    /// with the shortest representation which parses to the same `double`. The exact value of a
+    /// rounded parameter remains visible in the QIR recorded by [`TranspileOptions::trace`].
+    pub float_precision: Option<usize>,
+    /// Write each constant parameter within `1e-6` of a nonzero multiple of `pi/m`, for `m` up to
+    /// 16, as the equivalent Quil expression, such as `pi/2` for `1.5707963`. This takes precedence
+    /// over [`TranspileOptions::float_precision`].
+    pub symbolic_pi: bool,
+    /// Simplify the transpiled program: cancel adjacent gates which are inverses of one another,
+    /// such as `H 0` twice or `S 0` followed by `DAGGER S 0`; merge consecutive rotations of the
+    /// same kind on a qubit by summing their constant angles; and drop rotations by a multiple of
+    /// `2*pi`. Gates are never moved past measurements or other non-gate instructions. The trace of
+    /// a merged rotation is that of its first gate.
+    pub optimize_quil: bool,
+    /// Renumber the qubits of the transpiled program as `0, 1, 2, ...`, in ascending order of the
+    /// indices used by the module, recording the index to which each was mapped as the
+    /// `qubit_mapping` of the output. This suits the QVM, which simulates every qubit up to the
+    /// greatest index used, and lets programs from frontends which allocate qubits differently be
+    /// compared.
+    pub compact_qubits: bool,
+    /// How to handle a program in which a gate follows a measurement or reset, which the base
+    /// profile forbids: leave it, warn of it, fail, or move the measurements and resets after the
+    /// gates where nothing depends on their order. Programs are left unchanged by default.
+    pub measurement_order: MeasurementOrder,
+    /// The name of the basic block holding the program's shot count loop. By default, this is found
+    /// as a block which begins with the `phi` counting shots and calls quantum intrinsics,
+    /// preferring one named `body` should there be several.
+    pub body_block: Option<String>,
+    /// Limits on the size of the module and of the Quil program translated from it.
+    pub limits: Limits,
+    /// The check of the module's conformance to the QIR specification, or one of its profiles, to
+    /// run before transpilation, and whether a violation is ignored, logged, or fails transpilation.
+    /// No check is run by default.
+    pub conformance: ConformanceOptions,
+    /// Compile the transpiled program to native Quil with this compiler, such as [`quilc::Quilc`],
+    /// returning it as the `native_program` of the output alongside the program as transpiled.
+    pub quil_compiler: Option<std::sync::Arc<dyn quilc::QuilCompiler>>,
+    /// Fail with an error, rather than returning it among the `warnings` of the output, on any
+    /// [`TranspileWarning`], such as a `%Result` which is recorded but never measured.
+    pub deny_warnings: bool,
+    /// What to do with each call to `__quantum__rt__message`, as emitted for Q#'s `Message()`,
+    /// among the quantum instructions of a program: leave it in the module, write its message into
+    /// the Quil program as `PRAGMA MESSAGE`, or remove it. Calls are left in the module by default.
+    pub message_handling: MessageHandling,
+}
+
+/// Limits on the size of the modules and Quil programs handled, beyond which transformation and
+/// transpilation fail with an error rather than continuing. These protect services which handle
+/// untrusted input from modules large enough to make pattern matching impractically slow. Each
+/// limit is disabled when `None`, as it is by default for all but `max_unrolled_iterations`.
+#[cfg(feature = "quil")]
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// The greatest number of basic blocks the module may hold, across all of its functions.
+    pub max_blocks: Option<usize>,
+    /// The greatest number of instructions any one basic block may hold.
+    pub max_instructions_per_block: Option<usize>,
+    /// The greatest number of instructions any one Quil program may hold once translated.
+    pub max_quil_instructions: Option<usize>,
+    /// The greatest depth to which calls are followed from a function being transformed, where a
+    /// function it calls directly is at depth one.
+    pub max_call_depth: Option<usize>,
+    /// The greatest number of iterations of a loop within a shot which is unrolled into a Quil
+    /// program. It is 4096 by default, since a loop which is never left would otherwise be evaluated
+    /// forever.
+    pub max_unrolled_iterations: Option<usize>,
+}
+
+#[cfg(feature = "quil")]
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_blocks: None,
+            max_instructions_per_block: None,
+            max_quil_instructions: None,
+            max_call_depth: None,
+            max_unrolled_iterations: Some(4096),
+        }
+    }
+}
+
+#[cfg(feature = "quil")]
+impl From<&TranspileOptions> for ContextOptions {
+    fn from(options: &TranspileOptions) -> Self {
+        Self {
+            trace: options.trace,
+            float_precision: options.float_precision,
+            symbolic_pi: options.symbolic_pi,
+            optimize_quil: options.optimize_quil,
+            compact_qubits: options.compact_qubits,
+            measurement_order: options.measurement_order,
+            body_block: options.body_block.clone(),
+            limits: options.limits,
+            deny_warnings: options.deny_warnings,
+            message_handling: options.message_handling,
+            ..Self::default()
+        }
+    }
 }
 
 /// Transpile the given QIR bitcode into the equivalent Quil program, extracting the shot count from
@@ -125,7 +1134,48 @@ pub struct PatchOptions {
 ///
 /// # Errors
 /// 1. Returns a [`eyre::Report`] with human readable messages if the transpilation fails.
+#[cfg(feature = "quil")]
 pub fn transpile_qir_to_quil(bitcode: &[u8]) -> Result<ProgramOutput> {
+    transpile_qir_to_quil_with_options(bitcode, &TranspileOptions::default())
+}
+
+/// As [`transpile_qir_to_quil`], with the given options.
+///
+/// # Errors
+/// 1. Returns a [`eyre::Report`] with human readable messages if the transpilation fails.
+#[cfg(feature = "quil")]
+pub fn transpile_qir_to_quil_with_options(
+    bitcode: &[u8],
+    options: &TranspileOptions,
+) -> Result<ProgramOutput> {
+    let context = inkwell::context::Context::create();
+    let mut context =
+        QCSCompilerContext::new_from_data(&context, bitcode, ExecutionTarget::Qvm, options.into())?;
+    validate_supported_instructions(&context.module)?;
+    enforce_conformance(&context.module, &options.conformance)?;
+    let mut output =
+        shot_count_block::quil::transpile_module(&mut context).wrap_err("transpilation failed")?;
+    if let Some(compiler) = &options.quil_compiler {
+        output.native_program = Some(
+            compiler
+                .compile(&output.program)
+                .wrap_err("native compilation failed")?,
+        );
+    }
+    Ok(output)
+}
+
+/// Transpile each basic block of the given QIR bitcode's entrypoint which calls quantum intrinsics
+/// into its own Quil program, each with its own shot count and recorded output, keyed by the name of
+/// the block. Whereas [`transpile_qir_to_quil`] only transpiles a single shot count loop, this allows a
+/// program with several shot count loops to be analyzed block by block. Blocks in which no shot
+/// count loop is found are omitted.
+///
+/// # Errors
+/// 1. Returns a [`eyre::Report`] with human readable messages if the transpilation of any block
+///    fails.
+#[cfg(feature = "quil")]
+pub fn transpile_qir_to_quil_by_block(bitcode: &[u8]) -> Result<Vec<(String, ProgramOutput)>> {
     let context = inkwell::context::Context::create();
     let mut context = QCSCompilerContext::new_from_data(
         &context,
@@ -133,14 +1183,27 @@ pub fn transpile_qir_to_quil(bitcode: &[u8]) -> Result<ProgramOutput> {
         ExecutionTarget::Qvm,
         ContextOptions::default(),
     )?;
-    shot_count_block::quil::transpile_module(&mut context).wrap_err("transpilation failed")
+    validate_supported_instructions(&context.module)?;
+    let entrypoint =
+        crate::interop::entrypoint::get_entry_function(&context.module).ok_or_else(|| {
+            crate::error::KindError::MissingEntrypoint(String::from(
+                "entrypoint not found in module",
+            ))
+        })?;
+    shot_count_block::quil::transpile_function_blocks(&mut context, entrypoint)
+        .wrap_err("transpilation failed")
 }
 
-/// Transpile the given unitary format QIR bitcode into the equivalent Quil program.
+/// Transpile the function named `function_name` in the given QIR bitcode into the equivalent Quil
+/// program, extracting the shot count from its program loop. Unlike [`transpile_qir_to_quil`], the
+/// function need not be the module's entrypoint, so that each kernel of a module holding several
+/// may be transpiled separately.
 ///
 /// # Errors
-/// 1. Returns a [`eyre::Report`] with human readable messages if the transpilation fails.
-pub fn transpile_unitary_qir_to_quil(bitcode: &[u8]) -> Result<unitary::quil::ProgramOutput> {
+/// 1. Returns a [`eyre::Report`] with human readable messages if no such function is defined or if
+///    the transpilation fails.
+#[cfg(feature = "quil")]
+pub fn transpile_function_to_quil(bitcode: &[u8], function_name: &str) -> Result<ProgramOutput> {
     let context = inkwell::context::Context::create();
     let mut context = QCSCompilerContext::new_from_data(
         &context,
@@ -148,5 +1211,131 @@ pub fn transpile_unitary_qir_to_quil(bitcode: &[u8]) -> Result<unitary::quil::Pr
         ExecutionTarget::Qvm,
         ContextOptions::default(),
     )?;
-    unitary::quil::transpile_module(&mut context).wrap_err("transpilation failed")
+    validate_supported_instructions(&context.module)?;
+    let function = context
+        .module
+        .get_function(function_name)
+        .ok_or_else(|| eyre::eyre!("function {} not found in module", function_name))?;
+    shot_count_block::quil::transpile_function(&mut context, function)
+        .wrap_err_with(|| format!("transpilation of function {} failed", function_name))
+}
+
+/// Transpile the given unitary format QIR bitcode into the equivalent Quil program.
+///
+/// # Errors
+/// 1. Returns a [`eyre::Report`] with human readable messages if the transpilation fails.
+#[cfg(feature = "quil")]
+pub fn transpile_unitary_qir_to_quil(bitcode: &[u8]) -> Result<unitary::quil::ProgramOutput> {
+    transpile_unitary_qir_to_quil_with_options(bitcode, &TranspileOptions::default())
+}
+
+/// As [`transpile_unitary_qir_to_quil`], with the given options.
+///
+/// # Errors
+/// 1. Returns a [`eyre::Report`] with human readable messages if the transpilation fails.
+#[cfg(feature = "quil")]
+pub fn transpile_unitary_qir_to_quil_with_options(
+    bitcode: &[u8],
+    options: &TranspileOptions,
+) -> Result<unitary::quil::ProgramOutput> {
+    let context = inkwell::context::Context::create();
+    let mut context =
+        QCSCompilerContext::new_from_data(&context, bitcode, ExecutionTarget::Qvm, options.into())?;
+    validate_supported_instructions(&context.module)?;
+    enforce_conformance(&context.module, &options.conformance)?;
+    let mut output =
+        unitary::quil::transpile_module(&mut context).wrap_err("transpilation failed")?;
+    if let Some(compiler) = &options.quil_compiler {
+        output.native_program = Some(
+            compiler
+                .compile(&output.program)
+                .wrap_err("native compilation failed")?,
+        );
+    }
+    Ok(output)
+}
+
+/// Compile textual LLVM IR to bitcode using the version of LLVM linked into this crate, so that
+/// test fixtures may be built reproducibly without a separate `llvm-as` of a matching version.
+///
+/// # Errors
+/// 1. Returns a [`eyre::Report`] if the IR cannot be parsed or does not form a valid module.
+#[cfg(feature = "quil")]
+pub fn compile_ir_to_bitcode(ir: &str) -> Result<Vec<u8>> {
+    let context = inkwell::context::Context::create();
+    let module = crate::interop::load::load_module_from_ir(&context, ir)?;
+    Ok(module.write_bitcode_to_memory().as_slice().to_vec())
+}
+
+/// Run both the shot-count and unitary pattern matchers over the given QIR bitcode and describe,
+/// instruction by instruction, which rule each instruction matched or why matching stopped. This is
+/// intended to help diagnose programs which [`transpile_qir_to_quil`] or
+/// [`transpile_unitary_qir_to_quil`] reject, without reference to the pattern matching source.
+///
+/// # Errors
+/// 1. Returns a [`eyre::Report`] if the bitcode cannot be loaded. Failure to match either pattern
+///    is described in the returned explanation rather than returned as an error.
+#[cfg(feature = "quil")]
+pub fn explain_qir_to_quil(bitcode: &[u8]) -> Result<String> {
+    let options = || ContextOptions {
+        explain: true,
+        ..ContextOptions::default()
+    };
+    let mut explanation = String::new();
+
+    let context = inkwell::context::Context::create();
+    let mut context =
+        QCSCompilerContext::new_from_data(&context, bitcode, ExecutionTarget::Qvm, options())?;
+    let result = validate_supported_instructions(&context.module)
+        .and_then(|_| shot_count_block::quil::transpile_module(&mut context))
+        .map(|output| format!("matched with a shot count of {}", output.shot_count));
+    explain_pattern(&mut explanation, "shot count", &context.explanation, result);
+
+    let context = inkwell::context::Context::create();
+    let mut context =
+        QCSCompilerContext::new_from_data(&context, bitcode, ExecutionTarget::Qvm, options())?;
+    let result = validate_supported_instructions(&context.module)
+        .and_then(|_| unitary::quil::transpile_module(&mut context))
+        .map(|_| String::from("matched"));
+    explain_pattern(&mut explanation, "unitary", &context.explanation, result);
+
+    Ok(explanation)
+}
+
+/// Append the explanation of a single pattern, and the result of matching it, to `explanation`.
+#[cfg(feature = "quil")]
+fn explain_pattern(
+    explanation: &mut String,
+    pattern: &str,
+    lines: &[String],
+    result: Result<String>,
+) {
+    explanation.push_str(&format!("== {} pattern ==\n", pattern));
+    for line in lines {
+        explanation.push_str(&format!("{}\n", line));
+    }
+    match result {
+        Ok(outcome) => explanation.push_str(&format!("result: {}\n", outcome)),
+        Err(error) => explanation.push_str(&format!("result: failed: {:#}\n", error)),
+    }
+}
+
+/// Transpile and patch arbitrary, possibly malformed or adversarial, bytes as QIR bitcode, exercising
+/// both the Quil transpilation and the QIR transformation. Intended for fuzzing and for services
+/// which accept bitcode from untrusted sources: any input which cannot be handled results in an
+/// error rather than a panic.
+///
+/// # Errors
+/// 1. Returns a [`eyre::Report`] if the input is not a supported QIR program.
+#[cfg(feature = "quil")]
+pub fn try_transpile_arbitrary(bytes: &[u8]) -> Result<ProgramOutput> {
+    let output = transpile_qir_to_quil(bytes)?;
+
+    #[cfg(feature = "transform")]
+    {
+        let context = inkwell::context::Context::create();
+        patch_qir_with_qcs(PatchOptions::default(), bytes, &context)?;
+    }
+
+    Ok(output)
 }
@@ -0,0 +1,107 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A description of every Quil program inserted into a patched module, so that an external
+//! orchestrator may manage the execution of a module holding several programs and collect the
+//! data each of them records.
+
+use std::fmt;
+
+#[cfg(feature = "serde_support")]
+use serde::Serialize;
+
+use crate::context::QCSCompilerContext;
+use crate::RecordedOutput;
+
+/// The Quil programs inserted into a patched module, as returned by [`crate::patch_with_manifest`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde_support", derive(Serialize))]
+pub struct ExperimentManifest {
+    /// The target on which the programs execute, as `qvm` or a QPU ID.
+    pub target: String,
+    /// Each program, in the order it was inserted into the module.
+    pub programs: Vec<ManifestProgram>,
+}
+
+/// A Quil program inserted into a patched module.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde_support", derive(Serialize))]
+pub struct ManifestProgram {
+    /// The function holding the block which the program replaced.
+    pub function: String,
+    /// The block which the program replaced.
+    pub block: String,
+    /// The number of shots for which the program runs, or `None` for a unitary program, which
+    /// runs once each time its block is reached.
+    pub shot_count: Option<u64>,
+    /// The Quil program, as it is sent for execution.
+    pub program: String,
+    /// The output recorded from the readout of each shot, in order.
+    pub recorded_output: Vec<RecordedOutput>,
+    /// The index of the program in the executable cache, if executables are cached.
+    pub cache_index: Option<usize>,
+}
+
+impl ExperimentManifest {
+    pub(crate) fn from_context(context: &QCSCompilerContext) -> Self {
+        Self {
+            target: context.target.to_string(),
+            programs: context
+                .emitted_programs
+                .iter()
+                .map(|emitted| ManifestProgram {
+                    function: emitted.function.clone(),
+                    block: emitted.block.clone(),
+                    shot_count: emitted.shot_count,
+                    program: emitted.program.to_string(true),
+                    recorded_output: emitted.recorded_output.clone(),
+                    cache_index: emitted.cache_index,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for ExperimentManifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} program(s) targeting {}:",
+            self.programs.len(),
+            self.target
+        )?;
+        for (index, program) in self.programs.iter().enumerate() {
+            write!(
+                f,
+                "\n{}. @{}, block %{}",
+                index, program.function, program.block
+            )?;
+            if let Some(shot_count) = program.shot_count {
+                write!(f, ", {} shot(s)", shot_count)?;
+            }
+            if let Some(cache_index) = program.cache_index {
+                write!(f, ", cache index {}", cache_index)?;
+            }
+            writeln!(f, ":")?;
+            for line in program.program.lines() {
+                writeln!(f, "    {}", line)?;
+            }
+            writeln!(f, "  recording:")?;
+            for recorded_output in &program.recorded_output {
+                writeln!(f, "    {:?}", recorded_output)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,282 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::Write;
+
+use super::{
+    get_host_computed, get_integer, get_shot_count, get_value, incompatible_record_type, Error,
+    OutputFormat, RegisterValue, Registers,
+};
+use crate::RecordedOutput;
+
+#[cfg(test)]
+use qcs::RegisterData;
+
+/// Formats output of QIR programs as CSV, with a header row followed by one row per shot, for
+/// loading into dataframe libraries such as `pandas` or `polars`.
+///
+/// The first column, `shot`, holds the shot ID. Each value recorded by the program then becomes
+/// a column named after the tag it was recorded with or, if it has none, after its record type and
+/// position among the recorded values, e.g. `result_0`. Tuple and array markers do not produce
+/// columns.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Default)]
+pub struct CsvOutputFormat(pub Vec<String>);
+
+impl OutputFormat for CsvOutputFormat {
+    /// Create a [`CsvOutputFormat`].
+    /// Will return [`enum@Error`] if something about the [`qcs::RegisterData`] or [`RecordedOutput`] is
+    /// unsupported, or if any of the result's data is indexed out-of-range.
+    ///
+    /// # Errors
+    ///
+    /// See [`enum@Error`].
+    fn try_new(result: &Registers, mapping: &[RecordedOutput]) -> Result<Self, Error> {
+        let mut output = vec![];
+        for_each_row(result, mapping, |row| {
+            output.push(row.to_owned());
+            Ok(())
+        })?;
+        Ok(Self(output))
+    }
+
+    /// Write each row of CSV output to `writer` as it is formatted.
+    ///
+    /// # Errors
+    ///
+    /// See [`enum@Error`].
+    fn try_format_to<W: Write>(
+        result: &Registers,
+        mapping: &[RecordedOutput],
+        writer: &mut W,
+    ) -> Result<(), Error> {
+        let mut first = true;
+        for_each_row(result, mapping, |row| {
+            if !first {
+                writer.write_all(b"\n")?;
+            }
+            first = false;
+            writer.write_all(row.as_bytes())?;
+            Ok(())
+        })
+    }
+}
+
+/// The record type of a value recorded as `recorded_output`, used to name its column, or `None` if
+/// it is a marker which records no value.
+fn record_type(recorded_output: &RecordedOutput) -> Option<&'static str> {
+    match recorded_output {
+        RecordedOutput::ResultReadoutOffset { .. }
+        | RecordedOutput::FlippedResultReadoutOffset { .. } => Some("result"),
+        RecordedOutput::BoolReadoutOffset { .. } | RecordedOutput::HostComputedBool { .. } => {
+            Some("bool")
+        }
+        RecordedOutput::IntegerReadoutOffset { .. }
+        | RecordedOutput::HostComputedInteger { .. } => Some("integer"),
+        RecordedOutput::DoubleReadoutOffset { .. } => Some("double"),
+        RecordedOutput::ShotStart
+        | RecordedOutput::ShotEnd
+        | RecordedOutput::TupleStart
+        | RecordedOutput::TupleEnd
+        | RecordedOutput::ArrayStart { .. }
+        | RecordedOutput::ArrayEnd => None,
+    }
+}
+
+/// The tag with which `recorded_output` was recorded, if any.
+fn tag(recorded_output: &RecordedOutput) -> Option<&str> {
+    match recorded_output {
+        RecordedOutput::ResultReadoutOffset { tag, .. }
+        | RecordedOutput::FlippedResultReadoutOffset { tag, .. }
+        | RecordedOutput::BoolReadoutOffset { tag, .. }
+        | RecordedOutput::IntegerReadoutOffset { tag, .. }
+        | RecordedOutput::DoubleReadoutOffset { tag, .. }
+        | RecordedOutput::HostComputedInteger { tag, .. }
+        | RecordedOutput::HostComputedBool { tag, .. } => tag.as_deref(),
+        _ => None,
+    }
+}
+
+/// Quote `field` if it contains a delimiter, quote, or line break, doubling any quotes within it.
+fn quote(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Format the header row followed by the row for each shot of `result`, passing each to `emit`.
+fn for_each_row(
+    result: &Registers,
+    mapping: &[RecordedOutput],
+    mut emit: impl FnMut(&str) -> Result<(), Error>,
+) -> Result<(), Error> {
+    // Only the outputs recorded within a single shot are repeated for each row
+    let mapping = mapping
+        .iter()
+        .position(|recorded_output| matches!(recorded_output, RecordedOutput::ShotEnd))
+        .map_or(mapping, |end| &mapping[..end]);
+
+    let mut row = String::from("shot");
+    let columns = mapping
+        .iter()
+        .filter_map(|recorded_output| Some((recorded_output, record_type(recorded_output)?)));
+    for (index, (recorded_output, record_type)) in columns.enumerate() {
+        let name = match tag(recorded_output) {
+            Some(tag) => quote(tag),
+            None => format!("{}_{}", record_type, index),
+        };
+        row.push(',');
+        row.push_str(&name);
+    }
+    emit(&row)?;
+
+    for shot_idx in 0..get_shot_count(result) {
+        row.clear();
+        row.push_str(&(shot_idx + 1).to_string());
+        for recorded_output in mapping {
+            let value = match recorded_output {
+                RecordedOutput::ResultReadoutOffset { region, offset, .. } => {
+                    get_integer(result, recorded_output, region, shot_idx, *offset)?.to_string()
+                }
+                RecordedOutput::FlippedResultReadoutOffset { region, offset, .. } => {
+                    (get_integer(result, recorded_output, region, shot_idx, *offset)? ^ 1)
+                        .to_string()
+                }
+                RecordedOutput::BoolReadoutOffset { region, offset, .. } => {
+                    (get_integer(result, recorded_output, region, shot_idx, *offset)? != 0)
+                        .to_string()
+                }
+                RecordedOutput::IntegerReadoutOffset { region, offset, .. } => {
+                    get_integer(result, recorded_output, region, shot_idx, *offset)?.to_string()
+                }
+                RecordedOutput::DoubleReadoutOffset { region, offset, .. } => {
+                    match get_value(result, region, shot_idx, *offset)? {
+                        value @ (RegisterValue::Double(..) | RegisterValue::Complex(..)) => {
+                            value.to_string()
+                        }
+                        RegisterValue::Integer(..) => {
+                            return Err(incompatible_record_type(region, recorded_output))
+                        }
+                    }
+                }
+                RecordedOutput::HostComputedInteger { .. } => {
+                    get_host_computed(result, recorded_output, shot_idx)?.to_string()
+                }
+                RecordedOutput::HostComputedBool { .. } => {
+                    (get_host_computed(result, recorded_output, shot_idx)? != 0).to_string()
+                }
+                RecordedOutput::ShotStart
+                | RecordedOutput::ShotEnd
+                | RecordedOutput::TupleStart
+                | RecordedOutput::TupleEnd
+                | RecordedOutput::ArrayStart { .. }
+                | RecordedOutput::ArrayEnd => continue,
+            };
+            row.push(',');
+            row.push_str(&value);
+        }
+        emit(&row)?;
+    }
+    Ok(())
+}
+
+impl std::fmt::Display for CsvOutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0.join("\n"))
+    }
+}
+
+#[cfg(test)]
+fn registers(data: RegisterData) -> Registers {
+    Registers::from([("ro".into(), data)])
+}
+
+#[test]
+fn test_execution_result_csv_output() {
+    let execution_result = registers(RegisterData::I8(vec![vec![1, 0], vec![0, 1], vec![1, 1]]));
+    let mapping = [
+        RecordedOutput::ShotStart,
+        RecordedOutput::TupleStart,
+        RecordedOutput::ResultReadoutOffset {
+            region: String::from("ro"),
+            offset: 0,
+            tag: None,
+        },
+        RecordedOutput::FlippedResultReadoutOffset {
+            region: String::from("ro"),
+            offset: 1,
+            tag: None,
+        },
+        RecordedOutput::TupleEnd,
+        RecordedOutput::BoolReadoutOffset {
+            region: String::from("ro"),
+            offset: 1,
+            tag: None,
+        },
+        RecordedOutput::ShotEnd,
+    ];
+
+    const EXPECTED_OUTPUT: &str = r#"
+shot,result_0,result_1,bool_2
+1,1,1,false
+2,0,0,true
+3,1,0,true
+"#;
+
+    assert_eq!(
+        super::try_format::<CsvOutputFormat>(&execution_result, &mapping).unwrap(),
+        EXPECTED_OUTPUT.trim()
+    );
+
+    let mut streamed = vec![];
+    super::try_format_to::<CsvOutputFormat, _>(&execution_result, &mapping, &mut streamed).unwrap();
+    assert_eq!(String::from_utf8(streamed).unwrap(), EXPECTED_OUTPUT.trim());
+}
+
+#[test]
+fn test_execution_result_csv_output_tagged() {
+    let execution_result = registers(RegisterData::I8(vec![vec![1, 0], vec![0, 1]]));
+    let mapping = [
+        RecordedOutput::ShotStart,
+        RecordedOutput::ResultReadoutOffset {
+            region: String::from("ro"),
+            offset: 0,
+            tag: Some(String::from("q0")),
+        },
+        RecordedOutput::ResultReadoutOffset {
+            region: String::from("ro"),
+            offset: 1,
+            tag: None,
+        },
+        RecordedOutput::BoolReadoutOffset {
+            region: String::from("ro"),
+            offset: 1,
+            tag: Some(String::from("say \"yes\", or no")),
+        },
+        RecordedOutput::ShotEnd,
+    ];
+
+    const EXPECTED_OUTPUT: &str = r#"
+shot,q0,result_1,"say ""yes"", or no"
+1,1,0,false
+2,0,1,true
+"#;
+
+    assert_eq!(
+        super::try_format::<CsvOutputFormat>(&execution_result, &mapping).unwrap(),
+        EXPECTED_OUTPUT.trim()
+    );
+}
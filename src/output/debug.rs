@@ -12,9 +12,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{Error, OutputFormat};
+use std::io::Write;
+
+use super::{
+    get_host_computed, get_integer, get_shot_count, get_value, incompatible_record_type, Error,
+    OutputFormat, RegisterValue, Registers,
+};
 use crate::RecordedOutput;
 
+#[cfg(test)]
 use qcs::RegisterData;
 
 /// Formats output of QIR programs in a debug-friendly structure.
@@ -24,7 +30,7 @@ pub struct DebugOutputFormat(pub Vec<String>);
 
 impl OutputFormat for DebugOutputFormat {
     /// Create an [`DebugOutputFormat`].
-    /// Will return [`enum@Error`] if something about the [`RegisterData`] or [`RecordedOutput`] is
+    /// Will return [`enum@Error`] if something about the [`qcs::RegisterData`] or [`RecordedOutput`] is
     /// unsupported, or if any of the result's data is indexed out-of-range.
     ///
     /// # Arguments
@@ -35,60 +41,105 @@ impl OutputFormat for DebugOutputFormat {
     /// # Errors
     ///
     /// See [`enum@Error`].
-    fn try_new(result: &RegisterData, mapping: &[RecordedOutput]) -> Result<Self, Error> {
+    fn try_new(result: &Registers, mapping: &[RecordedOutput]) -> Result<Self, Error> {
         let mut output = vec![];
-        match result {
-            RegisterData::I8(shots_results) => {
-                for (shot_idx, shot) in shots_results.iter().enumerate() {
-                    for recorded_output in mapping {
-                        let shot_id = shot_idx + 1;
-                        match recorded_output {
-                            RecordedOutput::ShotStart => {
-                                output.push(format!("[shot:{} start]", shot_id));
-                            }
-                            RecordedOutput::ShotEnd => {
-                                output.push(format!("[shot:{} end]", shot_id));
-                                break;
-                            }
-                            RecordedOutput::ResultReadoutOffset(index) => {
-                                #[allow(clippy::cast_possible_truncation)]
-                                let index = *index as usize;
-                                if let Some(result) = shot.get(index) {
-                                    output.push(format!("[shot:{} result {}]", shot_id, result));
-                                } else {
-                                    return Err(Error::NoShotDataAtIndex(shot_id, index));
-                                }
-                            }
-                            RecordedOutput::BoolReadoutOffset(..)
-                            | RecordedOutput::IntegerReadoutOffset(..)
-                            | RecordedOutput::DoubleReadoutOffset(..) => {
-                                return Err(Error::UnimplementedRecordType(format!(
-                                    "{:?}",
-                                    recorded_output
-                                )))
-                            }
-                            RecordedOutput::TupleStart => {
-                                output.push(format!("[shot:{} tuple_start]", shot_id));
-                            }
-                            RecordedOutput::TupleEnd => {
-                                output.push(format!("[shot:{} tuple_end]", shot_id));
-                            }
-                            RecordedOutput::ArrayStart => {
-                                output.push(format!("[shot:{} array_start]", shot_id));
-                            }
-                            RecordedOutput::ArrayEnd => {
-                                output.push(format!("[shot:{} array_end]", shot_id));
-                            }
+        for_each_line(result, mapping, |line| {
+            output.push(line.to_string());
+            Ok(())
+        })?;
+        Ok(Self(output))
+    }
+
+    /// Write each line of debug output to `writer` as it is formatted, rather than collecting
+    /// every shot into memory first.
+    ///
+    /// # Errors
+    ///
+    /// See [`enum@Error`].
+    fn try_format_to<W: Write>(
+        result: &Registers,
+        mapping: &[RecordedOutput],
+        writer: &mut W,
+    ) -> Result<(), Error> {
+        let mut first = true;
+        for_each_line(result, mapping, |line| {
+            if !first {
+                writer.write_all(b"\n")?;
+            }
+            first = false;
+            writer.write_fmt(line)?;
+            Ok(())
+        })
+    }
+}
+
+/// Format each line of debug output for `result`, in order, passing each to `emit`.
+fn for_each_line(
+    result: &Registers,
+    mapping: &[RecordedOutput],
+    mut emit: impl FnMut(std::fmt::Arguments) -> Result<(), Error>,
+) -> Result<(), Error> {
+    for shot_idx in 0..get_shot_count(result) {
+        let shot_id = shot_idx + 1;
+        for recorded_output in mapping {
+            match recorded_output {
+                RecordedOutput::ShotStart => {
+                    emit(format_args!("[shot:{} start]", shot_id))?;
+                }
+                RecordedOutput::ShotEnd => {
+                    emit(format_args!("[shot:{} end]", shot_id))?;
+                    break;
+                }
+                RecordedOutput::ResultReadoutOffset { region, offset, .. } => {
+                    let result = get_integer(result, recorded_output, region, shot_idx, *offset)?;
+                    emit(format_args!("[shot:{} result {}]", shot_id, result))?;
+                }
+                RecordedOutput::FlippedResultReadoutOffset { region, offset, .. } => {
+                    let result = get_integer(result, recorded_output, region, shot_idx, *offset)?;
+                    emit(format_args!("[shot:{} result {}]", shot_id, result ^ 1))?;
+                }
+                RecordedOutput::BoolReadoutOffset { region, offset, .. } => {
+                    let value = get_integer(result, recorded_output, region, shot_idx, *offset)?;
+                    emit(format_args!("[shot:{} bool {}]", shot_id, value != 0))?;
+                }
+                RecordedOutput::IntegerReadoutOffset { region, offset, .. } => {
+                    let value = get_integer(result, recorded_output, region, shot_idx, *offset)?;
+                    emit(format_args!("[shot:{} integer {}]", shot_id, value))?;
+                }
+                RecordedOutput::DoubleReadoutOffset { region, offset, .. } => {
+                    match get_value(result, region, shot_idx, *offset)? {
+                        value @ (RegisterValue::Double(..) | RegisterValue::Complex(..)) => {
+                            emit(format_args!("[shot:{} double {}]", shot_id, value))?;
+                        }
+                        RegisterValue::Integer(..) => {
+                            return Err(incompatible_record_type(region, recorded_output))
                         }
                     }
                 }
-                Ok(Self(output))
-            }
-            RegisterData::Complex32(..) | RegisterData::F64(..) | RegisterData::I16(..) => {
-                Err(Error::UnimplementedResultType(format!("{:?}", result)))
+                RecordedOutput::HostComputedInteger { .. } => {
+                    let value = get_host_computed(result, recorded_output, shot_idx)?;
+                    emit(format_args!("[shot:{} integer {}]", shot_id, value))?;
+                }
+                RecordedOutput::HostComputedBool { .. } => {
+                    let value = get_host_computed(result, recorded_output, shot_idx)?;
+                    emit(format_args!("[shot:{} bool {}]", shot_id, value != 0))?;
+                }
+                RecordedOutput::TupleStart => {
+                    emit(format_args!("[shot:{} tuple_start]", shot_id))?;
+                }
+                RecordedOutput::TupleEnd => {
+                    emit(format_args!("[shot:{} tuple_end]", shot_id))?;
+                }
+                RecordedOutput::ArrayStart { .. } => {
+                    emit(format_args!("[shot:{} array_start]", shot_id))?;
+                }
+                RecordedOutput::ArrayEnd => {
+                    emit(format_args!("[shot:{} array_end]", shot_id))?;
+                }
             }
         }
     }
+    Ok(())
 }
 
 impl std::fmt::Display for DebugOutputFormat {
@@ -97,15 +148,35 @@ impl std::fmt::Display for DebugOutputFormat {
     }
 }
 
+#[cfg(test)]
+fn registers(data: RegisterData) -> Registers {
+    Registers::from([("ro".into(), data)])
+}
+
 #[test]
 fn test_execution_result_debug_output() {
-    let execution_result =
-        RegisterData::I8(vec![vec![1, 2, 3], vec![10, 20, 30], vec![11, 22, 33]]);
+    let execution_result = registers(RegisterData::I8(vec![
+        vec![1, 2, 3],
+        vec![10, 20, 30],
+        vec![11, 22, 33],
+    ]));
     let mapping = [
         RecordedOutput::ShotStart,
-        RecordedOutput::ResultReadoutOffset(0),
-        RecordedOutput::ResultReadoutOffset(1),
-        RecordedOutput::ResultReadoutOffset(2),
+        RecordedOutput::ResultReadoutOffset {
+            region: String::from("ro"),
+            offset: 0,
+            tag: None,
+        },
+        RecordedOutput::ResultReadoutOffset {
+            region: String::from("ro"),
+            offset: 1,
+            tag: None,
+        },
+        RecordedOutput::ResultReadoutOffset {
+            region: String::from("ro"),
+            offset: 2,
+            tag: None,
+        },
         RecordedOutput::ShotEnd,
     ];
 
@@ -139,20 +210,173 @@ fn test_execution_result_debug_output() {
 #[test]
 fn test_out_of_range_debug_output() {
     // use misaligned result data with mapping data to trigger `NoShotDataAtIndex` error
-    let execution_result = RegisterData::I8(vec![vec![1, 2, 3], vec![10, 20]]);
+    let execution_result = registers(RegisterData::I8(vec![vec![1, 2, 3], vec![10, 20]]));
     let mapping = [
         RecordedOutput::ShotStart,
-        RecordedOutput::ResultReadoutOffset(0),
-        RecordedOutput::ResultReadoutOffset(1),
-        RecordedOutput::ResultReadoutOffset(2),
+        RecordedOutput::ResultReadoutOffset {
+            region: String::from("ro"),
+            offset: 0,
+            tag: None,
+        },
+        RecordedOutput::ResultReadoutOffset {
+            region: String::from("ro"),
+            offset: 1,
+            tag: None,
+        },
+        RecordedOutput::ResultReadoutOffset {
+            region: String::from("ro"),
+            offset: 2,
+            tag: None,
+        },
         RecordedOutput::ShotEnd,
     ];
 
     let try_output = DebugOutputFormat::try_new(&execution_result, &mapping);
-    if let Some(Error::NoShotDataAtIndex(shot_id, index)) = try_output.err() {
+    if let Some(Error::NoShotDataAtIndex(shot_id, region, index)) = try_output.err() {
         assert_eq!(shot_id, 2);
+        assert_eq!(region, "ro");
         assert_eq!(index, 2)
     } else {
         assert!(false);
     }
 }
+
+#[test]
+fn test_flipped_readout_debug_output() {
+    let execution_result = registers(RegisterData::I8(vec![vec![0, 1], vec![1, 1]]));
+    let mapping = [
+        RecordedOutput::ShotStart,
+        RecordedOutput::FlippedResultReadoutOffset {
+            region: String::from("ro"),
+            offset: 0,
+            tag: None,
+        },
+        RecordedOutput::ResultReadoutOffset {
+            region: String::from("ro"),
+            offset: 1,
+            tag: None,
+        },
+        RecordedOutput::ShotEnd,
+    ];
+
+    const EXPECTED_OUTPUT: &str = r#"
+[shot:1 start]
+[shot:1 result 1]
+[shot:1 result 1]
+[shot:1 end]
+[shot:2 start]
+[shot:2 result 0]
+[shot:2 result 1]
+[shot:2 end]
+"#;
+
+    assert_eq!(
+        super::try_format::<DebugOutputFormat>(&execution_result, &mapping).unwrap(),
+        EXPECTED_OUTPUT.trim()
+    )
+}
+
+#[test]
+fn test_missing_register_debug_output() {
+    let execution_result = registers(RegisterData::I8(vec![vec![0]]));
+    let mapping = [RecordedOutput::ResultReadoutOffset {
+        region: String::from("other"),
+        offset: 0,
+        tag: None,
+    }];
+
+    let try_output = DebugOutputFormat::try_new(&execution_result, &mapping);
+    assert!(matches!(try_output, Err(Error::MissingRegister(region)) if region == "other"));
+}
+
+#[test]
+fn test_typed_register_debug_output() {
+    let execution_result = Registers::from([
+        ("ro".into(), RegisterData::I8(vec![vec![1, 0]])),
+        ("counts".into(), RegisterData::I16(vec![vec![-300]])),
+        ("theta".into(), RegisterData::F64(vec![vec![0.25]])),
+        (
+            "iq".into(),
+            RegisterData::Complex32(vec![vec![num_complex::Complex32::new(1.5, -2.0)]]),
+        ),
+    ]);
+    let mapping = [
+        RecordedOutput::ShotStart,
+        RecordedOutput::BoolReadoutOffset {
+            region: String::from("ro"),
+            offset: 1,
+            tag: None,
+        },
+        RecordedOutput::IntegerReadoutOffset {
+            region: String::from("counts"),
+            offset: 0,
+            tag: None,
+        },
+        RecordedOutput::DoubleReadoutOffset {
+            region: String::from("theta"),
+            offset: 0,
+            tag: None,
+        },
+        RecordedOutput::DoubleReadoutOffset {
+            region: String::from("iq"),
+            offset: 0,
+            tag: None,
+        },
+        RecordedOutput::ShotEnd,
+    ];
+
+    const EXPECTED_OUTPUT: &str = r#"
+[shot:1 start]
+[shot:1 bool false]
+[shot:1 integer -300]
+[shot:1 double 0.25]
+[shot:1 double 1.5-2i]
+[shot:1 end]
+"#;
+
+    assert_eq!(
+        super::try_format::<DebugOutputFormat>(&execution_result, &mapping).unwrap(),
+        EXPECTED_OUTPUT.trim()
+    )
+}
+
+#[test]
+fn test_incompatible_register_debug_output() {
+    let execution_result = registers(RegisterData::F64(vec![vec![0.5]]));
+    let mapping = [RecordedOutput::ResultReadoutOffset {
+        region: String::from("ro"),
+        offset: 0,
+        tag: None,
+    }];
+
+    let try_output = DebugOutputFormat::try_new(&execution_result, &mapping);
+    assert!(matches!(try_output, Err(Error::IncompatibleRecordType(region, _)) if region == "ro"));
+}
+
+#[test]
+fn test_streamed_debug_output_matches_display() {
+    let execution_result = registers(RegisterData::I8(vec![vec![1, 0], vec![0, 1]]));
+    let mapping = [
+        RecordedOutput::ShotStart,
+        RecordedOutput::ResultReadoutOffset {
+            region: String::from("ro"),
+            offset: 0,
+            tag: None,
+        },
+        RecordedOutput::FlippedResultReadoutOffset {
+            region: String::from("ro"),
+            offset: 1,
+            tag: None,
+        },
+        RecordedOutput::ShotEnd,
+    ];
+
+    let mut streamed = vec![];
+    super::try_format_to::<DebugOutputFormat, _>(&execution_result, &mapping, &mut streamed)
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(streamed).unwrap(),
+        super::try_format::<DebugOutputFormat>(&execution_result, &mapping).unwrap()
+    )
+}
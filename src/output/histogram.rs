@@ -0,0 +1,206 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+
+use super::{
+    get_host_computed, get_integer, get_shot_count, get_value, incompatible_record_type, Error,
+    OutputFormat, RegisterValue, Registers,
+};
+use crate::RecordedOutput;
+
+#[cfg(test)]
+use qcs::RegisterData;
+
+/// Aggregates output of QIR programs into the number of shots which recorded each distinct
+/// outcome.
+///
+/// Each outcome is keyed by the values a shot recorded, in order. `Result` and `Bool` values are
+/// written as single bits and run together into bitstrings; other values, tuples, and arrays are
+/// separated by commas, with tuples enclosed in `()` and arrays in `[]`. For instance, a shot
+/// which records a tuple of two results followed by an integer is keyed as `(01),7`.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Default)]
+pub struct HistogramOutputFormat(pub BTreeMap<String, usize>);
+
+impl OutputFormat for HistogramOutputFormat {
+    /// Create a [`HistogramOutputFormat`].
+    /// Will return [`enum@Error`] if something about the [`qcs::RegisterData`] or [`RecordedOutput`] is
+    /// unsupported, or if any of the result's data is indexed out-of-range.
+    ///
+    /// # Errors
+    ///
+    /// See [`enum@Error`].
+    fn try_new(result: &Registers, mapping: &[RecordedOutput]) -> Result<Self, Error> {
+        let mut counts = BTreeMap::new();
+        for shot_idx in 0..get_shot_count(result) {
+            let mut key = OutcomeKey::default();
+            for recorded_output in mapping {
+                match recorded_output {
+                    RecordedOutput::ShotStart => {}
+                    RecordedOutput::ShotEnd => break,
+                    RecordedOutput::ResultReadoutOffset { region, offset, .. } => {
+                        let value =
+                            get_integer(result, recorded_output, region, shot_idx, *offset)?;
+                        key.push_bit(value != 0);
+                    }
+                    RecordedOutput::FlippedResultReadoutOffset { region, offset, .. } => {
+                        let value =
+                            get_integer(result, recorded_output, region, shot_idx, *offset)?;
+                        key.push_bit(value == 0);
+                    }
+                    RecordedOutput::BoolReadoutOffset { region, offset, .. } => {
+                        let value =
+                            get_integer(result, recorded_output, region, shot_idx, *offset)?;
+                        key.push_bit(value != 0);
+                    }
+                    RecordedOutput::IntegerReadoutOffset { region, offset, .. } => {
+                        let value =
+                            get_integer(result, recorded_output, region, shot_idx, *offset)?;
+                        key.push_value(&value.to_string());
+                    }
+                    RecordedOutput::DoubleReadoutOffset { region, offset, .. } => {
+                        match get_value(result, region, shot_idx, *offset)? {
+                            value @ (RegisterValue::Double(..) | RegisterValue::Complex(..)) => {
+                                key.push_value(&value.to_string());
+                            }
+                            RegisterValue::Integer(..) => {
+                                return Err(incompatible_record_type(region, recorded_output))
+                            }
+                        }
+                    }
+                    RecordedOutput::HostComputedInteger { .. } => {
+                        let value = get_host_computed(result, recorded_output, shot_idx)?;
+                        key.push_value(&value.to_string());
+                    }
+                    RecordedOutput::HostComputedBool { .. } => {
+                        let value = get_host_computed(result, recorded_output, shot_idx)?;
+                        key.push_bit(value != 0);
+                    }
+                    RecordedOutput::TupleStart => key.open('('),
+                    RecordedOutput::TupleEnd => key.close(')'),
+                    RecordedOutput::ArrayStart { .. } => key.open('['),
+                    RecordedOutput::ArrayEnd => key.close(']'),
+                }
+            }
+            *counts.entry(key.0).or_insert(0) += 1;
+        }
+        Ok(Self(counts))
+    }
+}
+
+/// Builds the key identifying the outcome of a single shot.
+#[derive(Default)]
+struct OutcomeKey(String, Separator);
+
+/// What, if anything, must precede the next item written to an [`OutcomeKey`].
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Separator {
+    /// The next item is the first within its tuple or array, or within the shot.
+    None,
+    /// The previous item was a bit, which a following bit is run together with.
+    AfterBit,
+    /// The previous item was some other value, tuple, or array.
+    AfterValue,
+}
+
+impl Default for Separator {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+impl OutcomeKey {
+    fn push_bit(&mut self, bit: bool) {
+        if self.1 == Separator::AfterValue {
+            self.0.push(',');
+        }
+        self.0.push(if bit { '1' } else { '0' });
+        self.1 = Separator::AfterBit;
+    }
+
+    fn push_value(&mut self, value: &str) {
+        self.separate();
+        self.0.push_str(value);
+        self.1 = Separator::AfterValue;
+    }
+
+    fn open(&mut self, delimiter: char) {
+        self.separate();
+        self.0.push(delimiter);
+        self.1 = Separator::None;
+    }
+
+    fn close(&mut self, delimiter: char) {
+        self.0.push(delimiter);
+        self.1 = Separator::AfterValue;
+    }
+
+    fn separate(&mut self) {
+        if self.1 != Separator::None {
+            self.0.push(',');
+        }
+    }
+}
+
+impl std::fmt::Display for HistogramOutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let lines: Vec<String> = self
+            .0
+            .iter()
+            .map(|(outcome, count)| format!("{}: {}", outcome, count))
+            .collect();
+        f.write_str(&lines.join("\n"))
+    }
+}
+
+#[test]
+fn test_execution_result_histogram_output() {
+    let execution_result = Registers::from([(
+        "ro".into(),
+        RegisterData::I8(vec![vec![1, 0], vec![0, 1], vec![1, 0], vec![1, 1]]),
+    )]);
+    let mapping = [
+        RecordedOutput::ShotStart,
+        RecordedOutput::ResultReadoutOffset {
+            region: String::from("ro"),
+            offset: 0,
+            tag: None,
+        },
+        RecordedOutput::TupleStart,
+        RecordedOutput::ResultReadoutOffset {
+            region: String::from("ro"),
+            offset: 0,
+            tag: None,
+        },
+        RecordedOutput::ResultReadoutOffset {
+            region: String::from("ro"),
+            offset: 1,
+            tag: None,
+        },
+        RecordedOutput::TupleEnd,
+        RecordedOutput::ShotEnd,
+    ];
+
+    const EXPECTED_OUTPUT: &str = r#"
+0,(01): 1
+1,(10): 2
+1,(11): 1
+"#;
+
+    assert_eq!(
+        super::try_format::<HistogramOutputFormat>(&execution_result, &mapping).unwrap(),
+        EXPECTED_OUTPUT.trim()
+    )
+}
@@ -12,13 +12,18 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod csv;
 pub mod debug;
+pub mod histogram;
+pub use csv::CsvOutputFormat;
 pub use debug::DebugOutputFormat;
+pub use histogram::HistogramOutputFormat;
 
-use std::fmt::Display;
+use std::{collections::HashMap, fmt::Display, io::Write};
 
 use crate::RecordedOutput;
 
+use num_complex::Complex32;
 use qcs::RegisterData;
 use thiserror::Error;
 
@@ -34,10 +39,47 @@ pub enum Error {
     UnimplementedRecordType(String),
 
     /// Encountered when [`RegisterData`] data was indexed out-of-range.
-    #[error("No data was available in the `RegisterData` for shot ID {0} at index {1}")]
-    NoShotDataAtIndex(usize, usize),
+    #[error("No data was available in register `{1}` for shot ID {0} at index {2}")]
+    NoShotDataAtIndex(usize, String, usize),
+
+    /// Encountered when a [`RecordedOutput`] refers to a register which is absent from the results.
+    #[error("No data was returned for register `{0}`")]
+    MissingRegister(String),
+
+    /// Encountered when a [`RecordedOutput`] refers to a register whose data type cannot represent it,
+    /// such as a `Result` read from a floating-point register.
+    #[error("the data in register `{0}` cannot be read as record type `{1}`")]
+    IncompatibleRecordType(String, String),
+
+    /// Encountered when formatted output could not be written.
+    #[error("failed to write output: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// A single value read out of a register for one shot, widened from its [`RegisterData`] type.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum RegisterValue {
+    /// Read from [`RegisterData::I8`] or [`RegisterData::I16`].
+    Integer(i64),
+    /// Read from [`RegisterData::F64`].
+    Double(f64),
+    /// Read from [`RegisterData::Complex32`].
+    Complex(Complex32),
 }
 
+impl Display for RegisterValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Integer(value) => write!(f, "{}", value),
+            Self::Double(value) => write!(f, "{}", value),
+            Self::Complex(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// The data returned from executing a program, keyed by register name, as returned by [`qcs::Executable`].
+pub type Registers = HashMap<Box<str>, RegisterData>;
+
 #[allow(clippy::module_name_repetitions)]
 /// An [`OutputFormat`] describes the behavior required to translate QCS [`RegisterData`] values
 /// into an environment-specific output format.
@@ -49,13 +91,33 @@ pub trait OutputFormat: Display {
     /// # Errors
     ///
     /// See [`enum@Error`].
-    fn try_new(result: &RegisterData, mapping: &[RecordedOutput]) -> Result<Self, Error>
+    fn try_new(result: &Registers, mapping: &[RecordedOutput]) -> Result<Self, Error>
     where
         Self: Sized;
+
+    /// Format program output directly into `writer`. The default implementation builds the whole
+    /// output with [`OutputFormat::try_new`] before writing it; formats which can emit output a
+    /// shot at a time should override this so that large results need not be held in memory.
+    ///
+    /// # Errors
+    ///
+    /// See [`enum@Error`].
+    fn try_format_to<W: Write>(
+        result: &Registers,
+        mapping: &[RecordedOutput],
+        writer: &mut W,
+    ) -> Result<(), Error>
+    where
+        Self: Sized,
+    {
+        let output = Self::try_new(result, mapping)?;
+        write!(writer, "{}", output)?;
+        Ok(())
+    }
 }
 
 /// A generic function over `F`: [`OutputFormat`], which attempts to format program output based on
-/// the `&RegisterData` and `&[RecordedOutput]` provided. Caller must specify the concrete
+/// the `&Registers` and `&[RecordedOutput]` provided. Caller must specify the concrete
 /// implementation of the `OutputFormat`, e.g. using `DebugOutputFormat` in this crate.
 ///
 /// While some [`RecordedOutput`] and [`RegisterData`] variants may be unimplemented for various
@@ -67,26 +129,147 @@ pub trait OutputFormat: Display {
 /// See `Error`.
 ///
 /// ```
-/// pub use qcs_sdk_qir::output::{try_format, DebugOutputFormat, Error};
+/// pub use qcs_sdk_qir::output::{try_format, DebugOutputFormat, Error, Registers};
 /// use qcs::RegisterData;
 /// use qcs_sdk_qir::RecordedOutput;
 ///
 /// fn format_output() -> Result<String, Error> {
 ///     // in practice, `result` and `mapping` would be provided to you from other QCS SDK
 ///     // function calls, not constructed manually as done here for demonstration purposes.
-///     let result = &RegisterData::I8(vec![vec![1]]);
+///     let result: Registers = [("ro".into(), RegisterData::I8(vec![vec![1]]))].into_iter().collect();
 ///     let mapping: &[RecordedOutput] = &[
-///         RecordedOutput::ShotStart, RecordedOutput::ResultReadoutOffset(0), RecordedOutput::ShotEnd
+///         RecordedOutput::ShotStart,
+///         RecordedOutput::ResultReadoutOffset { region: String::from("ro"), offset: 0, tag: None },
+///         RecordedOutput::ShotEnd,
 ///     ];
 ///
-///     let output = try_format::<DebugOutputFormat>(result, mapping)?;
+///     let output = try_format::<DebugOutputFormat>(&result, mapping)?;
 ///     assert_eq!(output.lines().count(), 3);
 ///     Ok(output)
 /// }
 /// ```
-pub fn try_format<F>(result: &RegisterData, mapping: &[RecordedOutput]) -> Result<String, Error>
+pub fn try_format<F>(result: &Registers, mapping: &[RecordedOutput]) -> Result<String, Error>
 where
     F: OutputFormat,
 {
     F::try_new(result, mapping).map(|output| output.to_string())
 }
+
+/// As [`try_format`], but writes the formatted output to `writer` as it is produced rather than
+/// returning it as a single `String`. Prefer this for results with many shots, passing a buffered
+/// writer such as [`std::io::BufWriter`].
+///
+/// # Errors
+///
+/// See `Error`.
+pub fn try_format_to<F, W>(
+    result: &Registers,
+    mapping: &[RecordedOutput],
+    writer: &mut W,
+) -> Result<(), Error>
+where
+    F: OutputFormat,
+    W: Write,
+{
+    F::try_format_to(result, mapping, writer)
+}
+
+/// The number of shots for which data was returned, taken as the longest of any register.
+pub(crate) fn get_shot_count(registers: &Registers) -> usize {
+    registers
+        .values()
+        .map(|data| match data {
+            RegisterData::I8(shots) => shots.len(),
+            RegisterData::I16(shots) => shots.len(),
+            RegisterData::F64(shots) => shots.len(),
+            RegisterData::Complex32(shots) => shots.len(),
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Look up the register holding a recorded value, failing if it was not returned.
+pub(crate) fn get_register<'a>(
+    registers: &'a Registers,
+    region: &str,
+) -> Result<&'a RegisterData, Error> {
+    registers
+        .get(region)
+        .ok_or_else(|| Error::MissingRegister(region.to_owned()))
+}
+
+/// Read the value at `offset` within `region` for the shot at `shot_idx`.
+pub(crate) fn get_value(
+    registers: &Registers,
+    region: &str,
+    shot_idx: usize,
+    offset: u64,
+) -> Result<RegisterValue, Error> {
+    #[allow(clippy::cast_possible_truncation)]
+    let index = offset as usize;
+    let value = match get_register(registers, region)? {
+        RegisterData::I8(shots) => shots
+            .get(shot_idx)
+            .and_then(|shot| shot.get(index))
+            .map(|value| RegisterValue::Integer(i64::from(*value))),
+        RegisterData::I16(shots) => shots
+            .get(shot_idx)
+            .and_then(|shot| shot.get(index))
+            .map(|value| RegisterValue::Integer(i64::from(*value))),
+        RegisterData::F64(shots) => shots
+            .get(shot_idx)
+            .and_then(|shot| shot.get(index))
+            .map(|value| RegisterValue::Double(*value)),
+        RegisterData::Complex32(shots) => shots
+            .get(shot_idx)
+            .and_then(|shot| shot.get(index))
+            .map(|value| RegisterValue::Complex(*value)),
+    };
+    value.ok_or_else(|| Error::NoShotDataAtIndex(shot_idx + 1, region.to_owned(), index))
+}
+
+/// Read an integral value for `recorded_output` at `offset` within `region` for the shot at
+/// `shot_idx`, failing if the register holds floating-point data.
+pub(crate) fn get_integer(
+    registers: &Registers,
+    recorded_output: &RecordedOutput,
+    region: &str,
+    shot_idx: usize,
+    offset: u64,
+) -> Result<i64, Error> {
+    match get_value(registers, region, shot_idx, offset)? {
+        RegisterValue::Integer(value) => Ok(value),
+        RegisterValue::Double(..) | RegisterValue::Complex(..) => {
+            Err(incompatible_record_type(region, recorded_output))
+        }
+    }
+}
+
+/// Evaluate a [`RecordedOutput::HostComputedInteger`] or [`RecordedOutput::HostComputedBool`] for
+/// a shot from its readout, if its computation is known.
+pub(crate) fn get_host_computed(
+    registers: &Registers,
+    recorded_output: &RecordedOutput,
+    shot_idx: usize,
+) -> Result<i64, Error> {
+    match recorded_output {
+        RecordedOutput::HostComputedInteger {
+            expression: Some(expression),
+            ..
+        }
+        | RecordedOutput::HostComputedBool {
+            expression: Some(expression),
+            ..
+        } => expression.evaluate(&mut |region, offset| {
+            get_integer(registers, recorded_output, region, shot_idx, offset)
+        }),
+        _ => Err(Error::UnimplementedRecordType(format!(
+            "{:?}",
+            recorded_output
+        ))),
+    }
+}
+
+pub(crate) fn incompatible_record_type(region: &str, recorded_output: &RecordedOutput) -> Error {
+    Error::IncompatibleRecordType(region.to_owned(), format!("{:?}", recorded_output))
+}
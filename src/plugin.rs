@@ -0,0 +1,129 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! C entry points through which the LLVM pass plugin in `plugin/` runs the transformation of a
+//! module from within `opt` or any other tool using LLVM's new pass manager.
+//!
+//! The plugin's C++ shim hands the module over as bitcode, since the transformation operates on
+//! modules loaded into its own [`inkwell::context::Context`], and replaces the contents of the
+//! module with the patched bitcode returned. Options are read from the environment, as by
+//! [`PatchOptions::from_env`], since a pass pipeline cannot carry them.
+
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+
+use eyre::{eyre, Result};
+use inkwell::context::Context;
+
+use crate::{patch_module, PatchFormat, PatchOptions};
+
+/// The [`PatchFormat`] numbered `format` in the C interface, in the order declared by the shim.
+fn format_from_c(format: c_int) -> Result<PatchFormat> {
+    match format {
+        0 => Ok(PatchFormat::ShotCount),
+        1 => Ok(PatchFormat::Hybrid),
+        2 => Ok(PatchFormat::Unitary),
+        _ => Err(eyre!("unknown patch format {}", format)),
+    }
+}
+
+/// Patch `bitcode` in the given `format`, with options read from the environment, returning the
+/// patched module as bitcode.
+///
+/// # Errors
+/// 1. Returns a [`eyre::Report`] if an option cannot be parsed or the module cannot be patched.
+pub fn patch_bitcode(format: PatchFormat, bitcode: &[u8]) -> Result<Vec<u8>> {
+    let context = Context::create();
+    let module = patch_module(PatchOptions::from_env()?, &[bitcode], &context, format)?;
+    Ok(module.write_bitcode_to_memory().as_slice().to_vec())
+}
+
+/// Patch the `length` bytes of bitcode at `bitcode` in the numbered `format`. On success, returns 0
+/// and stores the patched bitcode in `output` and `output_length`, to be freed by
+/// [`qcs_sdk_qir_free_bitcode`]. On failure, returns 1 and stores a message in `error`, to be freed
+/// by [`qcs_sdk_qir_free_error`].
+///
+/// # Safety
+/// `bitcode` must point to `length` readable bytes, and `output`, `output_length`, and `error` must
+/// be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn qcs_sdk_qir_patch_bitcode(
+    format: c_int,
+    bitcode: *const u8,
+    length: usize,
+    output: *mut *mut u8,
+    output_length: *mut usize,
+    error: *mut *mut c_char,
+) -> c_int {
+    let bitcode = std::slice::from_raw_parts(bitcode, length);
+    // Unwinding out of an `extern "C"` function would abort, so panics are reported as errors
+    let result = std::panic::catch_unwind(|| patch_bitcode(format_from_c(format)?, bitcode))
+        .unwrap_or_else(|_| Err(eyre!("the transformation panicked")));
+
+    match result {
+        Ok(patched) => {
+            let patched = patched.into_boxed_slice();
+            *output_length = patched.len();
+            *output = Box::into_raw(patched).cast::<u8>();
+            0
+        }
+        Err(report) => {
+            let message = format!("{:#}", report).replace('\0', " ");
+            *error = CString::new(message).unwrap_or_default().into_raw();
+            1
+        }
+    }
+}
+
+/// Free bitcode returned by [`qcs_sdk_qir_patch_bitcode`].
+///
+/// # Safety
+/// `bitcode` and `length` must be as stored by a single successful call to
+/// [`qcs_sdk_qir_patch_bitcode`], and not already freed.
+#[no_mangle]
+pub unsafe extern "C" fn qcs_sdk_qir_free_bitcode(bitcode: *mut u8, length: usize) {
+    drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+        bitcode, length,
+    )));
+}
+
+/// Free an error message returned by [`qcs_sdk_qir_patch_bitcode`].
+///
+/// # Safety
+/// `error` must be as stored by a single failed call to [`qcs_sdk_qir_patch_bitcode`], and not
+/// already freed.
+#[no_mangle]
+pub unsafe extern "C" fn qcs_sdk_qir_free_error(error: *mut c_char) {
+    drop(CString::from_raw(error));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patched_bitcode_can_be_loaded() {
+        let data = std::fs::read("tests/fixtures/programs/bell_state.bc").unwrap();
+        let patched = patch_bitcode(format_from_c(0).unwrap(), &data).unwrap();
+
+        let context = Context::create();
+        let module = crate::interop::load::load_module_from_bitcode(&context, &patched).unwrap();
+        assert!(crate::interop::abi::get_patched_abi_version(&module).is_some());
+    }
+
+    #[test]
+    fn unknown_formats_are_rejected() {
+        assert!(format_from_c(3).is_err());
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Assembly of the declarations, resets, and pragmas surrounding the body of a Quil program, as
+//! done for each program transpiled from QIR, for tools which build Quil from other sources.
+//!
+//! A program is assembled by first declaring its memory regions and then prepending, in order, any
+//! reset and pragmas, so that the pragmas come first among its instructions:
+//!
+//! ```
+//! use std::str::FromStr;
+//! use qcs_sdk_qir::quil_builder::{declare_readout, initial_rewiring, prepend_pragmas, prepend_reset};
+//!
+//! let mut program = quil_rs::Program::from_str("H 0\nMEASURE 0 ro[0]\n").unwrap();
+//! declare_readout(&mut program, 1);
+//! let program = prepend_reset(program);
+//! let program = prepend_pragmas(program, [initial_rewiring("PARTIAL")]);
+//! assert_eq!(
+//!     program.to_string(true),
+//!     "DECLARE ro BIT[1]\nPRAGMA INITIAL_REWIRING \"PARTIAL\"\nRESET\nH 0\nMEASURE 0 ro[0]\n"
+//! );
+//! ```
+
+use quil_rs::instruction::{Declaration, Instruction, Pragma, Reset, ScalarType, Vector};
+
+/// Name of the memory region into which a program reads out its measurements.
+pub const READOUT_MEMORY_REGION_NAME: &str = "ro";
+
+/// Name of the memory region from which a program reads the parameters set before each execution.
+pub const PARAMETER_MEMORY_REGION_NAME: &str = "__qir_param";
+
+/// Declare the region of `length` bits into which `program` reads out its measurements.
+pub fn declare_readout(program: &mut quil_rs::Program, length: u64) {
+    program.add_instruction(Instruction::Declaration(Declaration {
+        name: String::from(READOUT_MEMORY_REGION_NAME),
+        size: Vector {
+            data_type: ScalarType::Bit,
+            length,
+        },
+        sharing: None,
+    }));
+}
+
+/// Declare the region of `count` reals from which `program` reads its parameters, unless it has
+/// none.
+pub fn declare_params(program: &mut quil_rs::Program, count: u64) {
+    if count == 0 {
+        return;
+    }
+    program.add_instruction(Instruction::Declaration(Declaration {
+        name: String::from(PARAMETER_MEMORY_REGION_NAME),
+        size: Vector {
+            data_type: ScalarType::Real,
+            length: count,
+        },
+        sharing: None,
+    }));
+}
+
+/// Return `program` beginning with a reset of every qubit, as for active reset.
+pub fn prepend_reset(program: quil_rs::Program) -> quil_rs::Program {
+    prepend_instructions(program, [Instruction::Reset(Reset { qubit: None })])
+}
+
+/// Return `program` beginning with `pragmas`, in the order given.
+pub fn prepend_pragmas(
+    program: quil_rs::Program,
+    pragmas: impl IntoIterator<Item = Pragma>,
+) -> quil_rs::Program {
+    prepend_instructions(program, pragmas.into_iter().map(Instruction::Pragma))
+}
+
+/// The `PRAGMA INITIAL_REWIRING` selecting the `rewiring` strategy of the compiler, such as
+/// `PARTIAL` or `NAIVE`.
+pub fn initial_rewiring(rewiring: &str) -> Pragma {
+    Pragma {
+        name: String::from("INITIAL_REWIRING"),
+        arguments: vec![format!("\"{}\"", rewiring)],
+        data: None,
+    }
+}
+
+/// Return `program` beginning with `instructions`, which are added to a new program followed by a
+/// copy of each instruction of `program`.
+fn prepend_instructions(
+    program: quil_rs::Program,
+    instructions: impl IntoIterator<Item = Instruction>,
+) -> quil_rs::Program {
+    let mut new_program = quil_rs::Program::new();
+    for instruction in instructions {
+        new_program.add_instruction(instruction);
+    }
+    for instruction in program.to_instructions(true) {
+        new_program.add_instruction(instruction);
+    }
+    new_program
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::{declare_params, declare_readout, prepend_pragmas};
+
+    #[test]
+    fn declares_parameters_only_when_there_are_some() {
+        let mut program = quil_rs::Program::from_str("RX(__qir_param[0]) 0\n").unwrap();
+        declare_params(&mut program, 0);
+        assert!(!program.to_string(true).contains("DECLARE"));
+
+        declare_readout(&mut program, 2);
+        declare_params(&mut program, 1);
+        let program = prepend_pragmas(program, []);
+        assert_eq!(
+            program.to_string(true),
+            "DECLARE __qir_param REAL[1]\nDECLARE ro BIT[2]\nRX(__qir_param[0]) 0\n"
+        );
+    }
+}
@@ -0,0 +1,90 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Native compilation of the Quil programs transpiled from QIR, so that a program may be returned
+//! ready for a given instruction set architecture (ISA) alongside the program as transpiled.
+
+use std::fmt;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use eyre::{eyre, Result, WrapErr};
+
+/// Compiles Quil programs to native Quil. Registered through
+/// [`crate::TranspileOptions::quil_compiler`].
+pub trait QuilCompiler: fmt::Debug + Send + Sync {
+    /// Compile `program` to native Quil.
+    ///
+    /// # Errors
+    /// Returns the diagnostics of the compiler if the program does not compile.
+    fn compile(&self, program: &quil_rs::Program) -> Result<quil_rs::Program>;
+}
+
+/// A [`QuilCompiler`] running the `quilc` executable, which reads a program on its standard input
+/// and writes its native compilation to its standard output.
+#[derive(Clone, Debug)]
+pub struct Quilc {
+    /// The `quilc` executable to run, found on the `PATH` unless this is a path.
+    pub command: PathBuf,
+    /// The ISA to compile for, as given to `quilc --isa`: either the name of one built into quilc,
+    /// such as `8Q`, or the path of a JSON file describing a QPU.
+    pub isa: String,
+}
+
+impl Quilc {
+    /// Compile for `isa` with the `quilc` found on the `PATH`.
+    pub fn new(isa: impl Into<String>) -> Self {
+        Self {
+            command: PathBuf::from("quilc"),
+            isa: isa.into(),
+        }
+    }
+}
+
+impl QuilCompiler for Quilc {
+    fn compile(&self, program: &quil_rs::Program) -> Result<quil_rs::Program> {
+        let mut child = Command::new(&self.command)
+            .arg("--isa")
+            .arg(&self.isa)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .wrap_err_with(|| format!("failed to run {}", self.command.display()))?;
+
+        // The handle is dropped once written, closing quilc's standard input so that it compiles
+        child
+            .stdin
+            .take()
+            .ok_or_else(|| eyre!("failed to open the standard input of quilc"))?
+            .write_all(program.to_string(true).as_bytes())
+            .wrap_err("failed to write the program to quilc")?;
+        let output = child
+            .wait_with_output()
+            .wrap_err("failed to read the output of quilc")?;
+
+        if !output.status.success() {
+            return Err(eyre!(
+                "quilc failed to compile the program for {} ({}): {}",
+                self.isa,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        quil_rs::Program::from_str(&String::from_utf8_lossy(&output.stdout))
+            .map_err(|error| eyre!("quilc returned invalid Quil: {}", error))
+    }
+}
@@ -0,0 +1,337 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rust implementations of the C functions declared in `helper/libqcs.h` and defined in
+//! `helper/helper.h`, with the same signatures and failure behavior. These are mapped over the
+//! declarations in a patched module before it is run by the JIT or, with the `mock-runtime`
+//! feature, exported under their C names so that a patched module can be statically linked against
+//! this crate in place of the helper library and the QCS C SDK.
+
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int};
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use qcs::RegisterData;
+
+use super::backend::{ExecutionBackend, ExecutionRequest};
+
+lazy_static! {
+    /// The backend used by [`execute_on_qvm`] and [`execute_on_qpu`] while a module is running.
+    pub(crate) static ref BACKEND: Mutex<Option<Box<dyn ExecutionBackend + Send>>> =
+        Mutex::new(None);
+}
+
+pub(crate) struct Executable(ExecutionRequest);
+
+pub(crate) struct ExecutionResult(Result<RegisterData, String>);
+
+pub(crate) struct ExecutableCache(Vec<Option<Box<Executable>>>);
+
+/// The runtime ABI as `(symbol name, address)` pairs.
+#[cfg(feature = "execution-jit")]
+pub(crate) fn symbols() -> Vec<(&'static str, usize)> {
+    vec![
+        ("executable_from_quil", executable_from_quil as usize),
+        ("wrap_in_shots", wrap_in_shots as usize),
+        ("set_param", set_param as usize),
+        ("execute_on_qvm", execute_on_qvm as usize),
+        ("execute_on_qpu", execute_on_qpu as usize),
+        ("panic_on_failure", panic_on_failure as usize),
+        ("get_readout_bit", get_readout_bit as usize),
+        ("free_executable", free_executable as usize),
+        ("free_execution_result", free_execution_result as usize),
+        ("reset_execution_result", reset_execution_result as usize),
+        ("execute_on_qvm_into", execute_on_qvm_into as usize),
+        ("execute_on_qpu_into", execute_on_qpu_into as usize),
+        ("create_executable_cache", create_executable_cache as usize),
+        (
+            "add_executable_cache_item",
+            add_executable_cache_item as usize,
+        ),
+        (
+            "read_from_executable_cache",
+            read_from_executable_cache as usize,
+        ),
+        ("free_executable_cache", free_executable_cache as usize),
+        ("use_qcs", use_qcs as usize),
+    ]
+}
+
+/// Print the message and exit, as the C helper does; unwinding out of an `extern "C"` function
+/// would abort without the message.
+fn fail(message: &str) -> ! {
+    println!("{}", message);
+    std::process::exit(1)
+}
+
+unsafe fn string_from_ptr(ptr: *const c_char) -> String {
+    CStr::from_ptr(ptr).to_string_lossy().into_owned()
+}
+
+#[cfg_attr(feature = "mock-runtime", no_mangle)]
+unsafe extern "C" fn executable_from_quil(quil: *const c_char) -> *mut Executable {
+    Box::into_raw(Box::new(Executable(ExecutionRequest {
+        quil: string_from_ptr(quil),
+        shots: 1,
+        parameters: vec![],
+        quantum_processor_id: None,
+    })))
+}
+
+#[cfg_attr(feature = "mock-runtime", no_mangle)]
+unsafe extern "C" fn wrap_in_shots(executable: *mut Executable, shots: c_int) {
+    match u32::try_from(shots) {
+        Ok(shots) => (*executable).0.shots = shots,
+        Err(_) => fail(&format!("shot count must be positive; got {}", shots)),
+    }
+}
+
+#[cfg_attr(feature = "mock-runtime", no_mangle)]
+unsafe extern "C" fn set_param(
+    executable: *mut Executable,
+    name: *const c_char,
+    index: c_int,
+    value: f64,
+) {
+    let index = usize::try_from(index)
+        .unwrap_or_else(|_| fail(&format!("parameter index must be >= 0; got {}", index)));
+    let parameters = &mut (*executable).0.parameters;
+    let name = string_from_ptr(name);
+    parameters.retain(|(existing_name, existing_index, _)| {
+        *existing_name != name || *existing_index != index
+    });
+    parameters.push((name, index, value));
+}
+
+unsafe fn execute(
+    executable: *mut Executable,
+    quantum_processor_id: Option<String>,
+) -> Result<RegisterData, String> {
+    let request = ExecutionRequest {
+        quantum_processor_id,
+        ..(*executable).0.clone()
+    };
+    match BACKEND.lock() {
+        Ok(mut backend) => match installed_or_default(&mut backend) {
+            Some(backend) => backend
+                .execute(&request)
+                .map_err(|error| format!("{:?}", error)),
+            None => Err(String::from("no execution backend is configured")),
+        },
+        Err(_) => Err(String::from("the execution backend lock was poisoned")),
+    }
+}
+
+/// The installed backend or, if none has been installed, the default backend.
+fn installed_or_default(
+    backend: &mut Option<Box<dyn ExecutionBackend + Send>>,
+) -> Option<&mut Box<dyn ExecutionBackend + Send>> {
+    if backend.is_none() {
+        *backend = default_backend();
+    }
+
+    backend.as_mut()
+}
+
+/// When statically linked with the `mock-runtime` feature, nothing installs a backend, so a mock
+/// backend is configured from the environment.
+#[cfg(feature = "mock-runtime")]
+fn default_backend() -> Option<Box<dyn ExecutionBackend + Send>> {
+    match super::mock::MockBackend::from_env() {
+        Ok(mock) => Some(Box::new(mock)),
+        Err(error) => fail(&format!("failed to configure mock runtime: {:?}", error)),
+    }
+}
+
+#[cfg(not(feature = "mock-runtime"))]
+fn default_backend() -> Option<Box<dyn ExecutionBackend + Send>> {
+    None
+}
+
+#[cfg_attr(feature = "mock-runtime", no_mangle)]
+unsafe extern "C" fn execute_on_qvm(executable: *mut Executable) -> *mut ExecutionResult {
+    Box::into_raw(Box::new(ExecutionResult(execute(executable, None))))
+}
+
+#[cfg_attr(feature = "mock-runtime", no_mangle)]
+unsafe extern "C" fn execute_on_qpu(
+    executable: *mut Executable,
+    quantum_processor_id: *const c_char,
+) -> *mut ExecutionResult {
+    Box::into_raw(Box::new(ExecutionResult(execute(
+        executable,
+        Some(string_from_ptr(quantum_processor_id)),
+    ))))
+}
+
+/// Empty `result` so that it may be filled again, or allocate an empty result if it is null.
+#[cfg_attr(feature = "mock-runtime", no_mangle)]
+unsafe extern "C" fn reset_execution_result(result: *mut ExecutionResult) -> *mut ExecutionResult {
+    let empty = Err(String::from(
+        "the result has not been filled by an execution",
+    ));
+    if result.is_null() {
+        Box::into_raw(Box::new(ExecutionResult(empty)))
+    } else {
+        (*result).0 = empty;
+        result
+    }
+}
+
+#[cfg_attr(feature = "mock-runtime", no_mangle)]
+unsafe extern "C" fn execute_on_qvm_into(
+    executable: *mut Executable,
+    result: *mut ExecutionResult,
+) {
+    (*result).0 = execute(executable, None);
+}
+
+#[cfg_attr(feature = "mock-runtime", no_mangle)]
+unsafe extern "C" fn execute_on_qpu_into(
+    executable: *mut Executable,
+    quantum_processor_id: *const c_char,
+    result: *mut ExecutionResult,
+) {
+    (*result).0 = execute(executable, Some(string_from_ptr(quantum_processor_id)));
+}
+
+#[cfg_attr(feature = "mock-runtime", no_mangle)]
+unsafe extern "C" fn panic_on_failure(result: *mut ExecutionResult) {
+    if let Err(error) = &(*result).0 {
+        fail(&format!("error in execution: {}", error));
+    }
+}
+
+/// As in the C helper, `shot_index` is 1-indexed while `readout_index` is 0-indexed.
+#[cfg_attr(feature = "mock-runtime", no_mangle)]
+unsafe extern "C" fn get_readout_bit(
+    result: *mut ExecutionResult,
+    shot_index: i64,
+    readout_index: i64,
+) -> bool {
+    let shots = match &(*result).0 {
+        Ok(RegisterData::I8(shots)) => shots,
+        Ok(_) => fail("data not of type byte"),
+        Err(_) => fail("no data"),
+    };
+
+    let shot = usize::try_from(shot_index)
+        .ok()
+        .and_then(|shot_index| shot_index.checked_sub(1))
+        .unwrap_or_else(|| {
+            fail(&format!(
+                "shot data array is indexed from 1; shot index must be >= 1; got {}",
+                shot_index
+            ))
+        });
+    let shot = shots.get(shot).unwrap_or_else(|| {
+        fail(&format!(
+            "requested shot index {}; only {} shots taken",
+            shot_index,
+            shots.len()
+        ))
+    });
+
+    let readout = usize::try_from(readout_index).unwrap_or_else(|_| {
+        fail(&format!(
+            "readout data array is indexed from 0; readout index must be >= 0; got {}",
+            readout_index
+        ))
+    });
+    let bit = shot.get(readout).unwrap_or_else(|| {
+        fail(&format!(
+            "requested readout index {}; only {} elements in `ro` register",
+            readout_index,
+            shot.len()
+        ))
+    });
+
+    *bit != 0
+}
+
+#[cfg_attr(feature = "mock-runtime", no_mangle)]
+unsafe extern "C" fn free_executable(executable: *mut Executable) {
+    drop(Box::from_raw(executable));
+}
+
+#[cfg_attr(feature = "mock-runtime", no_mangle)]
+unsafe extern "C" fn free_execution_result(result: *mut ExecutionResult) {
+    drop(Box::from_raw(result));
+}
+
+#[cfg_attr(feature = "mock-runtime", no_mangle)]
+unsafe extern "C" fn create_executable_cache(length: c_int) -> *mut ExecutableCache {
+    let length = usize::try_from(length).unwrap_or_default();
+    Box::into_raw(Box::new(ExecutableCache(
+        std::iter::repeat_with(|| None).take(length).collect(),
+    )))
+}
+
+#[cfg_attr(feature = "mock-runtime", no_mangle)]
+unsafe extern "C" fn add_executable_cache_item(
+    cache: *mut ExecutableCache,
+    index: c_int,
+    quil: *const c_char,
+) {
+    let executable = Box::from_raw(executable_from_quil(quil));
+    match usize::try_from(index)
+        .ok()
+        .and_then(|index| (*cache).0.get_mut(index))
+    {
+        Some(item) => *item = Some(executable),
+        None => fail(&format!(
+            "cache only holds {} executables but tried to write to index {}",
+            (*cache).0.len(),
+            index
+        )),
+    }
+}
+
+#[cfg_attr(feature = "mock-runtime", no_mangle)]
+unsafe extern "C" fn read_from_executable_cache(
+    cache: *mut ExecutableCache,
+    index: c_int,
+) -> *mut Executable {
+    let length = (*cache).0.len();
+    match usize::try_from(index)
+        .ok()
+        .and_then(|index| (*cache).0.get_mut(index))
+    {
+        Some(Some(executable)) => &mut **executable,
+        Some(None) => fail(&format!(
+            "read executable from cache at index {} but it was null",
+            index
+        )),
+        None => fail(&format!(
+            "cache only holds {} executables but tried to read from index {}",
+            length, index
+        )),
+    }
+}
+
+#[cfg_attr(feature = "mock-runtime", no_mangle)]
+unsafe extern "C" fn free_executable_cache(cache: *mut ExecutableCache) {
+    drop(Box::from_raw(cache));
+}
+
+/// As in the C helper, programs are executed unless `QCS_QIR_USE_QCS` is `0` or `false`, in which
+/// case a module patched to keep its original code runs that code instead.
+#[cfg_attr(feature = "mock-runtime", no_mangle)]
+extern "C" fn use_qcs() -> bool {
+    !matches!(
+        std::env::var("QCS_QIR_USE_QCS").as_deref(),
+        Ok("0" | "false")
+    )
+}
@@ -0,0 +1,91 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use eyre::Result;
+#[cfg(feature = "execution-jit")]
+use eyre::{eyre, WrapErr};
+#[cfg(feature = "execution-jit")]
+use qcs::Executable;
+use qcs::RegisterData;
+
+/// A request to execute a Quil program, assembled from the runtime calls made by a patched module
+/// between `executable_from_quil` and `execute_on_qvm` / `execute_on_qpu`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExecutionRequest {
+    pub quil: String,
+    pub shots: u32,
+    /// Parameter values to set before execution, as `(memory region name, index, value)`.
+    pub parameters: Vec<(String, usize, f64)>,
+    /// The QPU to execute on, or `None` to execute on the QVM.
+    pub quantum_processor_id: Option<String>,
+}
+
+/// Executes the Quil programs requested by a patched module while it runs in-process.
+pub trait ExecutionBackend {
+    /// Execute the request, returning the contents of the `ro` register for each shot.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`eyre::Report`] if the program could not be executed.
+    fn execute(&mut self, request: &ExecutionRequest) -> Result<RegisterData>;
+}
+
+/// An [`ExecutionBackend`] which executes programs on Rigetti QCS using the [`qcs`] crate.
+#[cfg(feature = "execution-jit")]
+pub struct QcsBackend {
+    runtime: tokio::runtime::Runtime,
+}
+
+#[cfg(feature = "execution-jit")]
+impl QcsBackend {
+    /// Create a new [`QcsBackend`].
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`eyre::Report`] if the async runtime used to drive execution could not be created.
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            runtime: tokio::runtime::Runtime::new()?,
+        })
+    }
+}
+
+#[cfg(feature = "execution-jit")]
+impl ExecutionBackend for QcsBackend {
+    fn execute(&mut self, request: &ExecutionRequest) -> Result<RegisterData> {
+        let shots = u16::try_from(request.shots)
+            .wrap_err_with(|| format!("cannot execute {} shots on QCS", request.shots))?;
+        let mut executable = Executable::from_quil(request.quil.as_str())
+            .with_shots(shots)
+            .read_from("ro");
+        for (name, index, value) in &request.parameters {
+            executable.with_parameter(name.as_str(), *index, *value);
+        }
+
+        let mut registers = self.runtime.block_on(async {
+            match &request.quantum_processor_id {
+                Some(quantum_processor_id) => {
+                    executable
+                        .execute_on_qpu(quantum_processor_id.as_str())
+                        .await
+                }
+                None => executable.execute_on_qvm().await,
+            }
+        })?;
+
+        registers
+            .remove("ro")
+            .ok_or_else(|| eyre!("execution returned no data for the `ro` register"))
+    }
+}
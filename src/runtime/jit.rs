@@ -0,0 +1,197 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use eyre::{eyre, Result};
+use inkwell::module::Module;
+use inkwell::targets::{InitializationConfig, Target};
+use inkwell::OptimizationLevel;
+
+use super::abi;
+use super::backend::{ExecutionBackend, QcsBackend};
+use crate::RuntimeFlavor;
+
+/// Run a patched module in-process, executing its Quil programs on Rigetti QCS. See
+/// [`run_patched_module_with_backend`].
+///
+/// # Errors
+///
+/// Returns a [`eyre::Report`] if the module could not be run.
+pub fn run_patched_module(module: &Module) -> Result<i32> {
+    run_patched_module_with_backend(module, QcsBackend::new()?)
+}
+
+/// Run a patched module in-process using LLVM's JIT, resolving the runtime ABI it calls into to
+/// implementations in this crate which execute programs using `backend`. The module must have been
+/// patched with [`crate::PatchOptions::add_main_entrypoint`]; the exit status of its `main` function
+/// is returned.
+///
+/// Note that, like the C helper, the runtime exits the process if execution fails.
+///
+/// # Errors
+///
+/// Returns a [`eyre::Report`] if the module has no `main` function or the JIT could not be created.
+pub fn run_patched_module_with_backend<B>(module: &Module, backend: B) -> Result<i32>
+where
+    B: ExecutionBackend + Send + 'static,
+{
+    let main = module.get_function("main").ok_or_else(|| {
+        eyre!("module has no `main` function; was it patched with a main entrypoint?")
+    })?;
+
+    Target::initialize_native(&InitializationConfig::default()).map_err(|e| eyre!(e))?;
+    let engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .map_err(|e| eyre!(e.to_string()))?;
+
+    // The module may have been patched for either flavor of runtime which this crate provides
+    for (name, address) in abi::symbols() {
+        for flavor in [RuntimeFlavor::QcsC, RuntimeFlavor::QcsRust] {
+            if let Some(function) = module.get_function(&flavor.function_name(name)) {
+                engine.add_global_mapping(&function, address);
+            }
+        }
+    }
+
+    let mut installed = abi::BACKEND
+        .lock()
+        .map_err(|_| eyre!("the execution backend lock was poisoned"))?;
+    if installed.is_some() {
+        return Err(eyre!("another patched module is already running"));
+    }
+    *installed = Some(Box::new(backend));
+    drop(installed);
+
+    let status = unsafe { engine.run_function_as_main(main, &[]) };
+
+    if let Ok(mut installed) = abi::BACKEND.lock() {
+        installed.take();
+    }
+
+    Ok(status)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use qcs::RegisterData;
+
+    use super::*;
+    use crate::runtime::ExecutionRequest;
+    use crate::PatchOptions;
+
+    lazy_static::lazy_static! {
+        /// Held by each test while its module runs, since only one patched module may run at a time.
+        static ref RUNNING: Mutex<()> = Mutex::new(());
+    }
+
+    /// Records each request and reads out `1` for every bit of every shot.
+    struct RecordingBackend(Arc<Mutex<Vec<ExecutionRequest>>>);
+
+    impl ExecutionBackend for RecordingBackend {
+        fn execute(&mut self, request: &ExecutionRequest) -> Result<RegisterData> {
+            self.0.lock().unwrap().push(request.clone());
+            Ok(RegisterData::I8(vec![vec![1]; request.shots as usize]))
+        }
+    }
+
+    #[test]
+    fn runs_patched_module_in_process() {
+        let _running = RUNNING.lock().unwrap();
+        let bitcode = std::fs::read("tests/fixtures/programs/measure.bc").unwrap();
+        let context = inkwell::context::Context::create();
+        let module = crate::patch_qir_with_qcs(
+            PatchOptions {
+                add_main_entrypoint: true,
+                ..PatchOptions::default()
+            },
+            &bitcode,
+            &context,
+        )
+        .unwrap();
+
+        let requests = Arc::new(Mutex::new(vec![]));
+        let status =
+            run_patched_module_with_backend(&module, RecordingBackend(requests.clone())).unwrap();
+
+        assert_eq!(status, 0);
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].shots, 42);
+        assert_eq!(requests[0].quantum_processor_id, None);
+    }
+
+    #[test]
+    fn runs_module_reusing_chunked_execution_results() {
+        let _running = RUNNING.lock().unwrap();
+        let bitcode = std::fs::read("tests/fixtures/programs/measure.bc").unwrap();
+        let context = inkwell::context::Context::create();
+        let module = crate::patch_qir_with_qcs(
+            PatchOptions {
+                add_main_entrypoint: true,
+                reuse_execution_results: true,
+                shot_chunking: crate::ShotChunking::Count(2),
+                ..PatchOptions::default()
+            },
+            &bitcode,
+            &context,
+        )
+        .unwrap();
+
+        let requests = Arc::new(Mutex::new(vec![]));
+        let status =
+            run_patched_module_with_backend(&module, RecordingBackend(requests.clone())).unwrap();
+
+        assert_eq!(status, 0);
+        let requests = requests.lock().unwrap();
+        assert_eq!(
+            requests
+                .iter()
+                .map(|request| request.shots)
+                .collect::<Vec<_>>(),
+            [21, 21]
+        );
+    }
+
+    #[test]
+    fn runs_module_reusing_execution_results_on_qpu() {
+        let _running = RUNNING.lock().unwrap();
+        let bitcode = std::fs::read("tests/fixtures/programs/measure.bc").unwrap();
+        let context = inkwell::context::Context::create();
+        let module = crate::patch_qir_with_qcs(
+            PatchOptions {
+                add_main_entrypoint: true,
+                reuse_execution_results: true,
+                execution_target: crate::ExecutionTarget::Qpu(String::from("Aspen-11")),
+                ..PatchOptions::default()
+            },
+            &bitcode,
+            &context,
+        )
+        .unwrap();
+
+        let requests = Arc::new(Mutex::new(vec![]));
+        let status =
+            run_patched_module_with_backend(&module, RecordingBackend(requests.clone())).unwrap();
+
+        assert_eq!(status, 0);
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].shots, 42);
+        assert_eq!(
+            requests[0].quantum_processor_id.as_deref(),
+            Some("Aspen-11")
+        );
+    }
+}
@@ -0,0 +1,168 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+
+use eyre::{eyre, Result, WrapErr};
+use qcs::RegisterData;
+
+use super::backend::{ExecutionBackend, ExecutionRequest};
+
+/// The environment variable naming a readout table file, used to configure the mock runtime when
+/// it is statically linked into a patched program rather than run through the JIT.
+pub const MOCK_READOUT_ENV_VAR: &str = "QCS_SDK_QIR_MOCK_READOUT";
+
+/// An [`ExecutionBackend`] which never contacts QVM or QCS, instead returning canned readout data.
+///
+/// Readout is given as a table with one row per shot, each row holding the `ro` register for that
+/// shot. When a request asks for more shots than the table has rows, the rows are repeated in order.
+#[derive(Clone, Debug, Default)]
+pub struct MockBackend {
+    readout: Vec<Vec<i8>>,
+    program_readout: HashMap<String, Vec<Vec<i8>>>,
+}
+
+impl MockBackend {
+    /// Create a [`MockBackend`] which returns `readout` for every program.
+    #[must_use]
+    pub fn new(readout: Vec<Vec<i8>>) -> Self {
+        Self {
+            readout,
+            program_readout: HashMap::new(),
+        }
+    }
+
+    /// Return `readout` when executing `quil`, instead of the default readout.
+    #[must_use]
+    pub fn with_program(mut self, quil: &str, readout: Vec<Vec<i8>>) -> Self {
+        self.program_readout
+            .insert(quil.trim().to_string(), readout);
+        self
+    }
+
+    /// Parse a readout table, with one shot per line and the bits of each shot separated by
+    /// whitespace or commas. Blank lines and lines starting with `#` are ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`eyre::Report`] if any bit is not an integer.
+    pub fn from_table(table: &str) -> Result<Self> {
+        let readout = table
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .enumerate()
+            .map(|(row, line)| {
+                line.split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|bit| !bit.is_empty())
+                    .map(|bit| {
+                        bit.parse::<i8>().wrap_err_with(|| {
+                            format!("invalid readout value `{}` in row {}", bit, row + 1)
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self::new(readout))
+    }
+
+    /// Load the readout table named by [`MOCK_READOUT_ENV_VAR`].
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`eyre::Report`] if the variable is unset or the table cannot be read or parsed.
+    pub fn from_env() -> Result<Self> {
+        let path = std::env::var(MOCK_READOUT_ENV_VAR)
+            .wrap_err_with(|| format!("{} is not set", MOCK_READOUT_ENV_VAR))?;
+        let table = std::fs::read_to_string(&path)
+            .wrap_err_with(|| format!("failed to read mock readout table {}", path))?;
+        Self::from_table(&table)
+    }
+}
+
+impl ExecutionBackend for MockBackend {
+    fn execute(&mut self, request: &ExecutionRequest) -> Result<RegisterData> {
+        let readout = self
+            .program_readout
+            .get(request.quil.trim())
+            .unwrap_or(&self.readout);
+
+        if readout.is_empty() {
+            return Err(eyre!("the mock readout table is empty"));
+        }
+
+        Ok(RegisterData::I8(
+            readout
+                .iter()
+                .cycle()
+                .take(request.shots as usize)
+                .cloned()
+                .collect(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bits(data: RegisterData) -> Vec<Vec<i8>> {
+        match data {
+            RegisterData::I8(bits) => bits,
+            _ => panic!("expected readout of type i8"),
+        }
+    }
+
+    fn request(quil: &str, shots: u32) -> ExecutionRequest {
+        ExecutionRequest {
+            quil: quil.to_string(),
+            shots,
+            parameters: vec![],
+            quantum_processor_id: None,
+        }
+    }
+
+    #[test]
+    fn parses_table_and_repeats_rows() {
+        let mut backend = MockBackend::from_table("# shot readout\n0 1\n1,0\n\n").unwrap();
+        let data = backend.execute(&request("MEASURE 0 ro[0]", 3)).unwrap();
+
+        assert_eq!(bits(data), vec![vec![0, 1], vec![1, 0], vec![0, 1]]);
+    }
+
+    #[test]
+    fn returns_program_specific_readout() {
+        let mut backend =
+            MockBackend::new(vec![vec![0]]).with_program("MEASURE 0 ro[0]\n", vec![vec![1]]);
+
+        assert_eq!(
+            bits(backend.execute(&request("MEASURE 0 ro[0]", 1)).unwrap()),
+            vec![vec![1]]
+        );
+        assert_eq!(
+            bits(
+                backend
+                    .execute(&request("X 0\nMEASURE 0 ro[0]", 1))
+                    .unwrap()
+            ),
+            vec![vec![0]]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_table() {
+        assert!(MockBackend::from_table("0 x").is_err());
+    }
+}
@@ -0,0 +1,32 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An in-process implementation of the runtime ABI which patched modules call into (see
+//! `helper/helper.h`), along with a JIT driver to run patched modules against it and a mock backend
+//! for running them without access to QVM or QCS.
+
+pub(crate) mod abi;
+pub mod backend;
+#[cfg(feature = "execution-jit")]
+pub mod jit;
+#[cfg(feature = "mock-runtime")]
+pub mod mock;
+
+#[cfg(feature = "execution-jit")]
+pub use backend::QcsBackend;
+pub use backend::{ExecutionBackend, ExecutionRequest};
+#[cfg(feature = "execution-jit")]
+pub use jit::{run_patched_module, run_patched_module_with_backend};
+#[cfg(feature = "mock-runtime")]
+pub use mock::MockBackend;
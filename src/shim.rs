@@ -0,0 +1,378 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Generation of a minimal QIR runtime for a patched module, so that it links without the full Q#
+//! runtime. Once quantum instructions are replaced, a module is often left referring only to the
+//! array, tuple, string, and reference-counting functions of the runtime, none of which need to do
+//! much outside of a simulator.
+//!
+//! Output recorded by the `*_record_output` functions is dropped, as they do nothing in the shim; a
+//! host which reads the recorded output must link a runtime defining them.
+
+use std::convert::TryInto;
+
+use eyre::{eyre, Result};
+use inkwell::{
+    builder::Builder,
+    context::Context,
+    module::{Linkage, Module},
+    types::BasicTypeEnum,
+    values::{BasicValueEnum, FunctionValue, IntValue, PointerValue},
+    IntPredicate,
+};
+
+use crate::interop::compat::{build_load, default_address_space};
+
+/// Prefix of the names of the QIR runtime functions.
+const RUNTIME_FUNCTION_PREFIX: &str = "__quantum__rt__";
+
+/// Size in bytes of the header preceding the elements of an array: its element size, then its length.
+const ARRAY_HEADER_SIZE: u64 = 16;
+
+/// Build a module defining each QIR runtime function (`__quantum__rt__*`) which `patched` declares and
+/// still refers to:
+///
+/// - `array_create_1d`, `array_get_element_ptr_1d`, and `array_get_size_1d` manage arrays allocated
+///   with `malloc`, each preceded by its element size and length
+/// - `tuple_create` allocates a tuple with `malloc`
+/// - `string_create` returns the C string it is given
+/// - `result_get_zero`, `result_get_one`, and `result_equal` compare results by address
+/// - `fail` aborts the process, as the program cannot continue
+/// - any other function does nothing and returns zero or null
+///
+/// Memory is never freed. Each function is `weak`, so that any definition in a full runtime linked
+/// alongside takes precedence.
+///
+/// # Errors
+/// 1. Returns a [`eyre::Report`] if one of the functions above is declared with an unexpected
+///    signature.
+pub fn generate_runtime_shim<'ctx>(
+    context: &'ctx Context,
+    patched: &Module<'ctx>,
+) -> Result<Module<'ctx>> {
+    let shim = context.create_module("qcs_sdk_qir_runtime_shim");
+    shim.set_triple(&patched.get_triple());
+    shim.set_data_layout(&patched.get_data_layout());
+    let builder = context.create_builder();
+
+    for declaration in patched.get_functions() {
+        let name = declaration.get_name().to_string_lossy().into_owned();
+        let is_referenced = declaration
+            .as_global_value()
+            .as_pointer_value()
+            .get_first_use()
+            .is_some();
+        if !name.starts_with(RUNTIME_FUNCTION_PREFIX)
+            || declaration.count_basic_blocks() > 0
+            || !is_referenced
+        {
+            continue;
+        }
+
+        let function = shim.add_function(&name, declaration.get_type(), Some(Linkage::WeakAny));
+        let entry = context.append_basic_block(function, "entry");
+        builder.position_at_end(entry);
+        build_body(
+            context,
+            &shim,
+            &builder,
+            function,
+            &name[RUNTIME_FUNCTION_PREFIX.len()..],
+        )
+        .map_err(|error| error.wrap_err(format!("failed to generate @{}", name)))?;
+    }
+
+    Ok(shim)
+}
+
+/// Build the body of the runtime function `__quantum__rt__<operation>` at the builder's position.
+#[allow(clippy::too_many_lines)]
+fn build_body<'ctx>(
+    context: &'ctx Context,
+    shim: &Module<'ctx>,
+    builder: &Builder<'ctx>,
+    function: FunctionValue<'ctx>,
+    operation: &str,
+) -> Result<()> {
+    let i64_type = context.i64_type();
+    let int_parameter = |index: u32| match function.get_nth_param(index) {
+        Some(BasicValueEnum::IntValue(value)) => Ok(value),
+        _ => Err(eyre!("expected an integer parameter at position {}", index)),
+    };
+    let pointer_parameter = |index: u32| match function.get_nth_param(index) {
+        Some(BasicValueEnum::PointerValue(value)) => Ok(value),
+        _ => Err(eyre!("expected a pointer parameter at position {}", index)),
+    };
+    let return_type = function.get_type().get_return_type();
+
+    let value: Option<BasicValueEnum> = match operation {
+        "array_create_1d" => {
+            let element_size =
+                builder.build_int_z_extend(int_parameter(0)?, i64_type, "element_size");
+            let length = int_parameter(1)?;
+            let size = builder.build_int_add(
+                builder.build_int_mul(element_size, length, ""),
+                i64_type.const_int(ARRAY_HEADER_SIZE, false),
+                "size",
+            );
+            let array = build_malloc(context, shim, builder, size)?;
+            builder.build_store(offset_pointer(context, builder, array, 0), element_size);
+            builder.build_store(offset_pointer(context, builder, array, 8), length);
+            Some(cast_pointer(builder, array, return_type)?)
+        }
+        "array_get_size_1d" => {
+            let array = pointer_parameter(0)?;
+            Some(build_load(
+                builder,
+                i64_type.into(),
+                offset_pointer(context, builder, array, 8),
+                "length",
+            ))
+        }
+        "array_get_element_ptr_1d" => {
+            let array = pointer_parameter(0)?;
+            let index = int_parameter(1)?;
+            let element_size: IntValue = build_load(
+                builder,
+                i64_type.into(),
+                offset_pointer(context, builder, array, 0),
+                "element_size",
+            )
+            .try_into()
+            .map_err(|_| eyre!("expected the element size of an array to be an integer"))?;
+            let address = builder.build_int_add(
+                builder.build_ptr_to_int(array, i64_type, ""),
+                builder.build_int_add(
+                    builder.build_int_mul(index, element_size, ""),
+                    i64_type.const_int(ARRAY_HEADER_SIZE, false),
+                    "",
+                ),
+                "",
+            );
+            Some(int_to_pointer(builder, address, return_type)?)
+        }
+        "tuple_create" => {
+            let tuple = build_malloc(context, shim, builder, int_parameter(0)?)?;
+            Some(cast_pointer(builder, tuple, return_type)?)
+        }
+        "string_create" => Some(cast_pointer(builder, pointer_parameter(0)?, return_type)?),
+        "result_get_zero" => Some(int_to_pointer(builder, i64_type.const_zero(), return_type)?),
+        "result_get_one" => Some(int_to_pointer(
+            builder,
+            i64_type.const_int(1, false),
+            return_type,
+        )?),
+        "result_equal" => {
+            let left = builder.build_ptr_to_int(pointer_parameter(0)?, i64_type, "");
+            let right = builder.build_ptr_to_int(pointer_parameter(1)?, i64_type, "");
+            Some(
+                builder
+                    .build_int_compare(IntPredicate::EQ, left, right, "")
+                    .into(),
+            )
+        }
+        "fail" => {
+            build_abort(context, shim, builder);
+            builder.build_unreachable();
+            return Ok(());
+        }
+        _ => return_type.map(zero_value),
+    };
+
+    match value {
+        Some(value) => builder.build_return(Some(&value)),
+        None => builder.build_return(None),
+    };
+    Ok(())
+}
+
+/// Call `malloc`, declaring it in the shim if it is not already.
+fn build_malloc<'ctx>(
+    context: &'ctx Context,
+    shim: &Module<'ctx>,
+    builder: &Builder<'ctx>,
+    size: IntValue<'ctx>,
+) -> Result<PointerValue<'ctx>> {
+    let malloc = shim.get_function("malloc").unwrap_or_else(|| {
+        shim.add_function(
+            "malloc",
+            context
+                .i8_type()
+                .ptr_type(default_address_space())
+                .fn_type(&[context.i64_type().into()], false),
+            None,
+        )
+    });
+    builder
+        .build_call(malloc, &[size.into()], "")
+        .try_as_basic_value()
+        .left()
+        .and_then(|value| value.try_into().ok())
+        .ok_or_else(|| eyre!("expected malloc to return a pointer"))
+}
+
+/// Call `abort`, declaring it in the shim if it is not already.
+fn build_abort<'ctx>(context: &'ctx Context, shim: &Module<'ctx>, builder: &Builder<'ctx>) {
+    let abort = shim.get_function("abort").unwrap_or_else(|| {
+        shim.add_function("abort", context.void_type().fn_type(&[], false), None)
+    });
+    builder.build_call(abort, &[], "");
+}
+
+/// Return a pointer to the `i64` at `offset` bytes from `pointer`.
+fn offset_pointer<'ctx>(
+    context: &'ctx Context,
+    builder: &Builder<'ctx>,
+    pointer: PointerValue<'ctx>,
+    offset: u64,
+) -> PointerValue<'ctx> {
+    let i64_type = context.i64_type();
+    let address = builder.build_int_add(
+        builder.build_ptr_to_int(pointer, i64_type, ""),
+        i64_type.const_int(offset, false),
+        "",
+    );
+    builder.build_int_to_ptr(address, i64_type.ptr_type(default_address_space()), "")
+}
+
+fn cast_pointer<'ctx>(
+    builder: &Builder<'ctx>,
+    pointer: PointerValue<'ctx>,
+    return_type: Option<BasicTypeEnum<'ctx>>,
+) -> Result<BasicValueEnum<'ctx>> {
+    match return_type {
+        Some(BasicTypeEnum::PointerType(pointer_type)) => {
+            Ok(builder.build_pointer_cast(pointer, pointer_type, "").into())
+        }
+        _ => Err(eyre!("expected the function to return a pointer")),
+    }
+}
+
+fn int_to_pointer<'ctx>(
+    builder: &Builder<'ctx>,
+    address: IntValue<'ctx>,
+    return_type: Option<BasicTypeEnum<'ctx>>,
+) -> Result<BasicValueEnum<'ctx>> {
+    match return_type {
+        Some(BasicTypeEnum::PointerType(pointer_type)) => {
+            Ok(builder.build_int_to_ptr(address, pointer_type, "").into())
+        }
+        _ => Err(eyre!("expected the function to return a pointer")),
+    }
+}
+
+fn zero_value(value_type: BasicTypeEnum) -> BasicValueEnum {
+    match value_type {
+        BasicTypeEnum::ArrayType(array_type) => array_type.const_zero().into(),
+        BasicTypeEnum::FloatType(float_type) => float_type.const_zero().into(),
+        BasicTypeEnum::IntType(int_type) => int_type.const_zero().into(),
+        BasicTypeEnum::PointerType(pointer_type) => pointer_type.const_null().into(),
+        BasicTypeEnum::StructType(struct_type) => struct_type.const_zero().into(),
+        BasicTypeEnum::VectorType(vector_type) => vector_type.const_zero().into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::interop::load::load_module_from_ir;
+
+    const IR: &str = r#"
+%Array = type opaque
+%String = type opaque
+
+declare %Array* @__quantum__rt__array_create_1d(i32, i64)
+declare i8* @__quantum__rt__array_get_element_ptr_1d(%Array*, i64)
+declare i64 @__quantum__rt__array_get_size_1d(%Array*)
+declare void @__quantum__rt__array_update_reference_count(%Array*, i32)
+declare %String* @__quantum__rt__string_create(i8*)
+declare void @__quantum__rt__message(%String*)
+declare void @__quantum__rt__tuple_update_alias_count(i8*, i32)
+
+define i64 @f() {
+entry:
+  %array = call %Array* @__quantum__rt__array_create_1d(i32 8, i64 3)
+  %element = call i8* @__quantum__rt__array_get_element_ptr_1d(%Array* %array, i64 2)
+  %size = call i64 @__quantum__rt__array_get_size_1d(%Array* %array)
+  call void @__quantum__rt__array_update_reference_count(%Array* %array, i32 -1)
+  %string = call %String* @__quantum__rt__string_create(i8* %element)
+  call void @__quantum__rt__message(%String* %string)
+  ret i64 %size
+}
+"#;
+
+    #[test]
+    fn defines_each_referenced_runtime_function() {
+        let context = Context::create();
+        let module = load_module_from_ir(&context, IR).unwrap();
+
+        let shim = generate_runtime_shim(&context, &module).unwrap();
+        shim.verify().unwrap();
+
+        for name in [
+            "__quantum__rt__array_create_1d",
+            "__quantum__rt__array_get_element_ptr_1d",
+            "__quantum__rt__array_get_size_1d",
+            "__quantum__rt__array_update_reference_count",
+            "__quantum__rt__string_create",
+            "__quantum__rt__message",
+        ] {
+            let function = shim.get_function(name).unwrap();
+            assert!(function.count_basic_blocks() > 0, "{} has no body", name);
+            assert_eq!(function.get_linkage(), Linkage::WeakAny);
+        }
+        assert!(shim
+            .get_function("__quantum__rt__tuple_update_alias_count")
+            .is_none());
+
+        // The shim links into the module it was generated for.
+        module.link_in_module(shim).unwrap();
+        module.verify().unwrap();
+    }
+
+    #[test]
+    fn fail_aborts() {
+        let context = Context::create();
+        let module = load_module_from_ir(
+            &context,
+            r#"
+%String = type opaque
+
+declare void @__quantum__rt__fail(%String*)
+
+define void @f(%String* %message) {
+entry:
+  call void @__quantum__rt__fail(%String* %message)
+  ret void
+}
+"#,
+        )
+        .unwrap();
+
+        let shim = generate_runtime_shim(&context, &module).unwrap();
+        shim.verify().unwrap();
+
+        let fail = shim
+            .get_function("__quantum__rt__fail")
+            .unwrap()
+            .print_to_string()
+            .to_string();
+        assert!(
+            fail.contains("call void @abort()") && fail.contains("unreachable"),
+            "expected @__quantum__rt__fail to abort in:\n{}",
+            fail
+        );
+    }
+}
@@ -0,0 +1,220 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Lets the caller govern the cost of a patched module at compile time, by reviewing the shot count
+// of each program extracted from it before that shot count is compiled in.
+use eyre::{eyre, Result, WrapErr};
+use inkwell::basic_block::BasicBlock;
+use log::info;
+
+use crate::context::QCSCompilerContext;
+use crate::transform::get_used_qubits;
+
+/// What is known of a program when its shot count is reviewed by a [`ShotCountPolicy`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProgramMetrics {
+    /// The name of the function holding the shot count loop
+    pub function: String,
+    /// The name of the basic block holding the shot count loop
+    pub block: String,
+    /// The number of instructions in the Quil program, including declarations
+    pub instruction_count: usize,
+    /// The distinct qubit indices used by the program, in ascending order
+    pub qubits: Vec<u64>,
+    /// The number of parameters set on the program before each execution
+    pub parameter_count: usize,
+    /// The number of bits of the `ro` region read out on each shot
+    pub readout_length: u64,
+}
+
+/// Reviews the shot count inferred for each program as a module is patched.
+///
+/// A policy is registered through [`crate::PatchOptions::shot_count_policy`]. Any closure taking
+/// the shot count and the [`ProgramMetrics`] and returning a shot count is a policy.
+pub trait ShotCountPolicy: Send + Sync {
+    /// Return the shot count with which to execute the program, which must be positive.
+    ///
+    /// # Errors
+    /// Returning an error vetoes the program, aborting the transformation.
+    fn adjust_shot_count(&self, shot_count: u64, metrics: &ProgramMetrics) -> Result<u64>;
+}
+
+impl<F> ShotCountPolicy for F
+where
+    F: Fn(u64, &ProgramMetrics) -> Result<u64> + Send + Sync,
+{
+    fn adjust_shot_count(&self, shot_count: u64, metrics: &ProgramMetrics) -> Result<u64> {
+        self(shot_count, metrics)
+    }
+}
+
+/// A [`ShotCountPolicy`] which clamps every shot count to a maximum.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MaxShotCount(pub u64);
+
+impl ShotCountPolicy for MaxShotCount {
+    fn adjust_shot_count(&self, shot_count: u64, _metrics: &ProgramMetrics) -> Result<u64> {
+        Ok(shot_count.min(self.0))
+    }
+}
+
+/// Review `shot_count`, inferred for `program` from `basic_block`, with the registered policy, if
+/// any, and return the shot count to compile in.
+pub(crate) fn apply_shot_count_policy(
+    context: &QCSCompilerContext,
+    program: &quil_rs::Program,
+    basic_block: BasicBlock,
+    shot_count: u64,
+    parameter_count: usize,
+    readout_length: u64,
+) -> Result<u64> {
+    let policy = match &context.options.shot_count_policy {
+        Some(policy) => policy,
+        None => return Ok(shot_count),
+    };
+
+    let metrics = ProgramMetrics {
+        function: basic_block
+            .get_parent()
+            .map(|function| function.get_name().to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        block: basic_block.get_name().to_string_lossy().into_owned(),
+        instruction_count: program.to_instructions(true).len(),
+        qubits: get_used_qubits(program),
+        parameter_count,
+        readout_length,
+    };
+    let adjusted = policy
+        .adjust_shot_count(shot_count, &metrics)
+        .wrap_err_with(|| {
+            format!(
+                "shot count policy rejected the program in @{}, block %{}",
+                metrics.function, metrics.block
+            )
+        })?;
+
+    if adjusted == 0 {
+        return Err(eyre!(
+            "shot count policy adjusted the shot count of the program in @{}, block %{} to 0",
+            metrics.function,
+            metrics.block
+        ));
+    }
+    if adjusted != shot_count {
+        info!(
+            "shot count policy adjusted the shot count of the program in @{}, block %{} from {} to {}",
+            metrics.function, metrics.block, shot_count, adjusted
+        );
+    }
+    Ok(adjusted)
+}
+
+/// How to split the shots of each program among separate executions, for instance to fit each one
+/// within the limits of a QPU reservation. The shots are spread evenly, so that the executions
+/// differ by at most one shot, and are read back in order as if from a single execution.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShotChunking {
+    /// Execute every shot at once.
+    Whole,
+    /// Split the shots among this many executions, or one per shot if there are fewer shots.
+    Count(u64),
+    /// Split the shots among as few executions as hold at most this many shots each.
+    MaxShots(u64),
+}
+
+impl Default for ShotChunking {
+    fn default() -> Self {
+        Self::Whole
+    }
+}
+
+impl std::str::FromStr for ShotChunking {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let lowercase = s.to_lowercase();
+        let (policy, size) = match lowercase.split_once(':') {
+            Some((policy, size)) => (policy, Some(size)),
+            None => (lowercase.as_str(), None),
+        };
+        let size = size
+            .map(|size| match size.parse::<u64>() {
+                Ok(size) if size > 0 => Ok(size),
+                _ => Err(format!(
+                    "invalid shot chunking size \"{}\"; expected a positive integer",
+                    size
+                )),
+            })
+            .transpose()?;
+        match (policy, size) {
+            ("whole", None) => Ok(Self::Whole),
+            ("count", Some(count)) => Ok(Self::Count(count)),
+            ("max-shots", Some(max_shots)) => Ok(Self::MaxShots(max_shots)),
+            _ => Err(format!(
+                "unknown shot chunking policy \"{}\"; expected \"whole\", \"count:<executions>\", or \"max-shots:<shots>\"",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for ShotChunking {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Whole => write!(f, "whole"),
+            Self::Count(count) => write!(f, "count:{}", count),
+            Self::MaxShots(max_shots) => write!(f, "max-shots:{}", max_shots),
+        }
+    }
+}
+
+impl ShotChunking {
+    /// The shot count of each execution among which `shot_count` shots are split, in order. The
+    /// earlier executions take the remainder, one shot each.
+    pub(crate) fn split(self, shot_count: u64) -> Vec<u64> {
+        let executions = match self {
+            Self::Whole => 1,
+            Self::Count(count) => count.min(shot_count),
+            Self::MaxShots(max_shots) => (shot_count + max_shots - 1) / max_shots,
+        }
+        .max(1);
+        let (quotient, remainder) = (shot_count / executions, shot_count % executions);
+        (0..executions)
+            .map(|index| quotient + u64::from(index < remainder))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::ShotChunking;
+
+    #[test]
+    fn splits_shots_evenly() {
+        assert_eq!(ShotChunking::Whole.split(1000), [1000]);
+        assert_eq!(ShotChunking::Count(3).split(1000), [334, 333, 333]);
+        assert_eq!(ShotChunking::Count(8).split(3), [1, 1, 1]);
+        assert_eq!(ShotChunking::MaxShots(400).split(1000), [334, 333, 333]);
+        assert_eq!(ShotChunking::MaxShots(500).split(1000), [500, 500]);
+
+        assert_eq!(
+            ShotChunking::from_str("max-shots:400"),
+            Ok(ShotChunking::MaxShots(400))
+        );
+        assert!(ShotChunking::from_str("count:0").is_err());
+        assert!(ShotChunking::from_str("whole:2").is_err());
+    }
+}
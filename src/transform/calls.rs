@@ -0,0 +1,102 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Traversal of the functions called from those selected for transformation. The traversal keeps
+// its own work list rather than recursing, so that a deep chain of calls cannot exhaust the stack.
+use std::collections::HashSet;
+
+use eyre::{eyre, Result};
+use inkwell::{basic_block::BasicBlock, values::FunctionValue};
+
+use crate::context::QCSCompilerContext;
+use crate::error::KindError;
+
+/// Transform each basic block of `function` with `transform_block`, which returns the functions
+/// called from the block, then each function those call in turn, depth first and in the order
+/// they are called. Each function is transformed once, however many times it is called. Calls are
+/// not followed from the functions of an allowlist.
+///
+/// # Errors
+///
+/// Fails if a function calls itself, directly or through others, naming the cycle of calls; or if
+/// a chain of calls is deeper than the `max_call_depth` of the [`crate::Limits`] in use.
+pub(crate) fn transform_call_graph<'ctx>(
+    context: &mut QCSCompilerContext<'ctx>,
+    function: FunctionValue<'ctx>,
+    transform_block: fn(
+        &mut QCSCompilerContext<'ctx>,
+        BasicBlock<'ctx>,
+    ) -> Result<Vec<FunctionValue<'ctx>>>,
+) -> Result<()> {
+    let follow_calls = context.options.function_allowlist.is_none();
+    let max_call_depth = context.options.limits.max_call_depth;
+
+    let mut transformed = HashSet::new();
+    // Each entry is the chain of calls from `function` to the function to be transformed.
+    let mut work_list = vec![vec![function]];
+    while let Some(call_path) = work_list.pop() {
+        let current = call_path[call_path.len() - 1];
+        if !transformed.insert(current) {
+            continue;
+        }
+
+        let mut called_functions = vec![];
+        for basic_block in current.get_basic_blocks() {
+            called_functions.extend(transform_block(context, basic_block)?);
+        }
+        if !follow_calls {
+            continue;
+        }
+
+        // Pushed in reverse, so that they are popped in the order they are called.
+        for called_function in called_functions.into_iter().rev() {
+            if called_function.count_basic_blocks() == 0 {
+                continue;
+            }
+            if let Some(start) = call_path
+                .iter()
+                .position(|caller| *caller == called_function)
+            {
+                return Err(eyre!(
+                    "recursive function calls cannot be transformed: {}",
+                    format_call_path(&call_path[start..], called_function)
+                ));
+            }
+            if let Some(max_call_depth) = max_call_depth {
+                if call_path.len() > max_call_depth {
+                    return Err(KindError::LimitExceeded(format!(
+                        "function calls are nested more deeply than the limit of {}: {}",
+                        max_call_depth,
+                        format_call_path(&call_path, called_function)
+                    ))
+                    .into());
+                }
+            }
+            let mut called_path = call_path.clone();
+            called_path.push(called_function);
+            work_list.push(called_path);
+        }
+    }
+    Ok(())
+}
+
+/// Render a chain of calls as `@a -> @b -> @c`, ending with `called_function`.
+fn format_call_path(call_path: &[FunctionValue], called_function: FunctionValue) -> String {
+    call_path
+        .iter()
+        .chain(std::iter::once(&called_function))
+        .map(|function| format!("@{}", function.get_name().to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
@@ -0,0 +1,157 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Reconstructs the classical computation of a recorded value from the LLVM instructions producing
+// it, so that an executor of the Quil program alone may reproduce it from the readout.
+use either::Either;
+use inkwell::{
+    values::{BasicValueEnum, InstructionOpcode, InstructionValue, IntValue},
+    IntPredicate,
+};
+
+use crate::context::QCSCompilerContext;
+use crate::expression::{BinaryOperator, ClassicalExpression};
+
+/// The depth beyond which an expression is not reconstructed, so that a long chain of instructions
+/// is left to the executor to evaluate from its LLVM IR.
+const MAX_EXPRESSION_DEPTH: usize = 64;
+
+/// Whether `instruction` computes a value without side effects, so that the quantum instructions
+/// on either side of it may be gathered into one program without changing what either computes.
+pub(crate) fn is_pure_classical_instruction(instruction: InstructionValue) -> bool {
+    matches!(
+        instruction.get_opcode(),
+        InstructionOpcode::Add
+            | InstructionOpcode::FAdd
+            | InstructionOpcode::Sub
+            | InstructionOpcode::FSub
+            | InstructionOpcode::Mul
+            | InstructionOpcode::FMul
+            | InstructionOpcode::FDiv
+            | InstructionOpcode::FNeg
+            | InstructionOpcode::Shl
+            | InstructionOpcode::LShr
+            | InstructionOpcode::AShr
+            | InstructionOpcode::And
+            | InstructionOpcode::Or
+            | InstructionOpcode::Xor
+            | InstructionOpcode::Trunc
+            | InstructionOpcode::ZExt
+            | InstructionOpcode::SExt
+            | InstructionOpcode::FPToUI
+            | InstructionOpcode::FPToSI
+            | InstructionOpcode::UIToFP
+            | InstructionOpcode::SIToFP
+            | InstructionOpcode::FPTrunc
+            | InstructionOpcode::FPExt
+            | InstructionOpcode::BitCast
+            | InstructionOpcode::ICmp
+            | InstructionOpcode::FCmp
+            | InstructionOpcode::Select
+            | InstructionOpcode::ExtractValue
+            | InstructionOpcode::InsertValue
+    )
+}
+
+/// Reconstruct the computation of `value` from the values of readout, given the `read_result`
+/// calls of the program along with the `ro` offset each reads. Returns `None` if `value` depends on
+/// anything other than readout and constants, or is computed by an unsupported instruction.
+pub(crate) fn reconstruct_expression<'ctx>(
+    context: &QCSCompilerContext<'ctx>,
+    value: IntValue<'ctx>,
+    readout_instructions: &[(u64, InstructionValue<'ctx>)],
+) -> Option<ClassicalExpression> {
+    reconstruct(context, value, readout_instructions, 0)
+}
+
+fn reconstruct<'ctx>(
+    context: &QCSCompilerContext<'ctx>,
+    value: IntValue<'ctx>,
+    readout_instructions: &[(u64, InstructionValue<'ctx>)],
+    depth: usize,
+) -> Option<ClassicalExpression> {
+    if depth > MAX_EXPRESSION_DEPTH {
+        return None;
+    }
+    if value.is_const() {
+        // Booleans are zero-extended, so that `true` is `1` rather than `-1`
+        let constant = if value.get_type().get_bit_width() == 1 {
+            value
+                .get_zero_extended_constant()
+                .and_then(|value| i64::try_from(value).ok())
+        } else {
+            value.get_sign_extended_constant()
+        };
+        return constant.map(ClassicalExpression::Constant);
+    }
+
+    let instruction = value.as_instruction()?;
+    if let Some((offset, _)) = readout_instructions
+        .iter()
+        .find(|(_, readout_instruction)| *readout_instruction == instruction)
+    {
+        let readout = ClassicalExpression::Readout {
+            region: String::from("ro"),
+            offset: *offset,
+        };
+        // Flipped readout is un-flipped where it is used within the program
+        return Some(if context.options.flip_readout {
+            ClassicalExpression::Binary {
+                operator: BinaryOperator::Xor,
+                left: Box::new(readout),
+                right: Box::new(ClassicalExpression::Constant(1)),
+            }
+        } else {
+            readout
+        });
+    }
+
+    let operand = |index| match instruction.get_operand(index) {
+        Some(Either::Left(BasicValueEnum::IntValue(operand))) => {
+            reconstruct(context, operand, readout_instructions, depth + 1).map(Box::new)
+        }
+        _ => None,
+    };
+    let binary = |operator| {
+        Some(ClassicalExpression::Binary {
+            operator,
+            left: operand(0)?,
+            right: operand(1)?,
+        })
+    };
+
+    match instruction.get_opcode() {
+        InstructionOpcode::ZExt | InstructionOpcode::Trunc => operand(0).map(|operand| *operand),
+        InstructionOpcode::And => binary(BinaryOperator::And),
+        InstructionOpcode::Or => binary(BinaryOperator::Or),
+        InstructionOpcode::Xor => binary(BinaryOperator::Xor),
+        InstructionOpcode::Add => binary(BinaryOperator::Add),
+        InstructionOpcode::Sub => binary(BinaryOperator::Sub),
+        InstructionOpcode::Mul => binary(BinaryOperator::Mul),
+        InstructionOpcode::ICmp => binary(match instruction.get_icmp_predicate()? {
+            IntPredicate::EQ => BinaryOperator::Eq,
+            IntPredicate::NE => BinaryOperator::Ne,
+            IntPredicate::ULT | IntPredicate::SLT => BinaryOperator::Lt,
+            IntPredicate::ULE | IntPredicate::SLE => BinaryOperator::Le,
+            IntPredicate::UGT | IntPredicate::SGT => BinaryOperator::Gt,
+            IntPredicate::UGE | IntPredicate::SGE => BinaryOperator::Ge,
+        }),
+        InstructionOpcode::Select => Some(ClassicalExpression::Select {
+            condition: operand(0)?,
+            if_true: operand(1)?,
+            if_false: operand(2)?,
+        }),
+        _ => None,
+    }
+}
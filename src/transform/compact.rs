@@ -0,0 +1,97 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Renumbers the qubits of a transpiled program densely, for targets such as the QVM which simulate
+// every qubit up to the greatest index used.
+use std::collections::BTreeMap;
+
+use quil_rs::instruction::{
+    Gate, Instruction, Measurement, Qubit, Reset, SetFrequency, SetPhase, SetScale, ShiftFrequency,
+    ShiftPhase,
+};
+
+/// Renumber the fixed qubits used by the body of `program` as `0, 1, 2, ...`, in ascending order
+/// of their original indices, returning the renumbered program along with the index to which each
+/// original index was mapped. Qubits are renumbered in gates, measurements, resets, and frame
+/// updates; the headers of the program, such as calibrations, are left unchanged.
+pub(crate) fn compact_qubits(program: &quil_rs::Program) -> (quil_rs::Program, BTreeMap<u64, u64>) {
+    let instructions = program.to_instructions(true);
+    let mut body = program.to_instructions(false);
+    let (headers, _) = instructions.split_at(instructions.len() - body.len());
+
+    let mut mapping = BTreeMap::new();
+    for instruction in &mut body {
+        for qubit in get_qubits_mut(instruction) {
+            if let Qubit::Fixed(index) = qubit {
+                mapping.insert(*index, 0);
+            }
+        }
+    }
+    for (emitted, index) in mapping.values_mut().zip(0..) {
+        *emitted = index;
+    }
+
+    let mut output = quil_rs::Program::new();
+    for instruction in headers {
+        output.add_instruction(instruction.clone());
+    }
+    for mut instruction in body {
+        for qubit in get_qubits_mut(&mut instruction) {
+            if let Qubit::Fixed(index) = qubit {
+                *index = mapping[&*index];
+            }
+        }
+        output.add_instruction(instruction);
+    }
+    (output, mapping)
+}
+
+/// The qubits on which `instruction` acts, if it is one which [`compact_qubits`] renumbers.
+fn get_qubits_mut(instruction: &mut Instruction) -> Vec<&mut Qubit> {
+    match instruction {
+        Instruction::Gate(Gate { qubits, .. }) => qubits.iter_mut().collect(),
+        Instruction::Measurement(Measurement { qubit, .. })
+        | Instruction::Reset(Reset { qubit: Some(qubit) }) => vec![qubit],
+        Instruction::SetFrequency(SetFrequency { frame, .. })
+        | Instruction::SetPhase(SetPhase { frame, .. })
+        | Instruction::SetScale(SetScale { frame, .. })
+        | Instruction::ShiftFrequency(ShiftFrequency { frame, .. })
+        | Instruction::ShiftPhase(ShiftPhase { frame, .. }) => frame.qubits.iter_mut().collect(),
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::compact_qubits;
+
+    #[test]
+    fn renumbers_qubits_in_ascending_order() {
+        let program = quil_rs::Program::from_str(
+            "DECLARE ro BIT[2]\nH 17\nCNOT 17 5\nRESET 40\nMEASURE 5 ro[0]\nMEASURE 17 ro[1]\n",
+        )
+        .unwrap();
+        let (program, mapping) = compact_qubits(&program);
+        assert_eq!(
+            program.to_string(true),
+            "DECLARE ro BIT[2]\nH 1\nCNOT 1 0\nRESET 2\nMEASURE 0 ro[0]\nMEASURE 1 ro[1]\n"
+        );
+        assert_eq!(
+            mapping.into_iter().collect::<Vec<_>>(),
+            [(5, 0), (17, 1), (40, 2)]
+        );
+    }
+}
@@ -0,0 +1,180 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conversion of constant parameters, as found in QIR, to Quil expressions.
+use std::f64::consts::PI;
+
+use either::Either;
+use inkwell::values::{BasicValueEnum, FloatValue, InstructionOpcode};
+use quil_rs::expression::{Expression, InfixOperator, PrefixOperator};
+
+use super::round_float;
+
+/// The largest denominator `m` for which a constant is recognized as a multiple of `pi/m`.
+const MAX_PI_DENOMINATOR: i64 = 16;
+
+/// How far a constant may be from a multiple of `pi/m` and still be written as one, allowing for
+/// frontends which emit `pi/2` as `1.5707963`.
+const PI_TOLERANCE: f64 = 1e-6;
+
+/// Build the Quil expression for a constant parameter. When `symbolic_pi` is set, a constant within
+/// tolerance of a nonzero multiple of `pi/m` is written symbolically, e.g. `pi/2` or `-3*pi/4`;
+/// other constants are rounded to `float_precision` decimal places, if given.
+pub(crate) fn build_constant_expression(
+    value: f64,
+    float_precision: Option<usize>,
+    symbolic_pi: bool,
+) -> Expression {
+    if symbolic_pi {
+        if let Some((numerator, denominator)) = get_pi_fraction(value) {
+            return build_pi_expression(numerator, denominator);
+        }
+    }
+    Expression::Number(round_float(value, float_precision).into())
+}
+
+/// Resolve a parameter chosen by a `select` on a constant condition, such as
+/// `select i1 true, double 1.0, double 2.0`, to the candidate it chooses. Any other value is
+/// returned unchanged.
+pub(crate) fn fold_constant_select(value: FloatValue) -> FloatValue {
+    let mut value = value;
+    while let Some(select) = value
+        .as_instruction()
+        .filter(|instruction| instruction.get_opcode() == InstructionOpcode::Select)
+    {
+        let condition = match select.get_operand(0) {
+            Some(Either::Left(BasicValueEnum::IntValue(condition))) => {
+                condition.get_zero_extended_constant()
+            }
+            _ => None,
+        };
+        // A `select` chooses its second operand when its condition holds, and its third otherwise.
+        let chosen = match condition {
+            Some(0) => select.get_operand(2),
+            Some(_) => select.get_operand(1),
+            None => break,
+        };
+        match chosen {
+            Some(Either::Left(BasicValueEnum::FloatValue(chosen))) => value = chosen,
+            _ => break,
+        }
+    }
+    value
+}
+
+/// Whether two gate parameters hold the same value, and so may share a slot in the parameter memory
+/// region: either they are the same LLVM value, or both are constants whose values are equal bit
+/// for bit, such as `float 0.5` and `double 0.5`. Bit equality keeps `0.0` and `-0.0` apart.
+pub(crate) fn is_same_parameter(left: FloatValue, right: FloatValue) -> bool {
+    if left == right {
+        return true;
+    }
+    match (left.get_constant(), right.get_constant()) {
+        (Some((left, _)), Some((right, _))) => left.to_bits() == right.to_bits(),
+        _ => false,
+    }
+}
+
+/// Find the fraction `n/m`, in lowest terms, such that `value` is within tolerance of `n·pi/m`.
+#[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+fn get_pi_fraction(value: f64) -> Option<(i64, i64)> {
+    if !value.is_finite() {
+        return None;
+    }
+    // Trying each denominator in increasing order finds the fraction in lowest terms first
+    (1..=MAX_PI_DENOMINATOR).find_map(|denominator| {
+        let numerator = (value * denominator as f64 / PI).round();
+        if numerator == 0.0 || numerator.abs() > i64::MAX as f64 {
+            return None;
+        }
+        let nearest = numerator * PI / denominator as f64;
+        if (value - nearest).abs() < PI_TOLERANCE {
+            Some((numerator as i64, denominator))
+        } else {
+            None
+        }
+    })
+}
+
+/// Build the expression `n*pi/m`, omitting a numerator or denominator of one.
+fn build_pi_expression(numerator: i64, denominator: i64) -> Expression {
+    let mut expression = Expression::PiConstant;
+    if numerator.abs() != 1 {
+        #[allow(clippy::cast_precision_loss)]
+        let multiple = Expression::Number((numerator.abs() as f64).into());
+        expression = Expression::Infix {
+            left: Box::new(multiple),
+            operator: InfixOperator::Star,
+            right: Box::new(expression),
+        };
+    }
+    if denominator != 1 {
+        #[allow(clippy::cast_precision_loss)]
+        let divisor = Expression::Number((denominator as f64).into());
+        expression = Expression::Infix {
+            left: Box::new(expression),
+            operator: InfixOperator::Slash,
+            right: Box::new(divisor),
+        };
+    }
+    if numerator < 0 {
+        expression = Expression::Prefix {
+            operator: PrefixOperator::Minus,
+            expression: Box::new(expression),
+        };
+    }
+    expression
+}
+
+#[cfg(test)]
+mod test {
+    use std::f64::consts::PI;
+
+    use super::{get_pi_fraction, is_same_parameter};
+
+    #[test]
+    fn recognizes_multiples_of_pi() {
+        assert_eq!(get_pi_fraction(PI), Some((1, 1)));
+        assert_eq!(get_pi_fraction(1.570_796_3), Some((1, 2)));
+        assert_eq!(get_pi_fraction(-3.0 * PI / 4.0), Some((-3, 4)));
+        assert_eq!(get_pi_fraction(2.0 * PI / 6.0), Some((1, 3)));
+        assert_eq!(get_pi_fraction(5.0 * PI), Some((5, 1)));
+    }
+
+    #[test]
+    fn leaves_other_constants_alone() {
+        assert_eq!(get_pi_fraction(0.0), None);
+        assert_eq!(get_pi_fraction(2.0), None);
+        assert_eq!(get_pi_fraction(12.123_456_789), None);
+        assert_eq!(get_pi_fraction(f64::NAN), None);
+    }
+
+    #[test]
+    fn constant_parameters_are_the_same_when_bit_identical() {
+        let context = inkwell::context::Context::create();
+        let double = context.f64_type().const_float(0.5);
+        let float = context.f32_type().const_float(0.5);
+
+        assert!(is_same_parameter(double, double));
+        assert!(is_same_parameter(double, float));
+        assert!(!is_same_parameter(
+            double,
+            context.f64_type().const_float(0.25)
+        ));
+        assert!(!is_same_parameter(
+            context.f64_type().const_float(0.0),
+            context.f64_type().const_float(-0.0)
+        ));
+    }
+}
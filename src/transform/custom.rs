@@ -0,0 +1,248 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Extension point for handling intrinsics which the built-in patterns do not recognize.
+use std::str::FromStr;
+
+use either::Either;
+use eyre::{Result, WrapErr};
+use inkwell::{
+    basic_block::BasicBlock,
+    values::{BasicValueEnum, FunctionValue, InstructionOpcode, InstructionValue},
+};
+
+use crate::{
+    context::QCSCompilerContext,
+    error::KindError,
+    interop::{
+        instruction::{
+            get_called_function_name, get_pointer_parameter_type, pointer_value_to_string,
+            pointer_value_to_u64, runtime_string_to_string, QirPointerType,
+        },
+        validate::describe_location,
+    },
+    RecordedOutput,
+};
+
+/// Handles calls which none of the built-in patterns recognize, such as vendor-specific intrinsics,
+/// by translating them into Quil.
+///
+/// Handlers are registered through [`crate::PatchOptions`] and are offered each unrecognized call
+/// within a quantum program, in the order they were registered, until one handles it. A handled call
+/// is removed from the program, and its output appended to the Quil program being built.
+pub trait CustomInstructionHandler: Send + Sync {
+    /// Translate a call into Quil, or return `Ok(None)` to leave it to the next handler.
+    ///
+    /// # Errors
+    /// Returning an error aborts the transformation, for instance when a recognized intrinsic is
+    /// called with arguments which the handler cannot translate.
+    fn handle_call(&self, call: &IntrinsicCall) -> Result<Option<CustomInstructionOutput>>;
+}
+
+/// A call instruction offered to a [`CustomInstructionHandler`].
+#[derive(Clone, Debug)]
+pub struct IntrinsicCall {
+    /// The name of the called function, such as `__vendor__pulse__body`
+    pub function_name: String,
+    /// The arguments to the call, in order
+    pub arguments: Vec<IntrinsicArgument>,
+}
+
+/// An argument to an [`IntrinsicCall`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum IntrinsicArgument {
+    /// A `%Qubit*` with a static index
+    Qubit(u64),
+    /// A `%Result*` with a static index
+    Result(u64),
+    /// A constant integer
+    Integer(i64),
+    /// A constant floating-point value
+    Double(f64),
+    /// A pointer to a constant string, such as a global initialized with `c"..."`, or a `%String*`
+    /// created from one by `__quantum__rt__string_create`
+    String(String),
+    /// Any other value, such as one computed at runtime
+    Other,
+}
+
+/// The translation of a call by a [`CustomInstructionHandler`].
+#[derive(Clone, Debug, Default)]
+pub struct CustomInstructionOutput {
+    /// Instructions to append to the Quil program in place of the call
+    pub instructions: Vec<quil_rs::instruction::Instruction>,
+    /// Entries to append to the program's recorded output
+    pub recorded_output: Vec<RecordedOutput>,
+}
+
+/// A [`CustomInstructionHandler`] which merges Quil embedded in the module into the program being
+/// built, as an escape hatch for Quil features which no built-in pattern produces, such as pulse
+/// operations. Each call to the named function must pass the Quil as a constant string, which is
+/// parsed and merged at the position of the call. Definitions such as `DEFCAL` and `DEFWAVEFORM`
+/// are kept with the program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawQuilHandler {
+    function_name: String,
+}
+
+impl RawQuilHandler {
+    /// Merge the Quil passed to each call of the function named `function_name`, such as
+    /// `__vendor__quil__body`, which takes a single `i8*`.
+    pub fn new(function_name: impl Into<String>) -> Self {
+        Self {
+            function_name: function_name.into(),
+        }
+    }
+}
+
+impl CustomInstructionHandler for RawQuilHandler {
+    fn handle_call(&self, call: &IntrinsicCall) -> Result<Option<CustomInstructionOutput>> {
+        if call.function_name != self.function_name {
+            return Ok(None);
+        }
+        let quil = match call.arguments.as_slice() {
+            [IntrinsicArgument::String(quil)] => quil,
+            _ => {
+                return Err(KindError::InvalidArgument(format!(
+                    "expected argument 0 of @{} to be a constant string of Quil, but got {:?}",
+                    call.function_name, call.arguments
+                ))
+                .into())
+            }
+        };
+        let program = quil_rs::Program::from_str(quil).map_err(|error| {
+            KindError::InvalidArgument(format!(
+                "invalid Quil given to @{}: {}",
+                call.function_name, error
+            ))
+        })?;
+
+        Ok(Some(CustomInstructionOutput {
+            instructions: program.to_instructions(true),
+            ..CustomInstructionOutput::default()
+        }))
+    }
+}
+
+/// The intrinsic through which a frontend may inject literal Quil, such as pragmas or pulse
+/// operations, at a specific point in the generated program. It takes a single `i8*` pointing to
+/// a constant string of Quil, and is handled as by a [`RawQuilHandler`] without being registered.
+pub const QUIL_INLINE_FUNCTION_NAME: &str = "__qcs__quil__inline";
+
+/// Offer a call instruction to the handler of [`QUIL_INLINE_FUNCTION_NAME`] and then to each
+/// registered handler in turn, returning the output of the first one which handles it. Returns
+/// `Ok(None)` if the instruction is not a call or no handler took it. An error from a handler is
+/// reported along with the location of the call in the module.
+pub(crate) fn handle_custom_instruction<'ctx>(
+    context: &QCSCompilerContext<'ctx>,
+    instruction: InstructionValue<'ctx>,
+) -> Result<Option<CustomInstructionOutput>> {
+    if instruction.get_opcode() != InstructionOpcode::Call {
+        return Ok(None);
+    }
+
+    let function = match get_called_function_name(instruction)?
+        .and_then(|function_name| context.module.get_function(&function_name))
+    {
+        Some(function) => function,
+        None => return Ok(None),
+    };
+    let function_name = function.get_name().to_string_lossy().into_owned();
+    let inline_handler = (function_name == QUIL_INLINE_FUNCTION_NAME)
+        .then(|| RawQuilHandler::new(QUIL_INLINE_FUNCTION_NAME));
+    if inline_handler.is_none() && context.options.custom_instruction_handlers.is_empty() {
+        return Ok(None);
+    }
+
+    let call = IntrinsicCall {
+        function_name,
+        arguments: get_intrinsic_arguments(context, instruction, function)?,
+    };
+    let handlers = inline_handler
+        .iter()
+        .map(|handler| handler as &dyn CustomInstructionHandler)
+        .chain(
+            context
+                .options
+                .custom_instruction_handlers
+                .iter()
+                .map(AsRef::as_ref),
+        );
+
+    for handler in handlers {
+        let output = handler.handle_call(&call).wrap_err_with(|| {
+            let location = match (
+                instruction.get_parent(),
+                instruction.get_parent().and_then(BasicBlock::get_parent),
+            ) {
+                (Some(block), Some(function)) => describe_location(function, block, instruction),
+                _ => format!("  {:?}", instruction),
+            };
+            format!("failed to translate the call at\n{}", location)
+        })?;
+        if output.is_some() {
+            return Ok(output);
+        }
+    }
+
+    Ok(None)
+}
+
+fn get_intrinsic_arguments<'ctx>(
+    context: &QCSCompilerContext<'ctx>,
+    instruction: InstructionValue<'ctx>,
+    function: FunctionValue<'ctx>,
+) -> Result<Vec<IntrinsicArgument>> {
+    // The final operand of a call instruction is the function being called
+    let argument_count = instruction.get_num_operands().saturating_sub(1);
+
+    (0..argument_count)
+        .map(|operand_index| {
+            let argument = match instruction.get_operand(operand_index) {
+                Some(Either::Left(BasicValueEnum::PointerValue(value))) => {
+                    let index = pointer_value_to_u64(context, value);
+                    match (
+                        get_pointer_parameter_type(function, operand_index as usize)?,
+                        index,
+                    ) {
+                        (Some(QirPointerType::Qubit), Some(index)) => {
+                            IntrinsicArgument::Qubit(index)
+                        }
+                        (Some(QirPointerType::Result), Some(index)) => {
+                            IntrinsicArgument::Result(index)
+                        }
+                        (Some(QirPointerType::String), _) => {
+                            runtime_string_to_string(context, value)
+                                .map_or(IntrinsicArgument::Other, IntrinsicArgument::String)
+                        }
+                        (None, _) => pointer_value_to_string(context, value)
+                            .or_else(|| runtime_string_to_string(context, value))
+                            .map_or(IntrinsicArgument::Other, IntrinsicArgument::String),
+                        _ => IntrinsicArgument::Other,
+                    }
+                }
+                Some(Either::Left(BasicValueEnum::IntValue(value))) => value
+                    .get_sign_extended_constant()
+                    .map_or(IntrinsicArgument::Other, IntrinsicArgument::Integer),
+                Some(Either::Left(BasicValueEnum::FloatValue(value))) => value
+                    .get_constant()
+                    .map_or(IntrinsicArgument::Other, |(value, _)| {
+                        IntrinsicArgument::Double(value)
+                    }),
+                _ => IntrinsicArgument::Other,
+            };
+            Ok(argument)
+        })
+        .collect()
+}
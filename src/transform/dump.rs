@@ -0,0 +1,197 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Writes the intermediate state of each basic block as it is transformed, so that a complex
+// transformation may be debugged offline from the files left behind.
+use std::fs;
+use std::path::PathBuf;
+
+use eyre::{Result, WrapErr};
+use inkwell::{
+    basic_block::BasicBlock,
+    values::{AnyValue, FunctionValue},
+};
+#[cfg(feature = "serde_support")]
+use serde_json::{json, Value};
+
+use crate::context::QCSCompilerContext;
+use crate::transform::shot_count_block::pattern::ShotCountPatternMatchContext;
+use crate::transform::unitary::pattern::UnitaryPatternMatchContext;
+
+/// A pattern context whose state may be dumped once it has matched a basic block.
+pub(crate) trait DumpedPatternContext {
+    /// Whether the pattern matched the block, which is otherwise left unchanged and not dumped
+    fn is_match(&self) -> bool;
+
+    /// The Quil program extracted from the block
+    fn quil_program(&self) -> &quil_rs::Program;
+
+    /// The state of the pattern context, with LLVM values written as IR
+    #[cfg(feature = "serde_support")]
+    fn to_json(&self) -> Value;
+}
+
+impl DumpedPatternContext for ShotCountPatternMatchContext<'_> {
+    fn is_match(&self) -> bool {
+        self.get_program_data().is_some()
+    }
+
+    fn quil_program(&self) -> &quil_rs::Program {
+        &self.quil_program
+    }
+
+    #[cfg(feature = "serde_support")]
+    fn to_json(&self) -> Value {
+        json!({
+            "pattern": "shot_count",
+            "shot_count": self.shot_count,
+            "recorded_output": self.recording.entries(),
+            "read_result_mapping": self.read_result_mapping.iter().collect::<Vec<_>>(),
+            "parameters": self.parameters.iter().map(print).collect::<Vec<_>>(),
+            "use_active_reset": self.use_active_reset,
+            "instructions_to_remove": self.instructions_to_remove.iter().map(print).collect::<Vec<_>>(),
+            "classical_control_branches": self
+                .classical_control_branches
+                .iter()
+                .map(|branch| print(&branch.branch_instruction))
+                .collect::<Vec<_>>(),
+            "unrolled_blocks": self
+                .unrolled_blocks
+                .iter()
+                .map(|block| block.get_name().to_string_lossy().into_owned())
+                .collect::<Vec<_>>(),
+            "trace": self.trace,
+        })
+    }
+}
+
+impl DumpedPatternContext for UnitaryPatternMatchContext<'_> {
+    fn is_match(&self) -> bool {
+        self.get_program_data().is_some()
+    }
+
+    fn quil_program(&self) -> &quil_rs::Program {
+        &self.quil_program
+    }
+
+    #[cfg(feature = "serde_support")]
+    fn to_json(&self) -> Value {
+        json!({
+            "pattern": "unitary",
+            "recorded_output": self.recording.entries(),
+            "read_result_mapping": self.read_result_mapping.iter().collect::<Vec<_>>(),
+            "parameters": self.parameters.iter().map(print).collect::<Vec<_>>(),
+            "use_active_reset": self.use_active_reset,
+            "instructions_to_remove": self.instructions_to_remove.iter().map(print).collect::<Vec<_>>(),
+            "trace": self.trace,
+        })
+    }
+}
+
+#[cfg(feature = "serde_support")]
+fn print(value: &impl AnyValue<'_>) -> String {
+    value.print_to_string().to_string().trim().to_owned()
+}
+
+/// The files written for a single basic block, each named `<function>.<block>.<kind>`:
+///
+/// - `context.json`: the pattern context which matched the block, with serde support enabled
+/// - `quil`: the Quil program extracted from the block
+/// - `before.ll` and `after.ll`: the IR of the enclosing function before and after transformation
+///
+/// Nothing is written for a block which no pattern matches.
+pub(crate) struct BlockDump<'ctx> {
+    path_prefix: PathBuf,
+    function: FunctionValue<'ctx>,
+    ir_before: String,
+    matched: bool,
+}
+
+impl<'ctx> BlockDump<'ctx> {
+    /// Begin dumping `basic_block`, if requested, by capturing the IR of its function before the
+    /// block is matched.
+    pub(crate) fn begin(
+        context: &QCSCompilerContext<'ctx>,
+        basic_block: BasicBlock<'ctx>,
+    ) -> Option<Self> {
+        let directory = context.options.dump_intermediate.as_ref()?;
+        let function = basic_block.get_parent()?;
+        let block_name = match basic_block.get_name().to_string_lossy() {
+            name if name.is_empty() => format!(
+                "block{}",
+                function
+                    .get_basic_blocks()
+                    .iter()
+                    .position(|block| *block == basic_block)
+                    .unwrap_or_default()
+            ),
+            name => name.into_owned(),
+        };
+        let file_name = format!("{}.{}", function.get_name().to_string_lossy(), block_name)
+            .replace(
+                |c: char| !(c.is_ascii_alphanumeric() || "._-".contains(c)),
+                "_",
+            );
+
+        Some(Self {
+            path_prefix: directory.join(file_name),
+            function,
+            ir_before: function.print_to_string().to_string(),
+            matched: false,
+        })
+    }
+
+    /// Write the pattern context and the Quil program extracted from the block, if it matched.
+    pub(crate) fn write_match(
+        &mut self,
+        pattern_context: &impl DumpedPatternContext,
+    ) -> Result<()> {
+        if !pattern_context.is_match() {
+            return Ok(());
+        }
+        self.matched = true;
+        if let Some(directory) = self.path_prefix.parent() {
+            fs::create_dir_all(directory).wrap_err_with(|| {
+                format!(
+                    "failed to create intermediate state directory {:?}",
+                    directory
+                )
+            })?;
+        }
+
+        #[cfg(feature = "serde_support")]
+        self.write(
+            "context.json",
+            &serde_json::to_string_pretty(&pattern_context.to_json())?,
+        )?;
+        self.write("quil", &pattern_context.quil_program().to_string(true))?;
+        self.write("before.ll", &self.ir_before)
+    }
+
+    /// Write the IR of the function once the block has been transformed.
+    pub(crate) fn finish(self) -> Result<()> {
+        if self.matched {
+            self.write("after.ll", &self.function.print_to_string().to_string())?;
+        }
+        Ok(())
+    }
+
+    fn write(&self, kind: &str, contents: &str) -> Result<()> {
+        let mut path = self.path_prefix.clone().into_os_string();
+        path.push(".");
+        path.push(kind);
+        fs::write(&path, contents)
+            .wrap_err_with(|| format!("failed to write intermediate state to {:?}", path))
+    }
+}
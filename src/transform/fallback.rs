@@ -0,0 +1,187 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// This module keeps the original code of a module alongside its transformation, so that a patched
+// module may either execute Quil on QCS or fall back at runtime to another QIR runtime, such as a
+// simulator implementing the quantum intrinsics.
+use std::convert::TryInto;
+
+use eyre::{eyre, Result};
+use inkwell::{
+    attributes::AttributeLoc,
+    module::{Linkage, Module},
+    values::{BasicMetadataValueEnum, FunctionValue, IntValue},
+};
+
+use crate::context::QCSCompilerContext;
+use crate::error::KindError;
+use crate::interop::call::build_runtime_call;
+use crate::interop::entrypoint::get_entry_function;
+use crate::transform::get_functions_to_transform;
+use crate::validation::ENTRY_POINT_ATTRIBUTES;
+
+/// Name of the runtime function which decides whether to take the QCS path through the module.
+pub(crate) const FN_NAME_USE_QCS: &str = "use_qcs";
+
+/// Suffix given to the original copy of each function defined in the module.
+const FALLBACK_SUFFIX: &str = "__qir_fallback";
+
+/// Prepare a copy of the module, before it is transformed, from which to restore its original code.
+/// Each function it defines is renamed with a suffix and made internal, and each global it defines
+/// is left to be resolved against the transformed module, so that state is shared between the two.
+pub(crate) fn copy_original_code<'ctx>(module: &Module<'ctx>) -> Module<'ctx> {
+    let original = module.clone();
+
+    for function in original.get_functions() {
+        if function.count_basic_blocks() == 0 {
+            continue;
+        }
+        // The transformed function remains the entrypoint.
+        for attribute in ENTRY_POINT_ATTRIBUTES {
+            function.remove_string_attribute(AttributeLoc::Function, attribute);
+        }
+        let name = function.get_name().to_string_lossy().into_owned();
+        function
+            .as_global_value()
+            .as_pointer_value()
+            .set_name(&format!("{}{}", name, FALLBACK_SUFFIX));
+        function.set_linkage(Linkage::Internal);
+    }
+
+    let mut globals = vec![];
+    let mut next_global = original.get_first_global();
+    while let Some(global) = next_global {
+        globals.push(global);
+        next_global = global.get_next_global();
+    }
+    for global in globals {
+        if global.get_name().to_bytes().starts_with(b"llvm.") {
+            // Appending globals such as `llvm.global_ctors` would otherwise be doubled by linking.
+            // SAFETY: the global is not referenced by any instruction.
+            unsafe { global.delete() };
+        } else if global.get_initializer().is_some()
+            && !matches!(global.get_linkage(), Linkage::Internal | Linkage::Private)
+        {
+            global.set_linkage(Linkage::AvailableExternally);
+        }
+    }
+
+    original
+}
+
+/// Link the original code into the transformed module, and begin each function from which
+/// transformation started by calling `use_qcs()`. If it returns false, the original copy of the
+/// function is called in its place, with the same arguments, and its result is returned.
+pub(crate) fn add_fallback_to_original_code<'ctx>(
+    context: &QCSCompilerContext<'ctx>,
+    original: Module<'ctx>,
+) -> Result<()> {
+    let entrypoint_function = get_entry_function(&context.module).ok_or_else(|| {
+        KindError::MissingEntrypoint(String::from("entrypoint not found in module"))
+    })?;
+    let functions = get_functions_to_transform(context, entrypoint_function)?;
+
+    context
+        .module
+        .link_in_module(original)
+        .map_err(|e| eyre!(e.to_string()).wrap_err("failed to link the original code"))?;
+
+    let use_qcs_name = context
+        .options
+        .runtime_flavor
+        .function_name(FN_NAME_USE_QCS);
+    // `use_qcs` takes the user context like the runtime functions, since the same runtime provides it.
+    let parameters = if context.options.user_context {
+        vec![context.types.string().into()]
+    } else {
+        vec![]
+    };
+    let use_qcs_function = context
+        .module
+        .get_function(&use_qcs_name)
+        .unwrap_or_else(|| {
+            context.module.add_function(
+                &use_qcs_name,
+                context.base_context.bool_type().fn_type(&parameters, false),
+                None,
+            )
+        });
+
+    for function in functions {
+        add_dispatch(context, function, use_qcs_function)?;
+    }
+
+    Ok(())
+}
+
+/// Insert a new entry block into `function` which branches to its former entry block if `use_qcs()`
+/// returns true, and otherwise returns the result of calling its original copy.
+fn add_dispatch<'ctx>(
+    context: &QCSCompilerContext<'ctx>,
+    function: FunctionValue<'ctx>,
+    use_qcs_function: FunctionValue<'ctx>,
+) -> Result<()> {
+    let name = function.get_name().to_string_lossy().into_owned();
+    let original_function = context
+        .module
+        .get_function(&format!("{}{}", name, FALLBACK_SUFFIX))
+        .ok_or_else(|| eyre!("no original copy of @{} was found", name))?;
+    let entry_basic_block = function
+        .get_first_basic_block()
+        .ok_or_else(|| eyre!("function @{} has no basic blocks", name))?;
+
+    let dispatch_basic_block = context
+        .base_context
+        .prepend_basic_block(entry_basic_block, "qcs_dispatch");
+    let fallback_basic_block = context
+        .base_context
+        .insert_basic_block_after(dispatch_basic_block, "qcs_fallback");
+
+    context.builder.position_at_end(dispatch_basic_block);
+    let use_qcs: IntValue = build_runtime_call(context, use_qcs_function, &[])
+        .try_as_basic_value()
+        .left()
+        .ok_or_else(|| {
+            eyre!(
+                "expected @{} to return a value",
+                use_qcs_function.get_name().to_string_lossy()
+            )
+        })?
+        .try_into()
+        .map_err(|_| {
+            eyre!(
+                "expected @{} to return an integer",
+                use_qcs_function.get_name().to_string_lossy()
+            )
+        })?;
+    context
+        .builder
+        .build_conditional_branch(use_qcs, entry_basic_block, fallback_basic_block);
+
+    context.builder.position_at_end(fallback_basic_block);
+    let arguments = function
+        .get_param_iter()
+        .map(BasicMetadataValueEnum::from)
+        .collect::<Vec<_>>();
+    let call = context
+        .builder
+        .build_call(original_function, &arguments, "");
+    call.set_call_convention(original_function.get_call_conventions());
+    match call.try_as_basic_value().left() {
+        Some(value) => context.builder.build_return(Some(&value)),
+        None => context.builder.build_return(None),
+    };
+
+    Ok(())
+}
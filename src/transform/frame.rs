@@ -0,0 +1,88 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Lowering of frame intrinsics, which mutate the Quil-T frames on a qubit, for programs which drive
+//! pulse parameters directly, such as those used in hardware characterization.
+use eyre::{eyre, Result};
+use lazy_static::lazy_static;
+use quil_rs::{
+    expression::Expression,
+    instruction::{
+        FrameIdentifier, Instruction, Qubit, SetFrequency, SetPhase, SetScale, ShiftFrequency,
+        ShiftPhase,
+    },
+};
+use regex::Regex;
+
+/// The frame on each qubit which frame intrinsics mutate.
+pub(crate) const FRAME_NAME: &str = "rf";
+
+lazy_static! {
+    /// Matches frame intrinsics, each of which takes a `double` value and a `%Qubit*`, such as
+    /// `__quantum__qis__set_frame_scale__body(double, %Qubit*)`.
+    pub(crate) static ref QIS_FRAME_INTRINSIC_REGEX: Regex = Regex::new(
+        r"^__quantum__qis__(?P<mutation>set|shift)_frame_(?P<attribute>scale|phase|frequency)__body$"
+    )
+    .unwrap();
+}
+
+/// Build the Quil-T frame mutation for a frame intrinsic, given the `mutation` and `attribute` captured
+/// by [`QIS_FRAME_INTRINSIC_REGEX`]:
+///
+/// | Intrinsic                 | Quil-T            |
+/// |---------------------------|-------------------|
+/// | `set_frame_scale`         | `SET-SCALE`       |
+/// | `set_frame_phase`         | `SET-PHASE`       |
+/// | `shift_frame_phase`       | `SHIFT-PHASE`     |
+/// | `set_frame_frequency`     | `SET-FREQUENCY`   |
+/// | `shift_frame_frequency`   | `SHIFT-FREQUENCY` |
+pub(crate) fn build_frame_instruction(
+    mutation: &str,
+    attribute: &str,
+    qubit: u64,
+    value: Expression,
+) -> Result<Instruction> {
+    let frame = FrameIdentifier {
+        name: String::from(FRAME_NAME),
+        qubits: vec![Qubit::Fixed(qubit)],
+    };
+
+    match (mutation, attribute) {
+        ("set", "scale") => Ok(Instruction::SetScale(SetScale {
+            frame,
+            scale: value,
+        })),
+        ("set", "phase") => Ok(Instruction::SetPhase(SetPhase {
+            frame,
+            phase: value,
+        })),
+        ("shift", "phase") => Ok(Instruction::ShiftPhase(ShiftPhase {
+            frame,
+            phase: value,
+        })),
+        ("set", "frequency") => Ok(Instruction::SetFrequency(SetFrequency {
+            frame,
+            frequency: value,
+        })),
+        ("shift", "frequency") => Ok(Instruction::ShiftFrequency(ShiftFrequency {
+            frame,
+            frequency: value,
+        })),
+        _ => Err(eyre!(
+            "Quil-T has no instruction to {} a frame's {}",
+            mutation,
+            attribute
+        )),
+    }
+}
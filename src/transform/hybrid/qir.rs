@@ -0,0 +1,130 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// This module handles "hybrid" programs, in which a classical optimizer loop repeatedly executes a
+// parametric quantum program, updating its parameters between executions based on readout.
+use eyre::Result;
+use inkwell::{
+    basic_block::BasicBlock,
+    values::{FunctionValue, InstructionOpcode},
+};
+use log::debug;
+
+use crate::context::QCSCompilerContext;
+use crate::error::KindError;
+use crate::interop::entrypoint::get_entry_function;
+use crate::transform::calls::transform_call_graph;
+use crate::transform::dump::BlockDump;
+use crate::transform::get_functions_to_transform;
+use crate::transform::shot_count_block::{
+    pattern::ShotCountPatternMatchContext,
+    qir::{build_executable_cache, insert_quil_program},
+};
+
+/// Mutate a context such that each shot count loop is executed using a shared library call, as with
+/// the shot count transform. Where a shot count loop is nested within a parameter update loop, the
+/// executable is created once ahead of that loop and only its parameters are updated on each iteration.
+#[allow(dead_code)]
+pub(crate) fn transpile_module(context: &mut QCSCompilerContext) -> Result<()> {
+    let entrypoint_function = get_entry_function(&context.module).ok_or_else(|| {
+        KindError::MissingEntrypoint(String::from("entrypoint not found in module"))
+    })?;
+    for function in get_functions_to_transform(context, entrypoint_function)? {
+        transform_call_graph(context, function, transpile_basic_block)?;
+    }
+    build_executable_cache(context, entrypoint_function)
+}
+
+pub(crate) fn transpile_basic_block<'ctx>(
+    context: &mut QCSCompilerContext<'ctx>,
+    basic_block: BasicBlock<'ctx>,
+) -> eyre::Result<Vec<FunctionValue<'ctx>>> {
+    let mut dump = BlockDump::begin(context, basic_block);
+    let pattern_context =
+        ShotCountPatternMatchContext::from_basic_block(context, basic_block, true)?;
+    let called_functions = pattern_context.called_functions.clone();
+    if let Some(dump) = &mut dump {
+        dump.write_match(&pattern_context)?;
+    }
+
+    let hoist_executable = is_parameter_update_loop(&pattern_context);
+    if hoist_executable {
+        debug!(
+            "block {} is driven by a parameter update loop; creating its executable once",
+            basic_block.get_name().to_string_lossy()
+        );
+    }
+
+    insert_quil_program(context, pattern_context, basic_block, hoist_executable)?;
+    dump.map_or(Ok(()), BlockDump::finish)?;
+    Ok(called_functions)
+}
+
+/// Whether any of the dynamic parameters of the program are carried by a `phi` instruction, which
+/// indicates that they are updated by an enclosing classical loop between executions.
+fn is_parameter_update_loop(pattern_context: &ShotCountPatternMatchContext) -> bool {
+    pattern_context
+        .get_dynamic_parameters()
+        .iter()
+        .filter_map(|parameter| parameter.as_instruction())
+        .any(|instruction| instruction.get_opcode() == InstructionOpcode::Phi)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::context::context::ContextOptions;
+    use crate::context::target::ExecutionTarget;
+    use crate::interop::instruction::get_called_function_name;
+
+    #[test]
+    fn creates_executable_once_outside_parameter_update_loop() {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let base_context = inkwell::context::Context::create();
+        let data = std::fs::read("tests/fixtures/programs/parameter_update_loop.bc").unwrap();
+        let mut context = QCSCompilerContext::new_from_data(
+            &base_context,
+            &data,
+            ExecutionTarget::Qvm,
+            ContextOptions::default(),
+        )
+        .unwrap();
+        transpile_module(&mut context).expect("transformation failed");
+
+        let function = get_entry_function(&context.module).unwrap();
+        let entry_block = function.get_first_basic_block().unwrap();
+        let executable_from_quil_blocks = function
+            .get_basic_blocks()
+            .into_iter()
+            .flat_map(|block| {
+                let mut instructions = vec![];
+                let mut next_instruction = block.get_first_instruction();
+                while let Some(instruction) = next_instruction {
+                    instructions.push((block, instruction));
+                    next_instruction = instruction.get_next_instruction();
+                }
+                instructions
+            })
+            .filter(|(_, instruction)| {
+                instruction.get_opcode() == InstructionOpcode::Call
+                    && get_called_function_name(*instruction).unwrap().as_deref()
+                        == Some("executable_from_quil")
+            })
+            .map(|(block, _)| block)
+            .collect::<Vec<_>>();
+
+        assert_eq!(executable_from_quil_blocks, vec![entry_block]);
+    }
+}
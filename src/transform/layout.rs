@@ -0,0 +1,149 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Once a module is patched, lays out the blocks of each function it changed in a stable order and
+// gives the blocks it injected predictable names, so that the patched IR of a module changes little
+// when the module does.
+use std::collections::{HashMap, HashSet};
+
+use eyre::{eyre, Result};
+use inkwell::{
+    basic_block::BasicBlock,
+    values::{FunctionValue, InstructionOpcode},
+};
+
+use crate::context::QCSCompilerContext;
+use crate::interop::cfg;
+
+/// A block injected by the transformation, such as the one executing a program.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct InjectedBlock<'ctx> {
+    pub(crate) block: BasicBlock<'ctx>,
+    /// The block of the module for which this block was injected
+    pub(crate) origin: BasicBlock<'ctx>,
+    /// What the block does, as `execution` or `cleanup`
+    pub(crate) role: &'static str,
+}
+
+/// Order the blocks of each function holding an injected block by a depth-first walk of its
+/// control flow from the entry block, in which a block precedes those it branches to (loops aside)
+/// and the targets of each branch are visited in the order written; unreachable blocks follow in
+/// their original order. Then name each injected block `{origin}_{role}`, or just `{role}` if its
+/// origin is unnamed, numbering repeats of a name within a function `.1`, `.2`, and so on in order.
+pub(crate) fn finalize_block_layout(context: &QCSCompilerContext) -> Result<()> {
+    let mut functions: Vec<FunctionValue> = vec![];
+    for injected in &context.injected_blocks {
+        let function = injected
+            .block
+            .get_parent()
+            .ok_or_else(|| eyre!("expected an injected block to belong to a function"))?;
+        if !functions.contains(&function) {
+            functions.push(function);
+        }
+    }
+
+    for function in functions {
+        let order = get_block_order(function);
+        for pair in order.windows(2) {
+            pair[1]
+                .move_after(pair[0])
+                .map_err(|_| eyre!("failed to reorder the blocks of a function"))?;
+        }
+
+        let mut injected = context
+            .injected_blocks
+            .iter()
+            .filter(|injected| injected.block.get_parent() == Some(function))
+            .collect::<Vec<_>>();
+        injected.sort_by_key(|injected| order.iter().position(|block| *block == injected.block));
+        rename_injected_blocks(function, &injected);
+    }
+    Ok(())
+}
+
+/// The blocks of `function` in reverse post-order from its entry block, followed by any which
+/// cannot be reached, in their original order.
+fn get_block_order<'ctx>(function: FunctionValue<'ctx>) -> Vec<BasicBlock<'ctx>> {
+    let blocks = function.get_basic_blocks();
+    let entry = match blocks.first() {
+        Some(entry) => *entry,
+        None => return vec![],
+    };
+
+    // The successors of a block in reverse of the order written, so that popping them visits them
+    // as written. A conditional `br` holds its `false` target before its `true` target, so its
+    // operands are already in reverse.
+    let pending_successors = |block: BasicBlock<'ctx>| {
+        let mut successors = cfg::successors(block);
+        if block.get_terminator().map_or(true, |terminator| {
+            terminator.get_opcode() != InstructionOpcode::Br
+        }) {
+            successors.reverse();
+        }
+        successors
+    };
+
+    let mut visited = HashSet::from([entry]);
+    let mut post_order = vec![];
+    // Each entry is a block and the successors it has yet to visit, in reverse.
+    let mut stack = vec![(entry, pending_successors(entry))];
+    while let Some((block, successors)) = stack.last_mut() {
+        match successors.pop() {
+            Some(successor) => {
+                if visited.insert(successor) {
+                    stack.push((successor, pending_successors(successor)));
+                }
+            }
+            None => {
+                post_order.push(*block);
+                stack.pop();
+            }
+        }
+    }
+
+    post_order.reverse();
+    post_order.extend(blocks.into_iter().filter(|block| !visited.contains(block)));
+    post_order
+}
+
+fn rename_injected_blocks(function: FunctionValue, injected: &[&InjectedBlock]) {
+    // Clear the names first, so that a name being given is not taken by one about to be replaced.
+    for injected in injected {
+        injected.block.set_name("");
+    }
+    let mut taken = function
+        .get_basic_blocks()
+        .iter()
+        .map(|block| block.get_name().to_string_lossy().into_owned())
+        .filter(|name| !name.is_empty())
+        .collect::<HashSet<_>>();
+
+    let mut repeats: HashMap<String, usize> = HashMap::new();
+    for injected in injected {
+        let origin = injected.origin.get_name().to_string_lossy();
+        let base = if origin.is_empty() {
+            String::from(injected.role)
+        } else {
+            format!("{}_{}", origin, injected.role)
+        };
+        let mut name = base.clone();
+        while taken.contains(&name) {
+            let count = repeats.entry(base.clone()).or_insert(0);
+            *count += 1;
+            name = format!("{}.{}", base, count);
+        }
+        injected.block.set_name(&name);
+        taken.insert(name);
+    }
+}
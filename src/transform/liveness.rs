@@ -0,0 +1,127 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Tracks which qubits are left in use by each program extracted from a module, so that a later
+// program reusing one of them may first re-initialize it.
+use inkwell::basic_block::BasicBlock;
+use log::{info, warn};
+use quil_rs::instruction::{Instruction, Reset};
+
+use crate::context::QCSCompilerContext;
+use crate::transform::get_used_qubits;
+
+/// How to handle a qubit used by a program when a program extracted earlier from the same module
+/// also used it, and so may have left it in a state other than `|0>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QubitReuse {
+    /// Leave the program unchanged.
+    Ignore,
+    /// Leave the program unchanged, logging a warning naming the reused qubits.
+    Warn,
+    /// Prepend a `RESET` of each reused qubit to the program, unless it already resets every qubit.
+    Reset,
+}
+
+impl Default for QubitReuse {
+    fn default() -> Self {
+        Self::Ignore
+    }
+}
+
+impl std::str::FromStr for QubitReuse {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ignore" => Ok(Self::Ignore),
+            "warn" => Ok(Self::Warn),
+            "reset" => Ok(Self::Reset),
+            _ => Err(format!(
+                "unknown qubit reuse policy \"{}\"; expected \"ignore\", \"warn\", or \"reset\"",
+                s
+            )),
+        }
+    }
+}
+
+/// Return the qubits used by `program` which any program already emitted into the module also
+/// used, in ascending order.
+pub(crate) fn get_reused_qubits(
+    context: &QCSCompilerContext,
+    program: &quil_rs::Program,
+) -> Vec<u64> {
+    let live_qubits = context
+        .emitted_programs
+        .iter()
+        .flat_map(|emitted| get_used_qubits(&emitted.program))
+        .collect::<std::collections::HashSet<_>>();
+    get_used_qubits(program)
+        .into_iter()
+        .filter(|qubit| live_qubits.contains(qubit))
+        .collect()
+}
+
+/// Apply the configured [`QubitReuse`] to `program`, extracted from `basic_block`, before it is
+/// emitted into the module.
+pub(crate) fn handle_reused_qubits(
+    context: &QCSCompilerContext,
+    program: quil_rs::Program,
+    basic_block: BasicBlock,
+) -> quil_rs::Program {
+    if context.options.qubit_reuse == QubitReuse::Ignore {
+        return program;
+    }
+    let reused_qubits = get_reused_qubits(context, &program);
+    if reused_qubits.is_empty() {
+        return program;
+    }
+
+    let function = basic_block
+        .get_parent()
+        .map(|function| function.get_name().to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let block = basic_block.get_name().to_string_lossy().into_owned();
+    let instructions = program.to_instructions(true);
+    let resets_all_qubits = instructions
+        .iter()
+        .any(|instruction| matches!(instruction, Instruction::Reset(Reset { qubit: None })));
+
+    match context.options.qubit_reuse {
+        QubitReuse::Reset if !resets_all_qubits => {
+            info!(
+                "resetting qubit(s) {:?} of the program in @{}, block %{}, used by an earlier program",
+                reused_qubits, function, block
+            );
+            // Prepend the resets to the program via copy
+            let mut new_program = quil_rs::program::Program::new();
+            for qubit in reused_qubits {
+                new_program.add_instruction(Instruction::Reset(Reset {
+                    qubit: Some(quil_rs::instruction::Qubit::Fixed(qubit)),
+                }));
+            }
+            for instruction in instructions {
+                new_program.add_instruction(instruction);
+            }
+            new_program
+        }
+        QubitReuse::Warn => {
+            warn!(
+                "qubit(s) {:?} of the program in @{}, block %{} were used by an earlier program and are not reset",
+                reused_qubits, function, block
+            );
+            program
+        }
+        _ => program,
+    }
+}
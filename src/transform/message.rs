@@ -0,0 +1,151 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Handling of `__quantum__rt__message(%String*)`, to which Q#'s `Message()` lowers, when it is
+// called among the quantum instructions of a program.
+use either::Either;
+use eyre::{eyre, Result};
+use inkwell::values::{AnyValueEnum, BasicValueEnum, InstructionOpcode, InstructionValue};
+use quil_rs::instruction::{Instruction, Pragma};
+
+use crate::context::QCSCompilerContext;
+use crate::interop::instruction::{
+    get_called_function_name, runtime_string_to_string, STRING_CREATE_FUNCTION_NAME,
+};
+
+const MESSAGE_FUNCTION_NAME: &str = "__quantum__rt__message";
+const STRING_REFERENCE_COUNT_FUNCTION_NAME: &str = "__quantum__rt__string_update_reference_count";
+
+/// What to do with a call to `__quantum__rt__message` among the quantum instructions of a program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageHandling {
+    /// Leave the call in the module, where the runtime prints the message. Since the quantum
+    /// instructions of a block are executed together, the message is printed before the program
+    /// runs rather than between the instructions around it.
+    Retain,
+    /// Remove the call, writing its message into the Quil program where it was called as
+    /// `PRAGMA MESSAGE "..."`. The message must be a constant string.
+    Pragma,
+    /// Remove the call.
+    Remove,
+}
+
+impl Default for MessageHandling {
+    fn default() -> Self {
+        Self::Retain
+    }
+}
+
+impl std::str::FromStr for MessageHandling {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "retain" => Ok(Self::Retain),
+            "pragma" => Ok(Self::Pragma),
+            "remove" => Ok(Self::Remove),
+            _ => Err(format!(
+                "unknown message handling \"{}\"; expected \"retain\", \"pragma\", or \"remove\"",
+                s
+            )),
+        }
+    }
+}
+
+/// If `instruction` calls `__quantum__rt__message`, handle it as configured, adding any `PRAGMA` to
+/// `quil_program` and the call to `instructions_to_remove` if it is not retained, and return
+/// whether it did. The creation of a string used only by messages, and the updates of its reference
+/// count, are handled along with them.
+pub(crate) fn handle_message<'ctx>(
+    context: &QCSCompilerContext<'ctx>,
+    instruction: InstructionValue<'ctx>,
+    quil_program: &mut quil_rs::Program,
+    instructions_to_remove: &mut Vec<InstructionValue<'ctx>>,
+) -> Result<bool> {
+    let is_message = calls(instruction, MESSAGE_FUNCTION_NAME);
+    let is_message_string = creates_message_string(instruction)
+        || (calls(instruction, STRING_REFERENCE_COUNT_FUNCTION_NAME)
+            && get_string_operand(instruction).map_or(false, creates_message_string));
+    if !is_message && !is_message_string {
+        return Ok(false);
+    }
+
+    match context.options.message_handling {
+        MessageHandling::Retain => {}
+        MessageHandling::Pragma if is_message => {
+            let message = match instruction.get_operand(0) {
+                Some(Either::Left(BasicValueEnum::PointerValue(string))) => {
+                    runtime_string_to_string(context, string)
+                }
+                _ => None,
+            };
+            let message = message.ok_or_else(|| {
+                eyre!(
+                    "expected a constant string given to {} in {:?}",
+                    MESSAGE_FUNCTION_NAME,
+                    instruction
+                )
+            })?;
+            quil_program.add_instruction(Instruction::Pragma(Pragma {
+                name: String::from("MESSAGE"),
+                arguments: vec![],
+                data: Some(message),
+            }));
+            instructions_to_remove.push(instruction);
+        }
+        MessageHandling::Pragma | MessageHandling::Remove => {
+            instructions_to_remove.push(instruction);
+        }
+    }
+    Ok(true)
+}
+
+/// Whether `instruction` calls the function named `function_name`.
+fn calls(instruction: InstructionValue, function_name: &str) -> bool {
+    instruction.get_opcode() == InstructionOpcode::Call
+        && get_called_function_name(instruction)
+            .ok()
+            .flatten()
+            .map_or(false, |name| name == function_name)
+}
+
+/// The instruction computing the `%String*` given as the first argument of `instruction`, if any.
+fn get_string_operand(instruction: InstructionValue) -> Option<InstructionValue> {
+    match instruction.get_operand(0)? {
+        Either::Left(BasicValueEnum::PointerValue(string)) => string.as_instruction(),
+        _ => None,
+    }
+}
+
+/// Whether `instruction` creates a string with `__quantum__rt__string_create` which is used only
+/// by messages and updates of its reference count.
+fn creates_message_string(instruction: InstructionValue) -> bool {
+    if !calls(instruction, STRING_CREATE_FUNCTION_NAME) {
+        return false;
+    }
+    let mut next_use = instruction.get_first_use();
+    let mut used_by_message = false;
+    while let Some(value_use) = next_use {
+        match value_use.get_user() {
+            AnyValueEnum::InstructionValue(user) if calls(user, MESSAGE_FUNCTION_NAME) => {
+                used_by_message = true;
+            }
+            AnyValueEnum::InstructionValue(user)
+                if calls(user, STRING_REFERENCE_COUNT_FUNCTION_NAME) => {}
+            _ => return false,
+        }
+        next_use = value_use.get_next_use();
+    }
+    used_by_message
+}
@@ -12,7 +12,269 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use eyre::{eyre, Result};
+use inkwell::values::{AnyValue, InstructionValue};
+
+#[cfg(feature = "transform")]
+use inkwell::values::FunctionValue;
+#[cfg(feature = "transform")]
+use log::warn;
+#[cfg(feature = "transform")]
+use quil_rs::instruction::{Gate, Instruction, Measurement, Qubit, Reset};
+
+#[cfg(feature = "transform")]
+use crate::{context::context::EmittedProgram, ExecutionTarget};
+use crate::{
+    context::QCSCompilerContext, error::KindError, interop::instruction::operand_to_integer,
+    RecordedOutput,
+};
+
+pub(crate) use crate::quil_builder::PARAMETER_MEMORY_REGION_NAME;
+
+#[cfg(feature = "transform")]
+pub(crate) mod budget;
+#[cfg(feature = "transform")]
+pub(crate) mod calls;
+pub(crate) mod classical;
+pub(crate) mod compact;
+pub(crate) mod constant;
+pub(crate) mod custom;
+#[cfg(feature = "transform")]
+pub(crate) mod dump;
+#[cfg(feature = "transform")]
+pub(crate) mod fallback;
+pub(crate) mod frame;
+#[cfg(feature = "transform")]
+pub(crate) mod hybrid;
+#[cfg(feature = "transform")]
+pub(crate) mod layout;
+#[cfg(feature = "transform")]
+pub(crate) mod liveness;
+pub(crate) mod message;
+pub(crate) mod ordering;
+pub(crate) mod pauli;
+pub(crate) mod peephole;
+#[cfg(feature = "transform")]
+pub(crate) mod postprocess;
+pub(crate) mod recording;
 pub(crate) mod shot_count_block;
 pub(crate) mod unitary;
+pub(crate) mod unroll;
+
+/// Return the functions from which to start transformation: the entrypoint, from which calls are
+/// followed as they are matched, or else each function named in the allowlist, in the order given.
+/// Calls are not followed from the functions of an allowlist, so that unlisted functions are left
+/// untouched.
+#[cfg(feature = "transform")]
+pub(crate) fn get_functions_to_transform<'ctx>(
+    context: &QCSCompilerContext<'ctx>,
+    entrypoint: FunctionValue<'ctx>,
+) -> Result<Vec<FunctionValue<'ctx>>> {
+    match &context.options.function_allowlist {
+        None => Ok(vec![entrypoint]),
+        Some(allowlist) => allowlist
+            .iter()
+            .map(|name| {
+                context
+                    .module
+                    .get_function(name)
+                    .filter(|function| function.count_basic_blocks() > 0)
+                    .ok_or_else(|| {
+                        eyre!(
+                            "function @{} was selected for transformation, but is not defined in the module",
+                            name
+                        )
+                    })
+            })
+            .collect(),
+    }
+}
+
+/// Prepend the Quil-T calibrations carried by the input module, if any, to a generated program.
+pub(crate) fn prepend_calibrations(
+    context: &QCSCompilerContext,
+    program: quil_rs::Program,
+) -> quil_rs::Program {
+    match &context.calibrations {
+        Some(calibrations) => {
+            // Prepend the calibrations to the program via copy
+            let mut new_program = calibrations.clone();
+            for instruction in program.to_instructions(true) {
+                new_program.add_instruction(instruction);
+            }
+            new_program
+        }
+        None => program,
+    }
+}
+
+/// Record the integer passed to an `__quantum__rt__int_record_output` call as computed by the host,
+/// since it is produced by classical instructions in the module rather than read out from the QPU.
+/// Its computation is reconstructed from the readout of the `readout_instructions` if possible.
+pub(crate) fn get_host_computed_integer<'ctx>(
+    context: &QCSCompilerContext<'ctx>,
+    instruction: InstructionValue<'ctx>,
+    readout_instructions: &[(u64, InstructionValue<'ctx>)],
+    tag: Option<String>,
+) -> Result<RecordedOutput> {
+    let (value, expression) =
+        get_host_computed_value(context, instruction, readout_instructions, "an integer")?;
+    Ok(RecordedOutput::HostComputedInteger {
+        value,
+        expression,
+        tag,
+    })
+}
+
+/// As [`get_host_computed_integer`], for the boolean passed to `__quantum__rt__bool_record_output`.
+pub(crate) fn get_host_computed_bool<'ctx>(
+    context: &QCSCompilerContext<'ctx>,
+    instruction: InstructionValue<'ctx>,
+    readout_instructions: &[(u64, InstructionValue<'ctx>)],
+    tag: Option<String>,
+) -> Result<RecordedOutput> {
+    let (value, expression) =
+        get_host_computed_value(context, instruction, readout_instructions, "a boolean")?;
+    Ok(RecordedOutput::HostComputedBool {
+        value,
+        expression,
+        tag,
+    })
+}
+
+fn get_host_computed_value<'ctx>(
+    context: &QCSCompilerContext<'ctx>,
+    instruction: InstructionValue<'ctx>,
+    readout_instructions: &[(u64, InstructionValue<'ctx>)],
+    description: &str,
+) -> Result<(String, Option<crate::expression::ClassicalExpression>)> {
+    let value = instruction
+        .get_operand(0)
+        .and_then(operand_to_integer)
+        .ok_or_else(|| eyre!("expected {} to record in {:?}", description, instruction))?;
+    Ok((
+        value.print_to_string().to_string().trim().to_owned(),
+        classical::reconstruct_expression(context, value, readout_instructions),
+    ))
+}
+
+/// Render `program` as Quil, preceding each instruction translated from QIR with a comment naming
+/// its source instruction. `trace` holds those sources in order for the final instructions of the
+/// program, since any others were prepended after translation.
+pub(crate) fn format_traced_program(program: &quil_rs::Program, trace: &[String]) -> String {
+    let instructions = program.to_instructions(true);
+    let untraced_length = instructions.len().saturating_sub(trace.len());
+    let mut output = String::new();
+    for (index, instruction) in instructions.iter().enumerate() {
+        if let Some(source) = index
+            .checked_sub(untraced_length)
+            .and_then(|index| trace.get(index))
+        {
+            output.push_str(&format!("# from: {}\n", source));
+        }
+        output.push_str(&format!("{}\n", instruction));
+    }
+    output
+}
+
+/// Fail if `program` holds more instructions than `limits` allows.
+pub(crate) fn validate_quil_program_length(
+    program: &quil_rs::Program,
+    limits: &crate::Limits,
+) -> Result<()> {
+    match limits.max_quil_instructions {
+        Some(max_instructions) if program.to_instructions(false).len() > max_instructions => {
+            Err(KindError::LimitExceeded(format!(
+                "Quil program contains more than the limit of {} instructions",
+                max_instructions
+            ))
+            .into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The distinct fixed qubit indices used by the gates, measurements, and resets of `program`, in
+/// ascending order.
+#[cfg(feature = "transform")]
+pub(crate) fn get_used_qubits(program: &quil_rs::Program) -> Vec<u64> {
+    let mut qubits = program
+        .to_instructions(false)
+        .into_iter()
+        .flat_map(|instruction| match instruction {
+            Instruction::Gate(Gate { qubits, .. }) => qubits,
+            Instruction::Measurement(Measurement { qubit, .. })
+            | Instruction::Reset(Reset { qubit: Some(qubit) }) => vec![qubit],
+            _ => vec![],
+        })
+        .filter_map(|qubit| match qubit {
+            Qubit::Fixed(index) => Some(index),
+            Qubit::Variable(_) => None,
+        })
+        .collect::<Vec<_>>();
+    qubits.sort_unstable();
+    qubits.dedup();
+    qubits
+}
+
+/// Compare the greatest qubit index used by any of `programs` with the number of qubits on the
+/// target, given as `qubit_count` or else as known for the target, so that a program too large for
+/// its target is reported when it is patched rather than when it is executed. A program which is
+/// too large fails if `deny` is set, and is otherwise logged as a warning. Nothing is checked if the
+/// size of the target is not known.
+#[cfg(feature = "transform")]
+pub(crate) fn check_target_qubit_count(
+    programs: &[EmittedProgram],
+    target: &ExecutionTarget,
+    qubit_count: Option<u64>,
+    deny: bool,
+) -> Result<()> {
+    let qubit_count = match qubit_count.or_else(|| target.qubit_count()) {
+        Some(qubit_count) => qubit_count,
+        None => return Ok(()),
+    };
+    let max_qubit = programs
+        .iter()
+        .flat_map(|emitted| get_used_qubits(&emitted.program))
+        .max();
+
+    match max_qubit {
+        Some(max_qubit) if max_qubit >= qubit_count => {
+            let message = format!(
+                "program uses qubit {}, but {} only has qubits 0 to {}",
+                max_qubit,
+                match target {
+                    ExecutionTarget::Qpu(id) => format!("QPU {}", id),
+                    ExecutionTarget::Qvm => String::from("the QVM"),
+                },
+                qubit_count.saturating_sub(1)
+            );
+            if deny {
+                Err(eyre!(message))
+            } else {
+                warn!("{}", message);
+                Ok(())
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Describe how pattern matching treated `instruction`, as one entry of an explanation requested
+/// through [`crate::explain_qir_to_quil`].
+pub(crate) fn explain_instruction(instruction: InstructionValue, outcome: &str) -> String {
+    format!(
+        "{}\n    => {}",
+        instruction.print_to_string().to_string().trim(),
+        outcome
+    )
+}
 
-pub(crate) const PARAMETER_MEMORY_REGION_NAME: &str = "__qir_param";
+/// Round `value` to `precision` decimal places, if given, so that it is written to Quil without the
+/// noise of its binary representation.
+pub(crate) fn round_float(value: f64, precision: Option<usize>) -> f64 {
+    match precision {
+        Some(precision) => format!("{:.*}", precision, value).parse().unwrap_or(value),
+        None => value,
+    }
+}
@@ -0,0 +1,238 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Checks that the programs extracted from a module measure their qubits only after applying every
+// gate, as the base profile requires, and moves measurements to the end of a program which does
+// not when nothing depends on their order.
+use eyre::{eyre, Result};
+use log::warn;
+use quil_rs::instruction::{Instruction, Measurement, Qubit, Reset};
+
+/// How to handle an extracted program in which a gate follows a measurement or reset, which the
+/// base profile forbids.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MeasurementOrder {
+    /// Leave the program unchanged.
+    Ignore,
+    /// Leave the program unchanged, logging a warning naming the first gate out of order.
+    Warn,
+    /// Fail, naming the first gate out of order.
+    Deny,
+    /// Move each measurement and reset after the gates which follow it, keeping their order among
+    /// themselves, and fail if one of those gates acts on its qubit or another instruction stands
+    /// in the way.
+    Reorder,
+}
+
+impl Default for MeasurementOrder {
+    fn default() -> Self {
+        Self::Ignore
+    }
+}
+
+impl std::str::FromStr for MeasurementOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ignore" => Ok(Self::Ignore),
+            "warn" => Ok(Self::Warn),
+            "deny" => Ok(Self::Deny),
+            "reorder" => Ok(Self::Reorder),
+            _ => Err(format!(
+                "unknown measurement order policy \"{}\"; expected \"ignore\", \"warn\", \"deny\", or \"reorder\"",
+                s
+            )),
+        }
+    }
+}
+
+/// Apply `policy` to the body of `program`, whose instructions were translated from those of
+/// `trace`, returning the program along with its trace, reordered to match.
+pub(crate) fn apply_measurement_order(
+    policy: MeasurementOrder,
+    program: quil_rs::Program,
+    trace: Vec<String>,
+) -> Result<(quil_rs::Program, Vec<String>)> {
+    if policy == MeasurementOrder::Ignore {
+        return Ok((program, trace));
+    }
+    let body = program.to_instructions(false);
+    let first_irreversible = match body.iter().position(is_irreversible) {
+        Some(index) => index,
+        None => return Ok((program, trace)),
+    };
+    let out_of_order = match body[first_irreversible..]
+        .iter()
+        .find(|instruction| matches!(instruction, Instruction::Gate(_)))
+    {
+        Some(gate) => gate,
+        None => return Ok((program, trace)),
+    };
+
+    match policy {
+        MeasurementOrder::Warn => {
+            warn!(
+                "gate `{}` follows `{}`; the base profile requires measurements after every gate",
+                out_of_order, body[first_irreversible]
+            );
+            Ok((program, trace))
+        }
+        MeasurementOrder::Deny => Err(eyre!(
+            "gate `{}` follows `{}`; the base profile requires measurements after every gate",
+            out_of_order,
+            body[first_irreversible]
+        )),
+        _ => reorder(program, body, trace),
+    }
+}
+
+/// Move every measurement and reset in `body` after the last gate, failing if that would change
+/// what the program computes.
+fn reorder(
+    program: quil_rs::Program,
+    body: Vec<Instruction>,
+    trace: Vec<String>,
+) -> Result<(quil_rs::Program, Vec<String>)> {
+    let last_gate = body
+        .iter()
+        .rposition(|instruction| matches!(instruction, Instruction::Gate(_)))
+        .unwrap_or_default();
+
+    // Each measurement or reset before the last gate is moved past every instruction which follows
+    // it up to that gate, other than the measurements and resets, which keep their order.
+    for (index, instruction) in body.iter().enumerate().take(last_gate) {
+        if !is_irreversible(instruction) {
+            continue;
+        }
+        let measured = get_irreversible_qubit(instruction);
+        if let Some(blocker) = body[index + 1..=last_gate]
+            .iter()
+            .find(|later| match later {
+                Instruction::Gate(gate) => {
+                    measured.map_or(true, |measured| gate.qubits.contains(measured))
+                }
+                later => !is_irreversible(later),
+            })
+        {
+            return Err(eyre!(
+                "cannot move `{}` after `{}`, on which the program's result depends",
+                instruction,
+                blocker
+            ));
+        }
+    }
+
+    let instructions = program.to_instructions(true);
+    let (headers, _) = instructions.split_at(instructions.len() - body.len());
+    let untraced_length = body.len().saturating_sub(trace.len());
+    let get_trace = |index: usize| {
+        index
+            .checked_sub(untraced_length)
+            .and_then(|index| trace.get(index))
+            .cloned()
+    };
+
+    let mut order = (0..body.len())
+        .filter(|index| *index > last_gate || !is_irreversible(&body[*index]))
+        .collect::<Vec<_>>();
+    let moved = (0..last_gate).filter(|index| is_irreversible(&body[*index]));
+    // The moved instructions follow the last gate, and precede any which already followed it
+    let position = order
+        .iter()
+        .position(|index| *index > last_gate)
+        .unwrap_or(order.len());
+    order.splice(position..position, moved);
+
+    let mut output = quil_rs::Program::new();
+    for instruction in headers {
+        output.add_instruction(instruction.clone());
+    }
+    let mut reordered_trace = vec![];
+    for index in order {
+        output.add_instruction(body[index].clone());
+        reordered_trace.extend(get_trace(index));
+    }
+    Ok((output, reordered_trace))
+}
+
+/// Whether `instruction` is a measurement or reset, after which the base profile permits no gate.
+fn is_irreversible(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Measurement(_) | Instruction::Reset(_)
+    )
+}
+
+/// The qubit measured or reset by `instruction`, or `None` if it resets every qubit.
+fn get_irreversible_qubit(instruction: &Instruction) -> Option<&Qubit> {
+    match instruction {
+        Instruction::Measurement(Measurement { qubit, .. })
+        | Instruction::Reset(Reset { qubit: Some(qubit) }) => Some(qubit),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::{apply_measurement_order, MeasurementOrder};
+
+    fn apply(policy: MeasurementOrder, quil: &str) -> eyre::Result<(String, Vec<String>)> {
+        let program = quil_rs::Program::from_str(quil).unwrap();
+        let trace = program
+            .to_instructions(false)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        apply_measurement_order(policy, program, trace)
+            .map(|(program, trace)| (program.to_string(true), trace))
+    }
+
+    #[test]
+    fn moves_measurements_after_gates_on_other_qubits() {
+        let quil = "DECLARE ro BIT[2]\nH 0\nMEASURE 0 ro[0]\nX 1\nH 1\nMEASURE 1 ro[1]\n";
+        let (program, trace) = apply(MeasurementOrder::Reorder, quil).unwrap();
+        assert_eq!(
+            program,
+            "DECLARE ro BIT[2]\nH 0\nX 1\nH 1\nMEASURE 0 ro[0]\nMEASURE 1 ro[1]\n"
+        );
+        assert_eq!(
+            trace,
+            ["H 0", "X 1", "H 1", "MEASURE 0 ro[0]", "MEASURE 1 ro[1]"]
+        );
+
+        assert_eq!(apply(MeasurementOrder::Warn, quil).unwrap().0, quil);
+        assert!(apply(MeasurementOrder::Deny, quil)
+            .unwrap_err()
+            .to_string()
+            .contains("gate `X 1` follows `MEASURE 0 ro[0]`"));
+    }
+
+    #[test]
+    fn refuses_to_reorder_dependent_instructions() {
+        let error = apply(
+            MeasurementOrder::Reorder,
+            "DECLARE ro BIT[1]\nMEASURE 0 ro[0]\nH 0\n",
+        )
+        .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("cannot move `MEASURE 0 ro[0]` after `H 0`"));
+
+        assert!(apply(MeasurementOrder::Reorder, "RESET\nH 0\n").is_err());
+        assert!(apply(MeasurementOrder::Reorder, "RESET 1\nPRAGMA BARRIER\nH 0\n").is_err());
+    }
+}
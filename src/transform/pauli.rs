@@ -0,0 +1,268 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Lowering of intrinsics which act on a Pauli string, such as joint measurement and Pauli
+//! exponentials, to the basis changes and parity computations which implement them using Quil's
+//! standard gates.
+use eyre::{eyre, Result};
+use quil_rs::{
+    expression::Expression,
+    instruction::{Gate, GateModifier, Instruction, Measurement, MemoryReference, Qubit},
+};
+
+use crate::interop::instruction::ArrayElement;
+
+/// A single-qubit Pauli operator, as encoded in a QIR `%Pauli` (`i2`) value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Pauli {
+    I,
+    X,
+    Z,
+    Y,
+}
+
+impl Pauli {
+    /// Decode a `%Pauli` value, in which `I`, `X`, `Z`, and `Y` are `0` through `3`.
+    pub(crate) fn from_qir(value: u64) -> Result<Self> {
+        match value {
+            0 => Ok(Self::I),
+            1 => Ok(Self::X),
+            2 => Ok(Self::Z),
+            3 => Ok(Self::Y),
+            other => Err(eyre!("{} is not a valid Pauli value", other)),
+        }
+    }
+}
+
+/// Pair each Pauli of a Pauli string with the qubit it acts on, dropping identities, given the
+/// elements of the `%Array*` of Paulis and the `%Array*` of qubits passed to an intrinsic.
+pub(crate) fn get_pauli_terms(
+    paulis: &[ArrayElement],
+    qubits: &[ArrayElement],
+) -> Result<Vec<(Pauli, u64)>> {
+    if paulis.len() != qubits.len() {
+        return Err(eyre!(
+            "expected as many qubits as Paulis; got {} Paulis and {} qubits",
+            paulis.len(),
+            qubits.len()
+        ));
+    }
+
+    paulis
+        .iter()
+        .zip(qubits)
+        .filter_map(|(pauli, qubit)| match (pauli, qubit) {
+            (ArrayElement::Integer(pauli), ArrayElement::Qubit(qubit)) => {
+                match Pauli::from_qir(*pauli) {
+                    Ok(Pauli::I) => None,
+                    Ok(pauli) => Some(Ok((pauli, *qubit))),
+                    Err(error) => Some(Err(error)),
+                }
+            }
+            _ => Some(Err(eyre!(
+                "expected an array of Paulis and an array of qubits; got {:?} and {:?}",
+                pauli,
+                qubit
+            ))),
+        })
+        .collect()
+}
+
+fn gate(name: &str, qubits: &[u64], dagger: bool) -> Instruction {
+    Instruction::Gate(Gate {
+        name: name.to_owned(),
+        parameters: vec![],
+        qubits: qubits.iter().copied().map(Qubit::Fixed).collect(),
+        modifiers: if dagger {
+            vec![GateModifier::Dagger]
+        } else {
+            vec![]
+        },
+    })
+}
+
+/// The gates rotating each term's qubit so that its Pauli is measured in the computational basis.
+fn basis_change(terms: &[(Pauli, u64)]) -> Vec<Instruction> {
+    terms
+        .iter()
+        .flat_map(|(pauli, qubit)| match pauli {
+            Pauli::X => vec![gate("H", &[*qubit], false)],
+            Pauli::Y => vec![gate("S", &[*qubit], true), gate("H", &[*qubit], false)],
+            Pauli::I | Pauli::Z => vec![],
+        })
+        .collect()
+}
+
+/// The inverse of [`basis_change`].
+fn undo_basis_change(terms: &[(Pauli, u64)]) -> Vec<Instruction> {
+    terms
+        .iter()
+        .rev()
+        .flat_map(|(pauli, qubit)| match pauli {
+            Pauli::X => vec![gate("H", &[*qubit], false)],
+            Pauli::Y => vec![gate("H", &[*qubit], false), gate("S", &[*qubit], false)],
+            Pauli::I | Pauli::Z => vec![],
+        })
+        .collect()
+}
+
+/// The CNOTs accumulating the parity of every term's qubit onto the qubit of the last term.
+fn parity_ladder(terms: &[(Pauli, u64)]) -> Vec<Instruction> {
+    terms
+        .windows(2)
+        .map(|pair| gate("CNOT", &[pair[0].1, pair[1].1], false))
+        .collect()
+}
+
+/// Surround the instructions which `build` returns for the qubit of the last of `terms` with the
+/// basis changes and parity computation which make that qubit carry the joint Pauli observable
+/// `terms`. Returns `None` if `terms` is empty.
+fn conjugate_by_parity(
+    terms: &[(Pauli, u64)],
+    build: impl FnOnce(u64) -> Vec<Instruction>,
+) -> Option<Vec<Instruction>> {
+    let parity_qubit = terms.last().map(|(_, qubit)| *qubit)?;
+
+    let ladder = parity_ladder(terms);
+    let mut instructions = basis_change(terms);
+    instructions.extend(ladder.iter().cloned());
+    instructions.extend(build(parity_qubit));
+    instructions.extend(ladder.into_iter().rev());
+    instructions.extend(undo_basis_change(terms));
+    Some(instructions)
+}
+
+/// Build the Quil measuring the joint Pauli observable `terms` into `target`: each qubit is rotated
+/// into the computational basis, the parity of all of them is accumulated onto the last, which is
+/// measured, and then the parity computation and basis changes are undone. When `flip` is set the
+/// measured qubit is flipped around the measurement, as for `mz` with flipped readout.
+pub(crate) fn build_joint_measurement(
+    terms: &[(Pauli, u64)],
+    target: MemoryReference,
+    flip: bool,
+) -> Result<Vec<Instruction>> {
+    conjugate_by_parity(terms, |measured_qubit| {
+        let mut instructions = vec![];
+        if flip {
+            instructions.push(gate("X", &[measured_qubit], false));
+        }
+        instructions.push(Instruction::Measurement(Measurement {
+            qubit: Qubit::Fixed(measured_qubit),
+            target: Some(target),
+        }));
+        if flip {
+            instructions.push(gate("X", &[measured_qubit], false));
+        }
+        instructions
+    })
+    .ok_or_else(|| eyre!("cannot measure a Pauli string made only of identities"))
+}
+
+/// Build the Quil applying `exp(iθP)` for the Pauli string `terms`, where `angle` is the `RZ` angle
+/// `-2θ` which implements it on the parity qubit. A string made only of identities contributes
+/// a global phase, so no instructions are needed.
+pub(crate) fn build_pauli_exponential(
+    terms: &[(Pauli, u64)],
+    angle: Expression,
+) -> Vec<Instruction> {
+    conjugate_by_parity(terms, |parity_qubit| {
+        vec![Instruction::Gate(Gate {
+            name: String::from("RZ"),
+            parameters: vec![angle],
+            qubits: vec![Qubit::Fixed(parity_qubit)],
+            modifiers: vec![],
+        })]
+    })
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn to_quil(instructions: &[Instruction]) -> String {
+        instructions
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn joint_measurement_changes_basis_and_computes_parity() {
+        let terms = get_pauli_terms(
+            &[
+                ArrayElement::Integer(1),
+                ArrayElement::Integer(0),
+                ArrayElement::Integer(3),
+            ],
+            &[
+                ArrayElement::Qubit(0),
+                ArrayElement::Qubit(1),
+                ArrayElement::Qubit(2),
+            ],
+        )
+        .unwrap();
+        let instructions = build_joint_measurement(
+            &terms,
+            MemoryReference {
+                name: String::from("ro"),
+                index: 0,
+            },
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(
+            to_quil(&instructions),
+            "H 0\nDAGGER S 2\nH 2\nCNOT 0 2\nMEASURE 2 ro[0]\nCNOT 0 2\nH 2\nS 2\nH 0"
+        );
+    }
+
+    #[test]
+    fn rejects_mismatched_pauli_strings() {
+        assert!(get_pauli_terms(&[ArrayElement::Integer(1)], &[]).is_err());
+        assert!(get_pauli_terms(&[ArrayElement::Integer(4)], &[ArrayElement::Qubit(0)]).is_err());
+        let identity =
+            get_pauli_terms(&[ArrayElement::Integer(0)], &[ArrayElement::Qubit(0)]).unwrap();
+        assert!(build_joint_measurement(
+            &identity,
+            MemoryReference {
+                name: String::from("ro"),
+                index: 0,
+            },
+            false,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn pauli_exponential_rotates_parity_qubit() {
+        let terms = get_pauli_terms(
+            &[ArrayElement::Integer(2), ArrayElement::Integer(1)],
+            &[ArrayElement::Qubit(0), ArrayElement::Qubit(1)],
+        )
+        .unwrap();
+        let instructions = build_pauli_exponential(&terms, Expression::Number(0.5.into()));
+
+        assert_eq!(
+            to_quil(&instructions),
+            "H 1\nCNOT 0 1\nRZ(0.5) 1\nCNOT 0 1\nH 1"
+        );
+
+        let identity =
+            get_pauli_terms(&[ArrayElement::Integer(0)], &[ArrayElement::Qubit(0)]).unwrap();
+        assert!(build_pauli_exponential(&identity, Expression::Number(0.5.into())).is_empty());
+    }
+}
@@ -0,0 +1,341 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Peephole optimization of the Quil programs extracted from a module, removing gates which undo
+// one another and folding runs of rotations into one.
+use std::collections::HashMap;
+use std::f64::consts::{PI, TAU};
+
+use quil_rs::{
+    expression::{Expression, InfixOperator, PrefixOperator},
+    instruction::{Gate, GateModifier, Instruction, Measurement, Qubit, Reset},
+};
+
+use crate::context::context::ContextOptions;
+use crate::transform::constant::build_constant_expression;
+
+/// Gates which are their own inverse.
+const SELF_INVERSE_GATES: &[&str] = &["H", "X", "Y", "Z", "CNOT", "CZ", "SWAP"];
+
+/// Gates which undo one another when one of the two is applied with `DAGGER`.
+const DAGGER_INVERSE_GATES: &[&str] = &["S", "T"];
+
+/// Gates whose operands may be given in either order.
+const SYMMETRIC_GATES: &[&str] = &["CZ", "SWAP"];
+
+/// Single-qubit rotations, each taking one angle, such that consecutive rotations on a qubit
+/// compose by adding their angles.
+const ROTATION_GATES: &[&str] = &["RX", "RY", "RZ", "PHASE"];
+
+/// How far a rotation angle may be from a multiple of `2*pi` and still be dropped as the identity.
+const IDENTITY_TOLERANCE: f64 = 1e-9;
+
+/// Optimize the body of `program`, returning it along with the trace of the instructions which
+/// remain. Adjacent gates which are inverses of one another, such as `H 0` followed by `H 0`, or
+/// `S 0` followed by `DAGGER S 0`, are removed; consecutive rotations of the same kind on a qubit
+/// are merged by summing their constant angles; and rotations by a multiple of `2*pi` are dropped.
+///
+/// Gates are only considered adjacent when no other instruction acts on any of their qubits in
+/// between. A measurement or reset of a qubit separates the gates either side of it on that qubit,
+/// and any other instruction which is not a gate separates every gate before it from every gate
+/// after it. Gates with modifiers other than a lone `DAGGER`, or with parameters which are not
+/// constant, are left as they are.
+pub(crate) fn optimize_program(
+    options: &ContextOptions,
+    program: &quil_rs::Program,
+    trace: &[String],
+) -> (quil_rs::Program, Vec<String>) {
+    let instructions = program.to_instructions(true);
+    let body = program.to_instructions(false);
+    let (headers, _) = instructions.split_at(instructions.len() - body.len());
+    let untraced_length = body.len().saturating_sub(trace.len());
+
+    let mut optimized: Vec<Option<Instruction>> = Vec::with_capacity(body.len());
+    // For each qubit, the surviving instructions acting on it since the last barrier, in order
+    let mut stacks: HashMap<u64, Vec<usize>> = HashMap::new();
+
+    for instruction in body {
+        let index = optimized.len();
+        match &instruction {
+            Instruction::Gate(gate) => {
+                let qubits = match get_fixed_qubits(gate) {
+                    Some(qubits) => qubits,
+                    None => {
+                        stacks.clear();
+                        optimized.push(Some(instruction));
+                        continue;
+                    }
+                };
+                if is_identity_rotation(gate) {
+                    optimized.push(None);
+                    continue;
+                }
+
+                let previous = qubits
+                    .iter()
+                    .map(|qubit| stacks.get(qubit).and_then(|stack| stack.last().copied()))
+                    .collect::<Option<Vec<_>>>()
+                    .filter(|previous| previous.windows(2).all(|pair| pair[0] == pair[1]))
+                    .and_then(|previous| previous.first().copied());
+                let previous_gate = previous.and_then(|previous| match &optimized[previous] {
+                    Some(Instruction::Gate(previous_gate)) => Some((previous, previous_gate)),
+                    _ => None,
+                });
+
+                match previous_gate {
+                    Some((previous, previous_gate)) if are_inverses(previous_gate, gate) => {
+                        optimized[previous] = None;
+                        optimized.push(None);
+                        pop_qubits(&mut stacks, &qubits);
+                    }
+                    Some((previous, previous_gate)) => {
+                        match merge_rotations(options, previous_gate, gate) {
+                            Some(merged) if is_identity_rotation(&merged) => {
+                                optimized[previous] = None;
+                                optimized.push(None);
+                                pop_qubits(&mut stacks, &qubits);
+                            }
+                            Some(merged) => {
+                                optimized[previous] = Some(Instruction::Gate(merged));
+                                optimized.push(None);
+                            }
+                            None => {
+                                push_qubits(&mut stacks, &qubits, index);
+                                optimized.push(Some(instruction));
+                            }
+                        }
+                    }
+                    None => {
+                        push_qubits(&mut stacks, &qubits, index);
+                        optimized.push(Some(instruction));
+                    }
+                }
+            }
+            Instruction::Measurement(Measurement {
+                qubit: Qubit::Fixed(qubit),
+                ..
+            })
+            | Instruction::Reset(Reset {
+                qubit: Some(Qubit::Fixed(qubit)),
+            }) => {
+                push_qubits(&mut stacks, &[*qubit], index);
+                optimized.push(Some(instruction));
+            }
+            _ => {
+                stacks.clear();
+                optimized.push(Some(instruction));
+            }
+        }
+    }
+
+    let mut output = quil_rs::Program::new();
+    for instruction in headers {
+        output.add_instruction(instruction.clone());
+    }
+    let mut optimized_trace = vec![];
+    for (index, instruction) in optimized.into_iter().enumerate() {
+        if let Some(instruction) = instruction {
+            if let Some(source) = index
+                .checked_sub(untraced_length)
+                .and_then(|index| trace.get(index))
+            {
+                optimized_trace.push(source.clone());
+            }
+            output.add_instruction(instruction);
+        }
+    }
+    (output, optimized_trace)
+}
+
+/// Return the indices of the qubits on which `gate` acts, if each is fixed.
+fn get_fixed_qubits(gate: &Gate) -> Option<Vec<u64>> {
+    gate.qubits
+        .iter()
+        .map(|qubit| match qubit {
+            Qubit::Fixed(index) => Some(*index),
+            _ => None,
+        })
+        .collect()
+}
+
+fn push_qubits(stacks: &mut HashMap<u64, Vec<usize>>, qubits: &[u64], index: usize) {
+    for qubit in qubits {
+        stacks.entry(*qubit).or_default().push(index);
+    }
+}
+
+fn pop_qubits(stacks: &mut HashMap<u64, Vec<usize>>, qubits: &[u64]) {
+    for qubit in qubits {
+        if let Some(stack) = stacks.get_mut(qubit) {
+            stack.pop();
+        }
+    }
+}
+
+/// Whether `gate` carries no modifier, or only a single `DAGGER`.
+fn is_daggered(gate: &Gate) -> Option<bool> {
+    match gate.modifiers.as_slice() {
+        [] => Some(false),
+        [GateModifier::Dagger] => Some(true),
+        _ => None,
+    }
+}
+
+/// Whether `gate` is immediately undone by `next`, which follows it on the same qubits.
+fn are_inverses(gate: &Gate, next: &Gate) -> bool {
+    if gate.name != next.name || !gate.parameters.is_empty() || !next.parameters.is_empty() {
+        return false;
+    }
+    let (mut qubits, mut next_qubits) = match (get_fixed_qubits(gate), get_fixed_qubits(next)) {
+        (Some(qubits), Some(next_qubits)) => (qubits, next_qubits),
+        _ => return false,
+    };
+    if SYMMETRIC_GATES.contains(&gate.name.as_str()) {
+        qubits.sort_unstable();
+        next_qubits.sort_unstable();
+    }
+    if qubits != next_qubits {
+        return false;
+    }
+    match (is_daggered(gate), is_daggered(next)) {
+        (Some(_), Some(_)) if SELF_INVERSE_GATES.contains(&gate.name.as_str()) => true,
+        (Some(daggered), Some(next_daggered))
+            if DAGGER_INVERSE_GATES.contains(&gate.name.as_str()) =>
+        {
+            daggered != next_daggered
+        }
+        _ => false,
+    }
+}
+
+/// Merge `next` into `gate` when both are unmodified rotations of the same kind on the same qubit,
+/// by constant angles, returning the single rotation by the sum of their angles.
+fn merge_rotations(options: &ContextOptions, gate: &Gate, next: &Gate) -> Option<Gate> {
+    let angle = get_rotation_angle(gate)?;
+    let next_angle = get_rotation_angle(next)?;
+    if gate.name != next.name || gate.qubits != next.qubits {
+        return None;
+    }
+    Some(Gate {
+        parameters: vec![build_constant_expression(
+            angle + next_angle,
+            options.float_precision,
+            options.symbolic_pi,
+        )],
+        ..gate.clone()
+    })
+}
+
+/// The angle of `gate`, if it is an unmodified rotation by a constant angle.
+fn get_rotation_angle(gate: &Gate) -> Option<f64> {
+    if !ROTATION_GATES.contains(&gate.name.as_str()) || !gate.modifiers.is_empty() {
+        return None;
+    }
+    match gate.parameters.as_slice() {
+        [angle] => evaluate_constant(angle),
+        _ => None,
+    }
+}
+
+/// Whether `gate` is a rotation by a multiple of `2*pi`, and so acts as the identity, up to a
+/// global phase.
+fn is_identity_rotation(gate: &Gate) -> bool {
+    get_rotation_angle(gate).map_or(false, |angle| {
+        (angle - (angle / TAU).round() * TAU).abs() < IDENTITY_TOLERANCE
+    })
+}
+
+/// Evaluate an expression built only from real numbers and `pi`, as written for constant
+/// parameters.
+fn evaluate_constant(expression: &Expression) -> Option<f64> {
+    match expression {
+        Expression::Number(number) if number.im == 0.0 => Some(number.re),
+        Expression::PiConstant => Some(PI),
+        Expression::Prefix {
+            operator: PrefixOperator::Minus,
+            expression,
+        } => evaluate_constant(expression).map(|value| -value),
+        Expression::Prefix {
+            operator: PrefixOperator::Plus,
+            expression,
+        } => evaluate_constant(expression),
+        Expression::Infix {
+            left,
+            operator,
+            right,
+        } => {
+            let left = evaluate_constant(left)?;
+            let right = evaluate_constant(right)?;
+            match operator {
+                InfixOperator::Plus => Some(left + right),
+                InfixOperator::Minus => Some(left - right),
+                InfixOperator::Star => Some(left * right),
+                InfixOperator::Slash => Some(left / right),
+                InfixOperator::Caret => Some(left.powf(right)),
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use crate::context::context::ContextOptions;
+
+    use super::optimize_program;
+
+    fn optimize(quil: &str, trace: &[&str]) -> (String, Vec<String>) {
+        let options = ContextOptions {
+            symbolic_pi: true,
+            ..ContextOptions::default()
+        };
+        let program = quil_rs::Program::from_str(quil).unwrap();
+        let trace = trace.iter().map(ToString::to_string).collect::<Vec<_>>();
+        let (program, trace) = optimize_program(&options, &program, &trace);
+        (program.to_string(true), trace)
+    }
+
+    #[test]
+    fn cancels_adjacent_inverse_gates() {
+        let (program, _) = optimize(
+            "X 0\nH 0\nH 0\nX 0\nS 1\nDAGGER S 1\nCNOT 0 1\nCNOT 0 1\nCZ 0 1\nCZ 1 0\nT 2\n",
+            &[],
+        );
+        assert_eq!(program, "T 2\n");
+    }
+
+    #[test]
+    fn merges_rotations_and_drops_identities() {
+        let (program, trace) = optimize(
+            "RZ(pi/4) 0\nRZ(pi/4) 0\nRX(0.5) 1\nRX(-0.5) 1\nPHASE(2*pi) 2\nRY(theta) 3\nRY(0.5) 3\n",
+            &["a", "b", "c", "d", "e", "f", "g"],
+        );
+        assert_eq!(program, "RZ(pi/2) 0\nRY(theta) 3\nRY(0.5) 3\n");
+        assert_eq!(trace, vec!["a", "f", "g"]);
+    }
+
+    #[test]
+    fn gates_are_not_matched_across_other_instructions() {
+        let (program, _) = optimize(
+            "DECLARE ro BIT[1]\nH 0\nMEASURE 0 ro[0]\nH 0\nX 1\nCNOT 1 0\nX 1\nZ 2\nPRAGMA BARRIER\nZ 2\nH 3\nMEASURE 0 ro[0]\nH 3\n",
+            &[],
+        );
+        assert_eq!(
+            program,
+            "DECLARE ro BIT[1]\nH 0\nMEASURE 0 ro[0]\nH 0\nX 1\nCNOT 1 0\nX 1\nZ 2\nPRAGMA BARRIER\nZ 2\nMEASURE 0 ro[0]\n"
+        );
+    }
+}
@@ -0,0 +1,66 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Lets the caller rewrite each Quil program from their own code before it is embedded in the
+// patched module.
+use eyre::{Result, WrapErr};
+use inkwell::basic_block::BasicBlock;
+
+use crate::context::QCSCompilerContext;
+
+/// Rewrites each Quil program a module is patched with, once it is assembled, with its
+/// declarations, calibrations, and pragmas, and before it is embedded in the module.
+///
+/// Post-processors are registered through [`crate::PatchOptions::quil_post_processors`] and run in
+/// the order they were registered, each given the program as left by the one before. Any closure
+/// taking the program mutably and returning a result is a post-processor.
+pub trait QuilPostProcessor: Send + Sync {
+    /// Rewrite `program` in place, such as to append calibrations, inject pragmas, or replace
+    /// gates.
+    ///
+    /// # Errors
+    /// Returning an error aborts the transformation.
+    fn process(&self, program: &mut quil_rs::Program) -> Result<()>;
+}
+
+impl<F> QuilPostProcessor for F
+where
+    F: Fn(&mut quil_rs::Program) -> Result<()> + Send + Sync,
+{
+    fn process(&self, program: &mut quil_rs::Program) -> Result<()> {
+        self(program)
+    }
+}
+
+/// Run the registered post-processors, if any, over `program`, extracted from `basic_block`.
+pub(crate) fn apply_post_processors(
+    context: &QCSCompilerContext,
+    program: &mut quil_rs::Program,
+    basic_block: BasicBlock,
+) -> Result<()> {
+    for (index, post_processor) in context.options.quil_post_processors.iter().enumerate() {
+        post_processor.process(program).wrap_err_with(|| {
+            format!(
+                "Quil post-processor {} failed on the program in @{}, block %{}",
+                index,
+                basic_block
+                    .get_parent()
+                    .map(|function| function.get_name().to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                basic_block.get_name().to_string_lossy()
+            )
+        })?;
+    }
+    Ok(())
+}
@@ -0,0 +1,565 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Plans the output recorded by a program from its `__quantum__rt__*_record_output` calls, shared
+// by the shot count and unitary pattern matchers. Tuples and arrays are tracked as they are
+// recorded, so that unbalanced or improperly nested output is reported at transpile time rather
+// than corrupting output at runtime.
+use std::fmt;
+
+use either::Either;
+use eyre::{eyre, Result};
+use indexmap::IndexMap;
+use inkwell::values::{BasicValueEnum, InstructionOpcode, InstructionValue};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::context::QCSCompilerContext;
+use crate::error::KindError;
+use crate::interop::instruction::{
+    get_called_function_name, operand_to_integer, pointer_value_to_string,
+    runtime_string_to_string, OperationArgument,
+};
+use crate::quil_builder::READOUT_MEMORY_REGION_NAME;
+use crate::transform::{get_host_computed_bool, get_host_computed_integer};
+use crate::{RecordedOutput, TranspileWarning};
+
+lazy_static! {
+    static ref RT_RECORD_OUTPUT_INTRINSIC_REGEX: Regex =
+        Regex::new("^__quantum__rt__(?P<record_type>.+)_record_output$").unwrap();
+}
+
+/// A kind of container within recorded output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ContainerKind {
+    Tuple,
+    Array,
+}
+
+impl ContainerKind {
+    fn start_marker(self, length: Option<u64>, tag: Option<String>) -> RecordedOutput {
+        match self {
+            Self::Tuple => RecordedOutput::TupleStart,
+            Self::Array => RecordedOutput::ArrayStart { len: length, tag },
+        }
+    }
+
+    fn end_marker(self) -> RecordedOutput {
+        match self {
+            Self::Tuple => RecordedOutput::TupleEnd,
+            Self::Array => RecordedOutput::ArrayEnd,
+        }
+    }
+}
+
+impl fmt::Display for ContainerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tuple => write!(f, "tuple"),
+            Self::Array => write!(f, "array"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct OpenContainer {
+    kind: ContainerKind,
+    /// The number of elements given to `__quantum__rt__{kind}_record_output`, if the container was
+    /// started that way rather than with an explicit start marker
+    length: Option<u64>,
+    recorded: u64,
+}
+
+/// Read the number of elements given to `__quantum__rt__{record_type}_record_output`, which must
+/// be a constant.
+fn get_container_length(instruction: InstructionValue, record_type: &str) -> Result<u64> {
+    instruction
+        .get_operand(0)
+        .and_then(operand_to_integer)
+        .and_then(|length| length.get_zero_extended_constant())
+        .ok_or_else(|| {
+            eyre::Report::new(KindError::InvalidArgument(format!(
+                "expected a constant number of elements given to __quantum__rt__{}_record_output in {:?}",
+                record_type,
+                instruction
+            )))
+        })
+}
+
+/// Read the tag given as operand 1 of `__quantum__rt__{record_type}_record_output`, which must be
+/// null or point to a constant string global, returning `None` if it is null or was not given.
+fn get_record_tag(
+    context: &QCSCompilerContext,
+    instruction: InstructionValue,
+    record_type: &str,
+) -> Result<Option<String>> {
+    let error = || {
+        eyre::Report::new(KindError::InvalidArgument(format!(
+            "expected a null or constant string tag given to __quantum__rt__{}_record_output in {:?}",
+            record_type,
+            instruction
+        )))
+    };
+    // The final operand of a call instruction is the function being called
+    if instruction.get_num_operands() < 3 {
+        return Ok(None);
+    }
+    let tag = match instruction.get_operand(1) {
+        Some(Either::Left(BasicValueEnum::PointerValue(tag))) => tag,
+        _ => return Err(error()),
+    };
+    if tag.is_null() {
+        return Ok(None);
+    }
+    pointer_value_to_string(context, tag)
+        .or_else(|| runtime_string_to_string(context, tag))
+        .map(Some)
+        .ok_or_else(error)
+}
+
+/// The tuples and arrays of recorded output which have been started but not yet ended, innermost last.
+#[derive(Debug, Default)]
+struct OpenContainers(Vec<OpenContainer>);
+
+impl OpenContainers {
+    /// Start a container. If `length` is given, as by `__quantum__rt__array_record_output`, the
+    /// container ends once that many elements have been recorded within it; otherwise, it ends at
+    /// the matching end marker. Only arrays record their `length` and `tag` in `output`.
+    fn start(
+        &mut self,
+        output: &mut Vec<RecordedOutput>,
+        kind: ContainerKind,
+        length: Option<u64>,
+        tag: Option<String>,
+    ) {
+        output.push(kind.start_marker(length, tag));
+        if length == Some(0) {
+            output.push(kind.end_marker());
+            self.element_recorded(output);
+        } else {
+            self.0.push(OpenContainer {
+                kind,
+                length,
+                recorded: 0,
+            });
+        }
+    }
+
+    /// End the innermost container at an explicit end marker.
+    fn end(&mut self, output: &mut Vec<RecordedOutput>, kind: ContainerKind) -> Result<()> {
+        let message = match self.0.last() {
+            None => format!(
+                "a {} end was recorded without a matching {} start",
+                kind, kind
+            ),
+            Some(open) if open.kind != kind => format!(
+                "a {} end was recorded within an open {}",
+                kind, open.kind
+            ),
+            Some(OpenContainer {
+                length: Some(length),
+                recorded,
+                ..
+            }) => format!(
+                "a {} end was recorded after {} of the {} elements given to __quantum__rt__{}_record_output",
+                kind,
+                recorded,
+                length,
+                kind
+            ),
+            Some(_) => {
+                self.0.pop();
+                output.push(kind.end_marker());
+                self.element_recorded(output);
+                return Ok(());
+            }
+        };
+        Err(KindError::UnbalancedRecordedOutput(message).into())
+    }
+
+    /// Record a single value, ending each container which it completes.
+    fn record(&mut self, output: &mut Vec<RecordedOutput>, value: RecordedOutput) {
+        output.push(value);
+        self.element_recorded(output);
+    }
+
+    fn element_recorded(&mut self, output: &mut Vec<RecordedOutput>) {
+        while let Some(open) = self.0.last_mut() {
+            open.recorded += 1;
+            if open.length != Some(open.recorded) {
+                break;
+            }
+            let kind = open.kind;
+            self.0.pop();
+            output.push(kind.end_marker());
+        }
+    }
+
+    /// Check that every container has ended and that the markers of `output`, including any
+    /// recorded by custom instruction handlers, are balanced and properly nested.
+    fn finish(&self, output: &[RecordedOutput]) -> Result<()> {
+        if let Some(open) = self.0.last() {
+            let message = match open.length {
+                Some(length) => format!(
+                    "only {} of the {} elements given to __quantum__rt__{}_record_output were recorded",
+                    open.recorded,
+                    length,
+                    open.kind
+                ),
+                None => format!(
+                    "a {} start was recorded without a matching {} end",
+                    open.kind, open.kind
+                ),
+            };
+            return Err(KindError::UnbalancedRecordedOutput(message).into());
+        }
+
+        let mut open_kinds = vec![];
+        for value in output {
+            match value {
+                RecordedOutput::TupleStart => open_kinds.push(ContainerKind::Tuple),
+                RecordedOutput::ArrayStart { .. } => open_kinds.push(ContainerKind::Array),
+                RecordedOutput::TupleEnd | RecordedOutput::ArrayEnd => {
+                    let kind = if matches!(value, RecordedOutput::TupleEnd) {
+                        ContainerKind::Tuple
+                    } else {
+                        ContainerKind::Array
+                    };
+                    match open_kinds.pop() {
+                        Some(open) if open == kind => {}
+                        Some(open) => {
+                            return Err(KindError::UnbalancedRecordedOutput(format!(
+                                "a {} end was recorded within an open {}",
+                                kind, open
+                            ))
+                            .into())
+                        }
+                        None => {
+                            return Err(KindError::UnbalancedRecordedOutput(format!(
+                                "a {} end was recorded without a matching {} start",
+                                kind, kind
+                            ))
+                            .into())
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        match open_kinds.pop() {
+            Some(kind) => Err(KindError::UnbalancedRecordedOutput(format!(
+                "a {} start was recorded without a matching {} end",
+                kind, kind
+            ))
+            .into()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Returns the number of bits which must be declared in the `ro` memory region to hold every
+/// readout value of `read_result_mapping`.
+pub(crate) fn readout_length(read_result_mapping: &IndexMap<u64, u64>) -> u64 {
+    read_result_mapping
+        .values()
+        .max()
+        .map_or(0, |max_index| max_index + 1)
+}
+
+/// Assign the `ro` offset into which a measurement targeting `result` is read out.
+///
+/// Result indices may be sparse rather than increasing monotonically from 0.
+/// If used naively (i.e. %Result 5 as `ro[5]`), this would result in sparse, suboptimal allocation
+/// in the readout data fetched following execution. So, instead, we assign Result indices to
+/// monotonically increasing `ro` region offsets so as to keep `ro` dense,
+/// unless the user has asked for stable addressing via `sparse_readout`.
+///
+/// A Result may be the target of more than one measurement within a shot. Each
+/// measurement is given its own `ro` offset, and the mapping is updated to point at
+/// the latest one, so that reads and recorded output observe the value in program order.
+pub(crate) fn assign_readout_offset(
+    context: &QCSCompilerContext,
+    read_result_mapping: &mut IndexMap<u64, u64>,
+    result: u64,
+) -> Result<u64> {
+    if read_result_mapping.contains_key(&result) {
+        if context.options.sparse_readout {
+            return Err(eyre!(
+                "Result index {} is measured more than once, which is not supported with sparse readout",
+                result
+            ));
+        }
+        log::debug!(
+            "Result index {} is measured again; assigning a new readout offset",
+            result
+        );
+    }
+    let ro_buffer_index = if context.options.sparse_readout {
+        result
+    } else {
+        readout_length(read_result_mapping)
+    };
+    read_result_mapping.insert(result, ro_buffer_index);
+
+    Ok(ro_buffer_index)
+}
+
+/// Builds the [`crate::RecordingPlan`] of a program as its instructions are matched.
+#[derive(Debug, Default)]
+pub(crate) struct RecordingPlanBuilder {
+    output: Vec<RecordedOutput>,
+    open_containers: OpenContainers,
+    warnings: Vec<TranspileWarning>,
+}
+
+impl RecordingPlanBuilder {
+    /// The output planned so far, in order.
+    pub(crate) fn entries(&self) -> &[RecordedOutput] {
+        &self.output
+    }
+
+    /// The likely mistakes found in the output planned so far, in the order they were found.
+    pub(crate) fn warnings(&self) -> &[TranspileWarning] {
+        &self.warnings
+    }
+
+    /// Report `warning`, failing instead if warnings are denied.
+    fn warn(&mut self, context: &QCSCompilerContext, warning: TranspileWarning) -> Result<()> {
+        if context.options.deny_warnings {
+            return Err(eyre!("{}", warning));
+        }
+        log::warn!("{}", warning);
+        self.warnings.push(warning);
+        Ok(())
+    }
+
+    /// Mark the start of the output recorded by each shot.
+    pub(crate) fn start_shot(&mut self) {
+        self.output.push(RecordedOutput::ShotStart);
+    }
+
+    /// Mark the end of the output recorded by each shot.
+    pub(crate) fn end_shot(&mut self) {
+        self.output.push(RecordedOutput::ShotEnd);
+    }
+
+    /// Append output planned elsewhere, such as by a custom instruction handler. Its containers
+    /// are checked by [`RecordingPlanBuilder::finish`] rather than as they are appended.
+    pub(crate) fn extend(&mut self, values: impl IntoIterator<Item = RecordedOutput>) {
+        self.output.extend(values);
+    }
+
+    /// Plan the output of `instruction` if it calls `__quantum__rt__{record_type}_record_output`,
+    /// returning whether it did. The `%Result` recorded by `__quantum__rt__result_record_output`
+    /// is read from the arguments returned by `get_arguments`, so that each matcher may resolve
+    /// them as it does for quantum instructions; a `%Result` which was never measured is assigned
+    /// the next offset of `read_result_mapping` and reported as a [`TranspileWarning`].
+    pub(crate) fn record_call<'ctx>(
+        &mut self,
+        context: &QCSCompilerContext<'ctx>,
+        instruction: InstructionValue<'ctx>,
+        read_result_mapping: &mut IndexMap<u64, u64>,
+        readout_instruction_mapping: &[(u64, InstructionValue<'ctx>)],
+        get_arguments: impl FnOnce() -> Result<Vec<OperationArgument>>,
+    ) -> Result<bool> {
+        if instruction.get_opcode() != InstructionOpcode::Call {
+            return Ok(false);
+        }
+        let function_name = match get_called_function_name(instruction)? {
+            Some(function_name) => function_name,
+            None => return Ok(false),
+        };
+        let record_type = match RT_RECORD_OUTPUT_INTRINSIC_REGEX.captures(&function_name) {
+            Some(captures) => captures["record_type"].to_owned(),
+            None => return Ok(false),
+        };
+        let record_type = record_type.as_str();
+
+        match record_type {
+            "result" => {
+                let arguments = get_arguments()?;
+                let result_index = match arguments.get(0) {
+                    Some(OperationArgument::Result(result_index)) => *result_index,
+                    _ => {
+                        return Err(KindError::InvalidArgument(format!(
+                            "malformed or missing arguments for: __quantum_rt__{}_record_output",
+                            record_type
+                        ))
+                        .into())
+                    }
+                };
+                let next_ro_index = if context.options.sparse_readout {
+                    result_index
+                } else {
+                    readout_length(read_result_mapping)
+                };
+                let was_measured = read_result_mapping.contains_key(&result_index);
+                let offset = *read_result_mapping
+                    .entry(result_index)
+                    .or_insert(next_ro_index);
+                if !was_measured {
+                    self.warn(
+                        context,
+                        TranspileWarning::UnmeasuredResultRecorded {
+                            result: result_index,
+                            offset,
+                        },
+                    )?;
+                }
+                let region = String::from(READOUT_MEMORY_REGION_NAME);
+                let tag = get_record_tag(context, instruction, record_type)?;
+                let value = if context.options.flip_readout && was_measured {
+                    RecordedOutput::FlippedResultReadoutOffset {
+                        region,
+                        offset,
+                        tag,
+                    }
+                } else {
+                    RecordedOutput::ResultReadoutOffset {
+                        region,
+                        offset,
+                        tag,
+                    }
+                };
+                self.record(value);
+            }
+            "integer" | "int" => self.record(get_host_computed_integer(
+                context,
+                instruction,
+                readout_instruction_mapping,
+                get_record_tag(context, instruction, record_type)?,
+            )?),
+            "bool" => self.record(get_host_computed_bool(
+                context,
+                instruction,
+                readout_instruction_mapping,
+                get_record_tag(context, instruction, record_type)?,
+            )?),
+            "double" => {
+                return Err(eyre!("unimplemented record type: {}", record_type));
+            }
+            "tuple" | "array" => {
+                let kind = if record_type == "tuple" {
+                    ContainerKind::Tuple
+                } else {
+                    ContainerKind::Array
+                };
+                let length = get_container_length(instruction, record_type)?;
+                let tag = get_record_tag(context, instruction, record_type)?;
+                self.start(kind, Some(length), tag);
+            }
+            "tuple_start" => self.start(ContainerKind::Tuple, None, None),
+            "tuple_end" => self.end(ContainerKind::Tuple)?,
+            "array_start" => self.start(ContainerKind::Array, None, None),
+            "array_end" => self.end(ContainerKind::Array)?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn record(&mut self, value: RecordedOutput) {
+        self.open_containers.record(&mut self.output, value);
+    }
+
+    fn start(&mut self, kind: ContainerKind, length: Option<u64>, tag: Option<String>) {
+        self.open_containers
+            .start(&mut self.output, kind, length, tag);
+    }
+
+    fn end(&mut self, kind: ContainerKind) -> Result<()> {
+        self.open_containers.end(&mut self.output, kind)
+    }
+
+    /// Check that every container of the plan has ended and that its markers are balanced and
+    /// properly nested.
+    pub(crate) fn finish(&self) -> Result<()> {
+        self.open_containers.finish(&self.output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(offset: u64) -> RecordedOutput {
+        RecordedOutput::ResultReadoutOffset {
+            region: String::from("ro"),
+            offset,
+            tag: None,
+        }
+    }
+
+    fn markers(output: &[RecordedOutput]) -> String {
+        output
+            .iter()
+            .map(|value| match value {
+                RecordedOutput::TupleStart => "(",
+                RecordedOutput::TupleEnd => ")",
+                RecordedOutput::ArrayStart { .. } => "[",
+                RecordedOutput::ArrayEnd => "]",
+                _ => "r",
+            })
+            .collect()
+    }
+
+    #[test]
+    fn counted_containers_end_after_their_elements() {
+        let mut open = OpenContainers::default();
+        let mut output = vec![];
+        open.start(&mut output, ContainerKind::Array, Some(2), None);
+        open.start(&mut output, ContainerKind::Tuple, Some(2), None);
+        open.record(&mut output, result(0));
+        open.record(&mut output, result(1));
+        open.start(&mut output, ContainerKind::Tuple, None, None);
+        open.record(&mut output, result(2));
+        open.end(&mut output, ContainerKind::Tuple).unwrap();
+        open.start(&mut output, ContainerKind::Array, Some(0), None);
+
+        assert_eq!(markers(&output), "[(rr)(r)][]");
+        open.finish(&output).unwrap();
+    }
+
+    #[test]
+    fn unbalanced_containers_are_rejected() {
+        let mut open = OpenContainers::default();
+        let mut output = vec![];
+        assert!(open.end(&mut output, ContainerKind::Array).is_err());
+
+        open.start(&mut output, ContainerKind::Tuple, None, None);
+        open.start(&mut output, ContainerKind::Array, None, None);
+        let error = open.end(&mut output, ContainerKind::Tuple).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "a tuple end was recorded within an open array"
+        );
+        assert!(open.finish(&output).is_err());
+
+        let mut open = OpenContainers::default();
+        let mut output = vec![];
+        open.start(&mut output, ContainerKind::Array, Some(3), None);
+        open.record(&mut output, result(0));
+        let error = open.finish(&output).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "only 1 of the 3 elements given to __quantum__rt__array_record_output were recorded"
+        );
+
+        let output = vec![
+            RecordedOutput::TupleStart,
+            result(0),
+            RecordedOutput::ArrayEnd,
+        ];
+        assert!(OpenContainers::default().finish(&output).is_err());
+    }
+}
@@ -15,35 +15,47 @@
 // limitations under the License.
 
 use std::{
-    collections::{hash_map::DefaultHasher, HashMap},
+    collections::hash_map::DefaultHasher,
     convert::TryFrom,
     hash::{Hash, Hasher},
 };
 
 use either::Either;
 use eyre::{eyre, Result, WrapErr};
+use indexmap::IndexMap;
 use inkwell::{
     basic_block::BasicBlock,
     values::{
-        BasicValue, BasicValueEnum, FloatValue, FunctionValue, InstructionOpcode, InstructionValue,
+        AnyValue, BasicValue, BasicValueEnum, FloatValue, FunctionValue, InstructionOpcode,
+        InstructionValue,
     },
 };
 use lazy_static::lazy_static;
 use log::{debug, info};
 use quil_rs::{
-    expression::Expression,
+    expression::{Expression, InfixOperator},
     instruction::{GateModifier, MemoryReference, Qubit},
 };
 use regex::Regex;
 
 use crate::{
     context::QCSCompilerContext,
+    error::KindError,
     interop::instruction::{
-        get_called_function_name, get_qis_function_arguments, integer_value_to_u64,
+        get_bound_qis_function_arguments, get_called_function_name, integer_value_to_u64,
         operand_to_integer, OperationArgument,
     },
-    transform::PARAMETER_MEMORY_REGION_NAME,
-    RecordedOutput,
+    transform::{
+        constant::{build_constant_expression, fold_constant_select, is_same_parameter},
+        custom::handle_custom_instruction,
+        explain_instruction,
+        frame::{build_frame_instruction, QIS_FRAME_INTRINSIC_REGEX},
+        message::handle_message,
+        pauli::{build_joint_measurement, build_pauli_exponential, get_pauli_terms},
+        recording::{assign_readout_offset, readout_length, RecordingPlanBuilder},
+        unroll::get_gate_loop,
+        validate_quil_program_length, PARAMETER_MEMORY_REGION_NAME,
+    },
 };
 
 /// Each pattern matching function, if it finds a match, returns a result tuple including:
@@ -78,12 +90,26 @@ pub(crate) struct ShotCountPatternMatchContext<'ctx> {
     /// The quil program transpiled from quantum intrinsics
     pub(crate) quil_program: quil_rs::Program,
 
-    /// Signifies output to be recorded at the end of program execution
-    pub(crate) recorded_output: Vec<RecordedOutput>,
+    /// Plans the output to be recorded at the end of program execution
+    pub(crate) recording: RecordingPlanBuilder,
+
+    /// When tracing, the QIR instruction from which each instruction in `quil_program` was
+    /// translated, in order
+    pub(crate) trace: Vec<String>,
+
+    /// The number of decimal places to which constant parameters are rounded, if any
+    pub(crate) float_precision: Option<usize>,
+
+    /// Whether constant parameters near multiples of pi are written symbolically
+    pub(crate) symbolic_pi: bool,
 
     /// The shot count inferred from the loop instructions
     pub(crate) shot_count: Option<u64>,
 
+    /// The functions of the module called from the block, in the order they are called, to be
+    /// transformed in turn
+    pub(crate) called_functions: Vec<FunctionValue<'ctx>>,
+
     /// A list of instructions to remove from the program (for substitution with Quil)
     pub(crate) instructions_to_remove: Vec<InstructionValue<'ctx>>,
 
@@ -91,8 +117,9 @@ pub(crate) struct ShotCountPatternMatchContext<'ctx> {
     /// branch on shot count
     pub(crate) next_basic_block: Option<BasicBlock<'ctx>>,
 
-    /// Mapping of (read_result *Result index)->(ro memory region index)
-    pub(crate) read_result_mapping: HashMap<u64, u64>,
+    /// Mapping of (read_result *Result index)->(ro memory region index), in the order each Result
+    /// was first measured, so that anything emitted from it is the same on every run
+    pub(crate) read_result_mapping: IndexMap<u64, u64>,
 
     /// Pairings of (readout buffer index/offset) with the instruction which stores that readout value.
     pub(crate) readout_instruction_mapping: Vec<(u64, InstructionValue<'ctx>)>,
@@ -103,9 +130,40 @@ pub(crate) struct ShotCountPatternMatchContext<'ctx> {
 
     /// Whether or not to prepend a RESET instruction to the program to actively reset all qubits on each shot
     pub(crate) use_active_reset: bool,
+
+    /// Branches on readout values which were transpiled to Quil classical control flow
+    pub(crate) classical_control_branches: Vec<ClassicalControlBranch<'ctx>>,
+
+    /// The block containing the end of the shot count loop, which differs from the block in which the loop
+    /// starts when the loop body contains classical control flow
+    pub(crate) loop_end_block: Option<BasicBlock<'ctx>>,
+
+    /// The blocks of loops within the shot count loop which were unrolled into the Quil program
+    pub(crate) unrolled_blocks: Vec<BasicBlock<'ctx>>,
+
+    /// While a loop is being unrolled, the value of each of its `phi` instructions in the current iteration
+    pub(crate) loop_bindings: Vec<(InstructionValue<'ctx>, i64)>,
+}
+
+/// A conditional branch on the value of a `read_result` call, which has been transpiled into a Quil
+/// `JUMP-WHEN` or `JUMP-UNLESS` around the instructions of the conditionally-executed block.
+#[derive(Debug)]
+pub(crate) struct ClassicalControlBranch<'ctx> {
+    /// The original `br` instruction, which branches on the readout value
+    pub(crate) branch_instruction: InstructionValue<'ctx>,
+
+    /// The block at which control flow rejoins after the conditionally-executed block
+    pub(crate) merge_block: BasicBlock<'ctx>,
 }
 
 impl<'ctx> ShotCountPatternMatchContext<'ctx> {
+    /// Attribute each Quil instruction added since the last call to the QIR instruction `source`.
+    pub(crate) fn record_trace(&mut self, source: InstructionValue<'ctx>) {
+        let length = self.quil_program.to_instructions(false).len();
+        let source = source.print_to_string().to_string();
+        self.trace.resize(length, source.trim().to_owned());
+    }
+
     /// If the program contains any executable instructions (gates, pulses, etc) and a shot count has been inferred,
     /// return that information; otherwise, return `None` indicating that the pattern was not matched.
     pub(crate) fn get_program_data(&self) -> Option<(&quil_rs::Program, u64)> {
@@ -126,28 +184,41 @@ impl<'ctx> ShotCountPatternMatchContext<'ctx> {
     ///
     /// * `context`: overall compiler context
     /// * `basic_block`: the subject block to be searched for the pattern
-    /// * `visited_functions`: list of function names which have already been transpiled. Used to prevent recursion loops.
-    /// * `function_call_callback`: callback to be invoked when a function call is found within the block, in order to recursively
-    ///    transpile an LLVM module.
+    /// * `follow_calls`: whether calls to other functions of the module may be transformed in turn.
+    ///    If so, each is collected into `called_functions`; otherwise, such a call is an error, as
+    ///    Quil does not itself have function calls.
     pub(crate) fn from_basic_block(
         context: &mut QCSCompilerContext<'ctx>,
         basic_block: BasicBlock<'ctx>,
-        visited_functions: &[&str],
-        function_call_callback: fn(
-            &mut QCSCompilerContext<'ctx>,
-            FunctionValue<'ctx>,
-            &[&str],
-        ) -> Result<()>,
+        follow_calls: bool,
     ) -> Result<Self> {
         let mut next_instruction = basic_block.get_first_instruction();
-        let mut pattern_context = ShotCountPatternMatchContext::default();
+        let mut pattern_context = ShotCountPatternMatchContext {
+            float_precision: context.options.float_precision,
+            symbolic_pi: context.options.symbolic_pi,
+            ..ShotCountPatternMatchContext::default()
+        };
 
         info!(
             "starting transpile: block {}",
             basic_block.get_name().to_str()?
         );
 
+        if context.unrolled_blocks.contains(&basic_block) {
+            context.explain(|| {
+                String::from("stopped: this block was unrolled into an enclosing shot count loop")
+            });
+            return Ok(pattern_context);
+        }
+
+        let mut traced_instruction = None;
         while let Some(instruction) = next_instruction {
+            if context.options.trace {
+                if let Some(source) = traced_instruction.replace(instruction) {
+                    pattern_context.record_trace(source);
+                }
+            }
+
             // If we haven't yet found the loop start...
             if pattern_context.initial_instruction.is_none() {
                 // Check if we've found it in this instruction. If not, continue on to the next instruction until we do find it.
@@ -156,56 +227,172 @@ impl<'ctx> ShotCountPatternMatchContext<'ctx> {
                     shot_count_loop_start(&mut pattern_context, instruction)
                 {
                     debug!("matched shot count start: {:?}", instruction);
-                    pattern_context
-                        .recorded_output
-                        .push(RecordedOutput::ShotStart);
+                    context.explain(|| {
+                        explain_instruction(instruction, "matched the start of the shot count loop")
+                    });
+                    pattern_context.recording.start_shot();
                     next_instruction = pattern_instruction;
                     continue;
                 }
+                context.explain(|| {
+                    explain_instruction(
+                        instruction,
+                        "skipped: not a `phi` which starts the shot count loop",
+                    )
+                });
             } else if let Some((pattern_instruction, _)) =
                 quantum_instruction(context, &mut pattern_context, instruction)?
             {
                 debug!("matched quantum instruction: {:?}", instruction);
+                context
+                    .explain(|| explain_instruction(instruction, "matched a quantum instruction"));
                 next_instruction = pattern_instruction;
                 continue;
             } else if let Some((pattern_instruction, _)) =
                 rt_record_instruction(context, &mut pattern_context, instruction)?
             {
                 debug!("matched rt_record instruction: {:?}", instruction);
+                context.explain(|| explain_instruction(instruction, "matched an output recording"));
                 next_instruction = pattern_instruction;
                 continue;
+            } else if handle_message(
+                context,
+                instruction,
+                &mut pattern_context.quil_program,
+                &mut pattern_context.instructions_to_remove,
+            )? {
+                debug!("matched message: {:?}", instruction);
+                context.explain(|| {
+                    explain_instruction(instruction, "matched a message, or the string it prints")
+                });
+                next_instruction = instruction.get_next_instruction();
+                continue;
             } else if let Some((_, _)) =
                 shot_count_loop_end(context, &mut pattern_context, instruction)?
             {
                 debug!("matched shot count end: {:?}", instruction);
-                pattern_context
-                    .recorded_output
-                    .push(RecordedOutput::ShotEnd);
+                context.explain(|| {
+                    explain_instruction(instruction, "matched the end of the shot count loop")
+                });
+                pattern_context.loop_end_block = instruction.get_parent();
+                pattern_context.recording.end_shot();
                 break;
+            } else if let Some((pattern_instruction, _)) =
+                classical_control_branch(context, &mut pattern_context, instruction)?
+            {
+                debug!("matched classical control branch: {:?}", instruction);
+                context.explain(|| {
+                    explain_instruction(instruction, "matched a branch on a measurement result")
+                });
+                next_instruction = pattern_instruction;
+                continue;
+            } else if let Some((pattern_instruction, _)) =
+                unrolled_gate_loop(context, &mut pattern_context, instruction)?
+            {
+                debug!("matched gate loop: {:?}", instruction);
+                context.explain(|| {
+                    explain_instruction(
+                        instruction,
+                        "matched a loop with a constant trip count, which was unrolled",
+                    )
+                });
+                next_instruction = pattern_instruction;
+                continue;
+            } else if let Some(output) = handle_custom_instruction(context, instruction)? {
+                debug!("matched custom instruction: {:?}", instruction);
+                context.explain(|| {
+                    explain_instruction(instruction, "matched a custom instruction handler")
+                });
+                for quil_instruction in output.instructions {
+                    pattern_context
+                        .quil_program
+                        .add_instruction(quil_instruction);
+                }
+                pattern_context.recording.extend(output.recorded_output);
+                pattern_context.instructions_to_remove.push(instruction);
+                next_instruction = instruction.get_next_instruction();
+                continue;
             } else if instruction.get_opcode() == InstructionOpcode::Call {
                 // TODO: handle callbr?
                 if let Some(Either::Left(BasicValueEnum::PointerValue(pointer_value))) =
                     instruction.get_operand(0)
                 {
                     let function_name = pointer_value.get_name().to_str()?;
-                    if !visited_functions.contains(&function_name) {
-                        if let Some(function) = context.module.get_function(function_name) {
-                            let mut visited_functions = Vec::from(visited_functions);
-                            visited_functions.push(function_name);
-                            function_call_callback(context, function, &visited_functions)?;
-                            next_instruction = instruction.get_next_instruction();
-                            continue;
+                    if let Some(function) = context.module.get_function(function_name) {
+                        if !follow_calls {
+                            return Err(eyre!(
+                                "cannot transpile nested function calls to Quil; found \"{}\"",
+                                function_name
+                            ));
                         }
+                        context.explain(|| {
+                            explain_instruction(
+                                instruction,
+                                &format!(
+                                    "called function @{} will be transformed after this block",
+                                    function_name
+                                ),
+                            )
+                        });
+                        pattern_context.called_functions.push(function);
+                        next_instruction = instruction.get_next_instruction();
+                        continue;
                     }
                 }
+                context.explain(|| {
+                    explain_instruction(instruction, "skipped: call not recognized by any rule")
+                });
+            } else {
+                context
+                    .explain(|| explain_instruction(instruction, "skipped: classical instruction"));
             }
 
             next_instruction = instruction.get_next_instruction();
         }
 
+        if let Some(source) = traced_instruction {
+            pattern_context.record_trace(source);
+        }
+
+        validate_quil_program_length(&pattern_context.quil_program, &context.options.limits)?;
+        pattern_context.recording.finish()?;
+        context
+            .unrolled_blocks
+            .extend(pattern_context.unrolled_blocks.iter().copied());
+
+        if pattern_context.initial_instruction.is_none() {
+            context.explain(|| {
+                String::from(
+                    "stopped: no `phi` was found to start the shot count loop in this block",
+                )
+            });
+        } else if pattern_context.shot_count.is_none() {
+            context.explain(|| {
+                String::from(concat!(
+                    "stopped: no `add`, `icmp` against a constant shot count, ",
+                    "and `br` were found to end the shot count loop"
+                ))
+            });
+        }
+
         Ok(pattern_context)
     }
 
+    /// Returns the number of bits which must be declared in the `ro` memory region to hold every
+    /// readout value.
+    pub(crate) fn get_readout_length(&self) -> u64 {
+        readout_length(&self.read_result_mapping)
+    }
+
+    /// Returns the value from which each slot of the parameter memory region is set, as printed in
+    /// the module, in order.
+    pub(crate) fn get_parameter_table(&self) -> Vec<String> {
+        self.parameters
+            .iter()
+            .map(|value| value.print_to_string().to_string().trim().to_owned())
+            .collect()
+    }
+
     /// Returns the parameters which do not have a constant value.
     pub(crate) fn get_dynamic_parameters(&self) -> Vec<&FloatValue<'ctx>> {
         self.parameters
@@ -372,19 +559,242 @@ pub(crate) fn shot_count_loop_end<'a, 'ctx>(
     }
 }
 
+/// Match a conditional branch on the value returned by a `read_result` call within the shot count loop,
+/// where one target of the branch contains only quantum instructions and then rejoins the other target
+/// unconditionally. This is the shape of simple feed-forward, such as active qubit reset:
+///
+/// ```llvm
+///   %1 = tail call i1 @__quantum__qis__read_result__body(%Result* null)
+///   br i1 %1, label %then, label %continue
+///
+/// then:
+///   tail call void @__quantum__qis__x__body(%Qubit* null)
+///   br label %continue
+/// ```
+///
+/// The conditionally-executed instructions are transpiled to Quil classical control flow:
+///
+/// ```quil
+/// JUMP-UNLESS @qir_conditional_end_0 ro[0]
+/// X 0
+/// LABEL @qir_conditional_end_0
+/// ```
+///
+/// If matched, scanning continues at the first instruction of the block where control flow rejoins.
+pub(crate) fn classical_control_branch<'ctx>(
+    context: &QCSCompilerContext<'ctx>,
+    pattern_context: &mut ShotCountPatternMatchContext<'ctx>,
+    instruction: InstructionValue<'ctx>,
+) -> Result<PatternResult<'ctx, ()>> {
+    if instruction.get_opcode() != InstructionOpcode::Br || instruction.get_num_operands() != 3 {
+        return Ok(None);
+    }
+
+    let condition = match instruction.get_operand(0) {
+        Some(Either::Left(BasicValueEnum::IntValue(value))) => value.as_instruction(),
+        _ => None,
+    };
+
+    let ro_index = match condition.and_then(|condition| {
+        pattern_context
+            .readout_instruction_mapping
+            .iter()
+            .find(|(_, readout_instruction)| *readout_instruction == condition)
+            .map(|(ro_index, _)| *ro_index)
+    }) {
+        Some(ro_index) => ro_index,
+        None => return Ok(None),
+    };
+
+    let (then_block, else_block) = match (instruction.get_operand(2), instruction.get_operand(1)) {
+        (Some(Either::Right(then_block)), Some(Either::Right(else_block))) => {
+            (then_block, else_block)
+        }
+        _ => {
+            return Err(eyre!(
+                "expected basic block targets for branch {:?}",
+                instruction
+            ))
+        }
+    };
+
+    let (conditional_block, merge_block, jump_when) = if branches_unconditionally_to(
+        then_block, else_block,
+    ) {
+        (then_block, else_block, false)
+    } else if branches_unconditionally_to(else_block, then_block) {
+        (else_block, then_block, true)
+    } else {
+        return Err(eyre!(
+                "a branch on a readout value must have one target which rejoins the other unconditionally; got {:?}",
+                instruction
+            ));
+    };
+
+    if merge_block
+        .get_first_instruction()
+        .map_or(false, |first| first.get_opcode() == InstructionOpcode::Phi)
+    {
+        return Err(eyre!(
+            "phi instructions are not supported where control flow rejoins after a branch on a readout value: {}",
+            merge_block.get_name().to_string_lossy()
+        ));
+    }
+
+    // A flipped readout holds the complement of the measured value, so the jump condition is inverted.
+    let jump_when = jump_when != context.options.flip_readout;
+
+    let label = format!(
+        "qir_conditional_end_{}",
+        pattern_context.classical_control_branches.len()
+    );
+    let condition = MemoryReference {
+        name: String::from("ro"),
+        index: ro_index,
+    };
+
+    pattern_context.quil_program.add_instruction(if jump_when {
+        quil_rs::instruction::Instruction::JumpWhen(quil_rs::instruction::JumpWhen {
+            target: label.clone(),
+            condition,
+        })
+    } else {
+        quil_rs::instruction::Instruction::JumpUnless(quil_rs::instruction::JumpUnless {
+            target: label.clone(),
+            condition,
+        })
+    });
+
+    let mut next_instruction = conditional_block.get_first_instruction();
+    while let Some(conditional_instruction) = next_instruction {
+        if Some(conditional_instruction) == conditional_block.get_terminator() {
+            break;
+        }
+
+        if let Some((pattern_instruction, _)) =
+            quantum_instruction(context, pattern_context, conditional_instruction)?
+        {
+            next_instruction = pattern_instruction;
+        } else {
+            return Err(eyre!(
+                "only quantum instructions may be conditioned on a readout value; found {:?}",
+                conditional_instruction
+            ));
+        }
+    }
+
+    pattern_context
+        .quil_program
+        .add_instruction(quil_rs::instruction::Instruction::Label(
+            quil_rs::instruction::Label(label),
+        ));
+    pattern_context
+        .classical_control_branches
+        .push(ClassicalControlBranch {
+            branch_instruction: instruction,
+            merge_block,
+        });
+
+    Ok(Some((merge_block.get_first_instruction(), ())))
+}
+
+/// Match an unconditional branch, within the shot count loop, into a loop with a constant trip
+/// count which applies quantum intrinsics, such as one applying a gate to each of a range of qubits
+/// (see [`get_gate_loop`]). The quantum instructions of the loop are transpiled once per iteration,
+/// with the loop's `phi` values resolved for that iteration, so that:
+///
+/// ```llvm
+/// loop:
+///   %i = phi i64 [ 0, %body ], [ %next, %loop ]
+///   %qubit = inttoptr i64 %i to %Qubit*
+///   tail call void @__quantum__qis__h__body(%Qubit* %qubit)
+///   %next = add nuw nsw i64 %i, 1
+///   %done = icmp eq i64 %next, 3
+///   br i1 %done, label %exit, label %loop
+/// ```
+///
+/// becomes `H 0`, `H 1`, `H 2`. The loop itself is left in the patched module, where it no longer
+/// makes any quantum calls.
+///
+/// If matched, scanning continues at the first instruction of the block in which the loop is left.
+pub(crate) fn unrolled_gate_loop<'ctx>(
+    context: &QCSCompilerContext<'ctx>,
+    pattern_context: &mut ShotCountPatternMatchContext<'ctx>,
+    instruction: InstructionValue<'ctx>,
+) -> Result<PatternResult<'ctx, ()>> {
+    let gate_loop =
+        match get_gate_loop(instruction, context.options.limits.max_unrolled_iterations)? {
+            Some(gate_loop) => gate_loop,
+            None => return Ok(None),
+        };
+
+    let readout_count = pattern_context.readout_instruction_mapping.len();
+    let mut removal_count = None;
+    for bindings in gate_loop.iterations {
+        pattern_context.loop_bindings = bindings;
+
+        let mut next_instruction = gate_loop.block.get_first_instruction();
+        while let Some(loop_instruction) = next_instruction {
+            if Some(loop_instruction) == gate_loop.block.get_terminator() {
+                break;
+            }
+
+            if let Some((pattern_instruction, _)) =
+                quantum_instruction(context, pattern_context, loop_instruction)?
+            {
+                next_instruction = pattern_instruction;
+            } else if loop_instruction.get_opcode() == InstructionOpcode::Call {
+                return Err(eyre!(
+                    "only quantum instructions may be called within an unrolled loop; found {:?}",
+                    loop_instruction
+                ));
+            } else {
+                next_instruction = loop_instruction.get_next_instruction();
+            }
+        }
+
+        // Every iteration removes the same calls from the loop, which may only be removed once.
+        match removal_count {
+            Some(count) => pattern_context.instructions_to_remove.truncate(count),
+            None => removal_count = Some(pattern_context.instructions_to_remove.len()),
+        }
+    }
+    pattern_context.loop_bindings.clear();
+
+    if pattern_context.readout_instruction_mapping.len() != readout_count {
+        return Err(eyre!(
+            "readout values may not be read within an unrolled loop: {}",
+            gate_loop.block.get_name().to_string_lossy()
+        ));
+    }
+
+    pattern_context.unrolled_blocks.push(gate_loop.block);
+
+    Ok(Some((gate_loop.exit_block.get_first_instruction(), ())))
+}
+
+/// Whether the given block ends with an unconditional branch to `target`.
+fn branches_unconditionally_to(block: BasicBlock, target: BasicBlock) -> bool {
+    block.get_terminator().map_or(false, |terminator| {
+        terminator.get_opcode() == InstructionOpcode::Br
+            && terminator.get_num_operands() == 1
+            && terminator.get_operand(0) == Some(Either::Right(target))
+    })
+}
+
 macro_rules! match_qis_argument {
     ($variant:ident, $arguments:expr, $index:expr, $function_name:expr) => {{
         use OperationArgument::*;
 
         match $arguments.get($index) {
             Some($variant(contents)) => Ok(contents),
-            other => Err(eyre!(
+            other => Err(KindError::InvalidArgument(format!(
                 "expected argument {} of {} to be of type {:?}; got {:?}",
                 $index,
                 $function_name,
                 stringify!($variant),
                 other
-            )),
+            ))),
         }
     }};
 }
@@ -395,8 +805,13 @@ fn get_quil_parameter_expression<'ctx>(
     pattern_context: &mut ShotCountPatternMatchContext<'ctx>,
     float_value: FloatValue<'ctx>,
 ) -> Expression {
+    let float_value = fold_constant_select(float_value);
     if let Some((constant, _)) = float_value.get_constant() {
-        return Expression::Number(constant.into());
+        return build_constant_expression(
+            constant,
+            pattern_context.float_precision,
+            pattern_context.symbolic_pi,
+        );
     }
 
     let index = get_quil_parameter_index(pattern_context, float_value);
@@ -406,16 +821,40 @@ fn get_quil_parameter_expression<'ctx>(
     })
 }
 
+/// Given a `FloatValue` to be used as the parameter to a gate, return the Quil expression for that
+/// parameter multiplied by `factor`. Constants are multiplied before they are written out.
+fn get_scaled_quil_parameter_expression<'ctx>(
+    pattern_context: &mut ShotCountPatternMatchContext<'ctx>,
+    float_value: FloatValue<'ctx>,
+    factor: f64,
+) -> Expression {
+    let float_value = fold_constant_select(float_value);
+    if let Some((constant, _)) = float_value.get_constant() {
+        return build_constant_expression(
+            factor * constant,
+            pattern_context.float_precision,
+            pattern_context.symbolic_pi,
+        );
+    }
+
+    Expression::Infix {
+        left: Box::new(Expression::Number(factor.into())),
+        operator: InfixOperator::Star,
+        right: Box::new(get_quil_parameter_expression(pattern_context, float_value)),
+    }
+}
+
 /// Given a `FloatValue` to be used as the parameter to a gate, return the index within the
 /// Quil `MemoryReference` that should be used to store this parameter's value.
 pub(crate) fn get_quil_parameter_index<'ctx>(
     pattern_context: &mut ShotCountPatternMatchContext<'ctx>,
     float_value: FloatValue<'ctx>,
 ) -> usize {
+    let float_value = resolve_loop_invariant_phi(pattern_context, float_value);
     if let Some(index) = pattern_context
         .parameters
         .iter()
-        .position(|el| el == &float_value)
+        .position(|el| is_same_parameter(*el, float_value))
     {
         index
     } else {
@@ -424,6 +863,49 @@ pub(crate) fn get_quil_parameter_index<'ctx>(
     }
 }
 
+/// When the shot count loop is nested within an outer loop, such as a parameter sweep, a parameter
+/// set by the outer loop may reach a gate through a `phi` at the start of the shot count block which
+/// only carries that value around the loop:
+///
+/// ```llvm
+/// %theta.shot = phi double [ %theta, %sweep ], [ %theta.shot, %body ]
+/// ```
+///
+/// The parameter is set on the executable before any shot is run, where that `phi` is not yet
+/// available, so resolve it to the value flowing in from the outer loop. Any other value is
+/// returned unchanged.
+fn resolve_loop_invariant_phi<'ctx>(
+    pattern_context: &ShotCountPatternMatchContext<'ctx>,
+    float_value: FloatValue<'ctx>,
+) -> FloatValue<'ctx> {
+    let loop_block = pattern_context
+        .initial_instruction
+        .and_then(InstructionValue::get_parent);
+    let phi = match float_value.as_instruction() {
+        Some(instruction)
+            if instruction.get_opcode() == InstructionOpcode::Phi
+                && instruction.get_parent() == loop_block =>
+        {
+            instruction
+        }
+        _ => return float_value,
+    };
+
+    let mut incoming_values = (0..phi.get_num_operands())
+        .filter_map(|index| match phi.get_operand(index) {
+            Some(Either::Left(BasicValueEnum::FloatValue(value))) if value != float_value => {
+                Some(value)
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    incoming_values.dedup();
+    match incoming_values.as_slice() {
+        [value] => *value,
+        _ => float_value,
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 fn add_gate_instruction<'ctx>(
     pattern_context: &mut ShotCountPatternMatchContext<'ctx>,
@@ -479,8 +961,6 @@ lazy_static! {
         r"^__quantum__qis__(?P<operation>[^_]+)(?P<controlled>__ctl)?(?P<adjoint>__adj)?(__body)?$"
     )
     .unwrap();
-    static ref RT_RECORD_OUTPUT_INTRINSIC_REGEX: Regex =
-        Regex::new("^__quantum__rt__(?P<record_type>.+)_record_output$").unwrap();
 }
 
 pub(crate) fn rt_record_instruction<'ctx>(
@@ -488,61 +968,15 @@ pub(crate) fn rt_record_instruction<'ctx>(
     pattern_context: &mut ShotCountPatternMatchContext<'ctx>,
     instruction: InstructionValue<'ctx>,
 ) -> Result<PatternResult<'ctx, ()>> {
-    match instruction.get_opcode() {
-        inkwell::values::InstructionOpcode::Call => {
-            let function_target_name = get_called_function_name(instruction)?;
-
-            if let Some(function_name) = function_target_name {
-                if let Some(captures) = RT_RECORD_OUTPUT_INTRINSIC_REGEX.captures(&function_name) {
-                    let record_type = &captures["record_type"];
-
-                    match record_type {
-                        "result" => {
-                            let arguments = get_qis_function_arguments(context, instruction)?;
-                            if let Some(OperationArgument::Result(result_index)) = arguments.get(0)
-                            {
-                                let next_ro_index =
-                                    pattern_context.read_result_mapping.len() as u64;
-                                let index = pattern_context.read_result_mapping.entry(*result_index).or_insert_with(|| {
-                                    log::info!("Result index {} was read but was never the target of a measurement operation, so recorded output value will always be 0", result_index);
-                                    next_ro_index
-                                });
-                                pattern_context
-                                    .recorded_output
-                                    .push(RecordedOutput::ResultReadoutOffset(*index));
-                            } else {
-                                return Err(eyre!(
-                                    "malformed or missing arguments for: __quantum_rt__{}_record_output",
-                                    record_type
-                                ));
-                            }
-                        }
-                        "bool" | "integer" | "double" => {
-                            return Err(eyre!("unimplemented record type: {}", record_type));
-                        }
-                        "tuple_start" => pattern_context
-                            .recorded_output
-                            .push(RecordedOutput::TupleStart),
-                        "tuple_end" => pattern_context
-                            .recorded_output
-                            .push(RecordedOutput::TupleEnd),
-                        "array_start" => pattern_context
-                            .recorded_output
-                            .push(RecordedOutput::ArrayStart),
-                        "array_end" => pattern_context
-                            .recorded_output
-                            .push(RecordedOutput::ArrayEnd),
-                        _ => {
-                            return Ok(None);
-                        }
-                    }
-                    return Ok(Some((instruction.get_next_instruction(), ())));
-                }
-            }
-            Ok(None)
-        }
-        _ => Ok(None),
-    }
+    let loop_bindings = &pattern_context.loop_bindings;
+    let matched = pattern_context.recording.record_call(
+        context,
+        instruction,
+        &mut pattern_context.read_result_mapping,
+        &pattern_context.readout_instruction_mapping,
+        || get_bound_qis_function_arguments(context, instruction, loop_bindings),
+    )?;
+    Ok(matched.then(|| (instruction.get_next_instruction(), ())))
 }
 
 #[allow(clippy::too_many_lines)]
@@ -561,7 +995,11 @@ pub(crate) fn quantum_instruction<'ctx>(
                     let adjoint = captures.name("adjoint").is_some();
                     let controlled = captures.name("controlled").is_some();
 
-                    let arguments = get_qis_function_arguments(context, instruction)?;
+                    let arguments = get_bound_qis_function_arguments(
+                        context,
+                        instruction,
+                        &pattern_context.loop_bindings,
+                    )?;
 
                     let matched = match operation {
                         "swap" => {
@@ -738,33 +1176,98 @@ pub(crate) fn quantum_instruction<'ctx>(
                             )?;
                             true
                         }
+                        "exp" if !controlled => {
+                            let paulis =
+                                match_qis_argument!(Array, arguments, 0, function_name.as_str())?;
+                            let theta = *match_qis_argument!(
+                                Parameter,
+                                arguments,
+                                1,
+                                function_name.as_str()
+                            )?;
+                            let qubits =
+                                match_qis_argument!(Array, arguments, 2, function_name.as_str())?;
+                            let terms = get_pauli_terms(paulis, qubits)?;
+
+                            // exp(iθZ) is RZ(-2θ), and its adjoint RZ(2θ)
+                            let factor = if adjoint { 2.0 } else { -2.0 };
+                            let angle = get_scaled_quil_parameter_expression(
+                                pattern_context,
+                                theta,
+                                factor,
+                            );
+                            for instruction in build_pauli_exponential(&terms, angle) {
+                                pattern_context.quil_program.add_instruction(instruction);
+                            }
+
+                            true
+                        }
+                        "measure" => {
+                            let paulis =
+                                match_qis_argument!(Array, arguments, 0, function_name.as_str())?;
+                            let qubits =
+                                match_qis_argument!(Array, arguments, 1, function_name.as_str())?;
+                            let result =
+                                *match_qis_argument!(Result, arguments, 2, function_name.as_str())?;
+                            let terms = get_pauli_terms(paulis, qubits)?;
+
+                            let ro_buffer_index = assign_readout_offset(
+                                context,
+                                &mut pattern_context.read_result_mapping,
+                                result,
+                            )?;
+                            for instruction in build_joint_measurement(
+                                &terms,
+                                MemoryReference {
+                                    name: String::from("ro"),
+                                    index: ro_buffer_index,
+                                },
+                                context.options.flip_readout,
+                            )? {
+                                pattern_context.quil_program.add_instruction(instruction);
+                            }
+
+                            true
+                        }
                         "mz" => {
                             let qubit =
                                 *match_qis_argument!(Qubit, arguments, 0, function_name.as_str())?;
                             let result =
                                 *match_qis_argument!(Result, arguments, 1, function_name.as_str())?;
 
-                            // Result indices may be sparse rather than increasing monotonically from 0.
-                            // If used naively (i.e. %Result 5 as `ro[5]`), this would result in sparse, suboptimal allocation
-                            // in the readout data fetched following execution. So, instead, we assign Result indices to
-                            // monotonically increasing `ro` region offsets so as to keep `ro` dense.
-                            let next_ro_index = pattern_context.read_result_mapping.len() as u64;
-                            let ro_buffer_index = pattern_context
-                                .read_result_mapping
-                                .entry(result)
-                                .or_insert_with(|| next_ro_index);
+                            let ro_buffer_index = assign_readout_offset(
+                                context,
+                                &mut pattern_context.read_result_mapping,
+                                result,
+                            )?;
 
+                            let flip = quil_rs::instruction::Instruction::Gate(
+                                quil_rs::instruction::Gate {
+                                    name: String::from("X"),
+                                    parameters: vec![],
+                                    qubits: vec![Qubit::Fixed(qubit)],
+                                    modifiers: vec![],
+                                },
+                            );
+                            // When flipping readout, the qubit is flipped back after measurement so
+                            // that later operations see the state they would have without the flip.
+                            if context.options.flip_readout {
+                                pattern_context.quil_program.add_instruction(flip.clone());
+                            }
                             pattern_context.quil_program.add_instruction(
                                 quil_rs::instruction::Instruction::Measurement(
                                     quil_rs::instruction::Measurement {
                                         target: Some(MemoryReference {
                                             name: String::from("ro"),
-                                            index: *ro_buffer_index,
+                                            index: ro_buffer_index,
                                         }),
                                         qubit: Qubit::Fixed(qubit),
                                     },
                                 ),
                             );
+                            if context.options.flip_readout {
+                                pattern_context.quil_program.add_instruction(flip);
+                            }
 
                             true
                         }
@@ -778,7 +1281,11 @@ pub(crate) fn quantum_instruction<'ctx>(
                         Ok(None)
                     }
                 } else if function_name == "__quantum__qis__read_result__body" {
-                    let arguments = get_qis_function_arguments(context, instruction)?;
+                    let arguments = get_bound_qis_function_arguments(
+                        context,
+                        instruction,
+                        &pattern_context.loop_bindings,
+                    )?;
                     if let Some(OperationArgument::Result(result_index)) = arguments.get(0) {
                         let ro_index = pattern_context.read_result_mapping.get(result_index).ok_or_else(|| eyre!("Result index {} was never the target of a measurement operation", result_index))?;
                         pattern_context
@@ -790,6 +1297,26 @@ pub(crate) fn quantum_instruction<'ctx>(
                     }
                     pattern_context.instructions_to_remove.push(instruction);
                     Ok(Some((instruction.get_next_instruction(), ())))
+                } else if let Some(captures) = QIS_FRAME_INTRINSIC_REGEX.captures(&function_name) {
+                    let arguments = get_bound_qis_function_arguments(
+                        context,
+                        instruction,
+                        &pattern_context.loop_bindings,
+                    )?;
+                    let value =
+                        *match_qis_argument!(Parameter, arguments, 0, function_name.as_str())?;
+                    let qubit = *match_qis_argument!(Qubit, arguments, 1, function_name.as_str())?;
+                    let value = get_quil_parameter_expression(pattern_context, value);
+                    pattern_context
+                        .quil_program
+                        .add_instruction(build_frame_instruction(
+                            &captures["mutation"],
+                            &captures["attribute"],
+                            qubit,
+                            value,
+                        )?);
+                    pattern_context.instructions_to_remove.push(instruction);
+                    Ok(Some((instruction.get_next_instruction(), ())))
                 } else {
                     Ok(None)
                 }
@@ -12,34 +12,68 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::convert::TryInto;
+
+use either::Either;
 use eyre::{eyre, ContextCompat, Result};
 use inkwell::{
     basic_block::BasicBlock,
-    values::{AnyValue, FunctionValue, InstructionValue},
+    module::Linkage,
+    values::{
+        AnyValue, BasicValue, FloatValue, FunctionValue, InstructionOpcode, InstructionValue,
+        IntValue, PointerValue,
+    },
+    IntPredicate,
 };
 use log::{debug, info};
-use quil_rs::instruction::Vector;
 
 use crate::interop::{
     call,
+    cfg::{blocks_using, get_loop_exit_target, reaches_any},
+    compat::{build_load, default_address_space},
     entrypoint::get_entry_function,
-    instruction::{
-        get_conditional_branch_else_target, remove_instructions_in_safe_order,
+    rewrite::{
+        copy_name_and_metadata, remove_instructions_in_safe_order,
         replace_conditional_branch_target, replace_phi_clauses,
     },
 };
-use crate::{context::QCSCompilerContext, transform::PARAMETER_MEMORY_REGION_NAME};
+use crate::{
+    context::{context::EmittedProgram, values, QCSCompilerContext},
+    error::KindError,
+    quil_builder::{
+        declare_params, declare_readout, initial_rewiring, prepend_pragmas, prepend_reset,
+    },
+    transform::{
+        budget::apply_shot_count_policy, calls::transform_call_graph, dump::BlockDump,
+        get_functions_to_transform, layout::InjectedBlock, liveness::handle_reused_qubits,
+        ordering::apply_measurement_order, peephole::optimize_program,
+        postprocess::apply_post_processors, prepend_calibrations,
+    },
+    CallingConvention,
+};
 
 use super::pattern::ShotCountPatternMatchContext;
 
+/// The name of the function which populates the executable cache as the program starts.
+pub(crate) const FN_NAME_POPULATE_EXECUTABLE_ARRAY: &str = "populate_executable_array";
+
+/// The name of the function which frees the executable cache as the program exits.
+pub(crate) const FN_NAME_FREE_EXECUTABLE_ARRAY: &str = "free_executable_array";
+
+/// The name of the wrapper through which [`FN_NAME_FREE_EXECUTABLE_ARRAY`] is registered as a global
+/// destructor when it does not use the C calling convention.
+const FN_NAME_FREE_EXECUTABLE_ARRAY_AT_EXIT: &str = "free_executable_array_at_exit";
+
+/// The priority of the global destructor freeing the executable cache, the default for destructors
+/// which need not run in any particular order.
+const GLOBAL_DESTRUCTOR_PRIORITY: u64 = 65535;
+
 /// Build and insert an LLVM function which performs up-front translation of
 /// all Quil programs used in the module and stores them in an array referred
 /// to as the "executable cache".
 pub(crate) fn build_populate_executable_cache_function<'ctx>(
     context: &mut QCSCompilerContext<'ctx>,
 ) -> Result<FunctionValue<'ctx>> {
-    const FN_NAME_POPULATE_EXECUTABLE_ARRAY: &str = "populate_executable_array";
-
     if let Some(existing_function) = context
         .module
         .get_function(FN_NAME_POPULATE_EXECUTABLE_ARRAY)
@@ -53,46 +87,66 @@ pub(crate) fn build_populate_executable_cache_function<'ctx>(
             populate_executable_array_function_type,
             None,
         );
+        populate_executable_array_function
+            .set_call_conventions(context.options.helper_calling_convention.llvm_id());
         let basic_block = context
             .base_context
             .append_basic_block(populate_executable_array_function, "entry");
 
         context.builder.position_at_end(basic_block);
 
-        let actual_executable_cache = context
-            .builder
-            .build_call(
-                context.values.create_executable_cache(),
-                &[context
-                    .base_context
-                    .i32_type()
-                    .const_int(context.quil_programs.len() as u64, false)
-                    .into()],
-                "",
-            )
-            .try_as_basic_value()
-            .left()
-            .ok_or_else(|| eyre!("create_executable_cache does not have a return value"))?
-            .into_pointer_value();
+        let create_executable_cache_function = values::build_create_executable_cache_function(
+            context.base_context,
+            &context.builder,
+            &context.module,
+            &context.types,
+            &context.options.runtime_flavor,
+            context.options.user_context,
+        );
+        let actual_executable_cache: PointerValue = call::build_runtime_call(
+            context,
+            create_executable_cache_function,
+            &[context
+                .base_context
+                .i32_type()
+                .const_int(context.cached_programs().count() as u64, false)
+                .into()],
+        )
+        .try_as_basic_value()
+        .left()
+        .ok_or_else(|| eyre!("create_executable_cache does not have a return value"))?
+        .try_into()
+        .map_err(|_| eyre!("create_executable_cache does not return a pointer"))?;
 
         context.builder.build_store(
-            context.values.executable_cache().as_pointer_value(),
+            context.values.executable_cache()?.as_pointer_value(),
             actual_executable_cache,
         );
 
-        for index in 0..context.quil_programs.len() {
-            let program_text = context.quil_programs[index].to_string(true);
-
+        let add_executable_cache_item_function = values::build_add_executable_cache_item_function(
+            context.base_context,
+            &context.builder,
+            &context.module,
+            &context.types,
+            &context.options.runtime_flavor,
+            context.options.user_context,
+        );
+        let program_texts = context
+            .cached_programs()
+            .map(|program| program.to_string(true))
+            .collect::<Vec<_>>();
+        for (index, program_text) in program_texts.iter().enumerate() {
             let quil_program_global_string = unsafe {
                 // NOTE: this segfaults if the builder is not already positioned within a basic block
                 // see https://github.com/TheDan64/inkwell/issues/32
                 context
                     .builder
-                    .build_global_string(&program_text, "quil_program")
+                    .build_global_string(program_text, "quil_program")
             };
 
-            context.builder.build_call(
-                context.values.add_executable_cache_item(),
+            call::build_runtime_call(
+                context,
+                add_executable_cache_item_function,
                 &[
                     actual_executable_cache.into(),
                     context
@@ -105,7 +159,6 @@ pub(crate) fn build_populate_executable_cache_function<'ctx>(
                         .const_cast(context.types.string())
                         .into(),
                 ],
-                "",
             );
         }
 
@@ -115,13 +168,90 @@ pub(crate) fn build_populate_executable_cache_function<'ctx>(
     }
 }
 
-/// Mutate a context such that all contiguous instructions which may be transpiled by `transpile_instruction`
-/// are inlined and executed using a shared library call.
-#[allow(dead_code)]
-pub(crate) fn transpile_module(context: &mut QCSCompilerContext) -> Result<()> {
-    let entrypoint_function = get_entry_function(&context.module)
-        .ok_or_else(|| eyre!("entrypoint not found in module"))?;
-    transpile_function(context, entrypoint_function, &[])?;
+/// Build and insert an LLVM function which frees the executable cache, and with it every cached
+/// executable, leaving the cache empty. It is safe to call more than once, and is intended to be
+/// called as the program exits.
+pub(crate) fn build_free_executable_cache_function<'ctx>(
+    context: &mut QCSCompilerContext<'ctx>,
+) -> Result<FunctionValue<'ctx>> {
+    if let Some(existing_function) = context.module.get_function(FN_NAME_FREE_EXECUTABLE_ARRAY) {
+        return Ok(existing_function);
+    }
+
+    let free_executable_array_function = context.module.add_function(
+        FN_NAME_FREE_EXECUTABLE_ARRAY,
+        context.base_context.void_type().fn_type(&[], false),
+        None,
+    );
+    free_executable_array_function
+        .set_call_conventions(context.options.helper_calling_convention.llvm_id());
+    let entry_basic_block = context
+        .base_context
+        .append_basic_block(free_executable_array_function, "entry");
+    let free_basic_block = context
+        .base_context
+        .append_basic_block(free_executable_array_function, "free");
+    let exit_basic_block = context
+        .base_context
+        .append_basic_block(free_executable_array_function, "exit");
+
+    let executable_cache_type = context
+        .types
+        .executable_cache(&context.module)
+        .ptr_type(default_address_space());
+
+    context.builder.position_at_end(entry_basic_block);
+    let executable_cache: PointerValue = build_load(
+        &context.builder,
+        executable_cache_type.into(),
+        context.values.executable_cache()?.as_pointer_value(),
+        "",
+    )
+    .try_into()
+    .map_err(|_| eyre!("expected the executable cache to hold a pointer"))?;
+    let is_null = context.builder.build_is_null(executable_cache, "");
+    context
+        .builder
+        .build_conditional_branch(is_null, exit_basic_block, free_basic_block);
+
+    context.builder.position_at_end(free_basic_block);
+    let free_executable_cache_function = values::build_free_executable_cache_function(
+        context.base_context,
+        &context.builder,
+        &context.module,
+        &context.types,
+        &context.options.runtime_flavor,
+        context.options.user_context,
+    );
+    call::build_runtime_call(
+        context,
+        free_executable_cache_function,
+        &[executable_cache.into()],
+    );
+    context.builder.build_store(
+        context.values.executable_cache()?.as_pointer_value(),
+        executable_cache_type.const_null(),
+    );
+    context.builder.build_unconditional_branch(exit_basic_block);
+
+    context.builder.position_at_end(exit_basic_block);
+    context.builder.build_return(None);
+
+    Ok(free_executable_array_function)
+}
+
+/// Call the function populating the executable cache on entry to `entrypoint_function`, if any Quil
+/// program was cached while transforming the module. Otherwise, the cache would go unused, so no
+/// such function is built and the `executable_cache` global is removed from the module.
+pub(crate) fn build_executable_cache<'ctx>(
+    context: &mut QCSCompilerContext<'ctx>,
+    entrypoint_function: FunctionValue<'ctx>,
+) -> Result<()> {
+    if context.cached_programs().next().is_none() {
+        context.values.remove_unused_executable_cache();
+        return Ok(());
+    }
+
     let populate_function = build_populate_executable_cache_function(context)?;
 
     let entry_basic_block = entrypoint_function
@@ -135,35 +265,164 @@ pub(crate) fn transpile_module(context: &mut QCSCompilerContext) -> Result<()> {
         None => context.builder.position_at_end(entry_basic_block),
     };
 
-    context.builder.build_call(populate_function, &[], "");
+    context
+        .builder
+        .build_call(populate_function, &[], "")
+        .set_call_convention(populate_function.get_call_conventions());
 
-    Ok(())
+    let free_function = build_free_executable_cache_function(context)?;
+    register_global_destructor(context, free_function)
 }
 
-pub(crate) fn transpile_function<'ctx>(
+/// Register `function` in `llvm.global_dtors`, so that it also runs when a host which loads the
+/// module and calls its entrypoint directly, rather than through the generated `main`, exits or
+/// unloads it. Destructors are called with the C calling convention, so a function of another
+/// convention is registered through an internal wrapper.
+fn register_global_destructor<'ctx>(
     context: &mut QCSCompilerContext<'ctx>,
     function: FunctionValue<'ctx>,
-    visited_functions: &[&str],
-) -> eyre::Result<()> {
-    for current_basic_block in function.get_basic_blocks() {
-        transpile_basic_block(context, current_basic_block, visited_functions)?;
+) -> Result<()> {
+    let destructor = if function.get_call_conventions() == CallingConvention::C.llvm_id() {
+        function
+    } else {
+        let wrapper = context.module.add_function(
+            FN_NAME_FREE_EXECUTABLE_ARRAY_AT_EXIT,
+            context.base_context.void_type().fn_type(&[], false),
+            Some(Linkage::Internal),
+        );
+        context
+            .builder
+            .position_at_end(context.base_context.append_basic_block(wrapper, "entry"));
+        context
+            .builder
+            .build_call(function, &[], "")
+            .set_call_convention(function.get_call_conventions());
+        context.builder.build_return(None);
+        wrapper
+    };
+
+    // Linking appends the entry to any destructors the module already registers.
+    let destructors = context.base_context.create_module("qcs_global_dtors");
+    let declaration =
+        destructors.add_function(destructor.get_name().to_str()?, destructor.get_type(), None);
+    let i32_type = context.base_context.i32_type();
+    let i8_pointer_type = context
+        .base_context
+        .i8_type()
+        .ptr_type(default_address_space());
+    let declaration_pointer = declaration.as_global_value().as_pointer_value();
+    let entry_type = context.base_context.struct_type(
+        &[
+            i32_type.into(),
+            declaration_pointer.get_type().into(),
+            i8_pointer_type.into(),
+        ],
+        false,
+    );
+    let entry = entry_type.const_named_struct(&[
+        i32_type.const_int(GLOBAL_DESTRUCTOR_PRIORITY, false).into(),
+        declaration_pointer.into(),
+        i8_pointer_type.const_null().into(),
+    ]);
+    let global = destructors.add_global(entry_type.array_type(1), None, "llvm.global_dtors");
+    global.set_linkage(Linkage::Appending);
+    global.set_initializer(&entry_type.const_array(&[entry]));
+
+    context
+        .module
+        .link_in_module(destructors)
+        .map_err(|e| eyre!(e.to_string()).wrap_err("failed to register a global destructor"))
+}
+
+/// Mutate a context such that all contiguous instructions which may be transpiled by `transpile_instruction`
+/// are inlined and executed using a shared library call.
+#[allow(dead_code)]
+pub(crate) fn transpile_module(context: &mut QCSCompilerContext) -> Result<()> {
+    let entrypoint_function = get_entry_function(&context.module).ok_or_else(|| {
+        KindError::MissingEntrypoint(String::from("entrypoint not found in module"))
+    })?;
+    for function in get_functions_to_transform(context, entrypoint_function)? {
+        transform_call_graph(context, function, transpile_basic_block)?;
     }
-    Ok(())
+    build_executable_cache(context, entrypoint_function)
 }
 
 pub(crate) fn transpile_basic_block<'ctx>(
     context: &mut QCSCompilerContext<'ctx>,
     basic_block: BasicBlock<'ctx>,
-    visited_functions: &[&str],
-) -> eyre::Result<()> {
-    let pattern_context = ShotCountPatternMatchContext::from_basic_block(
-        context,
-        basic_block,
-        visited_functions,
-        transpile_function,
-    )?;
+) -> eyre::Result<Vec<FunctionValue<'ctx>>> {
+    let mut dump = BlockDump::begin(context, basic_block);
+    let pattern_context =
+        ShotCountPatternMatchContext::from_basic_block(context, basic_block, true)?;
+    let called_functions = pattern_context.called_functions.clone();
+    if let Some(dump) = &mut dump {
+        dump.write_match(&pattern_context)?;
+    }
 
-    insert_quil_program(context, pattern_context, basic_block)
+    insert_quil_program(context, pattern_context, basic_block, false)?;
+    dump.map_or(Ok(()), BlockDump::finish)?;
+    Ok(called_functions)
+}
+
+/// A parameter chosen at runtime by a `select` within the shot count loop, such as
+///
+/// ```llvm
+/// %theta = select i1 %flag, double %a, double %b
+/// ```
+///
+/// is not yet available where parameters are set on the executable, before any shot is run. So
+/// long as its condition and both candidates are computed before the loop, rebuild the `select` at
+/// the builder's position, where it chooses the same value. Any other value is returned unchanged.
+fn hoist_parameter_select<'ctx>(
+    context: &QCSCompilerContext<'ctx>,
+    value: FloatValue<'ctx>,
+    loop_blocks: &[BasicBlock<'ctx>],
+) -> Result<FloatValue<'ctx>> {
+    let select = match value.as_instruction() {
+        Some(instruction)
+            if instruction.get_opcode() == InstructionOpcode::Select
+                && instruction
+                    .get_parent()
+                    .map_or(false, |block| loop_blocks.contains(&block)) =>
+        {
+            instruction
+        }
+        _ => return Ok(value),
+    };
+
+    let operands = (0..3)
+        .map(|index| match select.get_operand(index) {
+            Some(Either::Left(operand))
+                if !operand
+                    .as_instruction_value()
+                    .and_then(InstructionValue::get_parent)
+                    .map_or(false, |block| loop_blocks.contains(&block)) =>
+            {
+                Ok(operand)
+            }
+            _ => Err(eyre!(
+                "a gate parameter chosen by `{}` must select between values computed before the shot count loop, on a condition computed before it",
+                select.print_to_string().to_string().trim()
+            )),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let condition: IntValue = operands[0].try_into().map_err(|_| {
+        eyre!(
+            "expected the condition of `{}` to be an integer",
+            select.print_to_string().to_string().trim()
+        )
+    })?;
+    context
+        .builder
+        .build_select(condition, operands[1], operands[2], "")
+        .try_into()
+        .map_err(|_| {
+            eyre!(
+                "expected `{}` to select a floating point gate parameter",
+                select.print_to_string().to_string().trim()
+            )
+        })
 }
 
 /// Insert the quil program which has been collected from a `BasicBlock` (if any):
@@ -171,11 +430,16 @@ pub(crate) fn transpile_basic_block<'ctx>(
 /// 1. Create a global variable with the program text
 /// 2. Insert a shared library call to execute that program text with shot count
 /// 3. Remove all of the relevant instructions from the program
+///
+/// If `hoist_executable` is set, the executable is created once in the entry block of the enclosing
+/// function rather than each time the program is executed; only parameter updates and execution
+/// remain within the execution block.
 #[allow(clippy::too_many_lines, clippy::unnecessary_wraps)]
 pub(crate) fn insert_quil_program<'ctx, 'p: 'ctx>(
     context: &mut QCSCompilerContext<'ctx>,
     pattern_context: ShotCountPatternMatchContext<'p>,
     basic_block: BasicBlock,
+    hoist_executable: bool,
 ) -> eyre::Result<()> {
     if let Some((program, shots)) = pattern_context.get_program_data() {
         debug!(
@@ -184,60 +448,49 @@ pub(crate) fn insert_quil_program<'ctx, 'p: 'ctx>(
             program.to_string(true)
         );
 
-        let mut program = program.clone();
-
-        program.add_instruction(quil_rs::instruction::Instruction::Declaration(
-            quil_rs::instruction::Declaration {
-                name: String::from("ro"),
-                size: Vector {
-                    data_type: quil_rs::instruction::ScalarType::Bit,
-                    length: pattern_context.read_result_mapping.len() as u64,
-                },
-                sharing: None,
-            },
-        ));
+        let program = if context.options.optimize_quil {
+            optimize_program(&context.options, program, &[]).0
+        } else {
+            program.clone()
+        };
+        let (mut program, _) =
+            apply_measurement_order(context.options.measurement_order, program, vec![])?;
 
-        if !pattern_context.get_dynamic_parameters().is_empty() {
-            program.add_instruction(quil_rs::instruction::Instruction::Declaration(
-                quil_rs::instruction::Declaration {
-                    name: String::from(PARAMETER_MEMORY_REGION_NAME),
-                    size: Vector {
-                        data_type: quil_rs::instruction::ScalarType::Real,
-                        length: pattern_context.get_dynamic_parameters().len() as u64,
-                    },
-                    sharing: None,
-                },
-            ));
-        }
+        declare_readout(&mut program, pattern_context.get_readout_length());
+        declare_params(
+            &mut program,
+            pattern_context.get_dynamic_parameters().len() as u64,
+        );
 
         if pattern_context.use_active_reset {
-            // Prepend a reset to the program via copy
-            let instructions = program.to_instructions(true);
-            let mut new_program = quil_rs::program::Program::new();
-            new_program.add_instruction(quil_rs::instruction::Instruction::Reset(
-                quil_rs::instruction::Reset { qubit: None },
-            ));
-            for instruction in instructions {
-                new_program.add_instruction(instruction);
-            }
-            program = new_program;
+            program = prepend_reset(program);
         }
 
+        program = handle_reused_qubits(context, program, basic_block);
+        program = prepend_calibrations(context, program);
+
         if let Some(rewiring_pragma) = &context.options.rewiring_pragma {
-            // Prepend a pragma to the program via copy
-            let instructions = program.to_instructions(true);
-            let mut new_program = quil_rs::program::Program::new();
-            new_program.add_instruction(quil_rs::instruction::Instruction::Pragma(
-                quil_rs::instruction::Pragma {
-                    name: String::from("INITIAL_REWIRING"),
-                    arguments: vec![format!("\"{}\"", rewiring_pragma.clone())],
-                    data: None,
-                },
-            ));
-            for instruction in instructions {
-                new_program.add_instruction(instruction);
-            }
-            program = new_program;
+            program = prepend_pragmas(program, [initial_rewiring(rewiring_pragma)]);
+        }
+
+        apply_post_processors(context, &mut program, basic_block)?;
+
+        let shots = apply_shot_count_policy(
+            context,
+            &program,
+            basic_block,
+            shots,
+            pattern_context.get_dynamic_parameters().len(),
+            pattern_context.get_readout_length(),
+        )?;
+        let chunks = context.options.shot_chunking.split(shots);
+        if chunks.len() > 1 {
+            info!(
+                "splitting the {} shots of basic block {} among {} executions",
+                shots,
+                basic_block.get_name().to_string_lossy(),
+                chunks.len()
+            );
         }
 
         // We write all the new instructions to a new basic block
@@ -246,18 +499,29 @@ pub(crate) fn insert_quil_program<'ctx, 'p: 'ctx>(
             &format!("{}_execution", basic_block.get_name().to_str()?),
         );
         basic_block.replace_all_uses_with(&execution_basic_block);
-        context.builder.position_at_end(execution_basic_block);
+        context.injected_blocks.push(InjectedBlock {
+            block: execution_basic_block,
+            origin: basic_block,
+            role: "execution",
+        });
 
-        let executable = if context.options.cache_executables {
-            let quil_program_index = context.quil_programs.len();
-            context.quil_programs.push(program);
+        if hoist_executable {
+            position_at_function_entry(context, basic_block)?;
+        } else {
+            context.builder.position_at_end(execution_basic_block);
+        }
 
+        let cache_index = context
+            .options
+            .cache_executables
+            .then(|| context.cached_programs().count());
+        let executable = if let Some(cache_index) = cache_index {
             call::get_executable(
                 context,
                 context
                     .base_context
                     .i32_type()
-                    .const_int(quil_program_index as u64, false),
+                    .const_int(cache_index as u64, false),
             )?
         } else {
             let program_text = program.to_string(true);
@@ -272,23 +536,59 @@ pub(crate) fn insert_quil_program<'ctx, 'p: 'ctx>(
             // Insert the shared library calls to send this program for execution
             call::executable_from_quil(context, quil_program_global_string.as_pointer_value())?
         };
+        context.emitted_programs.push(EmittedProgram {
+            program,
+            function: basic_block
+                .get_parent()
+                .map(|function| function.get_name().to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            block: basic_block.get_name().to_string_lossy().into_owned(),
+            shot_count: Some(shots),
+            recorded_output: pattern_context.recording.entries().to_vec(),
+            cache_index,
+        });
+
+        if chunks.len() == 1 {
+            call::wrap_in_shots(context, &executable, shots);
+        }
 
-        call::wrap_in_shots(context, &executable, shots);
+        context.builder.position_at_end(execution_basic_block);
 
+        // The shot count loop ends in a different block than it starts in when its body contains classical control flow.
+        let loop_end_block = pattern_context.loop_end_block.unwrap_or(basic_block);
         for (index, value) in pattern_context.parameters.iter().enumerate() {
-            call::set_param(context, &executable, index as u64, *value);
+            let value = hoist_parameter_select(context, *value, &[basic_block, loop_end_block])?;
+            call::set_param(context, &executable, index as u64, value);
         }
 
-        let execution_result = match &context.target {
-            crate::context::target::ExecutionTarget::Qpu(_) => {
-                call::execute_on_qpu(context, &executable)?
+        let mut execution_results = vec![];
+        for chunk in &chunks {
+            if chunks.len() > 1 {
+                call::wrap_in_shots(context, &executable, *chunk);
             }
-            crate::context::target::ExecutionTarget::Qvm => {
-                call::execute_on_qvm(context, &executable)?
-            }
-        };
+            let execution_result = if context.options.reuse_execution_results {
+                // The result is allocated once on entry to the function, and emptied before each
+                // execution refills it.
+                position_at_function_entry(context, basic_block)?;
+                let execution_result = call::create_execution_result(context)?;
+                context.builder.position_at_end(execution_basic_block);
+                call::reset_execution_result(context, &execution_result);
+                call::execute_into(context, &executable, &execution_result)?;
+                execution_result
+            } else {
+                match &context.target {
+                    crate::context::target::ExecutionTarget::Qpu(_) => {
+                        call::execute_on_qpu(context, &executable)?
+                    }
+                    crate::context::target::ExecutionTarget::Qvm => {
+                        call::execute_on_qvm(context, &executable)?
+                    }
+                }
+            };
 
-        call::panic_on_execution_result_failure(context, &execution_result);
+            call::panic_on_execution_result_failure(context, &execution_result);
+            execution_results.push(execution_result);
+        }
 
         // After execution we branch into the reduction block, which is everything left over after the
         // quantum instructions are removed.
@@ -303,55 +603,127 @@ pub(crate) fn insert_quil_program<'ctx, 'p: 'ctx>(
                 .ok_or_else(|| eyre!("Expected an initial instruction"))?,
         );
 
-        let shot_index = pattern_context
+        let shot_index: IntValue = pattern_context
             .initial_instruction
             .ok_or_else(|| eyre!("Expected an initial instruction"))?
             .as_any_value_enum()
-            .into_int_value();
+            .try_into()
+            .map_err(|_| eyre!("expected the shot index to be an integer"))?;
+        let (execution_result, shot_index) =
+            select_chunk(context, &execution_results, &chunks, shot_index)?;
 
         for (readout_index, instruction) in &pattern_context.readout_instruction_mapping {
-            let new_instruction =
+            let mut bit =
                 call::get_readout_bit(context, &execution_result, shot_index, *readout_index)?;
+            if context.options.flip_readout {
+                // The qubit was flipped before it was measured, so undo the flip before the program
+                // uses its value.
+                bit = context
+                    .builder
+                    .build_xor(bit, bit.get_type().const_all_ones(), "");
+            }
+            let new_instruction = bit
+                .as_instruction()
+                .ok_or_else(|| eyre!("Expected an instruction"))?;
 
-            instruction.replace_all_uses_with(
-                &new_instruction
-                    .as_instruction()
-                    .ok_or_else(|| eyre!("Expected an instruction"))?,
-            );
+            copy_name_and_metadata(context, *instruction, new_instruction)?;
+            instruction.replace_all_uses_with(&new_instruction);
+        }
+
+        // Branches on readout values were transpiled to Quil classical control flow, so the conditionally
+        // executed blocks are skipped and control flow proceeds directly to where it would have rejoined.
+        for branch in &pattern_context.classical_control_branches {
+            context.builder.position_before(&branch.branch_instruction);
+            let new_instruction = context
+                .builder
+                .build_unconditional_branch(branch.merge_block);
+            copy_name_and_metadata(context, branch.branch_instruction, new_instruction)?;
+            branch.branch_instruction.remove_from_basic_block();
         }
 
         let cleanup_basic_block = context.base_context.insert_basic_block_after(
-            basic_block,
+            loop_end_block,
             format!("{}_cleanup", basic_block.get_name().to_str()?).as_str(),
         );
+        context.injected_blocks.push(InjectedBlock {
+            block: cleanup_basic_block,
+            origin: basic_block,
+            role: "cleanup",
+        });
 
-        // Record which block was originally the target following execution & processing of shots in this block
-        let original_next_block = get_conditional_branch_else_target(
-            basic_block
-                .get_terminator()
-                .ok_or_else(|| eyre!("Expected a terminator"))?,
-        )
-        .wrap_err("expected the basic block to have a conditional 'else' target")?;
+        // Record which block was originally the target following execution & processing of shots in this
+        // block. The loop may be left from either side of its closing branch; the other side repeats the loop.
+        let (original_next_block, exits_on_then) =
+            get_loop_exit_target(loop_end_block, &[basic_block, execution_basic_block]).wrap_err(
+                "expected the shot count loop to end in a branch which leaves the loop",
+            )?;
 
         context.builder.position_at_end(cleanup_basic_block);
-        call::free_execution_result(context, &execution_result);
+        if !context.options.reuse_execution_results {
+            for execution_result in &execution_results {
+                call::free_execution_result(context, execution_result);
+            }
+        }
+        // A cached executable is freed along with the cache, and a hoisted one as the function returns
+        if cache_index.is_none() && !hoist_executable {
+            call::free_executable(context, &executable);
+        }
         context
             .builder
             .build_unconditional_branch(original_next_block);
 
+        let (then_block, else_block) = if exits_on_then {
+            (cleanup_basic_block, basic_block)
+        } else {
+            (basic_block, cleanup_basic_block)
+        };
         replace_conditional_branch_target(
             context,
-            basic_block
+            loop_end_block
                 .get_terminator()
                 .ok_or_else(|| eyre!("Expected a terminator"))?,
-            Some(&basic_block),
-            Some(&cleanup_basic_block),
+            Some(&then_block),
+            Some(&else_block),
         )?;
 
+        let function = basic_block
+            .get_parent()
+            .ok_or_else(|| eyre!("expected basic block to belong to a function"))?;
+        let free_executable_on_return = cache_index.is_none() && hoist_executable;
+        if context.options.reuse_execution_results || free_executable_on_return {
+            let execution_results_to_free: &[_] = if context.options.reuse_execution_results {
+                &execution_results
+            } else {
+                &[]
+            };
+            free_on_return(
+                context,
+                function,
+                free_executable_on_return.then(|| &executable),
+                execution_results_to_free,
+            )?;
+        }
+
+        // The result is freed once the loop is left, so no block which reads it (or frees it) may be
+        // reached afterwards unless it is first executed again. A reused result lives until the
+        // function returns, so may be read at any point after it is first filled.
+        let consumer_blocks = execution_results
+            .iter()
+            .flat_map(|execution_result| blocks_using(function, execution_result.0.into()))
+            .collect::<Vec<_>>();
+        if !context.options.reuse_execution_results
+            && reaches_any(cleanup_basic_block, &consumer_blocks, execution_basic_block)
+        {
+            return Err(eyre!(
+                "the result of executing block {} would be read after it is freed",
+                basic_block.get_name().to_string_lossy()
+            ));
+        }
+
         replace_phi_clauses(
             context,
             basic_block,
-            basic_block,
+            loop_end_block,
             execution_basic_block,
             true,
         )?;
@@ -375,9 +747,164 @@ pub(crate) fn insert_quil_program<'ctx, 'p: 'ctx>(
     Ok(())
 }
 
+/// Position the builder before the terminator of the entry block of the function holding
+/// `basic_block`, so that what is inserted runs once per call rather than once per execution.
+fn position_at_function_entry(context: &QCSCompilerContext, basic_block: BasicBlock) -> Result<()> {
+    let function_entry_terminator = basic_block
+        .get_parent()
+        .and_then(FunctionValue::get_first_basic_block)
+        .and_then(BasicBlock::get_terminator)
+        .ok_or_else(|| eyre!("expected function entry block to have a terminator"))?;
+    context.builder.position_before(&function_entry_terminator);
+    Ok(())
+}
+
+/// Free `executable` and each of `execution_results`, created on entry to `function`, before every
+/// return from it.
+fn free_on_return<'ctx>(
+    context: &mut QCSCompilerContext<'ctx>,
+    function: FunctionValue<'ctx>,
+    executable: Option<&call::Executable<'ctx>>,
+    execution_results: &[call::ExecutionResult<'ctx>],
+) -> Result<()> {
+    let returns = function
+        .get_basic_blocks()
+        .into_iter()
+        .filter_map(BasicBlock::get_terminator)
+        .filter(|terminator| terminator.get_opcode() == InstructionOpcode::Return)
+        .collect::<Vec<_>>();
+    if returns.is_empty() {
+        return Err(eyre!(
+            "function @{} never returns, so its executable and execution results cannot be freed",
+            function.get_name().to_string_lossy()
+        ));
+    }
+    for terminator in returns {
+        context.builder.position_before(&terminator);
+        for execution_result in execution_results {
+            call::free_execution_result(context, execution_result);
+        }
+        if let Some(executable) = executable {
+            call::free_executable(context, executable);
+        }
+    }
+    Ok(())
+}
+
+/// Select the result of the execution which ran the shot at `shot_index`, among the executions of
+/// `chunks` shots each, along with the index of that shot within its execution. Shots are indexed
+/// from 1, as by the runtime's `get_readout_bit`.
+fn select_chunk<'ctx>(
+    context: &QCSCompilerContext<'ctx>,
+    execution_results: &[call::ExecutionResult<'ctx>],
+    chunks: &[u64],
+    shot_index: IntValue<'ctx>,
+) -> Result<(call::ExecutionResult<'ctx>, IntValue<'ctx>)> {
+    let index_type = shot_index.get_type();
+    let mut execution_result = execution_results
+        .first()
+        .ok_or_else(|| eyre!("expected at least one execution result"))?
+        .0;
+    let mut chunk_shot_index = shot_index;
+    let mut start = 0;
+    // Each later execution takes over from the one before once the shot index passes the shots
+    // executed before it
+    for (chunk_result, previous_chunk) in execution_results.iter().skip(1).zip(chunks) {
+        start += previous_chunk;
+        let chunk_start = index_type.const_int(start, false);
+        let is_in_chunk =
+            context
+                .builder
+                .build_int_compare(IntPredicate::UGT, shot_index, chunk_start, "");
+        execution_result = context
+            .builder
+            .build_select(is_in_chunk, chunk_result.0, execution_result, "")
+            .try_into()
+            .map_err(|_| eyre!("expected the selected execution result to be a pointer"))?;
+        let offset = context.builder.build_int_sub(shot_index, chunk_start, "");
+        chunk_shot_index = context
+            .builder
+            .build_select(is_in_chunk, offset, chunk_shot_index, "")
+            .try_into()
+            .map_err(|_| eyre!("expected the selected shot index to be an integer"))?;
+    }
+    Ok((call::ExecutionResult(execution_result), chunk_shot_index))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::context::context::ContextOptions;
+    use crate::context::target::ExecutionTarget;
+
+    #[test]
+    fn shots_at_chunk_boundaries_read_their_own_execution() {
+        let base_context = inkwell::context::Context::create();
+        let data = std::fs::read("tests/fixtures/programs/shot_count_loop.bc").unwrap();
+        let context = QCSCompilerContext::new_from_data(
+            &base_context,
+            &data,
+            ExecutionTarget::Qvm,
+            ContextOptions::default(),
+        )
+        .unwrap();
+        let function = context.module.add_function(
+            "select_chunk",
+            base_context.void_type().fn_type(&[], false),
+            None,
+        );
+        context
+            .builder
+            .position_at_end(base_context.append_basic_block(function, "entry"));
+
+        // Each execution result is a distinct constant, so that selecting among them for a constant
+        // shot index folds to the result read by that shot
+        let index_type = base_context.i64_type();
+        let result_type = context
+            .types
+            .execution_result(&context.module)
+            .ptr_type(default_address_space());
+        let execution_results = (1..=3)
+            .map(|address| {
+                call::ExecutionResult(
+                    index_type
+                        .const_int(address, false)
+                        .const_to_pointer(result_type),
+                )
+            })
+            .collect::<Vec<_>>();
+        let chunks = [21, 21, 10];
+
+        // The first and last shot of each chunk, with the execution and the index within it from
+        // which each is read
+        for (shot_index, execution, chunk_shot_index) in [
+            (1, 0, 1),
+            (21, 0, 21),
+            (22, 1, 1),
+            (42, 1, 21),
+            (43, 2, 1),
+            (52, 2, 10),
+        ] {
+            let (execution_result, selected_shot_index) = select_chunk(
+                &context,
+                &execution_results,
+                &chunks,
+                index_type.const_int(shot_index, false),
+            )
+            .unwrap();
+            assert_eq!(
+                execution_result.0, execution_results[execution].0,
+                "shot {} read from the wrong execution",
+                shot_index
+            );
+            assert_eq!(
+                selected_shot_index.get_zero_extended_constant(),
+                Some(chunk_shot_index),
+                "shot {} read at the wrong index of its execution",
+                shot_index
+            );
+        }
+    }
 
     mod can_transpile_program_with {
         use crate::context::context::{ContextOptions, QCSCompilerContext};
@@ -399,10 +926,7 @@ mod test {
                         &base_context,
                         &data,
                         ExecutionTarget::Qvm,
-                        ContextOptions {
-                            cache_executables: false,
-                            rewiring_pragma: None,
-                        },
+                        ContextOptions::default(),
                     )
                     .unwrap();
                     transpile_module(&mut context).expect("transpilation failed");
@@ -425,5 +949,221 @@ mod test {
         make_snapshot_test!(toffoli);
         make_snapshot_test!(swap);
         make_snapshot_test!(entrypoint_attribute);
+
+        #[test]
+        fn preserved_names_and_metadata() {
+            let base_context = inkwell::context::Context::create();
+            let data = std::fs::read("tests/fixtures/programs/loop_metadata.bc").unwrap();
+            let mut context = QCSCompilerContext::new_from_data(
+                &base_context,
+                &data,
+                ExecutionTarget::Qvm,
+                ContextOptions::default(),
+            )
+            .unwrap();
+            transpile_module(&mut context).expect("transpilation failed");
+
+            let module = context.module.print_to_string().to_string();
+            assert!(
+                module.contains("%bit = call i1 @get_readout_bit("),
+                "expected readout value name to be preserved in:\n{}",
+                module
+            );
+            assert!(
+                module.contains("label %body_cleanup, !llvm.loop !0"),
+                "expected loop metadata to be preserved in:\n{}",
+                module
+            );
+        }
+
+        #[test]
+        fn parameters_carried_from_an_outer_loop() {
+            let base_context = inkwell::context::Context::create();
+            let data = std::fs::read("tests/fixtures/programs/parameter_sweep_loop.bc").unwrap();
+            let mut context = QCSCompilerContext::new_from_data(
+                &base_context,
+                &data,
+                ExecutionTarget::Qvm,
+                ContextOptions::default(),
+            )
+            .unwrap();
+            transpile_module(&mut context).expect("transpilation failed");
+
+            context
+                .module
+                .verify()
+                .map_err(|e| e.to_string())
+                .expect("transformed module is invalid");
+            let module = context.module.print_to_string().to_string();
+            assert!(
+                module.contains("i32 0, double %theta)"),
+                "expected the parameter to be set from the outer loop in:\n{}",
+                module
+            );
+        }
+
+        #[test]
+        fn loop_left_from_then_side() {
+            let base_context = inkwell::context::Context::create();
+            let data = std::fs::read("tests/fixtures/programs/loop_exit_first.bc").unwrap();
+            let mut context = QCSCompilerContext::new_from_data(
+                &base_context,
+                &data,
+                ExecutionTarget::Qvm,
+                ContextOptions::default(),
+            )
+            .unwrap();
+            transpile_module(&mut context).expect("transpilation failed");
+
+            context
+                .module
+                .verify()
+                .map_err(|e| e.to_string())
+                .expect("transformed module is invalid");
+            let module = context.module.print_to_string().to_string();
+            assert!(
+                module.contains("br i1 %3, label %body_cleanup, label %body"),
+                "expected the result to be freed only once the loop is left in:\n{}",
+                module
+            );
+        }
+
+        #[test]
+        fn reduction_phis_of_any_type() {
+            for (fixture, phi_type) in [("reduction_double", "double"), ("reduction_bool", "i1")] {
+                let base_context = inkwell::context::Context::create();
+                let data =
+                    std::fs::read(format!("tests/fixtures/programs/{}.bc", fixture)).unwrap();
+                let mut context = QCSCompilerContext::new_from_data(
+                    &base_context,
+                    &data,
+                    ExecutionTarget::Qvm,
+                    ContextOptions::default(),
+                )
+                .unwrap();
+                transpile_module(&mut context).expect("transpilation failed");
+
+                context
+                    .module
+                    .verify()
+                    .map_err(|e| e.to_string())
+                    .expect("transformed module is invalid");
+                let module = context.module.print_to_string().to_string();
+                assert!(
+                    module.contains(&format!("phi {} ", phi_type)),
+                    "expected a phi of type {} in:\n{}",
+                    phi_type,
+                    module
+                );
+            }
+        }
+
+        #[test]
+        fn executables_freed_with_or_without_caching() {
+            for cache_executables in [false, true] {
+                let base_context = inkwell::context::Context::create();
+                let data = std::fs::read("tests/fixtures/programs/shot_count_loop.bc").unwrap();
+                let mut context = QCSCompilerContext::new_from_data(
+                    &base_context,
+                    &data,
+                    ExecutionTarget::Qvm,
+                    ContextOptions {
+                        cache_executables,
+                        helper_calling_convention: CallingConvention::Fast,
+                        ..ContextOptions::default()
+                    },
+                )
+                .unwrap();
+                transpile_module(&mut context).expect("transpilation failed");
+
+                context
+                    .module
+                    .verify()
+                    .map_err(|e| e.to_string())
+                    .expect("transformed module is invalid");
+                let module = context.module.print_to_string().to_string();
+
+                // An executable built for a single execution is freed once the loop is left
+                assert_eq!(
+                    module.contains("call void @free_executable(%Executable* %"),
+                    !cache_executables,
+                    "unexpected freeing of the executable in:\n{}",
+                    module
+                );
+
+                // Cached executables are freed by a global destructor, which uses the C calling
+                // convention
+                let global_dtors = context.module.get_global("llvm.global_dtors");
+                assert_eq!(global_dtors.is_some(), cache_executables);
+                if let Some(global_dtors) = global_dtors {
+                    assert!(global_dtors
+                        .print_to_string()
+                        .to_string()
+                        .contains(&format!("@{}", FN_NAME_FREE_EXECUTABLE_ARRAY_AT_EXIT)));
+                    let destructor = context
+                        .module
+                        .get_function(FN_NAME_FREE_EXECUTABLE_ARRAY_AT_EXIT)
+                        .unwrap();
+                    assert_eq!(
+                        destructor.get_call_conventions(),
+                        CallingConvention::C.llvm_id()
+                    );
+                    assert!(destructor.print_to_string().to_string().contains(&format!(
+                        "call fastcc void @{}()",
+                        FN_NAME_FREE_EXECUTABLE_ARRAY
+                    )));
+                }
+            }
+        }
+
+        #[test]
+        fn executable_cache_only_when_caching() {
+            for cache_executables in [false, true] {
+                let base_context = inkwell::context::Context::create();
+                let data = std::fs::read("tests/fixtures/programs/shot_count_loop.bc").unwrap();
+                let mut context = QCSCompilerContext::new_from_data(
+                    &base_context,
+                    &data,
+                    ExecutionTarget::Qvm,
+                    ContextOptions {
+                        cache_executables,
+                        ..ContextOptions::default()
+                    },
+                )
+                .unwrap();
+                transpile_module(&mut context).expect("transpilation failed");
+
+                context
+                    .module
+                    .verify()
+                    .map_err(|e| e.to_string())
+                    .expect("transformed module is invalid");
+                assert_eq!(
+                    context
+                        .module
+                        .get_function("populate_executable_array")
+                        .is_some(),
+                    cache_executables
+                );
+                assert_eq!(
+                    context.module.get_global("executable_cache").is_some(),
+                    cache_executables
+                );
+                assert_eq!(
+                    context
+                        .module
+                        .get_function(values::FN_NAME_CREATE_EXECUTABLE_CACHE)
+                        .is_some(),
+                    cache_executables
+                );
+                assert_eq!(
+                    context
+                        .module
+                        .get_function(FN_NAME_FREE_EXECUTABLE_ARRAY)
+                        .is_some(),
+                    cache_executables
+                );
+            }
+        }
     }
 }
@@ -15,20 +15,36 @@
 // This module is responsible for the transpilation of contiguous subsequences of LLVM instructions
 // into quil, substituting those instructions with inline calls to a shared library responsible for
 // executing those quil instructions.
-use eyre::{eyre, Result};
-use inkwell::{basic_block::BasicBlock, values::FunctionValue};
-use quil_rs::instruction::Vector;
+use std::collections::BTreeMap;
+
+use eyre::{eyre, Result, WrapErr};
+use inkwell::{
+    basic_block::BasicBlock,
+    values::{AnyValue, FunctionValue, InstructionOpcode},
+};
 
 #[cfg(feature = "serde_support")]
 use serde::{ser::SerializeStruct, Serialize, Serializer};
 
-use crate::transform::PARAMETER_MEMORY_REGION_NAME;
-use crate::{context::QCSCompilerContext, interop::entrypoint::get_entry_function, RecordedOutput};
+use crate::transform::{
+    compact::compact_qubits, format_traced_program, ordering::apply_measurement_order,
+    peephole::optimize_program, prepend_calibrations,
+};
+use crate::{
+    context::QCSCompilerContext,
+    error::KindError,
+    interop::{entrypoint::get_entry_function, instruction::get_called_function_name},
+    quil_builder::{
+        declare_params, declare_readout, initial_rewiring, prepend_pragmas, prepend_reset,
+    },
+    validation::{get_qir_declarations, QirDeclarations},
+    RecordedOutput, RecordingPlan, TranspileWarning,
+};
 
 use super::pattern::ShotCountPatternMatchContext;
 
 /// Encapsulates the result of transpiling a QIR module to a Quil program
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ProgramOutput {
     /// The Quil program itself
     pub program: quil_rs::Program,
@@ -36,6 +52,38 @@ pub struct ProgramOutput {
     pub shot_count: u64,
     /// Signifies output to be recorded at the end of program execution
     pub recorded_output: Vec<RecordedOutput>,
+    /// When transpiled with tracing enabled, the QIR instruction from which each instruction
+    /// translated into `program` originated, in order. These correspond to the final instructions
+    /// of the program, following any which were prepended to it such as calibrations.
+    pub trace: Vec<String>,
+    /// The QIR value from which each element of the `__qir_param` memory region is set, as printed
+    /// in the module, in order. Parameters holding the same value share an element.
+    pub parameters: Vec<String>,
+    /// The program compiled to native Quil, when transpiled with a
+    /// [`crate::TranspileOptions::quil_compiler`].
+    pub native_program: Option<quil_rs::Program>,
+    /// The QIR version and profile which the module declares.
+    pub qir_declarations: QirDeclarations,
+    /// The index in `program` of each qubit of the module, when transpiled with
+    /// [`crate::TranspileOptions::compact_qubits`]; otherwise empty.
+    pub qubit_mapping: BTreeMap<u64, u64>,
+    /// The likely mistakes found in the module while transpiling it, in the order found.
+    pub warnings: Vec<TranspileWarning>,
+}
+
+impl ProgramOutput {
+    /// Render the program as Quil, with each instruction translated from QIR preceded by a comment
+    /// naming the instruction it was translated from, as in `# from: tail call void @...`.
+    #[must_use]
+    pub fn to_traced_string(&self) -> String {
+        format_traced_program(&self.program, &self.trace)
+    }
+
+    /// The output recorded by each shot of the program, for formatting its results.
+    #[must_use]
+    pub fn recording_plan(&self) -> RecordingPlan {
+        RecordingPlan::from(self.recorded_output.clone())
+    }
 }
 
 #[cfg(feature = "serde_support")]
@@ -44,10 +92,36 @@ impl Serialize for ProgramOutput {
     where
         S: Serializer,
     {
-        let mut output = serializer.serialize_struct("ProgramOutput", 3)?;
-        output.serialize_field("program", &self.program.to_string(true))?;
+        let mut output = serializer.serialize_struct("ProgramOutput", 8)?;
+        if self.trace.is_empty() {
+            output.serialize_field("program", &self.program.to_string(true))?;
+        } else {
+            output.serialize_field("program", &self.to_traced_string())?;
+        }
         output.serialize_field("shot_count", &self.shot_count)?;
         output.serialize_field("recorded_output", &self.recorded_output)?;
+        output.serialize_field("parameters", &self.parameters)?;
+        match &self.native_program {
+            Some(native_program) => {
+                output.serialize_field("native_program", &native_program.to_string(true))?;
+            }
+            None => output.skip_field("native_program")?,
+        }
+        if self.qir_declarations.is_empty() {
+            output.skip_field("qir_declarations")?;
+        } else {
+            output.serialize_field("qir_declarations", &self.qir_declarations)?;
+        }
+        if self.qubit_mapping.is_empty() {
+            output.skip_field("qubit_mapping")?;
+        } else {
+            output.serialize_field("qubit_mapping", &self.qubit_mapping)?;
+        }
+        if self.warnings.is_empty() {
+            output.skip_field("warnings")?;
+        } else {
+            output.serialize_field("warnings", &self.warnings)?;
+        }
         output.end()
     }
 }
@@ -56,26 +130,102 @@ impl Serialize for ProgramOutput {
 /// from a program loop counter.
 #[allow(dead_code)]
 pub(crate) fn transpile_module(context: &mut QCSCompilerContext) -> Result<ProgramOutput> {
-    let entrypoint_function = get_entry_function(&context.module)
-        .ok_or_else(|| eyre!("entrypoint not found in module"))?;
-    transpile_function(context, entrypoint_function, &[])
+    let entrypoint_function = get_entry_function(&context.module).ok_or_else(|| {
+        KindError::MissingEntrypoint(String::from("entrypoint not found in module"))
+    })?;
+    transpile_function(context, entrypoint_function)
 }
 
 /// Transpile a single QIR function body to a Quil program. This function may have any number
-/// of basic blocks, but only the block named `body` will be parsed for quantum instructions
-/// and transpiled to Quil; others will be ignored.
+/// of basic blocks, but only the one holding the shot count loop will be parsed for quantum
+/// instructions and transpiled to Quil; others will be ignored. See [`find_body_block`].
 pub(crate) fn transpile_function<'ctx>(
     context: &mut QCSCompilerContext<'ctx>,
     function: FunctionValue<'ctx>,
-    visited_functions: &[&str],
 ) -> eyre::Result<ProgramOutput> {
+    let body_block = find_body_block(function, context.options.body_block.as_deref())?;
+
+    transpile_basic_block(context, body_block)
+}
+
+/// Find the block of `function` holding its shot count loop. If `body_block` is given, this is the
+/// block of that name. Otherwise, it is a block which begins with the `phi` counting shots and calls
+/// quantum intrinsics, preferring one named `body` should there be several.
+pub(crate) fn find_body_block<'ctx>(
+    function: FunctionValue<'ctx>,
+    body_block: Option<&str>,
+) -> eyre::Result<BasicBlock<'ctx>> {
     let blocks = function.get_basic_blocks();
-    let body_block = blocks
+
+    if let Some(name) = body_block {
+        return blocks
+            .into_iter()
+            .find(|block| block.get_name().to_string_lossy() == name)
+            .ok_or_else(|| eyre!("no basic block named '{}' found in function", name));
+    }
+
+    let candidates: Vec<BasicBlock> = blocks
         .into_iter()
-        .find(|el| el.get_name().to_string_lossy() == "body")
-        .ok_or(eyre::eyre!("no basic block named 'body' found in function"))?;
+        .filter(|block| begins_with_phi(*block) && calls_quantum_intrinsics(*block))
+        .collect();
+    candidates
+        .iter()
+        .find(|block| block.get_name().to_string_lossy() == "body")
+        .or_else(|| candidates.first())
+        .copied()
+        .ok_or_else(|| eyre!("no basic block containing a shot count loop found in function"))
+}
 
-    transpile_basic_block(context, body_block, visited_functions)
+/// Whether `basic_block` begins with a `phi`, as does the block which starts a shot count loop.
+fn begins_with_phi(basic_block: BasicBlock) -> bool {
+    basic_block
+        .get_first_instruction()
+        .map_or(false, |instruction| {
+            instruction.get_opcode() == InstructionOpcode::Phi
+        })
+}
+
+/// Transpile each basic block of a QIR function which calls quantum intrinsics to its own Quil
+/// program, keyed by block name and in the order the blocks appear in the function. Blocks in which
+/// no shot count pattern is detected are omitted, so that blocks such as a one-time prologue do not
+/// prevent the others from being transpiled.
+pub(crate) fn transpile_function_blocks<'ctx>(
+    context: &mut QCSCompilerContext<'ctx>,
+    function: FunctionValue<'ctx>,
+) -> eyre::Result<Vec<(String, ProgramOutput)>> {
+    let mut outputs = vec![];
+    for basic_block in function.get_basic_blocks() {
+        if !calls_quantum_intrinsics(basic_block) {
+            continue;
+        }
+
+        let block_name = basic_block.get_name().to_string_lossy().into_owned();
+        let pattern_context =
+            ShotCountPatternMatchContext::from_basic_block(context, basic_block, false)
+                .wrap_err_with(|| format!("failed to transpile block {}", block_name))?;
+
+        if pattern_context.get_program_data().is_some() {
+            outputs.push((block_name, build_quil_program(context, &pattern_context)?));
+        }
+    }
+    Ok(outputs)
+}
+
+/// Whether `basic_block` contains any call to a quantum intrinsic (`__quantum__qis__*`).
+fn calls_quantum_intrinsics(basic_block: BasicBlock) -> bool {
+    let mut next_instruction = basic_block.get_first_instruction();
+    while let Some(instruction) = next_instruction {
+        if instruction.get_opcode() == InstructionOpcode::Call
+            && get_called_function_name(instruction)
+                .ok()
+                .flatten()
+                .map_or(false, |name| name.starts_with("__quantum__qis__"))
+        {
+            return true;
+        }
+        next_instruction = instruction.get_next_instruction();
+    }
+    false
 }
 
 /// Transpile a single QIR basic block to a Quil program. This block must match the pattern
@@ -83,33 +233,13 @@ pub(crate) fn transpile_function<'ctx>(
 pub(crate) fn transpile_basic_block<'ctx>(
     context: &mut QCSCompilerContext<'ctx>,
     basic_block: BasicBlock<'ctx>,
-    visited_functions: &[&str],
 ) -> eyre::Result<ProgramOutput> {
-    let pattern_context = ShotCountPatternMatchContext::from_basic_block(
-        context,
-        basic_block,
-        visited_functions,
-        fail_on_nested_function_call,
-    )?;
+    let pattern_context =
+        ShotCountPatternMatchContext::from_basic_block(context, basic_block, false)?;
 
     build_quil_program(context, &pattern_context)
 }
 
-/// This function exists to satisfy the signature needed to form a `ShotCountPatternMatchContext`.
-///
-/// However, because Quil does not itself have function calls, if there is a function call within
-/// the basic block being transpiled, this returns an error.
-pub(crate) fn fail_on_nested_function_call<'ctx>(
-    _context: &mut QCSCompilerContext<'ctx>,
-    function: FunctionValue<'ctx>,
-    _visited_functions: &[&str],
-) -> eyre::Result<()> {
-    Err(eyre::eyre!(
-        "cannot transpile nested function calls to Quil; found \"{}\"",
-        function.get_name().to_string_lossy()
-    ))
-}
-
 /// Build a Quil program from the information scraped into a shot-count pattern match.
 /// If no pattern was detected, return an error.
 pub(crate) fn build_quil_program<'ctx, 'p: 'ctx>(
@@ -117,66 +247,61 @@ pub(crate) fn build_quil_program<'ctx, 'p: 'ctx>(
     pattern_context: &ShotCountPatternMatchContext<'p>,
 ) -> eyre::Result<ProgramOutput> {
     if let Some((program, shots)) = pattern_context.get_program_data() {
-        let mut program = program.clone();
-
-        program.add_instruction(quil_rs::instruction::Instruction::Declaration(
-            quil_rs::instruction::Declaration {
-                name: String::from("ro"),
-                size: Vector {
-                    data_type: quil_rs::instruction::ScalarType::Bit,
-                    length: pattern_context.read_result_mapping.len() as u64,
-                },
-                sharing: None,
-            },
-        ));
-
-        if !pattern_context.get_dynamic_parameters().is_empty() {
-            program.add_instruction(quil_rs::instruction::Instruction::Declaration(
-                quil_rs::instruction::Declaration {
-                    name: String::from(PARAMETER_MEMORY_REGION_NAME),
-                    size: Vector {
-                        data_type: quil_rs::instruction::ScalarType::Real,
-                        length: pattern_context.get_dynamic_parameters().len() as u64,
-                    },
-                    sharing: None,
-                },
+        // A `select` between parameters on a condition computed at runtime is only supported when
+        // patching, where the choice is made on the host before the program is executed.
+        if let Some(select) = pattern_context
+            .get_dynamic_parameters()
+            .into_iter()
+            .filter_map(|value| value.as_instruction())
+            .find(|instruction| instruction.get_opcode() == InstructionOpcode::Select)
+        {
+            return Err(eyre::eyre!(
+                "cannot transpile a gate parameter chosen at runtime by `{}` to Quil; patch the module instead, so that the parameter is chosen before execution",
+                select.print_to_string().to_string().trim()
             ));
         }
 
+        let (program, trace) = if context.options.optimize_quil {
+            optimize_program(&context.options, program, &pattern_context.trace)
+        } else {
+            (program.clone(), pattern_context.trace.clone())
+        };
+        let (mut program, trace) =
+            apply_measurement_order(context.options.measurement_order, program, trace)?;
+        let qubit_mapping = if context.options.compact_qubits {
+            let (compacted, qubit_mapping) = compact_qubits(&program);
+            program = compacted;
+            qubit_mapping
+        } else {
+            BTreeMap::new()
+        };
+
+        declare_readout(&mut program, pattern_context.get_readout_length());
+        declare_params(
+            &mut program,
+            pattern_context.get_dynamic_parameters().len() as u64,
+        );
+
         if pattern_context.use_active_reset {
-            // Prepend a reset to the program via copy
-            let instructions = program.to_instructions(true);
-            let mut new_program = quil_rs::program::Program::new();
-            new_program.add_instruction(quil_rs::instruction::Instruction::Reset(
-                quil_rs::instruction::Reset { qubit: None },
-            ));
-            for instruction in instructions {
-                new_program.add_instruction(instruction);
-            }
-            program = new_program;
+            program = prepend_reset(program);
         }
 
+        program = prepend_calibrations(context, program);
+
         if let Some(rewiring_pragma) = &context.options.rewiring_pragma {
-            // Prepend a pragma to the program via copy
-            let instructions = program.to_instructions(true);
-            let mut new_program = quil_rs::program::Program::new();
-            new_program.add_instruction(quil_rs::instruction::Instruction::Pragma(
-                quil_rs::instruction::Pragma {
-                    name: String::from("INITIAL_REWIRING"),
-                    arguments: vec![format!("\"{}\"", rewiring_pragma.clone())],
-                    data: None,
-                },
-            ));
-            for instruction in instructions {
-                new_program.add_instruction(instruction);
-            }
-            program = new_program;
+            program = prepend_pragmas(program, [initial_rewiring(rewiring_pragma)]);
         }
 
         Ok(ProgramOutput {
             program,
             shot_count: shots,
-            recorded_output: pattern_context.recorded_output.clone(),
+            recorded_output: pattern_context.recording.entries().to_vec(),
+            trace,
+            parameters: pattern_context.get_parameter_table(),
+            native_program: None,
+            qir_declarations: get_qir_declarations(&context.module),
+            qubit_mapping,
+            warnings: pattern_context.recording.warnings().to_vec(),
         })
     } else {
         Err(eyre::eyre!(
@@ -208,10 +333,7 @@ mod test {
                         &base_context,
                         &data,
                         ExecutionTarget::Qvm,
-                        ContextOptions {
-                            cache_executables: false,
-                            rewiring_pragma: None,
-                        },
+                        ContextOptions::default(),
                     )
                     .unwrap();
                     let result = transpile_module(&mut context).expect("transpilation failed");
@@ -234,5 +356,160 @@ mod test {
         make_snapshot_test!(toffoli);
         make_snapshot_test!(swap);
         make_snapshot_test!(entrypoint_attribute);
+        make_snapshot_test!(remeasure);
+        make_snapshot_test!(conditional_reset);
+        make_snapshot_test!(frame_mutations);
+        make_snapshot_test!(joint_measurement);
+        make_snapshot_test!(pauli_exponential);
+        make_snapshot_test!(gate_loop);
+        make_snapshot_test!(constant_select);
+    }
+
+    #[test]
+    fn sparse_readout_preserves_result_indices() {
+        use crate::context::context::{ContextOptions, QCSCompilerContext};
+        use crate::context::target::ExecutionTarget;
+
+        let base_context = inkwell::context::Context::create();
+        let data = std::fs::read("tests/fixtures/programs/measure_sparse.bc").unwrap();
+        let mut context = QCSCompilerContext::new_from_data(
+            &base_context,
+            &data,
+            ExecutionTarget::Qvm,
+            ContextOptions {
+                sparse_readout: true,
+                ..ContextOptions::default()
+            },
+        )
+        .unwrap();
+        let result = transpile_module(&mut context).expect("transpilation failed");
+
+        insta::assert_snapshot!(result.program.to_string(true));
+    }
+
+    #[test]
+    fn flip_readout_surrounds_measurements() {
+        use crate::context::context::{ContextOptions, QCSCompilerContext};
+        use crate::context::target::ExecutionTarget;
+
+        let base_context = inkwell::context::Context::create();
+        let data = std::fs::read("tests/fixtures/programs/record_output.bc").unwrap();
+        let mut context = QCSCompilerContext::new_from_data(
+            &base_context,
+            &data,
+            ExecutionTarget::Qvm,
+            ContextOptions {
+                flip_readout: true,
+                ..ContextOptions::default()
+            },
+        )
+        .unwrap();
+        let result = transpile_module(&mut context).expect("transpilation failed");
+
+        insta::assert_snapshot!(result.program.to_string(true));
+        insta::assert_debug_snapshot!(result.recorded_output);
+    }
+
+    #[test]
+    fn runtime_select_parameters_are_rejected() {
+        use crate::context::context::{ContextOptions, QCSCompilerContext};
+        use crate::context::target::ExecutionTarget;
+
+        let base_context = inkwell::context::Context::create();
+        let data = std::fs::read("tests/fixtures/programs/runtime_select.bc").unwrap();
+        let mut context = QCSCompilerContext::new_from_data(
+            &base_context,
+            &data,
+            ExecutionTarget::Qvm,
+            ContextOptions::default(),
+        )
+        .unwrap();
+        let error = transpile_module(&mut context).unwrap_err();
+
+        assert!(
+            format!("{:?}", error).contains("patch the module instead"),
+            "unexpected error: {:?}",
+            error
+        );
+    }
+
+    #[test]
+    fn float_precision_rounds_constant_parameters() {
+        use crate::context::context::{ContextOptions, QCSCompilerContext};
+        use crate::context::target::ExecutionTarget;
+
+        let base_context = inkwell::context::Context::create();
+        let data = std::fs::read("tests/fixtures/programs/parametric.bc").unwrap();
+        let mut context = QCSCompilerContext::new_from_data(
+            &base_context,
+            &data,
+            ExecutionTarget::Qvm,
+            ContextOptions {
+                float_precision: Some(3),
+                ..ContextOptions::default()
+            },
+        )
+        .unwrap();
+        let result = transpile_module(&mut context).expect("transpilation failed");
+
+        insta::assert_snapshot!(result.program.to_string(true));
+    }
+
+    #[test]
+    fn computed_integers_are_recorded_as_host_computed() {
+        use crate::context::context::{ContextOptions, QCSCompilerContext};
+        use crate::context::target::ExecutionTarget;
+
+        let base_context = inkwell::context::Context::create();
+        let data = std::fs::read("tests/fixtures/programs/record_computed_integer.bc").unwrap();
+        let mut context = QCSCompilerContext::new_from_data(
+            &base_context,
+            &data,
+            ExecutionTarget::Qvm,
+            ContextOptions::default(),
+        )
+        .unwrap();
+        let result = transpile_module(&mut context).expect("transpilation failed");
+
+        match &result.recorded_output[..] {
+            [RecordedOutput::ShotStart, RecordedOutput::HostComputedInteger {
+                value, expression, ..
+            }, RecordedOutput::ShotEnd] => {
+                assert_eq!(value, "%sum = add i64 %z0, %z1");
+                assert_eq!(
+                    expression.as_ref().map(ToString::to_string).as_deref(),
+                    Some("(ro[0] + ro[1])")
+                );
+            }
+            other => panic!("unexpected recorded output: {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "llvm15-0")]
+    #[test]
+    fn opaque_pointers_transpile_like_typed_pointers() {
+        use crate::context::context::{ContextOptions, QCSCompilerContext};
+        use crate::context::target::ExecutionTarget;
+
+        let transpile = |path: &str| {
+            let base_context = inkwell::context::Context::create();
+            let data = std::fs::read(path).unwrap();
+            let mut context = QCSCompilerContext::new_from_data(
+                &base_context,
+                &data,
+                ExecutionTarget::Qvm,
+                ContextOptions::default(),
+            )
+            .unwrap();
+            transpile_module(&mut context)
+                .expect("transpilation failed")
+                .program
+                .to_string(true)
+        };
+
+        assert_eq!(
+            transpile("tests/fixtures/programs/measure_opaque.bc"),
+            transpile("tests/fixtures/programs/measure.bc")
+        );
     }
 }
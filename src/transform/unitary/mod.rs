@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-mod pattern;
+pub(crate) mod pattern;
+#[cfg(feature = "transform")]
 pub(crate) mod qir;
 pub(crate) mod quil;
@@ -12,28 +12,40 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::HashMap;
+use std::collections::HashSet;
 
+use either::Either;
 use eyre::{eyre, Result};
+use indexmap::IndexMap;
 use inkwell::{
     basic_block::BasicBlock,
-    values::{FloatValue, InstructionOpcode, InstructionValue},
+    values::{AnyValue, BasicValue, FloatValue, InstructionOpcode, InstructionValue},
 };
 use lazy_static::lazy_static;
 use log::{debug, info};
 use quil_rs::{
-    expression::Expression,
+    expression::{Expression, InfixOperator},
     instruction::{GateModifier, MemoryReference, Qubit},
 };
 use regex::Regex;
 
 use crate::{
     context::QCSCompilerContext,
+    error::KindError,
     interop::instruction::{
         get_called_function_name, get_qis_function_arguments, OperationArgument,
     },
-    transform::PARAMETER_MEMORY_REGION_NAME,
-    RecordedOutput,
+    transform::{
+        classical::is_pure_classical_instruction,
+        constant::{build_constant_expression, fold_constant_select, is_same_parameter},
+        custom::handle_custom_instruction,
+        explain_instruction,
+        frame::{build_frame_instruction, QIS_FRAME_INTRINSIC_REGEX},
+        message::handle_message,
+        pauli::{build_joint_measurement, build_pauli_exponential, get_pauli_terms},
+        recording::{assign_readout_offset, readout_length, RecordingPlanBuilder},
+        validate_quil_program_length, PARAMETER_MEMORY_REGION_NAME,
+    },
 };
 
 /// Each pattern matching function, if it finds a match, returns a result tuple including:
@@ -43,13 +55,15 @@ type PatternResult<'ctx, T> = Option<(Option<InstructionValue<'ctx>>, T)>;
 
 /// A `UnitaryPatternMatchContext` accumulates state as it scans a QIR program.
 /// It is used to extract a single quantum program from a QIR program, without shot count
-/// or any classical instructions beyond a final return statement.
+/// or any classical instructions with side effects beyond a final return statement.
 ///
 /// The pattern it looks for within a basic block is the following:
 ///
 /// * A sequence of these instructions only:
 ///   * Quantum Instructions - `quantum_qis` intrinsics
 ///   * Select Quantum Runtime Instructions - `quantum_rt`
+///   * Classical instructions without side effects, such as arithmetic, comparisons, and casts,
+///     which may separate quantum instructions without splitting the program
 /// * Terminated by _exactly_ a `ret void`
 ///
 /// If any other instructions are encountered, an error is returned.
@@ -58,14 +72,25 @@ pub(crate) struct UnitaryPatternMatchContext<'ctx> {
     /// The quil program transpiled from quantum intrinsics
     pub(crate) quil_program: quil_rs::Program,
 
-    /// Signifies output to be recorded at the end of program execution
-    pub(crate) recorded_output: Vec<RecordedOutput>,
+    /// Plans the output to be recorded at the end of program execution
+    pub(crate) recording: RecordingPlanBuilder,
+
+    /// When tracing, the QIR instruction from which each instruction in `quil_program` was
+    /// translated, in order
+    pub(crate) trace: Vec<String>,
+
+    /// The number of decimal places to which constant parameters are rounded, if any
+    pub(crate) float_precision: Option<usize>,
+
+    /// Whether constant parameters near multiples of pi are written symbolically
+    pub(crate) symbolic_pi: bool,
 
     /// A list of instructions to remove from the program (for substitution with Quil)
     pub(crate) instructions_to_remove: Vec<InstructionValue<'ctx>>,
 
-    /// Mapping of (read_result *Result index)->(ro memory region index)
-    pub(crate) read_result_mapping: HashMap<u64, u64>,
+    /// Mapping of (read_result *Result index)->(ro memory region index), in the order each Result
+    /// was first measured, so that anything emitted from it is the same on every run
+    pub(crate) read_result_mapping: IndexMap<u64, u64>,
 
     /// Pairings of (readout buffer index/offset) with the instruction which stores that readout value.
     pub(crate) readout_instruction_mapping: Vec<(u64, InstructionValue<'ctx>)>,
@@ -76,42 +101,128 @@ pub(crate) struct UnitaryPatternMatchContext<'ctx> {
 
     /// Whether or not to prepend a RESET instruction to the program to actively reset all qubits on each shot
     pub(crate) use_active_reset: bool,
+
+    /// Classical instructions computed from readout values, which are removed along with the
+    /// `read_result` calls they depend upon
+    pub(crate) readout_dependents: HashSet<InstructionValue<'ctx>>,
 }
 
 impl<'ctx> UnitaryPatternMatchContext<'ctx> {
+    /// Attribute each Quil instruction added since the last call to the QIR instruction `source`.
+    pub(crate) fn record_trace(&mut self, source: InstructionValue<'ctx>) {
+        let length = self.quil_program.to_instructions(false).len();
+        let source = source.print_to_string().to_string();
+        self.trace.resize(length, source.trim().to_owned());
+    }
+
     /// Build the pattern context from a basic block.
     ///
     /// # Arguments
     ///
     /// * `context`: overall compiler context
     /// * `basic_block`: the subject block to be searched for the pattern
+    /// * `parameters`: values assigned the first elements of the parameter memory region, in order,
+    ///   whether or not the block uses them
     pub(crate) fn from_basic_block(
         context: &mut QCSCompilerContext<'ctx>,
         basic_block: BasicBlock<'ctx>,
+        parameters: Vec<FloatValue<'ctx>>,
     ) -> Result<Self> {
         let mut next_instruction = basic_block.get_first_instruction();
-        let mut pattern_context = UnitaryPatternMatchContext::default();
+        let mut pattern_context = UnitaryPatternMatchContext {
+            float_precision: context.options.float_precision,
+            symbolic_pi: context.options.symbolic_pi,
+            parameters,
+            ..UnitaryPatternMatchContext::default()
+        };
 
         info!(
             "starting transpile: block {}",
             basic_block.get_name().to_str()?
         );
 
+        let mut traced_instruction = None;
         while let Some(instruction) = next_instruction {
+            if context.options.trace {
+                if let Some(source) = traced_instruction.replace(instruction) {
+                    pattern_context.record_trace(source);
+                }
+            }
+
             // If we haven't yet found the loop start...
             if let Some((pattern_instruction, _)) =
                 quantum_instruction(context, &mut pattern_context, instruction)?
             {
                 debug!("matched quantum instruction: {:?}", instruction);
+                context
+                    .explain(|| explain_instruction(instruction, "matched a quantum instruction"));
                 next_instruction = pattern_instruction;
             } else if let Some((pattern_instruction, _)) =
                 rt_record_instruction(context, &mut pattern_context, instruction)?
             {
                 debug!("matched rt_record instruction: {:?}", instruction);
+                context.explain(|| explain_instruction(instruction, "matched an output recording"));
                 next_instruction = pattern_instruction;
+            } else if handle_message(
+                context,
+                instruction,
+                &mut pattern_context.quil_program,
+                &mut pattern_context.instructions_to_remove,
+            )? {
+                debug!("matched message: {:?}", instruction);
+                context.explain(|| {
+                    explain_instruction(instruction, "matched a message, or the string it prints")
+                });
+                next_instruction = instruction.get_next_instruction();
+            } else if let Some(output) = handle_custom_instruction(context, instruction)? {
+                debug!("matched custom instruction: {:?}", instruction);
+                context.explain(|| {
+                    explain_instruction(instruction, "matched a custom instruction handler")
+                });
+                for quil_instruction in output.instructions {
+                    pattern_context
+                        .quil_program
+                        .add_instruction(quil_instruction);
+                }
+                pattern_context.recording.extend(output.recorded_output);
+                pattern_context.instructions_to_remove.push(instruction);
+                next_instruction = instruction.get_next_instruction();
+            } else if is_pure_classical_instruction(instruction) {
+                // Classical code between quantum instructions cannot change what those compute,
+                // since their arguments are constant, so the quantum instructions on either side
+                // are gathered into a single program. Code independent of readout stays where it
+                // is; code computing from readout can only be recorded, which the host evaluates.
+                if pattern_context.depends_on_readout(instruction) {
+                    context.explain(|| {
+                        explain_instruction(
+                            instruction,
+                            "skipped: classical instruction computed from readout is removed",
+                        )
+                    });
+                    pattern_context.readout_dependents.insert(instruction);
+                    pattern_context.instructions_to_remove.push(instruction);
+                } else {
+                    context.explain(|| {
+                        explain_instruction(instruction, "skipped: classical instruction")
+                    });
+                }
+                next_instruction = instruction.get_next_instruction();
             } else if instruction.get_opcode() == InstructionOpcode::Return {
+                context
+                    .explain(|| explain_instruction(instruction, "matched the end of the program"));
+                validate_quil_program_length(
+                    &pattern_context.quil_program,
+                    &context.options.limits,
+                )?;
+                pattern_context.recording.finish()?;
                 return Ok(pattern_context);
             } else {
+                context.explain(|| {
+                    explain_instruction(
+                        instruction,
+                        "stopped: only quantum instructions, output recording, and classical instructions without side effects are allowed in unitary QIR",
+                    )
+                });
                 return Err(eyre::eyre!(
                     "found instruction disallowed in Unitary QIR: {:?}",
                     instruction
@@ -119,9 +230,33 @@ impl<'ctx> UnitaryPatternMatchContext<'ctx> {
             }
         }
 
+        if let Some(source) = traced_instruction {
+            pattern_context.record_trace(source);
+        }
+
+        validate_quil_program_length(&pattern_context.quil_program, &context.options.limits)?;
+        pattern_context.recording.finish()?;
+
         Ok(pattern_context)
     }
 
+    /// Whether any operand of `instruction` is a readout value or computed from one.
+    fn depends_on_readout(&self, instruction: InstructionValue<'ctx>) -> bool {
+        (0..instruction.get_num_operands()).any(|index| {
+            let operand = match instruction.get_operand(index) {
+                Some(Either::Left(operand)) => operand,
+                _ => return false,
+            };
+            operand.as_instruction_value().map_or(false, |operand| {
+                self.readout_dependents.contains(&operand)
+                    || self
+                        .readout_instruction_mapping
+                        .iter()
+                        .any(|(_, readout)| *readout == operand)
+            })
+        })
+    }
+
     /// If the program contains any executable instructions (gates, pulses, etc) return that
     /// information; otherwise, return `None` indicating that the pattern was not matched.
     pub(crate) fn get_program_data(&self) -> Option<&quil_rs::Program> {
@@ -132,6 +267,21 @@ impl<'ctx> UnitaryPatternMatchContext<'ctx> {
         }
     }
 
+    /// Returns the number of bits which must be declared in the `ro` memory region to hold every
+    /// readout value.
+    pub(crate) fn get_readout_length(&self) -> u64 {
+        readout_length(&self.read_result_mapping)
+    }
+
+    /// Returns the value from which each slot of the parameter memory region is set, as printed in
+    /// the module, in order.
+    pub(crate) fn get_parameter_table(&self) -> Vec<String> {
+        self.parameters
+            .iter()
+            .map(|value| value.print_to_string().to_string().trim().to_owned())
+            .collect()
+    }
+
     /// Returns the parameters which do not have a constant value.
     pub(crate) fn get_dynamic_parameters(&self) -> Vec<&FloatValue<'ctx>> {
         self.parameters
@@ -147,13 +297,13 @@ macro_rules! match_qis_argument {
 
         match $arguments.get($index) {
             Some($variant(contents)) => Ok(contents),
-            other => Err(eyre!(
+            other => Err(KindError::InvalidArgument(format!(
                 "expected argument {} of {} to be of type {:?}; got {:?}",
                 $index,
                 $function_name,
                 stringify!($variant),
                 other
-            )),
+            ))),
         }
     }};
 }
@@ -164,8 +314,13 @@ fn get_quil_parameter_expression<'ctx>(
     pattern_context: &mut UnitaryPatternMatchContext<'ctx>,
     float_value: FloatValue<'ctx>,
 ) -> Expression {
+    let float_value = fold_constant_select(float_value);
     if let Some((constant, _)) = float_value.get_constant() {
-        return Expression::Number(constant.into());
+        return build_constant_expression(
+            constant,
+            pattern_context.float_precision,
+            pattern_context.symbolic_pi,
+        );
     }
 
     let index = get_quil_parameter_index(pattern_context, float_value);
@@ -175,6 +330,29 @@ fn get_quil_parameter_expression<'ctx>(
     })
 }
 
+/// Given a `FloatValue` to be used as the parameter to a gate, return the Quil expression for that
+/// parameter multiplied by `factor`. Constants are multiplied before they are written out.
+fn get_scaled_quil_parameter_expression<'ctx>(
+    pattern_context: &mut UnitaryPatternMatchContext<'ctx>,
+    float_value: FloatValue<'ctx>,
+    factor: f64,
+) -> Expression {
+    let float_value = fold_constant_select(float_value);
+    if let Some((constant, _)) = float_value.get_constant() {
+        return build_constant_expression(
+            factor * constant,
+            pattern_context.float_precision,
+            pattern_context.symbolic_pi,
+        );
+    }
+
+    Expression::Infix {
+        left: Box::new(Expression::Number(factor.into())),
+        operator: InfixOperator::Star,
+        right: Box::new(get_quil_parameter_expression(pattern_context, float_value)),
+    }
+}
+
 /// Given a `FloatValue` to be used as the parameter to a gate, return the index within the
 /// Quil `MemoryReference` that should be used to store this parameter's value.
 pub(crate) fn get_quil_parameter_index<'ctx>(
@@ -184,7 +362,7 @@ pub(crate) fn get_quil_parameter_index<'ctx>(
     if let Some(index) = pattern_context
         .parameters
         .iter()
-        .position(|el| el == &float_value)
+        .position(|el| is_same_parameter(*el, float_value))
     {
         index
     } else {
@@ -248,8 +426,6 @@ lazy_static! {
         r"^__quantum__qis__(?P<operation>[^_]+)(?P<controlled>__ctl)?(?P<adjoint>__adj)?(__body)?$"
     )
     .unwrap();
-    static ref RT_RECORD_OUTPUT_INTRINSIC_REGEX: Regex =
-        Regex::new("^__quantum__rt__(?P<record_type>.+)_record_output$").unwrap();
 }
 
 pub(crate) fn rt_record_instruction<'ctx>(
@@ -257,61 +433,14 @@ pub(crate) fn rt_record_instruction<'ctx>(
     pattern_context: &mut UnitaryPatternMatchContext<'ctx>,
     instruction: InstructionValue<'ctx>,
 ) -> Result<PatternResult<'ctx, ()>> {
-    match instruction.get_opcode() {
-        inkwell::values::InstructionOpcode::Call => {
-            let function_target_name = get_called_function_name(instruction)?;
-
-            if let Some(function_name) = function_target_name {
-                if let Some(captures) = RT_RECORD_OUTPUT_INTRINSIC_REGEX.captures(&function_name) {
-                    let record_type = &captures["record_type"];
-
-                    match record_type {
-                        "result" => {
-                            let arguments = get_qis_function_arguments(context, instruction)?;
-                            if let Some(OperationArgument::Result(result_index)) = arguments.get(0)
-                            {
-                                let next_ro_index =
-                                    pattern_context.read_result_mapping.len() as u64;
-                                let index = pattern_context.read_result_mapping.entry(*result_index).or_insert_with(|| {
-                                    log::info!("Result index {} was read but was never the target of a measurement operation, so recorded output value will always be 0", result_index);
-                                    next_ro_index
-                                });
-                                pattern_context
-                                    .recorded_output
-                                    .push(RecordedOutput::ResultReadoutOffset(*index));
-                            } else {
-                                return Err(eyre!(
-                                    "malformed or missing arguments for: __quantum_rt__{}_record_output",
-                                    record_type
-                                ));
-                            }
-                        }
-                        "bool" | "integer" | "double" => {
-                            return Err(eyre!("unimplemented record type: {}", record_type));
-                        }
-                        "tuple_start" => pattern_context
-                            .recorded_output
-                            .push(RecordedOutput::TupleStart),
-                        "tuple_end" => pattern_context
-                            .recorded_output
-                            .push(RecordedOutput::TupleEnd),
-                        "array_start" => pattern_context
-                            .recorded_output
-                            .push(RecordedOutput::ArrayStart),
-                        "array_end" => pattern_context
-                            .recorded_output
-                            .push(RecordedOutput::ArrayEnd),
-                        _ => {
-                            return Ok(None);
-                        }
-                    }
-                    return Ok(Some((instruction.get_next_instruction(), ())));
-                }
-            }
-            Ok(None)
-        }
-        _ => Ok(None),
-    }
+    let matched = pattern_context.recording.record_call(
+        context,
+        instruction,
+        &mut pattern_context.read_result_mapping,
+        &pattern_context.readout_instruction_mapping,
+        || get_qis_function_arguments(context, instruction),
+    )?;
+    Ok(matched.then(|| (instruction.get_next_instruction(), ())))
 }
 
 #[allow(clippy::too_many_lines)]
@@ -507,33 +636,98 @@ pub(crate) fn quantum_instruction<'ctx>(
                             )?;
                             true
                         }
+                        "exp" if !controlled => {
+                            let paulis =
+                                match_qis_argument!(Array, arguments, 0, function_name.as_str())?;
+                            let theta = *match_qis_argument!(
+                                Parameter,
+                                arguments,
+                                1,
+                                function_name.as_str()
+                            )?;
+                            let qubits =
+                                match_qis_argument!(Array, arguments, 2, function_name.as_str())?;
+                            let terms = get_pauli_terms(paulis, qubits)?;
+
+                            // exp(iθZ) is RZ(-2θ), and its adjoint RZ(2θ)
+                            let factor = if adjoint { 2.0 } else { -2.0 };
+                            let angle = get_scaled_quil_parameter_expression(
+                                pattern_context,
+                                theta,
+                                factor,
+                            );
+                            for instruction in build_pauli_exponential(&terms, angle) {
+                                pattern_context.quil_program.add_instruction(instruction);
+                            }
+
+                            true
+                        }
+                        "measure" => {
+                            let paulis =
+                                match_qis_argument!(Array, arguments, 0, function_name.as_str())?;
+                            let qubits =
+                                match_qis_argument!(Array, arguments, 1, function_name.as_str())?;
+                            let result =
+                                *match_qis_argument!(Result, arguments, 2, function_name.as_str())?;
+                            let terms = get_pauli_terms(paulis, qubits)?;
+
+                            let ro_buffer_index = assign_readout_offset(
+                                context,
+                                &mut pattern_context.read_result_mapping,
+                                result,
+                            )?;
+                            for instruction in build_joint_measurement(
+                                &terms,
+                                MemoryReference {
+                                    name: String::from("ro"),
+                                    index: ro_buffer_index,
+                                },
+                                context.options.flip_readout,
+                            )? {
+                                pattern_context.quil_program.add_instruction(instruction);
+                            }
+
+                            true
+                        }
                         "mz" => {
                             let qubit =
                                 *match_qis_argument!(Qubit, arguments, 0, function_name.as_str())?;
                             let result =
                                 *match_qis_argument!(Result, arguments, 1, function_name.as_str())?;
 
-                            // Result indices may be sparse rather than increasing monotonically from 0.
-                            // If used naively (i.e. %Result 5 as `ro[5]`), this would result in sparse, suboptimal allocation
-                            // in the readout data fetched following execution. So, instead, we assign Result indices to
-                            // monotonically increasing `ro` region offsets so as to keep `ro` dense.
-                            let next_ro_index = pattern_context.read_result_mapping.len() as u64;
-                            let ro_buffer_index = pattern_context
-                                .read_result_mapping
-                                .entry(result)
-                                .or_insert_with(|| next_ro_index);
+                            let ro_buffer_index = assign_readout_offset(
+                                context,
+                                &mut pattern_context.read_result_mapping,
+                                result,
+                            )?;
 
+                            let flip = quil_rs::instruction::Instruction::Gate(
+                                quil_rs::instruction::Gate {
+                                    name: String::from("X"),
+                                    parameters: vec![],
+                                    qubits: vec![Qubit::Fixed(qubit)],
+                                    modifiers: vec![],
+                                },
+                            );
+                            // When flipping readout, the qubit is flipped back after measurement so
+                            // that later operations see the state they would have without the flip.
+                            if context.options.flip_readout {
+                                pattern_context.quil_program.add_instruction(flip.clone());
+                            }
                             pattern_context.quil_program.add_instruction(
                                 quil_rs::instruction::Instruction::Measurement(
                                     quil_rs::instruction::Measurement {
                                         target: Some(MemoryReference {
                                             name: String::from("ro"),
-                                            index: *ro_buffer_index,
+                                            index: ro_buffer_index,
                                         }),
                                         qubit: Qubit::Fixed(qubit),
                                     },
                                 ),
                             );
+                            if context.options.flip_readout {
+                                pattern_context.quil_program.add_instruction(flip);
+                            }
 
                             true
                         }
@@ -559,6 +753,22 @@ pub(crate) fn quantum_instruction<'ctx>(
                     }
                     pattern_context.instructions_to_remove.push(instruction);
                     Ok(Some((instruction.get_next_instruction(), ())))
+                } else if let Some(captures) = QIS_FRAME_INTRINSIC_REGEX.captures(&function_name) {
+                    let arguments = get_qis_function_arguments(context, instruction)?;
+                    let value =
+                        *match_qis_argument!(Parameter, arguments, 0, function_name.as_str())?;
+                    let qubit = *match_qis_argument!(Qubit, arguments, 1, function_name.as_str())?;
+                    let value = get_quil_parameter_expression(pattern_context, value);
+                    pattern_context
+                        .quil_program
+                        .add_instruction(build_frame_instruction(
+                            &captures["mutation"],
+                            &captures["attribute"],
+                            qubit,
+                            value,
+                        )?);
+                    pattern_context.instructions_to_remove.push(instruction);
+                    Ok(Some((instruction.get_next_instruction(), ())))
                 } else {
                     Ok(None)
                 }
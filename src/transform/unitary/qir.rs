@@ -18,121 +18,40 @@ use inkwell::{
     values::{FunctionValue, InstructionOpcode},
 };
 use log::{debug, info};
-use quil_rs::instruction::Vector;
 
 use crate::interop::{
-    call, entrypoint::get_entry_function, instruction::remove_instructions_in_safe_order,
+    call,
+    entrypoint::get_entry_function,
+    metadata::get_shot_count,
+    rewrite::{copy_name_and_metadata, remove_instructions_in_safe_order},
+};
+use crate::{
+    context::{context::EmittedProgram, QCSCompilerContext},
+    error::KindError,
+    quil_builder::{
+        declare_params, declare_readout, initial_rewiring, prepend_pragmas, prepend_reset,
+    },
+    transform::{
+        dump::BlockDump, get_functions_to_transform, layout::InjectedBlock,
+        liveness::handle_reused_qubits, ordering::apply_measurement_order,
+        peephole::optimize_program, postprocess::apply_post_processors, prepend_calibrations,
+        shot_count_block::qir::build_executable_cache,
+    },
 };
-use crate::{context::QCSCompilerContext, transform::PARAMETER_MEMORY_REGION_NAME};
 
 use super::pattern::UnitaryPatternMatchContext;
 
-/// Build and insert an LLVM function which performs up-front translation of
-/// all Quil programs used in the module and stores them in an array referred
-/// to as the "executable cache".
-pub(crate) fn build_populate_executable_cache_function<'ctx>(
-    context: &mut QCSCompilerContext<'ctx>,
-) -> Result<FunctionValue<'ctx>> {
-    const FN_NAME_POPULATE_EXECUTABLE_ARRAY: &str = "populate_executable_array";
-
-    if let Some(existing_function) = context
-        .module
-        .get_function(FN_NAME_POPULATE_EXECUTABLE_ARRAY)
-    {
-        Ok(existing_function)
-    } else {
-        let populate_executable_array_function_type =
-            context.base_context.void_type().fn_type(&[], false);
-        let populate_executable_array_function = context.module.add_function(
-            FN_NAME_POPULATE_EXECUTABLE_ARRAY,
-            populate_executable_array_function_type,
-            None,
-        );
-        let basic_block = context
-            .base_context
-            .append_basic_block(populate_executable_array_function, "entry");
-
-        context.builder.position_at_end(basic_block);
-
-        let actual_executable_cache = context
-            .builder
-            .build_call(
-                context.values.create_executable_cache(),
-                &[context
-                    .base_context
-                    .i32_type()
-                    .const_int(context.quil_programs.len() as u64, false)
-                    .into()],
-                "",
-            )
-            .try_as_basic_value()
-            .left()
-            .ok_or_else(|| eyre!("create_executable_cache does not have a return value"))?
-            .into_pointer_value();
-
-        context.builder.build_store(
-            context.values.executable_cache().as_pointer_value(),
-            actual_executable_cache,
-        );
-
-        for index in 0..context.quil_programs.len() {
-            let program_text = context.quil_programs[index].to_string(true);
-
-            let quil_program_global_string = unsafe {
-                // NOTE: this segfaults if the builder is not already positioned within a basic block
-                // see https://github.com/TheDan64/inkwell/issues/32
-                context
-                    .builder
-                    .build_global_string(&program_text, "quil_program")
-            };
-
-            context.builder.build_call(
-                context.values.add_executable_cache_item(),
-                &[
-                    actual_executable_cache.into(),
-                    context
-                        .base_context
-                        .i32_type()
-                        .const_int(index as u64, false)
-                        .into(),
-                    quil_program_global_string
-                        .as_pointer_value()
-                        .const_cast(context.types.string())
-                        .into(),
-                ],
-                "",
-            );
-        }
-
-        context.builder.build_return(None);
-
-        Ok(populate_executable_array_function)
-    }
-}
-
 /// Mutate a context such that all contiguous instructions which may be transpiled by `transpile_instruction`
 /// are inlined and executed using a shared library call.
 #[allow(dead_code)]
 pub(crate) fn transpile_module(context: &mut QCSCompilerContext) -> Result<()> {
-    let entrypoint_function = get_entry_function(&context.module)
-        .ok_or_else(|| eyre!("entrypoint not found in module"))?;
-    transpile_function(context, entrypoint_function)?;
-    let populate_function = build_populate_executable_cache_function(context)?;
-
-    let entry_basic_block = entrypoint_function
-        .get_first_basic_block()
-        .ok_or_else(|| eyre!("entrypoint function has no basic blocks"))?;
-
-    match entry_basic_block.get_first_instruction() {
-        Some(instruction) => {
-            context.builder.position_before(&instruction);
-        }
-        None => context.builder.position_at_end(entry_basic_block),
-    };
-
-    context.builder.build_call(populate_function, &[], "");
-
-    Ok(())
+    let entrypoint_function = get_entry_function(&context.module).ok_or_else(|| {
+        KindError::MissingEntrypoint(String::from("entrypoint not found in module"))
+    })?;
+    for function in get_functions_to_transform(context, entrypoint_function)? {
+        transpile_function(context, function)?;
+    }
+    build_executable_cache(context, entrypoint_function)
 }
 
 pub(crate) fn transpile_function<'ctx>(
@@ -149,9 +68,15 @@ pub(crate) fn transpile_basic_block<'ctx>(
     context: &mut QCSCompilerContext<'ctx>,
     basic_block: BasicBlock<'ctx>,
 ) -> eyre::Result<()> {
-    let pattern_context = UnitaryPatternMatchContext::from_basic_block(context, basic_block)?;
+    let mut dump = BlockDump::begin(context, basic_block);
+    let pattern_context =
+        UnitaryPatternMatchContext::from_basic_block(context, basic_block, vec![])?;
+    if let Some(dump) = &mut dump {
+        dump.write_match(&pattern_context)?;
+    }
 
-    insert_quil_program(context, pattern_context, basic_block)
+    insert_quil_program(context, pattern_context, basic_block)?;
+    dump.map_or(Ok(()), BlockDump::finish)
 }
 
 /// Insert the quil program which has been collected from a `BasicBlock` (if any):
@@ -168,80 +93,62 @@ pub(crate) fn insert_quil_program<'ctx, 'p: 'ctx>(
     if let Some(program) = pattern_context.get_program_data() {
         debug!("inserting quil program: {}", program.to_string(true));
 
-        let mut program = program.clone();
-
-        program.add_instruction(quil_rs::instruction::Instruction::Declaration(
-            quil_rs::instruction::Declaration {
-                name: String::from("ro"),
-                size: Vector {
-                    data_type: quil_rs::instruction::ScalarType::Bit,
-                    length: pattern_context.read_result_mapping.len() as u64,
-                },
-                sharing: None,
-            },
-        ));
-
-        if !pattern_context.get_dynamic_parameters().is_empty() {
-            program.add_instruction(quil_rs::instruction::Instruction::Declaration(
-                quil_rs::instruction::Declaration {
-                    name: String::from(PARAMETER_MEMORY_REGION_NAME),
-                    size: Vector {
-                        data_type: quil_rs::instruction::ScalarType::Real,
-                        length: pattern_context.get_dynamic_parameters().len() as u64,
-                    },
-                    sharing: None,
-                },
-            ));
-        }
+        let program = if context.options.optimize_quil {
+            optimize_program(&context.options, program, &[]).0
+        } else {
+            program.clone()
+        };
+        let (mut program, _) =
+            apply_measurement_order(context.options.measurement_order, program, vec![])?;
+
+        declare_readout(&mut program, pattern_context.get_readout_length());
+        declare_params(
+            &mut program,
+            pattern_context.get_dynamic_parameters().len() as u64,
+        );
 
         if pattern_context.use_active_reset {
-            // Prepend a reset to the program via copy
-            let instructions = program.to_instructions(true);
-            let mut new_program = quil_rs::program::Program::new();
-            new_program.add_instruction(quil_rs::instruction::Instruction::Reset(
-                quil_rs::instruction::Reset { qubit: None },
-            ));
-            for instruction in instructions {
-                new_program.add_instruction(instruction);
-            }
-            program = new_program;
+            program = prepend_reset(program);
         }
 
+        program = handle_reused_qubits(context, program, basic_block);
+        program = prepend_calibrations(context, program);
+
         if let Some(rewiring_pragma) = &context.options.rewiring_pragma {
-            // Prepend a pragma to the program via copy
-            let instructions = program.to_instructions(true);
-            let mut new_program = quil_rs::program::Program::new();
-            new_program.add_instruction(quil_rs::instruction::Instruction::Pragma(
-                quil_rs::instruction::Pragma {
-                    name: String::from("INITIAL_REWIRING"),
-                    arguments: vec![format!("\"{}\"", rewiring_pragma.clone())],
-                    data: None,
-                },
-            ));
-            for instruction in instructions {
-                new_program.add_instruction(instruction);
-            }
-            program = new_program;
+            program = prepend_pragmas(program, [initial_rewiring(rewiring_pragma)]);
         }
 
+        apply_post_processors(context, &mut program, basic_block)?;
+
+        let shot_count = match basic_block.get_parent() {
+            Some(function) => get_shot_count(&context.module, function)?,
+            None => None,
+        };
+
         // We write all the new instructions to a new basic block
         let execution_basic_block = context.base_context.insert_basic_block_after(
             basic_block,
             &format!("{}_execution", basic_block.get_name().to_str()?),
         );
         basic_block.replace_all_uses_with(&execution_basic_block);
+        context.injected_blocks.push(InjectedBlock {
+            block: execution_basic_block,
+            origin: basic_block,
+            role: "execution",
+        });
         context.builder.position_at_end(execution_basic_block);
 
-        let executable = if context.options.cache_executables {
-            let quil_program_index = context.quil_programs.len();
-            context.quil_programs.push(program);
-
+        let cache_index = context
+            .options
+            .cache_executables
+            .then(|| context.cached_programs().count());
+        let executable = if let Some(cache_index) = cache_index {
             call::get_executable(
                 context,
                 context
                     .base_context
                     .i32_type()
-                    .const_int(quil_program_index as u64, false),
+                    .const_int(cache_index as u64, false),
             )?
         } else {
             let program_text = program.to_string(true);
@@ -256,6 +163,21 @@ pub(crate) fn insert_quil_program<'ctx, 'p: 'ctx>(
             // Insert the shared library calls to send this program for execution
             call::executable_from_quil(context, quil_program_global_string.as_pointer_value())?
         };
+        context.emitted_programs.push(EmittedProgram {
+            program,
+            function: basic_block
+                .get_parent()
+                .map(|function| function.get_name().to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            block: basic_block.get_name().to_string_lossy().into_owned(),
+            shot_count,
+            recorded_output: pattern_context.recording.entries().to_vec(),
+            cache_index,
+        });
+
+        if let Some(shot_count) = shot_count {
+            call::wrap_in_shots(context, &executable, shot_count);
+        }
 
         for (index, value) in pattern_context.parameters.iter().enumerate() {
             call::set_param(context, &executable, index as u64, *value);
@@ -276,6 +198,11 @@ pub(crate) fn insert_quil_program<'ctx, 'p: 'ctx>(
             basic_block,
             format!("{}_cleanup", basic_block.get_name().to_str()?).as_str(),
         );
+        context.injected_blocks.push(InjectedBlock {
+            block: cleanup_basic_block,
+            origin: basic_block,
+            role: "cleanup",
+        });
 
         context.builder.position_at_end(execution_basic_block);
         context
@@ -284,6 +211,10 @@ pub(crate) fn insert_quil_program<'ctx, 'p: 'ctx>(
 
         context.builder.position_at_end(cleanup_basic_block);
         call::free_execution_result(context, &execution_result);
+        // A cached executable is freed along with the cache
+        if cache_index.is_none() {
+            call::free_executable(context, &executable);
+        }
         context.builder.build_return(None);
 
         let entry_function = get_entry_function(&context.module)
@@ -301,11 +232,12 @@ pub(crate) fn insert_quil_program<'ctx, 'p: 'ctx>(
             .builder
             .position_before(&last_entry_block_instruction);
 
-        context
+        let branch_instruction = context
             .builder
             .build_unconditional_branch(execution_basic_block);
 
         if last_entry_block_instruction.get_opcode() == InstructionOpcode::Return {
+            copy_name_and_metadata(context, last_entry_block_instruction, branch_instruction)?;
             last_entry_block_instruction.remove_from_basic_block();
         }
 
@@ -354,10 +286,7 @@ mod test {
                         &base_context,
                         &data,
                         ExecutionTarget::Qvm,
-                        ContextOptions {
-                            cache_executables: false,
-                            rewiring_pragma: None,
-                        },
+                        ContextOptions::default(),
                     )
                     .unwrap();
                     transpile_module(&mut context).expect("transpilation failed");
@@ -371,5 +300,25 @@ mod test {
         make_snapshot_test!(entrypoint_attribute);
         make_snapshot_test!(qiskit_qir_measure);
         make_snapshot_test!(qiskit_qir_allow_unmeasured);
+
+        #[test]
+        fn shot_count_from_module_flag() {
+            let base_context = inkwell::context::Context::create();
+            let data = std::fs::read("tests/fixtures/programs/unitary/shots_metadata.bc").unwrap();
+            let mut context = QCSCompilerContext::new_from_data(
+                &base_context,
+                &data,
+                ExecutionTarget::Qvm,
+                ContextOptions::default(),
+            )
+            .unwrap();
+            transpile_module(&mut context).expect("transpilation failed");
+
+            let ir = context.module.print_to_string().to_string();
+            assert!(ir.lines().any(
+                |line| line.contains("call void @wrap_in_shots(") && line.ends_with("i32 500)")
+            ));
+            assert_eq!(context.emitted_programs[0].shot_count, Some(500));
+        }
     }
 }
@@ -15,20 +15,34 @@
 // This module is responsible for the transpilation of contiguous subsequences of LLVM instructions
 // into quil, substituting those instructions with inline calls to a shared library responsible for
 // executing those quil instructions.
+use std::collections::BTreeMap;
+
 use eyre::{eyre, Result};
 
 #[allow(unused)]
 use inkwell::types::AnyType;
 
-use inkwell::{basic_block::BasicBlock, values::FunctionValue};
-use quil_rs::instruction::Vector;
+use inkwell::{
+    basic_block::BasicBlock,
+    values::{BasicValueEnum, FloatValue, FunctionValue},
+};
 
 #[cfg(feature = "serde_support")]
 use serde::{ser::SerializeStruct, Serialize, Serializer};
 
 use crate::{
-    context::QCSCompilerContext, interop::entrypoint::get_entry_function,
-    transform::PARAMETER_MEMORY_REGION_NAME, RecordedOutput,
+    context::QCSCompilerContext,
+    error::KindError,
+    interop::{entrypoint::get_entry_function, metadata::get_shot_count},
+    quil_builder::{
+        declare_params, declare_readout, initial_rewiring, prepend_pragmas, prepend_reset,
+    },
+    transform::{
+        compact::compact_qubits, format_traced_program, ordering::apply_measurement_order,
+        peephole::optimize_program,
+    },
+    validation::{get_qir_declarations, QirDeclarations},
+    RecordedOutput, RecordingPlan, TranspileWarning,
 };
 
 use super::pattern::UnitaryPatternMatchContext;
@@ -38,9 +52,48 @@ use super::pattern::UnitaryPatternMatchContext;
 pub struct ProgramOutput {
     /// The Quil program itself
     pub program: quil_rs::Program,
+    /// The number of shots to run the program for, if the module gives one as metadata: the
+    /// `shots` attribute of the entrypoint, the `shots` module flag, or `!quantum.shots`.
+    pub shot_count: Option<u64>,
 
     /// Signifies output to be recorded at the end of program execution
     pub recorded_output: Vec<RecordedOutput>,
+    /// When transpiled with tracing enabled, the QIR instruction from which each instruction
+    /// translated into `program` originated, in order. These correspond to the final instructions
+    /// of the program, following any which were prepended to it such as calibrations.
+    pub trace: Vec<String>,
+    /// The QIR value from which each element of the `__qir_param` memory region is set, as printed
+    /// in the module, in order. Parameters holding the same value share an element.
+    pub parameters: Vec<String>,
+    /// The name of each argument of the entrypoint, as `%theta`, paired with the index of the
+    /// element of the `__qir_param` memory region which must be set to it, in the order of the
+    /// arguments. Each argument has an element, whether or not the program uses it.
+    pub arguments: Vec<(String, usize)>,
+    /// The program compiled to native Quil, when transpiled with a
+    /// [`crate::TranspileOptions::quil_compiler`].
+    pub native_program: Option<quil_rs::Program>,
+    /// The QIR version and profile which the module declares.
+    pub qir_declarations: QirDeclarations,
+    /// The index in `program` of each qubit of the module, when transpiled with
+    /// [`crate::TranspileOptions::compact_qubits`]; otherwise empty.
+    pub qubit_mapping: BTreeMap<u64, u64>,
+    /// The likely mistakes found in the module while transpiling it, in the order found.
+    pub warnings: Vec<TranspileWarning>,
+}
+
+impl ProgramOutput {
+    /// Render the program as Quil, with each instruction translated from QIR preceded by a comment
+    /// naming the instruction it was translated from, as in `# from: tail call void @...`.
+    #[must_use]
+    pub fn to_traced_string(&self) -> String {
+        format_traced_program(&self.program, &self.trace)
+    }
+
+    /// The output recorded by each shot of the program, for formatting its results.
+    #[must_use]
+    pub fn recording_plan(&self) -> RecordingPlan {
+        RecordingPlan::from(self.recorded_output.clone())
+    }
 }
 
 #[cfg(feature = "serde_support")]
@@ -49,9 +102,44 @@ impl Serialize for ProgramOutput {
     where
         S: Serializer,
     {
-        let mut output = serializer.serialize_struct("ProgramOutput", 3)?;
-        output.serialize_field("program", &self.program.to_string(true))?;
+        let mut output = serializer.serialize_struct("ProgramOutput", 9)?;
+        if self.trace.is_empty() {
+            output.serialize_field("program", &self.program.to_string(true))?;
+        } else {
+            output.serialize_field("program", &self.to_traced_string())?;
+        }
+        match self.shot_count {
+            Some(shot_count) => output.serialize_field("shot_count", &shot_count)?,
+            None => output.skip_field("shot_count")?,
+        }
         output.serialize_field("recorded_output", &self.recorded_output)?;
+        output.serialize_field("parameters", &self.parameters)?;
+        if self.arguments.is_empty() {
+            output.skip_field("arguments")?;
+        } else {
+            output.serialize_field("arguments", &self.arguments)?;
+        }
+        match &self.native_program {
+            Some(native_program) => {
+                output.serialize_field("native_program", &native_program.to_string(true))?;
+            }
+            None => output.skip_field("native_program")?,
+        }
+        if self.qir_declarations.is_empty() {
+            output.skip_field("qir_declarations")?;
+        } else {
+            output.serialize_field("qir_declarations", &self.qir_declarations)?;
+        }
+        if self.qubit_mapping.is_empty() {
+            output.skip_field("qubit_mapping")?;
+        } else {
+            output.serialize_field("qubit_mapping", &self.qubit_mapping)?;
+        }
+        if self.warnings.is_empty() {
+            output.skip_field("warnings")?;
+        } else {
+            output.serialize_field("warnings", &self.warnings)?;
+        }
         output.end()
     }
 }
@@ -59,32 +147,67 @@ impl Serialize for ProgramOutput {
 /// Transform an entire QIR module to a single Quil program
 #[allow(dead_code)]
 pub(crate) fn transpile_module(context: &mut QCSCompilerContext) -> Result<ProgramOutput> {
-    let entrypoint_function = get_entry_function(&context.module)
-        .ok_or_else(|| eyre!("entrypoint not found in module"))?;
+    let entrypoint_function = get_entry_function(&context.module).ok_or_else(|| {
+        KindError::MissingEntrypoint(String::from("entrypoint not found in module"))
+    })?;
     transpile_function(context, entrypoint_function)
 }
 
 /// Transpile a single QIR function body to a Quil program. This function may have a single basic
-/// block, comprised of quantum instructions.
+/// block, comprised of quantum instructions. Its arguments, each of which must be a `double`, are
+/// assigned the first elements of the parameter memory region, in order.
 pub(crate) fn transpile_function<'ctx>(
     context: &mut QCSCompilerContext<'ctx>,
     function: FunctionValue<'ctx>,
 ) -> eyre::Result<ProgramOutput> {
     // validate that the function returns void, as a requirement of the Unitary format
-    let void_func_ty = context.base_context.void_type().fn_type(&[], false);
     let func_ty = function.get_type();
-    if func_ty.ne(&void_func_ty) {
+    if func_ty.get_return_type().is_some() {
         return Err(eyre::eyre!(
             "expected function to return void; found {}",
             func_ty.print_to_string()
         ));
     }
 
+    // double parameters are given to the entrypoint as arguments, per the base profile
+    let f64_type = context.base_context.f64_type();
+    let mut arguments = vec![];
+    for (index, parameter) in function.get_param_iter().enumerate() {
+        match parameter {
+            BasicValueEnum::FloatValue(value) if value.get_type() == f64_type => {
+                arguments.push(value);
+            }
+            _ => {
+                return Err(eyre::eyre!(
+                    "expected each argument of the function to be a double; found {} for argument {}",
+                    parameter.get_type().print_to_string(),
+                    index
+                ));
+            }
+        }
+    }
+
     let blocks = function.get_basic_blocks();
     let block_count = blocks.len();
 
     match blocks.into_iter().next() {
-        Some(first_block) if block_count == 1 => transpile_basic_block(context, first_block),
+        Some(first_block) if block_count == 1 => {
+            let mut output = transpile_basic_block(context, first_block, arguments.clone())?;
+            output.shot_count = get_shot_count(&context.module, function)?;
+            output.arguments = arguments
+                .iter()
+                .enumerate()
+                .map(|(index, argument)| {
+                    let name = argument.get_name().to_string_lossy();
+                    if name.is_empty() {
+                        (format!("%{}", index), index)
+                    } else {
+                        (format!("%{}", name), index)
+                    }
+                })
+                .collect();
+            Ok(output)
+        }
         _ => Err(eyre::eyre!(
             "expected function to have a single basic block; found {}",
             block_count
@@ -124,12 +247,15 @@ fn validates_unitary_ret_void() {
 }
 
 /// Transpile a single QIR basic block to a Quil program. This block must match the pattern
-/// recognized by `UnitaryPatternMatchContext` in order to succeed.
+/// recognized by `UnitaryPatternMatchContext` in order to succeed. The values of `parameters` are
+/// assigned the first elements of the parameter memory region, in order.
 pub(crate) fn transpile_basic_block<'ctx>(
     context: &mut QCSCompilerContext<'ctx>,
     basic_block: BasicBlock<'ctx>,
+    parameters: Vec<FloatValue<'ctx>>,
 ) -> eyre::Result<ProgramOutput> {
-    let pattern_context = UnitaryPatternMatchContext::from_basic_block(context, basic_block)?;
+    let pattern_context =
+        UnitaryPatternMatchContext::from_basic_block(context, basic_block, parameters)?;
 
     build_quil_program(context, &pattern_context)
 }
@@ -141,65 +267,55 @@ pub(crate) fn build_quil_program<'ctx, 'p: 'ctx>(
     context: &mut QCSCompilerContext<'ctx>,
     pattern_context: &UnitaryPatternMatchContext<'p>,
 ) -> eyre::Result<ProgramOutput> {
-    let mut program = pattern_context.quil_program.clone();
-
-    program.add_instruction(quil_rs::instruction::Instruction::Declaration(
-        quil_rs::instruction::Declaration {
-            name: String::from("ro"),
-            size: Vector {
-                data_type: quil_rs::instruction::ScalarType::Bit,
-                length: pattern_context.read_result_mapping.len() as u64,
-            },
-            sharing: None,
-        },
-    ));
-
-    if !pattern_context.get_dynamic_parameters().is_empty() {
-        program.add_instruction(quil_rs::instruction::Instruction::Declaration(
-            quil_rs::instruction::Declaration {
-                name: String::from(PARAMETER_MEMORY_REGION_NAME),
-                size: Vector {
-                    data_type: quil_rs::instruction::ScalarType::Real,
-                    length: pattern_context.get_dynamic_parameters().len() as u64,
-                },
-                sharing: None,
-            },
-        ));
-    }
+    let (program, trace) = if context.options.optimize_quil {
+        optimize_program(
+            &context.options,
+            &pattern_context.quil_program,
+            &pattern_context.trace,
+        )
+    } else {
+        (
+            pattern_context.quil_program.clone(),
+            pattern_context.trace.clone(),
+        )
+    };
+    let (mut program, trace) =
+        apply_measurement_order(context.options.measurement_order, program, trace)?;
+    let qubit_mapping = if context.options.compact_qubits {
+        let (compacted, qubit_mapping) = compact_qubits(&program);
+        program = compacted;
+        qubit_mapping
+    } else {
+        BTreeMap::new()
+    };
+
+    declare_readout(&mut program, pattern_context.get_readout_length());
+    declare_params(
+        &mut program,
+        pattern_context.get_dynamic_parameters().len() as u64,
+    );
 
     if pattern_context.use_active_reset {
-        // Prepend a reset to the program via copy
-        let instructions = program.to_instructions(true);
-        let mut new_program = quil_rs::program::Program::new();
-        new_program.add_instruction(quil_rs::instruction::Instruction::Reset(
-            quil_rs::instruction::Reset { qubit: None },
-        ));
-        for instruction in instructions {
-            new_program.add_instruction(instruction);
-        }
-        program = new_program;
+        program = prepend_reset(program);
     }
 
+    program = prepend_calibrations(context, program);
+
     if let Some(rewiring_pragma) = &context.options.rewiring_pragma {
-        // Prepend a pragma to the program via copy
-        let instructions = program.to_instructions(true);
-        let mut new_program = quil_rs::program::Program::new();
-        new_program.add_instruction(quil_rs::instruction::Instruction::Pragma(
-            quil_rs::instruction::Pragma {
-                name: String::from("INITIAL_REWIRING"),
-                arguments: vec![format!("\"{}\"", rewiring_pragma.clone())],
-                data: None,
-            },
-        ));
-        for instruction in instructions {
-            new_program.add_instruction(instruction);
-        }
-        program = new_program;
+        program = prepend_pragmas(program, [initial_rewiring(rewiring_pragma)]);
     }
 
     Ok(ProgramOutput {
         program,
-        recorded_output: pattern_context.recorded_output.clone(),
+        shot_count: None,
+        recorded_output: pattern_context.recording.entries().to_vec(),
+        trace,
+        parameters: pattern_context.get_parameter_table(),
+        arguments: vec![],
+        native_program: None,
+        qir_declarations: get_qir_declarations(&context.module),
+        qubit_mapping,
+        warnings: pattern_context.recording.warnings().to_vec(),
     })
 }
 
@@ -228,10 +344,7 @@ mod test {
                         &base_context,
                         &data,
                         ExecutionTarget::Qvm,
-                        ContextOptions {
-                            cache_executables: false,
-                            rewiring_pragma: None,
-                        },
+                        ContextOptions::default(),
                     )
                     .unwrap();
                     let result = transpile_module(&mut context).expect("transpilation failed");
@@ -0,0 +1,278 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Evaluation of loops with a constant trip count within a shot, such as one applying the same gate
+//! to each of a range of qubits, so that their quantum instructions may be unrolled into Quil.
+use std::convert::TryInto;
+
+use either::Either;
+use eyre::{eyre, Result};
+use inkwell::{
+    basic_block::BasicBlock,
+    values::{InstructionOpcode, InstructionValue, PhiValue},
+};
+
+use crate::error::KindError;
+use crate::interop::instruction::evaluate_integer;
+
+/// A loop consisting of a single block which branches back to itself, entered unconditionally.
+#[derive(Debug)]
+pub(crate) struct GateLoop<'ctx> {
+    /// The body of the loop
+    pub(crate) block: BasicBlock<'ctx>,
+
+    /// The block to which control passes once the loop is left
+    pub(crate) exit_block: BasicBlock<'ctx>,
+
+    /// The value of each of the loop's `phi` instructions in each iteration, in order
+    pub(crate) iterations: Vec<Vec<(InstructionValue<'ctx>, i64)>>,
+}
+
+/// Given an unconditional branch, return the loop it enters if its target is a single block which
+/// branches back to itself, such as:
+///
+/// ```llvm
+///   br label %loop
+///
+/// loop:
+///   %i = phi i64 [ 0, %body ], [ %next, %loop ]
+///   %qubit = inttoptr i64 %i to %Qubit*
+///   tail call void @__quantum__qis__h__body(%Qubit* %qubit)
+///   %next = add nuw nsw i64 %i, 1
+///   %done = icmp eq i64 %next, 4
+///   br i1 %done, label %exit, label %loop
+/// ```
+///
+/// The loop's trip count must be constant: each `phi` must start from a constant and be carried
+/// around the loop by integer arithmetic, which is evaluated to determine the value of every `phi`
+/// in each iteration until the loop is left, failing if it runs for more than `max_iterations`.
+pub(crate) fn get_gate_loop(
+    branch: InstructionValue,
+    max_iterations: Option<usize>,
+) -> Result<Option<GateLoop>> {
+    if branch.get_opcode() != InstructionOpcode::Br || branch.get_num_operands() != 1 {
+        return Ok(None);
+    }
+    let (preheader, block) = match (branch.get_parent(), branch.get_operand(0)) {
+        (Some(preheader), Some(Either::Right(block))) => (preheader, block),
+        _ => return Ok(None),
+    };
+    let terminator = match block.get_terminator() {
+        Some(terminator)
+            if terminator.get_opcode() == InstructionOpcode::Br
+                && terminator.get_num_operands() == 3 =>
+        {
+            terminator
+        }
+        _ => return Ok(None),
+    };
+
+    // A conditional branch's operands are ordered as its condition, then its `else` and `then` targets.
+    let (continues_on_then, exit_block) =
+        match (terminator.get_operand(2), terminator.get_operand(1)) {
+            (Some(Either::Right(then_block)), Some(Either::Right(else_block))) => {
+                if then_block == block && else_block != block {
+                    (true, else_block)
+                } else if else_block == block && then_block != block {
+                    (false, then_block)
+                } else {
+                    return Ok(None);
+                }
+            }
+            _ => return Ok(None),
+        };
+    let name = block.get_name().to_string_lossy().into_owned();
+
+    let mut phis: Vec<PhiValue> = vec![];
+    let mut next_instruction = block.get_first_instruction();
+    while let Some(instruction) = next_instruction {
+        if instruction.get_opcode() != InstructionOpcode::Phi {
+            break;
+        }
+        phis.push(
+            instruction
+                .try_into()
+                .map_err(|_| eyre!("expected a phi instruction"))?,
+        );
+        next_instruction = instruction.get_next_instruction();
+    }
+
+    let incoming_value = |phi: &PhiValue, from: BasicBlock| {
+        (0..phi.count_incoming())
+            .filter_map(|index| phi.get_incoming(index))
+            .find(|(_, incoming_block)| *incoming_block == from)
+            .map(|(value, _)| value)
+    };
+    let not_constant = || {
+        eyre!(
+            "unable to unroll loop {}: its trip count is not constant",
+            name
+        )
+    };
+
+    let mut bindings = phis
+        .iter()
+        .map(|phi| {
+            incoming_value(phi, preheader)
+                .and_then(|value| evaluate_integer(value, &[]))
+                .map(|value| (phi.as_instruction(), value))
+                .ok_or_else(not_constant)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut iterations = vec![];
+    loop {
+        if let Some(max_iterations) = max_iterations {
+            if iterations.len() == max_iterations {
+                return Err(KindError::LimitExceeded(format!(
+                    "unable to unroll loop {}: it runs for more than {} iterations",
+                    name, max_iterations
+                ))
+                .into());
+            }
+        }
+
+        let condition = terminator
+            .get_operand(0)
+            .and_then(Either::left)
+            .and_then(|condition| evaluate_integer(condition, &bindings))
+            .ok_or_else(not_constant)?;
+        let next_bindings = phis
+            .iter()
+            .map(|phi| {
+                incoming_value(phi, block)
+                    .and_then(|value| evaluate_integer(value, &bindings))
+                    .map(|value| (phi.as_instruction(), value))
+                    .ok_or_else(not_constant)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        iterations.push(bindings);
+        if (condition != 0) != continues_on_then {
+            break;
+        }
+        bindings = next_bindings;
+    }
+
+    Ok(Some(GateLoop {
+        block,
+        exit_block,
+        iterations,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use inkwell::values::AnyValue;
+
+    use super::*;
+    use crate::interop::load::load_module_from_ir;
+
+    /// The values taken by the first `phi` of the loop entered from `@main`'s entry block in each
+    /// iteration, along with each value of the instruction named `%index` in that iteration.
+    fn unroll(ir: &str, max_iterations: Option<usize>) -> Result<Vec<(i64, Option<i64>)>> {
+        let context = inkwell::context::Context::create();
+        let module = load_module_from_ir(&context, ir).unwrap();
+        let branch = module
+            .get_function("main")
+            .and_then(|function| function.get_first_basic_block())
+            .and_then(|block| block.get_terminator())
+            .unwrap();
+        let gate_loop = get_gate_loop(branch, max_iterations)?.unwrap();
+
+        let mut index = None;
+        let mut next_instruction = gate_loop.block.get_first_instruction();
+        while let Some(instruction) = next_instruction {
+            if instruction.get_name().and_then(|name| name.to_str().ok()) == Some("index") {
+                index = Some(instruction);
+            }
+            next_instruction = instruction.get_next_instruction();
+        }
+
+        Ok(gate_loop
+            .iterations
+            .iter()
+            .map(|bindings| {
+                let value = index.and_then(|index| {
+                    evaluate_integer(index.as_any_value_enum().try_into().ok()?, bindings)
+                });
+                (bindings[0].1, value)
+            })
+            .collect())
+    }
+
+    #[test]
+    fn unrolls_i8_counter_wrapping_past_its_signed_range() {
+        const IR: &str = r#"
+define void @main() {
+entry:
+  br label %loop
+
+loop:
+  %i = phi i8 [ 126, %entry ], [ %next, %loop ]
+  %index = zext i8 %i to i64
+  %next = add i8 %i, 1
+  %done = icmp eq i8 %next, -128
+  br i1 %done, label %exit, label %loop
+
+exit:
+  ret void
+}
+"#;
+        assert_eq!(
+            unroll(IR, None).unwrap(),
+            [(126, Some(126)), (127, Some(127))]
+        );
+    }
+
+    #[test]
+    fn unrolls_i1_counter() {
+        const IR: &str = r#"
+define void @main() {
+entry:
+  br label %loop
+
+loop:
+  %flag = phi i1 [ false, %entry ], [ true, %loop ]
+  %index = zext i1 %flag to i64
+  br i1 %flag, label %exit, label %loop
+
+exit:
+  ret void
+}
+"#;
+        assert_eq!(unroll(IR, None).unwrap(), [(0, Some(0)), (1, Some(1))]);
+    }
+
+    #[test]
+    fn fails_beyond_max_iterations() {
+        const IR: &str = r#"
+define void @main() {
+entry:
+  br label %loop
+
+loop:
+  %i = phi i8 [ 0, %entry ], [ %next, %loop ]
+  %next = add i8 %i, 1
+  %done = icmp uge i8 %next, 200
+  br i1 %done, label %exit, label %loop
+
+exit:
+  ret void
+}
+"#;
+        assert_eq!(unroll(IR, Some(200)).unwrap().len(), 200);
+        assert!(unroll(IR, Some(199)).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Verification that each program patched into a module for a QPU translates for that QPU, and that
+//! the readout it records survives translation, so that a program which would fail at its first
+//! execution fails when it is patched instead, with the diagnostics of the translation service.
+
+use eyre::{eyre, Result, WrapErr};
+
+use crate::context::context::EmittedProgram;
+use crate::RecordedOutput;
+
+/// A program as translated for a QPU.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TranslatedProgram {
+    /// Each memory reference which is read out, as `ro[0]` (or `ro` for the first element of a
+    /// region), paired with the name of the readout source it is filled from.
+    pub ro_sources: Vec<(String, String)>,
+}
+
+/// Translates Quil programs for a QPU, as the QCS translation service does when a program is first
+/// executed. Registered through [`crate::PatchOptions::translation_service`].
+pub trait TranslationService: Send + Sync {
+    /// Translate `quil` to run `shot_count` shots on the QPU `quantum_processor_id`.
+    ///
+    /// # Errors
+    /// Returns the diagnostics of the service if the program does not translate.
+    fn translate(
+        &self,
+        quil: &str,
+        shot_count: u64,
+        quantum_processor_id: &str,
+    ) -> Result<TranslatedProgram>;
+}
+
+/// Translate each program with `service` and check that every memory reference recorded by the
+/// program is read out by the translation.
+pub(crate) fn verify_translation(
+    service: &dyn TranslationService,
+    programs: &[EmittedProgram],
+    quantum_processor_id: &str,
+) -> Result<()> {
+    for emitted in programs {
+        let location = format!("@{}, block %{}", emitted.function, emitted.block);
+        let translated = service
+            .translate(
+                &emitted.program.to_string(true),
+                emitted.shot_count.unwrap_or(1),
+                quantum_processor_id,
+            )
+            .wrap_err_with(|| {
+                format!(
+                    "the program in {} failed translation for {}",
+                    location, quantum_processor_id
+                )
+            })?;
+
+        let missing = get_recorded_readouts(&emitted.recorded_output)
+            .into_iter()
+            .filter(|(region, offset)| {
+                !translated.ro_sources.iter().any(|(reference, _)| {
+                    *reference == format!("{}[{}]", region, offset)
+                        || (*offset == 0 && reference == region)
+                })
+            })
+            .map(|(region, offset)| format!("{}[{}]", region, offset))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(eyre!(
+                "the program in {} records {}, which translation for {} does not read out",
+                location,
+                missing.join(", "),
+                quantum_processor_id
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The distinct memory references read by `recorded_output`, in the order they are first recorded.
+fn get_recorded_readouts(recorded_output: &[RecordedOutput]) -> Vec<(String, u64)> {
+    let mut readouts: Vec<(String, u64)> = vec![];
+    for value in recorded_output {
+        let references = match value {
+            RecordedOutput::ResultReadoutOffset { region, offset, .. }
+            | RecordedOutput::FlippedResultReadoutOffset { region, offset, .. }
+            | RecordedOutput::BoolReadoutOffset { region, offset, .. }
+            | RecordedOutput::IntegerReadoutOffset { region, offset, .. }
+            | RecordedOutput::DoubleReadoutOffset { region, offset, .. } => {
+                vec![(region.as_str(), *offset)]
+            }
+            RecordedOutput::HostComputedInteger {
+                expression: Some(expression),
+                ..
+            }
+            | RecordedOutput::HostComputedBool {
+                expression: Some(expression),
+                ..
+            } => expression.readouts(),
+            _ => vec![],
+        };
+        for (region, offset) in references {
+            if !readouts
+                .iter()
+                .any(|(seen_region, seen_offset)| seen_region == region && *seen_offset == offset)
+            {
+                readouts.push((region.to_owned(), offset));
+            }
+        }
+    }
+    readouts
+}
+
+/// A [`TranslationService`] calling the QCS translation API, with credentials loaded from the QCS
+/// configuration as for execution. The service translates only native Quil, so programs using
+/// gates which the QPU does not implement natively fail translation.
+#[cfg(feature = "translation")]
+pub struct QcsTranslationService {
+    runtime: tokio::runtime::Runtime,
+    configuration: qcs::configuration::Configuration,
+}
+
+#[cfg(feature = "translation")]
+impl QcsTranslationService {
+    /// Load the QCS configuration and create a [`QcsTranslationService`].
+    ///
+    /// # Errors
+    /// Returns a [`eyre::Report`] if the QCS configuration cannot be loaded.
+    pub fn new() -> Result<Self> {
+        let runtime = tokio::runtime::Runtime::new()?;
+        let configuration = runtime
+            .block_on(qcs::configuration::Configuration::load())
+            .map_err(|error| eyre!("failed to load the QCS configuration: {}", error))?;
+        Ok(Self {
+            runtime,
+            configuration,
+        })
+    }
+}
+
+#[cfg(feature = "translation")]
+impl TranslationService for QcsTranslationService {
+    fn translate(
+        &self,
+        quil: &str,
+        shot_count: u64,
+        quantum_processor_id: &str,
+    ) -> Result<TranslatedProgram> {
+        use qcs_api::{
+            apis::translation_api::translate_native_quil_to_encrypted_binary,
+            models::TranslateNativeQuilToEncryptedBinaryRequest,
+        };
+
+        let request = TranslateNativeQuilToEncryptedBinaryRequest {
+            num_shots: i32::try_from(shot_count)
+                .wrap_err_with(|| format!("cannot translate {} shots", shot_count))?,
+            quil: quil.to_owned(),
+            settings_timestamp: None,
+        };
+        let response = self
+            .runtime
+            .block_on(translate_native_quil_to_encrypted_binary(
+                self.configuration.as_ref(),
+                quantum_processor_id,
+                request,
+            ))
+            .map_err(|error| eyre!("{}", error))?;
+
+        Ok(TranslatedProgram {
+            ro_sources: response
+                .ro_sources
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|pair| match pair.as_slice() {
+                    [reference, source] => Some((reference.clone(), source.clone())),
+                    _ => None,
+                })
+                .collect(),
+        })
+    }
+}
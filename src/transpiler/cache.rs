@@ -0,0 +1,213 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Caches of transpilation results, keyed on the SHA-256 digest of the input bitcode, so that
+//! services which repeatedly transpile the same artifacts skip parsing and pattern matching them.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+use sha2::{Digest, Sha256};
+
+use crate::ProgramOutput;
+
+/// The SHA-256 digest of a QIR bitcode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CacheKey([u8; 32]);
+
+impl CacheKey {
+    /// Compute the key for the given bitcode.
+    #[must_use]
+    pub fn of(bitcode: &[u8]) -> Self {
+        Self(Sha256::digest(bitcode).into())
+    }
+}
+
+impl fmt::Display for CacheKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// A store of transpilation results. Implement this to back the cache of an
+/// [`super::OwnedTranspiler`] with other storage.
+pub trait TranspileCache {
+    /// Return the output previously stored for `key`, if any.
+    fn get(&self, key: &CacheKey) -> Option<ProgramOutput>;
+
+    /// Store the output for `key`. Failures to store are not reported; the output is simply
+    /// transpiled again next time.
+    fn insert(&self, key: CacheKey, output: &ProgramOutput);
+}
+
+/// A [`TranspileCache`] held in memory for the life of the process.
+#[derive(Debug, Default)]
+pub struct InMemoryCache {
+    entries: Mutex<HashMap<CacheKey, ProgramOutput>>,
+}
+
+impl TranspileCache for InMemoryCache {
+    fn get(&self, key: &CacheKey) -> Option<ProgramOutput> {
+        self.entries.lock().ok()?.get(key).cloned()
+    }
+
+    fn insert(&self, key: CacheKey, output: &ProgramOutput) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.insert(key, output.clone());
+        }
+    }
+}
+
+#[cfg(feature = "serde_support")]
+pub use directory::DirectoryCache;
+
+#[cfg(feature = "serde_support")]
+mod directory {
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    use log::debug;
+    use serde::Deserialize;
+
+    use super::{CacheKey, TranspileCache};
+    use crate::{validation::QirDeclarations, ProgramOutput, RecordedOutput, TranspileWarning};
+
+    /// A [`TranspileCache`] which stores each output as a JSON file, named for its key, in a
+    /// directory, so that it may be shared between processes.
+    #[derive(Debug)]
+    pub struct DirectoryCache {
+        directory: PathBuf,
+    }
+
+    /// The serialized form of [`ProgramOutput`].
+    #[derive(Deserialize)]
+    struct CachedProgramOutput {
+        program: String,
+        shot_count: u64,
+        recorded_output: Vec<RecordedOutput>,
+        #[serde(default)]
+        parameters: Vec<String>,
+        native_program: Option<String>,
+        #[serde(default)]
+        qir_declarations: QirDeclarations,
+        #[serde(default)]
+        qubit_mapping: BTreeMap<u64, u64>,
+        #[serde(default)]
+        warnings: Vec<TranspileWarning>,
+    }
+
+    impl DirectoryCache {
+        /// Create a cache in `directory`, which is created when the first output is stored.
+        pub fn new(directory: impl Into<PathBuf>) -> Self {
+            Self {
+                directory: directory.into(),
+            }
+        }
+
+        fn path(&self, key: &CacheKey) -> PathBuf {
+            self.directory.join(format!("{}.json", key))
+        }
+    }
+
+    impl TranspileCache for DirectoryCache {
+        fn get(&self, key: &CacheKey) -> Option<ProgramOutput> {
+            let data = std::fs::read(self.path(key)).ok()?;
+            let cached: CachedProgramOutput = match serde_json::from_slice(&data) {
+                Ok(cached) => cached,
+                Err(error) => {
+                    debug!("ignoring unreadable cache entry {}: {}", key, error);
+                    return None;
+                }
+            };
+            let native_program = cached
+                .native_program
+                .as_deref()
+                .map(quil_rs::Program::from_str)
+                .transpose();
+            match (quil_rs::Program::from_str(&cached.program), native_program) {
+                (Ok(program), Ok(native_program)) => Some(ProgramOutput {
+                    program,
+                    shot_count: cached.shot_count,
+                    recorded_output: cached.recorded_output,
+                    trace: vec![],
+                    parameters: cached.parameters,
+                    native_program,
+                    qir_declarations: cached.qir_declarations,
+                    qubit_mapping: cached.qubit_mapping,
+                    warnings: cached.warnings,
+                }),
+                (Err(error), _) | (_, Err(error)) => {
+                    debug!("ignoring cache entry {} with invalid Quil: {}", key, error);
+                    None
+                }
+            }
+        }
+
+        fn insert(&self, key: CacheKey, output: &ProgramOutput) {
+            let result = std::fs::create_dir_all(&self.directory)
+                .map_err(|error| error.to_string())
+                .and_then(|_| serde_json::to_vec(output).map_err(|error| error.to_string()))
+                .and_then(|data| {
+                    std::fs::write(self.path(&key), data).map_err(|error| error.to_string())
+                });
+            if let Err(error) = result {
+                debug!("failed to store cache entry {}: {}", key, error);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn key_is_hex_sha256() {
+        assert_eq!(
+            CacheKey::of(b"").to_string(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[cfg(feature = "serde_support")]
+    #[test]
+    fn directory_cache_round_trips_output() {
+        let data = std::fs::read("tests/fixtures/programs/record_output.bc").unwrap();
+        let output = crate::transpile_qir_to_quil(&data).unwrap();
+        let key = CacheKey::of(&data);
+
+        let directory =
+            std::env::temp_dir().join(format!("qcs-sdk-qir-cache-test-{}", std::process::id()));
+        let cache = DirectoryCache::new(&directory);
+        assert!(cache.get(&key).is_none());
+        cache.insert(key, &output);
+        let cached = cache.get(&key).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(
+            cached.program.to_string(true),
+            output.program.to_string(true)
+        );
+        assert_eq!(cached.shot_count, output.shot_count);
+        assert_eq!(
+            format!("{:?}", cached.recorded_output),
+            format!("{:?}", output.recorded_output)
+        );
+    }
+}
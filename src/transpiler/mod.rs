@@ -0,0 +1,293 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+
+use eyre::{eyre, Result};
+
+#[cfg(feature = "transform")]
+use crate::PatchOptions;
+use crate::{transform::unitary, ProgramOutput};
+
+pub mod cache;
+
+use cache::{CacheKey, TranspileCache};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A transpiler which owns the LLVM state it uses, for long-lived and multi-threaded callers such
+/// as web services.
+///
+/// LLVM contexts may not be shared between threads, so each request is run on one of a pool of
+/// worker threads, each of which confines its LLVM contexts to itself. [`OwnedTranspiler`] is
+/// `Send` and `Sync`, and may be shared between threads behind an [`Arc`]. Because an LLVM module
+/// cannot leave its worker, patched modules are returned as bitcode.
+pub struct OwnedTranspiler {
+    sender: Option<Mutex<mpsc::Sender<Job>>>,
+    workers: Vec<JoinHandle<()>>,
+    cache: Option<Box<dyn TranspileCache + Send + Sync>>,
+}
+
+impl OwnedTranspiler {
+    /// Create a transpiler with a single worker thread.
+    ///
+    /// # Errors
+    /// 1. Returns a [`eyre::Report`] if the worker thread could not be started.
+    pub fn new() -> Result<Self> {
+        Self::with_workers(1)
+    }
+
+    /// Create a transpiler with `worker_count` worker threads, each of which handles one request at
+    /// a time.
+    ///
+    /// # Errors
+    /// 1. Returns a [`eyre::Report`] if `worker_count` is zero or a worker thread could not be started.
+    pub fn with_workers(worker_count: usize) -> Result<Self> {
+        if worker_count == 0 {
+            return Err(eyre!("a transpiler requires at least one worker"));
+        }
+
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..worker_count)
+            .map(|index| {
+                let receiver = receiver.clone();
+                std::thread::Builder::new()
+                    .name(format!("qcs-sdk-qir-transpiler-{}", index))
+                    .spawn(move || loop {
+                        let job = match receiver.lock() {
+                            Ok(receiver) => receiver.recv(),
+                            Err(_) => return,
+                        };
+                        match job {
+                            // A panicking job drops its result sender, which its caller reports as
+                            // an error; the worker carries on with the next job.
+                            Ok(job) => drop(catch_unwind(AssertUnwindSafe(job))),
+                            Err(_) => return,
+                        }
+                    })
+                    .map_err(|e| eyre!("failed to start transpiler worker: {}", e))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            sender: Some(Mutex::new(sender)),
+            workers,
+            cache: None,
+        })
+    }
+
+    /// Reuse the output of [`Self::transpile_qir_to_quil`] for bitcode it has already transpiled, as
+    /// identified by its SHA-256 digest, storing outputs in `cache`. See [`cache::InMemoryCache`]
+    /// and [`cache::DirectoryCache`].
+    #[must_use]
+    pub fn with_cache(mut self, cache: impl TranspileCache + Send + Sync + 'static) -> Self {
+        self.cache = Some(Box::new(cache));
+        self
+    }
+
+    /// See [`crate::transpile_qir_to_quil`].
+    ///
+    /// # Errors
+    /// 1. Returns a [`eyre::Report`] if the transpilation fails or the worker has stopped.
+    pub fn transpile_qir_to_quil(&self, bitcode: &[u8]) -> Result<ProgramOutput> {
+        let key = self.cache.as_ref().map(|_| CacheKey::of(bitcode));
+        if let (Some(cache), Some(key)) = (&self.cache, &key) {
+            if let Some(output) = cache.get(key) {
+                return Ok(output);
+            }
+        }
+
+        let bitcode = bitcode.to_vec();
+        let output = self.run(move || crate::transpile_qir_to_quil(&bitcode))?;
+
+        if let (Some(cache), Some(key)) = (&self.cache, key) {
+            cache.insert(key, &output);
+        }
+        Ok(output)
+    }
+
+    /// See [`crate::transpile_unitary_qir_to_quil`].
+    ///
+    /// # Errors
+    /// 1. Returns a [`eyre::Report`] if the transpilation fails or the worker has stopped.
+    pub fn transpile_unitary_qir_to_quil(
+        &self,
+        bitcode: &[u8],
+    ) -> Result<unitary::quil::ProgramOutput> {
+        let bitcode = bitcode.to_vec();
+        self.run(move || crate::transpile_unitary_qir_to_quil(&bitcode))
+    }
+
+    /// See [`crate::patch_qir_with_qcs`]; the patched module is returned as bitcode.
+    ///
+    /// # Errors
+    /// 1. Returns a [`eyre::Report`] if the compilation fails or the worker has stopped.
+    #[cfg(feature = "transform")]
+    pub fn patch_qir_with_qcs(&self, options: PatchOptions, bitcode: &[u8]) -> Result<Vec<u8>> {
+        let bitcode = bitcode.to_vec();
+        self.run(move || {
+            let context = inkwell::context::Context::create();
+            let module = crate::patch_qir_with_qcs(options, &bitcode, &context)?;
+            Ok(module.write_bitcode_to_memory().as_slice().to_vec())
+        })
+    }
+
+    /// See [`crate::patch_hybrid_qir_with_qcs`]; the patched module is returned as bitcode.
+    ///
+    /// # Errors
+    /// 1. Returns a [`eyre::Report`] if the compilation fails or the worker has stopped.
+    #[cfg(feature = "transform")]
+    pub fn patch_hybrid_qir_with_qcs(
+        &self,
+        options: PatchOptions,
+        bitcode: &[u8],
+    ) -> Result<Vec<u8>> {
+        let bitcode = bitcode.to_vec();
+        self.run(move || {
+            let context = inkwell::context::Context::create();
+            let module = crate::patch_hybrid_qir_with_qcs(options, &bitcode, &context)?;
+            Ok(module.write_bitcode_to_memory().as_slice().to_vec())
+        })
+    }
+
+    /// See [`crate::patch_unitary_qir_with_qcs`]; the patched module is returned as bitcode.
+    ///
+    /// # Errors
+    /// 1. Returns a [`eyre::Report`] if the compilation fails or the worker has stopped.
+    #[cfg(feature = "transform")]
+    pub fn patch_unitary_qir_with_qcs(
+        &self,
+        options: PatchOptions,
+        bitcode: &[u8],
+    ) -> Result<Vec<u8>> {
+        let bitcode = bitcode.to_vec();
+        self.run(move || {
+            let context = inkwell::context::Context::create();
+            let module = crate::patch_unitary_qir_with_qcs(options, &bitcode, &context)?;
+            Ok(module.write_bitcode_to_memory().as_slice().to_vec())
+        })
+    }
+
+    /// Run `job` on a worker and wait for its result.
+    fn run<T, F>(&self, job: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T> + Send + 'static,
+    {
+        let (result_sender, result_receiver) = mpsc::channel();
+        let job: Job = Box::new(move || {
+            // The caller may have stopped waiting; there is nobody to report that to.
+            let _ = result_sender.send(job());
+        });
+
+        self.sender
+            .as_ref()
+            .ok_or_else(|| eyre!("transpiler has been shut down"))?
+            .lock()
+            .map_err(|_| eyre!("transpiler request queue lock was poisoned"))?
+            .send(job)
+            .map_err(|_| eyre!("transpiler workers have stopped"))?;
+
+        result_receiver
+            .recv()
+            .map_err(|_| eyre!("transpiler worker failed before completing the request"))?
+    }
+}
+
+impl Drop for OwnedTranspiler {
+    fn drop(&mut self) {
+        // Closing the queue stops each worker once it is idle.
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::OwnedTranspiler;
+
+    #[test]
+    fn is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<OwnedTranspiler>();
+    }
+
+    #[test]
+    fn transpiles_from_many_threads() {
+        let data = std::fs::read("tests/fixtures/programs/bell_state.bc").unwrap();
+        let expected = crate::transpile_qir_to_quil(&data)
+            .unwrap()
+            .program
+            .to_string(true);
+
+        let transpiler = Arc::new(OwnedTranspiler::with_workers(2).unwrap());
+        let threads = (0..4)
+            .map(|_| {
+                let transpiler = transpiler.clone();
+                let data = data.clone();
+                std::thread::spawn(move || {
+                    transpiler
+                        .transpile_qir_to_quil(&data)
+                        .unwrap()
+                        .program
+                        .to_string(true)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn reuses_cached_output() {
+        use super::cache::{CacheKey, InMemoryCache, TranspileCache};
+
+        let data = std::fs::read("tests/fixtures/programs/measure.bc").unwrap();
+        let mut cached = crate::transpile_qir_to_quil(&data).unwrap();
+        cached.shot_count = 7;
+        let cache = InMemoryCache::default();
+        cache.insert(CacheKey::of(&data), &cached);
+
+        let transpiler = OwnedTranspiler::new().unwrap().with_cache(cache);
+        assert_eq!(
+            transpiler.transpile_qir_to_quil(&data).unwrap().shot_count,
+            7
+        );
+
+        let other = std::fs::read("tests/fixtures/programs/bell_state.bc").unwrap();
+        assert_ne!(
+            transpiler.transpile_qir_to_quil(&other).unwrap().shot_count,
+            7
+        );
+    }
+
+    #[test]
+    fn reports_errors_from_workers() {
+        let transpiler = OwnedTranspiler::new().unwrap();
+        assert!(transpiler.transpile_qir_to_quil(b"not bitcode").is_err());
+        assert!(transpiler
+            .transpile_qir_to_quil(&std::fs::read("tests/fixtures/programs/measure.bc").unwrap())
+            .is_ok());
+    }
+}
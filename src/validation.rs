@@ -0,0 +1,555 @@
+// Copyright 2022 Rigetti Computing
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checks of a module's conformance to the QIR specification and its profiles.
+//!
+//! Conformance is checked rule by rule, each rule applying at some [`ConformanceLevel`]s, so that
+//! every violation may be reported at once. Checks may be run standalone with [`check_conformance`],
+//! or before transformation and transpilation by setting [`ConformanceOptions`] on
+//! [`crate::TranspileOptions`] or `PatchOptions`.
+use std::{fmt, str::FromStr};
+
+use eyre::{eyre, Report, Result};
+use inkwell::{
+    attributes::AttributeLoc,
+    context::Context,
+    module::Module,
+    values::{BasicValueEnum, FunctionValue, InstructionValue},
+};
+use log::warn;
+
+#[cfg(feature = "serde_support")]
+use serde::{Deserialize, Serialize};
+
+use crate::interop::{
+    entrypoint::get_entry_function,
+    load::load_module_from_bitcode,
+    metadata::get_module_flag,
+    validate::{describe_location, get_called_function, get_reachable_blocks},
+};
+
+/// The attributes which may mark a function as the entry point, in the older and newer spellings of
+/// the specification.
+pub(crate) const ENTRY_POINT_ATTRIBUTES: &[&str] = &["EntryPoint", "entry_point"];
+const REQUIRED_QUBITS_ATTRIBUTES: &[&str] = &["requiredQubits", "required_num_qubits"];
+const REQUIRED_RESULTS_ATTRIBUTES: &[&str] = &["requiredResults", "required_num_results"];
+const PROFILE_ATTRIBUTES: &[&str] = &["qir_profiles"];
+
+/// The intrinsics which are irreversible whether or not their declarations are marked so.
+const IRREVERSIBLE_INTRINSICS: &[&str] = &[
+    "__quantum__qis__m__body",
+    "__quantum__qis__measure__body",
+    "__quantum__qis__mz__body",
+    "__quantum__qis__reset__body",
+];
+
+/// How closely a module must follow the QIR specification.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConformanceLevel {
+    /// The base profile: a straight-line program which measures its qubits only after applying
+    /// every gate, and records its output once it is done.
+    BaseProfile,
+    /// The adaptive profile, which, unlike the base profile, allows gates to follow measurement.
+    AdaptiveProfile,
+    /// The full specification, which only requires a well-formed entry point and qubit lifetimes.
+    Full,
+}
+
+impl Default for ConformanceLevel {
+    fn default() -> Self {
+        Self::Full
+    }
+}
+
+impl fmt::Display for ConformanceLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BaseProfile => write!(f, "the base profile"),
+            Self::AdaptiveProfile => write!(f, "the adaptive profile"),
+            Self::Full => write!(f, "the full QIR specification"),
+        }
+    }
+}
+
+impl FromStr for ConformanceLevel {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "base" => Ok(Self::BaseProfile),
+            "adaptive" => Ok(Self::AdaptiveProfile),
+            "full" => Ok(Self::Full),
+            other => Err(eyre!(
+                "unrecognized conformance level {}; expected \"base\", \"adaptive\", or \"full\"",
+                other
+            )),
+        }
+    }
+}
+
+/// What to do with a module which does not conform when checked before transformation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConformanceStrictness {
+    /// Skip the check.
+    Ignore,
+    /// Log each violation as a warning and continue.
+    Warn,
+    /// Fail with an error listing every violation.
+    Deny,
+}
+
+impl Default for ConformanceStrictness {
+    fn default() -> Self {
+        Self::Ignore
+    }
+}
+
+impl FromStr for ConformanceStrictness {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(Self::Ignore),
+            "warn" => Ok(Self::Warn),
+            "deny" => Ok(Self::Deny),
+            other => Err(eyre!(
+                "unrecognized conformance strictness {}; expected \"ignore\", \"warn\", or \"deny\"",
+                other
+            )),
+        }
+    }
+}
+
+/// The conformance check to run before transformation or transpilation. By default, none is run.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ConformanceOptions {
+    /// The level against which to check the module.
+    pub level: ConformanceLevel,
+    /// What to do should the module not conform.
+    pub strictness: ConformanceStrictness,
+}
+
+/// A rule of the QIR specification which a module may violate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConformanceRule {
+    /// The module must have an entry point. Under either profile, it must be marked with the
+    /// `EntryPoint` attribute, declare the number of qubits and results it requires, and take no
+    /// parameters.
+    EntryPointAttributes,
+    /// Under the base profile, no gate may follow an irreversible operation such as a measurement.
+    IrreversibleOperationOrder,
+    /// Under either profile, output must be recorded after every quantum operation.
+    OutputRecordingAtEnd,
+    /// A qubit must not be used once it has been released.
+    QubitReuseAfterRelease,
+}
+
+impl fmt::Display for ConformanceRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EntryPointAttributes => write!(f, "entry-point-attributes"),
+            Self::IrreversibleOperationOrder => write!(f, "irreversible-operation-order"),
+            Self::OutputRecordingAtEnd => write!(f, "output-recording-at-end"),
+            Self::QubitReuseAfterRelease => write!(f, "qubit-reuse-after-release"),
+        }
+    }
+}
+
+/// A single violation of a [`ConformanceRule`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConformanceViolation {
+    /// The rule violated.
+    pub rule: ConformanceRule,
+    /// What is wrong, including the instruction at fault if there is one.
+    pub message: String,
+}
+
+impl fmt::Display for ConformanceViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}", self.rule, self.message)
+    }
+}
+
+/// The outcome of checking a module against a [`ConformanceLevel`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConformanceReport {
+    /// The level checked against.
+    pub level: ConformanceLevel,
+    /// Every violation found, in the order in which the rules are listed in [`ConformanceRule`].
+    pub violations: Vec<ConformanceViolation>,
+}
+
+impl ConformanceReport {
+    /// Whether the module conforms, having no violations.
+    #[must_use]
+    pub fn is_conformant(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_conformant() {
+            return write!(f, "module conforms to {}", self.level);
+        }
+        write!(
+            f,
+            "module does not conform to {}: {} violation(s)",
+            self.level,
+            self.violations.len()
+        )?;
+        for violation in &self.violations {
+            write!(f, "\n  {}", violation)?;
+        }
+        Ok(())
+    }
+}
+
+/// The version of the QIR specification, and the profile of it, which a module declares it follows:
+/// its `qir_major_version` and `qir_minor_version` module flags and the `qir_profiles` attribute of
+/// its entry point. Each is `None` if the module does not declare it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct QirDeclarations {
+    /// The major version of the QIR specification.
+    pub major_version: Option<u64>,
+    /// The minor version of the QIR specification.
+    pub minor_version: Option<u64>,
+    /// The profile, as named by the specification, such as `base_profile` or `adaptive_profile`.
+    pub profile: Option<String>,
+}
+
+impl QirDeclarations {
+    /// Whether the module declares none of these.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Read the QIR version and profile which the given bitcode declares.
+///
+/// # Errors
+/// 1. Returns a [`eyre::Report`] if the bitcode cannot be loaded.
+pub fn read_qir_declarations(bitcode: &[u8]) -> Result<QirDeclarations> {
+    let context = Context::create();
+    let module = load_module_from_bitcode(&context, bitcode)?;
+    Ok(get_qir_declarations(&module))
+}
+
+/// Read the QIR version and profile which the module declares.
+pub(crate) fn get_qir_declarations(module: &Module) -> QirDeclarations {
+    let get_version = |name| match get_module_flag(module, name)? {
+        inkwell::values::BasicMetadataValueEnum::IntValue(value) => {
+            value.get_zero_extended_constant()
+        }
+        _ => None,
+    };
+    QirDeclarations {
+        major_version: get_version("qir_major_version"),
+        minor_version: get_version("qir_minor_version"),
+        profile: get_entry_function(module)
+            .and_then(|entrypoint| get_string_attribute(entrypoint, PROFILE_ATTRIBUTES)),
+    }
+}
+
+/// Check the given QIR bitcode against `level`, reporting every violation found.
+///
+/// # Errors
+/// 1. Returns a [`eyre::Report`] if the bitcode cannot be loaded. Violations are reported in the
+///    returned [`ConformanceReport`] rather than as an error.
+pub fn check_conformance(bitcode: &[u8], level: ConformanceLevel) -> Result<ConformanceReport> {
+    let context = Context::create();
+    let module = load_module_from_bitcode(&context, bitcode)?;
+    Ok(check_module_conformance(&module, level))
+}
+
+/// Run the check configured by `options` over the module, warning of or failing on violations.
+pub(crate) fn enforce_conformance(module: &Module, options: &ConformanceOptions) -> Result<()> {
+    if options.strictness == ConformanceStrictness::Ignore {
+        return Ok(());
+    }
+
+    let report = check_module_conformance(module, options.level);
+    match options.strictness {
+        _ if report.is_conformant() => Ok(()),
+        ConformanceStrictness::Deny => Err(eyre!("{}", report)),
+        _ => {
+            for violation in &report.violations {
+                warn!("module does not conform to {}: {}", report.level, violation);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Check the module's entry point against `level`. Instructions are visited in the order of the
+/// entry point's reachable blocks, so that an operation in a loop is not checked against those in
+/// its own later iterations.
+pub(crate) fn check_module_conformance(
+    module: &Module,
+    level: ConformanceLevel,
+) -> ConformanceReport {
+    let mut violations = vec![];
+    let profile = level != ConformanceLevel::Full;
+
+    let entrypoint = match get_entry_function(module).or_else(|| {
+        module
+            .get_functions()
+            .find(|function| get_string_attribute(*function, ENTRY_POINT_ATTRIBUTES).is_some())
+    }) {
+        Some(function) => function,
+        None => {
+            violations.push(ConformanceViolation {
+                rule: ConformanceRule::EntryPointAttributes,
+                message: String::from("module has no entry point"),
+            });
+            return ConformanceReport { level, violations };
+        }
+    };
+
+    if profile {
+        violations.extend(
+            check_entry_point_attributes(entrypoint)
+                .into_iter()
+                .map(|message| ConformanceViolation {
+                    rule: ConformanceRule::EntryPointAttributes,
+                    message,
+                }),
+        );
+    }
+
+    let mut seen_irreversible = false;
+    let mut seen_record = false;
+    let mut released: Vec<BasicValueEnum> = vec![];
+
+    for block in get_reachable_blocks(entrypoint) {
+        let mut next_instruction = block.get_first_instruction();
+        while let Some(instruction) = next_instruction {
+            next_instruction = instruction.get_next_instruction();
+            let function = match get_called_function(module, instruction) {
+                Some(function) => function,
+                None => continue,
+            };
+            let name = function.get_name().to_string_lossy().into_owned();
+            let location = || {
+                describe_location(entrypoint, block, instruction)
+                    .trim()
+                    .to_string()
+            };
+
+            if let Some(release) = get_qubit_operands(instruction)
+                .into_iter()
+                .find(|operand| released.contains(operand))
+            {
+                violations.push(ConformanceViolation {
+                    rule: ConformanceRule::QubitReuseAfterRelease,
+                    message: format!(
+                        "qubit {} is used after it was released: {}",
+                        describe_value(release),
+                        location()
+                    ),
+                });
+            }
+            if name.starts_with("__quantum__rt__qubit_release") {
+                released.extend(get_qubit_operands(instruction));
+                continue;
+            }
+
+            if name.starts_with("__quantum__rt__") && name.ends_with("_record_output") {
+                seen_record = true;
+                continue;
+            }
+            if !name.starts_with("__quantum__qis__") || name == "__quantum__qis__read_result__body"
+            {
+                continue;
+            }
+
+            if profile && seen_record {
+                violations.push(ConformanceViolation {
+                    rule: ConformanceRule::OutputRecordingAtEnd,
+                    message: format!(
+                        "quantum operation follows the recording of output: {}",
+                        location()
+                    ),
+                });
+            }
+
+            if is_irreversible(function) {
+                seen_irreversible = true;
+            } else if level == ConformanceLevel::BaseProfile && seen_irreversible {
+                violations.push(ConformanceViolation {
+                    rule: ConformanceRule::IrreversibleOperationOrder,
+                    message: format!("gate follows an irreversible operation: {}", location()),
+                });
+            }
+        }
+    }
+
+    violations.sort_by_key(|violation| violation.rule);
+    ConformanceReport { level, violations }
+}
+
+/// Describe each way in which the entry point's attributes and signature fall short of a profile.
+fn check_entry_point_attributes(entrypoint: FunctionValue) -> Vec<String> {
+    let name = entrypoint.get_name().to_string_lossy().into_owned();
+    let mut problems = vec![];
+
+    if get_string_attribute(entrypoint, ENTRY_POINT_ATTRIBUTES).is_none() {
+        problems.push(format!(
+            "entry point @{} is not marked with the EntryPoint attribute",
+            name
+        ));
+    }
+    for (attributes, description) in [
+        (REQUIRED_QUBITS_ATTRIBUTES, "qubits"),
+        (REQUIRED_RESULTS_ATTRIBUTES, "results"),
+    ] {
+        match get_string_attribute(entrypoint, attributes) {
+            Some(value) if value.parse::<u64>().is_ok() => {}
+            Some(value) => problems.push(format!(
+                "entry point @{} declares a non-integer number of required {}: {:?}",
+                name, description, value
+            )),
+            None => problems.push(format!(
+                "entry point @{} does not declare the number of {} it requires with a {} attribute",
+                name, description, attributes[0]
+            )),
+        }
+    }
+    if entrypoint.count_params() > 0 {
+        problems.push(format!("entry point @{} takes parameters", name));
+    }
+
+    problems
+}
+
+/// Return the value of the first of the given string attributes set on the function.
+fn get_string_attribute(function: FunctionValue, names: &[&str]) -> Option<String> {
+    names.iter().find_map(|name| {
+        function
+            .get_string_attribute(AttributeLoc::Function, name)
+            .map(|attribute| attribute.get_string_value().to_string_lossy().into_owned())
+    })
+}
+
+/// Whether calling the function is irreversible, as measurement and reset are.
+fn is_irreversible(function: FunctionValue) -> bool {
+    IRREVERSIBLE_INTRINSICS.contains(&function.get_name().to_string_lossy().as_ref())
+        || function
+            .get_string_attribute(AttributeLoc::Function, "irreversible")
+            .is_some()
+}
+
+/// Return the non-constant pointer operands of a call, which are the dynamically allocated qubits
+/// it acts upon. Static qubits are constants and may not be released.
+fn get_qubit_operands(instruction: InstructionValue) -> Vec<BasicValueEnum> {
+    (0..instruction.get_num_operands().saturating_sub(1))
+        .filter_map(|index| instruction.get_operand(index)?.left())
+        .filter(|operand| {
+            matches!(operand, BasicValueEnum::PointerValue(pointer) if !pointer.is_const())
+        })
+        .collect()
+}
+
+fn describe_value(value: BasicValueEnum) -> String {
+    let name = match value {
+        BasicValueEnum::PointerValue(pointer) => pointer.get_name().to_string_lossy().into_owned(),
+        _ => String::new(),
+    };
+    if name.is_empty() {
+        String::from("<unnamed>")
+    } else {
+        format!("%{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(path: &str, level: ConformanceLevel) -> ConformanceReport {
+        check_conformance(&std::fs::read(path).unwrap(), level).unwrap()
+    }
+
+    #[test]
+    fn base_profile_programs_conform() {
+        for level in [
+            ConformanceLevel::BaseProfile,
+            ConformanceLevel::AdaptiveProfile,
+            ConformanceLevel::Full,
+        ] {
+            let report = check(
+                "tests/fixtures/programs/unitary/qiskit_qir_measure.bc",
+                level,
+            );
+            assert!(report.is_conformant(), "{}", report);
+        }
+    }
+
+    #[test]
+    fn reports_each_violated_rule() {
+        let path = "tests/fixtures/programs/nonconformant.bc";
+
+        let report = check(path, ConformanceLevel::BaseProfile);
+        assert_eq!(
+            report
+                .violations
+                .iter()
+                .map(|violation| violation.rule)
+                .collect::<Vec<_>>(),
+            vec![
+                ConformanceRule::EntryPointAttributes,
+                ConformanceRule::IrreversibleOperationOrder,
+                ConformanceRule::OutputRecordingAtEnd,
+                ConformanceRule::QubitReuseAfterRelease,
+            ]
+        );
+        insta::assert_snapshot!(report.to_string());
+
+        let report = check(path, ConformanceLevel::AdaptiveProfile);
+        assert!(!report
+            .violations
+            .iter()
+            .any(|violation| violation.rule == ConformanceRule::IrreversibleOperationOrder));
+
+        let report = check(path, ConformanceLevel::Full);
+        assert_eq!(
+            report
+                .violations
+                .iter()
+                .map(|violation| violation.rule)
+                .collect::<Vec<_>>(),
+            vec![ConformanceRule::QubitReuseAfterRelease]
+        );
+    }
+
+    #[test]
+    fn enforces_configured_strictness() {
+        let context = Context::create();
+        let data = std::fs::read("tests/fixtures/programs/nonconformant.bc").unwrap();
+        let module = load_module_from_bitcode(&context, &data).unwrap();
+
+        let options = |strictness| ConformanceOptions {
+            level: ConformanceLevel::BaseProfile,
+            strictness,
+        };
+        enforce_conformance(&module, &options(ConformanceStrictness::Ignore)).unwrap();
+        enforce_conformance(&module, &options(ConformanceStrictness::Warn)).unwrap();
+        let error = enforce_conformance(&module, &options(ConformanceStrictness::Deny))
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("module does not conform to the base profile: 4 violation(s)"));
+    }
+}
@@ -0,0 +1,27 @@
+%Qubit = type opaque
+%Result = type opaque
+
+; the rotation angle must precede the qubit.
+declare void @__quantum__qis__rx__body(%Qubit*, double) local_unnamed_addr
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+
+define internal fastcc void @QuantumApplication__Run__body() unnamed_addr {
+
+entry:
+    br label %body
+
+body:
+    ; shot count variable
+    %0 = phi i64 [ %1, %body ], [ 1, %entry ]
+
+    tail call void @__quantum__qis__rx__body(%Qubit* null, double 1.0)
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+
+    ; shot count branch
+    %1 = add nuw nsw i64 %0, 1
+    %2 = icmp ult i64 %0, 42
+    br i1 %2, label %body, label %exit
+
+exit:
+    ret void
+}
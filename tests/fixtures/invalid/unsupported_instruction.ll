@@ -0,0 +1,30 @@
+%Qubit = type opaque
+%Result = type opaque
+
+declare void @__quantum__qis__h__body(%Qubit*) local_unnamed_addr
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+
+@shots = internal global i64 0
+
+; the shots are counted atomically, which cannot be transpiled.
+define internal fastcc void @QuantumApplication__Run__body() unnamed_addr {
+
+entry:
+    br label %body
+
+body:
+    ; shot count variable
+    %0 = phi i64 [ %2, %body ], [ 1, %entry ]
+
+    tail call void @__quantum__qis__h__body(%Qubit* null)
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+    %1 = atomicrmw add i64* @shots, i64 1 seq_cst
+
+    ; shot count branch
+    %2 = add nuw nsw i64 %0, 1
+    %3 = icmp ult i64 %0, 42
+    br i1 %3, label %body, label %exit
+
+exit:
+    ret void
+}
@@ -0,0 +1,31 @@
+%Qubit = type opaque
+%Result = type opaque
+
+; qubits and results are in address space 2, except where an intrinsic is declared with the
+; default address space and its arguments are cast into it
+declare void @__quantum__qis__h__body(%Qubit addrspace(2)*) local_unnamed_addr
+declare void @__quantum__qis__cnot__body(%Qubit*, %Qubit*) local_unnamed_addr
+declare void @__quantum__qis__mz__body(%Qubit addrspace(2)*, %Result addrspace(2)*) local_unnamed_addr
+
+define internal fastcc void @QuantumApplication__Run__body() unnamed_addr {
+
+entry:
+    br label %body
+
+body:
+    ; shot count variable
+    %0 = phi i64 [ %1, %body ], [ 1, %entry ]
+
+    tail call void @__quantum__qis__h__body(%Qubit addrspace(2)* null)
+    tail call void @__quantum__qis__cnot__body(%Qubit* addrspacecast (%Qubit addrspace(2)* null to %Qubit*), %Qubit* addrspacecast (%Qubit addrspace(2)* inttoptr (i64 1 to %Qubit addrspace(2)*) to %Qubit*))
+    tail call void @__quantum__qis__mz__body(%Qubit addrspace(2)* null, %Result addrspace(2)* null)
+    tail call void @__quantum__qis__mz__body(%Qubit addrspace(2)* inttoptr (i64 1 to %Qubit addrspace(2)*), %Result addrspace(2)* inttoptr (i64 1 to %Result addrspace(2)*))
+
+    ; shot count branch
+    %1 = add nuw nsw i64 %0, 1
+    %2 = icmp ult i64 %0, 42
+    br i1 %2, label %body, label %exit
+
+exit:
+    ret void
+}
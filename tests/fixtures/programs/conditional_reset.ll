@@ -0,0 +1,34 @@
+%Qubit = type opaque
+%Result = type opaque
+
+declare void @__quantum__qis__x__body(%Qubit*) local_unnamed_addr
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+declare i1 @__quantum__qis__read_result__body(%Result*) local_unnamed_addr
+
+; flips a qubit back to the ground state if it was measured in the excited state
+define internal fastcc void @QuantumApplication__Run__body() unnamed_addr {
+
+entry:
+    br label %body
+
+body:
+    ; shot count variable
+    %0 = phi i64 [ %2, %continue ], [ 1, %entry ]
+
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+    %1 = tail call i1 @__quantum__qis__read_result__body(%Result* null)
+    br i1 %1, label %then, label %continue
+
+then:
+    tail call void @__quantum__qis__x__body(%Qubit* null)
+    br label %continue
+
+continue:
+    ; shot count branch
+    %2 = add nuw nsw i64 %0, 1
+    %3 = icmp ult i64 %0, 42
+    br i1 %3, label %body, label %exit
+
+exit:
+    ret void
+}
@@ -0,0 +1,26 @@
+%Qubit = type opaque
+%Result = type opaque
+
+declare void @__quantum__qis__rz__body(double, %Qubit*) local_unnamed_addr
+
+; function that executes RZ gates whose parameters are chosen by `select` on constant conditions
+define internal fastcc void @QuantumApplication__Run__body() unnamed_addr {
+
+entry:
+    %0 = fadd double 1.000000e+00, 2.000000e+00
+    br label %body
+
+body:
+    ; shot count variable
+    %1 = phi i64 [ %2, %body ], [ 1, %entry ]
+    %taken = select i1 true, double 5.000000e-01, double %0
+    %not_taken = select i1 false, double %0, double 2.500000e-01
+    tail call void @__quantum__qis__rz__body(double %taken, %Qubit* null)
+    tail call void @__quantum__qis__rz__body(double %not_taken, %Qubit* null)
+    %2 = add nuw nsw i64 %1, 1
+    %3 = icmp ult i64 %1, 1000
+    br i1 %3, label %body, label %exit
+
+exit:
+    ret void
+}
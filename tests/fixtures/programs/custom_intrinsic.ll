@@ -0,0 +1,29 @@
+%Qubit = type opaque
+%Result = type opaque
+
+declare void @__quantum__qis__h__body(%Qubit*) local_unnamed_addr
+declare void @__vendor__pulse__body(%Qubit*, double, i64) local_unnamed_addr
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+
+; a program calling a vendor-specific intrinsic, which no built-in pattern recognizes
+define internal fastcc void @QuantumApplication__Run__body() unnamed_addr {
+
+entry:
+    br label %body
+
+body:
+    ; shot count variable
+    %0 = phi i64 [ %1, %body ], [ 1, %entry ]
+
+    tail call void @__quantum__qis__h__body(%Qubit* null)
+    tail call void @__vendor__pulse__body(%Qubit* nonnull inttoptr (i64 1 to %Qubit*), double 5.000000e-01, i64 3)
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+
+    ; shot count branch
+    %1 = add nuw nsw i64 %0, 1
+    %2 = icmp ult i64 %0, 42
+    br i1 %2, label %body, label %exit
+
+exit:
+    ret void
+}
@@ -0,0 +1,32 @@
+%Qubit = type opaque
+%Result = type opaque
+
+declare void @__quantum__qis__h__body(%Qubit*) local_unnamed_addr
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+declare i1 @__quantum__qis__read_result__body(%Result*) local_unnamed_addr
+
+; prepares and measures a single qubit, using a custom calibration for H attached as metadata
+define internal fastcc void @QuantumApplication__Run__body() unnamed_addr {
+
+entry:
+    br label %body
+
+body:
+    ; shot count variable
+    %0 = phi i64 [ %2, %body ], [ 1, %entry ]
+
+    tail call void @__quantum__qis__h__body(%Qubit* null)
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+    %1 = tail call i1 @__quantum__qis__read_result__body(%Result* null)
+
+    ; shot count branch
+    %2 = add nuw nsw i64 %0, 1
+    %3 = icmp ult i64 %0, 42
+    br i1 %3, label %body, label %exit
+
+exit:
+    ret void
+}
+
+!qcs.defcal = !{!0}
+!0 = !{!"DEFCAL H 0:\0A    RZ(pi/2) 0\0A    RX(pi/2) 0\0A    RZ(pi/2) 0"}
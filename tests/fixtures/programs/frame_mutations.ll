@@ -0,0 +1,31 @@
+%Qubit = type opaque
+%Result = type opaque
+
+declare void @__quantum__qis__set_frame_scale__body(double, %Qubit*) local_unnamed_addr
+declare void @__quantum__qis__shift_frame_phase__body(double, %Qubit*) local_unnamed_addr
+declare void @__quantum__qis__set_frame_frequency__body(double, %Qubit*) local_unnamed_addr
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+
+; mutates the frames of two qubits before measuring one of them
+define internal fastcc void @QuantumApplication__Run__body() unnamed_addr {
+
+entry:
+    br label %body
+
+body:
+    ; shot count variable
+    %0 = phi i64 [ %1, %body ], [ 1, %entry ]
+
+    tail call void @__quantum__qis__set_frame_scale__body(double 0.5, %Qubit* null)
+    tail call void @__quantum__qis__shift_frame_phase__body(double 1.5, %Qubit* null)
+    tail call void @__quantum__qis__set_frame_frequency__body(double 5.0e9, %Qubit* nonnull inttoptr (i64 1 to %Qubit*))
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+
+    ; shot count branch
+    %1 = add nuw nsw i64 %0, 1
+    %2 = icmp ult i64 %0, 42
+    br i1 %2, label %body, label %exit
+
+exit:
+    ret void
+}
@@ -0,0 +1,36 @@
+%Qubit = type opaque
+%Result = type opaque
+
+declare void @__quantum__qis__h__body(%Qubit*) local_unnamed_addr
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+
+; apply H to, and measure, each of qubits 0 to 2 in a loop with a constant trip count within each shot.
+define internal fastcc void @QuantumApplication__Run__body() unnamed_addr {
+
+entry:
+    br label %body
+
+body:
+    ; shot count variable
+    %0 = phi i64 [ %1, %shot_end ], [ 1, %entry ]
+    br label %loop
+
+loop:
+    %i = phi i64 [ 0, %body ], [ %next, %loop ]
+    %qubit = inttoptr i64 %i to %Qubit*
+    %result = inttoptr i64 %i to %Result*
+    tail call void @__quantum__qis__h__body(%Qubit* %qubit)
+    tail call void @__quantum__qis__mz__body(%Qubit* %qubit, %Result* %result)
+    %next = add nuw nsw i64 %i, 1
+    %done = icmp eq i64 %next, 3
+    br i1 %done, label %shot_end, label %loop
+
+shot_end:
+    ; shot count branch
+    %1 = add nuw nsw i64 %0, 1
+    %2 = icmp ult i64 %0, 42
+    br i1 %2, label %body, label %exit
+
+exit:
+    ret void
+}
@@ -0,0 +1,55 @@
+%Qubit = type opaque
+%Result = type opaque
+%Array = type opaque
+
+declare %Array* @__quantum__rt__array_create_1d(i32, i64) local_unnamed_addr
+declare i8* @__quantum__rt__array_get_element_ptr_1d(%Array*, i64) local_unnamed_addr
+declare void @__quantum__rt__array_update_reference_count(%Array*, i32) local_unnamed_addr
+declare void @__quantum__qis__h__body(%Qubit*) local_unnamed_addr
+declare void @__quantum__qis__cnot__body(%Qubit*, %Qubit*) local_unnamed_addr
+declare void @__quantum__qis__measure__body(%Array*, %Array*, %Result*) local_unnamed_addr
+declare i1 @__quantum__qis__read_result__body(%Result*) local_unnamed_addr
+
+; prepare a Bell state and measure its XX parity, passing the Pauli string and qubits as arrays built
+; from constants, as Q# does for `Measure([PauliX, PauliX], [q0, q1])`.
+define internal fastcc void @QuantumApplication__Run__body() unnamed_addr {
+
+entry:
+    br label %body
+
+body:
+    ; shot count variable
+    %0 = phi i64 [ %2, %body ], [ 1, %entry ]
+
+    tail call void @__quantum__qis__h__body(%Qubit* null)
+    tail call void @__quantum__qis__cnot__body(%Qubit* null, %Qubit* nonnull inttoptr (i64 1 to %Qubit*))
+
+    %paulis = call %Array* @__quantum__rt__array_create_1d(i32 1, i64 2)
+    %pauli.0.raw = call i8* @__quantum__rt__array_get_element_ptr_1d(%Array* %paulis, i64 0)
+    %pauli.0 = bitcast i8* %pauli.0.raw to i2*
+    store i2 1, i2* %pauli.0, align 1
+    %pauli.1.raw = call i8* @__quantum__rt__array_get_element_ptr_1d(%Array* %paulis, i64 1)
+    %pauli.1 = bitcast i8* %pauli.1.raw to i2*
+    store i2 1, i2* %pauli.1, align 1
+
+    %qubits = call %Array* @__quantum__rt__array_create_1d(i32 8, i64 2)
+    %qubit.0.raw = call i8* @__quantum__rt__array_get_element_ptr_1d(%Array* %qubits, i64 0)
+    %qubit.0 = bitcast i8* %qubit.0.raw to %Qubit**
+    store %Qubit* null, %Qubit** %qubit.0, align 8
+    %qubit.1.raw = call i8* @__quantum__rt__array_get_element_ptr_1d(%Array* %qubits, i64 1)
+    %qubit.1 = bitcast i8* %qubit.1.raw to %Qubit**
+    store %Qubit* inttoptr (i64 1 to %Qubit*), %Qubit** %qubit.1, align 8
+
+    tail call void @__quantum__qis__measure__body(%Array* %paulis, %Array* %qubits, %Result* null)
+    call void @__quantum__rt__array_update_reference_count(%Array* %paulis, i32 -1)
+    call void @__quantum__rt__array_update_reference_count(%Array* %qubits, i32 -1)
+    %1 = tail call i1 @__quantum__qis__read_result__body(%Result* null)
+
+    ; shot count branch
+    %2 = add nuw nsw i64 %0, 1
+    %3 = icmp ult i64 %0, 42
+    br i1 %3, label %body, label %exit
+
+exit:
+    ret void
+}
@@ -0,0 +1,28 @@
+%Qubit = type opaque
+%Result = type opaque
+
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+declare void @prepare_bell_state()
+
+; an entrypoint which calls into a separately compiled library module
+define internal fastcc void @QuantumApplication__Run__body() unnamed_addr {
+
+entry:
+    br label %body
+
+body:
+    ; shot count variable
+    %0 = phi i64 [ %1, %body ], [ 1, %entry ]
+
+    call void @prepare_bell_state()
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+    tail call void @__quantum__qis__mz__body(%Qubit* nonnull inttoptr (i64 1 to %Qubit*), %Result* nonnull inttoptr (i64 1 to %Result*))
+
+    ; shot count branch
+    %1 = add nuw nsw i64 %0, 1
+    %2 = icmp ult i64 %0, 42
+    br i1 %2, label %body, label %exit
+
+exit:
+    ret void
+}
@@ -0,0 +1,27 @@
+
+declare void @__quantum__qis__h__body(ptr) local_unnamed_addr
+declare void @__quantum__qis__mz__body(ptr, ptr) local_unnamed_addr
+declare i1 @__quantum__qis__read_result__body(ptr) local_unnamed_addr
+
+; the same program as measure.ll, written with opaque pointers as emitted by LLVM 15+.
+define internal fastcc void @QuantumApplication__Run__body() unnamed_addr {
+
+entry:
+    br label %body
+
+body:
+    ; shot count variable
+    %0 = phi i64 [ %2, %body ], [ 1, %entry ]
+
+    ; measure a given qubit index
+    tail call void @__quantum__qis__mz__body(ptr nonnull inttoptr (i64 1 to ptr), ptr null)
+    %1 = tail call i1 @__quantum__qis__read_result__body(ptr null)
+
+    ; shot count branch
+    %2 = add nuw nsw i64 %0, 1
+    %3 = icmp ult i64 %0, 42
+    br i1 %3, label %body, label %exit
+
+exit:
+    ret void
+}
\ No newline at end of file
@@ -0,0 +1,54 @@
+%Qubit = type opaque
+%Result = type opaque
+
+declare void @__quantum__qis__h__body(%Qubit*) local_unnamed_addr
+declare void @__quantum__qis__x__body(%Qubit*) local_unnamed_addr
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+
+; an entrypoint calling two kernels, each intended for a different backend
+define void @run() #0 {
+entry:
+    call void @qcs_kernel()
+    call void @simulator_kernel()
+    ret void
+}
+
+define void @qcs_kernel() {
+
+entry:
+    br label %body
+
+body:
+    %0 = phi i64 [ %1, %body ], [ 1, %entry ]
+
+    tail call void @__quantum__qis__h__body(%Qubit* null)
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+
+    %1 = add nuw nsw i64 %0, 1
+    %2 = icmp ult i64 %0, 42
+    br i1 %2, label %body, label %exit
+
+exit:
+    ret void
+}
+
+define void @simulator_kernel() {
+
+entry:
+    br label %body
+
+body:
+    %0 = phi i64 [ %1, %body ], [ 1, %entry ]
+
+    tail call void @__quantum__qis__x__body(%Qubit* null)
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+
+    %1 = add nuw nsw i64 %0, 1
+    %2 = icmp ult i64 %0, 10
+    br i1 %2, label %body, label %exit
+
+exit:
+    ret void
+}
+
+attributes #0 = { "EntryPoint" }
@@ -0,0 +1,42 @@
+%Qubit = type opaque
+%Result = type opaque
+
+declare void @__quantum__qis__h__body(%Qubit*) local_unnamed_addr
+declare void @__quantum__qis__x__body(%Qubit*) local_unnamed_addr
+declare void @__quantum__qis__cnot__body(%Qubit*, %Qubit*) local_unnamed_addr
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+
+; a single function with two shot count loops, preceded by a block which is run only once
+define void @main() #0 {
+
+entry:
+    tail call void @__quantum__qis__x__body(%Qubit* null)
+    br label %bell
+
+bell:
+    %0 = phi i64 [ %1, %bell ], [ 1, %entry ]
+
+    tail call void @__quantum__qis__h__body(%Qubit* null)
+    tail call void @__quantum__qis__cnot__body(%Qubit* null, %Qubit* nonnull inttoptr (i64 1 to %Qubit*))
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+    tail call void @__quantum__qis__mz__body(%Qubit* nonnull inttoptr (i64 1 to %Qubit*), %Result* nonnull inttoptr (i64 1 to %Result*))
+
+    %1 = add nuw nsw i64 %0, 1
+    %2 = icmp ult i64 %0, 42
+    br i1 %2, label %bell, label %flip
+
+flip:
+    %3 = phi i64 [ %4, %flip ], [ 1, %bell ]
+
+    tail call void @__quantum__qis__x__body(%Qubit* null)
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+
+    %4 = add nuw nsw i64 %3, 1
+    %5 = icmp ult i64 %3, 10
+    br i1 %5, label %flip, label %exit
+
+exit:
+    ret void
+}
+
+attributes #0 = { "EntryPoint" }
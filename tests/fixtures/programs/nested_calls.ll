@@ -0,0 +1,60 @@
+%Qubit = type opaque
+%Result = type opaque
+
+declare void @__quantum__qis__h__body(%Qubit*) local_unnamed_addr
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+
+; each shot count loop calls the next function, the last of which is called twice
+define void @main() #0 {
+entry:
+    br label %body
+
+body:
+    %0 = phi i64 [ %1, %body ], [ 1, %entry ]
+    tail call void @__quantum__qis__h__body(%Qubit* null)
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+    call void @middle()
+    %1 = add nuw nsw i64 %0, 1
+    %2 = icmp ult i64 %0, 42
+    br i1 %2, label %body, label %exit
+
+exit:
+    ret void
+}
+
+define internal void @middle() {
+entry:
+    br label %body
+
+body:
+    %0 = phi i64 [ %1, %body ], [ 1, %entry ]
+    tail call void @__quantum__qis__h__body(%Qubit* null)
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+    call void @kernel()
+    call void @kernel()
+    %1 = add nuw nsw i64 %0, 1
+    %2 = icmp ult i64 %0, 42
+    br i1 %2, label %body, label %exit
+
+exit:
+    ret void
+}
+
+define internal void @kernel() {
+entry:
+    br label %body
+
+body:
+    %0 = phi i64 [ %1, %body ], [ 1, %entry ]
+    tail call void @__quantum__qis__h__body(%Qubit* null)
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+
+    %1 = add nuw nsw i64 %0, 1
+    %2 = icmp ult i64 %0, 42
+    br i1 %2, label %body, label %exit
+
+exit:
+    ret void
+}
+
+attributes #0 = { "EntryPoint" }
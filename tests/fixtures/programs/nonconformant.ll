@@ -0,0 +1,26 @@
+%Qubit = type opaque
+%Result = type opaque
+
+declare %Qubit* @__quantum__rt__qubit_allocate()
+declare void @__quantum__rt__qubit_release(%Qubit*)
+declare void @__quantum__qis__h__body(%Qubit*)
+declare void @__quantum__qis__x__body(%Qubit*)
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*)
+declare void @__quantum__rt__result_record_output(%Result*)
+
+; an entry point which declares the qubits but not the results it requires, applies a gate after
+; measurement, measures after recording output, and measures a qubit after releasing it
+define void @QuantumApplication__Run__body() #0 {
+entry:
+  %q = call %Qubit* @__quantum__rt__qubit_allocate()
+  call void @__quantum__qis__h__body(%Qubit* null)
+  call void @__quantum__qis__h__body(%Qubit* %q)
+  call void @__quantum__rt__qubit_release(%Qubit* %q)
+  call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+  call void @__quantum__qis__x__body(%Qubit* inttoptr (i64 1 to %Qubit*))
+  call void @__quantum__rt__result_record_output(%Result* null)
+  call void @__quantum__qis__mz__body(%Qubit* %q, %Result* inttoptr (i64 1 to %Result*))
+  ret void
+}
+
+attributes #0 = { "EntryPoint" "requiredQubits"="2" }
@@ -0,0 +1,25 @@
+%Qubit = type opaque
+%Result = type opaque
+
+declare void @__quantum__qis__h__body(%Qubit*) local_unnamed_addr
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+
+; a shot count loop in an unnamed basic block, as emitted by frontends which do not name their blocks
+define void @main() #0 {
+    br label %1
+
+1:
+    %2 = phi i64 [ %3, %1 ], [ 1, %0 ]
+
+    tail call void @__quantum__qis__h__body(%Qubit* null)
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+
+    %3 = add nuw nsw i64 %2, 1
+    %4 = icmp ult i64 %2, 16
+    br i1 %4, label %1, label %5
+
+5:
+    ret void
+}
+
+attributes #0 = { "EntryPoint" }
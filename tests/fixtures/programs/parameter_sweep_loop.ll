@@ -0,0 +1,39 @@
+%Qubit = type opaque
+%Result = type opaque
+
+declare void @__quantum__qis__rx__body(double, %Qubit*) local_unnamed_addr
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+declare i1 @__quantum__qis__read_result__body(%Result*) local_unnamed_addr
+
+; a shot count loop nested within a sweep over a parameter, which reaches the gate through a phi
+; at the start of the shot count block, as LLVM emits for values carried around a loop
+define internal fastcc void @QuantumApplication__Run__body() unnamed_addr {
+
+entry:
+    br label %sweep
+
+sweep:
+    %theta = phi double [ 0.0, %entry ], [ %next_theta, %next ]
+    br label %body
+
+body:
+    ; shot count variable
+    %0 = phi i64 [ %2, %body ], [ 1, %sweep ]
+    %theta.shot = phi double [ %theta, %sweep ], [ %theta.shot, %body ]
+    tail call void @__quantum__qis__rx__body(double %theta.shot, %Qubit* null)
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+    %1 = tail call i1 @__quantum__qis__read_result__body(%Result* null)
+
+    ; shot count branch
+    %2 = add nuw nsw i64 %0, 1
+    %3 = icmp ult i64 %0, 100
+    br i1 %3, label %body, label %next
+
+next:
+    %next_theta = fadd double %theta, 2.500000e-01
+    %4 = fcmp olt double %next_theta, 3.140000e+00
+    br i1 %4, label %sweep, label %exit
+
+exit:
+    ret void
+}
@@ -0,0 +1,40 @@
+%Qubit = type opaque
+%Result = type opaque
+
+declare void @__quantum__qis__rz__body(double, %Qubit*) local_unnamed_addr
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+declare i1 @__quantum__qis__read_result__body(%Result*) local_unnamed_addr
+
+; an outer classical loop which updates the parameter of a shot count loop between executions
+define internal fastcc void @QuantumApplication__Run__body() unnamed_addr {
+
+entry:
+    br label %optimize
+
+optimize:
+    ; parameter and iteration count carried across executions
+    %theta = phi double [ 0.0, %entry ], [ %next_theta, %update ]
+    %iteration = phi i64 [ 0, %entry ], [ %next_iteration, %update ]
+    br label %body
+
+body:
+    ; shot count variable
+    %0 = phi i64 [ %2, %body ], [ 1, %optimize ]
+    tail call void @__quantum__qis__rz__body(double %theta, %Qubit* null)
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+    %1 = tail call i1 @__quantum__qis__read_result__body(%Result* null)
+
+    ; shot count branch
+    %2 = add nuw nsw i64 %0, 1
+    %3 = icmp ult i64 %0, 100
+    br i1 %3, label %body, label %update
+
+update:
+    %next_theta = fadd double %theta, 1.000000e-01
+    %next_iteration = add nuw nsw i64 %iteration, 1
+    %4 = icmp ult i64 %iteration, 10
+    br i1 %4, label %optimize, label %exit
+
+exit:
+    ret void
+}
@@ -0,0 +1,28 @@
+%Qubit = type opaque
+%Result = type opaque
+
+declare %Qubit* @__quantum__rt__qubit_allocate()
+declare void @__quantum__qis__h__body(%Qubit*) local_unnamed_addr
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+
+define internal fastcc void @QuantumApplication__Run__body() unnamed_addr {
+
+entry:
+    %q = call %Qubit* @__quantum__rt__qubit_allocate()
+    br label %body
+
+body:
+    ; shot count variable
+    %0 = phi i64 [ %1, %body ], [ 1, %entry ]
+
+    tail call void @__quantum__qis__h__body(%Qubit* %q)
+    tail call void @__quantum__qis__mz__body(%Qubit* %q, %Result* null)
+
+    ; shot count branch
+    %1 = add nuw nsw i64 %0, 1
+    %2 = icmp ult i64 %0, 42
+    br i1 %2, label %body, label %exit
+
+exit:
+    ret void
+}
@@ -0,0 +1,31 @@
+%Qubit = type opaque
+%Result = type opaque
+
+@0 = internal constant [92 x i8] c"PRAGMA PRESERVE_BLOCK\0APULSE 0 \22rf\22 flat(duration: 1e-6, iq: 1.0)\0APRAGMA END_PRESERVE_BLOCK\0A\00"
+
+declare void @__quantum__qis__h__body(%Qubit*) local_unnamed_addr
+declare void @__qcs__quil__inline(i8*) local_unnamed_addr
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+
+; a program injecting literal Quil through the __qcs__quil__inline intrinsic
+define internal fastcc void @QuantumApplication__Run__body() unnamed_addr {
+
+entry:
+    br label %body
+
+body:
+    ; shot count variable
+    %0 = phi i64 [ %1, %body ], [ 1, %entry ]
+
+    tail call void @__quantum__qis__h__body(%Qubit* null)
+    tail call void @__qcs__quil__inline(i8* getelementptr inbounds ([92 x i8], [92 x i8]* @0, i64 0, i64 0))
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+
+    ; shot count branch
+    %1 = add nuw nsw i64 %0, 1
+    %2 = icmp ult i64 %0, 42
+    br i1 %2, label %body, label %exit
+
+exit:
+    ret void
+}
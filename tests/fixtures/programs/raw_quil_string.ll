@@ -0,0 +1,34 @@
+%Qubit = type opaque
+%Result = type opaque
+%String = type opaque
+
+@0 = internal constant [9 x i8] c"X 0\0AY 0\0A\00"
+
+declare void @__quantum__qis__h__body(%Qubit*) local_unnamed_addr
+declare %String* @__quantum__rt__string_create(i8*) local_unnamed_addr
+declare void @__vendor__quil__body(%String*) local_unnamed_addr
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+
+; a program embedding raw Quil, passed as a runtime string created from a constant
+define internal fastcc void @QuantumApplication__Run__body() unnamed_addr {
+
+entry:
+    %quil = tail call %String* @__quantum__rt__string_create(i8* getelementptr inbounds ([9 x i8], [9 x i8]* @0, i64 0, i64 0))
+    br label %body
+
+body:
+    ; shot count variable
+    %0 = phi i64 [ %1, %body ], [ 1, %entry ]
+
+    tail call void @__quantum__qis__h__body(%Qubit* null)
+    tail call void @__vendor__quil__body(%String* %quil)
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+
+    ; shot count branch
+    %1 = add nuw nsw i64 %0, 1
+    %2 = icmp ult i64 %0, 42
+    br i1 %2, label %body, label %exit
+
+exit:
+    ret void
+}
@@ -0,0 +1,33 @@
+%Qubit = type opaque
+%Result = type opaque
+
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+declare i1 @__quantum__qis__read_result__body(%Result*)
+declare void @__quantum__rt__int_record_output(i64, i8*)
+
+define internal fastcc void @QuantumApplication__Run__body() unnamed_addr {
+
+entry:
+    br label %body
+
+body:
+    ; shot count variable
+    %0 = phi i64 [ %1, %body ], [ 1, %entry ]
+
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+    tail call void @__quantum__qis__mz__body(%Qubit* nonnull inttoptr (i64 1 to %Qubit*), %Result* nonnull inttoptr (i64 1 to %Result*))
+    %r0 = tail call i1 @__quantum__qis__read_result__body(%Result* null)
+    %r1 = tail call i1 @__quantum__qis__read_result__body(%Result* nonnull inttoptr (i64 1 to %Result*))
+    %z0 = zext i1 %r0 to i64
+    %z1 = zext i1 %r1 to i64
+    %sum = add i64 %z0, %z1
+    tail call void @__quantum__rt__int_record_output(i64 %sum, i8* null)
+
+    ; shot count branch
+    %1 = add nuw nsw i64 %0, 1
+    %2 = icmp ult i64 %0, 42
+    br i1 %2, label %body, label %exit
+
+exit:
+    ret void
+}
@@ -0,0 +1,42 @@
+%Qubit = type opaque
+%Result = type opaque
+
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+declare i1 @__quantum__qis__read_result__body(%Result*)
+declare void @__quantum__rt__bool_record_output(i1, i8*)
+
+; records the parity and the majority vote of three measurements, computed on the host
+define internal fastcc void @QuantumApplication__Run__body() unnamed_addr {
+
+entry:
+    br label %body
+
+body:
+    ; shot count variable
+    %0 = phi i64 [ %1, %body ], [ 1, %entry ]
+
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+    tail call void @__quantum__qis__mz__body(%Qubit* nonnull inttoptr (i64 1 to %Qubit*), %Result* nonnull inttoptr (i64 1 to %Result*))
+    tail call void @__quantum__qis__mz__body(%Qubit* nonnull inttoptr (i64 2 to %Qubit*), %Result* nonnull inttoptr (i64 2 to %Result*))
+    %r0 = tail call i1 @__quantum__qis__read_result__body(%Result* null)
+    %r1 = tail call i1 @__quantum__qis__read_result__body(%Result* nonnull inttoptr (i64 1 to %Result*))
+    %r2 = tail call i1 @__quantum__qis__read_result__body(%Result* nonnull inttoptr (i64 2 to %Result*))
+    %x01 = xor i1 %r0, %r1
+    %parity = xor i1 %x01, %r2
+    tail call void @__quantum__rt__bool_record_output(i1 %parity, i8* null)
+    %z0 = zext i1 %r0 to i64
+    %z1 = zext i1 %r1 to i64
+    %z2 = zext i1 %r2 to i64
+    %s01 = add i64 %z0, %z1
+    %sum = add i64 %s01, %z2
+    %majority = icmp uge i64 %sum, 2
+    tail call void @__quantum__rt__bool_record_output(i1 %majority, i8* null)
+
+    ; shot count branch
+    %1 = add nuw nsw i64 %0, 1
+    %2 = icmp ult i64 %0, 42
+    br i1 %2, label %body, label %exit
+
+exit:
+    ret void
+}
@@ -0,0 +1,59 @@
+%Qubit = type opaque
+%Result = type opaque
+
+declare void @__quantum__qis__h__body(%Qubit*) local_unnamed_addr
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+
+; a shot count loop calling a pair of mutually recursive functions
+define void @main() #0 {
+entry:
+    br label %body
+
+body:
+    %0 = phi i64 [ %1, %body ], [ 1, %entry ]
+    tail call void @__quantum__qis__h__body(%Qubit* null)
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+    call void @ping()
+    %1 = add nuw nsw i64 %0, 1
+    %2 = icmp ult i64 %0, 42
+    br i1 %2, label %body, label %exit
+
+exit:
+    ret void
+}
+
+define internal void @ping() {
+entry:
+    br label %body
+
+body:
+    %0 = phi i64 [ %1, %body ], [ 1, %entry ]
+    tail call void @__quantum__qis__h__body(%Qubit* null)
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+    call void @pong()
+    %1 = add nuw nsw i64 %0, 1
+    %2 = icmp ult i64 %0, 42
+    br i1 %2, label %body, label %exit
+
+exit:
+    ret void
+}
+
+define internal void @pong() {
+entry:
+    br label %body
+
+body:
+    %0 = phi i64 [ %1, %body ], [ 1, %entry ]
+    tail call void @__quantum__qis__h__body(%Qubit* null)
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+    call void @ping()
+    %1 = add nuw nsw i64 %0, 1
+    %2 = icmp ult i64 %0, 42
+    br i1 %2, label %body, label %exit
+
+exit:
+    ret void
+}
+
+attributes #0 = { "EntryPoint" }
@@ -0,0 +1,33 @@
+%Qubit = type opaque
+%Result = type opaque
+
+declare void @__quantum__qis__h__body(%Qubit*) local_unnamed_addr
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+declare i1 @__quantum__qis__read_result__body(%Result*) local_unnamed_addr
+
+; simple function which executes a hadamard gate and that's it.
+define internal fastcc void @QuantumApplication__Run__body() unnamed_addr {
+
+entry:
+    br label %body
+
+body:
+    ; shot count variable
+    %0 = phi i64 [ %3, %body ], [ 1, %entry ]
+    %any = phi i1 [ %2, %body ], [ false, %entry ]
+    tail call void @__quantum__qis__h__body(%Qubit* nonnull inttoptr (i64 2 to %Qubit*))
+
+    ; measure a given qubit index
+    tail call void @__quantum__qis__mz__body(%Qubit* nonnull inttoptr (i64 2 to %Qubit*), %Result* null)
+    %1 = tail call i1 @__quantum__qis__read_result__body(%Result* null)
+
+    %2 = or i1 %any, %1
+
+    ; shot count branch
+    %3 = add nuw nsw i64 %0, 1
+    %4 = icmp ult i64 %0, 42
+    br i1 %4, label %body, label %exit
+
+exit:
+    ret void
+}
\ No newline at end of file
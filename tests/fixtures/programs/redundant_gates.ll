@@ -0,0 +1,46 @@
+%Qubit = type opaque
+%Result = type opaque
+
+declare void @__quantum__qis__h__body(%Qubit*) local_unnamed_addr
+declare void @__quantum__qis__s__body(%Qubit*) local_unnamed_addr
+declare void @__quantum__qis__s__adj(%Qubit*) local_unnamed_addr
+declare void @__quantum__qis__rx__body(double, %Qubit*) local_unnamed_addr
+declare void @__quantum__qis__rz__body(double, %Qubit*) local_unnamed_addr
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+
+define internal fastcc void @QuantumApplication__Run__body() unnamed_addr {
+
+entry:
+    br label %body
+
+body:
+    ; shot count variable
+    %0 = phi i64 [ %1, %body ], [ 1, %entry ]
+
+    ; cancel one another
+    tail call void @__quantum__qis__h__body(%Qubit* null)
+    tail call void @__quantum__qis__h__body(%Qubit* null)
+    tail call void @__quantum__qis__s__body(%Qubit* nonnull inttoptr (i64 1 to %Qubit*))
+    tail call void @__quantum__qis__s__adj(%Qubit* nonnull inttoptr (i64 1 to %Qubit*))
+
+    ; merge into a single rotation by pi/2
+    tail call void @__quantum__qis__rz__body(double 0x3FE921FB54442D18, %Qubit* null)
+    tail call void @__quantum__qis__rz__body(double 0x3FE921FB54442D18, %Qubit* null)
+
+    ; the identity
+    tail call void @__quantum__qis__rx__body(double 0.0, %Qubit* nonnull inttoptr (i64 1 to %Qubit*))
+
+    ; not adjacent, as a measurement separates them
+    tail call void @__quantum__qis__h__body(%Qubit* nonnull inttoptr (i64 1 to %Qubit*))
+    tail call void @__quantum__qis__mz__body(%Qubit* nonnull inttoptr (i64 1 to %Qubit*), %Result* nonnull inttoptr (i64 1 to %Result*))
+    tail call void @__quantum__qis__h__body(%Qubit* nonnull inttoptr (i64 1 to %Qubit*))
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+
+    ; shot count branch
+    %1 = add nuw nsw i64 %0, 1
+    %2 = icmp ult i64 %0, 42
+    br i1 %2, label %body, label %exit
+
+exit:
+    ret void
+}
@@ -0,0 +1,31 @@
+%Qubit = type opaque
+%Result = type opaque
+
+declare void @__quantum__qis__x__body(%Qubit*) local_unnamed_addr
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+declare i1 @__quantum__qis__read_result__body(%Result*) local_unnamed_addr
+
+; measures the same qubit into the same result twice within a single shot
+define internal fastcc void @QuantumApplication__Run__body() unnamed_addr {
+
+entry:
+    br label %body
+
+body:
+    ; shot count variable
+    %0 = phi i64 [ %3, %body ], [ 1, %entry ]
+
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+    %1 = tail call i1 @__quantum__qis__read_result__body(%Result* null)
+    tail call void @__quantum__qis__x__body(%Qubit* null)
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+    %2 = tail call i1 @__quantum__qis__read_result__body(%Result* null)
+
+    ; shot count branch
+    %3 = add nuw nsw i64 %0, 1
+    %4 = icmp ult i64 %0, 42
+    br i1 %4, label %body, label %exit
+
+exit:
+    ret void
+}
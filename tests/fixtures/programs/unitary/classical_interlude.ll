@@ -0,0 +1,23 @@
+%Qubit = type opaque
+%Result = type opaque
+
+declare void @__quantum__qis__h__body(%Qubit*) local_unnamed_addr
+declare void @__quantum__qis__rx__body(double, %Qubit*) local_unnamed_addr
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+declare i1 @__quantum__qis__read_result__body(%Result*) local_unnamed_addr
+
+; two quantum regions separated by classical code, one part of which is computed from readout
+define void @main() #0 {
+entry:
+    tail call void @__quantum__qis__h__body(%Qubit* null)
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+    %0 = tail call i1 @__quantum__qis__read_result__body(%Result* null)
+    %1 = zext i1 %0 to i64
+    %2 = add i64 %1, 1
+    %3 = fmul double 2.0, 3.0
+    tail call void @__quantum__qis__rx__body(double 1.0, %Qubit* nonnull inttoptr (i64 1 to %Qubit*))
+    tail call void @__quantum__qis__mz__body(%Qubit* nonnull inttoptr (i64 1 to %Qubit*), %Result* nonnull inttoptr (i64 1 to %Result*))
+    ret void
+}
+
+attributes #0 = { "EntryPoint" }
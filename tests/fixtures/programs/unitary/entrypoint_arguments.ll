@@ -0,0 +1,17 @@
+%Qubit = type opaque
+%Result = type opaque
+
+declare void @__quantum__qis__rx__body(double, %Qubit*) local_unnamed_addr
+declare void @__quantum__qis__rz__body(double, %Qubit*) local_unnamed_addr
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+
+; parameters are given to the entrypoint as arguments, one of which is unused
+define internal fastcc void @QuantumApplication__Run__body(double %theta, double %unused, double %phi) unnamed_addr {
+
+entry:
+    tail call void @__quantum__qis__rz__body(double %phi, %Qubit* null)
+    tail call void @__quantum__qis__rx__body(double %theta, %Qubit* null)
+    tail call void @__quantum__qis__rx__body(double %theta, %Qubit* nonnull inttoptr (i64 1 to %Qubit*))
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+    ret void
+}
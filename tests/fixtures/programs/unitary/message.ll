@@ -0,0 +1,24 @@
+%Qubit = type opaque
+%Result = type opaque
+%String = type opaque
+
+@0 = internal constant [13 x i8] c"Hello, world\00"
+
+declare void @__quantum__qis__h__body(%Qubit*) local_unnamed_addr
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+declare %String* @__quantum__rt__string_create(i8*) local_unnamed_addr
+declare void @__quantum__rt__message(%String*) local_unnamed_addr
+declare void @__quantum__rt__string_update_reference_count(%String*, i32) local_unnamed_addr
+
+; as emitted for Q#'s `Message("Hello, world")` between two operations
+define void @main() #0 {
+entry:
+    tail call void @__quantum__qis__h__body(%Qubit* null)
+    %0 = tail call %String* @__quantum__rt__string_create(i8* getelementptr inbounds ([13 x i8], [13 x i8]* @0, i64 0, i64 0))
+    tail call void @__quantum__rt__message(%String* %0)
+    tail call void @__quantum__rt__string_update_reference_count(%String* %0, i32 -1)
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+    ret void
+}
+
+attributes #0 = { "EntryPoint" }
@@ -0,0 +1,17 @@
+%Qubit = type opaque
+%Result = type opaque
+
+declare void @__quantum__qis__h__body(%Qubit*) local_unnamed_addr
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+
+; the shot count is given as a module flag, as the program itself has no shot count loop
+define internal fastcc void @QuantumApplication__Run__body() unnamed_addr {
+
+entry:
+    tail call void @__quantum__qis__h__body(%Qubit* null)
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+    ret void
+}
+
+!llvm.module.flags = !{!0}
+!0 = !{i32 1, !"shots", i64 500}
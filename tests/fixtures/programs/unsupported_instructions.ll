@@ -0,0 +1,52 @@
+%Qubit = type opaque
+%Result = type opaque
+
+declare void @__quantum__qis__h__body(%Qubit*) local_unnamed_addr
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*) local_unnamed_addr
+
+@counter = internal global i64 0
+
+; a helper which is called from the entrypoint, and so is also scanned
+define internal void @count_shot() {
+entry:
+    %previous = atomicrmw add i64* @counter, i64 1 seq_cst
+    ret void
+}
+
+; never called, and so never scanned
+define internal void @unused() {
+entry:
+    fence seq_cst
+    ret void
+}
+
+define internal fastcc void @QuantumApplication__Run__body() unnamed_addr {
+
+entry:
+    br label %body
+
+body:
+    ; shot count variable
+    %0 = phi i64 [ %1, %latch ], [ 1, %entry ]
+
+    tail call void @__quantum__qis__h__body(%Qubit* null)
+    tail call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+    call void @count_shot()
+    switch i64 %0, label %latch [ i64 7, label %skip ]
+
+skip:
+    br label %latch
+
+latch:
+    ; shot count branch
+    %1 = add nuw nsw i64 %0, 1
+    %2 = icmp ult i64 %0, 42
+    br i1 %2, label %body, label %exit
+
+exit:
+    ret void
+
+unreachable_block:
+    fence seq_cst
+    ret void
+}
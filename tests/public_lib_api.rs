@@ -1,9 +1,30 @@
-use std::fs::read;
+use std::{
+    fs::read,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
 
 use qcs::RegisterData;
 use qcs_sdk_qir::{
+    dry_run_patch,
+    error::{transpile_with_error_kind, TranspileError},
+    explain_qir_to_quil,
     output::{self, DebugOutputFormat},
-    transpile_qir_to_quil,
+    patch_qir_with_qcs, patch_unitary_qir_with_qcs, patch_with_manifest,
+    quilc::{QuilCompiler, Quilc},
+    read_provenance,
+    translation::{TranslatedProgram, TranslationService},
+    transpile_function_to_quil, transpile_qir_to_quil, transpile_qir_to_quil_by_block,
+    transpile_qir_to_quil_with_options, transpile_unitary_qir_to_quil,
+    transpile_unitary_qir_to_quil_with_options,
+    transpiler::cache::CacheKey,
+    try_transpile_arbitrary,
+    validation::{read_qir_declarations, QirDeclarations},
+    CallingConvention, CustomInstructionHandler, CustomInstructionOutput, ExecutionTarget,
+    IntrinsicArgument, IntrinsicCall, Limits, MaxShotCount, MeasurementOrder, MessageHandling,
+    PatchFormat, PatchOptions, ProgramMetrics, QubitReuse, RawQuilHandler, RecordedOutput,
+    RuntimeFlavor, ShotChunking, TranspileOptions, TranspileWarning,
 };
 
 #[test]
@@ -14,6 +35,461 @@ fn transpile_qir_to_quil_bell_state() {
     insta::assert_display_snapshot!(output.shot_count);
 }
 
+#[test]
+fn transpile_each_function_to_quil() {
+    let data = read("tests/fixtures/programs/multiple_kernels.bc").unwrap();
+
+    let bell_state = transpile_function_to_quil(&data, "prepare_bell_state").unwrap();
+    assert_eq!(bell_state.shot_count, 42);
+    assert_eq!(
+        bell_state.program.to_string(true),
+        "DECLARE ro BIT[2]\nH 0\nCNOT 0 1\nMEASURE 0 ro[0]\nMEASURE 1 ro[1]\n"
+    );
+
+    let flip_qubit = transpile_function_to_quil(&data, "flip_qubit").unwrap();
+    assert_eq!(flip_qubit.shot_count, 10);
+    assert_eq!(
+        flip_qubit.program.to_string(true),
+        "DECLARE ro BIT[1]\nX 0\nMEASURE 0 ro[0]\n"
+    );
+
+    assert!(transpile_function_to_quil(&data, "missing").is_err());
+}
+
+#[test]
+fn shot_count_loop_is_found_without_a_body_block() {
+    let data = read("tests/fixtures/programs/numbered_blocks.bc").unwrap();
+    let output = transpile_qir_to_quil(&data).unwrap();
+    assert_eq!(output.shot_count, 16);
+    assert_eq!(
+        output.program.to_string(true),
+        "DECLARE ro BIT[1]\nH 0\nMEASURE 0 ro[0]\n"
+    );
+
+    let data = read("tests/fixtures/programs/multiple_blocks.bc").unwrap();
+    assert_eq!(transpile_qir_to_quil(&data).unwrap().shot_count, 42);
+
+    let options = TranspileOptions {
+        body_block: Some(String::from("flip")),
+        ..TranspileOptions::default()
+    };
+    let output = transpile_qir_to_quil_with_options(&data, &options).unwrap();
+    assert_eq!(output.shot_count, 10);
+
+    let options = TranspileOptions {
+        body_block: Some(String::from("missing")),
+        ..TranspileOptions::default()
+    };
+    assert!(transpile_qir_to_quil_with_options(&data, &options).is_err());
+}
+
+/// Nativizes the bell state as quilc would for an ISA of `RZ`, `RX` and `CZ`.
+#[derive(Debug)]
+struct MockQuilCompiler;
+
+impl QuilCompiler for MockQuilCompiler {
+    fn compile(&self, program: &quil_rs::Program) -> eyre::Result<quil_rs::Program> {
+        assert!(program.to_string(true).contains("H 0\nCNOT 0 1\n"));
+        quil_rs::Program::from_str(
+            "DECLARE ro BIT[2]\nRZ(pi/2) 0\nRX(pi/2) 0\nRZ(pi/2) 1\nRX(pi/2) 1\nCZ 1 0\nRX(-pi/2) 1\nRZ(-pi/2) 1\nMEASURE 0 ro[0]\nMEASURE 1 ro[1]\n",
+        )
+        .map_err(|error| eyre::eyre!("{}", error))
+    }
+}
+
+#[test]
+fn quil_compiler_returns_native_program_alongside_raw_program() {
+    let data = read("tests/fixtures/programs/bell_state.bc").unwrap();
+    let options = TranspileOptions {
+        quil_compiler: Some(Arc::new(MockQuilCompiler)),
+        ..TranspileOptions::default()
+    };
+    let output = transpile_qir_to_quil_with_options(&data, &options).unwrap();
+    assert_eq!(
+        output.program.to_string(true),
+        transpile_qir_to_quil(&data)
+            .unwrap()
+            .program
+            .to_string(true)
+    );
+    let native_program = output.native_program.unwrap().to_string(true);
+    assert!(native_program.contains("CZ 1 0\n"));
+    assert!(!native_program.contains("CNOT"));
+
+    let options = TranspileOptions {
+        quil_compiler: Some(Arc::new(Quilc {
+            command: "tests/fixtures/no-such-quilc".into(),
+            ..Quilc::new("8Q")
+        })),
+        ..TranspileOptions::default()
+    };
+    let error = transpile_qir_to_quil_with_options(&data, &options).unwrap_err();
+    assert_eq!(error.to_string(), "native compilation failed");
+    assert!(format!("{:#}", error).contains("failed to run tests/fixtures/no-such-quilc"));
+}
+
+#[test]
+fn unitary_entrypoint_arguments_are_mapped_to_parameters() {
+    let data = read("tests/fixtures/programs/unitary/entrypoint_arguments.bc").unwrap();
+    let output = transpile_unitary_qir_to_quil(&data).unwrap();
+    let program = output.program.to_string(true);
+    assert!(program.contains("DECLARE __qir_param REAL[3]\n"));
+    assert!(program.contains(
+        "RZ(__qir_param[2]) 0\nRX(__qir_param[0]) 0\nRX(__qir_param[0]) 1\nMEASURE 0 ro[0]\n"
+    ));
+    assert_eq!(
+        output.parameters,
+        ["double %theta", "double %unused", "double %phi"]
+    );
+    assert_eq!(
+        output.arguments,
+        [
+            (String::from("%theta"), 0),
+            (String::from("%unused"), 1),
+            (String::from("%phi"), 2)
+        ]
+    );
+}
+
+#[test]
+fn unitary_quantum_regions_separated_by_classical_code_form_one_program() {
+    let data = read("tests/fixtures/programs/unitary/classical_interlude.bc").unwrap();
+    let program = transpile_unitary_qir_to_quil(&data)
+        .unwrap()
+        .program
+        .to_string(true);
+    assert!(program.contains("H 0\nMEASURE 0 ro[0]\nRX("));
+    assert!(program.ends_with(" 1\nMEASURE 1 ro[1]\n"));
+
+    let context = inkwell::context::Context::create();
+    let module = patch_unitary_qir_with_qcs(PatchOptions::default(), &data, &context).unwrap();
+    module.verify().unwrap();
+    let ir = module.print_to_string().to_string();
+    assert!(!ir.contains("call i1 @__quantum__qis__read_result__body"));
+    assert!(!ir.contains("zext i1"));
+    assert!(ir.contains("fmul double"));
+}
+
+#[test]
+fn recording_unmeasured_results_is_warned_of_or_denied() {
+    let data = read("tests/fixtures/programs/unitary/qiskit_qir_allow_unmeasured.bc").unwrap();
+    let output = transpile_unitary_qir_to_quil(&data).unwrap();
+    assert_eq!(
+        output.warnings,
+        [
+            TranspileWarning::UnmeasuredResultRecorded {
+                result: 3,
+                offset: 1
+            },
+            TranspileWarning::UnmeasuredResultRecorded {
+                result: 2,
+                offset: 2
+            },
+            TranspileWarning::UnmeasuredResultRecorded {
+                result: 1,
+                offset: 3
+            },
+        ]
+    );
+
+    let options = TranspileOptions {
+        deny_warnings: true,
+        ..TranspileOptions::default()
+    };
+    let error = transpile_unitary_qir_to_quil_with_options(&data, &options).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "result 3 is recorded but never measured, so ro[1] always records 0"
+    );
+
+    let data = read("tests/fixtures/programs/unitary/bell_state.bc").unwrap();
+    assert!(transpile_unitary_qir_to_quil(&data)
+        .unwrap()
+        .warnings
+        .is_empty());
+}
+
+#[test]
+fn messages_are_retained_written_as_pragmas_or_removed() {
+    let data = read("tests/fixtures/programs/unitary/message.bc").unwrap();
+    let transpile = |message_handling| {
+        let options = TranspileOptions {
+            message_handling,
+            ..TranspileOptions::default()
+        };
+        transpile_unitary_qir_to_quil_with_options(&data, &options)
+            .unwrap()
+            .program
+            .to_string(true)
+    };
+    assert!(transpile(MessageHandling::Retain).ends_with("H 0\nMEASURE 0 ro[0]\n"));
+    assert!(transpile(MessageHandling::Pragma)
+        .ends_with("H 0\nPRAGMA MESSAGE \"Hello, world\"\nMEASURE 0 ro[0]\n"));
+    assert!(transpile(MessageHandling::Remove).ends_with("H 0\nMEASURE 0 ro[0]\n"));
+
+    let patch = |message_handling| {
+        let context = inkwell::context::Context::create();
+        let options = PatchOptions {
+            message_handling,
+            ..PatchOptions::default()
+        };
+        let module = patch_unitary_qir_with_qcs(options, &data, &context).unwrap();
+        module.verify().unwrap();
+        module.print_to_string().to_string()
+    };
+    assert!(patch(MessageHandling::Retain).contains("call void @__quantum__rt__message("));
+    for message_handling in [MessageHandling::Pragma, MessageHandling::Remove] {
+        let ir = patch(message_handling);
+        assert!(!ir.contains("call void @__quantum__rt__message("));
+        assert!(!ir.contains("call %String* @__quantum__rt__string_create("));
+    }
+}
+
+#[test]
+fn unitary_shot_count_is_read_from_module_metadata() {
+    let data = read("tests/fixtures/programs/unitary/shots_metadata.bc").unwrap();
+    assert_eq!(
+        transpile_unitary_qir_to_quil(&data).unwrap().shot_count,
+        Some(500)
+    );
+
+    let data = read("tests/fixtures/programs/unitary/bell_state.bc").unwrap();
+    assert_eq!(
+        transpile_unitary_qir_to_quil(&data).unwrap().shot_count,
+        None
+    );
+}
+
+#[test]
+fn qubits_and_results_may_be_in_any_address_space() {
+    let data = read("tests/fixtures/programs/address_space.bc").unwrap();
+    let output = transpile_qir_to_quil(&data).unwrap();
+    assert_eq!(
+        output.program.to_string(true),
+        "DECLARE ro BIT[2]\nH 0\nCNOT 0 1\nMEASURE 0 ro[0]\nMEASURE 1 ro[1]\n"
+    );
+}
+
+#[test]
+fn optimize_quil_cancels_inverses_and_merges_rotations() {
+    let data = read("tests/fixtures/programs/redundant_gates.bc").unwrap();
+    let options = TranspileOptions {
+        trace: true,
+        symbolic_pi: true,
+        optimize_quil: true,
+        ..TranspileOptions::default()
+    };
+    let output = transpile_qir_to_quil_with_options(&data, &options).unwrap();
+    assert_eq!(
+        output.program.to_string(true),
+        "DECLARE ro BIT[2]\nRZ(pi/2) 0\nH 1\nMEASURE 1 ro[0]\nH 1\nMEASURE 0 ro[1]\n"
+    );
+    assert_eq!(output.trace.len(), 5);
+    assert!(output.trace[0].contains("__quantum__qis__rz__body"));
+
+    let options = TranspileOptions {
+        optimize_quil: false,
+        ..options
+    };
+    let output = transpile_qir_to_quil_with_options(&data, &options).unwrap();
+    assert_eq!(output.program.to_instructions(false).len(), 11);
+}
+
+#[test]
+fn compact_qubits_renumbers_qubits_densely() {
+    let data = read("tests/fixtures/programs/sparse_qubits.bc").unwrap();
+    let output = transpile_qir_to_quil(&data).unwrap();
+    assert!(output.qubit_mapping.is_empty());
+    assert!(output.program.to_string(true).contains("CNOT 17 5\n"));
+
+    let options = TranspileOptions {
+        compact_qubits: true,
+        ..TranspileOptions::default()
+    };
+    let output = transpile_qir_to_quil_with_options(&data, &options).unwrap();
+    assert_eq!(
+        output.program.to_string(true),
+        "DECLARE ro BIT[2]\nH 1\nCNOT 1 0\nMEASURE 0 ro[0]\nMEASURE 1 ro[1]\n"
+    );
+    assert_eq!(
+        output.qubit_mapping.into_iter().collect::<Vec<_>>(),
+        [(5, 0), (17, 1)]
+    );
+}
+
+#[test]
+fn measurement_order_is_checked_and_normalized() {
+    let data = read("tests/fixtures/programs/measure_before_gate.bc").unwrap();
+    let options = TranspileOptions {
+        measurement_order: MeasurementOrder::Deny,
+        ..TranspileOptions::default()
+    };
+    let error = transpile_qir_to_quil_with_options(&data, &options).unwrap_err();
+    assert!(format!("{:#}", error).contains("gate `X 1` follows `MEASURE 0 ro[0]`"));
+
+    let options = TranspileOptions {
+        measurement_order: MeasurementOrder::Reorder,
+        ..TranspileOptions::default()
+    };
+    let output = transpile_qir_to_quil_with_options(&data, &options).unwrap();
+    assert_eq!(
+        output.program.to_string(true),
+        "DECLARE ro BIT[2]\nH 0\nX 1\nMEASURE 0 ro[0]\nMEASURE 1 ro[1]\n"
+    );
+}
+
+#[test]
+fn qir_version_and_profile_are_read_from_the_module() {
+    let data = read("tests/fixtures/programs/unitary/base_profile.bc").unwrap();
+    let expected = QirDeclarations {
+        major_version: Some(1),
+        minor_version: Some(0),
+        profile: Some(String::from("base_profile")),
+    };
+    assert_eq!(read_qir_declarations(&data).unwrap(), expected);
+    assert_eq!(
+        transpile_unitary_qir_to_quil(&data)
+            .unwrap()
+            .qir_declarations,
+        expected
+    );
+
+    let data = read("tests/fixtures/programs/bell_state.bc").unwrap();
+    assert!(read_qir_declarations(&data).unwrap().is_empty());
+    assert!(transpile_qir_to_quil(&data)
+        .unwrap()
+        .qir_declarations
+        .is_empty());
+}
+
+#[test]
+fn size_limits_abort_transpilation() {
+    let data = read("tests/fixtures/programs/bell_state.bc").unwrap();
+    let transpile_with_limits = |limits| {
+        let options = TranspileOptions {
+            limits,
+            ..TranspileOptions::default()
+        };
+        transpile_qir_to_quil_with_options(&data, &options)
+    };
+
+    transpile_with_limits(Limits {
+        max_blocks: Some(3),
+        max_instructions_per_block: Some(16),
+        max_quil_instructions: Some(4),
+        max_call_depth: Some(0),
+        max_unrolled_iterations: None,
+    })
+    .unwrap();
+
+    let error = transpile_with_limits(Limits {
+        max_quil_instructions: Some(3),
+        ..Limits::default()
+    })
+    .unwrap_err();
+    assert!(format!("{:#}", error)
+        .contains("Quil program contains more than the limit of 3 instructions"));
+
+    let error = transpile_with_limits(Limits {
+        max_blocks: Some(2),
+        ..Limits::default()
+    })
+    .unwrap_err();
+    assert!(format!("{:#}", error).contains("more than the limit of 2 basic blocks"));
+}
+
+#[test]
+fn parameter_table_lists_each_parameter_slot() {
+    let data = read("tests/fixtures/programs/parametric.bc").unwrap();
+    let output = transpile_qir_to_quil(&data).unwrap();
+
+    // Both gates parameterized by `%0` read the same slot.
+    assert_eq!(
+        output.parameters,
+        vec![String::from("%0 = fadd double 1.000000e+00, 2.000000e+00")]
+    );
+}
+
+#[test]
+fn transpile_each_block_to_quil() {
+    let data = read("tests/fixtures/programs/multiple_blocks.bc").unwrap();
+    let outputs = transpile_qir_to_quil_by_block(&data).unwrap();
+
+    let block_names: Vec<&str> = outputs.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(block_names, ["bell", "flip"]);
+
+    assert_eq!(outputs[0].1.shot_count, 42);
+    assert_eq!(
+        outputs[0].1.program.to_string(true),
+        "DECLARE ro BIT[2]\nH 0\nCNOT 0 1\nMEASURE 0 ro[0]\nMEASURE 1 ro[1]\n"
+    );
+    assert_eq!(outputs[1].1.shot_count, 10);
+    assert_eq!(
+        outputs[1].1.program.to_string(true),
+        "DECLARE ro BIT[1]\nX 0\nMEASURE 0 ro[0]\n"
+    );
+}
+
+#[test]
+fn trace_annotates_quil_with_source_qir() {
+    let data = read("tests/fixtures/programs/bell_state.bc").unwrap();
+    let options = TranspileOptions {
+        trace: true,
+        ..TranspileOptions::default()
+    };
+    let output = transpile_qir_to_quil_with_options(&data, &options).unwrap();
+
+    assert_eq!(output.trace.len(), 4);
+    assert!(output.trace[0].contains("@__quantum__qis__h__body"));
+    assert!(output.trace[1].contains("@__quantum__qis__cnot__body"));
+
+    let traced = output.to_traced_string();
+    let lines: Vec<&str> = traced.lines().collect();
+    assert_eq!(lines[0], "DECLARE ro BIT[2]");
+    assert!(lines[1].starts_with("# from: tail call void @__quantum__qis__h__body("));
+    assert_eq!(lines[2], "H 0");
+    assert!(lines[3].starts_with("# from: tail call void @__quantum__qis__cnot__body("));
+    assert_eq!(lines[4], "CNOT 0 1");
+
+    let untraced = transpile_qir_to_quil(&data).unwrap();
+    assert!(untraced.trace.is_empty());
+    assert_eq!(
+        untraced.to_traced_string(),
+        untraced.program.to_string(true)
+    );
+}
+
+#[test]
+fn explain_describes_each_pattern_match() {
+    let data = read("tests/fixtures/programs/bell_state.bc").unwrap();
+    let explanation = explain_qir_to_quil(&data).unwrap();
+    let (shot_count, unitary) = explanation.split_once("== unitary pattern ==").unwrap();
+
+    assert!(shot_count.starts_with("== shot count pattern ==\n"));
+    assert!(shot_count.contains("=> matched the start of the shot count loop"));
+    assert!(shot_count
+        .contains("@__quantum__qis__h__body(%Qubit* null)\n    => matched a quantum instruction"));
+    assert!(shot_count.contains("=> matched the end of the shot count loop"));
+    assert!(shot_count.ends_with("result: matched with a shot count of 42\n"));
+
+    assert!(unitary.contains("result: failed: "));
+}
+
+#[test]
+fn dynamically_allocated_qubits_are_diagnosed() {
+    let data = read("tests/fixtures/programs/qubit_allocate.bc").unwrap();
+    let error = transpile_qir_to_quil(&data).unwrap_err();
+
+    let diagnostic = error
+        .chain()
+        .map(ToString::to_string)
+        .find(|message| message.contains("unable to determine the Qubit index"))
+        .expect("expected a diagnostic for the allocated qubit");
+    assert!(diagnostic.contains("@__quantum__qis__h__body(%Qubit* %q)"));
+    assert!(diagnostic.contains("@__quantum__rt__qubit_allocate()"));
+    assert!(diagnostic.contains("dynamic qubit and result allocation is not supported"));
+}
+
 #[test]
 fn capture_recorded_output_and_convert() {
     let data = read("tests/fixtures/programs/record_output.bc").unwrap();
@@ -21,9 +497,1089 @@ fn capture_recorded_output_and_convert() {
     insta::assert_json_snapshot!(&output.recorded_output);
 
     let debug_format = output::try_format::<DebugOutputFormat>(
-        &RegisterData::I8(vec![vec![1, 2], vec![2, 4], vec![3, 6]]),
+        &output::Registers::from([(
+            "ro".into(),
+            RegisterData::I8(vec![vec![1, 2, 3], vec![2, 4, 6], vec![3, 6, 9]]),
+        )]),
         &output.recorded_output,
     )
     .unwrap();
     insta::assert_snapshot!(debug_format);
 }
+
+#[test]
+fn recording_plan_formats_like_recorded_output() {
+    let data = read("tests/fixtures/programs/record_output.bc").unwrap();
+    let output = transpile_qir_to_quil(&data).unwrap();
+    let plan = output.recording_plan();
+    assert_eq!(plan.len(), output.recorded_output.len());
+    assert!(plan.values().all(|value| !matches!(
+        value,
+        RecordedOutput::ShotStart | RecordedOutput::TupleStart | RecordedOutput::TupleEnd
+    )));
+
+    let registers = output::Registers::from([(
+        "ro".into(),
+        RegisterData::I8(vec![vec![1, 2, 3], vec![2, 4, 6], vec![3, 6, 9]]),
+    )]);
+    assert_eq!(
+        output::try_format::<DebugOutputFormat>(&registers, &plan).unwrap(),
+        output::try_format::<DebugOutputFormat>(&registers, &output.recorded_output).unwrap()
+    );
+}
+
+#[test]
+fn host_computed_values_are_reconstructed_from_readout() {
+    let data = read("tests/fixtures/programs/record_parity.bc").unwrap();
+    let output = transpile_qir_to_quil(&data).unwrap();
+    let expressions = output
+        .recorded_output
+        .iter()
+        .filter_map(|value| match value {
+            RecordedOutput::HostComputedBool { expression, .. } => {
+                Some(expression.as_ref().unwrap().to_string())
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        expressions,
+        [
+            "((ro[0] ^ ro[1]) ^ ro[2])",
+            "(((ro[0] + ro[1]) + ro[2]) >= 2)"
+        ]
+    );
+
+    let debug_format = output::try_format::<DebugOutputFormat>(
+        &output::Registers::from([(
+            "ro".into(),
+            RegisterData::I8(vec![vec![1, 0, 0], vec![1, 1, 0], vec![1, 1, 1]]),
+        )]),
+        &output.recorded_output,
+    )
+    .unwrap();
+    let values = debug_format
+        .lines()
+        .filter(|line| line.contains(" bool "))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        values,
+        [
+            "[shot:1 bool true]",
+            "[shot:1 bool false]",
+            "[shot:2 bool false]",
+            "[shot:2 bool true]",
+            "[shot:3 bool true]",
+            "[shot:3 bool true]",
+        ]
+    );
+}
+
+#[test]
+fn tuples_and_arrays_in_recorded_output_are_validated() {
+    let data = read("tests/fixtures/programs/counted_record_output.bc").unwrap();
+    let output = transpile_qir_to_quil(&data).unwrap();
+    let markers = output
+        .recorded_output
+        .iter()
+        .map(|value| match value {
+            RecordedOutput::ShotStart => '<',
+            RecordedOutput::ShotEnd => '>',
+            RecordedOutput::TupleStart => '(',
+            RecordedOutput::TupleEnd => ')',
+            RecordedOutput::ArrayStart { .. } => '[',
+            RecordedOutput::ArrayEnd => ']',
+            _ => 'r',
+        })
+        .collect::<String>();
+    assert_eq!(markers, "<[(rr)(r)]>");
+    assert!(matches!(
+        &output.recorded_output[1],
+        RecordedOutput::ArrayStart { len: Some(2), tag: Some(tag) } if tag == "pairs"
+    ));
+
+    let data = read("tests/fixtures/invalid/unbalanced_record_output.bc").unwrap();
+    let error = transpile_qir_to_quil(&data).unwrap_err();
+    assert!(format!("{:#}", error).contains("a tuple end was recorded within an open array"));
+}
+
+/// The classified error of transpiling `bitcode`, which is expected to fail.
+fn expect_transpile_error(bitcode: &[u8]) -> TranspileError {
+    match transpile_with_error_kind(bitcode) {
+        Ok(output) => panic!(
+            "expected transpilation to fail, but it produced:\n{}",
+            output.program.to_string(true)
+        ),
+        Err(error) => error,
+    }
+}
+
+#[test]
+fn invalid_programs_fail_with_documented_kinds() {
+    let mut kinds = std::fs::read_dir("tests/fixtures/invalid")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .map_or(false, |extension| extension == "bc")
+        })
+        .map(|path| {
+            let error = expect_transpile_error(&read(&path).unwrap());
+            let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+            (name, error.kind)
+        })
+        .collect::<Vec<_>>();
+    kinds.sort_by(|(left, _), (right, _)| left.cmp(right));
+    insta::assert_debug_snapshot!(kinds);
+}
+
+/// Every file under `directory` and its subdirectories, in a stable order.
+fn fixture_files(directory: &Path) -> Vec<PathBuf> {
+    let mut files = vec![];
+    for entry in std::fs::read_dir(directory).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.extend(fixture_files(&path));
+        } else {
+            files.push(path);
+        }
+    }
+    files.sort();
+    files
+}
+
+#[test]
+fn try_transpile_arbitrary_never_panics_on_mutated_fixtures() {
+    for path in fixture_files(Path::new("tests/fixtures")) {
+        let data = read(&path).unwrap();
+
+        // Truncate each file, and flip a growing number of bytes spread through it
+        let mut inputs = vec![data[..data.len() / 2].to_vec()];
+        for seed in 1..=8usize {
+            let mut mutated = data.clone();
+            let mut position = seed * 7919;
+            for _ in 0..seed {
+                if mutated.is_empty() {
+                    break;
+                }
+                let index = position % mutated.len();
+                mutated[index] ^= 0xff >> (seed % 8);
+                position = position * 31 + 17;
+            }
+            inputs.push(mutated);
+        }
+
+        for (index, input) in inputs.iter().enumerate() {
+            let outcome = std::panic::catch_unwind(|| try_transpile_arbitrary(input));
+            assert!(
+                outcome.is_ok(),
+                "mutation {} of {} panicked",
+                index,
+                path.display()
+            );
+        }
+    }
+}
+
+#[test]
+fn patch_overrides_target_triple_and_data_layout() {
+    let data = read("tests/fixtures/programs/measure.bc").unwrap();
+    let context = inkwell::context::Context::create();
+    let options = PatchOptions {
+        target_triple: Some(String::from("x86_64-unknown-linux-gnu")),
+        data_layout: Some(String::from("e-m:e-i64:64-f80:128-n8:16:32:64-S128")),
+        ..PatchOptions::default()
+    };
+    let module = patch_qir_with_qcs(options, &data, &context).unwrap();
+
+    assert_eq!(
+        module.get_triple().as_str().to_str().unwrap(),
+        "x86_64-unknown-linux-gnu"
+    );
+    assert_eq!(
+        module.get_data_layout().as_str().to_str().unwrap(),
+        "e-m:e-i64:64-f80:128-n8:16:32:64-S128"
+    );
+}
+
+#[test]
+fn programs_too_large_for_the_target_are_reported_when_patching() {
+    let data = read("tests/fixtures/programs/bell_state.bc").unwrap();
+    let context = inkwell::context::Context::create();
+
+    // A QVM has no fixed size unless one is given.
+    let options = PatchOptions {
+        deny_excess_qubits: true,
+        ..PatchOptions::default()
+    };
+    patch_qir_with_qcs(options, &data, &context).unwrap();
+
+    let options = PatchOptions {
+        execution_target: ExecutionTarget::Qpu(String::from("Aspen-11")),
+        deny_excess_qubits: true,
+        ..PatchOptions::default()
+    };
+    patch_qir_with_qcs(options, &data, &context).unwrap();
+
+    let options = PatchOptions {
+        target_qubit_count: Some(1),
+        deny_excess_qubits: true,
+        ..PatchOptions::default()
+    };
+    let error = patch_qir_with_qcs(options, &data, &context).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "program uses qubit 1, but the QVM only has qubits 0 to 0"
+    );
+
+    // Without `deny_excess_qubits`, the program is only reported as a warning.
+    let options = PatchOptions {
+        target_qubit_count: Some(1),
+        ..PatchOptions::default()
+    };
+    patch_qir_with_qcs(options, &data, &context).unwrap();
+}
+
+#[test]
+fn patch_options_are_read_from_the_environment() {
+    std::env::set_var("QCS_QIR_TARGET", "Aspen-M-3");
+    std::env::set_var("QCS_QIR_CACHE_EXECUTABLES", "1");
+    std::env::set_var("QCS_QIR_DENY_EXCESS_QUBITS", "false");
+    std::env::set_var("QCS_QIR_TARGET_QUBITS", "9");
+    let options = PatchOptions::from_env().unwrap();
+    assert_eq!(
+        options.execution_target,
+        ExecutionTarget::Qpu(String::from("Aspen-M-3"))
+    );
+    assert!(options.cache_executables);
+    assert!(!options.deny_excess_qubits);
+    assert_eq!(options.target_qubit_count, Some(9));
+    assert_eq!(options.quil_rewiring_pragma, None);
+
+    std::env::set_var("QCS_QIR_TARGET_QUBITS", "nine");
+    assert!(PatchOptions::from_env().is_err());
+
+    for name in [
+        "QCS_QIR_TARGET",
+        "QCS_QIR_CACHE_EXECUTABLES",
+        "QCS_QIR_DENY_EXCESS_QUBITS",
+        "QCS_QIR_TARGET_QUBITS",
+    ] {
+        std::env::remove_var(name);
+    }
+}
+
+#[test]
+fn dry_run_reports_what_patching_would_change() {
+    let data = read("tests/fixtures/programs/measure.bc").unwrap();
+    let options = PatchOptions {
+        function_allowlist: Some(vec![
+            String::from("simulator_kernel"),
+            String::from("qcs_kernel"),
+        ]),
+        ..PatchOptions::default()
+    };
+    let summary = dry_run_patch(PatchFormat::ShotCount, options, &[&data]).unwrap();
+    let functions = summary
+        .programs
+        .iter()
+        .map(|program| program.function.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(functions, ["simulator_kernel", "qcs_kernel"]);
+
+    assert_eq!(summary.programs.len(), 1);
+    let program = &summary.programs[0];
+    assert_eq!(program.function, "QuantumApplication__Run__body");
+    assert_eq!(program.block, "body");
+    assert_eq!(program.shot_count, Some(42));
+    assert!(program.program.contains("MEASURE 1 ro[0]"));
+    for symbol in ["executable_from_quil", "wrap_in_shots", "execute_on_qvm"] {
+        assert!(
+            summary.symbols_added.iter().any(|added| added == symbol),
+            "expected {} to be added: {:?}",
+            symbol,
+            summary.symbols_added
+        );
+    }
+    assert!(!summary
+        .symbols_added
+        .iter()
+        .any(|added| added == "QuantumApplication__Run__body"));
+}
+
+#[test]
+fn runtime_flavor_selects_runtime_function_names() {
+    let data = read("tests/fixtures/programs/measure.bc").unwrap();
+    let flavors = [
+        ("qcs-rust", "qcs_sdk_qir_executable_from_quil"),
+        ("custom:acme_", "acme_executable_from_quil"),
+    ];
+    for (flavor, expected) in flavors {
+        let options = PatchOptions {
+            runtime_flavor: RuntimeFlavor::from_str(flavor).unwrap(),
+            ..PatchOptions::default()
+        };
+        let summary = dry_run_patch(PatchFormat::ShotCount, options, &[&data]).unwrap();
+        assert!(
+            summary.symbols_added.iter().any(|added| added == expected),
+            "expected {} to be added: {:?}",
+            expected,
+            summary.symbols_added
+        );
+        assert!(!summary
+            .symbols_added
+            .iter()
+            .any(|added| added == "executable_from_quil"));
+    }
+    assert!(RuntimeFlavor::from_str("custom:").is_err());
+}
+
+#[test]
+fn user_context_is_passed_to_every_runtime_call() {
+    let data = read("tests/fixtures/programs/measure.bc").unwrap();
+    let context = inkwell::context::Context::create();
+    let options = PatchOptions {
+        user_context: true,
+        ..PatchOptions::default()
+    };
+    let module = patch_qir_with_qcs(options, &data, &context).unwrap();
+
+    let global = module.get_global("qcs_sdk_qir_user_context").unwrap();
+    assert!(global.get_initializer().is_some());
+    // `executable_from_quil` otherwise takes only the `i8*` Quil program
+    let parameters = module
+        .get_function("executable_from_quil")
+        .unwrap()
+        .get_params();
+    assert_eq!(parameters.len(), 2);
+    assert_eq!(parameters[0].get_type(), parameters[1].get_type());
+    assert_eq!(
+        module
+            .get_function("get_readout_bit")
+            .unwrap()
+            .count_params(),
+        4
+    );
+    let ir = module.print_to_string().to_string();
+    assert!(ir.contains("load i8*, i8** @qcs_sdk_qir_user_context"));
+}
+
+#[test]
+fn patched_module_can_be_written_as_object_file() {
+    let data = read("tests/fixtures/programs/measure.bc").unwrap();
+    let context = inkwell::context::Context::create();
+    let options = PatchOptions {
+        add_main_entrypoint: true,
+        target_triple: Some(String::from("host")),
+        ..PatchOptions::default()
+    };
+    let module = patch_qir_with_qcs(options, &data, &context).unwrap();
+
+    let path = std::env::temp_dir().join("qcs_sdk_qir_measure.o");
+    qcs_sdk_qir::emit::write_object_file(&module, &path).unwrap();
+    assert!(std::fs::metadata(&path).unwrap().len() > 0);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn main_entrypoint_frees_cached_executables() {
+    let data = read("tests/fixtures/programs/measure.bc").unwrap();
+    let context = inkwell::context::Context::create();
+    let options = PatchOptions {
+        add_main_entrypoint: true,
+        cache_executables: true,
+        ..PatchOptions::default()
+    };
+    let module = patch_qir_with_qcs(options, &data, &context).unwrap();
+
+    // `main` calls the function which frees the cache, which is also registered as a destructor for
+    // hosts which call the entrypoint directly.
+    let ir = module.print_to_string().to_string();
+    assert!(
+        ir.contains("call void @free_executable_array()"),
+        "expected main to free the executable cache:\n{}",
+        ir
+    );
+    assert!(module
+        .get_global("llvm.global_dtors")
+        .unwrap()
+        .print_to_string()
+        .to_string()
+        .contains("@free_executable_array"));
+}
+
+#[test]
+fn helper_symbols_can_be_hidden_and_use_fastcc() {
+    let data = read("tests/fixtures/programs/measure.bc").unwrap();
+    let context = inkwell::context::Context::create();
+    let options = PatchOptions {
+        add_main_entrypoint: true,
+        cache_executables: true,
+        helper_calling_convention: CallingConvention::Fast,
+        hidden_visibility: true,
+        ..PatchOptions::default()
+    };
+    let module = patch_qir_with_qcs(options, &data, &context).unwrap();
+    module.verify().map_err(|e| e.to_string()).unwrap();
+
+    let ir = module.print_to_string().to_string();
+    for expected in [
+        "define hidden fastcc void @populate_executable_array()",
+        "define hidden fastcc void @free_executable_array()",
+        "call fastcc void @populate_executable_array()",
+        "call fastcc void @free_executable_array()",
+        "declare hidden noalias %Executable* @executable_from_quil(",
+        "@qcs_sdk_qir_abi_version = weak_odr hidden constant",
+        "define i32 @main()",
+    ] {
+        assert!(ir.contains(expected), "expected {:?} in:\n{}", expected, ir);
+    }
+}
+
+#[test]
+fn gate_loops_are_unrolled_when_patching() {
+    let data = read("tests/fixtures/programs/gate_loop.bc").unwrap();
+    let context = inkwell::context::Context::create();
+    let module = patch_qir_with_qcs(PatchOptions::default(), &data, &context).unwrap();
+    module.verify().map_err(|e| e.to_string()).unwrap();
+
+    // The loop is left in place, but its quantum calls are replaced by the unrolled program.
+    let ir = module.print_to_string().to_string();
+    assert!(
+        !ir.contains("call void @__quantum__qis__h__body"),
+        "expected the loop's quantum calls to be removed:\n{}",
+        ir
+    );
+    assert!(
+        ir.contains("H 0\\0AMEASURE 0 ro[0]\\0AH 1\\0AMEASURE 1 ro[1]\\0AH 2"),
+        "expected the loop to be unrolled into the Quil program:\n{}",
+        ir
+    );
+}
+
+#[test]
+fn called_functions_are_transformed_once_within_the_depth_limit() {
+    let data = read("tests/fixtures/programs/nested_calls.bc").unwrap();
+    let context = inkwell::context::Context::create();
+    let module = patch_qir_with_qcs(PatchOptions::default(), &data, &context).unwrap();
+    module.verify().map_err(|e| e.to_string()).unwrap();
+    let ir = module.print_to_string().to_string();
+    assert!(
+        !ir.contains("call void @__quantum__qis__h__body"),
+        "expected every called function to be transformed:\n{}",
+        ir
+    );
+
+    let options = PatchOptions {
+        limits: Limits {
+            max_call_depth: Some(1),
+            ..Limits::default()
+        },
+        ..PatchOptions::default()
+    };
+    let error = patch_qir_with_qcs(options, &data, &context).unwrap_err();
+    assert!(format!("{:#}", error).contains(
+        "function calls are nested more deeply than the limit of 1: @main -> @middle -> @kernel"
+    ));
+
+    let data = read("tests/fixtures/programs/recursive_calls.bc").unwrap();
+    let error = patch_qir_with_qcs(PatchOptions::default(), &data, &context).unwrap_err();
+    assert!(format!("{:#}", error)
+        .contains("recursive function calls cannot be transformed: @ping -> @pong -> @ping"));
+}
+
+#[test]
+fn stable_block_layout_orders_blocks_by_control_flow() {
+    let data = read("tests/fixtures/programs/bell_state.bc").unwrap();
+    let context = inkwell::context::Context::create();
+    let options = PatchOptions {
+        stable_block_layout: true,
+        ..PatchOptions::default()
+    };
+    let module = patch_qir_with_qcs(options, &data, &context).unwrap();
+    module.verify().map_err(|e| e.to_string()).unwrap();
+
+    let blocks = module
+        .get_function("QuantumApplication__Run__body")
+        .unwrap()
+        .get_basic_blocks()
+        .iter()
+        .map(|block| block.get_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        blocks,
+        ["entry", "body_execution", "body", "body_cleanup", "exit"]
+    );
+}
+
+#[test]
+fn runtime_select_parameters_are_chosen_before_execution() {
+    let data = read("tests/fixtures/programs/runtime_select.bc").unwrap();
+    let context = inkwell::context::Context::create();
+    let module = patch_qir_with_qcs(PatchOptions::default(), &data, &context).unwrap();
+    module.verify().map_err(|e| e.to_string()).unwrap();
+
+    // The `select` is rebuilt where parameters are set, before any shot is run.
+    let ir = module.print_to_string().to_string();
+    let execution_block = ir
+        .split("body_execution:")
+        .nth(1)
+        .and_then(|rest| rest.split("\n\n").next())
+        .unwrap_or_default();
+    assert!(
+        execution_block.contains("select i1 %flag, double %0, double 2.500000e-01"),
+        "expected the parameter to be chosen before execution:\n{}",
+        ir
+    );
+}
+
+#[test]
+fn patching_an_already_patched_module_leaves_it_unchanged() {
+    let data = read("tests/fixtures/programs/measure.bc").unwrap();
+    let context = inkwell::context::Context::create();
+    let patched = patch_qir_with_qcs(PatchOptions::default(), &data, &context).unwrap();
+    let patched_data = patched.write_bitcode_to_memory();
+
+    let repatched =
+        patch_qir_with_qcs(PatchOptions::default(), patched_data.as_slice(), &context).unwrap();
+    // The module ID is derived from the buffer each module was loaded from.
+    let without_module_id = |module: &inkwell::module::Module| {
+        module
+            .print_to_string()
+            .to_string()
+            .lines()
+            .filter(|line| !line.starts_with("; ModuleID"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    assert_eq!(without_module_id(&patched), without_module_id(&repatched));
+
+    // Modules patched before the runtime ABI version was recorded are detected too.
+    let data = read("tests/fixtures/programs/transformed_with_qcs_decls.bc").unwrap();
+    let repatched = patch_qir_with_qcs(PatchOptions::default(), &data, &context).unwrap();
+    assert!(repatched
+        .get_function("populate_executable_array.1")
+        .is_none());
+}
+
+#[test]
+fn patching_the_same_input_produces_identical_output() {
+    let data = read("tests/fixtures/programs/reduction.bc").unwrap();
+    let patch = || {
+        let context = inkwell::context::Context::create();
+        let options = PatchOptions {
+            cache_executables: true,
+            ..PatchOptions::default()
+        };
+        let module = patch_qir_with_qcs(options, &data, &context).unwrap();
+        module.write_bitcode_to_memory().as_slice().to_vec()
+    };
+
+    let first = patch();
+    for _ in 0..4 {
+        assert!(first == patch(), "patched bitcode differs between runs");
+    }
+}
+
+#[test]
+fn only_functions_in_the_allowlist_are_transformed() {
+    let data = read("tests/fixtures/programs/mixed_backends.bc").unwrap();
+    let context = inkwell::context::Context::create();
+    let options = PatchOptions {
+        function_allowlist: Some(vec![String::from("qcs_kernel")]),
+        ..PatchOptions::default()
+    };
+    let module = patch_qir_with_qcs(options, &data, &context).unwrap();
+    let function_text = |name: &str| {
+        module
+            .get_function(name)
+            .unwrap()
+            .print_to_string()
+            .to_string()
+    };
+
+    assert!(!function_text("qcs_kernel").contains("__quantum__qis__h__body"));
+    assert!(function_text("simulator_kernel").contains("__quantum__qis__x__body"));
+
+    let summary = dry_run_patch(PatchFormat::ShotCount, PatchOptions::default(), &[&data]).unwrap();
+    assert_eq!(summary.programs.len(), 2);
+
+    let options = PatchOptions {
+        function_allowlist: Some(vec![String::from("missing")]),
+        ..PatchOptions::default()
+    };
+    let error = patch_qir_with_qcs(options, &data, &context).unwrap_err();
+    assert!(format!("{:?}", error).contains("function @missing was selected for transformation"));
+}
+
+#[test]
+fn original_code_is_kept_behind_a_runtime_flag() {
+    let data = read("tests/fixtures/programs/entrypoint_attribute.bc").unwrap();
+    let context = inkwell::context::Context::create();
+    let options = PatchOptions {
+        keep_original_code: true,
+        add_main_entrypoint: true,
+        cache_executables: true,
+        ..PatchOptions::default()
+    };
+    let module = patch_qir_with_qcs(options, &data, &context).unwrap();
+    module.verify().unwrap();
+
+    let patched = module.get_function("some_function").unwrap();
+    let dispatch = patched.get_first_basic_block().unwrap();
+    assert_eq!(dispatch.get_name().to_str().unwrap(), "qcs_dispatch");
+    let patched_text = patched.print_to_string().to_string();
+    assert!(patched_text.contains("call i1 @use_qcs()"));
+    assert!(patched_text.contains("call fastcc void @some_function__qir_fallback()"));
+    assert!(!patched_text.contains("__quantum__qis__cnot__body"));
+
+    let original = module.get_function("some_function__qir_fallback").unwrap();
+    assert!(original
+        .print_to_string()
+        .to_string()
+        .contains("__quantum__qis__cnot__body"));
+    assert!(original
+        .get_string_attribute(inkwell::attributes::AttributeLoc::Function, "EntryPoint")
+        .is_none());
+
+    // The transformed function remains the entrypoint called by `main`.
+    let main_text = module
+        .get_function("main")
+        .unwrap()
+        .print_to_string()
+        .to_string();
+    assert!(main_text.contains("@some_function()"));
+}
+
+#[test]
+fn provenance_is_stamped_only_when_requested() {
+    let data = read("tests/fixtures/programs/measure.bc").unwrap();
+    let context = inkwell::context::Context::create();
+    let options = PatchOptions {
+        stamp_provenance: true,
+        cache_executables: true,
+        ..PatchOptions::default()
+    };
+    let module = patch_qir_with_qcs(options, &data, &context).unwrap();
+    assert!(module.get_global("qcs_sdk_qir_provenance").is_some());
+
+    let provenance = read_provenance(module.write_bitcode_to_memory().as_slice())
+        .unwrap()
+        .unwrap();
+    assert_eq!(provenance.crate_version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(provenance.input_sha256, CacheKey::of(&data).to_string());
+    assert!(provenance.options.contains("target=qvm"));
+    assert!(provenance.options.contains("cache_executables=true"));
+    assert!(provenance.timestamp > 0);
+
+    let module = patch_qir_with_qcs(PatchOptions::default(), &data, &context).unwrap();
+    assert!(module.get_global("qcs_sdk_qir_provenance").is_none());
+    assert_eq!(
+        read_provenance(module.write_bitcode_to_memory().as_slice()).unwrap(),
+        None
+    );
+}
+
+struct VendorPulseHandler;
+
+impl CustomInstructionHandler for VendorPulseHandler {
+    fn handle_call(&self, call: &IntrinsicCall) -> eyre::Result<Option<CustomInstructionOutput>> {
+        if call.function_name != "__vendor__pulse__body" {
+            return Ok(None);
+        }
+
+        assert_eq!(
+            call.arguments,
+            vec![
+                IntrinsicArgument::Qubit(1),
+                IntrinsicArgument::Double(0.5),
+                IntrinsicArgument::Integer(3)
+            ]
+        );
+
+        let instruction = quil_rs::instruction::Instruction::Gate(quil_rs::instruction::Gate {
+            name: String::from("VENDOR_PULSE"),
+            parameters: vec![],
+            qubits: vec![quil_rs::instruction::Qubit::Fixed(1)],
+            modifiers: vec![],
+        });
+        Ok(Some(CustomInstructionOutput {
+            instructions: vec![instruction],
+            ..CustomInstructionOutput::default()
+        }))
+    }
+}
+
+#[test]
+fn custom_instruction_handlers_translate_unrecognized_calls() {
+    let data = read("tests/fixtures/programs/custom_intrinsic.bc").unwrap();
+    let context = inkwell::context::Context::create();
+    let options = PatchOptions {
+        custom_instruction_handlers: vec![Arc::new(VendorPulseHandler)],
+        ..PatchOptions::default()
+    };
+    let module = patch_qir_with_qcs(options, &data, &context).unwrap();
+    let module_text = module.print_to_string().to_string();
+
+    assert!(module_text.contains("VENDOR_PULSE 1"));
+    assert!(!module_text.contains("call void @__vendor__pulse__body"));
+}
+
+#[test]
+fn raw_quil_is_merged_at_the_position_of_the_call() {
+    let data = read("tests/fixtures/programs/raw_quil.bc").unwrap();
+    let options = PatchOptions {
+        custom_instruction_handlers: vec![Arc::new(RawQuilHandler::new("__vendor__quil__body"))],
+        ..PatchOptions::default()
+    };
+    let summary = dry_run_patch(PatchFormat::ShotCount, options, &[&data]).unwrap();
+    let program = &summary.programs[0].program;
+
+    assert!(program.contains("DEFWAVEFORM my_wf"));
+    let h = program.find("H 0").unwrap();
+    let pulse = program.find("PULSE 0 \"rf\" my_wf").unwrap();
+    let measure = program.find("MEASURE 0 ro[0]").unwrap();
+    assert!(h < pulse && pulse < measure);
+}
+
+#[test]
+fn runtime_strings_created_from_constants_are_given_to_handlers() {
+    let data = read("tests/fixtures/programs/raw_quil_string.bc").unwrap();
+    let options = PatchOptions {
+        custom_instruction_handlers: vec![Arc::new(RawQuilHandler::new("__vendor__quil__body"))],
+        ..PatchOptions::default()
+    };
+    let summary = dry_run_patch(PatchFormat::ShotCount, options, &[&data]).unwrap();
+    assert!(summary.programs[0]
+        .program
+        .contains("H 0\nX 0\nY 0\nMEASURE 0 ro[0]\n"));
+}
+
+#[test]
+fn quil_inline_intrinsic_injects_literal_quil() {
+    let data = read("tests/fixtures/programs/quil_inline.bc").unwrap();
+    let program = transpile_qir_to_quil(&data)
+        .unwrap()
+        .program
+        .to_string(true);
+
+    let h = program.find("H 0").unwrap();
+    let pragma = program.find("PRAGMA PRESERVE_BLOCK").unwrap();
+    let pulse = program.find("PULSE 0 \"rf\" flat(").unwrap();
+    let measure = program.find("MEASURE 0 ro[0]").unwrap();
+    assert!(h < pragma && pragma < pulse && pulse < measure);
+
+    let error =
+        expect_transpile_error(&read("tests/fixtures/invalid/invalid_inline_quil.bc").unwrap());
+    assert!(error
+        .message
+        .contains("invalid Quil given to @__qcs__quil__inline"));
+    assert!(error.message.contains(
+        "in @QuantumApplication__Run__body, block %body: tail call void @__qcs__quil__inline"
+    ));
+}
+
+#[test]
+fn calibrations_in_metadata_are_prepended_to_programs() {
+    let data = read("tests/fixtures/programs/defcal_metadata.bc").unwrap();
+    let output = transpile_qir_to_quil(&data).unwrap();
+    let program = output.program.to_string(true);
+
+    assert!(program.contains("DEFCAL H 0"));
+    assert!(program.contains("MEASURE 0 ro[0]"));
+}
+
+#[test]
+fn intermediate_state_is_dumped_for_each_matched_block() {
+    let data = read("tests/fixtures/programs/measure.bc").unwrap();
+    let directory =
+        std::env::temp_dir().join(format!("qcs-sdk-qir-dump-test-{}", std::process::id()));
+    let context = inkwell::context::Context::create();
+    let options = PatchOptions {
+        dump_intermediate: Some(directory.clone()),
+        ..PatchOptions::default()
+    };
+    patch_qir_with_qcs(options, &data, &context).unwrap();
+
+    let dumped = |kind: &str| {
+        std::fs::read_to_string(
+            directory.join(format!("QuantumApplication__Run__body.body.{}", kind)),
+        )
+        .unwrap()
+    };
+    let quil = dumped("quil");
+    let before = dumped("before.ll");
+    let after = dumped("after.ll");
+    let pattern_context = dumped("context.json");
+    let file_count = std::fs::read_dir(&directory).unwrap().count();
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    assert!(quil.contains("MEASURE 1 ro[0]"));
+    assert!(before.contains("@__quantum__qis__mz__body"));
+    assert!(!after.contains("@__quantum__qis__mz__body"));
+    assert!(pattern_context.contains("\"shot_count\": 42"));
+    // Only the block holding the shot count loop was matched.
+    assert_eq!(file_count, 4);
+}
+
+#[test]
+fn shot_count_policy_adjusts_or_vetoes_programs() {
+    let data = read("tests/fixtures/programs/measure.bc").unwrap();
+
+    let options = PatchOptions {
+        shot_count_policy: Some(Arc::new(MaxShotCount(10))),
+        ..PatchOptions::default()
+    };
+    let summary = dry_run_patch(PatchFormat::ShotCount, options, &[&data]).unwrap();
+    assert_eq!(summary.programs[0].shot_count, Some(10));
+
+    let options = PatchOptions {
+        shot_count_policy: Some(Arc::new(
+            |shot_count: u64, metrics: &ProgramMetrics| -> eyre::Result<u64> {
+                assert_eq!(shot_count, 42);
+                assert_eq!(metrics.qubits, [1]);
+                assert_eq!(metrics.readout_length, 1);
+                Err(eyre::eyre!("over budget"))
+            },
+        )),
+        ..PatchOptions::default()
+    };
+    let error = dry_run_patch(PatchFormat::ShotCount, options, &[&data]).unwrap_err();
+    assert!(format!("{:#}", error).contains(
+        "shot count policy rejected the program in @QuantumApplication__Run__body, block %body: over budget"
+    ));
+}
+
+#[test]
+fn shot_chunking_splits_each_execution() {
+    let data = read("tests/fixtures/programs/measure.bc").unwrap();
+    let context = inkwell::context::Context::create();
+    let options = PatchOptions {
+        shot_chunking: ShotChunking::from_str("count:4").unwrap(),
+        ..PatchOptions::default()
+    };
+    let module = patch_qir_with_qcs(options, &data, &context).unwrap();
+    let ir = module.print_to_string().to_string();
+
+    // The 42 shots are split as 11, 11, 10, 10, each with its own result to read out and free.
+    assert_eq!(ir.matches("call void @wrap_in_shots(").count(), 4);
+    assert_eq!(ir.matches(", i32 11)").count(), 2);
+    assert_eq!(ir.matches(", i32 10)").count(), 2);
+    assert_eq!(
+        ir.matches("call %ExecutionResult* @execute_on_qvm(")
+            .count(),
+        4
+    );
+    assert_eq!(ir.matches("call void @free_execution_result(").count(), 4);
+    assert_eq!(ir.matches("call i1 @get_readout_bit(").count(), 1);
+
+    assert_eq!(ShotChunking::default(), ShotChunking::Whole);
+    assert!(ShotChunking::from_str("max-shots:0").is_err());
+}
+
+#[test]
+fn reused_execution_results_are_allocated_once_per_call() {
+    let data = read("tests/fixtures/programs/measure.bc").unwrap();
+    let context = inkwell::context::Context::create();
+    let options = PatchOptions {
+        reuse_execution_results: true,
+        ..PatchOptions::default()
+    };
+    let module = patch_qir_with_qcs(options, &data, &context).unwrap();
+    let ir = module.print_to_string().to_string();
+
+    assert!(ir.contains("call %ExecutionResult* @reset_execution_result(%ExecutionResult* null)"));
+    assert_eq!(ir.matches("call void @execute_on_qvm_into(").count(), 1);
+    assert!(!ir.contains("call %ExecutionResult* @execute_on_qvm("));
+
+    // The result is freed as the function returns, not as the shot count loop is left
+    let function = module
+        .get_function("QuantumApplication__Run__body")
+        .unwrap()
+        .print_to_string()
+        .to_string();
+    let free = function.find("call void @free_execution_result(").unwrap();
+    assert!(function[free..].trim_start().contains("ret void"));
+    assert!(!function[..free].contains("ret void"));
+}
+
+#[test]
+fn quil_post_processors_rewrite_each_program_in_order() {
+    let data = read("tests/fixtures/programs/measure.bc").unwrap();
+    let options = PatchOptions {
+        quil_post_processors: vec![
+            Arc::new(|program: &mut quil_rs::Program| -> eyre::Result<()> {
+                *program = quil_rs::Program::from_str(&format!(
+                    "PRAGMA PRESERVE_BLOCK\n{}",
+                    program.to_string(true)
+                ))
+                .map_err(|error| eyre::eyre!("{:?}", error))?;
+                Ok(())
+            }),
+            Arc::new(|program: &mut quil_rs::Program| -> eyre::Result<()> {
+                program.add_instruction(quil_rs::instruction::Instruction::Pragma(
+                    quil_rs::instruction::Pragma {
+                        name: String::from("END_PRESERVE_BLOCK"),
+                        arguments: vec![],
+                        data: None,
+                    },
+                ));
+                Ok(())
+            }),
+        ],
+        ..PatchOptions::default()
+    };
+    let summary = dry_run_patch(PatchFormat::ShotCount, options, &[&data]).unwrap();
+    let program = &summary.programs[0].program;
+    let preserve = program.find("PRAGMA PRESERVE_BLOCK\n").unwrap();
+    assert!(preserve < program.find("MEASURE 1 ro[0]").unwrap());
+    assert!(
+        program.ends_with("PRAGMA END_PRESERVE_BLOCK\n"),
+        "{}",
+        program
+    );
+
+    let options = PatchOptions {
+        quil_post_processors: vec![Arc::new(|_: &mut quil_rs::Program| -> eyre::Result<()> {
+            Err(eyre::eyre!("rejected"))
+        })],
+        ..PatchOptions::default()
+    };
+    let error = dry_run_patch(PatchFormat::ShotCount, options, &[&data]).unwrap_err();
+    assert!(format!("{:#}", error).contains(
+        "Quil post-processor 0 failed on the program in @QuantumApplication__Run__body, block %body: rejected"
+    ));
+}
+
+#[test]
+fn manifest_describes_each_inserted_program() {
+    let data = read("tests/fixtures/programs/multiple_kernels.bc").unwrap();
+    let context = inkwell::context::Context::create();
+    let options = PatchOptions {
+        cache_executables: true,
+        ..PatchOptions::default()
+    };
+    let (_module, manifest) =
+        patch_with_manifest(PatchFormat::ShotCount, options, &[&data], &context).unwrap();
+
+    assert_eq!(manifest.target, "qvm");
+    let programs = manifest
+        .programs
+        .iter()
+        .map(|program| {
+            (
+                program.function.as_str(),
+                program.shot_count,
+                program.cache_index,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        programs,
+        [
+            ("prepare_bell_state", Some(42), Some(0)),
+            ("flip_qubit", Some(10), Some(1)),
+        ]
+    );
+    assert_eq!(
+        manifest.programs[1].program,
+        "DECLARE ro BIT[1]\nX 0\nMEASURE 0 ro[0]\n"
+    );
+
+    let (_module, manifest) = patch_with_manifest(
+        PatchFormat::ShotCount,
+        PatchOptions::default(),
+        &[&data],
+        &context,
+    )
+    .unwrap();
+    assert!(manifest
+        .programs
+        .iter()
+        .all(|program| program.cache_index.is_none()));
+}
+
+#[test]
+fn qubits_reused_by_a_later_program_are_reset() {
+    let data = read("tests/fixtures/programs/multiple_kernels.bc").unwrap();
+    let context = inkwell::context::Context::create();
+    let patch = |qubit_reuse| {
+        let options = PatchOptions {
+            qubit_reuse,
+            ..PatchOptions::default()
+        };
+        let (_module, manifest) =
+            patch_with_manifest(PatchFormat::ShotCount, options, &[&data], &context).unwrap();
+        manifest
+            .programs
+            .into_iter()
+            .map(|program| program.program)
+            .collect::<Vec<_>>()
+    };
+
+    let programs = patch(QubitReuse::Reset);
+    assert_eq!(
+        programs[0],
+        "DECLARE ro BIT[2]\nH 0\nCNOT 0 1\nMEASURE 0 ro[0]\nMEASURE 1 ro[1]\n"
+    );
+    assert_eq!(
+        programs[1],
+        "DECLARE ro BIT[1]\nRESET 0\nX 0\nMEASURE 0 ro[0]\n"
+    );
+
+    for qubit_reuse in [QubitReuse::Ignore, QubitReuse::Warn] {
+        assert_eq!(
+            patch(qubit_reuse)[1],
+            "DECLARE ro BIT[1]\nX 0\nMEASURE 0 ro[0]\n"
+        );
+    }
+}
+
+struct MockTranslationService(Option<&'static [&'static str]>);
+
+impl TranslationService for MockTranslationService {
+    fn translate(
+        &self,
+        _quil: &str,
+        _shot_count: u64,
+        _quantum_processor_id: &str,
+    ) -> eyre::Result<TranslatedProgram> {
+        let references = self
+            .0
+            .ok_or_else(|| eyre::eyre!("gate H is not native to the QPU"))?;
+        Ok(TranslatedProgram {
+            ro_sources: references
+                .iter()
+                .map(|reference| (String::from(*reference), String::from("q0_ro")))
+                .collect(),
+        })
+    }
+}
+
+#[test]
+fn translation_verifies_recorded_readout_survives() {
+    let data = read("tests/fixtures/programs/record_parity.bc").unwrap();
+    let verify = |execution_target, references| {
+        let options = PatchOptions {
+            execution_target,
+            translation_service: Some(Arc::new(MockTranslationService(references))),
+            ..PatchOptions::default()
+        };
+        dry_run_patch(PatchFormat::ShotCount, options, &[&data]).map(|_| ())
+    };
+    let qpu = || ExecutionTarget::Qpu(String::from("Aspen-11"));
+
+    verify(qpu(), Some(&["ro", "ro[1]", "ro[2]"])).unwrap();
+
+    let error = verify(qpu(), Some(&["ro[0]", "ro[1]"])).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "the program in @QuantumApplication__Run__body, block %body records ro[2], which translation for Aspen-11 does not read out"
+    );
+
+    let error = format!("{:#}", verify(qpu(), None).unwrap_err());
+    assert!(error.contains("failed translation for Aspen-11"));
+    assert!(error.contains("gate H is not native to the QPU"));
+
+    verify(ExecutionTarget::Qvm, None).unwrap();
+}